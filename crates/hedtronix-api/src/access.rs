@@ -0,0 +1,117 @@
+//! Patient access checks shared by the chart handlers
+
+use std::sync::OnceLock;
+
+use axum::{async_trait, extract::FromRequestParts, http::request::Parts};
+use hedtronix_auth::{AuthorizationError, Claims, PatientScope};
+use hedtronix_core::{Appointment, Id, UserRole};
+use serde::Serialize;
+
+use crate::error::ApiError;
+use crate::state::AppState;
+
+/// Header carrying an emergency access grant token
+pub const BREAK_GLASS_HEADER: &str = "X-Break-Glass-Token";
+
+/// The authenticated caller, plus any emergency access grant they present
+pub struct Caller {
+    pub claims: Claims,
+    pub break_glass_token: Option<String>,
+    /// Role whose redaction rules apply, resolved on first use
+    redaction_role: OnceLock<UserRole>,
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Caller {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let claims = parts.extensions
+            .get::<Claims>()
+            .cloned()
+            .ok_or_else(|| ApiError::unauthorized("Authentication required"))?;
+
+        let break_glass_token = parts.headers
+            .get(BREAK_GLASS_HEADER)
+            .and_then(|h| h.to_str().ok())
+            .map(str::to_string);
+
+        Ok(Self { claims, break_glass_token, redaction_role: OnceLock::new() })
+    }
+}
+
+/// Refuse an action the caller's role does not grant, or that an offline
+/// token does not carry. Emergency access does not widen this.
+pub fn require_permission(state: &AppState, caller: &Caller, resource: &str, action: &str) -> Result<(), ApiError> {
+    Ok(state.access_policy().authorize(&caller.claims, resource, action)?)
+}
+
+/// Scope the caller has on a patient's chart. When the normal rules deny
+/// access, a valid emergency grant for the patient opens the full chart and
+/// the read is audited against the grant.
+pub fn require_patient(
+    state: &AppState,
+    caller: &Caller,
+    patient_id: Id,
+    entity_type: &str,
+    entity_id: Id,
+) -> Result<PatientScope, ApiError> {
+    let policy = state.access_policy();
+    let subject = policy.subject(&caller.claims)?;
+
+    match policy.require_patient(&subject, patient_id) {
+        Err(AuthorizationError::Denied(reason)) => {
+            break_glass(state, caller, patient_id, entity_type, entity_id, reason)
+        }
+        result => Ok(result?),
+    }
+}
+
+/// Like `require_patient`, for an appointment
+pub fn require_appointment(
+    state: &AppState,
+    caller: &Caller,
+    appointment: &Appointment,
+) -> Result<PatientScope, ApiError> {
+    let policy = state.access_policy();
+    let subject = policy.subject(&caller.claims)?;
+
+    match policy.require_appointment(&subject, appointment) {
+        Err(AuthorizationError::Denied(reason)) => break_glass(
+            state,
+            caller,
+            appointment.patient_id,
+            "Appointment",
+            appointment.id,
+            reason,
+        ),
+        result => Ok(result?),
+    }
+}
+
+/// Serialize a response with the redaction rules for the caller's role; a
+/// custom role gets the rules of the built-in role it is based on
+pub fn redact<T: Serialize>(state: &AppState, caller: &Caller, entity: &str, value: &T) -> serde_json::Value {
+    let role = *caller.redaction_role
+        .get_or_init(|| state.auth_state.permissions.base_role_for(&caller.claims));
+    state.auth_state.redaction_policy.redact(entity, role, value)
+}
+
+fn break_glass(
+    state: &AppState,
+    caller: &Caller,
+    patient_id: Id,
+    entity_type: &str,
+    entity_id: Id,
+    denied: &str,
+) -> Result<PatientScope, ApiError> {
+    let Some(token) = &caller.break_glass_token else {
+        return Err(ApiError::forbidden(denied));
+    };
+
+    let emergency = state.emergency_access();
+    let grant = emergency.authorize(&caller.claims, token, patient_id)?;
+    emergency.record_access(&caller.claims, &grant, entity_type, &entity_id.to_string())?;
+
+    Ok(PatientScope::Full)
+}
@@ -0,0 +1,184 @@
+//! Automatic audit capture for PHI routes
+//!
+//! Every successful request to a protected PHI route is appended to the
+//! audit log with the caller, device, client address and user agent. The
+//! layer runs inside `auth_middleware`, so the caller's claims are known.
+//! Lists and searches name the records they returned with `audited`, and get
+//! one entry per record so each patient's disclosure report and access
+//! monitoring see them.
+
+use std::net::SocketAddr;
+
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, OriginalUri, Request, State},
+    http::{header, Method},
+    middleware::Next,
+    response::Response,
+    Extension, Json,
+};
+use hedtronix_auth::Claims;
+use hedtronix_core::{AuditEventType, AuditLog, Id};
+use hedtronix_db::{AuditRepository, Database, DeviceRepository};
+
+/// State for the audit layer of one resource's routes
+#[derive(Clone)]
+pub struct AuditTrail {
+    db: Database,
+    entity_type: &'static str,
+}
+
+impl AuditTrail {
+    pub fn new(db: Database, entity_type: &'static str) -> Self {
+        Self { db, entity_type }
+    }
+}
+
+/// IDs of the records a list or search returned
+#[derive(Debug, Clone, Default)]
+pub struct AuditedEntities(pub Vec<String>);
+
+/// A list or search response, with the records it returned
+pub type Audited<T> = (Extension<AuditedEntities>, Json<T>);
+
+/// Respond with `body`, recording an audit entry for each of `ids`
+pub fn audited<T>(ids: impl IntoIterator<Item = impl ToString>, body: T) -> Audited<T> {
+    let ids = ids.into_iter().map(|id| id.to_string()).collect();
+    (Extension(AuditedEntities(ids)), Json(body))
+}
+
+/// Record the request once the handler has succeeded
+pub async fn audit_middleware(
+    State(trail): State<AuditTrail>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let method = request.method().clone();
+    let path = request
+        .extensions()
+        .get::<OriginalUri>()
+        .map(|uri| uri.path().to_string())
+        .unwrap_or_else(|| request.uri().path().to_string());
+    let claims = request.extensions().get::<Claims>().cloned();
+    let ip_address = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip().to_string());
+    let user_agent = request
+        .headers()
+        .get(header::USER_AGENT)
+        .and_then(|h| h.to_str().ok())
+        .map(str::to_string);
+
+    let response = next.run(request).await;
+
+    let Some(claims) = claims else {
+        return response;
+    };
+    if !response.status().is_success() {
+        return response;
+    }
+
+    let event_type = event_type(&method, &path);
+    let (response, entity_id) = match path_entity_id(&path) {
+        Some(id) => (response, id.to_string()),
+        // New records are only identified in the response body
+        None if event_type == AuditEventType::Create => created_entity_id(response).await,
+        None => (response, "*".to_string()),
+    };
+
+    let entity_ids = match response.extensions().get::<AuditedEntities>() {
+        Some(AuditedEntities(ids)) if !ids.is_empty() => ids.clone(),
+        _ => vec![entity_id],
+    };
+
+    let status = response.status().as_u16();
+    let recorded = tokio::task::spawn_blocking(move || {
+        let device_id = registered_device(&trail.db, &claims);
+        let mut changes = serde_json::json!({
+            "method": method.as_str(),
+            "path": path,
+            "status": status,
+        });
+        // Kept for access monitoring; the device column only holds known devices
+        if let (None, Some(claimed)) = (device_id, claims.device_id()) {
+            changes["unregistered_device_id"] = serde_json::json!(claimed);
+        }
+
+        let audit = AuditRepository::new(trail.db.clone());
+        for entity_id in entity_ids {
+            let mut log = AuditLog::new(
+                event_type,
+                claims.user_id(),
+                device_id,
+                trail.entity_type.to_string(),
+                entity_id,
+                changes.clone(),
+            );
+            log.ip_address = ip_address.clone();
+            log.user_agent = user_agent.clone();
+
+            if let Err(e) = audit.append(&log) {
+                tracing::error!("Failed to record audit entry for {} {}: {}", method, path, e);
+            }
+        }
+    })
+    .await;
+    if let Err(e) = recorded {
+        tracing::error!("Failed to record audit entry: {}", e);
+    }
+
+    response
+}
+
+/// Classify a request by method and path. Actions posted to an existing
+/// record (check-in, signing, adding an allergy) change it; searches only
+/// read.
+fn event_type(method: &Method, path: &str) -> AuditEventType {
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    if segments.contains(&"export") {
+        return AuditEventType::Export;
+    }
+
+    match *method {
+        Method::GET | Method::HEAD => AuditEventType::Read,
+        Method::PUT | Method::PATCH => AuditEventType::Update,
+        Method::DELETE => AuditEventType::Delete,
+        _ => match segments.last() {
+            Some(&"search") | Some(&"conflicts") => AuditEventType::Read,
+            _ if path_entity_id(path).is_some() => AuditEventType::Update,
+            _ => AuditEventType::Create,
+        },
+    }
+}
+
+/// The first ID in the path, e.g. the patient in `/patients/:id/allergies`
+fn path_entity_id(path: &str) -> Option<Id> {
+    path.split('/').find_map(|s| Id::parse_str(s).ok())
+}
+
+/// Read the `id` of a created record from the JSON response
+async fn created_entity_id(response: Response) -> (Response, String) {
+    let (parts, body) = response.into_parts();
+    let Ok(bytes) = axum::body::to_bytes(body, usize::MAX).await else {
+        return (Response::from_parts(parts, Body::empty()), "*".to_string());
+    };
+
+    let entity_id = serde_json::from_slice::<serde_json::Value>(&bytes)
+        .ok()
+        .and_then(|v| v.get("id").and_then(|id| id.as_str()).map(str::to_string))
+        .unwrap_or_else(|| "*".to_string());
+
+    (Response::from_parts(parts, Body::from(bytes)), entity_id)
+}
+
+/// The caller's device, if it has been registered
+fn registered_device(db: &Database, claims: &Claims) -> Option<Id> {
+    let device_id = claims.device_id()?;
+    DeviceRepository::new(db.clone())
+        .find_by_id(device_id)
+        .ok()
+        .flatten()
+        .map(|d| d.id)
+}
+
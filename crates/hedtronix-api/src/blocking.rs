@@ -0,0 +1,18 @@
+//! Running database work off the async executor
+//!
+//! Repositories and services block on SQLite. Handlers run them through
+//! `blocking`, so a slow query holds a thread of the blocking pool instead
+//! of one of the runtime's workers.
+
+use crate::error::ApiError;
+
+/// Run `f` on the blocking thread pool
+pub async fn blocking<T, F>(f: F) -> Result<T, ApiError>
+where
+    F: FnOnce() -> Result<T, ApiError> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| ApiError::internal(&e.to_string()))?
+}
@@ -1,9 +1,12 @@
 //! Server configuration
 
-use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 
-/// Server configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
+use hedtronix_crypto::SecretKey;
+
+/// Server configuration. Keys are `SecretKey`s, redacted in `Debug`; the
+/// configuration is deliberately not serializable.
+#[derive(Debug, Clone)]
 pub struct ServerConfig {
     /// Address to bind to (e.g., "0.0.0.0:8080")
     pub bind_address: String,
@@ -11,11 +14,85 @@ pub struct ServerConfig {
     /// Path to SQLite database file
     pub database_path: String,
     
-    /// JWT secret (32 bytes)
-    pub jwt_secret: Vec<u8>,
+    /// Read-only connections serving queries beside the single writer
+    pub database_readers: usize,
+    
+    /// Legacy HS256 JWT secret. When unset, tokens are signed with the
+    /// asymmetric keys stored in `jwt_keys_dir`.
+    pub jwt_secret: Option<Arc<SecretKey>>,
+    
+    /// JWT signing algorithm for generated keys ("EdDSA" or "ES256")
+    pub jwt_algorithm: String,
+    
+    /// Directory holding the JWT signing keys
+    pub jwt_keys_dir: String,
+    
+    /// Rotate the signing key once it is older than this many days (0 disables)
+    pub jwt_key_rotation_days: i64,
+    
+    /// PKCS#8 file holding the Ed25519 key that signs audit log entries;
+    /// generated on first start
+    pub audit_key_path: String,
+    
+    /// Verify the audit chain and anchor its head this often (0 disables)
+    pub audit_anchor_interval_minutes: u64,
+    
+    /// Directory holding encrypted audit archive segments
+    pub audit_archive_dir: String,
+    
+    /// Archive audit entries older than this many days
+    pub audit_retention_days: i64,
+    
+    /// Run audit log compaction this often (0 disables)
+    pub audit_compaction_interval_hours: u64,
+    
+    /// Scan new audit entries for anomalous access this often (0 disables)
+    pub access_monitor_interval_seconds: u64,
+    
+    /// JSON file overriding the default access monitoring rules
+    pub access_monitor_policy_path: Option<String>,
+    
+    /// JSON file overriding the default offline token policy
+    pub offline_policy_path: Option<String>,
+    
+    /// JSON file overriding the default MFA policy
+    pub mfa_policy_path: Option<String>,
     
-    /// Encryption key (32 bytes)
-    pub encryption_key: Vec<u8>,
+    /// JSON file overriding the default lockout and throttling policy
+    pub login_policy_path: Option<String>,
+    
+    /// JSON file overriding the default password policy
+    pub password_policy_path: Option<String>,
+    
+    /// JSON file overriding the default emergency access policy
+    pub emergency_policy_path: Option<String>,
+    
+    /// JSON file overriding the default field redaction rules
+    pub redaction_policy_path: Option<String>,
+    
+    /// Key encryption key (32 bytes) wrapping the stored data keys
+    pub encryption_key: Arc<SecretKey>,
+    
+    /// The key encryption key before the last rotation; data keys still
+    /// wrapped by it are rewrapped at startup
+    pub previous_encryption_key: Option<Arc<SecretKey>>,
+    
+    /// Key for field values written before envelope encryption, which also
+    /// derives the audit archive key. Defaults to `ENCRYPTION_KEY` as read
+    /// by earlier releases; set it explicitly before rotating `ENCRYPTION_KEY`.
+    pub legacy_encryption_key: Option<Arc<SecretKey>>,
+    
+    /// Rows processed per transaction by re-encryption and search indexing
+    pub reencryption_batch_size: u32,
+    
+    /// Wrap the key encryption key under users' passphrases instead of
+    /// taking it from `ENCRYPTION_KEY`, for workstations. Data keys still
+    /// wrapped by `ENCRYPTION_KEY` are moved to the device key at first
+    /// login.
+    pub device_lock: bool,
+    
+    /// JSON file overriding the default device lock policy
+    pub device_lock_policy_path: Option<String>,
     
     /// Log level
     pub log_level: String,
@@ -26,57 +103,185 @@ impl Default for ServerConfig {
         Self {
             bind_address: "0.0.0.0:8080".to_string(),
             database_path: "./hedtronix.db".to_string(),
-            jwt_secret: vec![0u8; 32], // Should be generated or loaded from env
-            encryption_key: vec![0u8; 32],
+            database_readers: hedtronix_db::DEFAULT_READERS,
+            jwt_secret: None,
+            jwt_algorithm: "EdDSA".to_string(),
+            jwt_keys_dir: "./keys/jwt".to_string(),
+            jwt_key_rotation_days: 30,
+            audit_key_path: "./keys/audit.ed25519".to_string(),
+            audit_anchor_interval_minutes: 60,
+            audit_archive_dir: "./data/audit-archive".to_string(),
+            audit_retention_days: 90,
+            audit_compaction_interval_hours: 24,
+            access_monitor_interval_seconds: 300,
+            access_monitor_policy_path: None,
+            offline_policy_path: None,
+            mfa_policy_path: None,
+            login_policy_path: None,
+            password_policy_path: None,
+            emergency_policy_path: None,
+            redaction_policy_path: None,
+            encryption_key: Arc::new(SecretKey::new(vec![0u8; 32])),
+            previous_encryption_key: None,
+            legacy_encryption_key: None,
+            reencryption_batch_size: hedtronix_db::DEFAULT_REENCRYPTION_BATCH,
+            device_lock: false,
+            device_lock_policy_path: None,
             log_level: "info".to_string(),
         }
     }
 }
 
 impl ServerConfig {
-    /// Load configuration from environment variables
-    pub fn from_env() -> Self {
+    /// Load configuration from environment variables. Fails when
+    /// `ENCRYPTION_KEY` is missing without device lock, or is not 32 bytes
+    /// of hex or base64.
+    pub fn from_env() -> anyhow::Result<Self> {
         let bind_address = std::env::var("BIND_ADDRESS")
             .unwrap_or_else(|_| "0.0.0.0:8080".to_string());
         
         let database_path = std::env::var("DATABASE_PATH")
             .unwrap_or_else(|_| "./hedtronix.db".to_string());
         
+        let database_readers = std::env::var("DATABASE_READERS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(hedtronix_db::DEFAULT_READERS);
+        
         let jwt_secret = std::env::var("JWT_SECRET")
-            .map(|s| s.into_bytes())
-            .unwrap_or_else(|_| {
-                // Generate a random secret if not provided
-                use hedtronix_crypto::keys::generate_encryption_key;
-                generate_encryption_key().unwrap_or_else(|_| vec![0u8; 32])
-            });
+            .ok()
+            .map(|s| Arc::new(SecretKey::new(s.into_bytes())));
+        
+        let jwt_algorithm = std::env::var("JWT_ALGORITHM")
+            .unwrap_or_else(|_| "EdDSA".to_string());
+        
+        let jwt_keys_dir = std::env::var("JWT_KEYS_DIR")
+            .unwrap_or_else(|_| "./keys/jwt".to_string());
+        
+        let jwt_key_rotation_days = std::env::var("JWT_KEY_ROTATION_DAYS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(30);
+
+        let audit_key_path = std::env::var("AUDIT_KEY_PATH")
+            .unwrap_or_else(|_| "./keys/audit.ed25519".to_string());
+
+        let audit_anchor_interval_minutes = std::env::var("AUDIT_ANCHOR_INTERVAL_MINUTES")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(60);
+
+        let audit_archive_dir = std::env::var("AUDIT_ARCHIVE_DIR")
+            .unwrap_or_else(|_| "./data/audit-archive".to_string());
+
+        let audit_retention_days = std::env::var("AUDIT_RETENTION_DAYS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(90);
+
+        let audit_compaction_interval_hours = std::env::var("AUDIT_COMPACTION_INTERVAL_HOURS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(24);
+
+        let access_monitor_interval_seconds = std::env::var("ACCESS_MONITOR_INTERVAL_SECONDS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(300);
+
+        let access_monitor_policy_path = std::env::var("ACCESS_MONITOR_POLICY_PATH").ok();
+
+        let offline_policy_path = std::env::var("OFFLINE_POLICY_PATH").ok();
+        
+        let mfa_policy_path = std::env::var("MFA_POLICY_PATH").ok();
+        
+        let login_policy_path = std::env::var("LOGIN_POLICY_PATH").ok();
+        
+        let password_policy_path = std::env::var("PASSWORD_POLICY_PATH").ok();
+        
+        let emergency_policy_path = std::env::var("EMERGENCY_ACCESS_POLICY_PATH").ok();
+        
+        let redaction_policy_path = std::env::var("REDACTION_POLICY_PATH").ok();
 
-        let encryption_key = std::env::var("ENCRYPTION_KEY")
-            .map(|s| {
-                 // Expecting hex string or raw bytes? Usually hex/base64 in env.
-                 // For simplicity, let's assume it's 32 bytes string or we handle generation.
-                 // If less than 32 bytes, pad it? 
-                 // Let's rely on standard hex decoding if possible, or just use bytes if raw string.
-                 // Ideally user provides base64.
-                 // implementation detail: if length != 32, we might fail.
-                 // For now, let's use the same logic as JWT (just bytes).
-                 let mut k = s.into_bytes();
-                 k.resize(32, 0); // simplistic padding
-                 k
-            })
-            .unwrap_or_else(|_| {
-                use hedtronix_crypto::keys::generate_encryption_key;
-                generate_encryption_key().unwrap_or_else(|_| vec![0u8; 32])
+        let device_lock = std::env::var("DEVICE_LOCK")
+            .map(|s| matches!(s.as_str(), "1" | "true"))
+            .unwrap_or(false);
+        
+        let device_lock_policy_path = std::env::var("DEVICE_LOCK_POLICY_PATH").ok();
+
+        let encryption_key = match std::env::var("ENCRYPTION_KEY") {
+            Ok(value) => parse_key("ENCRYPTION_KEY", &value)?,
+            Err(_) if device_lock => hedtronix_crypto::generate_encryption_key()
+                .map_err(|e| anyhow::anyhow!("Failed to generate an encryption key: {}", e))?,
+            // A key made up for one run would leave everything it wraps
+            // unreadable after a restart
+            Err(_) => anyhow::bail!("ENCRYPTION_KEY must be set unless DEVICE_LOCK is enabled"),
+        };
+        let encryption_key = Arc::new(encryption_key);
+        
+        let previous_encryption_key = std::env::var("PREVIOUS_ENCRYPTION_KEY")
+            .ok()
+            .map(|value| parse_key("PREVIOUS_ENCRYPTION_KEY", &value).map(Arc::new))
+            .transpose()?;
+        
+        // Earlier releases used the raw bytes of ENCRYPTION_KEY, zero-padded
+        let legacy_encryption_key = std::env::var("LEGACY_ENCRYPTION_KEY")
+            .or_else(|_| std::env::var("ENCRYPTION_KEY"))
+            .ok()
+            .map(|value| {
+                let mut key = value.into_bytes();
+                key.resize(32, 0);
+                Arc::new(SecretKey::new(key))
             });
         
+        let reencryption_batch_size = std::env::var("REENCRYPTION_BATCH_SIZE")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(hedtronix_db::DEFAULT_REENCRYPTION_BATCH);
+        
         let log_level = std::env::var("LOG_LEVEL")
             .unwrap_or_else(|_| "info".to_string());
         
-        Self {
+        Ok(Self {
             bind_address,
             database_path,
+            database_readers,
             jwt_secret,
+            jwt_algorithm,
+            jwt_keys_dir,
+            jwt_key_rotation_days,
+            audit_key_path,
+            audit_anchor_interval_minutes,
+            audit_archive_dir,
+            audit_retention_days,
+            audit_compaction_interval_hours,
+            access_monitor_interval_seconds,
+            access_monitor_policy_path,
+            offline_policy_path,
+            mfa_policy_path,
+            login_policy_path,
+            password_policy_path,
+            emergency_policy_path,
+            redaction_policy_path,
             encryption_key,
+            previous_encryption_key,
+            legacy_encryption_key,
+            reencryption_batch_size,
+            device_lock,
+            device_lock_policy_path,
             log_level,
-        }
+        })
     }
 }
+
+impl ServerConfig {
+    /// Key for pre-envelope field values and the audit archive
+    pub fn legacy_key(&self) -> &Arc<SecretKey> {
+        self.legacy_encryption_key.as_ref().unwrap_or(&self.encryption_key)
+    }
+}
+
+fn parse_key(name: &str, value: &str) -> anyhow::Result<SecretKey> {
+    hedtronix_crypto::parse_encryption_key(value)
+        .map_err(|_| anyhow::anyhow!("{} must be 32 bytes encoded as 64 hex digits or base64", name))
+}
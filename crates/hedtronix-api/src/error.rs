@@ -94,18 +94,52 @@ impl From<hedtronix_auth::SessionError> for ApiError {
             hedtronix_auth::SessionError::InvalidCredentials => {
                 ApiError::unauthorized("Invalid email or password")
             }
+            // Only reachable with a token for a deleted account; login
+            // answers an unknown email as a wrong password
             hedtronix_auth::SessionError::UserNotFound => {
-                ApiError::not_found("User")
+                ApiError::unauthorized("Invalid credentials")
             }
             hedtronix_auth::SessionError::UserDisabled => {
                 ApiError::forbidden("User account is disabled")
             }
+            hedtronix_auth::SessionError::AccountLocked(until) => {
+                ApiError::new(
+                    StatusCode::LOCKED,
+                    "Locked",
+                    &format!("Account is locked until {}", until.to_rfc3339()),
+                ).with_code("ACCOUNT_LOCKED")
+            }
+            hedtronix_auth::SessionError::Throttled(seconds) => {
+                ApiError::new(
+                    StatusCode::TOO_MANY_REQUESTS,
+                    "Too Many Requests",
+                    &format!("Too many login attempts; retry in {} seconds", seconds),
+                ).with_code("LOGIN_THROTTLED")
+            }
+            hedtronix_auth::SessionError::PasswordPolicy(msg) => {
+                ApiError::validation(&msg)
+            }
             hedtronix_auth::SessionError::DeviceNotRegistered => {
                 ApiError::unauthorized("Device not registered")
             }
             hedtronix_auth::SessionError::DeviceRevoked => {
                 ApiError::forbidden("Device has been revoked")
             }
+            hedtronix_auth::SessionError::InvalidDeviceKey(msg) => {
+                ApiError::validation(&msg)
+            }
+            hedtronix_auth::SessionError::OfflineTokenDenied(msg) => {
+                ApiError::forbidden(&msg)
+            }
+            hedtronix_auth::SessionError::PasswordChangeRequired => {
+                ApiError::forbidden("The password must be changed before continuing")
+            }
+            hedtronix_auth::SessionError::InvalidMfaCode => {
+                ApiError::unauthorized("Invalid MFA code")
+            }
+            hedtronix_auth::SessionError::Mfa(msg) => {
+                ApiError::forbidden(&msg)
+            }
             hedtronix_auth::SessionError::Token(msg) => {
                 ApiError::unauthorized(&msg)
             }
@@ -116,6 +150,63 @@ impl From<hedtronix_auth::SessionError> for ApiError {
     }
 }
 
+impl From<hedtronix_auth::AuthorizationError> for ApiError {
+    fn from(e: hedtronix_auth::AuthorizationError) -> Self {
+        match e {
+            hedtronix_auth::AuthorizationError::RoleNotFound(_) => ApiError::not_found("Role"),
+            hedtronix_auth::AuthorizationError::BuiltInRole(_)
+            | hedtronix_auth::AuthorizationError::RoleInUse(_) => ApiError::conflict(&e.to_string()),
+            hedtronix_auth::AuthorizationError::Invalid(msg) => ApiError::validation(&msg),
+            hedtronix_auth::AuthorizationError::NotFound(entity) => ApiError::not_found(entity),
+            hedtronix_auth::AuthorizationError::Denied(reason) => ApiError::forbidden(reason),
+            hedtronix_auth::AuthorizationError::Database(msg) => ApiError::internal(&msg),
+        }
+    }
+}
+
+impl From<hedtronix_auth::EmergencyAccessError> for ApiError {
+    fn from(e: hedtronix_auth::EmergencyAccessError) -> Self {
+        match e {
+            hedtronix_auth::EmergencyAccessError::NotPermitted(_)
+            | hedtronix_auth::EmergencyAccessError::InvalidGrant(_) => ApiError::forbidden(&e.to_string()),
+            hedtronix_auth::EmergencyAccessError::ReasonTooShort(_) => ApiError::validation(&e.to_string()),
+            hedtronix_auth::EmergencyAccessError::PatientNotFound => ApiError::not_found("Patient"),
+            hedtronix_auth::EmergencyAccessError::GrantNotFound => ApiError::not_found("Emergency access grant"),
+            hedtronix_auth::EmergencyAccessError::AlreadyReviewed => ApiError::conflict(&e.to_string()),
+            hedtronix_auth::EmergencyAccessError::Database(msg) => ApiError::internal(&msg),
+        }
+    }
+}
+
+impl From<hedtronix_auth::AccessMonitorError> for ApiError {
+    fn from(e: hedtronix_auth::AccessMonitorError) -> Self {
+        match e {
+            hedtronix_auth::AccessMonitorError::NotPermitted(_) => ApiError::forbidden(&e.to_string()),
+            hedtronix_auth::AccessMonitorError::PatientNotFound => ApiError::not_found("Patient"),
+            hedtronix_auth::AccessMonitorError::AlertNotFound => ApiError::not_found("Access alert"),
+            hedtronix_auth::AccessMonitorError::AlreadyReviewed => ApiError::conflict(&e.to_string()),
+            hedtronix_auth::AccessMonitorError::InvalidReview(msg) => ApiError::validation(&msg),
+            hedtronix_auth::AccessMonitorError::Database(msg) => ApiError::internal(&msg),
+        }
+    }
+}
+
+impl From<hedtronix_auth::DeviceLockError> for ApiError {
+    fn from(e: hedtronix_auth::DeviceLockError) -> Self {
+        match e {
+            hedtronix_auth::DeviceLockError::NotPermitted(_) => ApiError::forbidden(&e.to_string()),
+            hedtronix_auth::DeviceLockError::Locked => {
+                ApiError::new(StatusCode::LOCKED, "Locked", &e.to_string()).with_code("DEVICE_LOCKED")
+            }
+            hedtronix_auth::DeviceLockError::NotEnrolled => ApiError::not_found("Device key wrapping"),
+            hedtronix_auth::DeviceLockError::InvalidPassphrase => ApiError::unauthorized(&e.to_string()),
+            hedtronix_auth::DeviceLockError::LastWrapping => ApiError::conflict(&e.to_string()),
+            hedtronix_auth::DeviceLockError::Key(msg)
+            | hedtronix_auth::DeviceLockError::Database(msg) => ApiError::internal(&msg),
+        }
+    }
+}
+
 impl From<hedtronix_sync::SyncError> for ApiError {
     fn from(e: hedtronix_sync::SyncError) -> Self {
         match e {
@@ -124,6 +215,8 @@ impl From<hedtronix_sync::SyncError> for ApiError {
             hedtronix_sync::SyncError::Database(msg) => ApiError::internal(&msg),
             hedtronix_sync::SyncError::Serialization(msg) => ApiError::bad_request(&msg),
             hedtronix_sync::SyncError::SyncInProgress => ApiError::conflict("Sync already in progress"),
+            hedtronix_sync::SyncError::Envelope(msg) => ApiError::validation(&msg),
+            hedtronix_sync::SyncError::NotPermitted(msg) => ApiError::forbidden(&msg),
         }
     }
 }
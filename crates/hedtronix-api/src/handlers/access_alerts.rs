@@ -0,0 +1,138 @@
+//! Anomalous access alert queue handlers
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use hedtronix_auth::{AccessAlertPage, AccessScanReport, Claims};
+use hedtronix_core::{
+    AccessAlert, AccessAlertFilters, AccessAlertRule, AccessAlertStatus, Id, ReviewAccessAlert,
+    VipPatient,
+};
+use serde::Deserialize;
+
+use crate::blocking::blocking;
+use crate::error::ApiError;
+use crate::state::AppState;
+
+/// Query string for the alert queue
+#[derive(Debug, Deserialize)]
+pub struct AccessAlertQuery {
+    pub status: Option<AccessAlertStatus>,
+    pub rule: Option<AccessAlertRule>,
+    pub user_id: Option<Id>,
+    pub patient_id: Option<Id>,
+    pub page: Option<u32>,
+    pub limit: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FlagVipRequest {
+    pub patient_id: Id,
+    pub reason: Option<String>,
+}
+
+fn parse_id(id: &str, what: &str) -> Result<Id, ApiError> {
+    Id::parse_str(id).map_err(|_| ApiError::bad_request(&format!("Invalid {} ID", what)))
+}
+
+/// Alerts matching the filters, newest first
+pub async fn list_alerts(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Query(query): Query<AccessAlertQuery>,
+) -> Result<Json<AccessAlertPage>, ApiError> {
+    blocking(move || {
+        let filters = AccessAlertFilters {
+            status: query.status,
+            rule: query.rule,
+            user_id: query.user_id,
+            patient_id: query.patient_id,
+            page: query.page.unwrap_or(0),
+            limit: query.limit.unwrap_or(50).min(500),
+        };
+
+        Ok(Json(state.access_monitor().alerts(&claims, &filters)?))
+    })
+    .await
+}
+
+pub async fn get_alert(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<String>,
+) -> Result<Json<AccessAlert>, ApiError> {
+    blocking(move || {
+        let alert_id = parse_id(&id, "alert")?;
+        Ok(Json(state.access_monitor().find(&claims, alert_id)?))
+    })
+    .await
+}
+
+/// Escalate or dismiss an open alert
+pub async fn review_alert(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<String>,
+    Json(req): Json<ReviewAccessAlert>,
+) -> Result<Json<AccessAlert>, ApiError> {
+    blocking(move || {
+        let alert_id = parse_id(&id, "alert")?;
+        Ok(Json(state.access_monitor().review(&claims, alert_id, req)?))
+    })
+    .await
+}
+
+/// Scan new audit entries now rather than waiting for the background scan
+pub async fn scan(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Json<AccessScanReport>, ApiError> {
+    let monitor = state.access_monitor();
+    if !monitor.can_manage(&claims) {
+        return Err(ApiError::forbidden("Scanning requires the access_alerts:manage permission"));
+    }
+
+    let report = tokio::task::spawn_blocking(move || monitor.scan())
+        .await
+        .map_err(|e| ApiError::internal(&e.to_string()))??;
+
+    Ok(Json(report))
+}
+
+pub async fn list_vip_patients(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Json<Vec<VipPatient>>, ApiError> {
+    blocking(move || {
+        Ok(Json(state.access_monitor().vip_patients(&claims)?))
+    })
+    .await
+}
+
+/// Flag a patient for VIP access monitoring
+pub async fn flag_vip_patient(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(req): Json<FlagVipRequest>,
+) -> Result<(StatusCode, Json<VipPatient>), ApiError> {
+    blocking(move || {
+        let vip = state.access_monitor().flag_vip(&claims, req.patient_id, req.reason)?;
+        Ok((StatusCode::CREATED, Json(vip)))
+    })
+    .await
+}
+
+pub async fn unflag_vip_patient(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(patient_id): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    blocking(move || {
+        let patient_id = parse_id(&patient_id, "patient")?;
+        state.access_monitor().unflag_vip(&claims, patient_id)?;
+        Ok(StatusCode::NO_CONTENT)
+    })
+    .await
+}
@@ -6,7 +6,7 @@ use serde_json::json;
 
 /// GET /analytics/metrics
 /// Returns a placeholder set of operational metrics as defined in specs.
-pub async fn get_metrics(Extension(state): Extension<AppState>) -> impl IntoResponse {
+pub async fn get_metrics(Extension(_state): Extension<AppState>) -> impl IntoResponse {
     // TODO: Integrate with real metrics collection and storage.
     let metrics = json!({
         "operational": {
@@ -75,7 +75,7 @@ pub async fn get_metrics(Extension(state): Extension<AppState>) -> impl IntoResp
 
 /// GET /analytics/report
 /// Placeholder for dynamic dashboard generation.
-pub async fn get_report(Extension(state): Extension<AppState>) -> impl IntoResponse {
+pub async fn get_report(Extension(_state): Extension<AppState>) -> impl IntoResponse {
     // In a full implementation this would generate a report based on stored analytics data.
     let report = json!({
         "message": "Analytics reporting endpoint – implementation pending"
@@ -4,43 +4,68 @@ use axum::{
     extract::{Path, Query, State},
     Json,
 };
+use hedtronix_auth::{AccessPolicy, AuthorizationError, Subject};
 use hedtronix_core::{
-    Appointment, AppointmentType, AppointmentStatus, CalendarFilters, Id,
+    Appointment, AppointmentType, CalendarFilters, Id,
 };
 use hedtronix_db::AppointmentRepository;
 use serde::{Deserialize, Serialize};
 
+use crate::access::{self, Caller};
+use crate::audit::{audited, Audited};
+use crate::blocking::blocking;
 use crate::error::ApiError;
 use crate::state::AppState;
 
 /// List appointments
 pub async fn list_appointments(
     State(state): State<AppState>,
+    caller: Caller,
     Query(query): Query<CalendarQuery>,
-) -> Result<Json<ListAppointmentsResponse>, ApiError> {
-    let repo = AppointmentRepository::new(state.db.clone());
-    
-    // Default to today's appointments if no date range specified
-    let now = chrono::Utc::now();
-    let start = query.start.unwrap_or_else(|| now.date_naive().and_hms_opt(0, 0, 0).unwrap());
-    let end = query.end.unwrap_or_else(|| now.date_naive().and_hms_opt(23, 59, 59).unwrap());
-    
-    let filters = CalendarFilters {
-        start_date: chrono::DateTime::from_naive_utc_and_offset(start, chrono::Utc),
-        end_date: chrono::DateTime::from_naive_utc_and_offset(end, chrono::Utc),
-        ..Default::default()
-    };
-    
-    // For simplicity, getting all appointments (in production, would filter by provider)
-    let provider_id = Id::parse_str(&query.provider_id.unwrap_or_default())
-        .unwrap_or_else(|_| Id::new_v4());
-    
-    let appointments = repo.find_by_provider(provider_id, &filters)
-        .map_err(|e| ApiError::internal(&e.to_string()))?;
-    
-    Ok(Json(ListAppointmentsResponse {
-        appointments: appointments.into_iter().map(AppointmentDto::from).collect(),
-    }))
+) -> Result<Audited<ListAppointmentsResponse>, ApiError> {
+    blocking(move || {
+        access::require_permission(&state, &caller, "appointments", "read")?;
+        let repo = AppointmentRepository::new(state.db.clone());
+    
+        // Default to today's appointments if no date range specified
+        let now = chrono::Utc::now();
+        let start = query.start.unwrap_or_else(|| now.date_naive().and_hms_opt(0, 0, 0).unwrap());
+        let end = query.end.unwrap_or_else(|| now.date_naive().and_hms_opt(23, 59, 59).unwrap());
+    
+        let filters = CalendarFilters {
+            start_date: chrono::DateTime::from_naive_utc_and_offset(start, chrono::Utc),
+            end_date: chrono::DateTime::from_naive_utc_and_offset(end, chrono::Utc),
+            ..Default::default()
+        };
+    
+        let policy = state.access_policy();
+        let subject = policy.subject(&caller.claims)?;
+    
+        let appointments = if !subject.allows("appointments", "read") {
+            // Patients, who only hold `appointments:read_own`, list their own
+            // appointments whatever provider is asked for
+            let Some(patient_id) = subject.patient_id else {
+                return Ok(audited(Vec::<Id>::new(), ListAppointmentsResponse { appointments: Vec::new() }));
+            };
+            repo.find_by_patient(patient_id)
+                .map_err(|e| ApiError::internal(&e.to_string()))?
+                .into_iter()
+                .filter(|a| a.start_time >= filters.start_date && a.start_time <= filters.end_date)
+                .collect()
+        } else {
+            // Default to the caller's own schedule
+            let provider_id = query.provider_id
+                .and_then(|id| Id::parse_str(&id).ok())
+                .unwrap_or(subject.user_id);
+            repo.find_by_provider(provider_id, &filters)
+                .map_err(|e| ApiError::internal(&e.to_string()))?
+        };
+    
+        let appointments = visible_appointments(&policy, &subject, appointments)?;
+        let ids: Vec<String> = appointments.iter().map(|a| a.id.clone()).collect();
+        Ok(audited(ids, ListAppointmentsResponse { appointments }))
+    })
+    .await
 }
 
 #[derive(Debug, Deserialize)]
@@ -58,69 +83,86 @@ pub struct ListAppointmentsResponse {
 /// Get appointment by ID
 pub async fn get_appointment(
     State(state): State<AppState>,
+    caller: Caller,
     Path(id): Path<String>,
 ) -> Result<Json<AppointmentDto>, ApiError> {
-    let apt_id = Id::parse_str(&id)
-        .map_err(|_| ApiError::bad_request("Invalid appointment ID"))?;
-    
-    let repo = AppointmentRepository::new(state.db.clone());
-    let appointment = repo.find_by_id(apt_id)
-        .map_err(|e| ApiError::internal(&e.to_string()))?
-        .ok_or_else(|| ApiError::not_found("Appointment"))?;
-    
-    Ok(Json(AppointmentDto::from(appointment)))
+    blocking(move || {
+        let apt_id = Id::parse_str(&id)
+            .map_err(|_| ApiError::bad_request("Invalid appointment ID"))?;
+        access::require_permission(&state, &caller, "appointments", "read")?;
+    
+        let repo = AppointmentRepository::new(state.db.clone());
+        let appointment = repo.find_by_id(apt_id)
+            .map_err(|e| ApiError::internal(&e.to_string()))?
+            .ok_or_else(|| ApiError::not_found("Appointment"))?;
+        access::require_appointment(&state, &caller, &appointment)?;
+    
+        Ok(Json(AppointmentDto::from(appointment)))
+    })
+    .await
 }
 
 /// Create new appointment
 pub async fn create_appointment(
     State(state): State<AppState>,
+    caller: Caller,
     Json(req): Json<CreateAppointmentRequest>,
 ) -> Result<Json<AppointmentDto>, ApiError> {
-    let patient_id = Id::parse_str(&req.patient_id)
-        .map_err(|_| ApiError::bad_request("Invalid patient ID"))?;
-    let provider_id = Id::parse_str(&req.provider_id)
-        .map_err(|_| ApiError::bad_request("Invalid provider ID"))?;
-    let created_by = Id::parse_str(&req.created_by.unwrap_or_default())
-        .unwrap_or_else(|_| Id::new_v4());
-    
-    let start_time = chrono::DateTime::parse_from_rfc3339(&req.start_time)
-        .map_err(|_| ApiError::bad_request("Invalid start time format"))?
-        .with_timezone(&chrono::Utc);
-    
-    let apt_type = parse_appointment_type(&req.appointment_type)?;
-    
-    // Check for conflicts
-    let repo = AppointmentRepository::new(state.db.clone());
-    let end_time = start_time + chrono::Duration::minutes(req.duration as i64);
-    let conflicts = repo.check_conflicts(provider_id, start_time, end_time, None)
-        .map_err(|e| ApiError::internal(&e.to_string()))?;
-    
-    if !conflicts.is_empty() {
-        return Err(ApiError::conflict("Provider has conflicting appointments"));
-    }
+    blocking(move || {
+        let patient_id = Id::parse_str(&req.patient_id)
+            .map_err(|_| ApiError::bad_request("Invalid patient ID"))?;
+        let provider_id = Id::parse_str(&req.provider_id)
+            .map_err(|_| ApiError::bad_request("Invalid provider ID"))?;
+    
+        access::require_permission(&state, &caller, "appointments", "create")?;
+        let policy = state.access_policy();
+        let subject = policy.subject(&caller.claims)?;
+        policy.require_patient(&subject, patient_id)?;
+    
+        let created_by = req.created_by
+            .and_then(|id| Id::parse_str(&id).ok())
+            .unwrap_or(subject.user_id);
+    
+        let start_time = chrono::DateTime::parse_from_rfc3339(&req.start_time)
+            .map_err(|_| ApiError::bad_request("Invalid start time format"))?
+            .with_timezone(&chrono::Utc);
+    
+        let apt_type = parse_appointment_type(&req.appointment_type)?;
+    
+        // Check for conflicts
+        let repo = AppointmentRepository::new(state.db.clone());
+        let end_time = start_time + chrono::Duration::minutes(req.duration as i64);
+        let conflicts = repo.check_conflicts(provider_id, start_time, end_time, None)
+            .map_err(|e| ApiError::internal(&e.to_string()))?;
+    
+        if !conflicts.is_empty() {
+            return Err(ApiError::conflict("Provider has conflicting appointments"));
+        }
     
-    let appointment = Appointment::new(
-        patient_id,
-        provider_id,
-        start_time,
-        req.duration,
-        apt_type,
-        req.reason_for_visit,
-        created_by,
-    );
-    
-    repo.create(&appointment)
-        .map_err(|e| ApiError::internal(&e.to_string()))?;
-    
-    // Track for sync
-    let sync_engine = state.sync_engine();
-    let _ = sync_engine.track_create(
-        "Appointment",
-        appointment.id,
-        serde_json::to_value(&appointment).unwrap_or_default(),
-    );
-    
-    Ok(Json(AppointmentDto::from(appointment)))
+        let appointment = Appointment::new(
+            patient_id,
+            provider_id,
+            start_time,
+            req.duration,
+            apt_type,
+            req.reason_for_visit,
+            created_by,
+        );
+    
+        repo.create(&appointment)
+            .map_err(|e| ApiError::internal(&e.to_string()))?;
+    
+        // Track for sync
+        let sync_engine = state.sync_engine();
+        let _ = sync_engine.track_create(
+            "Appointment",
+            appointment.id,
+            serde_json::to_value(&appointment).unwrap_or_default(),
+        );
+    
+        Ok(Json(AppointmentDto::from(appointment)))
+    })
+    .await
 }
 
 #[derive(Debug, Deserialize)]
@@ -137,29 +179,35 @@ pub struct CreateAppointmentRequest {
 /// Update appointment
 pub async fn update_appointment(
     State(state): State<AppState>,
+    caller: Caller,
     Path(id): Path<String>,
     Json(req): Json<UpdateAppointmentRequest>,
 ) -> Result<Json<AppointmentDto>, ApiError> {
-    let apt_id = Id::parse_str(&id)
-        .map_err(|_| ApiError::bad_request("Invalid appointment ID"))?;
-    
-    let repo = AppointmentRepository::new(state.db.clone());
-    let mut appointment = repo.find_by_id(apt_id)
-        .map_err(|e| ApiError::internal(&e.to_string()))?
-        .ok_or_else(|| ApiError::not_found("Appointment"))?;
-    
-    if let Some(notes) = req.notes {
-        appointment.notes = Some(notes);
-    }
-    if let Some(reason) = req.reason_for_visit {
-        appointment.reason_for_visit = reason;
-    }
-    appointment.updated_at = chrono::Utc::now();
+    blocking(move || {
+        let apt_id = Id::parse_str(&id)
+            .map_err(|_| ApiError::bad_request("Invalid appointment ID"))?;
+        access::require_permission(&state, &caller, "appointments", "write")?;
+    
+        let repo = AppointmentRepository::new(state.db.clone());
+        let mut appointment = repo.find_by_id(apt_id)
+            .map_err(|e| ApiError::internal(&e.to_string()))?
+            .ok_or_else(|| ApiError::not_found("Appointment"))?;
+        access::require_appointment(&state, &caller, &appointment)?;
+    
+        if let Some(notes) = req.notes {
+            appointment.notes = Some(notes);
+        }
+        if let Some(reason) = req.reason_for_visit {
+            appointment.reason_for_visit = reason;
+        }
+        appointment.updated_at = chrono::Utc::now();
     
-    repo.update(&appointment)
-        .map_err(|e| ApiError::internal(&e.to_string()))?;
+        repo.update(&appointment)
+            .map_err(|e| ApiError::internal(&e.to_string()))?;
     
-    Ok(Json(AppointmentDto::from(appointment)))
+        Ok(Json(AppointmentDto::from(appointment)))
+    })
+    .await
 }
 
 #[derive(Debug, Deserialize)]
@@ -171,23 +219,29 @@ pub struct UpdateAppointmentRequest {
 /// Cancel appointment
 pub async fn cancel_appointment(
     State(state): State<AppState>,
+    caller: Caller,
     Path(id): Path<String>,
     Json(req): Json<CancelRequest>,
 ) -> Result<Json<AppointmentDto>, ApiError> {
-    let apt_id = Id::parse_str(&id)
-        .map_err(|_| ApiError::bad_request("Invalid appointment ID"))?;
+    blocking(move || {
+        let apt_id = Id::parse_str(&id)
+            .map_err(|_| ApiError::bad_request("Invalid appointment ID"))?;
+        access::require_permission(&state, &caller, "appointments", "cancel")?;
     
-    let repo = AppointmentRepository::new(state.db.clone());
-    let mut appointment = repo.find_by_id(apt_id)
-        .map_err(|e| ApiError::internal(&e.to_string()))?
-        .ok_or_else(|| ApiError::not_found("Appointment"))?;
+        let repo = AppointmentRepository::new(state.db.clone());
+        let mut appointment = repo.find_by_id(apt_id)
+            .map_err(|e| ApiError::internal(&e.to_string()))?
+            .ok_or_else(|| ApiError::not_found("Appointment"))?;
+        access::require_appointment(&state, &caller, &appointment)?;
     
-    appointment.cancel(req.reason.unwrap_or_else(|| "Cancelled".to_string()));
+        appointment.cancel(req.reason.unwrap_or_else(|| "Cancelled".to_string()));
     
-    repo.update(&appointment)
-        .map_err(|e| ApiError::internal(&e.to_string()))?;
+        repo.update(&appointment)
+            .map_err(|e| ApiError::internal(&e.to_string()))?;
     
-    Ok(Json(AppointmentDto::from(appointment)))
+        Ok(Json(AppointmentDto::from(appointment)))
+    })
+    .await
 }
 
 #[derive(Debug, Deserialize)]
@@ -198,71 +252,92 @@ pub struct CancelRequest {
 /// Check in patient
 pub async fn check_in(
     State(state): State<AppState>,
+    caller: Caller,
     Path(id): Path<String>,
 ) -> Result<Json<AppointmentDto>, ApiError> {
-    let apt_id = Id::parse_str(&id)
-        .map_err(|_| ApiError::bad_request("Invalid appointment ID"))?;
+    blocking(move || {
+        let apt_id = Id::parse_str(&id)
+            .map_err(|_| ApiError::bad_request("Invalid appointment ID"))?;
+        access::require_permission(&state, &caller, "appointments", "check_in")?;
     
-    let repo = AppointmentRepository::new(state.db.clone());
-    let mut appointment = repo.find_by_id(apt_id)
-        .map_err(|e| ApiError::internal(&e.to_string()))?
-        .ok_or_else(|| ApiError::not_found("Appointment"))?;
+        let repo = AppointmentRepository::new(state.db.clone());
+        let mut appointment = repo.find_by_id(apt_id)
+            .map_err(|e| ApiError::internal(&e.to_string()))?
+            .ok_or_else(|| ApiError::not_found("Appointment"))?;
+        access::require_appointment(&state, &caller, &appointment)?;
     
-    appointment.check_in();
+        appointment.check_in();
     
-    repo.update(&appointment)
-        .map_err(|e| ApiError::internal(&e.to_string()))?;
+        repo.update(&appointment)
+            .map_err(|e| ApiError::internal(&e.to_string()))?;
     
-    Ok(Json(AppointmentDto::from(appointment)))
+        Ok(Json(AppointmentDto::from(appointment)))
+    })
+    .await
 }
 
 /// Complete appointment
 pub async fn complete(
     State(state): State<AppState>,
+    caller: Caller,
     Path(id): Path<String>,
 ) -> Result<Json<AppointmentDto>, ApiError> {
-    let apt_id = Id::parse_str(&id)
-        .map_err(|_| ApiError::bad_request("Invalid appointment ID"))?;
+    blocking(move || {
+        let apt_id = Id::parse_str(&id)
+            .map_err(|_| ApiError::bad_request("Invalid appointment ID"))?;
+        access::require_permission(&state, &caller, "appointments", "write")?;
     
-    let repo = AppointmentRepository::new(state.db.clone());
-    let mut appointment = repo.find_by_id(apt_id)
-        .map_err(|e| ApiError::internal(&e.to_string()))?
-        .ok_or_else(|| ApiError::not_found("Appointment"))?;
+        let repo = AppointmentRepository::new(state.db.clone());
+        let mut appointment = repo.find_by_id(apt_id)
+            .map_err(|e| ApiError::internal(&e.to_string()))?
+            .ok_or_else(|| ApiError::not_found("Appointment"))?;
+        access::require_appointment(&state, &caller, &appointment)?;
     
-    appointment.complete();
+        appointment.complete();
     
-    repo.update(&appointment)
-        .map_err(|e| ApiError::internal(&e.to_string()))?;
+        repo.update(&appointment)
+            .map_err(|e| ApiError::internal(&e.to_string()))?;
     
-    Ok(Json(AppointmentDto::from(appointment)))
+        Ok(Json(AppointmentDto::from(appointment)))
+    })
+    .await
 }
 
 /// Check for conflicts
 pub async fn check_conflicts(
     State(state): State<AppState>,
+    caller: Caller,
     Json(req): Json<ConflictCheckRequest>,
 ) -> Result<Json<ConflictCheckResponse>, ApiError> {
-    let provider_id = Id::parse_str(&req.provider_id)
-        .map_err(|_| ApiError::bad_request("Invalid provider ID"))?;
-    
-    let start_time = chrono::DateTime::parse_from_rfc3339(&req.start_time)
-        .map_err(|_| ApiError::bad_request("Invalid start time"))?
-        .with_timezone(&chrono::Utc);
-    
-    let end_time = chrono::DateTime::parse_from_rfc3339(&req.end_time)
-        .map_err(|_| ApiError::bad_request("Invalid end time"))?
-        .with_timezone(&chrono::Utc);
-    
-    let exclude_id = req.exclude_id.and_then(|s| Id::parse_str(&s).ok());
-    
-    let repo = AppointmentRepository::new(state.db.clone());
-    let conflicts = repo.check_conflicts(provider_id, start_time, end_time, exclude_id)
-        .map_err(|e| ApiError::internal(&e.to_string()))?;
-    
-    Ok(Json(ConflictCheckResponse {
-        has_conflicts: !conflicts.is_empty(),
-        conflicts: conflicts.into_iter().map(AppointmentDto::from).collect(),
-    }))
+    blocking(move || {
+        access::require_permission(&state, &caller, "appointments", "read")?;
+        let provider_id = Id::parse_str(&req.provider_id)
+            .map_err(|_| ApiError::bad_request("Invalid provider ID"))?;
+    
+        let start_time = chrono::DateTime::parse_from_rfc3339(&req.start_time)
+            .map_err(|_| ApiError::bad_request("Invalid start time"))?
+            .with_timezone(&chrono::Utc);
+    
+        let end_time = chrono::DateTime::parse_from_rfc3339(&req.end_time)
+            .map_err(|_| ApiError::bad_request("Invalid end time"))?
+            .with_timezone(&chrono::Utc);
+    
+        let exclude_id = req.exclude_id.and_then(|s| Id::parse_str(&s).ok());
+    
+        let repo = AppointmentRepository::new(state.db.clone());
+        let conflicts = repo.check_conflicts(provider_id, start_time, end_time, exclude_id)
+            .map_err(|e| ApiError::internal(&e.to_string()))?;
+    
+        // Report the clash without revealing appointments the caller may not see
+        let policy = state.access_policy();
+        let subject = policy.subject(&caller.claims)?;
+    
+        Ok(Json(ConflictCheckResponse {
+            has_conflicts: !conflicts.is_empty(),
+            conflicts: visible_appointments(&policy, &subject, conflicts)?,
+        }))
+    })
+    .await
 }
 
 #[derive(Debug, Deserialize)]
@@ -282,14 +357,15 @@ pub struct ConflictCheckResponse {
 /// Get calendar view
 pub async fn get_calendar(
     State(state): State<AppState>,
+    caller: Caller,
     Query(query): Query<CalendarQuery>,
-) -> Result<Json<CalendarResponse>, ApiError> {
+) -> Result<Audited<CalendarResponse>, ApiError> {
     // Same as list_appointments but formatted for calendar
-    let response = list_appointments(State(state), Query(query)).await?;
+    let (audited, Json(response)) = list_appointments(State(state), caller, Query(query)).await?;
     
-    Ok(Json(CalendarResponse {
-        appointments: response.0.appointments,
-    }))
+    Ok((audited, Json(CalendarResponse {
+        appointments: response.appointments,
+    })))
 }
 
 #[derive(Debug, Serialize)]
@@ -298,6 +374,23 @@ pub struct CalendarResponse {
 }
 
 // Helper functions
+
+fn visible_appointments(
+    policy: &AccessPolicy,
+    subject: &Subject,
+    appointments: Vec<Appointment>,
+) -> Result<Vec<AppointmentDto>, ApiError> {
+    let mut visible = Vec::with_capacity(appointments.len());
+    for appointment in appointments {
+        match policy.require_appointment(subject, &appointment) {
+            Ok(_) => visible.push(AppointmentDto::from(appointment)),
+            Err(AuthorizationError::Denied(_)) | Err(AuthorizationError::NotFound(_)) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(visible)
+}
+
 fn parse_appointment_type(s: &str) -> Result<AppointmentType, ApiError> {
     match s.to_uppercase().as_str() {
         "NEW_PATIENT" => Ok(AppointmentType::NewPatient),
@@ -1,21 +1,278 @@
 //! Audit Log handlers for Hedtronix
 
-use axum::{extract::Extension, Json, response::IntoResponse};
+use axum::{
+    extract::{Path, Query, State},
+    Extension, Json,
+};
+use hedtronix_auth::Claims;
+use hedtronix_core::{AuditEventType, AuditLog, AuditLogFilters, Id, Timestamp};
+use hedtronix_db::{
+    ArchivedAuditLogs, AuditArchiveSegment, AuditChainReport, AuditChainVerifier,
+    AuditCompactionReport, AuditRepository, DeviceAuditRepository, ReplicatedAuditLog,
+    DEFAULT_SEGMENT_SIZE,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::blocking::blocking;
+use crate::error::ApiError;
 use crate::state::AppState;
-use serde_json::json;
-
-/// GET /audit/logs
-/// Returns a placeholder list of audit log entries.
-pub async fn list_audit_logs(Extension(_state): Extension<AppState>) -> impl IntoResponse {
-    // TODO: Integrate with actual audit log storage and filtering.
-    let logs = json!([]);
-    Json(logs)
-}
-
-/// GET /audit/logs/:id
-/// Placeholder for fetching a specific audit log entry.
-pub async fn get_audit_log(Extension(_state): Extension<AppState>) -> impl IntoResponse {
-    // Implementation pending.
-    let response = json!({"message": "Audit log detail endpoint – implementation pending"});
-    Json(response)
+
+/// Only callers granted `audit_logs:read` may query the audit log
+fn require_read(state: &AppState, claims: &Claims) -> Result<(), ApiError> {
+    if state.auth_state.permissions.authorize(claims, "audit_logs", "read") {
+        Ok(())
+    } else {
+        Err(ApiError::forbidden("Reading the audit log requires the audit_logs:read permission"))
+    }
+}
+
+/// Only callers granted `audit_logs:verify` may run integrity checks
+fn require_verify(state: &AppState, claims: &Claims) -> Result<(), ApiError> {
+    if state.auth_state.permissions.authorize(claims, "audit_logs", "verify") {
+        Ok(())
+    } else {
+        Err(ApiError::forbidden("Verifying the audit log requires the audit_logs:verify permission"))
+    }
+}
+
+/// Only callers granted `audit_logs:archive` may compact the audit log
+fn require_archive(state: &AppState, claims: &Claims) -> Result<(), ApiError> {
+    if state.auth_state.permissions.authorize(claims, "audit_logs", "archive") {
+        Ok(())
+    } else {
+        Err(ApiError::forbidden("Archiving the audit log requires the audit_logs:archive permission"))
+    }
+}
+
+/// Query string for the audit log search
+#[derive(Debug, Deserialize)]
+pub struct AuditLogQuery {
+    pub user_id: Option<Id>,
+    pub device_id: Option<Id>,
+    pub entity_type: Option<String>,
+    pub entity_id: Option<String>,
+    /// Comma-separated, e.g. `READ,EXPORT`
+    pub event_types: Option<String>,
+    pub start_time: Option<Timestamp>,
+    pub end_time: Option<Timestamp>,
+    pub page: Option<u32>,
+    pub limit: Option<u32>,
+    /// Sequence range; only used when searching the archive
+    pub from_sequence: Option<i64>,
+    pub to_sequence: Option<i64>,
+}
+
+impl AuditLogQuery {
+    fn into_filters(self) -> Result<AuditLogFilters, ApiError> {
+        let event_types = self.event_types
+            .map(|types| {
+                types
+                    .split(',')
+                    .map(|t| {
+                        let name = serde_json::Value::String(t.trim().to_uppercase());
+                        serde_json::from_value::<AuditEventType>(name)
+                            .map_err(|_| ApiError::bad_request(&format!("Unknown event type: {}", t)))
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .transpose()?;
+
+        Ok(AuditLogFilters {
+            user_id: self.user_id,
+            device_id: self.device_id,
+            entity_type: self.entity_type,
+            entity_id: self.entity_id,
+            event_types,
+            start_time: self.start_time,
+            end_time: self.end_time,
+            page: self.page.unwrap_or(0),
+            limit: self.limit.unwrap_or(50).min(500),
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ListAuditLogsResponse {
+    pub logs: Vec<AuditLog>,
+    pub total: i64,
+    pub page: u32,
+    pub limit: u32,
+}
+
+/// Search the audit log, newest first
+pub async fn list_audit_logs(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Query(query): Query<AuditLogQuery>,
+) -> Result<Json<ListAuditLogsResponse>, ApiError> {
+    blocking(move || {
+        require_read(&state, &claims)?;
+        let filters = query.into_filters()?;
+
+        let repo = AuditRepository::new(state.db.clone());
+        let logs = repo.search(&filters)?;
+        let total = repo.count(&filters)?;
+
+        Ok(Json(ListAuditLogsResponse {
+            logs,
+            total,
+            page: filters.page,
+            limit: filters.limit,
+        }))
+    })
+    .await
+}
+
+/// The server's entries merged with those replicated from devices, in HLC
+/// order
+pub async fn global_audit_view(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Query(query): Query<AuditLogQuery>,
+) -> Result<Json<Vec<ReplicatedAuditLog>>, ApiError> {
+    blocking(move || {
+        require_read(&state, &claims)?;
+        let filters = query.into_filters()?;
+
+        let logs = DeviceAuditRepository::new(state.db.clone()).global_view(&filters)?;
+        Ok(Json(logs))
+    })
+    .await
+}
+
+/// Get a single audit log entry
+pub async fn get_audit_log(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<String>,
+) -> Result<Json<AuditLog>, ApiError> {
+    blocking(move || {
+        require_read(&state, &claims)?;
+        let log_id = Id::parse_str(&id)
+            .map_err(|_| ApiError::bad_request("Invalid audit log ID"))?;
+
+        let log = AuditRepository::new(state.db.clone())
+            .find_by_id(log_id)?
+            .ok_or_else(|| ApiError::not_found("Audit log entry"))?;
+
+        Ok(Json(log))
+    })
+    .await
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyQuery {
+    /// Also decrypt and walk the archive segments
+    pub include_archive: Option<bool>,
+}
+
+/// Walk the hash chain and report the first broken link
+pub async fn verify_audit_chain(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Query(query): Query<VerifyQuery>,
+) -> Result<Json<AuditChainReport>, ApiError> {
+    require_verify(&state, &claims)?;
+
+    let mut verifier = AuditChainVerifier::new(state.db.clone());
+    if query.include_archive.unwrap_or(false) {
+        verifier = verifier.with_archive(state.audit_archive()?);
+    }
+    let report = tokio::task::spawn_blocking(move || verifier.verify())
+        .await
+        .map_err(|e| ApiError::internal(&e.to_string()))??;
+
+    Ok(Json(report))
+}
+
+/// Verify the chain and anchor its current head
+pub async fn anchor_audit_chain(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Json<AuditChainReport>, ApiError> {
+    require_verify(&state, &claims)?;
+
+    let verifier = AuditChainVerifier::new(state.db.clone());
+    let report = tokio::task::spawn_blocking(move || verifier.anchor())
+        .await
+        .map_err(|e| ApiError::internal(&e.to_string()))??;
+
+    Ok(Json(report))
+}
+
+/// Walk the sub-chain replicated from one device
+pub async fn verify_device_audit_chain(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<String>,
+) -> Result<Json<AuditChainReport>, ApiError> {
+    require_verify(&state, &claims)?;
+    let device_id = Id::parse_str(&id)
+        .map_err(|_| ApiError::bad_request("Invalid device ID"))?;
+
+    let verifier = AuditChainVerifier::new(state.db.clone());
+    let report = tokio::task::spawn_blocking(move || verifier.verify_device(device_id))
+        .await
+        .map_err(|e| ApiError::internal(&e.to_string()))??;
+
+    Ok(Json(report))
+}
+
+/// Archive segments in chain order
+pub async fn list_archive_segments(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Json<Vec<AuditArchiveSegment>>, ApiError> {
+    blocking(move || {
+        require_read(&state, &claims)?;
+        Ok(Json(state.audit_archive()?.segments()?))
+    })
+    .await
+}
+
+/// Search archived entries, newest first. Only segments overlapping the
+/// sequence and time range are decrypted.
+pub async fn search_archive(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Query(query): Query<AuditLogQuery>,
+) -> Result<Json<ArchivedAuditLogs>, ApiError> {
+    require_read(&state, &claims)?;
+    let (from_sequence, to_sequence) = (query.from_sequence, query.to_sequence);
+    let filters = query.into_filters()?;
+
+    let archive = state.audit_archive()?;
+    let logs = tokio::task::spawn_blocking(move || archive.search(&filters, from_sequence, to_sequence))
+        .await
+        .map_err(|e| ApiError::internal(&e.to_string()))??;
+
+    Ok(Json(logs))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CompactRequest {
+    /// Archive entries older than this many days
+    pub older_than_days: i64,
+    pub segment_size: Option<u32>,
+}
+
+/// Move old entries from the audit log into archive segments
+pub async fn compact_audit_log(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(req): Json<CompactRequest>,
+) -> Result<Json<AuditCompactionReport>, ApiError> {
+    require_archive(&state, &claims)?;
+    if req.older_than_days < 1 {
+        return Err(ApiError::bad_request("older_than_days must be at least 1"));
+    }
+
+    let cutoff = chrono::Utc::now() - chrono::Duration::days(req.older_than_days);
+    let segment_size = req.segment_size.unwrap_or(DEFAULT_SEGMENT_SIZE);
+    let archive = state.audit_archive()?;
+    let report = tokio::task::spawn_blocking(move || archive.compact(cutoff, segment_size))
+        .await
+        .map_err(|e| ApiError::internal(&e.to_string()))??;
+
+    Ok(Json(report))
 }
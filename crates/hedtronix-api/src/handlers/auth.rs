@@ -1,27 +1,183 @@
 //! Authentication handlers
 
-use axum::{extract::State, Json};
-use hedtronix_core::{Id, UserRole};
-use hedtronix_auth::{AuthService, LoginRequest, RefreshRequest, AuthResponse, TokenPair};
-use hedtronix_db::Database;
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{ConnectInfo, State},
+    http::HeaderMap,
+    Extension, Json,
+};
+use hedtronix_core::{Device, Id, RegisterDevice};
+use hedtronix_auth::{
+    AuthService, Claims, LoginRequest, LoginResponse, RefreshRequest, TokenPair,
+    JwkSet, MfaVerifyRequest, OfflineTokenRequest, OfflineTokenResponse, PasswordChangeRequest,
+    PasswordChangeResponse, RecoveryCodesResponse,
+    TotpConfirmRequest, TotpEnrollment, WebauthnRegistrationOptions, WebauthnRegistrationRequest,
+};
 use serde::{Deserialize, Serialize};
 
+use crate::blocking::blocking;
 use crate::error::ApiError;
 use crate::state::AppState;
 
+pub(crate) fn auth_service(state: &AppState) -> AuthService {
+    AuthService::new(state.auth_state.jwt_manager.clone(), state.db.clone())
+        .with_mfa_policy(state.auth_state.mfa_policy.clone())
+        .with_throttle_policy(state.auth_state.throttle_policy.clone())
+        .with_password_policy(state.auth_state.password_policy.clone())
+        .with_permission_checker(state.auth_state.permissions.clone())
+        .with_device_lock(state.auth_state.device_lock.clone())
+}
+
+fn bearer_token(headers: &HeaderMap) -> Result<&str, ApiError> {
+    headers
+        .get("Authorization")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .ok_or_else(|| ApiError::unauthorized("Missing bearer token"))
+}
+
+fn is_locked(state: &AppState) -> bool {
+    state.auth_state.device_lock.as_ref().is_some_and(|lock| !lock.is_unlocked())
+}
+
+/// Catch up on indexing and re-encryption paused while the keys were
+/// locked, once a completed login has unlocked the device
+fn resume_after_unlock(state: &AppState, was_locked: bool) {
+    if was_locked && !is_locked(state) {
+        if let Err(e) = crate::start_key_maintenance(state) {
+            tracing::error!("Failed to start key maintenance after unlock: {}", e);
+        }
+    }
+}
+
 /// Login request
 pub async fn login(
     State(state): State<AppState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
     Json(req): Json<LoginRequest>,
-) -> Result<Json<AuthResponse>, ApiError> {
-    let device_id = req.device_id
-        .and_then(|s| Id::parse_str(&s).ok())
-        .unwrap_or_else(Id::new_v4);
+) -> Result<Json<LoginResponse>, ApiError> {
+    blocking(move || {
+        let device_id = req.device_id
+            .and_then(|s| Id::parse_str(&s).ok())
+            .unwrap_or_else(Id::new_v4);
+        let ip_address = connect_info.map(|ConnectInfo(addr)| addr.ip().to_string());
+        let was_locked = is_locked(&state);
     
-    let auth_service = AuthService::new(&state.auth_state.jwt_secret, state.db.clone());
-    let response = auth_service.login(&req.email, &req.password, device_id)?;
+        let response = auth_service(&state)
+            .login(&req.email, &req.password, device_id, ip_address.as_deref())?;
+        resume_after_unlock(&state, was_locked);
     
-    Ok(Json(response))
+        Ok(Json(response))
+    })
+    .await
+}
+
+/// Answer an MFA challenge to complete login
+pub async fn verify_mfa(
+    State(state): State<AppState>,
+    Json(req): Json<MfaVerifyRequest>,
+) -> Result<Json<LoginResponse>, ApiError> {
+    blocking(move || {
+        let was_locked = is_locked(&state);
+        let response = auth_service(&state).verify_mfa(&req)?;
+        resume_after_unlock(&state, was_locked);
+
+        Ok(Json(response))
+    })
+    .await
+}
+
+/// Change the password. Accepts an access token, or the password change
+/// token from a login that requires one.
+pub async fn change_password(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<PasswordChangeRequest>,
+) -> Result<Json<PasswordChangeResponse>, ApiError> {
+    blocking(move || {
+        let response = auth_service(&state).change_password(
+            bearer_token(&headers)?,
+            &req.current_password,
+            &req.new_password,
+        )?;
+
+        Ok(Json(response))
+    })
+    .await
+}
+
+/// Start TOTP enrollment. Accepts an access token, or the MFA token from a
+/// login that requires enrollment.
+pub async fn enroll_totp(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<TotpEnrollment>, ApiError> {
+    blocking(move || {
+        let service = auth_service(&state);
+        let user = service.mfa_enrollment_user(bearer_token(&headers)?)?;
+
+        Ok(Json(service.enroll_totp(&user)?))
+    })
+    .await
+}
+
+/// Confirm TOTP enrollment with a first code
+pub async fn confirm_totp(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<TotpConfirmRequest>,
+) -> Result<Json<RecoveryCodesResponse>, ApiError> {
+    blocking(move || {
+        let service = auth_service(&state);
+        let user = service.mfa_enrollment_user(bearer_token(&headers)?)?;
+
+        Ok(Json(service.confirm_totp(&user, &req.code)?))
+    })
+    .await
+}
+
+/// Challenge for registering a WebAuthn authenticator
+pub async fn webauthn_options(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<WebauthnRegistrationOptions>, ApiError> {
+    blocking(move || {
+        let service = auth_service(&state);
+        let user = service.mfa_enrollment_user(bearer_token(&headers)?)?;
+
+        Ok(Json(service.webauthn_registration_options(&user)?))
+    })
+    .await
+}
+
+/// Register a WebAuthn authenticator
+pub async fn register_webauthn(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<WebauthnRegistrationRequest>,
+) -> Result<Json<RecoveryCodesResponse>, ApiError> {
+    blocking(move || {
+        let service = auth_service(&state);
+        let user = service.mfa_enrollment_user(bearer_token(&headers)?)?;
+
+        Ok(Json(service.register_webauthn(&user, &req)?))
+    })
+    .await
+}
+
+/// Replace the caller's recovery codes
+pub async fn regenerate_recovery_codes(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<RecoveryCodesResponse>, ApiError> {
+    blocking(move || {
+        let service = auth_service(&state);
+        let user = service.mfa_enrollment_user(bearer_token(&headers)?)?;
+
+        Ok(Json(service.regenerate_recovery_codes(&user)?))
+    })
+    .await
 }
 
 /// Refresh token
@@ -29,10 +185,54 @@ pub async fn refresh(
     State(state): State<AppState>,
     Json(req): Json<RefreshRequest>,
 ) -> Result<Json<TokenPair>, ApiError> {
-    let auth_service = AuthService::new(&state.auth_state.jwt_secret, state.db.clone());
-    let tokens = auth_service.refresh(&req.refresh_token)?;
+    blocking(move || {
+        let auth_service = auth_service(&state);
+        let tokens = auth_service.refresh(&req.refresh_token)?;
     
-    Ok(Json(tokens))
+        Ok(Json(tokens))
+    })
+    .await
+}
+
+/// Public JWT verification keys (JWKS)
+pub async fn jwks(State(state): State<AppState>) -> Json<JwkSet> {
+    Json(state.auth_state.jwt_manager.jwks())
+}
+
+/// Register the device the caller signed in with
+pub async fn register_device(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(req): Json<RegisterDevice>,
+) -> Result<Json<Device>, ApiError> {
+    blocking(move || {
+        let auth_service = auth_service(&state);
+        let device = auth_service.register_device(&claims, req)?;
+
+        Ok(Json(device))
+    })
+    .await
+}
+
+/// Issue an offline-capable token for the requested duration, capped by
+/// the role and device type policy
+pub async fn offline_token(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(req): Json<OfflineTokenRequest>,
+) -> Result<Json<OfflineTokenResponse>, ApiError> {
+    blocking(move || {
+        let auth_service = auth_service(&state);
+        let response = auth_service.issue_offline_token(
+            &claims,
+            &state.auth_state.offline_policy,
+            req.duration_hours,
+            req.permissions.as_deref(),
+        )?;
+
+        Ok(Json(response))
+    })
+    .await
 }
 
 /// Logout (invalidate token - currently just a placeholder)
@@ -46,44 +246,3 @@ pub struct LogoutResponse {
     pub success: bool,
 }
 
-/// Register new user (admin only in production)
-#[derive(Debug, Deserialize)]
-pub struct RegisterRequest {
-    pub email: String,
-    pub name: String,
-    pub password: String,
-    pub role: String,
-}
-
-pub async fn register(
-    State(state): State<AppState>,
-    Json(req): Json<RegisterRequest>,
-) -> Result<Json<RegisterResponse>, ApiError> {
-    let role = match req.role.to_uppercase().as_str() {
-        "PHYSICIAN" => UserRole::Physician,
-        "NURSE" => UserRole::Nurse,
-        "RECEPTIONIST" => UserRole::Receptionist,
-        "BILLING" => UserRole::Billing,
-        "ADMIN" => UserRole::Admin,
-        "PATIENT" => UserRole::Patient,
-        _ => return Err(ApiError::bad_request("Invalid role")),
-    };
-
-    let auth_service = AuthService::new(&state.auth_state.jwt_secret, state.db.clone());
-    let user = auth_service.register_user(&req.email, &req.name, &req.password, role)?;
-
-    Ok(Json(RegisterResponse {
-        id: user.id.to_string(),
-        email: user.email,
-        name: user.name,
-        role: user.role.as_str().to_string(),
-    }))
-}
-
-#[derive(Debug, Serialize)]
-pub struct RegisterResponse {
-    pub id: String,
-    pub email: String,
-    pub name: String,
-    pub role: String,
-}
@@ -1,25 +1,58 @@
 //! Billing handlers
 
 use axum::{
-    extract::{Path, Query, State},
+    extract::{Query, State},
     Json,
 };
-use hedtronix_core::{BillingEntry, BillingStatus, Id};
+use hedtronix_auth::{AccessPolicy, AuthorizationError, Subject};
+use hedtronix_core::{BillingEntry, Id};
 use hedtronix_db::BillingRepository;
 use serde::{Deserialize, Serialize};
+use crate::access::{self, Caller};
+use crate::audit::{audited, Audited};
+use crate::blocking::blocking;
 use crate::error::ApiError;
 use crate::state::AppState;
 
+/// List billing entries, optionally for one patient, limited to patients
+/// whose chart the caller may open
 pub async fn list_billing(
     State(state): State<AppState>,
-) -> Result<Json<ListBillingResponse>, ApiError> {
-    let repo = BillingRepository::new(state.db.clone());
-    let entries = repo.find_all()
+    caller: Caller,
+    Query(query): Query<ListBillingQuery>,
+) -> Result<Audited<ListBillingResponse>, ApiError> {
+    blocking(move || {
+        access::require_permission(&state, &caller, "billing", "read")?;
+        let repo = BillingRepository::new(state.db.clone());
+        let policy = state.access_policy();
+        let subject = policy.subject(&caller.claims)?;
+
+        let entries = if !subject.allows("billing", "read") {
+            // Patients, who only hold `billing:read_own`, list their own bills
+            let Some(patient_id) = subject.patient_id else {
+                return Ok(audited(Vec::<Id>::new(), ListBillingResponse { entries: Vec::new() }));
+            };
+            repo.find_by_patient(patient_id)
+        } else if let Some(id) = query.patient_id {
+            let patient_id = Id::parse_str(&id)
+                .map_err(|_| ApiError::bad_request("Invalid patient ID"))?;
+            access::require_patient(&state, &caller, patient_id, "Patient", patient_id)?;
+            repo.find_by_patient(patient_id)
+        } else {
+            repo.find_all()
+        }
         .map_err(|e| ApiError::internal(&e.to_string()))?;
-        
-    Ok(Json(ListBillingResponse { 
-        entries: entries.into_iter().map(BillingDto::from).collect() 
-    }))
+
+        let entries = visible_entries(&policy, &subject, entries)?;
+        let ids: Vec<String> = entries.iter().map(|e| e.id.clone()).collect();
+        Ok(audited(ids, ListBillingResponse { entries }))
+    })
+    .await
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListBillingQuery {
+    pub patient_id: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -27,41 +60,50 @@ pub struct ListBillingResponse {
     pub entries: Vec<BillingDto>,
 }
 
+/// Create a billing entry for a patient whose chart the caller may open
 pub async fn create_billing(
     State(state): State<AppState>,
+    caller: Caller,
     Json(req): Json<CreateBillingRequest>,
 ) -> Result<Json<BillingDto>, ApiError> {
-    let patient_id = Id::parse_str(&req.patient_id)
-        .map_err(|_| ApiError::bad_request("Invalid patient ID"))?;
-    let encounter_id = Id::parse_str(&req.encounter_id)
-        .map_err(|_| ApiError::bad_request("Invalid encounter ID"))?;
-    let provider_id = Id::parse_str(&req.provider_id)
-        .map_err(|_| ApiError::bad_request("Invalid provider ID"))?;
-    let created_by = provider_id;
+    blocking(move || {
+        let patient_id = Id::parse_str(&req.patient_id)
+            .map_err(|_| ApiError::bad_request("Invalid patient ID"))?;
+        let encounter_id = Id::parse_str(&req.encounter_id)
+            .map_err(|_| ApiError::bad_request("Invalid encounter ID"))?;
+        let provider_id = Id::parse_str(&req.provider_id)
+            .map_err(|_| ApiError::bad_request("Invalid provider ID"))?;
+
+        access::require_permission(&state, &caller, "billing", "write")?;
+        let policy = state.access_policy();
+        let subject = policy.subject(&caller.claims)?;
+        policy.require_patient(&subject, patient_id)?;
     
-    let entry = BillingEntry::new(
-        patient_id,
-        encounter_id,
-        provider_id,
-        req.cpt_code.clone(),
-        req.description.clone(),
-        req.unit_price.clone(),
-        created_by,
-    );
+        let entry = BillingEntry::new(
+            patient_id,
+            encounter_id,
+            provider_id,
+            req.cpt_code.clone(),
+            req.description.clone(),
+            req.unit_price.clone(),
+            subject.user_id,
+        );
     
-    let repo = BillingRepository::new(state.db.clone());
-    repo.create(&entry)
-        .map_err(|e| ApiError::internal(&e.to_string()))?;
+        let repo = BillingRepository::new(state.db.clone());
+        repo.create(&entry)
+            .map_err(|e| ApiError::internal(&e.to_string()))?;
     
-    // Sync tracking
-    let sync_engine = state.sync_engine();
-    let _ = sync_engine.track_create(
-        "BillingEntry",
-        entry.id,
-        serde_json::to_value(&entry).unwrap_or_default(),
-    );
+        // Sync tracking
+        let sync_engine = state.sync_engine();
+        let _ = sync_engine.track_create(
+            "BillingEntry",
+            entry.id,
+            serde_json::to_value(&entry).unwrap_or_default(),
+        );
     
-    Ok(Json(BillingDto::from(entry)))
+        Ok(Json(BillingDto::from(entry)))
+    })
+    .await
 }
 
 #[derive(Debug, Deserialize)]
@@ -97,66 +139,19 @@ impl From<BillingEntry> for BillingDto {
     }
 }
 
-pub async fn update_billing(
-    State(state): State<AppState>,
-    Path(id): Path<String>,
-    Json(req): Json<UpdateBillingRequest>,
-) -> Result<Json<BillingDto>, ApiError> {
-    let entry_id = Id::parse_str(&id)
-        .map_err(|_| ApiError::bad_request("Invalid ID"))?;
-        
-    let repo = BillingRepository::new(state.db.clone());
-    let mut entry = repo.find_by_id(entry_id)
-        .map_err(|e| ApiError::internal(&e.to_string()))?
-        .ok_or_else(|| ApiError::not_found("BillingEntry"))?;
-        
-    if let Some(status) = req.status {
-        match status.to_uppercase().as_str() {
-             "SUBMITTED" => entry.status = BillingStatus::Submitted,
-             "PAID" => entry.status = BillingStatus::Paid,
-             "DENIED" => entry.status = BillingStatus::Denied,
-             _ => {},
+/// Drop entries for patients whose chart the caller may not open
+fn visible_entries(
+    policy: &AccessPolicy,
+    subject: &Subject,
+    entries: Vec<BillingEntry>,
+) -> Result<Vec<BillingDto>, ApiError> {
+    let mut visible = Vec::with_capacity(entries.len());
+    for entry in entries {
+        match policy.require_patient(subject, entry.patient_id) {
+            Ok(_) => visible.push(BillingDto::from(entry)),
+            Err(AuthorizationError::Denied(_)) | Err(AuthorizationError::NotFound(_)) => {}
+            Err(e) => return Err(e.into()),
         }
     }
-    
-    entry.updated_at = chrono::Utc::now();
-    
-    repo.update(&entry)
-        .map_err(|e| ApiError::internal(&e.to_string()))?;
-        
-    // Sync tracking
-    let sync_engine = state.sync_engine();
-    let _ = sync_engine.track_update(
-        "BillingEntry",
-        entry.id,
-        serde_json::to_value(&entry).unwrap_or_default(),
-    );
-    
-    Ok(Json(BillingDto::from(entry)))
-}
-
-#[derive(Debug, Deserialize)]
-pub struct UpdateBillingRequest {
-    pub status: Option<String>,
-}
-
-pub async fn submit_billing(
-    State(_state): State<AppState>,
-    Json(_req): Json<SubmitBillingRequest>,
-) -> Result<Json<SubmitBillingResponse>, ApiError> {
-    Ok(Json(SubmitBillingResponse {
-        submitted_count: 0,
-        claim_numbers: vec![],
-    }))
-}
-
-#[derive(Debug, Deserialize)]
-pub struct SubmitBillingRequest {
-    pub entry_ids: Vec<String>,
-}
-
-#[derive(Debug, Serialize)]
-pub struct SubmitBillingResponse {
-    pub submitted_count: i32,
-    pub claim_numbers: Vec<String>,
+    Ok(visible)
 }
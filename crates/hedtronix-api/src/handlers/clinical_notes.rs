@@ -1,76 +1,125 @@
 //! Clinical Note handlers
 
 use axum::{
-    extract::{Path, Query, State},
+    extract::{Path, State},
     Json,
 };
+use hedtronix_auth::PatientScope;
 use hedtronix_core::{ClinicalNote, NoteType, NoteStatus, Id, ClinicalNoteDto};
 use hedtronix_db::ClinicalNoteRepository;
 use serde::{Deserialize, Serialize};
 
+use crate::access::{self, Caller};
+use crate::audit::{audited, Audited};
+use crate::blocking::blocking;
 use crate::error::ApiError;
 use crate::state::AppState;
 
+/// Any access to the chart lets the caller read notes; redaction decides
+/// whether they see the content
+fn require_chart(
+    state: &AppState,
+    caller: &Caller,
+    patient_id: Id,
+    entity_type: &str,
+    entity_id: Id,
+) -> Result<PatientScope, ApiError> {
+    access::require_patient(state, caller, patient_id, entity_type, entity_id)
+}
+
+/// Writing notes needs the `clinical_notes` grant for the action and full
+/// chart access; patients' grants only let them read their own
+fn require_author(
+    state: &AppState,
+    caller: &Caller,
+    action: &str,
+    patient_id: Id,
+    note_id: Id,
+) -> Result<(), ApiError> {
+    access::require_permission(state, caller, "clinical_notes", action)?;
+    match require_chart(state, caller, patient_id, "ClinicalNote", note_id)? {
+        PatientScope::Full => Ok(()),
+        PatientScope::Financial => Err(ApiError::forbidden("Editing clinical notes requires full chart access")),
+    }
+}
+
 /// List clinical notes for a patient
 pub async fn list_notes(
     State(state): State<AppState>,
+    caller: Caller,
     Path(patient_id): Path<String>,
-) -> Result<Json<ListNotesResponse>, ApiError> {
-    let pid = Id::parse_str(&patient_id)
-        .map_err(|_| ApiError::bad_request("Invalid patient ID"))?;
+) -> Result<Audited<ListNotesResponse>, ApiError> {
+    blocking(move || {
+        let pid = Id::parse_str(&patient_id)
+            .map_err(|_| ApiError::bad_request("Invalid patient ID"))?;
+        access::require_permission(&state, &caller, "clinical_notes", "read")?;
+        require_chart(&state, &caller, pid, "Patient", pid)?;
         
-    let repo = ClinicalNoteRepository::new(state.db.clone(), state.encryption_key.clone());
-    let notes = repo.find_by_patient(pid)
-        .map_err(|e| ApiError::internal(&e.to_string()))?;
+        let repo = ClinicalNoteRepository::new(state.db.clone(), state.keyring.clone());
+        let notes = repo.find_by_patient(pid)
+            .map_err(|e| ApiError::internal(&e.to_string()))?;
+
+        let ids: Vec<Id> = notes.iter().map(|n| n.id).collect();
 
-    Ok(Json(ListNotesResponse {
-        notes: notes.into_iter().map(ClinicalNoteDto::from).collect(),
-    }))
+        Ok(audited(ids, ListNotesResponse {
+            notes: notes.into_iter()
+                .map(|n| access::redact(&state, &caller, "ClinicalNote", &ClinicalNoteDto::from(n)))
+                .collect(),
+        }))
+    })
+    .await
 }
 
 #[derive(Debug, Serialize)]
 pub struct ListNotesResponse {
-    pub notes: Vec<ClinicalNoteDto>,
+    /// Redacted `ClinicalNoteDto`s
+    pub notes: Vec<serde_json::Value>,
 }
 
 /// Create clinical note
 pub async fn create_note(
     State(state): State<AppState>,
+    caller: Caller,
     Json(req): Json<CreateNoteRequest>,
-) -> Result<Json<ClinicalNoteDto>, ApiError> {
-    let patient_id = Id::parse_str(&req.patient_id)
-        .map_err(|_| ApiError::bad_request("Invalid patient ID"))?;
-    let author_id = Id::parse_str(&req.provider_id)
-        .map_err(|_| ApiError::bad_request("Invalid provider ID"))?;
+) -> Result<Json<serde_json::Value>, ApiError> {
+    blocking(move || {
+        let patient_id = Id::parse_str(&req.patient_id)
+            .map_err(|_| ApiError::bad_request("Invalid patient ID"))?;
+        let author_id = Id::parse_str(&req.provider_id)
+            .map_err(|_| ApiError::bad_request("Invalid provider ID"))?;
     
-    let note_type = match req.note_type.to_uppercase().as_str() {
-        "PROGRESS_NOTE" => NoteType::ProgressNote,
-        "CONSULTATION" => NoteType::Consultation,
-        "DISCHARGE_SUMMARY" => NoteType::DischargeSummary,
-        "PROCEDURE_NOTE" => NoteType::ProcedureNote,
-        _ => NoteType::ProgressNote,
-    };
+        let note_type = match req.note_type.to_uppercase().as_str() {
+            "PROGRESS_NOTE" => NoteType::ProgressNote,
+            "CONSULTATION" => NoteType::Consultation,
+            "DISCHARGE_SUMMARY" => NoteType::DischargeSummary,
+            "PROCEDURE_NOTE" => NoteType::ProcedureNote,
+            _ => NoteType::ProgressNote,
+        };
     
-    let mut note = ClinicalNote::new(patient_id, author_id, note_type);
-    note.content = req.content.unwrap_or_default();
+        let mut note = ClinicalNote::new(patient_id, author_id, note_type);
+        note.content = req.content.unwrap_or_default();
     
-    if let Some(encounter_id) = req.encounter_id {
-        note.encounter_id = Id::parse_str(&encounter_id).ok();
-    }
+        if let Some(encounter_id) = req.encounter_id {
+            note.encounter_id = Id::parse_str(&encounter_id).ok();
+        }
+    
+        require_author(&state, &caller, "create", patient_id, note.id)?;
     
-    let repo = ClinicalNoteRepository::new(state.db.clone(), state.encryption_key.clone());
-    repo.create(&note)
-        .map_err(|e| ApiError::internal(&e.to_string()))?;
+        let repo = ClinicalNoteRepository::new(state.db.clone(), state.keyring.clone());
+        repo.create(&note)
+            .map_err(|e| ApiError::internal(&e.to_string()))?;
     
-    // Track Sync
-    let sync_engine = state.sync_engine();
-    let _ = sync_engine.track_create(
-        "ClinicalNote",
-        note.id,
-        serde_json::to_value(&note).unwrap_or_default(),
-    );
-
-    Ok(Json(ClinicalNoteDto::from(note)))
+        // Track Sync
+        let sync_engine = state.sync_engine();
+        let _ = sync_engine.track_create(
+            "ClinicalNote",
+            note.id,
+            serde_json::to_value(&note).unwrap_or_default(),
+        );
+
+        Ok(Json(access::redact(&state, &caller, "ClinicalNote", &ClinicalNoteDto::from(note))))
+    })
+    .await
 }
 
 #[derive(Debug, Deserialize)]
@@ -85,57 +134,72 @@ pub struct CreateNoteRequest {
 /// Get note
 pub async fn get_note(
     State(state): State<AppState>,
+    caller: Caller,
     Path(id): Path<String>,
-) -> Result<Json<ClinicalNoteDto>, ApiError> {
-    let note_id = Id::parse_str(&id).map_err(|_| ApiError::bad_request("Invalid ID"))?;
+) -> Result<Json<serde_json::Value>, ApiError> {
+    blocking(move || {
+        let note_id = Id::parse_str(&id).map_err(|_| ApiError::bad_request("Invalid ID"))?;
+        access::require_permission(&state, &caller, "clinical_notes", "read")?;
     
-    let repo = ClinicalNoteRepository::new(state.db.clone(), state.encryption_key.clone());
-    let note = repo.find_by_id(note_id)
-        .map_err(|e| ApiError::internal(&e.to_string()))?
-        .ok_or_else(|| ApiError::not_found("ClinicalNote"))?;
+        let repo = ClinicalNoteRepository::new(state.db.clone(), state.keyring.clone());
+        let note = repo.find_by_id(note_id)
+            .map_err(|e| ApiError::internal(&e.to_string()))?
+            .ok_or_else(|| ApiError::not_found("ClinicalNote"))?;
+        require_chart(&state, &caller, note.patient_id, "ClinicalNote", note.id)?;
         
-    Ok(Json(ClinicalNoteDto::from(note)))
+        Ok(Json(access::redact(&state, &caller, "ClinicalNote", &ClinicalNoteDto::from(note))))
+    })
+    .await
 }
 
 /// Update note
 pub async fn update_note(
     State(state): State<AppState>,
+    caller: Caller,
     Path(id): Path<String>,
     Json(req): Json<UpdateNoteRequest>,
-) -> Result<Json<ClinicalNoteDto>, ApiError> {
-    let note_id = Id::parse_str(&id).map_err(|_| ApiError::bad_request("Invalid ID"))?;
+) -> Result<Json<serde_json::Value>, ApiError> {
+    blocking(move || {
+        let note_id = Id::parse_str(&id).map_err(|_| ApiError::bad_request("Invalid ID"))?;
     
-    let repo = ClinicalNoteRepository::new(state.db.clone(), state.encryption_key.clone());
-    let mut note = repo.find_by_id(note_id)
-         .map_err(|e| ApiError::internal(&e.to_string()))?
-         .ok_or_else(|| ApiError::not_found("ClinicalNote"))?;
+        let repo = ClinicalNoteRepository::new(state.db.clone(), state.keyring.clone());
+        let mut note = repo.find_by_id(note_id)
+             .map_err(|e| ApiError::internal(&e.to_string()))?
+             .ok_or_else(|| ApiError::not_found("ClinicalNote"))?;
+        require_author(&state, &caller, "write", note.patient_id, note.id)?;
+        // Marking a note signed here must not get around `clinical_notes:sign`
+        if req.status.as_deref().is_some_and(|s| s.eq_ignore_ascii_case("SIGNED")) {
+            access::require_permission(&state, &caller, "clinical_notes", "sign")?;
+        }
          
-    if let Some(content) = req.content {
-        note.content = content;
-    }
+        if let Some(content) = req.content {
+            note.content = content;
+        }
     
-    if let Some(status) = req.status {
-        match status.to_uppercase().as_str() {
-            "DRAFT" => note.status = NoteStatus::Draft,
-            "SIGNED" => note.status = NoteStatus::Signed, // Should use sign_note endpoint
-            _ => {},
+        if let Some(status) = req.status {
+            match status.to_uppercase().as_str() {
+                "DRAFT" => note.status = NoteStatus::Draft,
+                "SIGNED" => note.status = NoteStatus::Signed, // Should use sign_note endpoint
+                _ => {},
+            }
         }
-    }
     
-    note.updated_at = chrono::Utc::now();
+        note.updated_at = chrono::Utc::now();
     
-    repo.update(&note)
-        .map_err(|e| ApiError::internal(&e.to_string()))?;
+        repo.update(&note)
+            .map_err(|e| ApiError::internal(&e.to_string()))?;
         
-    // Track Sync
-    let sync_engine = state.sync_engine();
-    let _ = sync_engine.track_update(
-        "ClinicalNote",
-        note.id,
-        serde_json::to_value(&note).unwrap_or_default(),
-    );
+        // Track Sync
+        let sync_engine = state.sync_engine();
+        let _ = sync_engine.track_update(
+            "ClinicalNote",
+            note.id,
+            serde_json::to_value(&note).unwrap_or_default(),
+        );
      
-    Ok(Json(ClinicalNoteDto::from(note)))
+        Ok(Json(access::redact(&state, &caller, "ClinicalNote", &ClinicalNoteDto::from(note))))
+    })
+    .await
 }
 
 #[derive(Debug, Deserialize)]
@@ -147,32 +211,37 @@ pub struct UpdateNoteRequest {
 /// Sign note
 pub async fn sign_note(
     State(state): State<AppState>,
+    caller: Caller,
     Path(id): Path<String>,
     Json(req): Json<SignNoteRequest>,
-) -> Result<Json<ClinicalNoteDto>, ApiError> {
-    let note_id = Id::parse_str(&id).map_err(|_| ApiError::bad_request("Invalid ID"))?;
-    let signer_id = Id::parse_str(&req.signer_id).map_err(|_| ApiError::bad_request("Invalid signer ID"))?;
+) -> Result<Json<serde_json::Value>, ApiError> {
+    blocking(move || {
+        let note_id = Id::parse_str(&id).map_err(|_| ApiError::bad_request("Invalid ID"))?;
+        let signer_id = Id::parse_str(&req.signer_id).map_err(|_| ApiError::bad_request("Invalid signer ID"))?;
     
-    let repo = ClinicalNoteRepository::new(state.db.clone(), state.encryption_key.clone());
-    let mut note = repo.find_by_id(note_id)
-         .map_err(|e| ApiError::internal(&e.to_string()))?
-         .ok_or_else(|| ApiError::not_found("ClinicalNote"))?;
+        let repo = ClinicalNoteRepository::new(state.db.clone(), state.keyring.clone());
+        let mut note = repo.find_by_id(note_id)
+             .map_err(|e| ApiError::internal(&e.to_string()))?
+             .ok_or_else(|| ApiError::not_found("ClinicalNote"))?;
+        require_author(&state, &caller, "sign", note.patient_id, note.id)?;
          
-    note.sign(signer_id, req.signature_data)
-        .map_err(|e| ApiError::bad_request(e))?;
+        note.sign(signer_id, req.signature_data)
+            .map_err(ApiError::bad_request)?;
         
-    repo.update(&note)
-        .map_err(|e| ApiError::internal(&e.to_string()))?;
+        repo.update(&note)
+            .map_err(|e| ApiError::internal(&e.to_string()))?;
         
-    // Track Sync
-    let sync_engine = state.sync_engine();
-    let _ = sync_engine.track_update(
-        "ClinicalNote",
-        note.id,
-        serde_json::to_value(&note).unwrap_or_default(),
-    );
+        // Track Sync
+        let sync_engine = state.sync_engine();
+        let _ = sync_engine.track_update(
+            "ClinicalNote",
+            note.id,
+            serde_json::to_value(&note).unwrap_or_default(),
+        );
         
-    Ok(Json(ClinicalNoteDto::from(note)))
+        Ok(Json(access::redact(&state, &caller, "ClinicalNote", &ClinicalNoteDto::from(note))))
+    })
+    .await
 }
 
 #[derive(Debug, Deserialize)]
@@ -0,0 +1,63 @@
+//! Passphrase-locked device key handlers
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use hedtronix_auth::{Claims, DeviceLock, DeviceLockStatus};
+use hedtronix_core::Id;
+
+use crate::blocking::blocking;
+use crate::error::ApiError;
+use crate::state::AppState;
+
+fn device_lock(state: &AppState) -> Result<Arc<DeviceLock>, ApiError> {
+    state
+        .auth_state
+        .device_lock
+        .clone()
+        .ok_or_else(|| ApiError::not_found("Device lock"))
+}
+
+/// Whether the device key is set up and unlocked
+pub async fn status(
+    State(state): State<AppState>,
+    Extension(_claims): Extension<Claims>,
+) -> Result<Json<DeviceLockStatus>, ApiError> {
+    blocking(move || {
+        Ok(Json(device_lock(&state)?.status()?))
+    })
+    .await
+}
+
+/// Lock the device now, e.g. when stepping away from the workstation
+pub async fn lock(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<StatusCode, ApiError> {
+    blocking(move || {
+        device_lock(&state)?.lock()?;
+        tracing::info!("Device locked by {}", claims.sub);
+
+        Ok(StatusCode::NO_CONTENT)
+    })
+    .await
+}
+
+/// Remove a user's wrapping of the device key (requires `device_key:manage`)
+pub async fn remove_wrapping(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(user_id): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    blocking(move || {
+        let user_id = Id::parse_str(&user_id).map_err(|_| ApiError::bad_request("Invalid user ID"))?;
+        device_lock(&state)?.remove(&claims, user_id)?;
+
+        Ok(StatusCode::NO_CONTENT)
+    })
+    .await
+}
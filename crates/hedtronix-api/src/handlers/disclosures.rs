@@ -0,0 +1,93 @@
+//! Accounting of disclosures ("who looked at my chart") handlers
+
+use axum::{
+    extract::{Path, Query, State},
+    http::header,
+    response::{IntoResponse, Response},
+    Json,
+};
+use hedtronix_core::{Id, Timestamp};
+use hedtronix_db::{DisclosureRepository, PatientRepository};
+use serde::Deserialize;
+
+use crate::access::Caller;
+use crate::blocking::blocking;
+use crate::error::ApiError;
+use crate::state::AppState;
+
+/// Disclosures are accountable for six years
+const DEFAULT_PERIOD_DAYS: i64 = 6 * 365;
+
+#[derive(Debug, Deserialize)]
+pub struct DisclosureQuery {
+    pub start: Option<Timestamp>,
+    pub end: Option<Timestamp>,
+    /// `json` (default), `csv` or `pdf`
+    pub format: Option<String>,
+}
+
+/// Privacy staff with `audit_logs:read` may report on any patient; a
+/// patient may report on their own chart
+fn require_report(state: &AppState, caller: &Caller, patient_id: Id) -> Result<(), ApiError> {
+    if state.auth_state.permissions.authorize(&caller.claims, "audit_logs", "read") {
+        return Ok(());
+    }
+
+    let subject = state.access_policy().subject(&caller.claims)?;
+    if subject.patient_id == Some(patient_id) {
+        Ok(())
+    } else {
+        Err(ApiError::forbidden("Disclosure reports require the audit_logs:read permission"))
+    }
+}
+
+/// Every audited access to the patient's records in the period, grouped by
+/// user and purpose
+pub async fn get_disclosure_report(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(id): Path<String>,
+    Query(query): Query<DisclosureQuery>,
+) -> Result<Response, ApiError> {
+    blocking(move || {
+        let patient_id = Id::parse_str(&id)
+            .map_err(|_| ApiError::bad_request("Invalid patient ID"))?;
+        require_report(&state, &caller, patient_id)?;
+
+        let end = query.end.unwrap_or_else(chrono::Utc::now);
+        let start = query.start.unwrap_or(end - chrono::Duration::days(DEFAULT_PERIOD_DAYS));
+        if start >= end {
+            return Err(ApiError::bad_request("start must be before end"));
+        }
+
+        let patient = PatientRepository::new(state.db.clone(), state.keyring.clone())
+            .find_by_id(patient_id)?
+            .ok_or_else(|| ApiError::not_found("Patient"))?;
+        let report = DisclosureRepository::new(state.db.clone()).report(&patient, start, end)?;
+
+        let filename = format!("disclosures-{}-{}", patient.medical_record_number, end.format("%Y%m%d"));
+        let response = match query.format.as_deref().unwrap_or("json") {
+            "json" => Json(report).into_response(),
+            "csv" => (
+                [
+                    (header::CONTENT_TYPE, "text/csv; charset=utf-8".to_string()),
+                    (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}.csv\"", filename)),
+                ],
+                report.to_csv(),
+            )
+                .into_response(),
+            "pdf" => (
+                [
+                    (header::CONTENT_TYPE, "application/pdf".to_string()),
+                    (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}.pdf\"", filename)),
+                ],
+                report.to_pdf(),
+            )
+                .into_response(),
+            other => return Err(ApiError::bad_request(&format!("Unsupported format: {}", other))),
+        };
+
+        Ok(response)
+    })
+    .await
+}
@@ -0,0 +1,70 @@
+//! Break-the-glass emergency access handlers
+
+use axum::{
+    extract::{Path, State},
+    Extension, Json,
+};
+use hedtronix_auth::{Claims, EmergencyAccessResponse, EmergencyAccessReview};
+use hedtronix_core::{CreateEmergencyAccess, EmergencyAccessGrant, Id, ReviewEmergencyAccess};
+use validator::Validate;
+
+use crate::blocking::blocking;
+use crate::error::ApiError;
+use crate::state::AppState;
+
+/// Request time-limited access to a chart outside the caller's scope
+pub async fn request_access(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(req): Json<CreateEmergencyAccess>,
+) -> Result<Json<EmergencyAccessResponse>, ApiError> {
+    blocking(move || {
+        req.validate()
+            .map_err(|e| ApiError::validation(&e.to_string()))?;
+
+        Ok(Json(state.emergency_access().request(&claims, req)?))
+    })
+    .await
+}
+
+/// End a grant before it expires
+pub async fn revoke_access(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<String>,
+) -> Result<Json<EmergencyAccessGrant>, ApiError> {
+    blocking(move || {
+        let grant_id = Id::parse_str(&id)
+            .map_err(|_| ApiError::bad_request("Invalid grant ID"))?;
+
+        Ok(Json(state.emergency_access().revoke(&claims, grant_id)?))
+    })
+    .await
+}
+
+/// Privacy officer queue: grants not yet reviewed, with their accesses
+pub async fn list_pending_reviews(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Json<Vec<EmergencyAccessReview>>, ApiError> {
+    blocking(move || {
+        Ok(Json(state.emergency_access().pending_reviews(&claims)?))
+    })
+    .await
+}
+
+/// Record whether a grant was justified
+pub async fn review_access(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<String>,
+    Json(req): Json<ReviewEmergencyAccess>,
+) -> Result<Json<EmergencyAccessGrant>, ApiError> {
+    blocking(move || {
+        let grant_id = Id::parse_str(&id)
+            .map_err(|_| ApiError::bad_request("Invalid grant ID"))?;
+
+        Ok(Json(state.emergency_access().review(&claims, grant_id, req)?))
+    })
+    .await
+}
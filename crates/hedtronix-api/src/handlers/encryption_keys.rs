@@ -0,0 +1,119 @@
+//! Data encryption key rotation and re-encryption handlers
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use hedtronix_auth::Claims;
+use hedtronix_core::Id;
+use hedtronix_db::{DataKeyRecord, DataKeyReport, DataKeyRepository, KeyStore, ReencryptionJob};
+use serde::Serialize;
+
+use crate::blocking::blocking;
+use crate::error::ApiError;
+use crate::state::AppState;
+
+/// A new active data key and the job moving existing rows onto it
+#[derive(Debug, Serialize)]
+pub struct RotationResponse {
+    pub key: DataKeyRecord,
+    pub job: ReencryptionJob,
+}
+
+fn authorize(state: &AppState, claims: &Claims) -> Result<Arc<KeyStore>, ApiError> {
+    if !state.auth_state.permissions.authorize(claims, "encryption_keys", "manage") {
+        return Err(ApiError::forbidden("Requires the encryption_keys:manage permission"));
+    }
+    if let Some(key_store) = &state.key_store {
+        return Ok(key_store.clone());
+    }
+    match &state.auth_state.device_lock {
+        // Keys are wrapped by the device key while it is unlocked
+        Some(device_lock) => Ok(Arc::new(device_lock.key_store()?)),
+        None => Err(ApiError::internal("Envelope encryption is not configured")),
+    }
+}
+
+/// Record a re-encryption job and run it in the background
+fn start_reencryption(state: &AppState) -> Result<ReencryptionJob, ApiError> {
+    let reencryptor = state.reencryptor();
+    let job = reencryptor.start()?;
+    tokio::spawn(crate::reencrypt_fields(reencryptor, job.id, state.reencryption_batch_size));
+    Ok(job)
+}
+
+/// Stored data keys and how many values each still encrypts
+pub async fn list_keys(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Json<DataKeyReport>, ApiError> {
+    let key_store = authorize(&state, &claims)?;
+    let keyring = state.keyring.clone();
+
+    let report = tokio::task::spawn_blocking(move || key_store.report(&keyring))
+        .await
+        .map_err(|e| ApiError::internal(&e.to_string()))??;
+
+    Ok(Json(report))
+}
+
+/// Activate a new data key and start re-encrypting rows with it
+pub async fn rotate_key(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<(StatusCode, Json<RotationResponse>), ApiError> {
+    blocking(move || {
+        let key_store = authorize(&state, &claims)?;
+        let key = key_store.rotate(&state.keyring)?;
+        tracing::info!("Rotated data encryption key to {}", key.id);
+
+        let job = start_reencryption(&state)?;
+        Ok((StatusCode::ACCEPTED, Json(RotationResponse { key, job })))
+    })
+    .await
+}
+
+/// Re-encrypt rows not yet on the active key and ciphertext format, e.g.
+/// values left behind by a failed job
+pub async fn reencrypt(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<(StatusCode, Json<ReencryptionJob>), ApiError> {
+    blocking(move || {
+        authorize(&state, &claims)?;
+        Ok((StatusCode::ACCEPTED, Json(start_reencryption(&state)?)))
+    })
+    .await
+}
+
+pub async fn list_jobs(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Json<Vec<ReencryptionJob>>, ApiError> {
+    blocking(move || {
+        authorize(&state, &claims)?;
+        Ok(Json(DataKeyRepository::new(state.db.clone()).list_jobs(50)?))
+    })
+    .await
+}
+
+/// Progress of one re-encryption job
+pub async fn get_job(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<String>,
+) -> Result<Json<ReencryptionJob>, ApiError> {
+    blocking(move || {
+        authorize(&state, &claims)?;
+        let job_id = Id::parse_str(&id).map_err(|_| ApiError::bad_request("Invalid job ID"))?;
+
+        DataKeyRepository::new(state.db.clone())
+            .find_job(job_id)?
+            .map(Json)
+            .ok_or_else(|| ApiError::not_found("Re-encryption job"))
+    })
+    .await
+}
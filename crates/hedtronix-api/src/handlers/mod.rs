@@ -6,7 +6,13 @@ pub mod patients;
 pub mod appointments;
 pub mod sync;
 pub mod users;
+pub mod roles;
+pub mod emergency_access;
 pub mod clinical_notes;
 pub mod billing;
 pub mod analytics;
 pub mod audit_log;
+pub mod disclosures;
+pub mod access_alerts;
+pub mod encryption_keys;
+pub mod device_key;
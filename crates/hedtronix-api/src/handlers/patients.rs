@@ -4,13 +4,17 @@ use axum::{
     extract::{Path, Query, State},
     Json,
 };
+use hedtronix_auth::{AccessDecision, PatientScope};
 use hedtronix_core::{
-    Patient, CreatePatient, UpdatePatient, PatientSearchFilters,
-    Gender, Id, Allergy, Medication, AllergySeverity,
+    Patient, PatientSearchFilters,
+    Gender, Id, Allergy, InsuranceInfo, Medication, AllergySeverity,
 };
 use hedtronix_db::PatientRepository;
 use serde::{Deserialize, Serialize};
 
+use crate::access::{self, Caller};
+use crate::audit::{audited, Audited};
+use crate::blocking::blocking;
 use crate::error::ApiError;
 use crate::state::AppState;
 
@@ -24,32 +28,40 @@ pub struct ListQuery {
 
 pub async fn list_patients(
     State(state): State<AppState>,
+    caller: Caller,
     Query(query): Query<ListQuery>,
-) -> Result<Json<ListPatientsResponse>, ApiError> {
-    let repo = PatientRepository::new(state.db.clone(), state.encryption_key.clone());
-    let filters = PatientSearchFilters {
-        page: query.page.unwrap_or(0),
-        limit: query.limit.unwrap_or(20).min(100),
-        active_only: query.active_only.unwrap_or(true),
-        ..Default::default()
-    };
-    
-    let patients = repo.search(&filters)
-        .map_err(|e| ApiError::internal(&e.to_string()))?;
-    let total = repo.count()
-        .map_err(|e| ApiError::internal(&e.to_string()))?;
-    
-    Ok(Json(ListPatientsResponse {
-        patients: patients.into_iter().map(PatientDto::from).collect(),
-        total,
-        page: filters.page,
-        limit: filters.limit,
-    }))
+) -> Result<Audited<ListPatientsResponse>, ApiError> {
+    blocking(move || {
+        require_read(&state, &caller)?;
+        let repo = PatientRepository::new(state.db.clone(), state.keyring.clone());
+        let filters = PatientSearchFilters {
+            page: query.page.unwrap_or(0),
+            limit: query.limit.unwrap_or(20).min(100),
+            active_only: query.active_only.unwrap_or(true),
+            visible_to: state.access_policy().subject(&caller.claims)?.chart_filter(),
+            ..Default::default()
+        };
+    
+        let patients = repo.search(&filters)
+            .map_err(|e| ApiError::internal(&e.to_string()))?;
+        let (ids, patients) = visible_patients(&state, &caller, patients)?;
+        let total = repo.search_count(&filters)
+            .map_err(|e| ApiError::internal(&e.to_string()))?;
+    
+        Ok(audited(ids, ListPatientsResponse {
+            patients,
+            total,
+            page: filters.page,
+            limit: filters.limit,
+        }))
+    })
+    .await
 }
 
 #[derive(Debug, Serialize)]
 pub struct ListPatientsResponse {
-    pub patients: Vec<PatientDto>,
+    /// Redacted `PatientDto`s
+    pub patients: Vec<serde_json::Value>,
     pub total: i64,
     pub page: u32,
     pub limit: u32,
@@ -58,46 +70,61 @@ pub struct ListPatientsResponse {
 /// Get patient by ID
 pub async fn get_patient(
     State(state): State<AppState>,
+    caller: Caller,
     Path(id): Path<String>,
-) -> Result<Json<PatientDto>, ApiError> {
-    let patient_id = Id::parse_str(&id)
-        .map_err(|_| ApiError::bad_request("Invalid patient ID"))?;
-    
-    let repo = PatientRepository::new(state.db.clone(), state.encryption_key.clone());
-    let patient = repo.find_by_id(patient_id)
-        .map_err(|e| ApiError::internal(&e.to_string()))?
-        .ok_or_else(|| ApiError::not_found("Patient"))?;
-    
-    Ok(Json(PatientDto::from(patient)))
+) -> Result<Json<serde_json::Value>, ApiError> {
+    blocking(move || {
+        let patient_id = Id::parse_str(&id)
+            .map_err(|_| ApiError::bad_request("Invalid patient ID"))?;
+        require_read(&state, &caller)?;
+        let scope = require_patient(&state, &caller, patient_id)?;
+    
+        let repo = PatientRepository::new(state.db.clone(), state.keyring.clone());
+        let patient = repo.find_by_id(patient_id)
+            .map_err(|e| ApiError::internal(&e.to_string()))?
+            .ok_or_else(|| ApiError::not_found("Patient"))?;
+    
+        Ok(Json(access::redact(&state, &caller, "Patient", &PatientDto::scoped(patient, scope))))
+    })
+    .await
 }
 
 /// Create new patient
 pub async fn create_patient(
     State(state): State<AppState>,
+    caller: Caller,
     Json(req): Json<CreatePatientRequest>,
-) -> Result<Json<PatientDto>, ApiError> {
-    let gender = parse_gender(&req.gender)?;
-    let dob = chrono::NaiveDate::parse_from_str(&req.date_of_birth, "%Y-%m-%d")
-        .map_err(|_| ApiError::bad_request("Invalid date format, use YYYY-MM-DD"))?;
-    
-    let repo = PatientRepository::new(state.db.clone(), state.encryption_key.clone());
-    let mrn = repo.generate_mrn()
-        .map_err(|e| ApiError::internal(&e.to_string()))?;
-    
-    let patient = Patient::new(mrn, req.first_name, req.last_name, dob, gender);
-    
-    repo.create(&patient)
-        .map_err(|e| ApiError::internal(&e.to_string()))?;
-    
-    // Track for sync
-    let sync_engine = state.sync_engine();
-    let _ = sync_engine.track_create(
-        "Patient",
-        patient.id,
-        serde_json::to_value(&patient).unwrap_or_default(),
-    );
-    
-    Ok(Json(PatientDto::from(patient)))
+) -> Result<Json<serde_json::Value>, ApiError> {
+    blocking(move || {
+        access::require_permission(&state, &caller, "patients", "create")?;
+        let gender = parse_gender(&req.gender)?;
+        let dob = chrono::NaiveDate::parse_from_str(&req.date_of_birth, "%Y-%m-%d")
+            .map_err(|_| ApiError::bad_request("Invalid date format, use YYYY-MM-DD"))?;
+    
+        let repo = PatientRepository::new(state.db.clone(), state.keyring.clone());
+        let mrn = repo.generate_mrn()
+            .map_err(|e| ApiError::internal(&e.to_string()))?;
+    
+        let mut patient = Patient::new(mrn, req.first_name, req.last_name, dob, gender);
+        if let Some(phone) = req.phone {
+            patient.phone = phone;
+        }
+        patient.email = req.email;
+    
+        repo.create(&patient)
+            .map_err(|e| ApiError::internal(&e.to_string()))?;
+    
+        // Track for sync
+        let sync_engine = state.sync_engine();
+        let _ = sync_engine.track_create(
+            "Patient",
+            patient.id,
+            serde_json::to_value(&patient).unwrap_or_default(),
+        );
+    
+        Ok(Json(access::redact(&state, &caller, "Patient", &PatientDto::from(patient))))
+    })
+    .await
 }
 
 #[derive(Debug, Deserialize)]
@@ -113,44 +140,50 @@ pub struct CreatePatientRequest {
 /// Update patient
 pub async fn update_patient(
     State(state): State<AppState>,
+    caller: Caller,
     Path(id): Path<String>,
     Json(req): Json<UpdatePatientRequest>,
-) -> Result<Json<PatientDto>, ApiError> {
-    let patient_id = Id::parse_str(&id)
-        .map_err(|_| ApiError::bad_request("Invalid patient ID"))?;
-    
-    let repo = PatientRepository::new(state.db.clone(), state.encryption_key.clone());
-    let mut patient = repo.find_by_id(patient_id)
-        .map_err(|e| ApiError::internal(&e.to_string()))?
-        .ok_or_else(|| ApiError::not_found("Patient"))?;
-    
-    // Update fields
-    if let Some(first_name) = req.first_name {
-        patient.first_name = first_name;
-    }
-    if let Some(last_name) = req.last_name {
-        patient.last_name = last_name;
-    }
-    if let Some(phone) = req.phone {
-        patient.phone = phone;
-    }
-    if let Some(email) = req.email {
-        patient.email = Some(email);
-    }
-    patient.updated_at = chrono::Utc::now();
-    
-    repo.update(&patient)
-        .map_err(|e| ApiError::internal(&e.to_string()))?;
-    
-    // Track for sync
-    let sync_engine = state.sync_engine();
-    let _ = sync_engine.track_update(
-        "Patient",
-        patient.id,
-        serde_json::to_value(&patient).unwrap_or_default(),
-    );
-    
-    Ok(Json(PatientDto::from(patient)))
+) -> Result<Json<serde_json::Value>, ApiError> {
+    blocking(move || {
+        let patient_id = Id::parse_str(&id)
+            .map_err(|_| ApiError::bad_request("Invalid patient ID"))?;
+        access::require_permission(&state, &caller, "patients", "write")?;
+        let scope = require_patient(&state, &caller, patient_id)?;
+    
+        let repo = PatientRepository::new(state.db.clone(), state.keyring.clone());
+        let mut patient = repo.find_by_id(patient_id)
+            .map_err(|e| ApiError::internal(&e.to_string()))?
+            .ok_or_else(|| ApiError::not_found("Patient"))?;
+    
+        // Update fields
+        if let Some(first_name) = req.first_name {
+            patient.first_name = first_name;
+        }
+        if let Some(last_name) = req.last_name {
+            patient.last_name = last_name;
+        }
+        if let Some(phone) = req.phone {
+            patient.phone = phone;
+        }
+        if let Some(email) = req.email {
+            patient.email = Some(email);
+        }
+        patient.updated_at = chrono::Utc::now();
+    
+        repo.update(&patient)
+            .map_err(|e| ApiError::internal(&e.to_string()))?;
+    
+        // Track for sync
+        let sync_engine = state.sync_engine();
+        let _ = sync_engine.track_update(
+            "Patient",
+            patient.id,
+            serde_json::to_value(&patient).unwrap_or_default(),
+        );
+    
+        Ok(Json(access::redact(&state, &caller, "Patient", &PatientDto::scoped(patient, scope))))
+    })
+    .await
 }
 
 #[derive(Debug, Deserialize)]
@@ -164,27 +197,33 @@ pub struct UpdatePatientRequest {
 /// Delete patient (soft delete)
 pub async fn delete_patient(
     State(state): State<AppState>,
+    caller: Caller,
     Path(id): Path<String>,
 ) -> Result<Json<DeleteResponse>, ApiError> {
-    let patient_id = Id::parse_str(&id)
-        .map_err(|_| ApiError::bad_request("Invalid patient ID"))?;
-    
-    let repo = PatientRepository::new(state.db.clone(), state.encryption_key.clone());
-    let mut patient = repo.find_by_id(patient_id)
-        .map_err(|e| ApiError::internal(&e.to_string()))?
-        .ok_or_else(|| ApiError::not_found("Patient"))?;
-    
-    patient.active = false;
-    patient.updated_at = chrono::Utc::now();
-    
-    repo.update(&patient)
-        .map_err(|e| ApiError::internal(&e.to_string()))?;
-    
-    // Track for sync
-    let sync_engine = state.sync_engine();
-    let _ = sync_engine.track_delete("Patient", patient.id);
-    
-    Ok(Json(DeleteResponse { success: true }))
+    blocking(move || {
+        let patient_id = Id::parse_str(&id)
+            .map_err(|_| ApiError::bad_request("Invalid patient ID"))?;
+        access::require_permission(&state, &caller, "patients", "delete")?;
+        require_patient(&state, &caller, patient_id)?;
+    
+        let repo = PatientRepository::new(state.db.clone(), state.keyring.clone());
+        let mut patient = repo.find_by_id(patient_id)
+            .map_err(|e| ApiError::internal(&e.to_string()))?
+            .ok_or_else(|| ApiError::not_found("Patient"))?;
+    
+        patient.active = false;
+        patient.updated_at = chrono::Utc::now();
+    
+        repo.update(&patient)
+            .map_err(|e| ApiError::internal(&e.to_string()))?;
+    
+        // Track for sync
+        let sync_engine = state.sync_engine();
+        let _ = sync_engine.track_delete("Patient", patient.id);
+    
+        Ok(Json(DeleteResponse { success: true }))
+    })
+    .await
 }
 
 #[derive(Debug, Serialize)]
@@ -195,31 +234,51 @@ pub struct DeleteResponse {
 /// Search patients
 pub async fn search_patients(
     State(state): State<AppState>,
+    caller: Caller,
     Json(req): Json<SearchRequest>,
-) -> Result<Json<ListPatientsResponse>, ApiError> {
-    let repo = PatientRepository::new(state.db.clone(), state.encryption_key.clone());
-    let filters = PatientSearchFilters {
-        query: Some(req.query),
-        page: req.page.unwrap_or(0),
-        limit: req.limit.unwrap_or(20).min(100),
-        active_only: req.active_only.unwrap_or(true),
-        ..Default::default()
-    };
-    
-    let patients = repo.search(&filters)
-        .map_err(|e| ApiError::internal(&e.to_string()))?;
-    
-    Ok(Json(ListPatientsResponse {
-        patients: patients.into_iter().map(PatientDto::from).collect(),
-        total: 0, // Would need count query
-        page: filters.page,
-        limit: filters.limit,
-    }))
+) -> Result<Audited<ListPatientsResponse>, ApiError> {
+    blocking(move || {
+        require_read(&state, &caller)?;
+        let repo = PatientRepository::new(state.db.clone(), state.keyring.clone());
+        let filters = PatientSearchFilters {
+            query: req.query,
+            first_name: req.first_name,
+            last_name: req.last_name,
+            medical_record_number: req.medical_record_number,
+            date_of_birth: req.date_of_birth,
+            phone: req.phone,
+            page: req.page.unwrap_or(0),
+            limit: req.limit.unwrap_or(20).min(100),
+            active_only: req.active_only.unwrap_or(true),
+            visible_to: state.access_policy().subject(&caller.claims)?.chart_filter(),
+            ..Default::default()
+        };
+    
+        let patients = repo.search(&filters)
+            .map_err(|e| ApiError::internal(&e.to_string()))?;
+        let (ids, patients) = visible_patients(&state, &caller, patients)?;
+        let total = repo.search_count(&filters)
+            .map_err(|e| ApiError::internal(&e.to_string()))?;
+    
+        Ok(audited(ids, ListPatientsResponse {
+            patients,
+            total,
+            page: filters.page,
+            limit: filters.limit,
+        }))
+    })
+    .await
 }
 
+/// Free-text query and exact field filters; all given filters must match
 #[derive(Debug, Deserialize)]
 pub struct SearchRequest {
-    pub query: String,
+    pub query: Option<String>,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    pub medical_record_number: Option<String>,
+    pub date_of_birth: Option<chrono::NaiveDate>,
+    pub phone: Option<String>,
     pub page: Option<u32>,
     pub limit: Option<u32>,
     pub active_only: Option<bool>,
@@ -228,40 +287,46 @@ pub struct SearchRequest {
 /// Add allergy to patient
 pub async fn add_allergy(
     State(state): State<AppState>,
+    caller: Caller,
     Path(id): Path<String>,
     Json(req): Json<AddAllergyRequest>,
-) -> Result<Json<PatientDto>, ApiError> {
-    let patient_id = Id::parse_str(&id)
-        .map_err(|_| ApiError::bad_request("Invalid patient ID"))?;
-    
-    let repo = PatientRepository::new(state.db.clone(), state.encryption_key.clone());
-    let mut patient = repo.find_by_id(patient_id)
-        .map_err(|e| ApiError::internal(&e.to_string()))?
-        .ok_or_else(|| ApiError::not_found("Patient"))?;
-    
-    let severity = match req.severity.to_uppercase().as_str() {
-        "MILD" => AllergySeverity::Mild,
-        "MODERATE" => AllergySeverity::Moderate,
-        "SEVERE" => AllergySeverity::Severe,
-        "LIFE_THREATENING" => AllergySeverity::LifeThreatening,
-        _ => AllergySeverity::Moderate,
-    };
-    
-    let allergy = Allergy {
-        id: Id::new_v4(),
-        name: req.name,
-        severity,
-        reaction: req.reaction,
-        onset_date: req.onset_date,
-        created_at: chrono::Utc::now(),
-    };
-    
-    patient.add_allergy(allergy);
-    
-    repo.update(&patient)
-        .map_err(|e| ApiError::internal(&e.to_string()))?;
-    
-    Ok(Json(PatientDto::from(patient)))
+) -> Result<Json<serde_json::Value>, ApiError> {
+    blocking(move || {
+        let patient_id = Id::parse_str(&id)
+            .map_err(|_| ApiError::bad_request("Invalid patient ID"))?;
+        access::require_permission(&state, &caller, "patients", "write")?;
+        require_clinical(&state, &caller, patient_id)?;
+    
+        let repo = PatientRepository::new(state.db.clone(), state.keyring.clone());
+        let mut patient = repo.find_by_id(patient_id)
+            .map_err(|e| ApiError::internal(&e.to_string()))?
+            .ok_or_else(|| ApiError::not_found("Patient"))?;
+    
+        let severity = match req.severity.to_uppercase().as_str() {
+            "MILD" => AllergySeverity::Mild,
+            "MODERATE" => AllergySeverity::Moderate,
+            "SEVERE" => AllergySeverity::Severe,
+            "LIFE_THREATENING" => AllergySeverity::LifeThreatening,
+            _ => AllergySeverity::Moderate,
+        };
+    
+        let allergy = Allergy {
+            id: Id::new_v4(),
+            name: req.name,
+            severity,
+            reaction: req.reaction,
+            onset_date: req.onset_date,
+            created_at: chrono::Utc::now(),
+        };
+    
+        patient.add_allergy(allergy);
+    
+        repo.update(&patient)
+            .map_err(|e| ApiError::internal(&e.to_string()))?;
+    
+        Ok(Json(access::redact(&state, &caller, "Patient", &PatientDto::from(patient))))
+    })
+    .await
 }
 
 #[derive(Debug, Deserialize)]
@@ -275,34 +340,40 @@ pub struct AddAllergyRequest {
 /// Add medication to patient
 pub async fn add_medication(
     State(state): State<AppState>,
+    caller: Caller,
     Path(id): Path<String>,
     Json(req): Json<AddMedicationRequest>,
-) -> Result<Json<PatientDto>, ApiError> {
-    let patient_id = Id::parse_str(&id)
-        .map_err(|_| ApiError::bad_request("Invalid patient ID"))?;
-    
-    let repo = PatientRepository::new(state.db.clone(), state.encryption_key.clone());
-    let mut patient = repo.find_by_id(patient_id)
-        .map_err(|e| ApiError::internal(&e.to_string()))?
-        .ok_or_else(|| ApiError::not_found("Patient"))?;
-    
-    let medication = Medication {
-        id: Id::new_v4(),
-        name: req.name,
-        dosage: req.dosage,
-        frequency: req.frequency,
-        start_date: req.start_date,
-        end_date: None,
-        prescriber_id: None,
-        active: true,
-    };
-    
-    patient.add_medication(medication);
-    
-    repo.update(&patient)
-        .map_err(|e| ApiError::internal(&e.to_string()))?;
-    
-    Ok(Json(PatientDto::from(patient)))
+) -> Result<Json<serde_json::Value>, ApiError> {
+    blocking(move || {
+        let patient_id = Id::parse_str(&id)
+            .map_err(|_| ApiError::bad_request("Invalid patient ID"))?;
+        access::require_permission(&state, &caller, "patients", "write")?;
+        require_clinical(&state, &caller, patient_id)?;
+    
+        let repo = PatientRepository::new(state.db.clone(), state.keyring.clone());
+        let mut patient = repo.find_by_id(patient_id)
+            .map_err(|e| ApiError::internal(&e.to_string()))?
+            .ok_or_else(|| ApiError::not_found("Patient"))?;
+    
+        let medication = Medication {
+            id: Id::new_v4(),
+            name: req.name,
+            dosage: req.dosage,
+            frequency: req.frequency,
+            start_date: req.start_date,
+            end_date: None,
+            prescriber_id: None,
+            active: true,
+        };
+    
+        patient.add_medication(medication);
+    
+        repo.update(&patient)
+            .map_err(|e| ApiError::internal(&e.to_string()))?;
+    
+        Ok(Json(access::redact(&state, &caller, "Patient", &PatientDto::from(patient))))
+    })
+    .await
 }
 
 #[derive(Debug, Deserialize)]
@@ -314,6 +385,51 @@ pub struct AddMedicationRequest {
 }
 
 // Helper functions
+
+/// Reading charts needs `patients:read` or `patients:read_financial`;
+/// patients read their own with `own_data:read`
+fn require_read(state: &AppState, caller: &Caller) -> Result<(), ApiError> {
+    access::require_permission(state, caller, "patients", "read")
+        .or_else(|_| access::require_permission(state, caller, "patients", "read_financial"))
+        .or_else(|_| access::require_permission(state, caller, "own_data", "read"))
+}
+
+/// Scope the caller has on a patient's chart
+fn require_patient(state: &AppState, caller: &Caller, patient_id: Id) -> Result<PatientScope, ApiError> {
+    access::require_patient(state, caller, patient_id, "Patient", patient_id)
+}
+
+/// Allergies and medications may only be changed with full chart access
+fn require_clinical(state: &AppState, caller: &Caller, patient_id: Id) -> Result<(), ApiError> {
+    match require_patient(state, caller, patient_id)? {
+        PatientScope::Full => Ok(()),
+        PatientScope::Financial => Err(ApiError::forbidden("Clinical data requires full chart access")),
+    }
+}
+
+/// Redact each patient to the caller's scope. The search is already limited
+/// to charts the caller may open; any it cannot are dropped. Returns the IDs
+/// of the patients kept alongside their records.
+fn visible_patients(
+    state: &AppState,
+    caller: &Caller,
+    patients: Vec<Patient>,
+) -> Result<(Vec<Id>, Vec<serde_json::Value>), ApiError> {
+    let policy = state.access_policy();
+    let subject = policy.subject(&caller.claims)?;
+    
+    let mut ids = Vec::with_capacity(patients.len());
+    let mut visible = Vec::with_capacity(patients.len());
+    for patient in patients {
+        if let AccessDecision::Allow(scope) = policy.patient_access(&subject, patient.id)? {
+            ids.push(patient.id);
+            visible.push(access::redact(state, caller, "Patient", &PatientDto::scoped(patient, scope)));
+        }
+    }
+    
+    Ok((ids, visible))
+}
+
 fn parse_gender(s: &str) -> Result<Gender, ApiError> {
     match s.to_uppercase().as_str() {
         "MALE" | "M" => Ok(Gender::Male),
@@ -337,11 +453,26 @@ pub struct PatientDto {
     pub email: Option<String>,
     pub allergies: Vec<AllergyDto>,
     pub medications: Vec<MedicationDto>,
+    pub problems: Vec<String>,
+    pub insurance: InsuranceInfo,
     pub active: bool,
     pub created_at: String,
     pub updated_at: String,
 }
 
+impl PatientDto {
+    /// Financial scope leaves out allergies and medications; the problem
+    /// list stays for coding and is filtered by redaction
+    pub fn scoped(patient: Patient, scope: PatientScope) -> Self {
+        let mut dto = Self::from(patient);
+        if scope == PatientScope::Financial {
+            dto.allergies.clear();
+            dto.medications.clear();
+        }
+        dto
+    }
+}
+
 impl From<Patient> for PatientDto {
     fn from(p: Patient) -> Self {
         Self {
@@ -355,6 +486,8 @@ impl From<Patient> for PatientDto {
             email: p.email,
             allergies: p.allergies.into_iter().map(AllergyDto::from).collect(),
             medications: p.medications.into_iter().map(MedicationDto::from).collect(),
+            problems: p.problems,
+            insurance: p.insurance_info,
             active: p.active,
             created_at: p.created_at.to_rfc3339(),
             updated_at: p.updated_at.to_rfc3339(),
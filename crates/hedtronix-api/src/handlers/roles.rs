@@ -0,0 +1,139 @@
+//! Role administration handlers
+
+use axum::{
+    extract::{Path, State},
+    Extension, Json,
+};
+use hedtronix_core::{CreateRole, Id, Permission, Role};
+use hedtronix_auth::Claims;
+use serde::{Deserialize, Serialize};
+
+use crate::blocking::blocking;
+use crate::error::ApiError;
+use crate::state::AppState;
+
+/// Only callers granted `roles:manage` may change roles
+fn require_manage(state: &AppState, claims: &Claims) -> Result<(), ApiError> {
+    if state.auth_state.permissions.authorize(claims, "roles", "manage") {
+        Ok(())
+    } else {
+        Err(ApiError::forbidden("Managing roles requires the roles:manage permission"))
+    }
+}
+
+/// List built-in and custom roles with their permissions
+pub async fn list_roles(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Json<Vec<Role>>, ApiError> {
+    blocking(move || {
+        require_manage(&state, &claims)?;
+
+        Ok(Json(state.auth_state.permissions.list_roles()?))
+    })
+    .await
+}
+
+/// Get a role by ID
+pub async fn get_role(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<String>,
+) -> Result<Json<Role>, ApiError> {
+    blocking(move || {
+        require_manage(&state, &claims)?;
+
+        Ok(Json(state.auth_state.permissions.find_role(&id)?))
+    })
+    .await
+}
+
+/// Create a custom role
+pub async fn create_role(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(req): Json<CreateRole>,
+) -> Result<Json<Role>, ApiError> {
+    blocking(move || {
+        require_manage(&state, &claims)?;
+
+        Ok(Json(state.auth_state.permissions.create_role(req)?))
+    })
+    .await
+}
+
+/// Delete a custom role that no user holds
+pub async fn delete_role(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<String>,
+) -> Result<Json<DeleteResponse>, ApiError> {
+    blocking(move || {
+        require_manage(&state, &claims)?;
+
+        state.auth_state.permissions.delete_role(&id)?;
+
+        Ok(Json(DeleteResponse { success: true }))
+    })
+    .await
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeleteResponse {
+    pub success: bool,
+}
+
+/// Grant a resource/action pair to a role
+pub async fn grant_permission(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<String>,
+    Json(permission): Json<Permission>,
+) -> Result<Json<Role>, ApiError> {
+    blocking(move || {
+        require_manage(&state, &claims)?;
+
+        Ok(Json(state.auth_state.permissions.grant(&id, &permission)?))
+    })
+    .await
+}
+
+/// Revoke a grant, given as `resource:action`
+pub async fn revoke_permission(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path((id, permission)): Path<(String, String)>,
+) -> Result<Json<Role>, ApiError> {
+    blocking(move || {
+        require_manage(&state, &claims)?;
+
+        let permission = Permission::parse(&permission)
+            .ok_or_else(|| ApiError::bad_request("Permission must be resource:action"))?;
+
+        Ok(Json(state.auth_state.permissions.revoke(&id, &permission)?))
+    })
+    .await
+}
+
+/// Assign a built-in or custom role to a user
+pub async fn assign_role(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(user_id): Path<String>,
+    Json(req): Json<AssignRoleRequest>,
+) -> Result<Json<Role>, ApiError> {
+    blocking(move || {
+        require_manage(&state, &claims)?;
+
+        let user_id = Id::parse_str(&user_id)
+            .map_err(|_| ApiError::bad_request("Invalid user ID"))?;
+
+        Ok(Json(state.auth_state.permissions.assign_role(user_id, &req.role_id)?))
+    })
+    .await
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AssignRoleRequest {
+    pub role_id: String,
+}
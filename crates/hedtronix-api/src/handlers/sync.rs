@@ -1,91 +1,164 @@
 //! Sync handlers
 
-use axum::{extract::State, Json};
-use hedtronix_core::Id;
-use hedtronix_core::crdt::Change;
-use hedtronix_sync::{
-    protocol::{PushRequest, PushResponse, PullRequest, PullResponse, SyncHealth, SyncHealthStatus},
-    SyncState,
+use axum::{
+    extract::{Query, State},
+    Extension, Json,
 };
+use hedtronix_auth::Claims;
+use hedtronix_core::{AuditEventType, AuditLog, Device};
+use hedtronix_db::{AuditRepository, DeviceAuditRepository, DeviceRepository, SyncKeyGrant};
+use hedtronix_sync::protocol::{
+    AuditPushRequest, AuditPushResponse, GrantKeysRequest, PushRequest, PushResponse,
+    PullRequest, PullResponse, SyncHealth, SyncRecipient,
+};
+use hedtronix_sync::SyncRelay;
 
+use crate::blocking::blocking;
 use crate::error::ApiError;
 use crate::state::AppState;
 
-/// Push local changes to server
+/// Relay changes pushed by the calling device. Payloads are encrypted on
+/// the device; the relay checks envelopes and signatures and stores them
+/// unread.
 pub async fn push_changes(
     State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
     Json(req): Json<PushRequest>,
 ) -> Result<Json<PushResponse>, ApiError> {
-    let sync_engine = state.sync_engine();
-    
-    // Apply remote changes
-    let result = sync_engine.apply_remote_changes(req.changes)
-        .map_err(|e| ApiError::internal(&format!("Sync failed: {}", e)))?;
-        
-    // In a real implementation we would map conflicts to rejected changes with reasons
-    // For now we assume conflicts are rejected
-    let rejected = result.conflicts.into_iter()
-        .map(|id| hedtronix_sync::RejectedChange {
-            change_id: id,
-            reason: "Conflict detected".to_string(),
-        })
-        .collect();
-        
-    // Calculate acknowledged (all changes not in conflicts are considered successfully processed/resolved)
-    let acknowledged = req.changes.iter()
-        .map(|c| c.id)
-        .filter(|id| !result.conflicts.contains(id))
-        .collect();
-    
-    Ok(Json(PushResponse {
-        acknowledged,
-        rejected,
-        server_time: chrono::Utc::now(),
-    }))
+    blocking(move || {
+        let device = calling_device(&state, &claims)?;
+        Ok(Json(SyncRelay::new(state.db.clone()).push(&device, &req.changes)?))
+    })
+    .await
 }
 
-/// Pull changes from server
+/// Receive the calling device's audit sub-chain
+pub async fn push_audit(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(req): Json<AuditPushRequest>,
+) -> Result<Json<AuditPushResponse>, ApiError> {
+    blocking(move || {
+        let device = calling_device(&state, &claims)?;
+
+        let receipt = DeviceAuditRepository::new(state.db.clone())
+            .receive(&device, &req.entries)?;
+
+        // Order the server's record of the push after everything it received
+        let mut log = AuditLog::new(
+            AuditEventType::Sync,
+            claims.user_id(),
+            Some(device.id),
+            "AuditLog".to_string(),
+            device.id.to_string(),
+            serde_json::json!({
+                "audit_entries_accepted": receipt.accepted,
+                "audit_entries_rejected": receipt.rejected.len(),
+                "stored_through": receipt.stored_through,
+            }),
+        );
+        log.hlc = receipt.latest_hlc;
+        let log = AuditRepository::new(state.db.clone()).append(&log)?;
+
+        Ok(Json(AuditPushResponse {
+            stored_through: receipt.stored_through,
+            rejected: receipt.rejected,
+            server_hlc: log.hlc,
+            server_time: chrono::Utc::now(),
+        }))
+    })
+    .await
+}
+
+/// Pull changes relayed for the scopes the calling device holds keys for
 pub async fn pull_changes(
     State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
     Json(req): Json<PullRequest>,
 ) -> Result<Json<PullResponse>, ApiError> {
-    let sync_engine = state.sync_engine();
-    
-    // Get pending changes for the client
-    let limit = req.limit.unwrap_or(100);
-    // In a real implementation we would filter by 'since' timestamp and 'entity_types'
-    // For now we just get pending changes from the queue
-    let changes = sync_engine.get_pending_changes(limit)
-        .map_err(|e| ApiError::internal(&e.to_string()))?;
-        
-    // Mark these as synced (in a real app we'd wait for ack)
-    // For MVP we assume successful delivery
-    let change_ids: Vec<hedtronix_core::Id> = changes.iter().map(|c| c.id).collect();
-    if !change_ids.is_empty() {
-         let _ = sync_engine.mark_synced(&change_ids);
-    }
-    
-    Ok(Json(PullResponse {
-        changes,
-        has_more: false, // Would check if count > limit
-        next_cursor: None,
-        server_time: chrono::Utc::now(),
-    }))
+    blocking(move || {
+        let device = calling_device(&state, &claims)?;
+        let limit = req.limit.unwrap_or(100);
+
+        Ok(Json(SyncRelay::new(state.db.clone()).pull(&device, req.cursor.as_deref(), limit)?))
+    })
+    .await
+}
+
+/// Sync keys sealed to the calling device
+pub async fn list_key_grants(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Json<Vec<SyncKeyGrant>>, ApiError> {
+    blocking(move || {
+        let device = calling_device(&state, &claims)?;
+        Ok(Json(SyncRelay::new(state.db.clone()).grants(&device)?))
+    })
+    .await
+}
+
+/// Store sync keys sealed by the calling device for other devices. A new
+/// key requires the `sync_keys:manage` permission.
+pub async fn grant_keys(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(req): Json<GrantKeysRequest>,
+) -> Result<Json<Vec<SyncKeyGrant>>, ApiError> {
+    blocking(move || {
+        let device = calling_device(&state, &claims)?;
+        let may_create = state.auth_state.permissions.authorize(&claims, "sync_keys", "manage");
+        let grants = SyncRelay::new(state.db.clone()).grant(&device, &req.grants, may_create)?;
+        tracing::info!("Device {} granted {} sync keys", device.id, grants.len());
+
+        Ok(Json(grants))
+    })
+    .await
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct RecipientQuery {
+    pub scope: String,
+}
+
+/// Devices a scope's sync key can be sealed to, with their agreement keys
+pub async fn list_key_recipients(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Query(query): Query<RecipientQuery>,
+) -> Result<Json<Vec<SyncRecipient>>, ApiError> {
+    blocking(move || {
+        calling_device(&state, &claims)?;
+        Ok(Json(SyncRelay::new(state.db.clone()).recipients(&query.scope)?))
+    })
+    .await
+}
+
+/// The registered, unrevoked device the token is bound to
+fn calling_device(state: &AppState, claims: &Claims) -> Result<Device, ApiError> {
+    let device_id = claims.device_id()
+        .ok_or_else(|| ApiError::bad_request("Token is not bound to a device"))?;
+    DeviceRepository::new(state.db.clone())
+        .find_by_id(device_id)?
+        .filter(|d| d.is_valid())
+        .ok_or_else(|| ApiError::forbidden("Device is not registered or has been revoked"))
 }
 
 /// Get sync status
 pub async fn get_status(
     State(state): State<AppState>,
 ) -> Result<Json<SyncStatusResponse>, ApiError> {
-    let sync_engine = state.sync_engine();
-    let status = sync_engine.get_status();
+    blocking(move || {
+        let sync_engine = state.sync_engine();
+        let status = sync_engine.get_status();
     
-    Ok(Json(SyncStatusResponse {
-        state: format!("{:?}", status.state),
-        pending_changes: status.pending_changes,
-        last_sync: status.last_sync.map(|t| t.to_rfc3339()),
-        device_id: status.device_id,
-    }))
+        Ok(Json(SyncStatusResponse {
+            state: format!("{:?}", status.state),
+            pending_changes: status.pending_changes,
+            last_sync: status.last_sync.map(|t| t.to_rfc3339()),
+            device_id: status.device_id,
+        }))
+    })
+    .await
 }
 
 #[derive(Debug, serde::Serialize)]
@@ -100,17 +173,20 @@ pub struct SyncStatusResponse {
 pub async fn get_health(
     State(state): State<AppState>,
 ) -> Result<Json<SyncHealth>, ApiError> {
-    let sync_engine = state.sync_engine();
-    let pending = sync_engine.pending_count()
-        .map_err(|e| ApiError::internal(&e.to_string()))?;
-    let last_sync = sync_engine.get_last_sync()
-        .map_err(|e| ApiError::internal(&e.to_string()))?;
+    blocking(move || {
+        let sync_engine = state.sync_engine();
+        let pending = sync_engine.pending_count()
+            .map_err(|e| ApiError::internal(&e.to_string()))?;
+        let last_sync = sync_engine.get_last_sync()
+            .map_err(|e| ApiError::internal(&e.to_string()))?;
     
-    let health = if pending > 100 {
-        SyncHealth::warning(state.device_id.clone(), pending, "High number of pending changes")
-    } else {
-        SyncHealth::healthy(state.device_id.clone(), last_sync)
-    };
+        let health = if pending > 100 {
+            SyncHealth::warning(state.device_id.clone(), pending, "High number of pending changes")
+        } else {
+            SyncHealth::healthy(state.device_id.clone(), last_sync)
+        };
     
-    Ok(Json(health))
+        Ok(Json(health))
+    })
+    .await
 }
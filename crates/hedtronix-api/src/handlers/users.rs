@@ -2,14 +2,16 @@
 
 use axum::{
     extract::{Path, Query, State, Request},
-    Json,
+    Extension, Json,
 };
 use hedtronix_core::{User, UserRole, Id};
 use hedtronix_db::UserRepository;
 use hedtronix_auth::Claims;
 use serde::{Deserialize, Serialize};
 
+use crate::blocking::blocking;
 use crate::error::ApiError;
+use crate::handlers::auth::auth_service;
 use crate::state::AppState;
 
 /// List users (admin only)
@@ -21,23 +23,28 @@ pub struct ListQuery {
 
 pub async fn list_users(
     State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
     Query(query): Query<ListQuery>,
 ) -> Result<Json<ListUsersResponse>, ApiError> {
-    let repo = UserRepository::new(state.db.clone());
-    let limit = query.limit.unwrap_or(20).min(100);
-    let offset = query.page.unwrap_or(0) * limit;
+    blocking(move || {
+        require_read(&state, &claims)?;
+        let repo = UserRepository::new(state.db.clone());
+        let limit = query.limit.unwrap_or(20).min(100);
+        let offset = query.page.unwrap_or(0) * limit;
     
-    let users = repo.find_all(limit, offset)
-        .map_err(|e| ApiError::internal(&e.to_string()))?;
-    let total = repo.count()
-        .map_err(|e| ApiError::internal(&e.to_string()))?;
+        let users = repo.find_all(limit, offset)
+            .map_err(|e| ApiError::internal(&e.to_string()))?;
+        let total = repo.count()
+            .map_err(|e| ApiError::internal(&e.to_string()))?;
     
-    Ok(Json(ListUsersResponse {
-        users: users.into_iter().map(UserDto::from).collect(),
-        total,
-        page: query.page.unwrap_or(0),
-        limit,
-    }))
+        Ok(Json(ListUsersResponse {
+            users: users.into_iter().map(UserDto::from).collect(),
+            total,
+            page: query.page.unwrap_or(0),
+            limit,
+        }))
+    })
+    .await
 }
 
 #[derive(Debug, Serialize)]
@@ -51,36 +58,44 @@ pub struct ListUsersResponse {
 /// Get user by ID
 pub async fn get_user(
     State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
     Path(id): Path<String>,
 ) -> Result<Json<UserDto>, ApiError> {
-    let user_id = Id::parse_str(&id)
-        .map_err(|_| ApiError::bad_request("Invalid user ID"))?;
+    blocking(move || {
+        require_read(&state, &claims)?;
+        let user_id = Id::parse_str(&id)
+            .map_err(|_| ApiError::bad_request("Invalid user ID"))?;
     
-    let repo = UserRepository::new(state.db.clone());
-    let user = repo.find_by_id(user_id)
-        .map_err(|e| ApiError::internal(&e.to_string()))?
-        .ok_or_else(|| ApiError::not_found("User"))?;
+        let repo = UserRepository::new(state.db.clone());
+        let user = repo.find_by_id(user_id)
+            .map_err(|e| ApiError::internal(&e.to_string()))?
+            .ok_or_else(|| ApiError::not_found("User"))?;
     
-    Ok(Json(UserDto::from(user)))
+        Ok(Json(UserDto::from(user)))
+    })
+    .await
 }
 
 /// Create user (admin only)
 pub async fn create_user(
     State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
     Json(req): Json<CreateUserRequest>,
 ) -> Result<Json<UserDto>, ApiError> {
-    let role = parse_role(&req.role)?;
-    
-    let password_hash = hedtronix_crypto::hashing::hash_password(&req.password)
-        .map_err(|e| ApiError::internal(&e.to_string()))?;
-    
-    let user = User::new(req.email, req.name, role, password_hash);
+    blocking(move || {
+        if !state.auth_state.permissions.authorize(&claims, "users", "create") {
+            return Err(ApiError::forbidden("Only administrators can create accounts"));
+        }
+
+        let role = parse_role(&req.role)?;
     
-    let repo = UserRepository::new(state.db.clone());
-    repo.create(&user)
-        .map_err(|e| ApiError::internal(&e.to_string()))?;
+        // The user picks their own password at first login
+        let user = auth_service(&state)
+            .create_user_by_admin(&req.email, &req.name, &req.password, role)?;
     
-    Ok(Json(UserDto::from(user)))
+        Ok(Json(UserDto::from(user)))
+    })
+    .await
 }
 
 #[derive(Debug, Deserialize)]
@@ -94,35 +109,59 @@ pub struct CreateUserRequest {
 /// Update user
 pub async fn update_user(
     State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
     Path(id): Path<String>,
     Json(req): Json<UpdateUserRequest>,
 ) -> Result<Json<UserDto>, ApiError> {
-    let user_id = Id::parse_str(&id)
-        .map_err(|_| ApiError::bad_request("Invalid user ID"))?;
+    blocking(move || {
+        let user_id = Id::parse_str(&id)
+            .map_err(|_| ApiError::bad_request("Invalid user ID"))?;
+
+        // Users may edit their own name and email; anything else, including
+        // the role and the chart a portal account is linked to, needs
+        // `users:update`
+        let own_profile = claims.user_id() == Some(user_id)
+            && req.role.is_none()
+            && req.active.is_none()
+            && req.patient_id.is_none();
+        if !own_profile && !state.auth_state.permissions.authorize(&claims, "users", "update") {
+            return Err(ApiError::forbidden("Only administrators can change other accounts, roles or chart links"));
+        }
     
-    let repo = UserRepository::new(state.db.clone());
-    let mut user = repo.find_by_id(user_id)
-        .map_err(|e| ApiError::internal(&e.to_string()))?
-        .ok_or_else(|| ApiError::not_found("User"))?;
+        let repo = UserRepository::new(state.db.clone());
+        let mut user = repo.find_by_id(user_id)
+            .map_err(|e| ApiError::internal(&e.to_string()))?
+            .ok_or_else(|| ApiError::not_found("User"))?;
     
-    if let Some(name) = req.name {
-        user.name = name;
-    }
-    if let Some(email) = req.email {
-        user.email = email;
-    }
-    if let Some(active) = req.active {
-        user.active = active;
-    }
-    if let Some(role) = req.role {
-        user.role = parse_role(&role)?;
-    }
-    user.updated_at = chrono::Utc::now();
+        if let Some(name) = req.name {
+            user.name = name;
+        }
+        if let Some(email) = req.email {
+            user.email = email;
+        }
+        if let Some(active) = req.active {
+            user.active = active;
+        }
+        let role_changed = req.role.is_some();
+        if let Some(role) = req.role {
+            user.role = parse_role(&role)?;
+        }
+        if let Some(patient_id) = req.patient_id {
+            user.patient_id = Some(Id::parse_str(&patient_id)
+                .map_err(|_| ApiError::bad_request("Invalid patient ID"))?);
+        }
+        user.updated_at = chrono::Utc::now();
     
-    repo.update(&user)
-        .map_err(|e| ApiError::internal(&e.to_string()))?;
+        repo.update(&user)
+            .map_err(|e| ApiError::internal(&e.to_string()))?;
+        if role_changed {
+            // The checker caches each user's role
+            state.auth_state.permissions.invalidate();
+        }
     
-    Ok(Json(UserDto::from(user)))
+        Ok(Json(UserDto::from(user)))
+    })
+    .await
 }
 
 #[derive(Debug, Deserialize)]
@@ -131,28 +170,38 @@ pub struct UpdateUserRequest {
     pub email: Option<String>,
     pub role: Option<String>,
     pub active: Option<bool>,
+    /// Link a patient portal account to its chart
+    pub patient_id: Option<String>,
 }
 
 /// Delete user (soft delete)
 pub async fn delete_user(
     State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
     Path(id): Path<String>,
 ) -> Result<Json<DeleteResponse>, ApiError> {
-    let user_id = Id::parse_str(&id)
-        .map_err(|_| ApiError::bad_request("Invalid user ID"))?;
+    blocking(move || {
+        if !state.auth_state.permissions.authorize(&claims, "users", "update") {
+            return Err(ApiError::forbidden("Only administrators can deactivate accounts"));
+        }
+
+        let user_id = Id::parse_str(&id)
+            .map_err(|_| ApiError::bad_request("Invalid user ID"))?;
     
-    let repo = UserRepository::new(state.db.clone());
-    let mut user = repo.find_by_id(user_id)
-        .map_err(|e| ApiError::internal(&e.to_string()))?
-        .ok_or_else(|| ApiError::not_found("User"))?;
+        let repo = UserRepository::new(state.db.clone());
+        let mut user = repo.find_by_id(user_id)
+            .map_err(|e| ApiError::internal(&e.to_string()))?
+            .ok_or_else(|| ApiError::not_found("User"))?;
     
-    user.active = false;
-    user.updated_at = chrono::Utc::now();
+        user.active = false;
+        user.updated_at = chrono::Utc::now();
     
-    repo.update(&user)
-        .map_err(|e| ApiError::internal(&e.to_string()))?;
+        repo.update(&user)
+            .map_err(|e| ApiError::internal(&e.to_string()))?;
     
-    Ok(Json(DeleteResponse { success: true }))
+        Ok(Json(DeleteResponse { success: true }))
+    })
+    .await
 }
 
 #[derive(Debug, Serialize)]
@@ -160,27 +209,64 @@ pub struct DeleteResponse {
     pub success: bool,
 }
 
+/// Lift a login lockout (admin only)
+pub async fn unlock_user(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Json<UnlockResponse>, ApiError> {
+    blocking(move || {
+        if !state.auth_state.permissions.authorize(&claims, "users", "update") {
+            return Err(ApiError::forbidden("Only administrators can unlock accounts"));
+        }
+
+        let user_id = Id::parse_str(&id)
+            .map_err(|_| ApiError::bad_request("Invalid user ID"))?;
+    
+        auth_service(&state).unlock_user(user_id)?;
+    
+        Ok(Json(UnlockResponse { success: true }))
+    })
+    .await
+}
+
+#[derive(Debug, Serialize)]
+pub struct UnlockResponse {
+    pub success: bool,
+}
+
 /// Get current user from token
 pub async fn get_current_user(
     State(state): State<AppState>,
     request: Request,
 ) -> Result<Json<UserDto>, ApiError> {
-    let claims = request.extensions()
-        .get::<Claims>()
-        .ok_or_else(|| ApiError::unauthorized("Missing claims"))?;
+    blocking(move || {
+        let claims = request.extensions()
+            .get::<Claims>()
+            .ok_or_else(|| ApiError::unauthorized("Missing claims"))?;
     
-    let user_id = claims.user_id()
-        .ok_or_else(|| ApiError::unauthorized("Invalid user ID in token"))?;
+        let user_id = claims.user_id()
+            .ok_or_else(|| ApiError::unauthorized("Invalid user ID in token"))?;
     
-    let repo = UserRepository::new(state.db.clone());
-    let user = repo.find_by_id(user_id)
-        .map_err(|e| ApiError::internal(&e.to_string()))?
-        .ok_or_else(|| ApiError::not_found("User"))?;
+        let repo = UserRepository::new(state.db.clone());
+        let user = repo.find_by_id(user_id)
+            .map_err(|e| ApiError::internal(&e.to_string()))?
+            .ok_or_else(|| ApiError::not_found("User"))?;
     
-    Ok(Json(UserDto::from(user)))
+        Ok(Json(UserDto::from(user)))
+    })
+    .await
 }
 
 // Helper
+fn require_read(state: &AppState, claims: &Claims) -> Result<(), ApiError> {
+    if state.auth_state.permissions.authorize(claims, "users", "read") {
+        Ok(())
+    } else {
+        Err(ApiError::forbidden("Reading accounts requires the users:read permission"))
+    }
+}
+
 fn parse_role(s: &str) -> Result<UserRole, ApiError> {
     match s.to_uppercase().as_str() {
         "PHYSICIAN" => Ok(UserRole::Physician),
@@ -201,6 +287,7 @@ pub struct UserDto {
     pub name: String,
     pub role: String,
     pub department_id: Option<String>,
+    pub patient_id: Option<String>,
     pub active: bool,
     pub created_at: String,
     pub last_login_at: Option<String>,
@@ -214,6 +301,7 @@ impl From<User> for UserDto {
             name: u.name,
             role: u.role.as_str().to_string(),
             department_id: u.department_id.map(|id| id.to_string()),
+            patient_id: u.patient_id.map(|id| id.to_string()),
             active: u.active,
             created_at: u.created_at.to_rfc3339(),
             last_login_at: u.last_login_at.map(|t| t.to_rfc3339()),
@@ -3,21 +3,30 @@
 //! REST API for the healthcare operating system.
 
 use std::net::SocketAddr;
-use std::sync::Arc;
 
 use axum::{
-    routing::{get, post, put, delete},
+    routing::get,
     Router,
 };
 use tower_http::cors::{CorsLayer, Any};
 use tower_http::trace::TraceLayer;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
-use hedtronix_db::Database;
-use hedtronix_auth::AuthState;
+use hedtronix_core::Id;
+use hedtronix_db::{
+    AuditArchive, AuditChainReport, AuditChainVerifier, AuditSigner, DataKeyRepository, Database,
+    FieldReencryptor, KeyStore, PatientRepository, DEFAULT_SEGMENT_SIZE, run_migrations,
+};
+use hedtronix_auth::{
+    parse_algorithm, AccessMonitor, AccessMonitorPolicy, DeviceLock, DeviceLockPolicy, EmergencyAccessPolicy, JwtKeySet, JwtManager, LoginThrottlePolicy, MfaPolicy,
+    OfflineTokenPolicy, PasswordPolicy, RedactionPolicy,
+};
 
 mod routes;
 mod handlers;
+mod access;
+mod audit;
+mod blocking;
 mod state;
 mod error;
 pub mod config;
@@ -35,12 +44,124 @@ pub async fn start_server(config: config::ServerConfig) -> anyhow::Result<()> {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
-    // Initialize database
-    let mut db = Database::open(&config.database_path)?;
-    db.initialize()?;
+    // Initialize database, upgrading its schema
+    let mut db = Database::open_with_readers(&config.database_path, config.database_readers)?;
+    let migrations = run_migrations(&mut db)?;
+    if let Some(backup) = &migrations.backup {
+        tracing::info!("Backed up database to {}", backup.display());
+    }
+    if !migrations.applied.is_empty() {
+        tracing::info!(
+            "Migrated database schema from version {} to {}",
+            migrations.from_version,
+            migrations.to_version
+        );
+    }
+
+    // Load the audit signing key
+    let audit_signer = AuditSigner::load_or_create(std::path::Path::new(&config.audit_key_path))?;
+    tracing::info!("Signing audit log entries with key {}", audit_signer.key_id());
+    db.set_audit_signer(audit_signer);
+
+    // Load JWT signing keys
+    let jwt_manager = load_jwt_manager(&config)?;
 
     // Create app state
-    let state = AppState::new(db, config.jwt_secret.clone(), config.encryption_key.clone());
+    let mut state = AppState::new(db, jwt_manager, config.legacy_key().clone());
+    state.audit_archive_dir = std::path::PathBuf::from(&config.audit_archive_dir);
+    state.reencryption_batch_size = config.reencryption_batch_size;
+
+    if config.device_lock {
+        // The data keys stay locked until a user logs in
+        let mut device_lock = DeviceLock::new(state.db.clone(), state.keyring.clone())
+            .with_permission_checker(state.auth_state.permissions.clone())
+            .with_previous_key(config.encryption_key.clone());
+        if let Some(legacy) = &config.legacy_encryption_key {
+            device_lock = device_lock.with_legacy_key(legacy.clone());
+        }
+        if let Some(path) = &config.device_lock_policy_path {
+            let policy = DeviceLockPolicy::from_file(std::path::Path::new(path))
+                .map_err(|e| anyhow::anyhow!("Failed to load device lock policy: {}", e))?;
+            device_lock = device_lock.with_policy(std::sync::Arc::new(policy));
+        }
+        device_lock.lock()?;
+        state.auth_state = state.auth_state.with_device_lock(device_lock);
+    } else {
+        // Load the data encryption keys, rewrapping them first if the key
+        // encryption key was rotated
+        let key_store = KeyStore::new(state.db.clone(), &config.encryption_key)?;
+        if let Some(previous) = &config.previous_encryption_key {
+            let rewrapped = key_store.rewrap(previous)?;
+            if rewrapped > 0 {
+                tracing::info!("Rewrapped {} data keys with key encryption key {}", rewrapped, key_store.kek_id());
+            }
+        }
+        key_store.load(&state.keyring)?;
+        state.key_store = Some(std::sync::Arc::new(key_store));
+        start_key_maintenance(&state)?;
+    }
+
+    if let Some(path) = &config.offline_policy_path {
+        let policy = OfflineTokenPolicy::from_file(std::path::Path::new(path))
+            .map_err(|e| anyhow::anyhow!("Failed to load offline token policy: {}", e))?;
+        state.auth_state = state.auth_state.with_offline_policy(policy);
+    }
+
+    if let Some(path) = &config.mfa_policy_path {
+        let policy = MfaPolicy::from_file(std::path::Path::new(path))
+            .map_err(|e| anyhow::anyhow!("Failed to load MFA policy: {}", e))?;
+        state.auth_state = state.auth_state.with_mfa_policy(policy);
+    }
+
+    if let Some(path) = &config.login_policy_path {
+        let policy = LoginThrottlePolicy::from_file(std::path::Path::new(path))
+            .map_err(|e| anyhow::anyhow!("Failed to load login throttle policy: {}", e))?;
+        state.auth_state = state.auth_state.with_throttle_policy(policy);
+    }
+
+    if let Some(path) = &config.password_policy_path {
+        let policy = PasswordPolicy::from_file(std::path::Path::new(path))
+            .map_err(|e| anyhow::anyhow!("Failed to load password policy: {}", e))?;
+        state.auth_state = state.auth_state.with_password_policy(policy);
+    }
+
+    if let Some(path) = &config.emergency_policy_path {
+        let policy = EmergencyAccessPolicy::from_file(std::path::Path::new(path))
+            .map_err(|e| anyhow::anyhow!("Failed to load emergency access policy: {}", e))?;
+        state.auth_state = state.auth_state.with_emergency_policy(policy);
+    }
+
+    if let Some(path) = &config.redaction_policy_path {
+        let policy = RedactionPolicy::from_file(std::path::Path::new(path))
+            .map_err(|e| anyhow::anyhow!("Failed to load redaction policy: {}", e))?;
+        state.auth_state = state.auth_state.with_redaction_policy(policy);
+    }
+
+    if let Some(path) = &config.access_monitor_policy_path {
+        let policy = AccessMonitorPolicy::from_file(std::path::Path::new(path))
+            .map_err(|e| anyhow::anyhow!("Failed to load access monitoring policy: {}", e))?;
+        state.auth_state = state.auth_state.with_monitor_policy(policy);
+    }
+
+    if config.audit_anchor_interval_minutes > 0 {
+        let period = std::time::Duration::from_secs(config.audit_anchor_interval_minutes * 60);
+        tokio::spawn(anchor_audit_chain(state.db.clone(), period));
+    }
+
+    if config.audit_compaction_interval_hours > 0 {
+        let period = std::time::Duration::from_secs(config.audit_compaction_interval_hours * 3600);
+        let retention = chrono::Duration::days(config.audit_retention_days);
+        tokio::spawn(compact_audit_log(state.audit_archive()?, retention, period));
+    }
+
+    if config.access_monitor_interval_seconds > 0 {
+        let period = std::time::Duration::from_secs(config.access_monitor_interval_seconds);
+        tokio::spawn(monitor_access(state.access_monitor(), state.auth_state.device_lock.clone(), period));
+    }
+
+    if let Some(device_lock) = state.auth_state.device_lock.clone() {
+        tokio::spawn(lock_idle_device(device_lock));
+    }
 
     // Build router
     let app = create_router(state);
@@ -50,43 +171,259 @@ pub async fn start_server(config: config::ServerConfig) -> anyhow::Result<()> {
     tracing::info!("Starting HEDTRONIX server on {}", addr);
 
     let listener = tokio::net::TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
+    // Client addresses feed per-IP login throttling
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await?;
+
+    Ok(())
+}
+
+/// Periodically verify the audit chain and anchor its head, so entries
+/// removed from the end of the chain are detected by the next check
+async fn anchor_audit_chain(db: Database, period: std::time::Duration) {
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+
+        let verifier = AuditChainVerifier::new(db.clone());
+        match tokio::task::spawn_blocking(move || verifier.anchor()).await {
+            Ok(Ok(report)) if report.valid => {
+                tracing::debug!("Anchored audit chain at entry {:?}", report.head_sequence);
+            }
+            Ok(Ok(report)) => {
+                tracing::error!("Audit chain integrity check failed: {:?}", report.first_break);
+            }
+            Ok(Err(e)) => tracing::error!("Audit chain anchoring failed: {}", e),
+            Err(e) => tracing::error!("Audit chain anchoring task failed: {}", e),
+        }
+    }
+}
+
+/// Periodically move audit entries older than the retention period into
+/// the archive
+async fn compact_audit_log(archive: AuditArchive, retention: chrono::Duration, period: std::time::Duration) {
+    let archive = std::sync::Arc::new(archive);
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+
+        let archive = archive.clone();
+        let cutoff = chrono::Utc::now() - retention;
+        match tokio::task::spawn_blocking(move || archive.compact(cutoff, DEFAULT_SEGMENT_SIZE)).await {
+            Ok(Ok(report)) => {
+                tracing::debug!("Archived {} audit entries", report.entries_archived);
+            }
+            Ok(Err(e)) => tracing::error!("Audit log compaction failed: {}", e),
+            Err(e) => tracing::error!("Audit log compaction task failed: {}", e),
+        }
+    }
+}
+
+/// Periodically scan new audit entries for anomalous access. Scans wait
+/// while the device is locked, as rules read patient names.
+async fn monitor_access(
+    monitor: AccessMonitor,
+    device_lock: Option<std::sync::Arc<DeviceLock>>,
+    period: std::time::Duration,
+) {
+    let monitor = std::sync::Arc::new(monitor);
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+        if device_lock.as_ref().is_some_and(|lock| !lock.is_unlocked()) {
+            continue;
+        }
+
+        let monitor = monitor.clone();
+        match tokio::task::spawn_blocking(move || monitor.scan()).await {
+            Ok(Ok(report)) if !report.alerts.is_empty() => {
+                tracing::warn!("Raised {} access alerts", report.alerts.len());
+            }
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => tracing::error!("Access monitoring scan failed: {}", e),
+            Err(e) => tracing::error!("Access monitoring task failed: {}", e),
+        }
+    }
+}
+
+/// Lock the device once it has been idle for the policy's timeout
+async fn lock_idle_device(device_lock: std::sync::Arc<DeviceLock>) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(30));
+    loop {
+        interval.tick().await;
+
+        match device_lock.lock_if_idle() {
+            Ok(true) => tracing::info!("Locked the device after {} idle minutes", device_lock.policy().idle_timeout_minutes),
+            Ok(false) => {}
+            Err(e) => tracing::error!("Idle device lock failed: {}", e),
+        }
+    }
+}
+
+/// Once the data keys are loaded, build missing search indexes and move
+/// encrypted values onto the active key and ciphertext format
+pub(crate) fn start_key_maintenance(state: &AppState) -> anyhow::Result<()> {
+    let batch = state.reencryption_batch_size;
+
+    // Build blind indexes for patients written before patient search used them
+    let patients = PatientRepository::new(state.db.clone(), state.keyring.clone());
+    if patients.unindexed_count()? > 0 {
+        tokio::spawn(index_patients(patients, batch));
+    }
+
+    // Resume re-encryption interrupted by a restart, or start moving values
+    // still in an older ciphertext format or under a retired key
+    let running = DataKeyRepository::new(state.db.clone()).running_jobs()?;
+    for job in &running {
+        tracing::info!("Resuming re-encryption job {}", job.id);
+        tokio::spawn(reencrypt_fields(state.reencryptor(), job.id, batch));
+    }
+    let reencryptor = state.reencryptor();
+    let active_key = state.keyring.active_key_id().unwrap_or_default();
+    if running.is_empty() && reencryptor.pending_rows(&active_key)? > 0 {
+        let job = reencryptor.start()?;
+        tracing::info!("Re-encrypting {} rows under data key {}", job.total_rows, job.target_key_id);
+        tokio::spawn(reencrypt_fields(reencryptor, job.id, batch));
+    }
 
     Ok(())
 }
 
+/// Move encrypted rows onto the active data key, one batch per transaction
+pub(crate) async fn reencrypt_fields(reencryptor: FieldReencryptor, job_id: Id, batch: u32) {
+    match tokio::task::spawn_blocking(move || reencryptor.run(job_id, batch)).await {
+        Ok(Ok(job)) => {
+            tracing::info!("Re-encryption job {} finished: {} rows", job.id, job.processed_rows);
+        }
+        Ok(Err(e)) => tracing::error!("Re-encryption job {} failed: {}", job_id, e),
+        Err(e) => tracing::error!("Re-encryption task failed: {}", e),
+    }
+}
+
+/// Build missing patient blind indexes, one batch per transaction
+async fn index_patients(patients: PatientRepository, batch: u32) {
+    let patients = std::sync::Arc::new(patients);
+    let mut indexed = 0;
+    loop {
+        let repo = patients.clone();
+        match tokio::task::spawn_blocking(move || repo.index_batch(batch)).await {
+            Ok(Ok(0)) => break,
+            Ok(Ok(count)) => indexed += count,
+            Ok(Err(e)) => {
+                tracing::error!("Patient search indexing failed: {}", e);
+                return;
+            }
+            Err(e) => {
+                tracing::error!("Patient search indexing task failed: {}", e);
+                return;
+            }
+        }
+    }
+    tracing::info!("Built search indexes for {} patients", indexed);
+}
+
+/// Verify the audit chain of the configured database without starting the
+/// server. Signatures are checked when the audit key file exists, and
+/// archived entries when the archive directory exists.
+pub fn verify_audit_log(config: &config::ServerConfig) -> anyhow::Result<AuditChainReport> {
+    let mut db = Database::open(&config.database_path)?;
+
+    let key_path = std::path::Path::new(&config.audit_key_path);
+    if key_path.exists() {
+        db.set_audit_signer(AuditSigner::load_or_create(key_path)?);
+    }
+
+    let mut verifier = AuditChainVerifier::new(db.clone());
+    let archive_dir = std::path::Path::new(&config.audit_archive_dir);
+    if archive_dir.exists() {
+        verifier = verifier.with_archive(state::open_audit_archive(db, archive_dir, config.legacy_key())?);
+    }
+
+    Ok(verifier.verify()?)
+}
+
+/// Build the JWT manager from configuration, rotating the signing key when it
+/// has outlived the rotation interval
+fn load_jwt_manager(config: &config::ServerConfig) -> anyhow::Result<JwtManager> {
+    if let Some(secret) = &config.jwt_secret {
+        tracing::warn!("JWT_SECRET is set; signing tokens with legacy HS256");
+        return Ok(JwtManager::new(secret));
+    }
+
+    let algorithm = parse_algorithm(&config.jwt_algorithm)?;
+    let dir = std::path::Path::new(&config.jwt_keys_dir);
+    let mut keys = JwtKeySet::load_or_create(dir, algorithm)?;
+
+    if config.jwt_key_rotation_days > 0 {
+        let rotation = chrono::Duration::days(config.jwt_key_rotation_days);
+        let now = chrono::Utc::now();
+        let active_created = keys.signing_key().and_then(|k| k.created_at);
+
+        if active_created.is_some_and(|t| now - t > rotation) {
+            let kid = keys.rotate()?;
+            tracing::info!("Rotated JWT signing key, new kid {}", kid);
+        }
+
+        // Keep old keys long enough for every token they signed to expire
+        let manager = JwtManager::with_key_set(keys);
+        let cutoff = now - rotation - manager.refresh_token_expiry();
+        manager.with_keys_mut(|keys| keys.retire_older_than(cutoff))?;
+        manager.with_keys_mut(|keys| keys.save_dir(dir))??;
+        return Ok(manager);
+    }
+
+    Ok(JwtManager::with_key_set(keys))
+}
+
 /// Create the API router
 pub fn create_router(state: AppState) -> Router {
     Router::new()
         // Health check
         .route("/health", get(handlers::health::health_check))
         
+        // Public JWT verification keys
+        .route("/.well-known/jwks.json", get(handlers::auth::jwks))
+        
         // Authentication routes
-        .nest("/api/v1/auth", routes::auth_routes())
+        .nest("/api/v1/auth", routes::auth_routes(state.auth_state.clone()))
         
         // Patient routes
-        .nest("/api/v1/patients", routes::patient_routes())
+        .nest("/api/v1/patients", routes::patient_routes(state.auth_state.clone(), state.db.clone()))
         
         // Appointment routes
-        .nest("/api/v1/appointments", routes::appointment_routes())
+        .nest("/api/v1/appointments", routes::appointment_routes(state.auth_state.clone(), state.db.clone()))
         
         // Sync routes
-        .nest("/api/v1/sync", routes::sync_routes())
+        .nest("/api/v1/sync", routes::sync_routes(state.auth_state.clone()))
         
         // User routes (admin)
-        .nest("/api/v1/users", routes::user_routes())
+        .nest("/api/v1/users", routes::user_routes(state.auth_state.clone()))
+        
+        // Role administration routes
+        .nest("/api/v1/roles", routes::role_routes(state.auth_state.clone()))
+        
+        // Break-the-glass emergency access routes
+        .nest("/api/v1/emergency-access", routes::emergency_access_routes(state.auth_state.clone()))
+        
+        // Anomalous access alert routes
+        .nest("/api/v1/access-alerts", routes::access_alert_routes(state.auth_state.clone()))
+        
+        // Data encryption key routes
+        .nest("/api/v1/encryption-keys", routes::encryption_key_routes(state.auth_state.clone()))
+        
+        // Passphrase-locked device key routes
+        .nest("/api/v1/device-key", routes::device_key_routes(state.auth_state.clone()))
         
         // Clinical Notes routes
-        .nest("/api/v1/clinical-notes", routes::clinical_note_routes())
+        .nest("/api/v1/clinical-notes", routes::clinical_note_routes(state.auth_state.clone(), state.db.clone()))
         
         // Billing routes
-        .nest("/api/v1/billing", routes::billing_routes())
+        .nest("/api/v1/billing", routes::billing_routes(state.auth_state.clone(), state.db.clone()))
         
         // Analytics routes
         .nest("/api/v1/analytics", routes::analytics_routes())
         
         // Audit log routes
-        .nest("/api/v1/audit", routes::audit_log_routes())
+        .nest("/api/v1/audit", routes::audit_log_routes(state.auth_state.clone()))
         
         // CORS and tracing
         .layer(
@@ -5,7 +5,15 @@ use hedtronix_api::config::ServerConfig;
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     // Load configuration
-    let config = ServerConfig::from_env();
+    let config = ServerConfig::from_env()?;
+    
+    let args: Vec<String> = std::env::args().collect();
+    match args.get(1).map(String::as_str) {
+        Some("verify-audit") => return verify_audit(&config),
+        Some("escrow-key") => return escrow_key(&config, &args[2..]),
+        Some("recover-key") => return recover_key(),
+        _ => {}
+    }
     
     println!("╔══════════════════════════════════════════════════════════╗");
     println!("║                    HEDTRONIX v0.1.0                      ║");
@@ -20,3 +28,66 @@ async fn main() -> anyhow::Result<()> {
     
     Ok(())
 }
+
+/// `hedtronix verify-audit`: print the audit chain report as JSON and exit
+/// non-zero if the chain is broken
+fn verify_audit(config: &ServerConfig) -> anyhow::Result<()> {
+    let report = hedtronix_api::verify_audit_log(config)?;
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    
+    if !report.valid {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// `hedtronix escrow-key <threshold> <shares>`: split the key encryption key
+/// into shares for custodians, one per line
+fn escrow_key(config: &ServerConfig, args: &[String]) -> anyhow::Result<()> {
+    let [threshold, shares] = args else {
+        anyhow::bail!("Usage: hedtronix escrow-key <threshold> <shares>");
+    };
+    if std::env::var("ENCRYPTION_KEY").is_err() {
+        anyhow::bail!("ENCRYPTION_KEY must be set to the key to escrow");
+    }
+
+    let shares = hedtronix_crypto::split_key(&config.encryption_key, threshold.parse()?, shares.parse()?)?;
+    eprintln!(
+        "Key {} split into {} shares; any {} recover it. Give each custodian one line.",
+        shares[0].key_id(),
+        shares.len(),
+        shares[0].threshold(),
+    );
+    for share in &shares {
+        println!("{}", share.encode());
+    }
+    Ok(())
+}
+
+/// `hedtronix recover-key`: read shares from stdin, one per line, until the
+/// key can be reconstructed, then print it as hex for `ENCRYPTION_KEY`
+fn recover_key() -> anyhow::Result<()> {
+    let mut ceremony = hedtronix_crypto::RecoveryCeremony::new();
+    for line in std::io::stdin().lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match ceremony.add_share(&line) {
+            Ok(progress) => {
+                eprintln!("Accepted share: {} of {}", progress.received, progress.threshold);
+                if progress.is_complete() {
+                    break;
+                }
+            }
+            Err(e) => eprintln!("Rejected share: {}", e),
+        }
+    }
+
+    if !ceremony.progress().is_some_and(|p| p.is_complete()) {
+        anyhow::bail!("Not enough shares to recover the key");
+    }
+    let key = ceremony.recover()?;
+    println!("{}", key.iter().map(|b| format!("{:02x}", b)).collect::<String>());
+    Ok(())
+}
@@ -1,24 +1,44 @@
 //! Route definitions
 
 use axum::{
+    middleware::from_fn_with_state,
     routing::{get, post, put, delete},
     Router,
 };
+use hedtronix_auth::{auth_middleware, device_lock_middleware, AuthState};
+use hedtronix_db::Database;
 
+use crate::audit::{audit_middleware, AuditTrail};
 use crate::handlers;
 use crate::state::AppState;
 
-/// Authentication routes (public)
-pub fn auth_routes() -> Router<AppState> {
+/// Authentication routes (public, except device and offline token issuance)
+pub fn auth_routes(auth_state: AuthState) -> Router<AppState> {
+    let authenticated = from_fn_with_state(auth_state, auth_middleware);
+
     Router::new()
         .route("/login", post(handlers::auth::login))
         .route("/refresh", post(handlers::auth::refresh))
         .route("/logout", post(handlers::auth::logout))
-        .route("/register", post(handlers::auth::register))
+        .route("/mfa/verify", post(handlers::auth::verify_mfa))
+        .route("/password/change", post(handlers::auth::change_password))
+        .route("/mfa/totp/enroll", post(handlers::auth::enroll_totp))
+        .route("/mfa/totp/confirm", post(handlers::auth::confirm_totp))
+        .route("/mfa/webauthn/options", post(handlers::auth::webauthn_options))
+        .route("/mfa/webauthn/register", post(handlers::auth::register_webauthn))
+        .route("/mfa/recovery-codes", post(handlers::auth::regenerate_recovery_codes))
+        .route(
+            "/devices",
+            post(handlers::auth::register_device).route_layer(authenticated.clone()),
+        )
+        .route(
+            "/offline-token",
+            post(handlers::auth::offline_token).route_layer(authenticated),
+        )
 }
 
-/// Patient routes (protected)
-pub fn patient_routes() -> Router<AppState> {
+/// Patient routes (protected, audited)
+pub fn patient_routes(auth_state: AuthState, db: Database) -> Router<AppState> {
     Router::new()
         .route("/", get(handlers::patients::list_patients))
         .route("/", post(handlers::patients::create_patient))
@@ -27,11 +47,15 @@ pub fn patient_routes() -> Router<AppState> {
         .route("/:id", delete(handlers::patients::delete_patient))
         .route("/:id/allergies", post(handlers::patients::add_allergy))
         .route("/:id/medications", post(handlers::patients::add_medication))
+        .route("/:id/disclosures", get(handlers::disclosures::get_disclosure_report))
         .route("/search", post(handlers::patients::search_patients))
+        .route_layer(from_fn_with_state(AuditTrail::new(db, "Patient"), audit_middleware))
+        .route_layer(from_fn_with_state(auth_state.clone(), device_lock_middleware))
+        .route_layer(from_fn_with_state(auth_state, auth_middleware))
 }
 
-/// Appointment routes (protected)
-pub fn appointment_routes() -> Router<AppState> {
+/// Appointment routes (protected, audited)
+pub fn appointment_routes(auth_state: AuthState, db: Database) -> Router<AppState> {
     Router::new()
         .route("/", get(handlers::appointments::list_appointments))
         .route("/", post(handlers::appointments::create_appointment))
@@ -42,19 +66,30 @@ pub fn appointment_routes() -> Router<AppState> {
         .route("/:id/complete", post(handlers::appointments::complete))
         .route("/conflicts", post(handlers::appointments::check_conflicts))
         .route("/calendar", get(handlers::appointments::get_calendar))
+        .route_layer(from_fn_with_state(AuditTrail::new(db, "Appointment"), audit_middleware))
+        .route_layer(from_fn_with_state(auth_state.clone(), device_lock_middleware))
+        .route_layer(from_fn_with_state(auth_state, auth_middleware))
 }
 
 /// Sync routes (protected)
-pub fn sync_routes() -> Router<AppState> {
-    Router::new()
+pub fn sync_routes(auth_state: AuthState) -> Router<AppState> {
+    let device_routes = Router::new()
         .route("/push", post(handlers::sync::push_changes))
+        .route("/audit/push", post(handlers::sync::push_audit))
         .route("/pull", post(handlers::sync::pull_changes))
+        .route("/keys", get(handlers::sync::list_key_grants))
+        .route("/keys", post(handlers::sync::grant_keys))
+        .route("/keys/recipients", get(handlers::sync::list_key_recipients))
+        .route_layer(from_fn_with_state(auth_state, auth_middleware));
+
+    Router::new()
+        .merge(device_routes)
         .route("/status", get(handlers::sync::get_status))
         .route("/health", get(handlers::sync::get_health))
 }
 
-/// User management routes (admin only)
-pub fn user_routes() -> Router<AppState> {
+/// User management routes (protected; changing accounts is admin only)
+pub fn user_routes(auth_state: AuthState) -> Router<AppState> {
     Router::new()
         .route("/", get(handlers::users::list_users))
         .route("/", post(handlers::users::create_user))
@@ -62,23 +97,89 @@ pub fn user_routes() -> Router<AppState> {
         .route("/:id", put(handlers::users::update_user))
         .route("/:id", delete(handlers::users::delete_user))
         .route("/me", get(handlers::users::get_current_user))
+        .route("/:id/unlock", post(handlers::users::unlock_user))
+        .route("/:id/role", put(handlers::roles::assign_role))
+        .route_layer(from_fn_with_state(auth_state, auth_middleware))
+}
+
+/// Role administration routes (admin only)
+pub fn role_routes(auth_state: AuthState) -> Router<AppState> {
+    Router::new()
+        .route("/", get(handlers::roles::list_roles))
+        .route("/", post(handlers::roles::create_role))
+        .route("/:id", get(handlers::roles::get_role))
+        .route("/:id", delete(handlers::roles::delete_role))
+        .route("/:id/permissions", post(handlers::roles::grant_permission))
+        .route("/:id/permissions/:permission", delete(handlers::roles::revoke_permission))
+        .route_layer(from_fn_with_state(auth_state, auth_middleware))
+}
+
+/// Break-the-glass emergency access routes (protected)
+pub fn emergency_access_routes(auth_state: AuthState) -> Router<AppState> {
+    Router::new()
+        .route("/", post(handlers::emergency_access::request_access))
+        .route("/:id", delete(handlers::emergency_access::revoke_access))
+        .route("/reviews", get(handlers::emergency_access::list_pending_reviews))
+        .route("/:id/review", post(handlers::emergency_access::review_access))
+        .route_layer(from_fn_with_state(auth_state, auth_middleware))
+}
+
+/// Anomalous access alert queue and VIP list (protected)
+pub fn access_alert_routes(auth_state: AuthState) -> Router<AppState> {
+    Router::new()
+        .route("/", get(handlers::access_alerts::list_alerts))
+        .route("/scan", post(handlers::access_alerts::scan))
+        .route("/vip-patients", get(handlers::access_alerts::list_vip_patients))
+        .route("/vip-patients", post(handlers::access_alerts::flag_vip_patient))
+        .route("/vip-patients/:patient_id", delete(handlers::access_alerts::unflag_vip_patient))
+        .route("/:id", get(handlers::access_alerts::get_alert))
+        .route("/:id/review", post(handlers::access_alerts::review_alert))
+        .route_layer(from_fn_with_state(auth_state.clone(), device_lock_middleware))
+        .route_layer(from_fn_with_state(auth_state, auth_middleware))
+}
+
+/// Data encryption key rotation and re-encryption jobs (protected)
+pub fn encryption_key_routes(auth_state: AuthState) -> Router<AppState> {
+    Router::new()
+        .route("/", get(handlers::encryption_keys::list_keys))
+        .route("/rotate", post(handlers::encryption_keys::rotate_key))
+        .route("/reencrypt", post(handlers::encryption_keys::reencrypt))
+        .route("/jobs", get(handlers::encryption_keys::list_jobs))
+        .route("/jobs/:id", get(handlers::encryption_keys::get_job))
+        .route_layer(from_fn_with_state(auth_state.clone(), device_lock_middleware))
+        .route_layer(from_fn_with_state(auth_state, auth_middleware))
+}
+
+/// Passphrase-locked device key status and wrappings (protected)
+pub fn device_key_routes(auth_state: AuthState) -> Router<AppState> {
+    Router::new()
+        .route("/", get(handlers::device_key::status))
+        .route("/lock", post(handlers::device_key::lock))
+        .route("/wrappings/:user_id", delete(handlers::device_key::remove_wrapping))
+        .route_layer(from_fn_with_state(auth_state, auth_middleware))
 }
 
-/// Clinical Note routes
-pub fn clinical_note_routes() -> Router<AppState> {
+/// Clinical Note routes (protected, audited)
+pub fn clinical_note_routes(auth_state: AuthState, db: Database) -> Router<AppState> {
     Router::new()
         .route("/patient/:id", get(handlers::clinical_notes::list_notes))
         .route("/", post(handlers::clinical_notes::create_note))
         .route("/:id", get(handlers::clinical_notes::get_note))
         .route("/:id", put(handlers::clinical_notes::update_note))
         .route("/:id/sign", post(handlers::clinical_notes::sign_note))
+        .route_layer(from_fn_with_state(AuditTrail::new(db, "ClinicalNote"), audit_middleware))
+        .route_layer(from_fn_with_state(auth_state.clone(), device_lock_middleware))
+        .route_layer(from_fn_with_state(auth_state, auth_middleware))
 }
 
-/// Billing routes
-pub fn billing_routes() -> Router<AppState> {
+/// Billing routes (protected, audited)
+pub fn billing_routes(auth_state: AuthState, db: Database) -> Router<AppState> {
     Router::new()
         .route("/", get(handlers::billing::list_billing))
         .route("/", post(handlers::billing::create_billing))
+        .route_layer(from_fn_with_state(AuditTrail::new(db, "BillingEntry"), audit_middleware))
+        .route_layer(from_fn_with_state(auth_state.clone(), device_lock_middleware))
+        .route_layer(from_fn_with_state(auth_state, auth_middleware))
 }
 
 /// Analytics routes (protected)
@@ -88,9 +189,18 @@ pub fn analytics_routes() -> Router<AppState> {
         .route("/report", get(handlers::analytics::get_report))
 }
 
-/// Audit log routes (admin only)
-pub fn audit_log_routes() -> Router<AppState> {
+/// Audit log routes (requires `audit_logs:read`, `audit_logs:verify` for
+/// integrity checks or `audit_logs:archive` for compaction)
+pub fn audit_log_routes(auth_state: AuthState) -> Router<AppState> {
     Router::new()
         .route("/", get(handlers::audit_log::list_audit_logs))
+        .route("/global", get(handlers::audit_log::global_audit_view))
+        .route("/verify", get(handlers::audit_log::verify_audit_chain))
+        .route("/devices/:id/verify", get(handlers::audit_log::verify_device_audit_chain))
+        .route("/anchor", post(handlers::audit_log::anchor_audit_chain))
+        .route("/archive", get(handlers::audit_log::list_archive_segments))
+        .route("/archive/entries", get(handlers::audit_log::search_archive))
+        .route("/archive/compact", post(handlers::audit_log::compact_audit_log))
         .route("/:id", get(handlers::audit_log::get_audit_log))
+        .route_layer(from_fn_with_state(auth_state, auth_middleware))
 }
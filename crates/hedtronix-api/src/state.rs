@@ -1,8 +1,15 @@
 //! Application state
 
+use std::path::PathBuf;
 use std::sync::Arc;
-use hedtronix_db::Database;
-use hedtronix_auth::AuthState;
+
+use hedtronix_crypto::{Keyring, SecretKey};
+use hedtronix_db::{
+    AuditArchive, Database, DbError, FieldReencryptor, KeyStore, DEFAULT_REENCRYPTION_BATCH,
+};
+use hedtronix_auth::{
+    AccessMonitor, AccessPolicy, AuthState, EmergencyAccessService, JwtManager, PermissionChecker,
+};
 use hedtronix_sync::SyncEngine;
 
 /// Shared application state
@@ -10,21 +17,69 @@ use hedtronix_sync::SyncEngine;
 pub struct AppState {
     pub db: Database,
     pub auth_state: AuthState,
-    pub encryption_key: Vec<u8>,
+    /// Legacy field key; also derives the audit archive key
+    pub encryption_key: Arc<SecretKey>,
+    /// Data keys shared by every repository that encrypts fields
+    pub keyring: Arc<Keyring>,
+    /// Stored data keys, when envelope encryption is configured
+    pub key_store: Option<Arc<KeyStore>>,
+    pub reencryption_batch_size: u32,
     pub device_id: String,
+    pub audit_archive_dir: PathBuf,
 }
 
 impl AppState {
-    pub fn new(db: Database, jwt_secret: Vec<u8>, encryption_key: Vec<u8>) -> Self {
+    pub fn new(db: Database, jwt_manager: JwtManager, encryption_key: Arc<SecretKey>) -> Self {
         Self {
+            auth_state: AuthState::new(jwt_manager)
+                .with_permission_checker(PermissionChecker::new(db.clone())),
             db,
-            auth_state: AuthState::new(jwt_secret),
+            keyring: Arc::new(Keyring::legacy(&encryption_key).unwrap_or_default()),
+            key_store: None,
+            reencryption_batch_size: DEFAULT_REENCRYPTION_BATCH,
             encryption_key,
             device_id: uuid::Uuid::new_v4().to_string(),
+            audit_archive_dir: PathBuf::from("./data/audit-archive"),
         }
     }
 
+    pub fn audit_archive(&self) -> Result<AuditArchive, DbError> {
+        open_audit_archive(self.db.clone(), &self.audit_archive_dir, &self.encryption_key)
+    }
+
     pub fn sync_engine(&self) -> SyncEngine {
         SyncEngine::new(self.db.clone(), self.device_id.clone())
     }
+
+    pub fn access_policy(&self) -> AccessPolicy {
+        AccessPolicy::new(self.db.clone())
+            .with_permission_checker(self.auth_state.permissions.clone())
+    }
+
+    pub fn emergency_access(&self) -> EmergencyAccessService {
+        EmergencyAccessService::new(self.db.clone(), self.auth_state.jwt_manager.clone())
+            .with_policy(self.auth_state.emergency_policy.clone())
+            .with_permission_checker(self.auth_state.permissions.clone())
+    }
+
+    pub fn access_monitor(&self) -> AccessMonitor {
+        AccessMonitor::new(self.db.clone(), self.keyring.clone())
+            .with_policy(self.auth_state.monitor_policy.clone())
+            .with_permission_checker(self.auth_state.permissions.clone())
+    }
+
+    pub fn reencryptor(&self) -> FieldReencryptor {
+        FieldReencryptor::new(self.db.clone(), self.keyring.clone())
+    }
+}
+
+/// Open the audit archive, encrypted with a key derived from the data key
+pub(crate) fn open_audit_archive(
+    db: Database,
+    dir: &std::path::Path,
+    encryption_key: &[u8],
+) -> Result<AuditArchive, DbError> {
+    let key = hedtronix_crypto::derive_subkey(encryption_key, "audit-archive")
+        .map_err(|e| DbError::AuditArchive(e.to_string()))?;
+    AuditArchive::new(db, dir, &key)
 }
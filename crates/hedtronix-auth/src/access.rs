@@ -0,0 +1,418 @@
+//! Attribute-based access control for patient records
+//!
+//! Role permissions decide what kind of action a caller may take; this module
+//! decides which patients' records it may be taken on, by comparing the
+//! caller's grants and claims with the patient's care relationships.
+
+use std::sync::Arc;
+
+use hedtronix_core::{Appointment, ChartFilter, Id, PatientCareTeam};
+use hedtronix_db::{CareTeamRepository, Database, UserRepository};
+use serde::{Deserialize, Serialize};
+
+use crate::jwt::Claims;
+use crate::permissions::{AuthorizationError, Permission, PermissionChecker, Result};
+
+/// The caller, as seen by access policy
+#[derive(Debug, Clone)]
+pub struct Subject {
+    pub user_id: Id,
+    /// Grants of the caller's role, as resolved by `PermissionChecker`
+    pub permissions: Vec<Permission>,
+    pub department_id: Option<Id>,
+    /// Chart linked to a patient portal account
+    pub patient_id: Option<Id>,
+}
+
+impl Subject {
+    pub fn from_claims(claims: &Claims, permissions: Vec<Permission>, patient_id: Option<Id>) -> Option<Self> {
+        Some(Self {
+            user_id: claims.user_id()?,
+            permissions,
+            department_id: claims.department_id.as_deref().and_then(|s| Id::parse_str(s).ok()),
+            patient_id,
+        })
+    }
+
+    /// Whether the caller's role grants an action
+    pub fn allows(&self, resource: &str, action: &str) -> bool {
+        self.permissions.iter().any(|p| p.matches(resource, action))
+    }
+
+    /// Whether the caller may see every chart, in full or financial scope
+    pub fn sees_every_chart(&self) -> bool {
+        self.allows("patients", "read_all") || self.allows("patients", "read_financial")
+    }
+
+    /// Charts a patient search may return, matching `evaluate_patient_access`;
+    /// `None` when every chart is open to the caller
+    pub fn chart_filter(&self) -> Option<ChartFilter> {
+        if self.sees_every_chart() {
+            return None;
+        }
+
+        let clinician = self.allows("patients", "read");
+        Some(ChartFilter {
+            care_team_member: clinician.then_some(self.user_id),
+            department_id: self.department_id.filter(|_| clinician),
+            patient_id: self.patient_id.filter(|_| self.allows("own_data", "read")),
+        })
+    }
+
+    /// Whether the caller is on the patient's care team or works in a
+    /// department the patient has been seen in. A missing department on
+    /// either side never matches.
+    fn cares_for(&self, care_team: &PatientCareTeam) -> bool {
+        care_team.includes(self.user_id)
+            || self.department_id.is_some_and(|department| care_team.department_ids.contains(&department))
+    }
+}
+
+/// How much of a chart a caller may see
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum PatientScope {
+    /// The whole chart
+    Full,
+    /// Identity, contact, insurance and diagnoses; no allergies,
+    /// medications or note changes
+    Financial,
+}
+
+/// Outcome of an access check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AccessDecision {
+    Allow(PatientScope),
+    Deny(&'static str),
+}
+
+impl AccessDecision {
+    pub fn scope(&self) -> Option<PatientScope> {
+        match self {
+            AccessDecision::Allow(scope) => Some(*scope),
+            AccessDecision::Deny(_) => None,
+        }
+    }
+}
+
+/// Decide whether a caller may open a patient's chart, from the grants of
+/// their role and their relationship to the patient. Each grant opens
+/// charts on its own, and anything no grant opens is denied.
+///
+/// - `patients:read_all` opens every chart in full (admins, through `*:*`).
+/// - `patients:read` opens, in full, the charts of patients whose care team
+///   the caller is on or who have been seen in the caller's department.
+/// - `own_data:read` opens the chart linked to the caller's account.
+/// - `patients:read_financial` opens the financial view of any other chart.
+pub fn evaluate_patient_access(subject: &Subject, care_team: &PatientCareTeam) -> AccessDecision {
+    if subject.allows("patients", "read_all") {
+        return AccessDecision::Allow(PatientScope::Full);
+    }
+    if subject.allows("patients", "read") && subject.cares_for(care_team) {
+        return AccessDecision::Allow(PatientScope::Full);
+    }
+    if subject.allows("own_data", "read") && subject.patient_id == Some(care_team.patient_id) {
+        return AccessDecision::Allow(PatientScope::Full);
+    }
+    if subject.allows("patients", "read_financial") {
+        return AccessDecision::Allow(PatientScope::Financial);
+    }
+
+    if subject.allows("patients", "read") {
+        AccessDecision::Deny("Patient is outside the caller's department and care team")
+    } else if subject.allows("own_data", "read") {
+        AccessDecision::Deny("Patients may only access their own record")
+    } else {
+        AccessDecision::Deny("The caller's role grants no access to patient records")
+    }
+}
+
+/// Decide whether a caller may see an appointment. A provider with
+/// `appointments:read` always may see their own; otherwise access to the
+/// patient decides.
+pub fn evaluate_appointment_access(
+    subject: &Subject,
+    appointment: &Appointment,
+    care_team: &PatientCareTeam,
+) -> AccessDecision {
+    if appointment.provider_id == subject.user_id && subject.allows("appointments", "read") {
+        return AccessDecision::Allow(PatientScope::Full);
+    }
+
+    evaluate_patient_access(subject, care_team)
+}
+
+/// Loads the attributes policy decisions need
+pub struct AccessPolicy {
+    db: Database,
+    permissions: Arc<PermissionChecker>,
+}
+
+impl AccessPolicy {
+    pub fn new(db: Database) -> Self {
+        Self {
+            permissions: Arc::new(PermissionChecker::new(db.clone())),
+            db,
+        }
+    }
+
+    /// Share the server's permission checker and its cache
+    pub fn with_permission_checker(mut self, permissions: Arc<PermissionChecker>) -> Self {
+        self.permissions = permissions;
+        self
+    }
+
+    /// Refuse an action the caller's role does not grant, or that an
+    /// offline token does not carry. An `<action>_own` grant also counts:
+    /// patients hold those, and chart access confines them to their own
+    /// record.
+    pub fn authorize(&self, claims: &Claims, resource: &str, action: &str) -> Result<()> {
+        if self.permissions.authorize(claims, resource, action)
+            || self.permissions.authorize(claims, resource, &format!("{}_own", action))
+        {
+            Ok(())
+        } else {
+            Err(AuthorizationError::Denied("The caller's role or token does not permit this action"))
+        }
+    }
+
+    /// Resolve the caller: their role's current grants and the chart linked
+    /// to a portal account. Scope follows the role rather than an offline
+    /// token's restriction, which `authorize` applies to each action.
+    pub fn subject(&self, claims: &Claims) -> Result<Subject> {
+        let user_id = claims.user_id()
+            .ok_or_else(|| AuthorizationError::Invalid("Token has no valid subject".to_string()))?;
+        let permissions = self.permissions.get_permissions(&self.permissions.role_id_for(claims));
+
+        let patient_id = if permissions.iter().any(|p| p.matches("own_data", "read")) {
+            UserRepository::new(self.db.clone())
+                .find_by_id(user_id)
+                .map_err(|e| AuthorizationError::Database(e.to_string()))?
+                .and_then(|user| user.patient_id)
+        } else {
+            None
+        };
+
+        Subject::from_claims(claims, permissions, patient_id)
+            .ok_or_else(|| AuthorizationError::Invalid("Token has no valid subject".to_string()))
+    }
+
+    /// Care relationships for a patient; `None` if the patient does not exist
+    pub fn care_team(&self, patient_id: Id) -> Result<Option<PatientCareTeam>> {
+        CareTeamRepository::new(self.db.clone())
+            .find_for_patient(patient_id)
+            .map_err(|e| AuthorizationError::Database(e.to_string()))
+    }
+
+    /// Decision for a patient's chart
+    pub fn patient_access(&self, subject: &Subject, patient_id: Id) -> Result<AccessDecision> {
+        let care_team = self.care_team(patient_id)?.ok_or(AuthorizationError::NotFound("Patient"))?;
+        Ok(evaluate_patient_access(subject, &care_team))
+    }
+
+    /// Scope the caller has on a patient's chart, or `Denied`
+    pub fn require_patient(&self, subject: &Subject, patient_id: Id) -> Result<PatientScope> {
+        require(self.patient_access(subject, patient_id)?)
+    }
+
+    /// Scope the caller has on an appointment, or `Denied`
+    pub fn require_appointment(&self, subject: &Subject, appointment: &Appointment) -> Result<PatientScope> {
+        let care_team = self.care_team(appointment.patient_id)?
+            .ok_or(AuthorizationError::NotFound("Patient"))?;
+        require(evaluate_appointment_access(subject, appointment, &care_team))
+    }
+}
+
+fn require(decision: AccessDecision) -> Result<PatientScope> {
+    match decision {
+        AccessDecision::Allow(scope) => Ok(scope),
+        AccessDecision::Deny(reason) => Err(AuthorizationError::Denied(reason)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hedtronix_core::{default_permissions, User, UserRole};
+
+    fn subject(role: UserRole, department_id: Option<Id>) -> Subject {
+        Subject { user_id: Id::new_v4(), permissions: default_permissions(role), department_id, patient_id: None }
+    }
+
+    fn care_team(department_id: Option<Id>) -> PatientCareTeam {
+        PatientCareTeam {
+            patient_id: Id::new_v4(),
+            department_ids: department_id.into_iter().collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_patient_sees_only_own_chart() {
+        let team = care_team(None);
+        let mut patient = subject(UserRole::Patient, None);
+        assert!(matches!(evaluate_patient_access(&patient, &team), AccessDecision::Deny(_)));
+
+        patient.patient_id = Some(team.patient_id);
+        assert_eq!(evaluate_patient_access(&patient, &team), AccessDecision::Allow(PatientScope::Full));
+    }
+
+    #[test]
+    fn test_clinician_department_and_care_team() {
+        let cardiology = Id::new_v4();
+        let team = care_team(Some(cardiology));
+
+        let inside = subject(UserRole::Nurse, Some(cardiology));
+        assert_eq!(evaluate_patient_access(&inside, &team), AccessDecision::Allow(PatientScope::Full));
+
+        let outside = subject(UserRole::Physician, Some(Id::new_v4()));
+        assert!(matches!(evaluate_patient_access(&outside, &team), AccessDecision::Deny(_)));
+
+        // Treating the patient puts the clinician on the care team
+        let mut team = team;
+        team.provider_ids.insert(outside.user_id);
+        assert_eq!(evaluate_patient_access(&outside, &team), AccessDecision::Allow(PatientScope::Full));
+    }
+
+    #[test]
+    fn test_billing_gets_financial_scope() {
+        let team = care_team(Some(Id::new_v4()));
+        let billing = subject(UserRole::Billing, Some(Id::new_v4()));
+        assert_eq!(evaluate_patient_access(&billing, &team), AccessDecision::Allow(PatientScope::Financial));
+    }
+
+    #[test]
+    fn test_missing_department_opens_nothing() {
+        // A clinician without a department only sees their care team's charts
+        let nurse = subject(UserRole::Nurse, None);
+        assert!(matches!(evaluate_patient_access(&nurse, &care_team(Some(Id::new_v4()))), AccessDecision::Deny(_)));
+        assert!(matches!(evaluate_patient_access(&nurse, &care_team(None)), AccessDecision::Deny(_)));
+
+        // Nor does a patient never seen in a department open to everyone
+        let physician = subject(UserRole::Physician, Some(Id::new_v4()));
+        let mut team = care_team(None);
+        assert!(matches!(evaluate_patient_access(&physician, &team), AccessDecision::Deny(_)));
+
+        team.primary_care_physician_id = Some(physician.user_id);
+        assert_eq!(evaluate_patient_access(&physician, &team), AccessDecision::Allow(PatientScope::Full));
+    }
+
+    #[test]
+    fn test_appointment_provider_always_allowed() {
+        let team = care_team(Some(Id::new_v4()));
+        let provider = subject(UserRole::Physician, Some(Id::new_v4()));
+        let appointment = Appointment::new(
+            team.patient_id,
+            provider.user_id,
+            chrono::Utc::now(),
+            30,
+            hedtronix_core::AppointmentType::FollowUp,
+            "Follow-up".to_string(),
+            provider.user_id,
+        );
+
+        assert_eq!(
+            evaluate_appointment_access(&provider, &appointment, &team),
+            AccessDecision::Allow(PatientScope::Full)
+        );
+
+        let other = subject(UserRole::Physician, Some(Id::new_v4()));
+        assert!(matches!(evaluate_appointment_access(&other, &appointment, &team), AccessDecision::Deny(_)));
+    }
+
+    fn database() -> Database {
+        let mut db = Database::in_memory().unwrap();
+        db.initialize().unwrap();
+        db
+    }
+
+    fn staff(db: &Database, role: UserRole) -> Claims {
+        let user = User::new(format!("{}@example.com", Id::new_v4()), "Staff".into(), role, "hash".into());
+        UserRepository::new(db.clone()).create(&user).unwrap();
+
+        Claims {
+            sub: user.id.to_string(),
+            email: user.email.clone(),
+            role: role.as_str().to_string(),
+            device_id: Id::new_v4().to_string(),
+            department_id: None,
+            iat: 0,
+            exp: 0,
+            jti: String::new(),
+            offline: false,
+            permissions: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_offline_token_refused_actions_it_does_not_carry() {
+        let db = database();
+        let mut claims = staff(&db, UserRole::Nurse);
+        let policy = AccessPolicy::new(db);
+
+        assert!(policy.authorize(&claims, "clinical_notes", "write").is_ok());
+        assert!(policy.authorize(&claims, "clinical_notes", "sign").is_err());
+
+        claims.offline = true;
+        claims.permissions = vec!["patients:read".to_string(), "clinical_notes:read".to_string()];
+        assert!(policy.authorize(&claims, "clinical_notes", "read").is_ok());
+        assert!(matches!(
+            policy.authorize(&claims, "clinical_notes", "write"),
+            Err(AuthorizationError::Denied(_))
+        ));
+        assert!(policy.authorize(&claims, "patients", "write").is_err());
+    }
+
+    #[test]
+    fn test_revoked_grant_refuses_note_write() {
+        let db = database();
+        let cardiology = Id::new_v4();
+        let mut claims = staff(&db, UserRole::Physician);
+        claims.department_id = Some(cardiology.to_string());
+        let checker = Arc::new(PermissionChecker::new(db.clone()));
+        let policy = AccessPolicy::new(db).with_permission_checker(checker.clone());
+        let team = care_team(Some(cardiology));
+
+        assert!(policy.authorize(&claims, "clinical_notes", "write").is_ok());
+        let subject = policy.subject(&claims).unwrap();
+        assert_eq!(evaluate_patient_access(&subject, &team), AccessDecision::Allow(PatientScope::Full));
+
+        checker.revoke("PHYSICIAN", &Permission::new("clinical_notes", "write")).unwrap();
+        assert!(matches!(
+            policy.authorize(&claims, "clinical_notes", "write"),
+            Err(AuthorizationError::Denied(_))
+        ));
+        // Chart access follows `patients:read`, not the note grant
+        let subject = policy.subject(&claims).unwrap();
+        assert_eq!(evaluate_patient_access(&subject, &team), AccessDecision::Allow(PatientScope::Full));
+
+        // Revoking a grant never widens access
+        checker.revoke("PHYSICIAN", &Permission::new("patients", "read")).unwrap();
+        let subject = policy.subject(&claims).unwrap();
+        assert!(matches!(evaluate_patient_access(&subject, &team), AccessDecision::Deny(_)));
+    }
+
+    #[test]
+    fn test_custom_role_grants_chart_access() {
+        let db = database();
+        let claims = staff(&db, UserRole::Billing);
+        let checker = Arc::new(PermissionChecker::new(db.clone()));
+        let policy = AccessPolicy::new(db).with_permission_checker(checker.clone());
+        let team = care_team(Some(Id::new_v4()));
+
+        let subject = policy.subject(&claims).unwrap();
+        assert_eq!(evaluate_patient_access(&subject, &team), AccessDecision::Allow(PatientScope::Financial));
+
+        let role = checker.create_role(hedtronix_core::CreateRole {
+            name: "Chart Auditor".to_string(),
+            description: None,
+            base_role: UserRole::Billing,
+            permissions: vec![Permission::new("patients", "read_all")],
+        }).unwrap();
+        checker.assign_role(claims.user_id().unwrap(), &role.id).unwrap();
+
+        let subject = policy.subject(&claims).unwrap();
+        assert!(subject.sees_every_chart());
+        assert_eq!(evaluate_patient_access(&subject, &team), AccessDecision::Allow(PatientScope::Full));
+    }
+}
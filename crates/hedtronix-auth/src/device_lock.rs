@@ -0,0 +1,516 @@
+//! Passphrase-locked device database key
+//!
+//! On a workstation the key encryption key is not taken from the
+//! environment. It is a random device key, stored only wrapped under each
+//! user's passphrase (Argon2id), and held in memory while the device is
+//! unlocked. A successful login unlocks it; the first login on a fresh
+//! database creates it, and a login while unlocked enrolls the user with
+//! their own wrapping, so several users can share the workstation. The
+//! password is checked against the wrapping when it is verified, but the
+//! keys are only loaded once every login factor has passed. After
+//! the idle timeout the key is zeroized and every data key dropped from the
+//! keyring until the next login.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use hedtronix_core::Id;
+use hedtronix_crypto::{
+    Encryptor, KdfParams, KeyEncryptionKey, Keyring, PassphraseWrapping, SecretKey,
+};
+use hedtronix_db::{Database, DeviceKeyRepository, DeviceKeyWrapping, KeyStore};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::jwt::Claims;
+use crate::permissions::PermissionChecker;
+
+/// Device lock errors
+#[derive(Error, Debug)]
+pub enum DeviceLockError {
+    #[error("Not permitted: {0}")]
+    NotPermitted(String),
+
+    #[error("Device is locked")]
+    Locked,
+
+    #[error("User has no wrapping of the device key")]
+    NotEnrolled,
+
+    #[error("Passphrase does not open the device key")]
+    InvalidPassphrase,
+
+    #[error("The last wrapping of the device key cannot be removed")]
+    LastWrapping,
+
+    #[error("Key error: {0}")]
+    Key(String),
+
+    #[error("Database error: {0}")]
+    Database(String),
+}
+
+/// Result type for device lock operations
+pub type Result<T> = std::result::Result<T, DeviceLockError>;
+
+impl From<hedtronix_db::DbError> for DeviceLockError {
+    fn from(e: hedtronix_db::DbError) -> Self {
+        match e {
+            hedtronix_db::DbError::EncryptionKey(msg) => DeviceLockError::Key(msg),
+            e => DeviceLockError::Database(e.to_string()),
+        }
+    }
+}
+
+impl From<hedtronix_crypto::EncryptionError> for DeviceLockError {
+    fn from(e: hedtronix_crypto::EncryptionError) -> Self {
+        DeviceLockError::Key(e.to_string())
+    }
+}
+
+/// Idle timeout and passphrase KDF cost for the device key
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceLockPolicy {
+    /// Lock after this many minutes without an authenticated request
+    /// (0 never locks)
+    pub idle_timeout_minutes: u64,
+
+    /// Argon2id parameters for new wrappings; existing wrappings keep theirs
+    /// until the passphrase changes
+    pub kdf: KdfParams,
+}
+
+impl Default for DeviceLockPolicy {
+    fn default() -> Self {
+        Self {
+            idle_timeout_minutes: 15,
+            kdf: KdfParams::default(),
+        }
+    }
+}
+
+impl DeviceLockPolicy {
+    /// Load a policy from a JSON file
+    pub fn from_file(path: &std::path::Path) -> std::result::Result<Self, String> {
+        let json = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        serde_json::from_str(&json).map_err(|e| e.to_string())
+    }
+
+    fn idle_timeout(&self) -> Option<Duration> {
+        (self.idle_timeout_minutes > 0).then(|| Duration::from_secs(self.idle_timeout_minutes * 60))
+    }
+}
+
+/// Whether the device key exists and is loaded
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceLockStatus {
+    pub initialized: bool,
+    pub unlocked: bool,
+    pub enrolled_users: i64,
+    pub idle_timeout_minutes: u64,
+}
+
+struct LockState {
+    key: Option<Arc<SecretKey>>,
+    last_activity: Instant,
+    /// Opened by a verified password, waiting on the rest of the login
+    staged: HashMap<Id, StagedUnlock>,
+}
+
+/// A device key opened by a password whose login has not completed
+struct StagedUnlock {
+    key: Arc<SecretKey>,
+    /// Wrapping to store for a user who is new, or whose wrapping is stale
+    wrapping: Option<DeviceKeyWrapping>,
+    /// Whether this login creates the device key
+    creates_key: bool,
+    expires_at: Instant,
+}
+
+/// Unlocks, locks and shares the device key
+pub struct DeviceLock {
+    db: Database,
+    keyring: Arc<Keyring>,
+    policy: Arc<DeviceLockPolicy>,
+    permissions: Arc<PermissionChecker>,
+    previous_key: Option<Arc<SecretKey>>,
+    legacy_key: Option<Arc<SecretKey>>,
+    state: Mutex<LockState>,
+}
+
+impl DeviceLock {
+    /// A locked device. `keyring` is the one shared by the repositories; it
+    /// only holds keys while unlocked.
+    pub fn new(db: Database, keyring: Arc<Keyring>) -> Self {
+        Self {
+            db,
+            keyring,
+            policy: Arc::new(DeviceLockPolicy::default()),
+            permissions: Arc::new(PermissionChecker::built_in()),
+            previous_key: None,
+            legacy_key: None,
+            state: Mutex::new(LockState {
+                key: None,
+                last_activity: Instant::now(),
+                staged: HashMap::new(),
+            }),
+        }
+    }
+
+    pub fn with_policy(mut self, policy: Arc<DeviceLockPolicy>) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn with_permission_checker(mut self, permissions: Arc<PermissionChecker>) -> Self {
+        self.permissions = permissions;
+        self
+    }
+
+    /// Key encryption key the data keys were wrapped with before device
+    /// lock was enabled; they are rewrapped under the device key on unlock
+    pub fn with_previous_key(mut self, key: Arc<SecretKey>) -> Self {
+        self.previous_key = Some(key);
+        self
+    }
+
+    /// Key for field values written before envelope encryption, loaded
+    /// into the keyring while unlocked
+    pub fn with_legacy_key(mut self, key: Arc<SecretKey>) -> Self {
+        self.legacy_key = Some(key);
+        self
+    }
+
+    pub fn policy(&self) -> &DeviceLockPolicy {
+        &self.policy
+    }
+
+    pub fn is_unlocked(&self) -> bool {
+        self.state.lock().is_ok_and(|state| state.key.is_some())
+    }
+
+    pub fn status(&self) -> Result<DeviceLockStatus> {
+        let enrolled_users = self.repo().count()?;
+        Ok(DeviceLockStatus {
+            initialized: enrolled_users > 0,
+            unlocked: self.is_unlocked(),
+            enrolled_users,
+            idle_timeout_minutes: self.policy.idle_timeout_minutes,
+        })
+    }
+
+    /// Unlock with a user's passphrase, after login has verified it. The
+    /// first user creates the device key; a user without a wrapping, or
+    /// whose wrapping predates a password reset, is (re)enrolled when the
+    /// device is already unlocked.
+    pub fn unlock(&self, user_id: Id, passphrase: &str) -> Result<()> {
+        let mut state = self.lock_state()?;
+        let staged = self.open(&state, user_id, passphrase, Duration::ZERO)?;
+        self.apply(&mut state, staged)
+    }
+
+    /// Open the device key with a passphrase the password step of login has
+    /// verified, and hold it for `valid_for` until `complete_unlock` once
+    /// the remaining factors pass. Nothing is stored or loaded until then.
+    pub fn stage_unlock(&self, user_id: Id, passphrase: &str, valid_for: Duration) -> Result<()> {
+        let mut state = self.lock_state()?;
+        let staged = self.open(&state, user_id, passphrase, valid_for)?;
+        state.staged.retain(|_, s| s.expires_at > Instant::now());
+        state.staged.insert(user_id, staged);
+        Ok(())
+    }
+
+    /// Unlock with the key staged at the user's login, if any. Does nothing
+    /// when the password did not open the device key.
+    pub fn complete_unlock(&self, user_id: Id) -> Result<()> {
+        let mut state = self.lock_state()?;
+        match state.staged.remove(&user_id) {
+            Some(staged) if staged.expires_at > Instant::now() => self.apply(&mut state, staged),
+            Some(_) => Err(DeviceLockError::Locked),
+            None => Ok(()),
+        }
+    }
+
+    /// Find the device key for a passphrase, and the wrapping to store for
+    /// the user if they are new or their wrapping is stale
+    fn open(&self, state: &LockState, user_id: Id, passphrase: &str, valid_for: Duration) -> Result<StagedUnlock> {
+        let repo = self.repo();
+        let (key, wrapping, creates_key) = match repo.find(user_id)? {
+            Some(stored) => match stored.wrapping.unwrap(passphrase, &user_id.to_string()) {
+                Ok(key) => (Arc::new(key), None, false),
+                Err(_) => {
+                    let key = state.key.clone().ok_or(DeviceLockError::InvalidPassphrase)?;
+                    let wrapping = self.wrap(user_id, &key, passphrase)?;
+                    (key, Some(wrapping), false)
+                }
+            },
+            None if repo.count()? == 0 => {
+                let key = Arc::new(Encryptor::generate_key()?);
+                let wrapping = self.wrap(user_id, &key, passphrase)?;
+                (key, Some(wrapping), true)
+            }
+            None => {
+                let key = state.key.clone().ok_or(DeviceLockError::NotEnrolled)?;
+                let wrapping = self.wrap(user_id, &key, passphrase)?;
+                (key, Some(wrapping), false)
+            }
+        };
+
+        Ok(StagedUnlock { key, wrapping, creates_key, expires_at: Instant::now() + valid_for })
+    }
+
+    /// Store the user's wrapping and load the keys if the device is locked
+    fn apply(&self, state: &mut LockState, staged: StagedUnlock) -> Result<()> {
+        let repo = self.repo();
+        // Another user created the device key while this login was pending
+        if staged.creates_key && repo.count()? > 0 {
+            return Err(DeviceLockError::NotEnrolled);
+        }
+        if let Some(wrapping) = &staged.wrapping {
+            match (staged.creates_key, repo.find(wrapping.user_id)?.is_some()) {
+                (true, _) => tracing::info!("Created the device key for user {}", wrapping.user_id),
+                (false, true) => tracing::info!("Rewrapping the device key for user {}", wrapping.user_id),
+                (false, false) => tracing::info!("Enrolled user {} for the device key", wrapping.user_id),
+            }
+            repo.upsert(wrapping)?;
+        }
+
+        if state.key.is_none() {
+            self.load_keys(&staged.key)?;
+            state.key = Some(staged.key);
+        }
+        state.last_activity = Instant::now();
+        Ok(())
+    }
+
+    /// Rewrap a user's copy of the device key after a password change. The
+    /// current passphrase opens the old wrapping; failing that, the key in
+    /// memory is used if the device is unlocked.
+    pub fn change_passphrase(&self, user_id: Id, current: &str, new: &str) -> Result<()> {
+        let state = self.lock_state()?;
+        let key = match self.repo().find(user_id)? {
+            Some(stored) => match stored.wrapping.unwrap(current, &user_id.to_string()) {
+                Ok(key) => Arc::new(key),
+                Err(_) => state.key.clone().ok_or(DeviceLockError::InvalidPassphrase)?,
+            },
+            None => state.key.clone().ok_or(DeviceLockError::NotEnrolled)?,
+        };
+
+        self.store_wrapping(user_id, &key, new)
+    }
+
+    /// Remove a user's wrapping, e.g. when they leave the workstation's
+    /// staff. The last one is kept, or the data would become unreadable.
+    pub fn remove(&self, claims: &Claims, user_id: Id) -> Result<()> {
+        if !self.permissions.authorize(claims, "device_key", "manage") {
+            return Err(DeviceLockError::NotPermitted(
+                "Requires the device_key:manage permission".to_string(),
+            ));
+        }
+
+        let repo = self.repo();
+        if repo.find(user_id)?.is_none() {
+            return Err(DeviceLockError::NotEnrolled);
+        }
+        if repo.count()? <= 1 {
+            return Err(DeviceLockError::LastWrapping);
+        }
+        repo.delete(user_id)?;
+        Ok(())
+    }
+
+    /// Key store for rotating data keys under the device key, while
+    /// unlocked
+    pub fn key_store(&self) -> Result<KeyStore> {
+        let state = self.lock_state()?;
+        let key = state.key.as_ref().ok_or(DeviceLockError::Locked)?;
+        Ok(KeyStore::new(self.db.clone(), key)?)
+    }
+
+    /// Record activity, postponing the idle lock. Fails if the device is
+    /// locked.
+    pub fn touch(&self) -> Result<()> {
+        let mut state = self.lock_state()?;
+        if state.key.is_none() {
+            return Err(DeviceLockError::Locked);
+        }
+        state.last_activity = Instant::now();
+        Ok(())
+    }
+
+    /// Zeroize the device key and drop every key from the keyring
+    pub fn lock(&self) -> Result<()> {
+        let mut state = self.lock_state()?;
+        state.key = None;
+        state.staged.clear();
+        self.keyring.clear()?;
+        Ok(())
+    }
+
+    /// Lock if the idle timeout has passed since the last activity.
+    /// Returns whether it locked.
+    pub fn lock_if_idle(&self) -> Result<bool> {
+        let Some(timeout) = self.policy.idle_timeout() else {
+            return Ok(false);
+        };
+        let idle = {
+            let state = self.lock_state()?;
+            state.key.is_some() && state.last_activity.elapsed() >= timeout
+        };
+        if idle {
+            self.lock()?;
+        }
+        Ok(idle)
+    }
+
+    /// Load the data keys wrapped by the device key
+    fn load_keys(&self, key: &[u8]) -> Result<()> {
+        let key_store = KeyStore::new(self.db.clone(), key)?;
+        if let Some(previous) = &self.previous_key {
+            let rewrapped = key_store.rewrap(previous)?;
+            if rewrapped > 0 {
+                tracing::info!("Rewrapped {} data keys with the device key", rewrapped);
+            }
+        }
+        if let Some(legacy) = &self.legacy_key {
+            self.keyring.set_legacy_key(legacy)?;
+        }
+
+        if let Err(e) = key_store.load(&self.keyring) {
+            self.keyring.clear()?;
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    fn store_wrapping(&self, user_id: Id, key: &[u8], passphrase: &str) -> Result<()> {
+        self.repo().upsert(&self.wrap(user_id, key, passphrase)?)?;
+        Ok(())
+    }
+
+    fn wrap(&self, user_id: Id, key: &[u8], passphrase: &str) -> Result<DeviceKeyWrapping> {
+        let now = chrono::Utc::now();
+        Ok(DeviceKeyWrapping {
+            user_id,
+            key_id: KeyEncryptionKey::new(key)?.id().to_string(),
+            wrapping: PassphraseWrapping::wrap(key, passphrase, &user_id.to_string(), self.policy.kdf)?,
+            created_at: now,
+            updated_at: now,
+        })
+    }
+
+    fn repo(&self) -> DeviceKeyRepository {
+        DeviceKeyRepository::new(self.db.clone())
+    }
+
+    fn lock_state(&self) -> Result<std::sync::MutexGuard<'_, LockState>> {
+        self.state.lock().map_err(|_| DeviceLockError::Key("Device lock state poisoned".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use hedtronix_core::{Gender, Patient, User, UserRole};
+    use hedtronix_db::{PatientRepository, UserRepository};
+
+    use crate::jwt::JwtManager;
+
+    fn setup() -> (Database, Arc<Keyring>, DeviceLock) {
+        let mut db = Database::in_memory().unwrap();
+        db.initialize().unwrap();
+        let keyring = Arc::new(Keyring::new());
+        let policy = DeviceLockPolicy {
+            idle_timeout_minutes: 15,
+            kdf: KdfParams { memory_kib: 64, iterations: 1, parallelism: 1 },
+        };
+        let lock = DeviceLock::new(db.clone(), keyring.clone()).with_policy(Arc::new(policy));
+        (db, keyring, lock)
+    }
+
+    fn user(db: &Database, email: &str) -> User {
+        let user = User::new(email.into(), "Staff".into(), UserRole::Nurse, "hash".into());
+        UserRepository::new(db.clone()).create(&user).unwrap();
+        user
+    }
+
+    fn patient() -> Patient {
+        let dob = NaiveDate::from_ymd_opt(1980, 1, 2).unwrap();
+        Patient::new("MRN1".into(), "Ada".into(), "Lovelace".into(), dob, Gender::Female)
+    }
+
+    #[test]
+    fn test_unlock_lock_and_shared_wrappings() {
+        let (db, keyring, lock) = setup();
+        let alice = user(&db, "alice@example.com");
+        let bob = user(&db, "bob@example.com");
+        let patients = PatientRepository::new(db.clone(), keyring.clone());
+
+        // A locked, uninitialized device has no keys
+        assert!(patients.create(&patient()).is_err());
+        assert!(matches!(lock.unlock(bob.id, "bob-pass"), Ok(())));
+        lock.lock().unwrap();
+
+        // Bob created the device key; Alice cannot join while locked
+        assert!(matches!(lock.unlock(alice.id, "alice-pass"), Err(DeviceLockError::NotEnrolled)));
+        assert!(matches!(lock.unlock(bob.id, "wrong"), Err(DeviceLockError::InvalidPassphrase)));
+
+        lock.unlock(bob.id, "bob-pass").unwrap();
+        let stored = patient();
+        patients.create(&stored).unwrap();
+        // ...but is enrolled by logging in while Bob has it unlocked
+        lock.unlock(alice.id, "alice-pass").unwrap();
+        assert_eq!(lock.status().unwrap().enrolled_users, 2);
+
+        lock.lock().unwrap();
+        assert!(!lock.is_unlocked());
+        assert!(patients.find_by_id(stored.id).unwrap_or(None).is_none());
+
+        // Either passphrase opens the same data
+        lock.unlock(alice.id, "alice-pass").unwrap();
+        assert_eq!(patients.find_by_id(stored.id).unwrap().unwrap().first_name, "Ada");
+    }
+
+    #[test]
+    fn test_change_passphrase_and_remove() {
+        let (db, _keyring, lock) = setup();
+        let alice = user(&db, "alice@example.com");
+        let bob = user(&db, "bob@example.com");
+        lock.unlock(alice.id, "old-pass").unwrap();
+        lock.unlock(bob.id, "bob-pass").unwrap();
+        lock.lock().unwrap();
+
+        lock.change_passphrase(alice.id, "old-pass", "new-pass").unwrap();
+        assert!(lock.unlock(alice.id, "old-pass").is_err());
+        lock.unlock(alice.id, "new-pass").unwrap();
+
+        let jwt = JwtManager::new(b"secret");
+        let claims = |role| {
+            let token = jwt.create_access_token(alice.id, &alice.email, role, Id::new_v4(), None).unwrap();
+            jwt.validate_token(&token).unwrap()
+        };
+        let (admin, nurse) = (claims(UserRole::Admin), claims(UserRole::Nurse));
+        assert!(matches!(lock.remove(&nurse, bob.id), Err(DeviceLockError::NotPermitted(_))));
+        lock.remove(&admin, bob.id).unwrap();
+        assert!(matches!(lock.remove(&admin, alice.id), Err(DeviceLockError::LastWrapping)));
+    }
+
+    #[test]
+    fn test_locks_when_idle() {
+        let (db, keyring, lock) = setup();
+        let alice = user(&db, "alice@example.com");
+        lock.unlock(alice.id, "pass").unwrap();
+
+        assert!(!lock.lock_if_idle().unwrap());
+        lock.touch().unwrap();
+
+        lock.state.lock().unwrap().last_activity = Instant::now() - Duration::from_secs(16 * 60);
+        assert!(lock.lock_if_idle().unwrap());
+        assert!(!lock.is_unlocked());
+        assert!(lock.state.lock().unwrap().key.is_none());
+        assert!(keyring.active_key_id().is_none());
+        assert!(matches!(lock.touch(), Err(DeviceLockError::Locked)));
+    }
+}
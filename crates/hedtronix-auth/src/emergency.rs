@@ -0,0 +1,485 @@
+//! Break-the-glass emergency access
+//!
+//! A clinician outside a patient's care team can open the chart in an
+//! emergency by stating a reason. The grant is short-lived, every access
+//! made under it is audited, and each grant waits for a privacy officer to
+//! review it.
+
+use std::sync::Arc;
+
+use chrono::Duration;
+use hedtronix_core::{
+    AuditEventType, AuditLog, CreateEmergencyAccess, EmergencyAccessGrant, Id,
+    ReviewEmergencyAccess, UserRole,
+};
+use hedtronix_db::{
+    AuditRepository, CareTeamRepository, Database, DeviceRepository, EmergencyAccessRepository,
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::jwt::{Claims, JwtManager};
+use crate::permissions::PermissionChecker;
+
+/// Emergency access errors
+#[derive(Error, Debug)]
+pub enum EmergencyAccessError {
+    #[error("Emergency access not permitted: {0}")]
+    NotPermitted(String),
+
+    #[error("A reason of at least {0} characters is required")]
+    ReasonTooShort(usize),
+
+    #[error("Patient not found")]
+    PatientNotFound,
+
+    #[error("Emergency access grant not found")]
+    GrantNotFound,
+
+    #[error("Invalid emergency access grant: {0}")]
+    InvalidGrant(String),
+
+    #[error("Emergency access grant has already been reviewed")]
+    AlreadyReviewed,
+
+    #[error("Database error: {0}")]
+    Database(String),
+}
+
+/// Result type for emergency access operations
+pub type Result<T> = std::result::Result<T, EmergencyAccessError>;
+
+/// Who may break the glass, for how long, and how much justification is
+/// required
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmergencyAccessPolicy {
+    /// Base roles that may request emergency access
+    pub allowed_roles: Vec<UserRole>,
+
+    /// Grant length when the request does not ask for one
+    pub default_minutes: u32,
+
+    /// Longest grant that is ever issued
+    pub max_minutes: u32,
+
+    /// Shortest accepted justification
+    pub min_reason_length: usize,
+}
+
+impl Default for EmergencyAccessPolicy {
+    fn default() -> Self {
+        Self {
+            allowed_roles: vec![UserRole::Physician, UserRole::Nurse],
+            default_minutes: 60,
+            max_minutes: 240,
+            min_reason_length: 20,
+        }
+    }
+}
+
+impl EmergencyAccessPolicy {
+    /// Load a policy from a JSON file
+    pub fn from_file(path: &std::path::Path) -> std::result::Result<Self, String> {
+        let json = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        serde_json::from_str(&json).map_err(|e| e.to_string())
+    }
+
+    pub fn duration(&self, requested_minutes: Option<u32>) -> Duration {
+        let minutes = requested_minutes
+            .unwrap_or(self.default_minutes)
+            .clamp(1, self.max_minutes.max(1));
+        Duration::minutes(minutes as i64)
+    }
+}
+
+/// Issued grant with the token that unlocks it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmergencyAccessResponse {
+    /// Sent as `X-Break-Glass-Token` on requests for the patient's chart
+    pub grant_token: String,
+    pub grant: EmergencyAccessGrant,
+    pub expires_in: i64,
+}
+
+/// A grant awaiting review, with everything done under it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmergencyAccessReview {
+    pub grant: EmergencyAccessGrant,
+    pub accesses: Vec<AuditLog>,
+}
+
+/// Emergency access service
+pub struct EmergencyAccessService {
+    db: Database,
+    jwt_manager: Arc<JwtManager>,
+    policy: Arc<EmergencyAccessPolicy>,
+    permissions: Arc<PermissionChecker>,
+}
+
+impl EmergencyAccessService {
+    pub fn new(db: Database, jwt_manager: Arc<JwtManager>) -> Self {
+        Self {
+            permissions: Arc::new(PermissionChecker::new(db.clone())),
+            db,
+            jwt_manager,
+            policy: Arc::new(EmergencyAccessPolicy::default()),
+        }
+    }
+
+    /// Replace the default emergency access policy
+    pub fn with_policy(mut self, policy: Arc<EmergencyAccessPolicy>) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Share the server's permission checker
+    pub fn with_permission_checker(mut self, permissions: Arc<PermissionChecker>) -> Self {
+        self.permissions = permissions;
+        self
+    }
+
+    /// Whether the caller may review grants and see the review queue
+    pub fn can_review(&self, claims: &Claims) -> bool {
+        self.permissions.authorize(claims, "emergency_access", "review")
+    }
+
+    /// Open a patient's chart for a limited time
+    pub fn request(&self, claims: &Claims, req: CreateEmergencyAccess) -> Result<EmergencyAccessResponse> {
+        if claims.offline {
+            return Err(EmergencyAccessError::NotPermitted(
+                "Emergency access needs an online session".to_string(),
+            ));
+        }
+        if !self.policy.allowed_roles.contains(&claims.user_role()) {
+            return Err(EmergencyAccessError::NotPermitted(format!(
+                "Role {} may not request emergency access",
+                claims.role
+            )));
+        }
+
+        let reason = req.reason.trim().to_string();
+        if reason.chars().count() < self.policy.min_reason_length {
+            return Err(EmergencyAccessError::ReasonTooShort(self.policy.min_reason_length));
+        }
+
+        let user_id = claims.user_id()
+            .ok_or_else(|| EmergencyAccessError::NotPermitted("Token has no valid subject".to_string()))?;
+
+        CareTeamRepository::new(self.db.clone())
+            .find_for_patient(req.patient_id)
+            .map_err(|e| EmergencyAccessError::Database(e.to_string()))?
+            .ok_or(EmergencyAccessError::PatientNotFound)?;
+
+        let grant = EmergencyAccessGrant::new(
+            user_id,
+            req.patient_id,
+            self.registered_device(claims)?,
+            reason,
+            self.policy.duration(req.duration_minutes),
+        );
+
+        EmergencyAccessRepository::new(self.db.clone())
+            .create(&grant)
+            .map_err(|e| EmergencyAccessError::Database(e.to_string()))?;
+
+        let mut audit = AuditLog::new(
+            AuditEventType::Create,
+            Some(user_id),
+            grant.device_id,
+            "EmergencyAccessGrant".to_string(),
+            grant.id.to_string(),
+            serde_json::json!({
+                "patient_id": grant.patient_id,
+                "reason": grant.reason,
+                "expires_at": grant.expires_at.to_rfc3339(),
+            }),
+        );
+        audit.break_glass_grant_id = Some(grant.id);
+        self.append_audit(&audit)?;
+
+        let grant_token = self.jwt_manager
+            .create_break_glass_token(grant.id, user_id, grant.patient_id, grant.expires_at)
+            .map_err(|e| EmergencyAccessError::InvalidGrant(e.to_string()))?;
+
+        Ok(EmergencyAccessResponse {
+            grant_token,
+            expires_in: (grant.expires_at - grant.granted_at).num_seconds(),
+            grant,
+        })
+    }
+
+    /// Check a grant token presented by the caller for a patient's chart
+    pub fn authorize(&self, claims: &Claims, token: &str, patient_id: Id) -> Result<EmergencyAccessGrant> {
+        let grant_claims = self.jwt_manager
+            .validate_break_glass_token(token)
+            .map_err(|e| EmergencyAccessError::InvalidGrant(e.to_string()))?;
+
+        if grant_claims.sub != claims.sub {
+            return Err(EmergencyAccessError::InvalidGrant("Grant belongs to another user".to_string()));
+        }
+        if grant_claims.patient_id() != Some(patient_id) {
+            return Err(EmergencyAccessError::InvalidGrant("Grant is for another patient".to_string()));
+        }
+
+        let grant_id = grant_claims.grant_id().ok_or(EmergencyAccessError::GrantNotFound)?;
+        let grant = self.find(grant_id)?;
+        if !grant.is_active() {
+            return Err(EmergencyAccessError::InvalidGrant("Grant has expired or was revoked".to_string()));
+        }
+
+        Ok(grant)
+    }
+
+    /// Audit a read made under a grant
+    pub fn record_access(
+        &self,
+        claims: &Claims,
+        grant: &EmergencyAccessGrant,
+        entity_type: &str,
+        entity_id: &str,
+    ) -> Result<()> {
+        let audit = AuditLog::break_glass_read_event(
+            grant.user_id,
+            self.registered_device(claims)?,
+            entity_type,
+            entity_id,
+            grant.id,
+        );
+        self.append_audit(&audit)
+    }
+
+    pub fn find(&self, grant_id: Id) -> Result<EmergencyAccessGrant> {
+        EmergencyAccessRepository::new(self.db.clone())
+            .find_by_id(grant_id)
+            .map_err(|e| EmergencyAccessError::Database(e.to_string()))?
+            .ok_or(EmergencyAccessError::GrantNotFound)
+    }
+
+    /// End a grant early. The holder may always do so; anyone else needs
+    /// review rights.
+    pub fn revoke(&self, claims: &Claims, grant_id: Id) -> Result<EmergencyAccessGrant> {
+        let grant = self.find(grant_id)?;
+        if claims.user_id() != Some(grant.user_id) && !self.can_review(claims) {
+            return Err(EmergencyAccessError::NotPermitted(
+                "Only the holder or a reviewer may revoke a grant".to_string(),
+            ));
+        }
+
+        EmergencyAccessRepository::new(self.db.clone())
+            .revoke(grant_id)
+            .map_err(|e| EmergencyAccessError::Database(e.to_string()))?;
+
+        self.find(grant_id)
+    }
+
+    /// Grants awaiting review with the accesses made under each
+    pub fn pending_reviews(&self, claims: &Claims) -> Result<Vec<EmergencyAccessReview>> {
+        self.require_reviewer(claims)?;
+
+        let grants = EmergencyAccessRepository::new(self.db.clone())
+            .find_pending_review()
+            .map_err(|e| EmergencyAccessError::Database(e.to_string()))?;
+
+        let audit = AuditRepository::new(self.db.clone());
+        grants
+            .into_iter()
+            .map(|grant| {
+                let accesses = audit
+                    .find_by_break_glass_grant(grant.id)
+                    .map_err(|e| EmergencyAccessError::Database(e.to_string()))?;
+                Ok(EmergencyAccessReview { grant, accesses })
+            })
+            .collect()
+    }
+
+    /// Record the privacy officer's finding on a grant
+    pub fn review(
+        &self,
+        claims: &Claims,
+        grant_id: Id,
+        req: ReviewEmergencyAccess,
+    ) -> Result<EmergencyAccessGrant> {
+        self.require_reviewer(claims)?;
+
+        let reviewer_id = claims.user_id()
+            .ok_or_else(|| EmergencyAccessError::NotPermitted("Token has no valid subject".to_string()))?;
+        let grant = self.find(grant_id)?;
+        if grant.user_id == reviewer_id {
+            return Err(EmergencyAccessError::NotPermitted(
+                "A grant cannot be reviewed by its holder".to_string(),
+            ));
+        }
+
+        let reviewed = EmergencyAccessRepository::new(self.db.clone())
+            .review(grant_id, req.status, reviewer_id, req.notes.as_deref())
+            .map_err(|e| EmergencyAccessError::Database(e.to_string()))?;
+        if !reviewed {
+            return Err(EmergencyAccessError::AlreadyReviewed);
+        }
+
+        self.find(grant_id)
+    }
+
+    fn require_reviewer(&self, claims: &Claims) -> Result<()> {
+        if self.can_review(claims) {
+            Ok(())
+        } else {
+            Err(EmergencyAccessError::NotPermitted(
+                "Reviewing emergency access requires the emergency_access:review permission".to_string(),
+            ))
+        }
+    }
+
+    /// The caller's device, if it has been registered
+    fn registered_device(&self, claims: &Claims) -> Result<Option<Id>> {
+        let Some(device_id) = claims.device_id() else {
+            return Ok(None);
+        };
+
+        let device = DeviceRepository::new(self.db.clone())
+            .find_by_id(device_id)
+            .map_err(|e| EmergencyAccessError::Database(e.to_string()))?;
+
+        Ok(device.map(|d| d.id))
+    }
+
+    fn append_audit(&self, audit: &AuditLog) -> Result<()> {
+        AuditRepository::new(self.db.clone())
+            .append(audit)
+            .map(|_| ())
+            .map_err(|e| EmergencyAccessError::Database(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hedtronix_core::{Gender, Patient, User};
+    use hedtronix_db::{PatientRepository, UserRepository};
+
+    struct Fixture {
+        service: EmergencyAccessService,
+        jwt_manager: Arc<JwtManager>,
+        db: Database,
+        patient_id: Id,
+    }
+
+    fn setup() -> Fixture {
+        let mut db = Database::in_memory().unwrap();
+        db.initialize().unwrap();
+
+        let patient = Patient::new(
+            "MRN-1".to_string(),
+            "Pat".to_string(),
+            "Doe".to_string(),
+            chrono::NaiveDate::from_ymd_opt(1980, 1, 1).unwrap(),
+            Gender::Unknown,
+        );
+        let key = hedtronix_crypto::DataKey::from_bytes("test", &[0; 32]).unwrap();
+        let keyring = Arc::new(hedtronix_crypto::Keyring::with_key(key).unwrap());
+        PatientRepository::new(db.clone(), keyring).create(&patient).unwrap();
+
+        let jwt_manager = Arc::new(JwtManager::new(b"test-secret-key-32-bytes-long!!"));
+        Fixture {
+            service: EmergencyAccessService::new(db.clone(), jwt_manager.clone()),
+            jwt_manager,
+            db,
+            patient_id: patient.id,
+        }
+    }
+
+    fn claims_for(fixture: &Fixture, email: &str, role: UserRole) -> Claims {
+        let user = User::new(email.to_string(), "Test".to_string(), role, "hash".to_string());
+        UserRepository::new(fixture.db.clone()).create(&user).unwrap();
+
+        let token = fixture.jwt_manager
+            .create_access_token(user.id, &user.email, user.role, Id::new_v4(), None)
+            .unwrap();
+        fixture.jwt_manager.validate_token(&token).unwrap()
+    }
+
+    fn request(patient_id: Id, reason: &str) -> CreateEmergencyAccess {
+        CreateEmergencyAccess {
+            patient_id,
+            reason: reason.to_string(),
+            duration_minutes: Some(1000),
+        }
+    }
+
+    #[test]
+    fn test_grant_requires_reason_and_clinical_role() {
+        let fixture = setup();
+        let nurse = claims_for(&fixture, "nurse@example.com", UserRole::Nurse);
+        let billing = claims_for(&fixture, "billing@example.com", UserRole::Billing);
+
+        assert!(matches!(
+            fixture.service.request(&nurse, request(fixture.patient_id, "urgent")),
+            Err(EmergencyAccessError::ReasonTooShort(_))
+        ));
+        assert!(matches!(
+            fixture.service.request(&billing, request(fixture.patient_id, "Unconscious patient in the ED")),
+            Err(EmergencyAccessError::NotPermitted(_))
+        ));
+
+        let response = fixture.service
+            .request(&nurse, request(fixture.patient_id, "Unconscious patient in the ED"))
+            .unwrap();
+        // Capped at the policy maximum of four hours
+        assert_eq!(response.expires_in, 240 * 60);
+    }
+
+    #[test]
+    fn test_grant_token_is_bound_to_user_and_patient() {
+        let fixture = setup();
+        let nurse = claims_for(&fixture, "nurse@example.com", UserRole::Nurse);
+        let other = claims_for(&fixture, "other@example.com", UserRole::Nurse);
+
+        let response = fixture.service
+            .request(&nurse, request(fixture.patient_id, "Unconscious patient in the ED"))
+            .unwrap();
+
+        let grant = fixture.service.authorize(&nurse, &response.grant_token, fixture.patient_id).unwrap();
+        assert_eq!(grant.id, response.grant.id);
+        assert!(fixture.service.authorize(&other, &response.grant_token, fixture.patient_id).is_err());
+        assert!(fixture.service.authorize(&nurse, &response.grant_token, Id::new_v4()).is_err());
+
+        fixture.service.revoke(&nurse, grant.id).unwrap();
+        assert!(fixture.service.authorize(&nurse, &response.grant_token, fixture.patient_id).is_err());
+    }
+
+    #[test]
+    fn test_accesses_are_queued_for_review() {
+        let fixture = setup();
+        let nurse = claims_for(&fixture, "nurse@example.com", UserRole::Nurse);
+        let admin = claims_for(&fixture, "privacy@example.com", UserRole::Admin);
+
+        let response = fixture.service
+            .request(&nurse, request(fixture.patient_id, "Unconscious patient in the ED"))
+            .unwrap();
+        fixture.service
+            .record_access(&nurse, &response.grant, "Patient", &fixture.patient_id.to_string())
+            .unwrap();
+
+        assert!(fixture.service.pending_reviews(&nurse).is_err());
+
+        let pending = fixture.service.pending_reviews(&admin).unwrap();
+        assert_eq!(pending.len(), 1);
+        let reads: Vec<_> = pending[0].accesses.iter()
+            .filter(|a| a.event_type == AuditEventType::Read)
+            .collect();
+        assert_eq!(reads.len(), 1);
+        assert!(reads[0].is_break_glass());
+
+        let review = ReviewEmergencyAccess {
+            status: hedtronix_core::EmergencyReviewStatus::Justified,
+            notes: None,
+        };
+        assert!(fixture.service.review(&nurse, response.grant.id, review.clone()).is_err());
+        fixture.service.review(&admin, response.grant.id, review.clone()).unwrap();
+        assert!(matches!(
+            fixture.service.review(&admin, response.grant.id, review),
+            Err(EmergencyAccessError::AlreadyReviewed)
+        ));
+        assert!(fixture.service.pending_reviews(&admin).unwrap().is_empty());
+    }
+}
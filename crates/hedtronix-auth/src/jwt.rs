@@ -1,11 +1,17 @@
 //! JWT token management with offline support
 
-use chrono::{Duration, Utc};
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
-use serde::{Deserialize, Serialize};
+use std::sync::RwLock;
+
+use chrono::{DateTime, Duration, Utc};
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{decode, decode_header, encode, Header, Validation};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use thiserror::Error;
 use hedtronix_core::{Id, UserRole};
 
+use crate::keys::JwtKeySet;
+use crate::offline::OfflineGrant;
+
 /// JWT error types
 #[derive(Error, Debug)]
 pub enum JwtError {
@@ -23,6 +29,15 @@ pub enum JwtError {
     
     #[error("Missing claim: {0}")]
     MissingClaim(String),
+    
+    #[error("Unknown signing key: {0}")]
+    UnknownKey(String),
+    
+    #[error("No signing key available")]
+    NoSigningKey,
+    
+    #[error("Key store error: {0}")]
+    KeyStore(String),
 }
 
 /// Result type for JWT operations
@@ -57,6 +72,11 @@ pub struct Claims {
     
     /// Offline-capable flag
     pub offline: bool,
+    
+    /// Restricted `resource:action` set carried by offline tokens; empty
+    /// means the role's full permissions apply
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub permissions: Vec<String>,
 }
 
 impl Claims {
@@ -83,29 +103,228 @@ impl Claims {
     pub fn is_expired(&self) -> bool {
         Utc::now().timestamp() > self.exp
     }
+
+    /// Whether the token's own permission restriction allows an action
+    pub fn allows(&self, resource: &str, action: &str) -> bool {
+        self.permissions.is_empty()
+            || self.permissions.iter().any(|p| *p == format!("{}:{}", resource, action))
+    }
+}
+
+/// Audience of MFA challenge tokens. Access token validation sets no
+/// audience, so a challenge token is never accepted in its place.
+pub const MFA_AUDIENCE: &str = "mfa";
+
+/// Claims of the short-lived token issued between password and second factor
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MfaChallengeClaims {
+    /// Subject (user ID)
+    pub sub: String,
+    
+    /// Device the login started on
+    pub device_id: String,
+    
+    /// Always `MFA_AUDIENCE`
+    pub aud: String,
+    
+    /// WebAuthn challenge (base64url) bound to this login attempt
+    pub challenge: String,
+    
+    pub iat: i64,
+    pub exp: i64,
+    pub jti: String,
+}
+
+impl MfaChallengeClaims {
+    pub fn user_id(&self) -> Option<Id> {
+        Id::parse_str(&self.sub).ok()
+    }
+
+    pub fn device_id(&self) -> Option<Id> {
+        Id::parse_str(&self.device_id).ok()
+    }
+}
+
+/// Audience of refresh tokens. Refresh only accepts tokens with this
+/// audience, so access and offline tokens cannot be exchanged for new ones.
+pub const REFRESH_AUDIENCE: &str = "refresh";
+
+/// Claims of a refresh token
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RefreshClaims {
+    pub sub: String,
+    pub device_id: String,
+    pub aud: String,
+    pub iat: i64,
+    pub exp: i64,
+    pub jti: String,
+}
+
+impl RefreshClaims {
+    pub fn user_id(&self) -> Option<Id> {
+        Id::parse_str(&self.sub).ok()
+    }
+
+    pub fn device_id(&self) -> Option<Id> {
+        Id::parse_str(&self.device_id).ok()
+    }
+}
+
+/// Audience of tokens that only allow setting a new password
+pub const PASSWORD_CHANGE_AUDIENCE: &str = "password_change";
+
+/// Claims of the token issued instead of a session when the user must
+/// change their password first
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PasswordChangeClaims {
+    pub sub: String,
+    pub device_id: String,
+    pub aud: String,
+    pub iat: i64,
+    pub exp: i64,
+    pub jti: String,
+}
+
+impl PasswordChangeClaims {
+    pub fn user_id(&self) -> Option<Id> {
+        Id::parse_str(&self.sub).ok()
+    }
+
+    pub fn device_id(&self) -> Option<Id> {
+        Id::parse_str(&self.device_id).ok()
+    }
+}
+
+/// Audience of emergency access grant tokens
+pub const BREAK_GLASS_AUDIENCE: &str = "break_glass";
+
+/// Claims of an emergency access grant token. The token is presented
+/// alongside the caller's access token and only opens one patient's chart.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BreakGlassClaims {
+    pub sub: String,
+    pub patient_id: String,
+    pub aud: String,
+    pub iat: i64,
+    pub exp: i64,
+    /// Grant ID
+    pub jti: String,
+}
+
+impl BreakGlassClaims {
+    pub fn user_id(&self) -> Option<Id> {
+        Id::parse_str(&self.sub).ok()
+    }
+
+    pub fn patient_id(&self) -> Option<Id> {
+        Id::parse_str(&self.patient_id).ok()
+    }
+
+    pub fn grant_id(&self) -> Option<Id> {
+        Id::parse_str(&self.jti).ok()
+    }
 }
 
 /// JWT token manager
 pub struct JwtManager {
-    encoding_key: EncodingKey,
-    decoding_key: DecodingKey,
+    keys: RwLock<JwtKeySet>,
     access_token_expiry: Duration,
     refresh_token_expiry: Duration,
-    offline_token_expiry: Duration,
+    mfa_token_expiry: Duration,
 }
 
 impl JwtManager {
-    /// Create a new JWT manager with the given secret
+    /// Create a new JWT manager with the given HS256 secret
     pub fn new(secret: &[u8]) -> Self {
+        Self::with_key_set(JwtKeySet::hmac(secret))
+    }
+
+    /// Create a JWT manager backed by a (possibly asymmetric) key set
+    pub fn with_key_set(keys: JwtKeySet) -> Self {
         Self {
-            encoding_key: EncodingKey::from_secret(secret),
-            decoding_key: DecodingKey::from_secret(secret),
+            keys: RwLock::new(keys),
             access_token_expiry: Duration::minutes(15),
             refresh_token_expiry: Duration::days(7),
-            offline_token_expiry: Duration::hours(24),
+            mfa_token_expiry: Duration::minutes(5),
         }
     }
 
+    /// Create a verification-only manager from cached public keys, for
+    /// devices that must validate tokens while offline
+    pub fn from_jwks(jwks: &JwkSet) -> Result<Self> {
+        Ok(Self::with_key_set(JwtKeySet::from_jwks(jwks)?))
+    }
+
+    /// Public verification keys as a JWKS document
+    pub fn jwks(&self) -> JwkSet {
+        self.keys.read().map(|k| k.jwks()).unwrap_or(JwkSet { keys: Vec::new() })
+    }
+
+    /// Rotate to a new signing key, keeping the old one for verification
+    pub fn rotate_keys(&self) -> Result<String> {
+        self.keys.write()
+            .map_err(|e| JwtError::KeyStore(e.to_string()))?
+            .rotate()
+    }
+
+    /// Run a closure with mutable access to the key set
+    pub fn with_keys_mut<T>(&self, f: impl FnOnce(&mut JwtKeySet) -> T) -> Result<T> {
+        let mut keys = self.keys.write().map_err(|e| JwtError::KeyStore(e.to_string()))?;
+        Ok(f(&mut keys))
+    }
+
+    /// How long refresh tokens remain valid; old keys must be kept at least this long
+    pub fn refresh_token_expiry(&self) -> Duration {
+        self.refresh_token_expiry
+    }
+
+    /// How long MFA challenge and password change tokens remain valid
+    pub fn mfa_token_expiry(&self) -> Duration {
+        self.mfa_token_expiry
+    }
+
+    /// Sign claims with the active key, tagging the header with its kid
+    fn sign<T: Serialize>(&self, claims: &T) -> Result<String> {
+        let keys = self.keys.read().map_err(|e| JwtError::KeyStore(e.to_string()))?;
+        let key = keys.signing_key().ok_or(JwtError::NoSigningKey)?;
+        let encoding_key = key.encoding_key().ok_or(JwtError::NoSigningKey)?;
+
+        let mut header = Header::new(key.algorithm);
+        header.kid = key.kid.clone();
+
+        encode(&header, claims, encoding_key)
+            .map_err(|e| JwtError::Creation(e.to_string()))
+    }
+
+    /// Decode a token with the key named in its header
+    fn decode_claims<T: DeserializeOwned>(
+        &self,
+        token: &str,
+        validate_exp: bool,
+        audience: Option<&str>,
+    ) -> Result<T> {
+        let header = decode_header(token).map_err(|_| JwtError::Invalid)?;
+        let keys = self.keys.read().map_err(|e| JwtError::KeyStore(e.to_string()))?;
+        let key = keys.find(header.kid.as_deref())
+            .ok_or_else(|| JwtError::UnknownKey(header.kid.clone().unwrap_or_default()))?;
+
+        let mut validation = Validation::new(key.algorithm);
+        validation.validate_exp = validate_exp;
+        if let Some(audience) = audience {
+            validation.set_audience(&[audience]);
+        }
+
+        decode::<T>(token, key.decoding_key(), &validation)
+            .map(|data| data.claims)
+            .map_err(|e| {
+                if e.to_string().contains("ExpiredSignature") {
+                    JwtError::Expired
+                } else {
+                    JwtError::Validation(e.to_string())
+                }
+            })
+    }
+
     /// Create an access token
     pub fn create_access_token(
         &self,
@@ -126,13 +345,14 @@ impl JwtManager {
             exp: (now + self.access_token_expiry).timestamp(),
             jti: Id::new_v4().to_string(),
             offline: false,
+            permissions: Vec::new(),
         };
 
-        encode(&Header::default(), &claims, &self.encoding_key)
-            .map_err(|e| JwtError::Creation(e.to_string()))
+        self.sign(&claims)
     }
 
-    /// Create an offline-capable token (longer validity)
+    /// Create an offline-capable token for a grant approved by the
+    /// offline token policy
     pub fn create_offline_token(
         &self,
         user_id: Id,
@@ -140,7 +360,8 @@ impl JwtManager {
         role: UserRole,
         device_id: Id,
         department_id: Option<Id>,
-    ) -> Result<String> {
+        grant: &OfflineGrant,
+    ) -> Result<(String, Claims)> {
         let now = Utc::now();
         let claims = Claims {
             sub: user_id.to_string(),
@@ -149,57 +370,109 @@ impl JwtManager {
             device_id: device_id.to_string(),
             department_id: department_id.map(|id| id.to_string()),
             iat: now.timestamp(),
-            exp: (now + self.offline_token_expiry).timestamp(),
+            exp: (now + grant.duration).timestamp(),
             jti: Id::new_v4().to_string(),
             offline: true,
+            permissions: grant.permissions.clone(),
         };
 
-        encode(&Header::default(), &claims, &self.encoding_key)
-            .map_err(|e| JwtError::Creation(e.to_string()))
+        Ok((self.sign(&claims)?, claims))
     }
 
     /// Create a refresh token
     pub fn create_refresh_token(&self, user_id: Id, device_id: Id) -> Result<String> {
         let now = Utc::now();
-        let claims = Claims {
+        let claims = RefreshClaims {
             sub: user_id.to_string(),
-            email: String::new(),
-            role: String::new(),
             device_id: device_id.to_string(),
-            department_id: None,
+            aud: REFRESH_AUDIENCE.to_string(),
             iat: now.timestamp(),
             exp: (now + self.refresh_token_expiry).timestamp(),
             jti: Id::new_v4().to_string(),
-            offline: false,
         };
 
-        encode(&Header::default(), &claims, &self.encoding_key)
-            .map_err(|e| JwtError::Creation(e.to_string()))
+        self.sign(&claims)
+    }
+
+    /// Validate a refresh token
+    pub fn validate_refresh_token(&self, token: &str) -> Result<RefreshClaims> {
+        self.decode_claims(token, true, Some(REFRESH_AUDIENCE))
     }
 
     /// Validate and decode a token
     pub fn validate_token(&self, token: &str) -> Result<Claims> {
-        let validation = Validation::default();
-        
-        decode::<Claims>(token, &self.decoding_key, &validation)
-            .map(|data| data.claims)
-            .map_err(|e| {
-                if e.to_string().contains("ExpiredSignature") {
-                    JwtError::Expired
-                } else {
-                    JwtError::Validation(e.to_string())
-                }
-            })
+        self.decode_claims(token, true, None)
     }
 
     /// Decode a token without validation (for expired token inspection)
     pub fn decode_without_validation(&self, token: &str) -> Result<Claims> {
-        let mut validation = Validation::default();
-        validation.validate_exp = false;
-        
-        decode::<Claims>(token, &self.decoding_key, &validation)
-            .map(|data| data.claims)
-            .map_err(|e| JwtError::Validation(e.to_string()))
+        self.decode_claims(token, false, None)
+    }
+
+    /// Create the MFA challenge token returned after a correct password
+    pub fn create_mfa_token(&self, user_id: Id, device_id: Id, challenge: &str) -> Result<String> {
+        let now = Utc::now();
+        let claims = MfaChallengeClaims {
+            sub: user_id.to_string(),
+            device_id: device_id.to_string(),
+            aud: MFA_AUDIENCE.to_string(),
+            challenge: challenge.to_string(),
+            iat: now.timestamp(),
+            exp: (now + self.mfa_token_expiry).timestamp(),
+            jti: Id::new_v4().to_string(),
+        };
+
+        self.sign(&claims)
+    }
+
+    /// Validate an MFA challenge token
+    pub fn validate_mfa_token(&self, token: &str) -> Result<MfaChallengeClaims> {
+        self.decode_claims(token, true, Some(MFA_AUDIENCE))
+    }
+
+    /// Create a token that only allows the user to set a new password
+    pub fn create_password_change_token(&self, user_id: Id, device_id: Id) -> Result<String> {
+        let now = Utc::now();
+        let claims = PasswordChangeClaims {
+            sub: user_id.to_string(),
+            device_id: device_id.to_string(),
+            aud: PASSWORD_CHANGE_AUDIENCE.to_string(),
+            iat: now.timestamp(),
+            exp: (now + self.mfa_token_expiry).timestamp(),
+            jti: Id::new_v4().to_string(),
+        };
+
+        self.sign(&claims)
+    }
+
+    /// Validate a password change token
+    pub fn validate_password_change_token(&self, token: &str) -> Result<PasswordChangeClaims> {
+        self.decode_claims(token, true, Some(PASSWORD_CHANGE_AUDIENCE))
+    }
+
+    /// Create the token for an emergency access grant; it expires with the grant
+    pub fn create_break_glass_token(
+        &self,
+        grant_id: Id,
+        user_id: Id,
+        patient_id: Id,
+        expires_at: DateTime<Utc>,
+    ) -> Result<String> {
+        let claims = BreakGlassClaims {
+            sub: user_id.to_string(),
+            patient_id: patient_id.to_string(),
+            aud: BREAK_GLASS_AUDIENCE.to_string(),
+            iat: Utc::now().timestamp(),
+            exp: expires_at.timestamp(),
+            jti: grant_id.to_string(),
+        };
+
+        self.sign(&claims)
+    }
+
+    /// Validate an emergency access grant token
+    pub fn validate_break_glass_token(&self, token: &str) -> Result<BreakGlassClaims> {
+        self.decode_claims(token, true, Some(BREAK_GLASS_AUDIENCE))
     }
 
     /// Check if token needs refresh
@@ -258,16 +531,107 @@ mod tests {
     #[test]
     fn test_offline_token() {
         let manager = JwtManager::new(b"test-secret-key-32-bytes-long!!");
+        let grant = OfflineGrant {
+            duration: Duration::hours(8),
+            permissions: vec!["patients:read".to_string()],
+        };
         
-        let token = manager.create_offline_token(
+        let (token, _) = manager.create_offline_token(
             Id::new_v4(),
             "test@example.com",
             UserRole::Nurse,
             Id::new_v4(),
             None,
+            &grant,
         ).unwrap();
 
         let claims = manager.validate_token(&token).unwrap();
         assert!(claims.offline);
+        assert_eq!(claims.exp - claims.iat, 8 * 3600);
+        assert!(claims.allows("patients", "read"));
+        assert!(!claims.allows("patients", "write"));
+    }
+
+    #[test]
+    fn test_mfa_token_is_not_an_access_token() {
+        let manager = JwtManager::new(b"test-secret-key-32-bytes-long!!");
+        let user_id = Id::new_v4();
+
+        let mfa_token = manager.create_mfa_token(user_id, Id::new_v4(), "challenge").unwrap();
+        let claims = manager.validate_mfa_token(&mfa_token).unwrap();
+        assert_eq!(claims.user_id(), Some(user_id));
+        assert_eq!(claims.challenge, "challenge");
+        assert!(manager.validate_token(&mfa_token).is_err());
+
+        let access_token = manager.create_access_token(
+            user_id,
+            "test@example.com",
+            UserRole::Physician,
+            Id::new_v4(),
+            None,
+        ).unwrap();
+        assert!(manager.validate_mfa_token(&access_token).is_err());
+    }
+
+    #[test]
+    fn test_asymmetric_token_has_kid() {
+        for algorithm in [jsonwebtoken::Algorithm::EdDSA, jsonwebtoken::Algorithm::ES256] {
+            let manager = JwtManager::with_key_set(JwtKeySet::generate(algorithm).unwrap());
+            let token = manager.create_refresh_token(Id::new_v4(), Id::new_v4()).unwrap();
+
+            let header = decode_header(&token).unwrap();
+            assert_eq!(header.alg, algorithm);
+            assert!(header.kid.is_some());
+            assert!(manager.validate_refresh_token(&token).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_offline_verification_from_cached_jwks() {
+        let server = JwtManager::with_key_set(
+            JwtKeySet::generate(jsonwebtoken::Algorithm::EdDSA).unwrap(),
+        );
+        let grant = OfflineGrant {
+            duration: Duration::hours(24),
+            permissions: vec!["patients:read".to_string()],
+        };
+        let (token, _) = server.create_offline_token(
+            Id::new_v4(),
+            "test@example.com",
+            UserRole::Physician,
+            Id::new_v4(),
+            None,
+            &grant,
+        ).unwrap();
+
+        // A device caches the JWKS document and verifies without the private key
+        let cached = serde_json::to_string(&server.jwks()).unwrap();
+        let device = JwtManager::from_jwks(&serde_json::from_str(&cached).unwrap()).unwrap();
+
+        let claims = device.validate_token(&token).unwrap();
+        assert_eq!(claims.email, "test@example.com");
+        assert!(matches!(
+            device.create_refresh_token(Id::new_v4(), Id::new_v4()),
+            Err(JwtError::NoSigningKey)
+        ));
+    }
+
+    #[test]
+    fn test_tokens_survive_key_rotation() {
+        let manager = JwtManager::with_key_set(
+            JwtKeySet::generate(jsonwebtoken::Algorithm::EdDSA).unwrap(),
+        );
+        let old_token = manager.create_refresh_token(Id::new_v4(), Id::new_v4()).unwrap();
+
+        manager.rotate_keys().unwrap();
+        let new_token = manager.create_refresh_token(Id::new_v4(), Id::new_v4()).unwrap();
+
+        assert!(manager.validate_refresh_token(&old_token).is_ok());
+        assert!(manager.validate_refresh_token(&new_token).is_ok());
+        assert_ne!(
+            decode_header(&old_token).unwrap().kid,
+            decode_header(&new_token).unwrap().kid
+        );
+        assert_eq!(manager.jwks().keys.len(), 2);
     }
 }
@@ -0,0 +1,451 @@
+//! JWT signing keys with rotation and JWKS publication
+//!
+//! The server signs with a single active key and keeps older keys around for
+//! verification until the tokens they issued have expired. Offline devices
+//! build a verification-only key set from the published JWKS.
+
+use std::fs;
+use std::path::Path;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as BASE64URL, Engine as _};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use hedtronix_crypto::signing::{SignatureAlgorithm, SigningKeyPair};
+use hedtronix_crypto::SecretKey;
+use jsonwebtoken::jwk::{
+    AlgorithmParameters, CommonParameters, EllipticCurve, EllipticCurveKeyParameters,
+    EllipticCurveKeyType, Jwk, JwkSet, KeyAlgorithm, OctetKeyPairParameters, OctetKeyPairType,
+    PublicKeyUse,
+};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey};
+
+use crate::jwt::{JwtError, Result};
+
+const KID_TIME_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+const ACTIVE_FILE: &str = "active";
+
+/// A single JWT key, either able to sign or verification-only
+pub struct JwtKey {
+    pub kid: Option<String>,
+    pub algorithm: Algorithm,
+    pub created_at: Option<DateTime<Utc>>,
+    encoding_key: Option<EncodingKey>,
+    decoding_key: DecodingKey,
+    pkcs8: Option<SecretKey>,
+    jwk: Option<Jwk>,
+}
+
+impl JwtKey {
+    /// Shared-secret HS256 key (legacy deployments)
+    pub fn hmac(secret: &[u8]) -> Self {
+        Self {
+            kid: None,
+            algorithm: Algorithm::HS256,
+            created_at: None,
+            encoding_key: Some(EncodingKey::from_secret(secret)),
+            decoding_key: DecodingKey::from_secret(secret),
+            pkcs8: None,
+            jwk: None,
+        }
+    }
+
+    /// Generate a new EdDSA or ES256 signing key
+    pub fn generate(algorithm: Algorithm) -> Result<Self> {
+        let pair = SigningKeyPair::generate(signature_algorithm(algorithm)?)
+            .map_err(|e| JwtError::KeyStore(e.to_string()))?;
+        let now = Utc::now();
+        let suffix = hedtronix_crypto::keys::generate_random_bytes(4)
+            .map_err(|e| JwtError::KeyStore(e.to_string()))?
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>();
+        let kid = format!("{}-{}", now.format(KID_TIME_FORMAT), suffix);
+
+        Self::from_pkcs8(&kid, algorithm, pair.pkcs8())
+    }
+
+    /// Load a signing key from PKCS#8 DER
+    pub fn from_pkcs8(kid: &str, algorithm: Algorithm, pkcs8: &[u8]) -> Result<Self> {
+        let pair = SigningKeyPair::from_pkcs8(signature_algorithm(algorithm)?, pkcs8)
+            .map_err(|e| JwtError::KeyStore(e.to_string()))?;
+        let jwk = public_jwk(kid, algorithm, pair.public_key())?;
+
+        let (encoding_key, decoding_key) = match algorithm {
+            Algorithm::EdDSA => (
+                EncodingKey::from_ed_der(pkcs8),
+                DecodingKey::from_ed_der(pair.public_key()),
+            ),
+            _ => (
+                EncodingKey::from_ec_der(pkcs8),
+                DecodingKey::from_ec_der(pair.public_key()),
+            ),
+        };
+
+        Ok(Self {
+            kid: Some(kid.to_string()),
+            algorithm,
+            created_at: kid_timestamp(kid),
+            encoding_key: Some(encoding_key),
+            decoding_key,
+            pkcs8: Some(SecretKey::from_slice(pkcs8)),
+            jwk: Some(jwk),
+        })
+    }
+
+    /// Verification-only key from a published JWK
+    pub fn from_jwk(jwk: &Jwk) -> Result<Self> {
+        let kid = jwk.common.key_id.clone()
+            .ok_or_else(|| JwtError::KeyStore("JWK is missing a kid".into()))?;
+        let algorithm = match &jwk.algorithm {
+            AlgorithmParameters::OctetKeyPair(_) => Algorithm::EdDSA,
+            AlgorithmParameters::EllipticCurve(params) if params.curve == EllipticCurve::P256 => {
+                Algorithm::ES256
+            }
+            _ => return Err(JwtError::KeyStore(format!("Unsupported JWK for key {}", kid))),
+        };
+        let decoding_key = DecodingKey::from_jwk(jwk)
+            .map_err(|e| JwtError::KeyStore(e.to_string()))?;
+
+        Ok(Self {
+            created_at: kid_timestamp(&kid),
+            kid: Some(kid),
+            algorithm,
+            encoding_key: None,
+            decoding_key,
+            pkcs8: None,
+            jwk: Some(jwk.clone()),
+        })
+    }
+
+    pub fn can_sign(&self) -> bool {
+        self.encoding_key.is_some()
+    }
+
+    pub(crate) fn encoding_key(&self) -> Option<&EncodingKey> {
+        self.encoding_key.as_ref()
+    }
+
+    pub(crate) fn decoding_key(&self) -> &DecodingKey {
+        &self.decoding_key
+    }
+}
+
+/// Ordered set of JWT keys: one active signer plus older verification keys
+pub struct JwtKeySet {
+    keys: Vec<JwtKey>,
+    active: Option<usize>,
+}
+
+impl JwtKeySet {
+    /// Key set holding a single HS256 shared secret
+    pub fn hmac(secret: &[u8]) -> Self {
+        Self {
+            keys: vec![JwtKey::hmac(secret)],
+            active: Some(0),
+        }
+    }
+
+    /// Key set with one freshly generated signing key
+    pub fn generate(algorithm: Algorithm) -> Result<Self> {
+        let mut set = Self { keys: Vec::new(), active: None };
+        set.add_signing_key(JwtKey::generate(algorithm)?);
+        Ok(set)
+    }
+
+    /// Verification-only key set built from a cached JWKS document
+    pub fn from_jwks(jwks: &JwkSet) -> Result<Self> {
+        let keys = jwks.keys.iter()
+            .map(JwtKey::from_jwk)
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self { keys, active: None })
+    }
+
+    /// Add a key and make it the active signer
+    pub fn add_signing_key(&mut self, key: JwtKey) {
+        self.keys.push(key);
+        self.active = Some(self.keys.len() - 1);
+    }
+
+    /// Add a verification-only key
+    pub fn add_verification_key(&mut self, key: JwtKey) {
+        self.keys.push(key);
+    }
+
+    /// Generate a new signing key with the active key's algorithm; the
+    /// previous key stays available for verification. Returns the new kid.
+    pub fn rotate(&mut self) -> Result<String> {
+        let algorithm = self.signing_key()
+            .map(|k| k.algorithm)
+            .ok_or(JwtError::NoSigningKey)?;
+        let key = JwtKey::generate(algorithm)?;
+        let kid = key.kid.clone().unwrap_or_default();
+        self.add_signing_key(key);
+        Ok(kid)
+    }
+
+    /// Remove a verification key. The active signing key cannot be retired.
+    pub fn retire(&mut self, kid: &str) -> bool {
+        let Some(index) = self.keys.iter().position(|k| k.kid.as_deref() == Some(kid)) else {
+            return false;
+        };
+        if self.active == Some(index) {
+            return false;
+        }
+
+        self.keys.remove(index);
+        if let Some(active) = self.active {
+            if active > index {
+                self.active = Some(active - 1);
+            }
+        }
+        true
+    }
+
+    /// Retire every non-active key created before the cutoff
+    pub fn retire_older_than(&mut self, cutoff: DateTime<Utc>) -> Vec<String> {
+        let stale: Vec<String> = self.keys.iter()
+            .filter(|k| k.created_at.is_some_and(|t| t < cutoff))
+            .filter_map(|k| k.kid.clone())
+            .collect();
+
+        stale.into_iter().filter(|kid| self.retire(kid)).collect()
+    }
+
+    pub fn signing_key(&self) -> Option<&JwtKey> {
+        self.active.and_then(|i| self.keys.get(i))
+    }
+
+    /// Find the key for a token header. Tokens without a kid (legacy HS256)
+    /// fall back to the active key.
+    pub fn find(&self, kid: Option<&str>) -> Option<&JwtKey> {
+        match kid {
+            Some(kid) => self.keys.iter().find(|k| k.kid.as_deref() == Some(kid)),
+            None => self.signing_key(),
+        }
+    }
+
+    pub fn kids(&self) -> Vec<String> {
+        self.keys.iter().filter_map(|k| k.kid.clone()).collect()
+    }
+
+    /// Public keys as a JWKS document. Shared secrets are never published.
+    pub fn jwks(&self) -> JwkSet {
+        JwkSet {
+            keys: self.keys.iter().filter_map(|k| k.jwk.clone()).collect(),
+        }
+    }
+
+    /// Load private keys from a directory of `<kid>.ed25519` / `<kid>.p256`
+    /// PKCS#8 files. The kid named in the `active` file signs; without it
+    /// the newest kid does.
+    pub fn load_dir(dir: &Path) -> Result<Self> {
+        let mut entries = Vec::new();
+        let read_dir = fs::read_dir(dir).map_err(|e| JwtError::KeyStore(e.to_string()))?;
+        for entry in read_dir {
+            let path = entry.map_err(|e| JwtError::KeyStore(e.to_string()))?.path();
+            let algorithm = match path.extension().and_then(|e| e.to_str()) {
+                Some("ed25519") => Algorithm::EdDSA,
+                Some("p256") => Algorithm::ES256,
+                _ => continue,
+            };
+            let Some(kid) = path.file_stem().and_then(|s| s.to_str()).map(String::from) else {
+                continue;
+            };
+            entries.push((kid, algorithm, path));
+        }
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut set = Self { keys: Vec::new(), active: None };
+        for (kid, algorithm, path) in entries {
+            let der = SecretKey::new(fs::read(&path).map_err(|e| JwtError::KeyStore(e.to_string()))?);
+            set.add_signing_key(JwtKey::from_pkcs8(&kid, algorithm, &der)?);
+        }
+
+        if let Ok(active_kid) = fs::read_to_string(dir.join(ACTIVE_FILE)) {
+            let active_kid = active_kid.trim();
+            if let Some(index) = set.keys.iter().position(|k| k.kid.as_deref() == Some(active_kid)) {
+                set.active = Some(index);
+            }
+        }
+        Ok(set)
+    }
+
+    /// Persist every private key to the directory and delete files for
+    /// keys that have been retired.
+    pub fn save_dir(&self, dir: &Path) -> Result<()> {
+        fs::create_dir_all(dir).map_err(|e| JwtError::KeyStore(e.to_string()))?;
+
+        let mut kept = Vec::new();
+        for key in &self.keys {
+            let (Some(kid), Some(der)) = (&key.kid, &key.pkcs8) else {
+                continue;
+            };
+            let path = dir.join(format!("{}.{}", kid, file_extension(key.algorithm)));
+            write_private_key(&path, der)?;
+            kept.push(path);
+        }
+
+        if let Some(kid) = self.signing_key().and_then(|k| k.kid.as_ref()) {
+            fs::write(dir.join(ACTIVE_FILE), kid).map_err(|e| JwtError::KeyStore(e.to_string()))?;
+        }
+
+        let read_dir = fs::read_dir(dir).map_err(|e| JwtError::KeyStore(e.to_string()))?;
+        for entry in read_dir.flatten() {
+            let path = entry.path();
+            let is_key = matches!(
+                path.extension().and_then(|e| e.to_str()),
+                Some("ed25519") | Some("p256")
+            );
+            if is_key && !kept.contains(&path) {
+                fs::remove_file(&path).map_err(|e| JwtError::KeyStore(e.to_string()))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Load keys from a directory, generating a first key if it is empty
+    pub fn load_or_create(dir: &Path, algorithm: Algorithm) -> Result<Self> {
+        let set = if dir.exists() { Self::load_dir(dir)? } else { Self { keys: Vec::new(), active: None } };
+        if set.signing_key().is_some() {
+            return Ok(set);
+        }
+
+        let set = Self::generate(algorithm)?;
+        set.save_dir(dir)?;
+        Ok(set)
+    }
+}
+
+/// Parse a JWT algorithm name accepted in configuration
+pub fn parse_algorithm(name: &str) -> Result<Algorithm> {
+    match name.to_uppercase().as_str() {
+        "EDDSA" | "ED25519" => Ok(Algorithm::EdDSA),
+        "ES256" => Ok(Algorithm::ES256),
+        "HS256" => Ok(Algorithm::HS256),
+        _ => Err(JwtError::KeyStore(format!("Unsupported JWT algorithm: {}", name))),
+    }
+}
+
+fn signature_algorithm(algorithm: Algorithm) -> Result<SignatureAlgorithm> {
+    match algorithm {
+        Algorithm::EdDSA => Ok(SignatureAlgorithm::Ed25519),
+        Algorithm::ES256 => Ok(SignatureAlgorithm::EcdsaP256),
+        other => Err(JwtError::KeyStore(format!("{:?} is not an asymmetric algorithm", other))),
+    }
+}
+
+fn file_extension(algorithm: Algorithm) -> &'static str {
+    match algorithm {
+        Algorithm::EdDSA => "ed25519",
+        _ => "p256",
+    }
+}
+
+fn kid_timestamp(kid: &str) -> Option<DateTime<Utc>> {
+    let stamp = kid.split('-').next()?;
+    NaiveDateTime::parse_from_str(stamp, KID_TIME_FORMAT)
+        .ok()
+        .map(|t| t.and_utc())
+}
+
+fn public_jwk(kid: &str, algorithm: Algorithm, public_key: &[u8]) -> Result<Jwk> {
+    let common = CommonParameters {
+        public_key_use: Some(PublicKeyUse::Signature),
+        key_id: Some(kid.to_string()),
+        ..Default::default()
+    };
+
+    let (key_algorithm, parameters) = match algorithm {
+        Algorithm::EdDSA => (
+            KeyAlgorithm::EdDSA,
+            AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                key_type: OctetKeyPairType::OctetKeyPair,
+                curve: EllipticCurve::Ed25519,
+                x: BASE64URL.encode(public_key),
+            }),
+        ),
+        Algorithm::ES256 => {
+            // Uncompressed SEC1 point: 0x04 || x || y
+            if public_key.len() != 65 || public_key[0] != 0x04 {
+                return Err(JwtError::KeyStore("Invalid P-256 public key".into()));
+            }
+            (
+                KeyAlgorithm::ES256,
+                AlgorithmParameters::EllipticCurve(EllipticCurveKeyParameters {
+                    key_type: EllipticCurveKeyType::EC,
+                    curve: EllipticCurve::P256,
+                    x: BASE64URL.encode(&public_key[1..33]),
+                    y: BASE64URL.encode(&public_key[33..]),
+                }),
+            )
+        }
+        other => return Err(JwtError::KeyStore(format!("{:?} has no public key", other))),
+    };
+
+    Ok(Jwk {
+        common: CommonParameters { key_algorithm: Some(key_algorithm), ..common },
+        algorithm: parameters,
+    })
+}
+
+#[cfg(unix)]
+fn write_private_key(path: &Path, der: &[u8]) -> Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+        .map_err(|e| JwtError::KeyStore(e.to_string()))?;
+    file.write_all(der).map_err(|e| JwtError::KeyStore(e.to_string()))
+}
+
+#[cfg(not(unix))]
+fn write_private_key(path: &Path, der: &[u8]) -> Result<()> {
+    fs::write(path, der).map_err(|e| JwtError::KeyStore(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_jwks_excludes_hmac() {
+        let set = JwtKeySet::hmac(b"secret");
+        assert!(set.jwks().keys.is_empty());
+    }
+
+    #[test]
+    fn test_rotation_keeps_old_key() {
+        let mut set = JwtKeySet::generate(Algorithm::EdDSA).unwrap();
+        let old_kid = set.signing_key().unwrap().kid.clone().unwrap();
+        let new_kid = set.rotate().unwrap();
+
+        assert_ne!(old_kid, new_kid);
+        assert_eq!(set.signing_key().unwrap().kid.as_deref(), Some(new_kid.as_str()));
+        assert!(set.find(Some(&old_kid)).is_some());
+        assert_eq!(set.jwks().keys.len(), 2);
+
+        assert!(!set.retire(&new_kid));
+        assert!(set.retire(&old_kid));
+        assert!(set.find(Some(&old_kid)).is_none());
+    }
+
+    #[test]
+    fn test_save_and_load_dir() {
+        let dir = std::env::temp_dir().join(format!("hedtronix-jwt-{}", uuid::Uuid::new_v4()));
+        let mut set = JwtKeySet::generate(Algorithm::ES256).unwrap();
+        set.rotate().unwrap();
+        set.save_dir(&dir).unwrap();
+
+        let loaded = JwtKeySet::load_dir(&dir).unwrap();
+        let mut expected = set.kids();
+        expected.sort();
+        assert_eq!(loaded.kids(), expected);
+        assert_eq!(loaded.signing_key().unwrap().kid, set.signing_key().unwrap().kid);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -2,12 +2,40 @@
 //!
 //! JWT-based authentication with device management for offline-first operation.
 
+pub mod access;
+pub mod device_lock;
+pub mod emergency;
 pub mod jwt;
+pub mod keys;
+pub mod lockout;
+pub mod monitor;
+pub mod mfa;
+pub mod offline;
+pub mod password;
 pub mod session;
 pub mod middleware;
 pub mod permissions;
+pub mod redaction;
 
+#[allow(ambiguous_glob_reexports)]
 pub use jwt::*;
+pub use access::*;
+#[allow(ambiguous_glob_reexports)]
+pub use device_lock::*;
+#[allow(ambiguous_glob_reexports)]
+pub use emergency::*;
+#[allow(ambiguous_glob_reexports)]
 pub use session::*;
+pub use keys::*;
+#[allow(ambiguous_glob_reexports)]
+pub use mfa::*;
+pub use offline::*;
+pub use lockout::*;
+#[allow(ambiguous_glob_reexports)]
+pub use monitor::*;
+pub use password::*;
+pub use redaction::*;
+pub use jsonwebtoken::jwk::{Jwk, JwkSet};
 pub use middleware::*;
+#[allow(ambiguous_glob_reexports)]
 pub use permissions::*;
@@ -0,0 +1,106 @@
+//! Login throttling and account lockout policy
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Limits on failed login attempts, per account and per client IP
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoginThrottlePolicy {
+    /// Failed attempts before an account is locked
+    pub max_account_failures: u32,
+
+    /// How long a locked account stays locked
+    pub lockout_minutes: i64,
+
+    /// Failed attempts from one IP within the window before it is blocked
+    pub max_ip_failures: u32,
+
+    /// Window over which per-IP failures are counted
+    pub ip_window_minutes: i64,
+
+    /// Delay after the first failure; doubles with each further failure
+    pub base_delay_ms: i64,
+
+    /// Upper bound on the progressive delay
+    pub max_delay_ms: i64,
+}
+
+impl Default for LoginThrottlePolicy {
+    fn default() -> Self {
+        Self {
+            max_account_failures: 5,
+            lockout_minutes: 15,
+            max_ip_failures: 20,
+            ip_window_minutes: 15,
+            base_delay_ms: 500,
+            max_delay_ms: 30_000,
+        }
+    }
+}
+
+impl LoginThrottlePolicy {
+    /// Load a policy from a JSON file
+    pub fn from_file(path: &std::path::Path) -> std::result::Result<Self, String> {
+        let json = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        serde_json::from_str(&json).map_err(|e| e.to_string())
+    }
+
+    /// Wait required after `failures` consecutive failures
+    pub fn delay_after(&self, failures: u32) -> Duration {
+        if failures == 0 {
+            return Duration::zero();
+        }
+
+        let factor = 1i64.checked_shl(failures.saturating_sub(1).min(31)).unwrap_or(i64::MAX);
+        Duration::milliseconds(self.base_delay_ms.saturating_mul(factor).min(self.max_delay_ms))
+    }
+
+    /// When the next attempt is allowed, given a failure count and the time
+    /// of the latest failure. `None` means an attempt may be made now.
+    pub fn retry_at(&self, failures: u32, last_failure: Option<DateTime<Utc>>) -> Option<DateTime<Utc>> {
+        let retry_at = last_failure? + self.delay_after(failures);
+        (retry_at > Utc::now()).then_some(retry_at)
+    }
+
+    /// Lockout expiry once an account reaches `failures`, if it should lock
+    pub fn lock_until(&self, failures: u32) -> Option<DateTime<Utc>> {
+        (failures >= self.max_account_failures)
+            .then(|| Utc::now() + Duration::minutes(self.lockout_minutes))
+    }
+
+    pub fn ip_window_start(&self) -> DateTime<Utc> {
+        Utc::now() - Duration::minutes(self.ip_window_minutes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progressive_delay() {
+        let policy = LoginThrottlePolicy::default();
+
+        assert_eq!(policy.delay_after(0), Duration::zero());
+        assert_eq!(policy.delay_after(1), Duration::milliseconds(500));
+        assert_eq!(policy.delay_after(3), Duration::milliseconds(2000));
+        assert_eq!(policy.delay_after(40), Duration::milliseconds(30_000));
+    }
+
+    #[test]
+    fn test_lockout_threshold() {
+        let policy = LoginThrottlePolicy::default();
+
+        assert!(policy.lock_until(4).is_none());
+        assert!(policy.lock_until(5).is_some_and(|t| t > Utc::now()));
+    }
+
+    #[test]
+    fn test_retry_at() {
+        let policy = LoginThrottlePolicy::default();
+
+        assert!(policy.retry_at(3, None).is_none());
+        assert!(policy.retry_at(3, Some(Utc::now())).is_some());
+        assert!(policy.retry_at(3, Some(Utc::now() - Duration::minutes(1))).is_none());
+    }
+}
@@ -0,0 +1,336 @@
+//! Multi-factor authentication: role policy, recovery codes and WebAuthn
+//! assertion verification
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use hedtronix_core::{UserRole, WebauthnCredential};
+use hedtronix_crypto::hashing::sha256_hash;
+use hedtronix_crypto::keys::generate_random_bytes;
+use hedtronix_crypto::otp::base32_encode;
+use hedtronix_crypto::signing::{verify_der_signature, SignatureAlgorithm};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// MFA error types
+#[derive(Error, Debug)]
+pub enum MfaError {
+    #[error("Random generation failed: {0}")]
+    Generation(String),
+
+    #[error("WebAuthn verification failed: {0}")]
+    WebAuthn(String),
+}
+
+/// Result type for MFA operations
+pub type Result<T> = std::result::Result<T, MfaError>;
+
+/// COSE algorithm identifier for ECDSA P-256 with SHA-256
+pub const COSE_ES256: i64 = -7;
+
+/// COSE algorithm identifier for Ed25519
+pub const COSE_EDDSA: i64 = -8;
+
+/// Number of recovery codes issued per enrollment
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+/// SPKI DER header preceding the raw uncompressed P-256 point
+const P256_SPKI_PREFIX: [u8; 26] = [
+    0x30, 0x59, 0x30, 0x13, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01,
+    0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07, 0x03, 0x42, 0x00,
+];
+
+/// SPKI DER header preceding the raw Ed25519 public key
+const ED25519_SPKI_PREFIX: [u8; 12] = [
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+
+/// Authenticator data flag: user present
+const FLAG_USER_PRESENT: u8 = 0x01;
+
+/// Which roles must pass a second factor, and WebAuthn relying party settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MfaPolicy {
+    /// Roles that cannot complete login without a second factor
+    pub required_roles: Vec<UserRole>,
+
+    /// Issuer label shown in authenticator apps
+    pub totp_issuer: String,
+
+    /// WebAuthn relying party ID (the site's domain)
+    pub rp_id: String,
+
+    /// Origin WebAuthn client data must come from
+    pub origin: String,
+}
+
+impl Default for MfaPolicy {
+    fn default() -> Self {
+        // Every staff role can read PHI
+        Self {
+            required_roles: vec![
+                UserRole::Physician,
+                UserRole::Nurse,
+                UserRole::Receptionist,
+                UserRole::Billing,
+                UserRole::Admin,
+            ],
+            totp_issuer: "HEDTRONIX".to_string(),
+            rp_id: "localhost".to_string(),
+            origin: "http://localhost:5173".to_string(),
+        }
+    }
+}
+
+impl MfaPolicy {
+    /// Load a policy from a JSON file
+    pub fn from_file(path: &std::path::Path) -> std::result::Result<Self, String> {
+        let json = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        serde_json::from_str(&json).map_err(|e| e.to_string())
+    }
+
+    pub fn requires_mfa(&self, role: UserRole) -> bool {
+        self.required_roles.contains(&role)
+    }
+}
+
+/// Generate a fresh set of recovery codes, formatted `XXXXX-XXXXX`
+pub fn generate_recovery_codes() -> Result<Vec<String>> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let bytes = generate_random_bytes(6).map_err(|e| MfaError::Generation(e.to_string()))?;
+            let code = base32_encode(&bytes);
+            Ok(format!("{}-{}", &code[..5], &code[5..]))
+        })
+        .collect()
+}
+
+/// Canonical form of a recovery code as typed by a user, used for hashing
+pub fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+/// Random WebAuthn challenge (base64url)
+pub fn generate_challenge() -> Result<String> {
+    let bytes = generate_random_bytes(32).map_err(|e| MfaError::Generation(e.to_string()))?;
+    Ok(URL_SAFE_NO_PAD.encode(bytes))
+}
+
+fn decode_b64url(value: &str, field: &str) -> Result<Vec<u8>> {
+    URL_SAFE_NO_PAD
+        .decode(value.trim_end_matches('='))
+        .map_err(|_| MfaError::WebAuthn(format!("{} is not valid base64url", field)))
+}
+
+fn signature_algorithm(cose_algorithm: i64) -> Result<SignatureAlgorithm> {
+    match cose_algorithm {
+        COSE_ES256 => Ok(SignatureAlgorithm::EcdsaP256),
+        COSE_EDDSA => Ok(SignatureAlgorithm::Ed25519),
+        other => Err(MfaError::WebAuthn(format!("Unsupported algorithm {}", other))),
+    }
+}
+
+/// Extract the raw public key from the SPKI DER returned by
+/// `AuthenticatorAttestationResponse.getPublicKey()`
+pub fn public_key_from_spki(cose_algorithm: i64, spki: &[u8]) -> Result<Vec<u8>> {
+    let prefix: &[u8] = match signature_algorithm(cose_algorithm)? {
+        SignatureAlgorithm::EcdsaP256 => &P256_SPKI_PREFIX,
+        SignatureAlgorithm::Ed25519 => &ED25519_SPKI_PREFIX,
+    };
+
+    spki.strip_prefix(prefix)
+        .filter(|key| !key.is_empty())
+        .map(|key| key.to_vec())
+        .ok_or_else(|| MfaError::WebAuthn("Public key does not match algorithm".to_string()))
+}
+
+#[derive(Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    kind: String,
+    challenge: String,
+    origin: String,
+}
+
+/// Check the client data type, challenge and origin
+pub fn verify_client_data(
+    client_data_json: &[u8],
+    expected_type: &str,
+    expected_challenge: &str,
+    policy: &MfaPolicy,
+) -> Result<()> {
+    let client_data: ClientData = serde_json::from_slice(client_data_json)
+        .map_err(|e| MfaError::WebAuthn(format!("Invalid client data: {}", e)))?;
+
+    if client_data.kind != expected_type {
+        return Err(MfaError::WebAuthn(format!("Unexpected ceremony type {}", client_data.kind)));
+    }
+    if client_data.challenge.trim_end_matches('=') != expected_challenge.trim_end_matches('=') {
+        return Err(MfaError::WebAuthn("Challenge mismatch".to_string()));
+    }
+    if client_data.origin != policy.origin {
+        return Err(MfaError::WebAuthn(format!("Unexpected origin {}", client_data.origin)));
+    }
+
+    Ok(())
+}
+
+/// WebAuthn assertion as posted by the browser (all fields base64url)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebauthnAssertion {
+    pub credential_id: String,
+    pub authenticator_data: String,
+    pub client_data_json: String,
+    pub signature: String,
+}
+
+/// Verify an assertion against a stored credential, returning the
+/// authenticator's new signature counter
+pub fn verify_assertion(
+    credential: &WebauthnCredential,
+    assertion: &WebauthnAssertion,
+    challenge: &str,
+    policy: &MfaPolicy,
+) -> Result<i64> {
+    let auth_data = decode_b64url(&assertion.authenticator_data, "authenticator_data")?;
+    let client_data_json = decode_b64url(&assertion.client_data_json, "client_data_json")?;
+    let sig = decode_b64url(&assertion.signature, "signature")?;
+
+    verify_client_data(&client_data_json, "webauthn.get", challenge, policy)?;
+
+    // rpIdHash (32) | flags (1) | signCount (4)
+    if auth_data.len() < 37 {
+        return Err(MfaError::WebAuthn("Authenticator data too short".to_string()));
+    }
+    if auth_data[..32] != sha256_hash(policy.rp_id.as_bytes())[..] {
+        return Err(MfaError::WebAuthn("Relying party mismatch".to_string()));
+    }
+    if auth_data[32] & FLAG_USER_PRESENT == 0 {
+        return Err(MfaError::WebAuthn("User presence not asserted".to_string()));
+    }
+
+    let sign_count = u32::from_be_bytes([auth_data[33], auth_data[34], auth_data[35], auth_data[36]]) as i64;
+    if sign_count != 0 && sign_count <= credential.sign_count {
+        return Err(MfaError::WebAuthn("Signature counter did not increase".to_string()));
+    }
+
+    let spki = decode_b64url(&credential.public_key, "public_key")?;
+    let public_key = public_key_from_spki(credential.algorithm, &spki)?;
+
+    let mut message = auth_data;
+    message.extend_from_slice(&sha256_hash(&client_data_json));
+
+    if !verify_der_signature(signature_algorithm(credential.algorithm)?, &public_key, &message, &sig) {
+        return Err(MfaError::WebAuthn("Invalid signature".to_string()));
+    }
+
+    Ok(sign_count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hedtronix_core::Id;
+    use hedtronix_crypto::signing::SigningKeyPair;
+
+    struct Authenticator {
+        pair: SigningKeyPair,
+        credential: WebauthnCredential,
+    }
+
+    impl Authenticator {
+        fn new() -> Self {
+            let pair = SigningKeyPair::generate(SignatureAlgorithm::Ed25519).unwrap();
+            let mut spki = ED25519_SPKI_PREFIX.to_vec();
+            spki.extend_from_slice(pair.public_key());
+
+            let credential = WebauthnCredential {
+                id: "cred-1".to_string(),
+                user_id: Id::new_v4(),
+                public_key: URL_SAFE_NO_PAD.encode(spki),
+                algorithm: COSE_EDDSA,
+                sign_count: 0,
+                name: None,
+                created_at: chrono::Utc::now(),
+                last_used_at: None,
+            };
+
+            Self { pair, credential }
+        }
+
+        fn assert(&self, policy: &MfaPolicy, challenge: &str, origin: &str, counter: u32) -> WebauthnAssertion {
+            let client_data = serde_json::json!({
+                "type": "webauthn.get",
+                "challenge": challenge,
+                "origin": origin,
+            })
+            .to_string();
+
+            let mut auth_data = sha256_hash(policy.rp_id.as_bytes());
+            auth_data.push(FLAG_USER_PRESENT);
+            auth_data.extend_from_slice(&counter.to_be_bytes());
+
+            let mut message = auth_data.clone();
+            message.extend_from_slice(&sha256_hash(client_data.as_bytes()));
+            let sig = self.pair.sign(&message).unwrap();
+
+            WebauthnAssertion {
+                credential_id: self.credential.id.clone(),
+                authenticator_data: URL_SAFE_NO_PAD.encode(auth_data),
+                client_data_json: URL_SAFE_NO_PAD.encode(client_data),
+                signature: URL_SAFE_NO_PAD.encode(sig),
+            }
+        }
+    }
+
+    #[test]
+    fn test_verify_assertion() {
+        let policy = MfaPolicy::default();
+        let authenticator = Authenticator::new();
+        let challenge = generate_challenge().unwrap();
+
+        let assertion = authenticator.assert(&policy, &challenge, &policy.origin, 5);
+        assert_eq!(verify_assertion(&authenticator.credential, &assertion, &challenge, &policy).unwrap(), 5);
+
+        // Wrong challenge
+        let other = generate_challenge().unwrap();
+        assert!(verify_assertion(&authenticator.credential, &assertion, &other, &policy).is_err());
+
+        // Wrong origin
+        let phished = authenticator.assert(&policy, &challenge, "https://evil.example", 6);
+        assert!(verify_assertion(&authenticator.credential, &phished, &challenge, &policy).is_err());
+
+        // Tampered signature
+        let mut tampered = authenticator.assert(&policy, &challenge, &policy.origin, 7);
+        tampered.signature = authenticator.assert(&policy, &other, &policy.origin, 7).signature;
+        assert!(verify_assertion(&authenticator.credential, &tampered, &challenge, &policy).is_err());
+    }
+
+    #[test]
+    fn test_counter_regression_rejected() {
+        let policy = MfaPolicy::default();
+        let mut authenticator = Authenticator::new();
+        authenticator.credential.sign_count = 10;
+        let challenge = generate_challenge().unwrap();
+
+        let assertion = authenticator.assert(&policy, &challenge, &policy.origin, 10);
+        assert!(verify_assertion(&authenticator.credential, &assertion, &challenge, &policy).is_err());
+    }
+
+    #[test]
+    fn test_recovery_codes() {
+        let codes = generate_recovery_codes().unwrap();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        assert_eq!(codes[0].len(), 11);
+        assert_eq!(normalize_recovery_code(&codes[0].to_lowercase()), codes[0].replace('-', ""));
+    }
+
+    #[test]
+    fn test_default_policy() {
+        let policy = MfaPolicy::default();
+        assert!(policy.requires_mfa(UserRole::Physician));
+        assert!(policy.requires_mfa(UserRole::Billing));
+        assert!(!policy.requires_mfa(UserRole::Patient));
+    }
+}
@@ -6,20 +6,104 @@ use axum::{
     middleware::Next,
     response::{IntoResponse, Response},
 };
-use hedtronix_core::UserRole;
 
-use crate::jwt::Claims;
+use std::sync::Arc;
+
+use crate::device_lock::DeviceLock;
+use crate::emergency::EmergencyAccessPolicy;
+use crate::jwt::{Claims, JwtManager};
+use crate::lockout::LoginThrottlePolicy;
+use crate::mfa::MfaPolicy;
+use crate::monitor::AccessMonitorPolicy;
+use crate::offline::OfflineTokenPolicy;
+use crate::password::PasswordPolicy;
 use crate::permissions::PermissionChecker;
+use crate::redaction::RedactionPolicy;
 
 /// Authentication state for middleware
 #[derive(Clone)]
 pub struct AuthState {
-    pub jwt_secret: Vec<u8>,
+    pub jwt_manager: Arc<JwtManager>,
+    pub offline_policy: Arc<OfflineTokenPolicy>,
+    pub mfa_policy: Arc<MfaPolicy>,
+    pub throttle_policy: Arc<LoginThrottlePolicy>,
+    pub password_policy: Arc<PasswordPolicy>,
+    pub permissions: Arc<PermissionChecker>,
+    pub emergency_policy: Arc<EmergencyAccessPolicy>,
+    pub redaction_policy: Arc<RedactionPolicy>,
+    pub monitor_policy: Arc<AccessMonitorPolicy>,
+    /// Set when the data keys are locked behind users' passphrases
+    pub device_lock: Option<Arc<DeviceLock>>,
 }
 
 impl AuthState {
-    pub fn new(jwt_secret: Vec<u8>) -> Self {
-        Self { jwt_secret }
+    pub fn new(jwt_manager: JwtManager) -> Self {
+        Self {
+            jwt_manager: Arc::new(jwt_manager),
+            offline_policy: Arc::new(OfflineTokenPolicy::default()),
+            mfa_policy: Arc::new(MfaPolicy::default()),
+            throttle_policy: Arc::new(LoginThrottlePolicy::default()),
+            password_policy: Arc::new(PasswordPolicy::default()),
+            permissions: Arc::new(PermissionChecker::built_in()),
+            emergency_policy: Arc::new(EmergencyAccessPolicy::default()),
+            redaction_policy: Arc::new(RedactionPolicy::default()),
+            monitor_policy: Arc::new(AccessMonitorPolicy::default()),
+            device_lock: None,
+        }
+    }
+
+    /// Replace the default offline token policy
+    pub fn with_offline_policy(mut self, policy: OfflineTokenPolicy) -> Self {
+        self.offline_policy = Arc::new(policy);
+        self
+    }
+
+    /// Replace the default MFA policy
+    pub fn with_mfa_policy(mut self, policy: MfaPolicy) -> Self {
+        self.mfa_policy = Arc::new(policy);
+        self
+    }
+
+    /// Use a database-backed permission checker
+    pub fn with_permission_checker(mut self, checker: PermissionChecker) -> Self {
+        self.permissions = Arc::new(checker);
+        self
+    }
+
+    /// Replace the default lockout and throttling policy
+    pub fn with_throttle_policy(mut self, policy: LoginThrottlePolicy) -> Self {
+        self.throttle_policy = Arc::new(policy);
+        self
+    }
+
+    /// Replace the default password policy
+    pub fn with_password_policy(mut self, policy: PasswordPolicy) -> Self {
+        self.password_policy = Arc::new(policy);
+        self
+    }
+
+    /// Replace the default emergency access policy
+    pub fn with_emergency_policy(mut self, policy: EmergencyAccessPolicy) -> Self {
+        self.emergency_policy = Arc::new(policy);
+        self
+    }
+
+    /// Replace the default field redaction rules
+    pub fn with_redaction_policy(mut self, policy: RedactionPolicy) -> Self {
+        self.redaction_policy = Arc::new(policy);
+        self
+    }
+
+    /// Replace the default access monitoring rules
+    pub fn with_monitor_policy(mut self, policy: AccessMonitorPolicy) -> Self {
+        self.monitor_policy = Arc::new(policy);
+        self
+    }
+
+    /// Lock the data keys behind users' passphrases
+    pub fn with_device_lock(mut self, device_lock: DeviceLock) -> Self {
+        self.device_lock = Some(Arc::new(device_lock));
+        self
     }
 }
 
@@ -39,18 +123,33 @@ pub async fn auth_middleware(
         _ => return Err(StatusCode::UNAUTHORIZED),
     };
 
-    let jwt_manager = crate::jwt::JwtManager::new(&state.jwt_secret);
-    
-    match jwt_manager.validate_token(token) {
+    match state.jwt_manager.validate_token(token) {
         Ok(claims) => {
             // Store claims in request extensions for later use
             request.extensions_mut().insert(claims);
+            request.extensions_mut().insert(state.permissions.clone());
+            // Authenticated requests keep an unlocked device from idling out
+            if let Some(device_lock) = &state.device_lock {
+                let _ = device_lock.touch();
+            }
             Ok(next.run(request).await)
         }
         Err(_) => Err(StatusCode::UNAUTHORIZED),
     }
 }
 
+/// Refuse requests needing the data keys while the device is locked
+pub async fn device_lock_middleware(
+    State(state): State<AuthState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    match &state.device_lock {
+        Some(device_lock) if !device_lock.is_unlocked() => Err(StatusCode::LOCKED),
+        _ => Ok(next.run(request).await),
+    }
+}
+
 /// Permission checking middleware generator
 pub fn require_permission(
     resource: &'static str,
@@ -63,9 +162,12 @@ pub fn require_permission(
                 .get::<Claims>()
                 .ok_or(StatusCode::UNAUTHORIZED)?;
 
-            let role = claims.user_role();
+            let checker = request
+                .extensions()
+                .get::<Arc<PermissionChecker>>()
+                .ok_or(StatusCode::INTERNAL_SERVER_ERROR)?;
             
-            if PermissionChecker::has_permission(role, resource, action) {
+            if checker.authorize(claims, resource, action) {
                 Ok(next.run(request).await)
             } else {
                 Err(StatusCode::FORBIDDEN)
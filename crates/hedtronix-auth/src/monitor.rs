@@ -0,0 +1,787 @@
+//! Anomalous access detection
+//!
+//! New audit entries are scanned in chain order against a set of rules for
+//! suspicious access: reading far more charts than usual, opening a VIP's
+//! or a namesake's chart, bursts of reads outside business hours and access
+//! from a device the user does not own. Matches are raised as alerts into a
+//! queue for the privacy office. Each rule raises at most one alert per user
+//! (and patient) per cooldown period.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use chrono::{Duration, NaiveDate, Timelike};
+use hedtronix_crypto::Keyring;
+use hedtronix_core::{
+    AccessAlert, AccessAlertFilters, AccessAlertRule, AccessAlertSeverity, AccessAlertStatus,
+    AuditEventType, AuditLog, AuditLogFilters, Id, Patient, ReviewAccessAlert, Timestamp, User,
+    VipPatient,
+};
+use hedtronix_db::{
+    AccessAlertRepository, AuditRepository, CareTeamRepository, Database, DeviceRepository,
+    PatientRepository, SyncRepository, UserRepository,
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::jwt::Claims;
+use crate::permissions::PermissionChecker;
+
+/// Sync metadata key holding the last audit sequence number scanned
+pub const ACCESS_MONITOR_CURSOR_KEY: &str = "access_monitor_sequence";
+
+/// Access monitoring errors
+#[derive(Error, Debug)]
+pub enum AccessMonitorError {
+    #[error("Not permitted: {0}")]
+    NotPermitted(String),
+
+    #[error("Patient not found")]
+    PatientNotFound,
+
+    #[error("Access alert not found")]
+    AlertNotFound,
+
+    #[error("Access alert has already been reviewed")]
+    AlreadyReviewed,
+
+    #[error("Invalid review: {0}")]
+    InvalidReview(String),
+
+    #[error("Database error: {0}")]
+    Database(String),
+}
+
+/// Result type for access monitoring operations
+pub type Result<T> = std::result::Result<T, AccessMonitorError>;
+
+impl From<hedtronix_db::DbError> for AccessMonitorError {
+    fn from(e: hedtronix_db::DbError) -> Self {
+        AccessMonitorError::Database(e.to_string())
+    }
+}
+
+/// Thresholds for the access monitoring rules
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccessMonitorPolicy {
+    /// Rules that raise alerts
+    pub enabled_rules: Vec<AccessAlertRule>,
+
+    /// Days of history the chart access baseline is taken over
+    pub baseline_days: i64,
+
+    /// Days of activity needed before the baseline is trusted
+    pub min_baseline_days: usize,
+
+    /// Alert when a day's charts exceed the baseline by this factor
+    pub volume_multiplier: f64,
+
+    /// Never alert on volume below this many charts a day. Users without a
+    /// baseline are held to this times `volume_multiplier`.
+    pub min_daily_charts: i64,
+
+    /// Business hours in local time, `[start, end)`
+    pub business_hours_start: u32,
+    pub business_hours_end: u32,
+
+    /// Offset of local time from UTC
+    pub utc_offset_minutes: i32,
+
+    /// Reads within this window outside business hours count as a burst...
+    pub burst_window_minutes: i64,
+
+    /// ...once there are this many of them
+    pub burst_threshold: i64,
+
+    /// A rule raises at most one alert per user (and patient) in this period
+    pub alert_cooldown_hours: i64,
+
+    /// Audit entries scanned per pass
+    pub batch_size: u32,
+}
+
+impl Default for AccessMonitorPolicy {
+    fn default() -> Self {
+        Self {
+            enabled_rules: vec![
+                AccessAlertRule::ExcessiveChartAccess,
+                AccessAlertRule::VipPatientAccess,
+                AccessAlertRule::SameSurname,
+                AccessAlertRule::OffHoursBurst,
+                AccessAlertRule::UnregisteredDevice,
+            ],
+            baseline_days: 30,
+            min_baseline_days: 5,
+            volume_multiplier: 3.0,
+            min_daily_charts: 30,
+            business_hours_start: 7,
+            business_hours_end: 19,
+            utc_offset_minutes: 0,
+            burst_window_minutes: 15,
+            burst_threshold: 10,
+            alert_cooldown_hours: 24,
+            batch_size: 1000,
+        }
+    }
+}
+
+impl AccessMonitorPolicy {
+    /// Load a policy from a JSON file
+    pub fn from_file(path: &std::path::Path) -> std::result::Result<Self, String> {
+        let json = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        serde_json::from_str(&json).map_err(|e| e.to_string())
+    }
+
+    pub fn enabled(&self, rule: AccessAlertRule) -> bool {
+        self.enabled_rules.contains(&rule)
+    }
+
+    /// Whether a time falls outside local business hours
+    pub fn is_off_hours(&self, timestamp: Timestamp) -> bool {
+        let hour = (timestamp + Duration::minutes(self.utc_offset_minutes as i64)).hour();
+        let (start, end) = (self.business_hours_start, self.business_hours_end);
+        if start <= end {
+            hour < start || hour >= end
+        } else {
+            // Business hours that wrap past midnight
+            hour >= end && hour < start
+        }
+    }
+
+    /// Charts a day above which a user with this history is flagged
+    pub fn volume_threshold(&self, history: &[(NaiveDate, i64)]) -> i64 {
+        if history.len() < self.min_baseline_days {
+            return (self.min_daily_charts as f64 * self.volume_multiplier).ceil() as i64;
+        }
+        let average = history.iter().map(|(_, n)| *n).sum::<i64>() as f64 / history.len() as f64;
+        ((average * self.volume_multiplier).ceil() as i64).max(self.min_daily_charts)
+    }
+}
+
+/// Outcome of one scan of the audit trail
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccessScanReport {
+    pub entries_scanned: usize,
+    /// Last audit sequence number scanned
+    pub scanned_through: i64,
+    pub alerts: Vec<AccessAlert>,
+}
+
+/// One page of the alert queue
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccessAlertPage {
+    pub alerts: Vec<AccessAlert>,
+    pub total: i64,
+}
+
+/// Users and patients looked up during a scan
+#[derive(Default)]
+struct ScanCache {
+    users: HashMap<Id, Option<User>>,
+    patients: HashMap<Id, Option<Patient>>,
+}
+
+/// Access monitoring service
+pub struct AccessMonitor {
+    db: Database,
+    keyring: Arc<Keyring>,
+    policy: Arc<AccessMonitorPolicy>,
+    permissions: Arc<PermissionChecker>,
+}
+
+impl AccessMonitor {
+    /// `keyring` decrypts patient names for the surname rule
+    pub fn new(db: Database, keyring: Arc<Keyring>) -> Self {
+        Self {
+            permissions: Arc::new(PermissionChecker::new(db.clone())),
+            db,
+            keyring,
+            policy: Arc::new(AccessMonitorPolicy::default()),
+        }
+    }
+
+    /// Replace the default monitoring policy
+    pub fn with_policy(mut self, policy: Arc<AccessMonitorPolicy>) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Share the server's permission checker
+    pub fn with_permission_checker(mut self, permissions: Arc<PermissionChecker>) -> Self {
+        self.permissions = permissions;
+        self
+    }
+
+    /// Scan audit entries appended since the last scan and raise alerts
+    pub fn scan(&self) -> Result<AccessScanReport> {
+        let sync = SyncRepository::new(self.db.clone());
+        let cursor: i64 = sync
+            .get_metadata(ACCESS_MONITOR_CURSOR_KEY)?
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0);
+
+        let entries = AuditRepository::new(self.db.clone())
+            .find_range(cursor + 1, self.policy.batch_size)?;
+        let Some(last) = entries.last().map(|e| e.sequence) else {
+            return Ok(AccessScanReport { entries_scanned: 0, scanned_through: cursor, alerts: Vec::new() });
+        };
+
+        let mut cache = ScanCache::default();
+        let mut alerts = Vec::new();
+        // Chart reads per user and day, for the volume rule
+        let mut chart_reads: BTreeMap<(Id, NaiveDate), Vec<Id>> = BTreeMap::new();
+
+        for entry in &entries {
+            let Some(user_id) = entry.user_id else {
+                continue;
+            };
+            if !matches!(
+                entry.event_type,
+                AuditEventType::Read
+                    | AuditEventType::Create
+                    | AuditEventType::Update
+                    | AuditEventType::Delete
+                    | AuditEventType::Export
+            ) {
+                continue;
+            }
+
+            self.check_device(entry, user_id, &mut alerts)?;
+
+            let patient_id = AccessAlertRepository::new(self.db.clone())
+                .patient_for_entity(&entry.entity_type, &entry.entity_id)?;
+            if let Some(patient_id) = patient_id {
+                self.check_patient(entry, user_id, patient_id, &mut cache, &mut alerts)?;
+            }
+
+            if entry.event_type == AuditEventType::Read {
+                if self.policy.is_off_hours(entry.timestamp) {
+                    self.check_burst(entry, user_id, &mut alerts)?;
+                }
+                if entry.entity_type == "Patient" {
+                    chart_reads
+                        .entry((user_id, entry.timestamp.date_naive()))
+                        .or_default()
+                        .push(entry.id);
+                }
+            }
+        }
+
+        for ((user_id, day), ids) in chart_reads {
+            self.check_volume(user_id, day, ids, &mut alerts)?;
+        }
+
+        sync.set_metadata(ACCESS_MONITOR_CURSOR_KEY, &last.to_string())?;
+
+        Ok(AccessScanReport { entries_scanned: entries.len(), scanned_through: last, alerts })
+    }
+
+    /// Access from a device that is unknown, revoked or someone else's
+    fn check_device(&self, entry: &AuditLog, user_id: Id, alerts: &mut Vec<AccessAlert>) -> Result<()> {
+        if !self.policy.enabled(AccessAlertRule::UnregisteredDevice) {
+            return Ok(());
+        }
+
+        let description = if let Some(device_id) = entry.device_id {
+            match DeviceRepository::new(self.db.clone()).find_by_id(device_id)? {
+                Some(device) if device.user_id != user_id => {
+                    format!("Accessed {} from device {} registered to another user", entry.entity_type, device_id)
+                }
+                Some(device) if !device.is_valid() => {
+                    format!("Accessed {} from revoked device {}", entry.entity_type, device_id)
+                }
+                Some(_) => return Ok(()),
+                None => format!("Accessed {} from unknown device {}", entry.entity_type, device_id),
+            }
+        } else if let Some(device_id) = entry.changes.get("unregistered_device_id").and_then(|v| v.as_str()) {
+            format!("Accessed {} from unregistered device {}", entry.entity_type, device_id)
+        } else {
+            return Ok(());
+        };
+
+        self.raise(
+            AccessAlert::new(
+                AccessAlertRule::UnregisteredDevice,
+                AccessAlertSeverity::High,
+                user_id,
+                None,
+                vec![entry.id],
+                description,
+            ),
+            alerts,
+        )
+    }
+
+    /// VIP and same-surname rules, which look at the patient concerned
+    fn check_patient(
+        &self,
+        entry: &AuditLog,
+        user_id: Id,
+        patient_id: Id,
+        cache: &mut ScanCache,
+        alerts: &mut Vec<AccessAlert>,
+    ) -> Result<()> {
+        let Some(user) = self.user(user_id, cache)? else {
+            return Ok(());
+        };
+        // Patients viewing their own chart
+        if user.patient_id == Some(patient_id) {
+            return Ok(());
+        }
+
+        if self.policy.enabled(AccessAlertRule::VipPatientAccess) {
+            if let Some(vip) = AccessAlertRepository::new(self.db.clone()).find_vip(patient_id)? {
+                let on_care_team = CareTeamRepository::new(self.db.clone())
+                    .find_for_patient(patient_id)?
+                    .is_some_and(|team| team.includes(user_id));
+                let severity = if on_care_team { AccessAlertSeverity::Medium } else { AccessAlertSeverity::High };
+                let description = format!(
+                    "{} accessed VIP patient's {}{}{}",
+                    user.name,
+                    entry.entity_type,
+                    if on_care_team { "" } else { " without a care relationship" },
+                    vip.reason.map(|r| format!(" (flagged: {})", r)).unwrap_or_default(),
+                );
+                self.raise(
+                    AccessAlert::new(
+                        AccessAlertRule::VipPatientAccess,
+                        severity,
+                        user_id,
+                        Some(patient_id),
+                        vec![entry.id],
+                        description,
+                    ),
+                    alerts,
+                )?;
+            }
+        }
+
+        if self.policy.enabled(AccessAlertRule::SameSurname) {
+            let user_surname = surname(&user.name);
+            let Some(patient) = self.patient(patient_id, cache)? else {
+                return Ok(());
+            };
+            if !user_surname.is_empty() && user_surname == surname(&patient.last_name) {
+                self.raise(
+                    AccessAlert::new(
+                        AccessAlertRule::SameSurname,
+                        AccessAlertSeverity::Medium,
+                        user_id,
+                        Some(patient_id),
+                        vec![entry.id],
+                        format!("{} accessed the {} of a patient with the same surname", user.name, entry.entity_type),
+                    ),
+                    alerts,
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Many reads in a short window outside business hours
+    fn check_burst(&self, entry: &AuditLog, user_id: Id, alerts: &mut Vec<AccessAlert>) -> Result<()> {
+        if !self.policy.enabled(AccessAlertRule::OffHoursBurst) {
+            return Ok(());
+        }
+
+        let filters = AuditLogFilters {
+            user_id: Some(user_id),
+            event_types: Some(vec![AuditEventType::Read]),
+            start_time: Some(entry.timestamp - Duration::minutes(self.policy.burst_window_minutes)),
+            end_time: Some(entry.timestamp + Duration::milliseconds(1)),
+            limit: 100,
+            ..Default::default()
+        };
+        let audit = AuditRepository::new(self.db.clone());
+        let reads = audit.count(&filters)?;
+        if reads < self.policy.burst_threshold {
+            return Ok(());
+        }
+
+        let ids = audit.search(&filters)?.into_iter().map(|e| e.id).collect();
+        self.raise(
+            AccessAlert::new(
+                AccessAlertRule::OffHoursBurst,
+                AccessAlertSeverity::Medium,
+                user_id,
+                None,
+                ids,
+                format!(
+                    "{} reads within {} minutes outside business hours, ending {}",
+                    reads,
+                    self.policy.burst_window_minutes,
+                    entry.timestamp.format("%Y-%m-%d %H:%M UTC"),
+                ),
+            ),
+            alerts,
+        )
+    }
+
+    /// Charts read on one day against the user's baseline
+    fn check_volume(&self, user_id: Id, day: NaiveDate, ids: Vec<Id>, alerts: &mut Vec<AccessAlert>) -> Result<()> {
+        if !self.policy.enabled(AccessAlertRule::ExcessiveChartAccess) {
+            return Ok(());
+        }
+
+        let day_start = day.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc();
+        let filters = |start: Timestamp, end: Timestamp| AuditLogFilters {
+            user_id: Some(user_id),
+            entity_type: Some("Patient".to_string()),
+            event_types: Some(vec![AuditEventType::Read]),
+            start_time: Some(start),
+            end_time: Some(end),
+            ..Default::default()
+        };
+        let audit = AuditRepository::new(self.db.clone());
+
+        let charts = audit
+            .daily_distinct_entities(&filters(day_start, day_start + Duration::days(1)))?
+            .first()
+            .map_or(0, |(_, n)| *n);
+        let history = audit.daily_distinct_entities(&filters(
+            day_start - Duration::days(self.policy.baseline_days),
+            day_start,
+        ))?;
+        let threshold = self.policy.volume_threshold(&history);
+        if charts < threshold {
+            return Ok(());
+        }
+
+        let baseline = if history.is_empty() {
+            "no baseline".to_string()
+        } else {
+            format!(
+                "baseline {:.1} a day over {} days",
+                history.iter().map(|(_, n)| *n).sum::<i64>() as f64 / history.len() as f64,
+                history.len()
+            )
+        };
+        self.raise(
+            AccessAlert::new(
+                AccessAlertRule::ExcessiveChartAccess,
+                AccessAlertSeverity::Medium,
+                user_id,
+                None,
+                ids.into_iter().take(100).collect(),
+                format!("{} charts read on {} ({}; threshold {})", charts, day, baseline, threshold),
+            ),
+            alerts,
+        )
+    }
+
+    /// Queue an alert unless the rule already raised one for the same user
+    /// and patient within the cooldown
+    fn raise(&self, alert: AccessAlert, alerts: &mut Vec<AccessAlert>) -> Result<()> {
+        let repo = AccessAlertRepository::new(self.db.clone());
+        let since = chrono::Utc::now() - Duration::hours(self.policy.alert_cooldown_hours);
+        if repo.raised_since(alert.rule, alert.user_id, alert.patient_id, since)? {
+            return Ok(());
+        }
+
+        repo.create(&alert)?;
+        alerts.push(alert);
+        Ok(())
+    }
+
+    fn user(&self, user_id: Id, cache: &mut ScanCache) -> Result<Option<User>> {
+        if let Some(user) = cache.users.get(&user_id) {
+            return Ok(user.clone());
+        }
+        let user = UserRepository::new(self.db.clone()).find_by_id(user_id)?;
+        cache.users.insert(user_id, user.clone());
+        Ok(user)
+    }
+
+    fn patient(&self, patient_id: Id, cache: &mut ScanCache) -> Result<Option<Patient>> {
+        if let Some(patient) = cache.patients.get(&patient_id) {
+            return Ok(patient.clone());
+        }
+        let patient = PatientRepository::new(self.db.clone(), self.keyring.clone()).find_by_id(patient_id)?;
+        cache.patients.insert(patient_id, patient.clone());
+        Ok(patient)
+    }
+
+    /// Whether the caller may see the alert queue
+    pub fn can_read(&self, claims: &Claims) -> bool {
+        self.permissions.authorize(claims, "access_alerts", "read")
+    }
+
+    /// Whether the caller may review alerts
+    pub fn can_review(&self, claims: &Claims) -> bool {
+        self.permissions.authorize(claims, "access_alerts", "review")
+    }
+
+    /// Whether the caller may maintain the VIP list and run scans
+    pub fn can_manage(&self, claims: &Claims) -> bool {
+        self.permissions.authorize(claims, "access_alerts", "manage")
+    }
+
+    /// One page of alerts matching the filters, newest first
+    pub fn alerts(&self, claims: &Claims, filters: &AccessAlertFilters) -> Result<AccessAlertPage> {
+        self.require(self.can_read(claims), "access_alerts:read")?;
+
+        let repo = AccessAlertRepository::new(self.db.clone());
+        Ok(AccessAlertPage { alerts: repo.search(filters)?, total: repo.count(filters)? })
+    }
+
+    pub fn find(&self, claims: &Claims, alert_id: Id) -> Result<AccessAlert> {
+        self.require(self.can_read(claims), "access_alerts:read")?;
+
+        AccessAlertRepository::new(self.db.clone())
+            .find_by_id(alert_id)?
+            .ok_or(AccessMonitorError::AlertNotFound)
+    }
+
+    /// Close an alert as escalated or dismissed
+    pub fn review(&self, claims: &Claims, alert_id: Id, req: ReviewAccessAlert) -> Result<AccessAlert> {
+        self.require(self.can_review(claims), "access_alerts:review")?;
+        if req.status == AccessAlertStatus::Open {
+            return Err(AccessMonitorError::InvalidReview(
+                "An alert must be escalated or dismissed".to_string(),
+            ));
+        }
+
+        let reviewer_id = claims.user_id()
+            .ok_or_else(|| AccessMonitorError::NotPermitted("Token has no valid subject".to_string()))?;
+        let repo = AccessAlertRepository::new(self.db.clone());
+        let alert = repo.find_by_id(alert_id)?.ok_or(AccessMonitorError::AlertNotFound)?;
+        if alert.user_id == reviewer_id {
+            return Err(AccessMonitorError::NotPermitted(
+                "An alert cannot be reviewed by the user it concerns".to_string(),
+            ));
+        }
+
+        if !repo.review(alert_id, req.status, reviewer_id, req.notes.as_deref())? {
+            return Err(AccessMonitorError::AlreadyReviewed);
+        }
+
+        repo.find_by_id(alert_id)?.ok_or(AccessMonitorError::AlertNotFound)
+    }
+
+    pub fn vip_patients(&self, claims: &Claims) -> Result<Vec<VipPatient>> {
+        self.require(self.can_read(claims), "access_alerts:read")?;
+        Ok(AccessAlertRepository::new(self.db.clone()).list_vips()?)
+    }
+
+    /// Flag a patient for VIP access monitoring
+    pub fn flag_vip(&self, claims: &Claims, patient_id: Id, reason: Option<String>) -> Result<VipPatient> {
+        self.require(self.can_manage(claims), "access_alerts:manage")?;
+
+        let repo = AccessAlertRepository::new(self.db.clone());
+        repo.patient_for_entity("Patient", &patient_id.to_string())?
+            .ok_or(AccessMonitorError::PatientNotFound)?;
+
+        let vip = VipPatient {
+            patient_id,
+            reason,
+            flagged_by: claims.user_id(),
+            flagged_at: chrono::Utc::now(),
+        };
+        repo.flag_vip(&vip)?;
+        Ok(vip)
+    }
+
+    pub fn unflag_vip(&self, claims: &Claims, patient_id: Id) -> Result<()> {
+        self.require(self.can_manage(claims), "access_alerts:manage")?;
+
+        if AccessAlertRepository::new(self.db.clone()).unflag_vip(patient_id)? {
+            Ok(())
+        } else {
+            Err(AccessMonitorError::PatientNotFound)
+        }
+    }
+
+    fn require(&self, allowed: bool, permission: &str) -> Result<()> {
+        if allowed {
+            Ok(())
+        } else {
+            Err(AccessMonitorError::NotPermitted(format!("Requires the {} permission", permission)))
+        }
+    }
+}
+
+/// Surname in a comparable form, from "First Last" or "Last, First"
+fn surname(name: &str) -> String {
+    let last = match name.split_once(',') {
+        Some((last, _)) => last,
+        None => name.split_whitespace().last().unwrap_or_default(),
+    };
+    last.chars()
+        .filter(|c| c.is_alphabetic())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hedtronix_core::{Device, DeviceType, Gender, UserRole};
+    use hedtronix_crypto::DataKey;
+
+    struct Fixture {
+        db: Database,
+        nurse: User,
+        patient: Patient,
+    }
+
+    fn keyring() -> Arc<Keyring> {
+        Arc::new(Keyring::with_key(DataKey::from_bytes("test", &[0; 32]).unwrap()).unwrap())
+    }
+
+    fn setup() -> Fixture {
+        let mut db = Database::in_memory().unwrap();
+        db.initialize().unwrap();
+
+        let nurse = User::new("nurse@example.com".into(), "Nora Smith".into(), UserRole::Nurse, "hash".into());
+        UserRepository::new(db.clone()).create(&nurse).unwrap();
+
+        let dob = NaiveDate::from_ymd_opt(1970, 5, 1).unwrap();
+        let patient = Patient::new("MRN1".into(), "Sam".into(), "SMITH".into(), dob, Gender::Male);
+        PatientRepository::new(db.clone(), keyring()).create(&patient).unwrap();
+
+        Fixture { db, nurse, patient }
+    }
+
+    fn monitor(fixture: &Fixture, policy: AccessMonitorPolicy) -> AccessMonitor {
+        AccessMonitor::new(fixture.db.clone(), keyring()).with_policy(Arc::new(policy))
+    }
+
+    fn only(rule: AccessAlertRule) -> AccessMonitorPolicy {
+        AccessMonitorPolicy { enabled_rules: vec![rule], ..Default::default() }
+    }
+
+    fn read(db: &Database, user: &User, entity_id: Id, at: Timestamp) -> AuditLog {
+        let mut log = AuditLog::new(
+            AuditEventType::Read,
+            Some(user.id),
+            None,
+            "Patient".to_string(),
+            entity_id.to_string(),
+            serde_json::json!({}),
+        );
+        log.timestamp = at;
+        AuditRepository::new(db.clone()).append(&log).unwrap()
+    }
+
+    #[test]
+    fn test_same_surname_and_vip_access() {
+        let fixture = setup();
+        let monitor = monitor(&fixture, AccessMonitorPolicy::default());
+        let midday = chrono::Utc::now().date_naive().and_hms_opt(12, 0, 0).unwrap().and_utc();
+
+        read(&fixture.db, &fixture.nurse, fixture.patient.id, midday);
+        let report = monitor.scan().unwrap();
+        assert_eq!(report.entries_scanned, 1);
+        let rules: Vec<_> = report.alerts.iter().map(|a| a.rule).collect();
+        assert_eq!(rules, vec![AccessAlertRule::SameSurname]);
+        assert_eq!(report.alerts[0].patient_id, Some(fixture.patient.id));
+
+        AccessAlertRepository::new(fixture.db.clone())
+            .flag_vip(&VipPatient {
+                patient_id: fixture.patient.id,
+                reason: Some("Public figure".into()),
+                flagged_by: None,
+                flagged_at: chrono::Utc::now(),
+            })
+            .unwrap();
+        read(&fixture.db, &fixture.nurse, fixture.patient.id, midday);
+        let report = monitor.scan().unwrap();
+
+        // The surname alert is within its cooldown
+        assert_eq!(report.entries_scanned, 1);
+        assert_eq!(report.alerts.len(), 1);
+        assert_eq!(report.alerts[0].rule, AccessAlertRule::VipPatientAccess);
+        assert_eq!(report.alerts[0].severity, AccessAlertSeverity::High);
+
+        // Nothing new to scan
+        assert_eq!(monitor.scan().unwrap().entries_scanned, 0);
+    }
+
+    #[test]
+    fn test_off_hours_burst() {
+        let fixture = setup();
+        let monitor = monitor(&fixture, only(AccessAlertRule::OffHoursBurst));
+        let night = chrono::Utc::now().date_naive().and_hms_opt(2, 0, 0).unwrap().and_utc();
+
+        for i in 0..9 {
+            read(&fixture.db, &fixture.nurse, Id::new_v4(), night + Duration::minutes(i));
+        }
+        assert!(monitor.scan().unwrap().alerts.is_empty());
+
+        read(&fixture.db, &fixture.nurse, Id::new_v4(), night + Duration::minutes(9));
+        let alerts = monitor.scan().unwrap().alerts;
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].rule, AccessAlertRule::OffHoursBurst);
+        assert_eq!(alerts[0].audit_log_ids.len(), 10);
+    }
+
+    #[test]
+    fn test_chart_volume_against_baseline() {
+        let fixture = setup();
+        let policy = AccessMonitorPolicy { min_daily_charts: 5, ..only(AccessAlertRule::ExcessiveChartAccess) };
+        let monitor = monitor(&fixture, policy);
+        let today = chrono::Utc::now().date_naive().and_hms_opt(12, 0, 0).unwrap().and_utc();
+
+        // Two charts a day for the last week
+        for day in 1..=7 {
+            for _ in 0..2 {
+                read(&fixture.db, &fixture.nurse, Id::new_v4(), today - Duration::days(day));
+            }
+        }
+        // Threshold is max(2 * 3, 5) = 6 distinct charts
+        for _ in 0..5 {
+            read(&fixture.db, &fixture.nurse, Id::new_v4(), today);
+        }
+        assert!(monitor.scan().unwrap().alerts.is_empty());
+
+        read(&fixture.db, &fixture.nurse, Id::new_v4(), today);
+        let alerts = monitor.scan().unwrap().alerts;
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].rule, AccessAlertRule::ExcessiveChartAccess);
+        assert!(alerts[0].description.starts_with("6 charts read"));
+    }
+
+    #[test]
+    fn test_device_of_another_user() {
+        let fixture = setup();
+        let monitor = monitor(&fixture, only(AccessAlertRule::UnregisteredDevice));
+
+        let other = User::new("other@example.com".into(), "Olly Other".into(), UserRole::Nurse, "hash".into());
+        UserRepository::new(fixture.db.clone()).create(&other).unwrap();
+        let device = Device::new(other.id, "key".into(), DeviceType::Tablet, "test".into());
+        DeviceRepository::new(fixture.db.clone()).create(&device).unwrap();
+
+        let mut log = AuditLog::new(
+            AuditEventType::Read,
+            Some(fixture.nurse.id),
+            Some(device.id),
+            "Patient".to_string(),
+            fixture.patient.id.to_string(),
+            serde_json::json!({}),
+        );
+        AuditRepository::new(fixture.db.clone()).append(&log).unwrap();
+        log = AuditLog::new(
+            AuditEventType::Read,
+            Some(other.id),
+            Some(device.id),
+            "Patient".to_string(),
+            fixture.patient.id.to_string(),
+            serde_json::json!({}),
+        );
+        AuditRepository::new(fixture.db.clone()).append(&log).unwrap();
+
+        let alerts = monitor.scan().unwrap().alerts;
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].user_id, fixture.nurse.id);
+        assert_eq!(alerts[0].severity, AccessAlertSeverity::High);
+    }
+
+    #[test]
+    fn test_surname_forms() {
+        assert_eq!(surname("Nora Smith"), "smith");
+        assert_eq!(surname("Smith, Nora"), "smith");
+        assert_eq!(surname("Sam O'Brien"), "obrien");
+        assert_eq!(surname(""), "");
+    }
+}
@@ -0,0 +1,177 @@
+//! Offline token issuance policy
+//!
+//! Offline tokens let a device keep working without reaching the server, so
+//! their lifetime and permissions are capped per role and per device type.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use chrono::Duration;
+use hedtronix_core::{DeviceType, UserRole};
+use serde::{Deserialize, Serialize};
+
+use crate::permissions::Permission;
+
+/// Policy limits for offline token issuance
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OfflineTokenPolicy {
+    /// Maximum validity in hours per role; roles not listed are never issued one
+    pub role_max_hours: HashMap<UserRole, u32>,
+
+    /// Maximum validity in hours per device type; device types not listed
+    /// are never issued one
+    pub device_max_hours: HashMap<DeviceType, u32>,
+
+    /// Actions never granted to an offline token, whatever the role allows
+    pub excluded_actions: Vec<String>,
+}
+
+impl Default for OfflineTokenPolicy {
+    fn default() -> Self {
+        Self {
+            role_max_hours: HashMap::from([
+                (UserRole::Physician, 72),
+                (UserRole::Nurse, 48),
+                (UserRole::Receptionist, 24),
+                (UserRole::Billing, 24),
+            ]),
+            device_max_hours: HashMap::from([
+                (DeviceType::Desktop, 72),
+                (DeviceType::Tablet, 72),
+                (DeviceType::Mobile, 24),
+            ]),
+            excluded_actions: vec![
+                "sign".to_string(),
+                "submit".to_string(),
+                "adjust".to_string(),
+                "pay".to_string(),
+            ],
+        }
+    }
+}
+
+/// An approved offline token grant
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OfflineGrant {
+    pub duration: Duration,
+    pub permissions: Vec<String>,
+}
+
+impl OfflineTokenPolicy {
+    /// Load a policy from a JSON file
+    pub fn from_file(path: &Path) -> std::result::Result<Self, String> {
+        let json = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        serde_json::from_str(&json).map_err(|e| e.to_string())
+    }
+
+    /// Longest offline validity allowed for a role on a device type
+    pub fn max_duration(&self, role: UserRole, device_type: DeviceType) -> Option<Duration> {
+        let role_max = *self.role_max_hours.get(&role)?;
+        let device_max = *self.device_max_hours.get(&device_type)?;
+        let hours = role_max.min(device_max);
+
+        (hours > 0).then(|| Duration::hours(hours as i64))
+    }
+
+    /// Evaluate a request, capping the duration and restricting the
+    /// permission set. `requested_permissions` of `None` asks for everything
+    /// the role allows offline. `role_permissions` are the caller's grants
+    /// from the `PermissionChecker`.
+    pub fn evaluate(
+        &self,
+        role: UserRole,
+        device_type: DeviceType,
+        role_permissions: &[Permission],
+        requested_hours: u32,
+        requested_permissions: Option<&[String]>,
+    ) -> std::result::Result<OfflineGrant, String> {
+        if requested_hours == 0 {
+            return Err("Requested duration must be at least one hour".to_string());
+        }
+
+        let max = self.max_duration(role, device_type).ok_or_else(|| {
+            format!(
+                "Offline tokens are not permitted for role {} on {} devices",
+                role.as_str(),
+                device_type.as_str()
+            )
+        })?;
+        let duration = Duration::hours(requested_hours as i64).min(max);
+
+        let allowed = self.allowed_permissions(role_permissions);
+        let permissions: Vec<String> = match requested_permissions {
+            Some(requested) => requested.iter()
+                .filter(|p| allowed.contains(p))
+                .cloned()
+                .collect(),
+            None => allowed,
+        };
+
+        if permissions.is_empty() {
+            return Err("No requested permission may be used offline".to_string());
+        }
+
+        Ok(OfflineGrant { duration, permissions })
+    }
+
+    /// `resource:action` pairs from a role's grants that may be carried in
+    /// an offline token
+    pub fn allowed_permissions(&self, role_permissions: &[Permission]) -> Vec<String> {
+        role_permissions
+            .iter()
+            .filter(|p| p.resource != "*" && !self.excluded_actions.contains(&p.action))
+            .map(|p| p.to_string())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grants(role: UserRole) -> Vec<Permission> {
+        hedtronix_core::default_permissions(role)
+    }
+
+    #[test]
+    fn test_kiosk_never_gets_offline_token() {
+        let policy = OfflineTokenPolicy::default();
+        assert!(policy.max_duration(UserRole::Physician, DeviceType::Kiosk).is_none());
+        assert!(policy.evaluate(UserRole::Physician, DeviceType::Kiosk, &grants(UserRole::Physician), 1, None).is_err());
+    }
+
+    #[test]
+    fn test_duration_is_capped() {
+        let policy = OfflineTokenPolicy::default();
+
+        let grant = policy.evaluate(UserRole::Physician, DeviceType::Desktop, &grants(UserRole::Physician), 200, None).unwrap();
+        assert_eq!(grant.duration, Duration::hours(72));
+
+        let grant = policy.evaluate(UserRole::Physician, DeviceType::Mobile, &grants(UserRole::Physician), 200, None).unwrap();
+        assert_eq!(grant.duration, Duration::hours(24));
+
+        let grant = policy.evaluate(UserRole::Nurse, DeviceType::Tablet, &grants(UserRole::Nurse), 8, None).unwrap();
+        assert_eq!(grant.duration, Duration::hours(8));
+    }
+
+    #[test]
+    fn test_permissions_are_restricted() {
+        let policy = OfflineTokenPolicy::default();
+
+        let grant = policy.evaluate(UserRole::Physician, DeviceType::Desktop, &grants(UserRole::Physician), 8, None).unwrap();
+        assert!(grant.permissions.contains(&"patients:read".to_string()));
+        assert!(!grant.permissions.contains(&"clinical_notes:sign".to_string()));
+
+        let requested = vec!["patients:read".to_string(), "billing:adjust".to_string()];
+        let grant = policy
+            .evaluate(UserRole::Physician, DeviceType::Desktop, &grants(UserRole::Physician), 8, Some(&requested))
+            .unwrap();
+        assert_eq!(grant.permissions, vec!["patients:read".to_string()]);
+    }
+
+    #[test]
+    fn test_admin_denied_by_default() {
+        let policy = OfflineTokenPolicy::default();
+        assert!(policy.evaluate(UserRole::Admin, DeviceType::Desktop, &grants(UserRole::Admin), 8, None).is_err());
+    }
+}
@@ -0,0 +1,140 @@
+//! Password policy: length, breached-password list and reuse history
+
+use std::collections::HashSet;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+/// Password policy settings, as loaded from configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PasswordPolicyConfig {
+    pub min_length: usize,
+    pub max_length: usize,
+
+    /// Number of previous passwords that may not be reused
+    pub history_size: usize,
+
+    /// File of known-breached passwords, one per line
+    pub breached_passwords_path: Option<String>,
+}
+
+impl Default for PasswordPolicyConfig {
+    fn default() -> Self {
+        Self {
+            min_length: 12,
+            max_length: 128,
+            history_size: 5,
+            breached_passwords_path: None,
+        }
+    }
+}
+
+/// Password policy with the breached-password list loaded
+#[derive(Debug, Clone, Default)]
+pub struct PasswordPolicy {
+    pub config: PasswordPolicyConfig,
+    breached: HashSet<String>,
+}
+
+impl PasswordPolicy {
+    /// Build a policy, reading the breached-password list if configured
+    pub fn load(config: PasswordPolicyConfig) -> std::result::Result<Self, String> {
+        let breached = match &config.breached_passwords_path {
+            Some(path) => std::fs::read_to_string(Path::new(path))
+                .map_err(|e| format!("{}: {}", path, e))?
+                .lines()
+                .map(|line| line.trim().to_lowercase())
+                .filter(|line| !line.is_empty())
+                .collect(),
+            None => HashSet::new(),
+        };
+
+        Ok(Self { config, breached })
+    }
+
+    /// Load the policy settings from a JSON file
+    pub fn from_file(path: &Path) -> std::result::Result<Self, String> {
+        let json = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let config = serde_json::from_str(&json).map_err(|e| e.to_string())?;
+        Self::load(config)
+    }
+
+    pub fn with_breached_passwords<I: IntoIterator<Item = String>>(mut self, passwords: I) -> Self {
+        self.breached.extend(passwords.into_iter().map(|p| p.to_lowercase()));
+        self
+    }
+
+    pub fn history_size(&self) -> usize {
+        self.config.history_size
+    }
+
+    /// Check a candidate password, returning the first rule it breaks.
+    /// Reuse against the history is checked separately, since that needs
+    /// the stored hashes.
+    pub fn validate(&self, password: &str, email: &str) -> std::result::Result<(), String> {
+        let length = password.chars().count();
+        if length < self.config.min_length {
+            return Err(format!(
+                "Password must be at least {} characters",
+                self.config.min_length
+            ));
+        }
+        if length > self.config.max_length {
+            return Err(format!(
+                "Password must be at most {} characters",
+                self.config.max_length
+            ));
+        }
+
+        let lowered = password.to_lowercase();
+        if self.breached.contains(&lowered) {
+            return Err("Password appears in a list of breached passwords".to_string());
+        }
+
+        let local_part = email.split('@').next().unwrap_or_default().to_lowercase();
+        if !local_part.is_empty() && lowered.contains(&local_part) {
+            return Err("Password must not contain the account name".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_length_rules() {
+        let policy = PasswordPolicy::default();
+
+        assert!(policy.validate("short", "doc@example.com").is_err());
+        assert!(policy.validate("a-long-enough-passphrase", "doc@example.com").is_ok());
+        assert!(policy.validate(&"x".repeat(200), "doc@example.com").is_err());
+    }
+
+    #[test]
+    fn test_breached_and_account_name() {
+        let policy = PasswordPolicy::default()
+            .with_breached_passwords(vec!["Correct-Horse-Battery".to_string()]);
+
+        assert!(policy.validate("correct-horse-battery", "doc@example.com").is_err());
+        assert!(policy.validate("drsmith-password-1", "drsmith@example.com").is_err());
+    }
+
+    #[test]
+    fn test_load_breached_list_from_file() {
+        let path = std::env::temp_dir().join(format!("breached-{}.txt", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "password123456\n\nqwertyuiop12\n").unwrap();
+
+        let policy = PasswordPolicy::load(PasswordPolicyConfig {
+            breached_passwords_path: Some(path.to_string_lossy().to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(policy.validate("QWERTYUIOP12", "doc@example.com").is_err());
+        assert!(policy.validate("a-long-enough-passphrase", "doc@example.com").is_ok());
+    }
+}
@@ -1,185 +1,279 @@
 //! Permission checking for RBAC
+//!
+//! Role grants live in the `roles` and `role_permissions` tables, seeded from
+//! `hedtronix_core::default_permissions`. `PermissionChecker` is the one place
+//! that answers "may this caller do that".
 
-use hedtronix_core::UserRole;
-use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::RwLock;
 
-/// Permission definition
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Permission {
-    pub resource: String,
-    pub action: String,
+use hedtronix_core::{default_permissions, CreateRole, Id, Role, UserRole};
+use hedtronix_db::{Database, RoleRepository, UserRepository};
+use thiserror::Error;
+use validator::Validate;
+
+use crate::jwt::Claims;
+
+pub use hedtronix_core::Permission;
+
+/// Role administration and access errors
+#[derive(Error, Debug)]
+pub enum AuthorizationError {
+    #[error("Role not found: {0}")]
+    RoleNotFound(String),
+
+    #[error("Built-in role {0} cannot be deleted")]
+    BuiltInRole(String),
+
+    #[error("Role {0} is still assigned to users")]
+    RoleInUse(String),
+
+    #[error("Invalid role: {0}")]
+    Invalid(String),
+
+    #[error("{0} not found")]
+    NotFound(&'static str),
+
+    #[error("Access denied: {0}")]
+    Denied(&'static str),
+
+    #[error("Database error: {0}")]
+    Database(String),
+}
+
+/// Result type for role administration and access checks
+pub type Result<T> = std::result::Result<T, AuthorizationError>;
+
+/// Authorization service. Grants are cached per role, and each user's role
+/// per user; both are reloaded after any change made through this service.
+/// Without a database only the built-in defaults are known.
+pub struct PermissionChecker {
+    db: Option<Database>,
+    cache: RwLock<HashMap<String, Vec<Permission>>>,
+    user_roles: RwLock<HashMap<Id, String>>,
 }
 
-impl Permission {
-    pub fn new(resource: &str, action: &str) -> Self {
+impl Default for PermissionChecker {
+    fn default() -> Self {
+        Self::built_in()
+    }
+}
+
+impl PermissionChecker {
+    pub fn new(db: Database) -> Self {
         Self {
-            resource: resource.to_string(),
-            action: action.to_string(),
+            db: Some(db),
+            cache: RwLock::new(HashMap::new()),
+            user_roles: RwLock::new(HashMap::new()),
         }
     }
-}
 
-/// Permission checker for RBAC
-pub struct PermissionChecker;
+    /// Checker that only knows the built-in roles' default grants
+    pub fn built_in() -> Self {
+        Self {
+            db: None,
+            cache: RwLock::new(HashMap::new()),
+            user_roles: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// All permissions granted to a role, by role id. Unknown roles get none.
+    pub fn get_permissions(&self, role_id: &str) -> Vec<Permission> {
+        if let Some(permissions) = self.cache.read().ok().and_then(|c| c.get(role_id).cloned()) {
+            return permissions;
+        }
+
+        let permissions = match &self.db {
+            Some(db) => match RoleRepository::new(db.clone()).find_by_id(role_id) {
+                Ok(role) => role.map(|r| r.permissions).unwrap_or_default(),
+                Err(e) => {
+                    // Deny rather than cache a failed read
+                    tracing::error!("Failed to load permissions for role {}: {}", role_id, e);
+                    return Vec::new();
+                }
+            },
+            None => hedtronix_core::ALL_USER_ROLES
+                .into_iter()
+                .find(|r| r.as_str() == role_id)
+                .map(default_permissions)
+                .unwrap_or_default(),
+        };
+
+        if let Ok(mut cache) = self.cache.write() {
+            cache.insert(role_id.to_string(), permissions.clone());
+        }
+        permissions
+    }
 
-impl PermissionChecker {
     /// Check if a role has permission for an action on a resource
-    pub fn has_permission(role: UserRole, resource: &str, action: &str) -> bool {
-        match role {
-            UserRole::Admin => true, // Admin has all permissions
-            UserRole::Physician => Self::physician_permissions(resource, action),
-            UserRole::Nurse => Self::nurse_permissions(resource, action),
-            UserRole::Receptionist => Self::receptionist_permissions(resource, action),
-            UserRole::Billing => Self::billing_permissions(resource, action),
-            UserRole::Patient => Self::patient_permissions(resource, action),
-        }
-    }
-
-    fn physician_permissions(resource: &str, action: &str) -> bool {
-        matches!(
-            (resource, action),
-            ("patients", "read" | "write" | "create" | "list")
-                | ("appointments", "read" | "write" | "create" | "list" | "cancel")
-                | ("clinical_notes", "read" | "write" | "create" | "sign" | "list")
-                | ("encounters", "read" | "write" | "create" | "list")
-                | ("prescriptions", "read" | "write" | "create" | "sign")
-                | ("billing", "read" | "list")
-                | ("reports", "read")
-                | ("users", "read")
-                | ("sync", "push" | "pull")
-        )
-    }
-
-    fn nurse_permissions(resource: &str, action: &str) -> bool {
-        matches!(
-            (resource, action),
-            ("patients", "read" | "write" | "list")
-                | ("appointments", "read" | "write" | "list")
-                | ("clinical_notes", "read" | "write" | "list")
-                | ("encounters", "read" | "write" | "list")
-                | ("vitals", "read" | "write" | "create")
-                | ("medication_administration", "read" | "write" | "create")
-                | ("billing", "read" | "list")
-                | ("users", "read")
-                | ("sync", "push" | "pull")
-        )
-    }
-
-    fn receptionist_permissions(resource: &str, action: &str) -> bool {
-        matches!(
-            (resource, action),
-            ("patients", "read" | "write" | "create" | "list")
-                | ("appointments", "read" | "write" | "create" | "cancel" | "check_in" | "list")
-                | ("billing", "read" | "create_charges" | "list")
-                | ("clinical_notes", "read")
-                | ("users", "read")
-                | ("rooms", "read" | "list")
-                | ("sync", "push" | "pull")
-        )
-    }
-
-    fn billing_permissions(resource: &str, action: &str) -> bool {
-        matches!(
-            (resource, action),
-            ("patients", "read" | "list")
-                | ("appointments", "read" | "list")
-                | ("clinical_notes", "read" | "list")
-                | ("encounters", "read" | "list")
-                | ("billing", "read" | "write" | "create" | "submit" | "adjust" | "list")
-                | ("reports", "read_financial")
-                | ("users", "read")
-                | ("sync", "push" | "pull")
-        )
-    }
-
-    fn patient_permissions(resource: &str, action: &str) -> bool {
-        matches!(
-            (resource, action),
-            ("own_data", "read")
-                | ("appointments", "read_own" | "create_own" | "cancel_own")
-                | ("clinical_notes", "read_own")
-                | ("billing", "read_own" | "pay")
-                | ("messages", "read" | "create")
-        )
-    }
-
-    /// Get all permissions for a role
-    pub fn get_permissions(role: UserRole) -> Vec<Permission> {
-        match role {
-            UserRole::Admin => vec![Permission::new("*", "*")],
-            UserRole::Physician => vec![
-                Permission::new("patients", "read"),
-                Permission::new("patients", "write"),
-                Permission::new("patients", "create"),
-                Permission::new("patients", "list"),
-                Permission::new("appointments", "read"),
-                Permission::new("appointments", "write"),
-                Permission::new("appointments", "create"),
-                Permission::new("appointments", "list"),
-                Permission::new("appointments", "cancel"),
-                Permission::new("clinical_notes", "read"),
-                Permission::new("clinical_notes", "write"),
-                Permission::new("clinical_notes", "create"),
-                Permission::new("clinical_notes", "sign"),
-                Permission::new("clinical_notes", "list"),
-                Permission::new("encounters", "read"),
-                Permission::new("encounters", "write"),
-                Permission::new("encounters", "create"),
-                Permission::new("encounters", "list"),
-                Permission::new("billing", "read"),
-                Permission::new("billing", "list"),
-            ],
-            UserRole::Nurse => vec![
-                Permission::new("patients", "read"),
-                Permission::new("patients", "write"),
-                Permission::new("patients", "list"),
-                Permission::new("appointments", "read"),
-                Permission::new("appointments", "write"),
-                Permission::new("appointments", "list"),
-                Permission::new("clinical_notes", "read"),
-                Permission::new("clinical_notes", "write"),
-                Permission::new("clinical_notes", "list"),
-                Permission::new("vitals", "read"),
-                Permission::new("vitals", "write"),
-                Permission::new("vitals", "create"),
-            ],
-            UserRole::Receptionist => vec![
-                Permission::new("patients", "read"),
-                Permission::new("patients", "write"),
-                Permission::new("patients", "create"),
-                Permission::new("patients", "list"),
-                Permission::new("appointments", "read"),
-                Permission::new("appointments", "write"),
-                Permission::new("appointments", "create"),
-                Permission::new("appointments", "cancel"),
-                Permission::new("appointments", "check_in"),
-                Permission::new("appointments", "list"),
-                Permission::new("billing", "read"),
-                Permission::new("billing", "create_charges"),
-                Permission::new("billing", "list"),
-            ],
-            UserRole::Billing => vec![
-                Permission::new("patients", "read"),
-                Permission::new("patients", "list"),
-                Permission::new("appointments", "read"),
-                Permission::new("appointments", "list"),
-                Permission::new("billing", "read"),
-                Permission::new("billing", "write"),
-                Permission::new("billing", "create"),
-                Permission::new("billing", "submit"),
-                Permission::new("billing", "adjust"),
-                Permission::new("billing", "list"),
-            ],
-            UserRole::Patient => vec![
-                Permission::new("own_data", "read"),
-                Permission::new("appointments", "read_own"),
-                Permission::new("appointments", "create_own"),
-                Permission::new("appointments", "cancel_own"),
-                Permission::new("billing", "read_own"),
-                Permission::new("billing", "pay"),
-            ],
+    pub fn has_permission(&self, role_id: &str, resource: &str, action: &str) -> bool {
+        self.get_permissions(role_id).iter().any(|p| p.matches(resource, action))
+    }
+
+    /// Role whose grants apply to the caller. Custom role assignments are
+    /// read from the user record once and cached until `assign_role` or
+    /// `invalidate`, so reassignment takes effect immediately.
+    pub fn role_id_for(&self, claims: &Claims) -> String {
+        let (Some(db), Some(user_id)) = (&self.db, claims.user_id()) else {
+            return claims.user_role().as_str().to_string();
+        };
+        if let Some(role_id) = self.user_roles.read().ok().and_then(|c| c.get(&user_id).cloned()) {
+            return role_id;
+        }
+
+        match UserRepository::new(db.clone()).find_by_id(user_id) {
+            Ok(Some(user)) => {
+                let role_id = user.role_key().to_string();
+                if let Ok(mut cache) = self.user_roles.write() {
+                    cache.insert(user_id, role_id.clone());
+                }
+                role_id
+            }
+            // Not cached, so a user created later is looked up again
+            _ => claims.user_role().as_str().to_string(),
         }
     }
+
+    /// Built-in role the caller's role is, or is based on. Rules declared
+    /// per built-in role, such as field redaction, reach custom roles
+    /// through it.
+    pub fn base_role_for(&self, claims: &Claims) -> UserRole {
+        self.find_role(&self.role_id_for(claims))
+            .map(|role| role.base_role)
+            .unwrap_or_else(|_| claims.user_role())
+    }
+
+    /// Whether the caller may perform an action. Offline tokens are further
+    /// limited to the permissions they carry.
+    pub fn authorize(&self, claims: &Claims, resource: &str, action: &str) -> bool {
+        claims.allows(resource, action)
+            && self.has_permission(&self.role_id_for(claims), resource, action)
+    }
+
+    /// Drop cached grants and role assignments so the next check reads the
+    /// database, e.g. after a user's role was changed elsewhere
+    pub fn invalidate(&self) {
+        if let Ok(mut cache) = self.cache.write() {
+            cache.clear();
+        }
+        if let Ok(mut user_roles) = self.user_roles.write() {
+            user_roles.clear();
+        }
+    }
+
+    fn roles(&self) -> Result<RoleRepository> {
+        self.db
+            .clone()
+            .map(RoleRepository::new)
+            .ok_or_else(|| AuthorizationError::Database("No role store configured".to_string()))
+    }
+
+    pub fn list_roles(&self) -> Result<Vec<Role>> {
+        self.roles()?
+            .find_all()
+            .map_err(|e| AuthorizationError::Database(e.to_string()))
+    }
+
+    pub fn find_role(&self, role_id: &str) -> Result<Role> {
+        self.roles()?
+            .find_by_id(role_id)
+            .map_err(|e| AuthorizationError::Database(e.to_string()))?
+            .ok_or_else(|| AuthorizationError::RoleNotFound(role_id.to_string()))
+    }
+
+    /// Create a custom role, e.g. "Medical Assistant" based on Nurse
+    pub fn create_role(&self, req: CreateRole) -> Result<Role> {
+        req.validate().map_err(|e| AuthorizationError::Invalid(e.to_string()))?;
+        if req.base_role == UserRole::Admin {
+            return Err(AuthorizationError::Invalid(
+                "Custom roles cannot be based on Admin".to_string(),
+            ));
+        }
+
+        if let Some(wildcard) = req.permissions.iter().find(|p| is_wildcard(p)) {
+            return Err(wildcard_error(wildcard));
+        }
+
+        let mut role = Role::new_custom(req.name, req.base_role, req.permissions);
+        role.description = req.description;
+
+        self.roles()?
+            .create(&role)
+            .map_err(|e| AuthorizationError::Invalid(e.to_string()))?;
+
+        Ok(role)
+    }
+
+    /// Add a grant to a role. Custom roles only take grants on named
+    /// resources.
+    pub fn grant(&self, role_id: &str, permission: &Permission) -> Result<Role> {
+        let role = self.find_role(role_id)?;
+        if !role.built_in && is_wildcard(permission) {
+            return Err(wildcard_error(permission));
+        }
+        self.roles()?
+            .grant(role_id, permission)
+            .map_err(|e| AuthorizationError::Database(e.to_string()))?;
+        self.invalidate();
+
+        self.find_role(role_id)
+    }
+
+    pub fn revoke(&self, role_id: &str, permission: &Permission) -> Result<Role> {
+        self.find_role(role_id)?;
+        self.roles()?
+            .revoke(role_id, permission)
+            .map_err(|e| AuthorizationError::Database(e.to_string()))?;
+        self.invalidate();
+
+        self.find_role(role_id)
+    }
+
+    pub fn delete_role(&self, role_id: &str) -> Result<()> {
+        let role = self.find_role(role_id)?;
+        if role.built_in {
+            return Err(AuthorizationError::BuiltInRole(role.name));
+        }
+
+        let repo = self.roles()?;
+        let assigned = repo.count_users(role_id)
+            .map_err(|e| AuthorizationError::Database(e.to_string()))?;
+        if assigned > 0 {
+            return Err(AuthorizationError::RoleInUse(role.name));
+        }
+
+        repo.delete(role_id)
+            .map_err(|e| AuthorizationError::Database(e.to_string()))?;
+        self.invalidate();
+
+        Ok(())
+    }
+
+    /// Assign a user a built-in or custom role
+    pub fn assign_role(&self, user_id: Id, role_id: &str) -> Result<Role> {
+        let role = self.find_role(role_id)?;
+        self.roles()?
+            .assign(user_id, &role)
+            .map_err(|e| match e {
+                hedtronix_db::DbError::NotFound(msg) => AuthorizationError::Invalid(msg),
+                e => AuthorizationError::Database(e.to_string()),
+            })?;
+        if let Ok(mut user_roles) = self.user_roles.write() {
+            user_roles.remove(&user_id);
+        }
+
+        Ok(role)
+    }
+}
+
+/// A grant on every resource, which would give a custom role the reach of
+/// Admin
+fn is_wildcard(permission: &Permission) -> bool {
+    permission.resource == "*"
+}
+
+fn wildcard_error(permission: &Permission) -> AuthorizationError {
+    AuthorizationError::Invalid(format!("Custom roles cannot be granted {}", permission))
 }
 
 /// Department-scoped permission check
@@ -204,28 +298,128 @@ pub fn check_department_access(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use hedtronix_core::User;
+
+    fn checker() -> PermissionChecker {
+        let mut db = Database::in_memory().unwrap();
+        db.initialize().unwrap();
+        PermissionChecker::new(db)
+    }
 
     #[test]
     fn test_admin_permissions() {
-        assert!(PermissionChecker::has_permission(UserRole::Admin, "anything", "anything"));
+        assert!(PermissionChecker::built_in().has_permission("ADMIN", "anything", "anything"));
+        assert!(checker().has_permission("ADMIN", "anything", "anything"));
     }
 
     #[test]
     fn test_physician_permissions() {
-        assert!(PermissionChecker::has_permission(UserRole::Physician, "patients", "write"));
-        assert!(PermissionChecker::has_permission(UserRole::Physician, "clinical_notes", "sign"));
-        assert!(!PermissionChecker::has_permission(UserRole::Physician, "billing", "adjust"));
+        let checker = checker();
+        assert!(checker.has_permission("PHYSICIAN", "patients", "write"));
+        assert!(checker.has_permission("PHYSICIAN", "clinical_notes", "sign"));
+        assert!(!checker.has_permission("PHYSICIAN", "billing", "adjust"));
     }
 
     #[test]
     fn test_nurse_permissions() {
-        assert!(PermissionChecker::has_permission(UserRole::Nurse, "vitals", "create"));
-        assert!(!PermissionChecker::has_permission(UserRole::Nurse, "clinical_notes", "sign"));
+        let checker = checker();
+        assert!(checker.has_permission("NURSE", "vitals", "create"));
+        assert!(!checker.has_permission("NURSE", "clinical_notes", "sign"));
     }
 
     #[test]
     fn test_receptionist_permissions() {
-        assert!(PermissionChecker::has_permission(UserRole::Receptionist, "appointments", "check_in"));
-        assert!(!PermissionChecker::has_permission(UserRole::Receptionist, "clinical_notes", "write"));
+        let checker = checker();
+        assert!(checker.has_permission("RECEPTIONIST", "appointments", "check_in"));
+        assert!(!checker.has_permission("RECEPTIONIST", "clinical_notes", "write"));
+    }
+
+    #[test]
+    fn test_get_permissions_matches_checks() {
+        let checker = checker();
+        for role in hedtronix_core::ALL_USER_ROLES {
+            let permissions = checker.get_permissions(role.as_str());
+            assert_eq!(permissions.len(), default_permissions(role).len());
+            assert!(permissions.iter().all(|p| checker.has_permission(role.as_str(), &p.resource, &p.action)));
+        }
+    }
+
+    #[test]
+    fn test_custom_role_grants() {
+        let checker = checker();
+        let role = checker.create_role(CreateRole {
+            name: "Lab Tech".to_string(),
+            description: None,
+            base_role: UserRole::Nurse,
+            permissions: vec![Permission::new("lab_results", "create")],
+        }).unwrap();
+
+        assert!(checker.has_permission(&role.id, "lab_results", "create"));
+        assert!(!checker.has_permission(&role.id, "patients", "write"));
+
+        // Grants take effect without a restart
+        checker.grant(&role.id, &Permission::new("patients", "read")).unwrap();
+        assert!(checker.has_permission(&role.id, "patients", "read"));
+        checker.revoke(&role.id, &Permission::new("lab_results", "create")).unwrap();
+        assert!(!checker.has_permission(&role.id, "lab_results", "create"));
+
+        assert!(matches!(checker.delete_role("NURSE"), Err(AuthorizationError::BuiltInRole(_))));
+    }
+
+    #[test]
+    fn test_custom_roles_reject_wildcard_grants() {
+        let checker = checker();
+        let create = |permission: Permission| checker.create_role(CreateRole {
+            name: "Auditor".to_string(),
+            description: None,
+            base_role: UserRole::Nurse,
+            permissions: vec![permission],
+        });
+        assert!(matches!(create(Permission::new("*", "*")), Err(AuthorizationError::Invalid(_))));
+        assert!(matches!(create(Permission::new("*", "read")), Err(AuthorizationError::Invalid(_))));
+
+        let role = create(Permission::new("audit_logs", "read")).unwrap();
+        for wildcard in [Permission::new("*", "*"), Permission::new("*", "delete")] {
+            assert!(matches!(checker.grant(&role.id, &wildcard), Err(AuthorizationError::Invalid(_))));
+            assert!(!checker.has_permission(&role.id, "patients", "delete"));
+        }
+    }
+
+    #[test]
+    fn test_assigned_custom_role_applies_to_caller() {
+        let checker = checker();
+        let db = checker.db.clone().unwrap();
+        let user = User::new("ma@example.com".into(), "MA".into(), UserRole::Nurse, "hash".into());
+        UserRepository::new(db).create(&user).unwrap();
+
+        let role = checker.create_role(CreateRole {
+            name: "Medical Assistant".to_string(),
+            description: None,
+            base_role: UserRole::Nurse,
+            permissions: vec![Permission::new("vitals", "create")],
+        }).unwrap();
+        checker.assign_role(user.id, &role.id).unwrap();
+
+        let claims = Claims {
+            sub: user.id.to_string(),
+            email: user.email.clone(),
+            role: "NURSE".to_string(),
+            device_id: Id::new_v4().to_string(),
+            department_id: None,
+            iat: 0,
+            exp: 0,
+            jti: String::new(),
+            offline: false,
+            permissions: Vec::new(),
+        };
+
+        assert!(checker.authorize(&claims, "vitals", "create"));
+        assert!(!checker.authorize(&claims, "clinical_notes", "write"));
+        assert_eq!(checker.base_role_for(&claims), UserRole::Nurse);
+        assert!(matches!(checker.delete_role(&role.id), Err(AuthorizationError::RoleInUse(_))));
+
+        // Reassignment replaces the cached role
+        checker.assign_role(user.id, "NURSE").unwrap();
+        assert_eq!(checker.role_id_for(&claims), "NURSE");
     }
 }
@@ -0,0 +1,275 @@
+//! Role-aware field redaction
+//!
+//! Access policy decides whether a caller may see a record; redaction
+//! decides which of its fields they see. Rules are declared per entity and
+//! field path and applied to the serialized response.
+
+use std::path::Path;
+
+use hedtronix_core::UserRole;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// What to do with a matched field
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum RedactionAction {
+    /// Remove the field from the response
+    Drop,
+
+    /// Replace all but the last `visible_suffix` characters with `*`
+    Mask { visible_suffix: usize },
+
+    /// Remove list entries that mention a keyword (case-insensitive) or
+    /// start with an ICD-10 code in one of the given chapters
+    RemoveMatching {
+        #[serde(default)]
+        keywords: Vec<String>,
+        #[serde(default)]
+        code_prefixes: Vec<String>,
+    },
+}
+
+/// One redaction rule
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RedactionRule {
+    /// Entity the rule applies to, e.g. `Patient`
+    pub entity: String,
+
+    /// Dot-separated path to the field; lists along the path apply the
+    /// rest of the path to every element
+    pub field: String,
+
+    /// Roles the rule applies to
+    pub roles: Vec<UserRole>,
+
+    #[serde(flatten)]
+    pub action: RedactionAction,
+}
+
+/// Redaction rules, applied in order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RedactionPolicy {
+    pub rules: Vec<RedactionRule>,
+}
+
+impl Default for RedactionPolicy {
+    fn default() -> Self {
+        use UserRole::*;
+
+        let rule = |entity: &str, field: &str, roles: &[UserRole], action: RedactionAction| RedactionRule {
+            entity: entity.to_string(),
+            field: field.to_string(),
+            roles: roles.to_vec(),
+            action,
+        };
+        let psychiatric = RedactionAction::RemoveMatching {
+            keywords: [
+                "psychiatric", "depress", "bipolar", "schizo", "anxiety", "ptsd",
+                "suicid", "substance", "alcohol use", "opioid use",
+            ]
+            .iter()
+            .map(|k| k.to_string())
+            .collect(),
+            // Mental and behavioural disorders
+            code_prefixes: vec!["F".to_string()],
+        };
+
+        Self {
+            rules: vec![
+                // Member IDs are frequently SSNs
+                rule("Patient", "insurance.subscriber_id", &[Physician, Nurse, Receptionist, Billing],
+                    RedactionAction::Mask { visible_suffix: 4 }),
+                rule("Patient", "insurance.policy_number", &[Physician, Nurse, Receptionist],
+                    RedactionAction::Mask { visible_suffix: 4 }),
+                rule("Patient", "insurance.subscriber_dob", &[Physician, Nurse], RedactionAction::Drop),
+                rule("Patient", "problems", &[Receptionist, Billing], psychiatric),
+                rule("ClinicalNote", "content", &[Receptionist, Billing], RedactionAction::Drop),
+            ],
+        }
+    }
+}
+
+impl RedactionPolicy {
+    /// Load a policy from a JSON file
+    pub fn from_file(path: &Path) -> std::result::Result<Self, String> {
+        let json = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        serde_json::from_str(&json).map_err(|e| e.to_string())
+    }
+
+    /// Serialize a value and apply the rules for an entity and role
+    pub fn redact<T: Serialize>(&self, entity: &str, role: UserRole, value: &T) -> Value {
+        let mut value = serde_json::to_value(value).unwrap_or(Value::Null);
+        self.apply(entity, role, &mut value);
+        value
+    }
+
+    /// Apply the rules for an entity and role in place
+    pub fn apply(&self, entity: &str, role: UserRole, value: &mut Value) {
+        for rule in &self.rules {
+            if rule.entity == entity && rule.roles.contains(&role) {
+                let path: Vec<&str> = rule.field.split('.').collect();
+                apply_at(value, &path, &rule.action);
+            }
+        }
+    }
+}
+
+fn apply_at(value: &mut Value, path: &[&str], action: &RedactionAction) {
+    if let Value::Array(items) = value {
+        for item in items {
+            apply_at(item, path, action);
+        }
+        return;
+    }
+
+    let Value::Object(map) = value else {
+        return;
+    };
+
+    match path {
+        [] => {}
+        [field] => match action {
+            RedactionAction::Drop => {
+                map.remove(*field);
+            }
+            RedactionAction::Mask { visible_suffix } => {
+                if let Some(Value::String(s)) = map.get_mut(*field) {
+                    *s = mask(s, *visible_suffix);
+                }
+            }
+            RedactionAction::RemoveMatching { keywords, code_prefixes } => {
+                if let Some(Value::Array(items)) = map.get_mut(*field) {
+                    items.retain(|item| match item {
+                        Value::String(s) => !matches_entry(s, keywords, code_prefixes),
+                        _ => true,
+                    });
+                }
+            }
+        },
+        [field, rest @ ..] => {
+            if let Some(child) = map.get_mut(*field) {
+                apply_at(child, rest, action);
+            }
+        }
+    }
+}
+
+fn mask(s: &str, visible_suffix: usize) -> String {
+    let len = s.chars().count();
+    let hidden = len.saturating_sub(visible_suffix);
+    s.chars()
+        .enumerate()
+        .map(|(i, c)| if i < hidden { '*' } else { c })
+        .collect()
+}
+
+fn matches_entry(entry: &str, keywords: &[String], code_prefixes: &[String]) -> bool {
+    let lower = entry.to_lowercase();
+    if keywords.iter().any(|k| lower.contains(&k.to_lowercase())) {
+        return true;
+    }
+
+    // An ICD-10 code is a chapter letter followed by two digits
+    let code = entry.split_whitespace().next().unwrap_or("");
+    code_prefixes.iter().any(|prefix| {
+        code.len() >= 3
+            && code.to_uppercase().starts_with(&prefix.to_uppercase())
+            && code.get(prefix.len()..)
+                .and_then(|rest| rest.chars().next())
+                .is_some_and(|c| c.is_ascii_digit())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn patient() -> Value {
+        json!({
+            "first_name": "Pat",
+            "insurance": {
+                "provider": "Acme Health",
+                "policy_number": "POL123456789",
+                "subscriber_id": "123-45-6789",
+                "subscriber_dob": "1980-01-01"
+            },
+            "problems": ["E11.9 Type 2 diabetes", "F32.9 Major depressive disorder", "History of PTSD"]
+        })
+    }
+
+    fn note() -> Value {
+        json!({ "id": "n1", "note_type": "PROGRESS_NOTE", "content": "Patient reports..." })
+    }
+
+    fn redact(entity: &str, role: UserRole, value: Value) -> Value {
+        RedactionPolicy::default().redact(entity, role, &value)
+    }
+
+    #[test]
+    fn test_physician_redaction() {
+        let p = redact("Patient", UserRole::Physician, patient());
+        assert_eq!(p["insurance"]["subscriber_id"], "*******6789");
+        assert_eq!(p["insurance"]["policy_number"], "********6789");
+        assert!(p["insurance"].get("subscriber_dob").is_none());
+        assert_eq!(p["problems"].as_array().unwrap().len(), 3);
+        assert_eq!(redact("ClinicalNote", UserRole::Physician, note())["content"], "Patient reports...");
+    }
+
+    #[test]
+    fn test_nurse_redaction() {
+        let p = redact("Patient", UserRole::Nurse, patient());
+        assert_eq!(p["insurance"]["subscriber_id"], "*******6789");
+        assert!(p["insurance"].get("subscriber_dob").is_none());
+        assert_eq!(p["problems"].as_array().unwrap().len(), 3);
+        assert!(redact("ClinicalNote", UserRole::Nurse, note()).get("content").is_some());
+    }
+
+    #[test]
+    fn test_receptionist_redaction() {
+        let p = redact("Patient", UserRole::Receptionist, patient());
+        assert_eq!(p["insurance"]["policy_number"], "********6789");
+        assert_eq!(p["insurance"]["subscriber_dob"], "1980-01-01");
+        assert_eq!(p["problems"], json!(["E11.9 Type 2 diabetes"]));
+
+        let n = redact("ClinicalNote", UserRole::Receptionist, note());
+        assert!(n.get("content").is_none());
+        assert_eq!(n["note_type"], "PROGRESS_NOTE");
+    }
+
+    #[test]
+    fn test_billing_redaction() {
+        let p = redact("Patient", UserRole::Billing, patient());
+        // Billing needs the policy number to submit claims
+        assert_eq!(p["insurance"]["policy_number"], "POL123456789");
+        assert_eq!(p["insurance"]["subscriber_id"], "*******6789");
+        assert_eq!(p["problems"], json!(["E11.9 Type 2 diabetes"]));
+        assert!(redact("ClinicalNote", UserRole::Billing, note()).get("content").is_none());
+    }
+
+    #[test]
+    fn test_admin_and_patient_see_everything() {
+        for role in [UserRole::Admin, UserRole::Patient] {
+            assert_eq!(redact("Patient", role, patient()), patient());
+            assert_eq!(redact("ClinicalNote", role, note()), note());
+        }
+    }
+
+    #[test]
+    fn test_rules_apply_inside_lists() {
+        let list = json!([patient(), patient()]);
+        let redacted = redact("Patient", UserRole::Receptionist, list);
+        for p in redacted.as_array().unwrap() {
+            assert_eq!(p["problems"].as_array().unwrap().len(), 1);
+        }
+    }
+
+    #[test]
+    fn test_policy_round_trips_through_json() {
+        let json = serde_json::to_string(&RedactionPolicy::default()).unwrap();
+        let policy: RedactionPolicy = serde_json::from_str(&json).unwrap();
+        assert_eq!(policy.rules.len(), RedactionPolicy::default().rules.len());
+        assert_eq!(policy.rules[0].action, RedactionAction::Mask { visible_suffix: 4 });
+    }
+}
@@ -1,11 +1,34 @@
 //! Session management for authentication
 
-use hedtronix_core::{Device, Id, User, UserRole};
-use hedtronix_db::{Database, UserRepository};
+use chrono::{DateTime, Utc};
+use hedtronix_core::{
+    AuditEventType, AuditLog, Device, Id, MfaMethod, MfaSettings, RecoveryCode, RegisterDevice,
+    User, UserRole, WebauthnCredential,
+};
+use hedtronix_db::{
+    AuditRepository, Database, DeviceRepository, LoginAttemptRepository, MfaRepository,
+    UserRepository,
+};
 use hedtronix_crypto::hashing::{hash_password, verify_password};
+use hedtronix_crypto::otp::{
+    base32_decode, base32_encode, generate_totp_secret, totp_provisioning_uri, verify_totp,
+};
+use std::sync::Arc;
 use thiserror::Error;
 
-use crate::jwt::{JwtManager, TokenPair, Claims};
+use crate::device_lock::DeviceLock;
+use crate::jwt::{JwtManager, MfaChallengeClaims, TokenPair, Claims};
+use crate::lockout::LoginThrottlePolicy;
+use crate::mfa::{
+    generate_challenge, generate_recovery_codes, normalize_recovery_code, public_key_from_spki,
+    verify_assertion, verify_client_data, MfaPolicy, WebauthnAssertion,
+};
+use crate::offline::OfflineTokenPolicy;
+use crate::password::PasswordPolicy;
+use crate::permissions::PermissionChecker;
+
+/// Clock drift tolerated on TOTP codes, in 30 second steps
+const TOTP_SKEW_STEPS: u64 = 1;
 
 /// Session error types
 #[derive(Error, Debug)]
@@ -19,12 +42,36 @@ pub enum SessionError {
     #[error("User disabled")]
     UserDisabled,
     
+    #[error("Account locked until {0}")]
+    AccountLocked(DateTime<Utc>),
+    
+    #[error("Too many login attempts; retry in {0} seconds")]
+    Throttled(i64),
+    
+    #[error("Password policy: {0}")]
+    PasswordPolicy(String),
+    
     #[error("Device not registered")]
     DeviceNotRegistered,
     
     #[error("Device revoked")]
     DeviceRevoked,
     
+    #[error("Invalid device key: {0}")]
+    InvalidDeviceKey(String),
+    
+    #[error("Offline token denied: {0}")]
+    OfflineTokenDenied(String),
+    
+    #[error("Password change required")]
+    PasswordChangeRequired,
+    
+    #[error("Invalid MFA code")]
+    InvalidMfaCode,
+    
+    #[error("MFA error: {0}")]
+    Mfa(String),
+    
     #[error("Token error: {0}")]
     Token(String),
     
@@ -37,46 +84,232 @@ pub type Result<T> = std::result::Result<T, SessionError>;
 
 /// Authentication service
 pub struct AuthService {
-    jwt_manager: JwtManager,
+    jwt_manager: Arc<JwtManager>,
+    mfa_policy: Arc<MfaPolicy>,
+    throttle_policy: Arc<LoginThrottlePolicy>,
+    password_policy: Arc<PasswordPolicy>,
+    permissions: Arc<PermissionChecker>,
+    device_lock: Option<Arc<DeviceLock>>,
     db: Database,
 }
 
 impl AuthService {
-    pub fn new(jwt_secret: &[u8], db: Database) -> Self {
+    pub fn new(jwt_manager: Arc<JwtManager>, db: Database) -> Self {
         Self {
-            jwt_manager: JwtManager::new(jwt_secret),
+            jwt_manager,
+            mfa_policy: Arc::new(MfaPolicy::default()),
+            throttle_policy: Arc::new(LoginThrottlePolicy::default()),
+            password_policy: Arc::new(PasswordPolicy::default()),
+            permissions: Arc::new(PermissionChecker::new(db.clone())),
+            device_lock: None,
             db,
         }
     }
 
-    /// Authenticate with email and password
+    /// Replace the default MFA policy
+    pub fn with_mfa_policy(mut self, mfa_policy: Arc<MfaPolicy>) -> Self {
+        self.mfa_policy = mfa_policy;
+        self
+    }
+
+    /// Share a permission checker, and its cache, with the caller
+    pub fn with_permission_checker(mut self, permissions: Arc<PermissionChecker>) -> Self {
+        self.permissions = permissions;
+        self
+    }
+
+    /// Replace the default lockout and throttling policy
+    pub fn with_throttle_policy(mut self, throttle_policy: Arc<LoginThrottlePolicy>) -> Self {
+        self.throttle_policy = throttle_policy;
+        self
+    }
+
+    /// Replace the default password policy
+    pub fn with_password_policy(mut self, password_policy: Arc<PasswordPolicy>) -> Self {
+        self.password_policy = password_policy;
+        self
+    }
+
+    /// Unlock the device key with the password once login completes and
+    /// rewrap it when the password changes, if the device is locked by
+    /// passphrase
+    pub fn with_device_lock(mut self, device_lock: Option<Arc<DeviceLock>>) -> Self {
+        self.device_lock = device_lock;
+        self
+    }
+
+    /// Authenticate with email and password. Users with an enrolled second
+    /// factor, or whose role requires one, get an MFA challenge instead of
+    /// tokens.
+    ///
+    /// Repeated failures are slowed down per account and per client IP, and
+    /// lock the account once the throttle policy's limit is reached.
     pub fn login(
         &self,
         email: &str,
         password: &str,
         device_id: Id,
-    ) -> Result<AuthResponse> {
+        ip_address: Option<&str>,
+    ) -> Result<LoginResponse> {
+        let attempts = LoginAttemptRepository::new(self.db.clone());
+        if let Some(ip) = ip_address {
+            self.check_ip_throttle(&attempts, ip)?;
+        }
+
         let user_repo = UserRepository::new(self.db.clone());
         
         // Find user by email
-        let user = user_repo.find_by_email(email)
+        let user = match user_repo.find_by_email(email)
             .map_err(|e| SessionError::Database(e.to_string()))?
-            .ok_or(SessionError::UserNotFound)?;
+        {
+            Some(user) => user,
+            None => {
+                // Hash anyway and answer as for a wrong password, so neither
+                // the response nor its timing shows whether the account exists
+                let _ = verify_password(password, dummy_password_hash());
+                attempts.record(email, None, ip_address, false)
+                    .map_err(|e| SessionError::Database(e.to_string()))?;
+                return Err(SessionError::InvalidCredentials);
+            }
+        };
 
         // Check if user is active
         if !user.active {
             return Err(SessionError::UserDisabled);
         }
 
+        // A locked account is refused before the password is even checked
+        if let Some(until) = user.locked_until.filter(|_| user.is_locked()) {
+            return Err(SessionError::AccountLocked(until));
+        }
+        if let Some(retry_at) = self.throttle_policy
+            .retry_at(user.failed_login_attempts, user.last_failed_login_at)
+        {
+            return Err(SessionError::Throttled(seconds_until(retry_at)));
+        }
+
         // Verify password
         let valid = verify_password(password, &user.password_hash)
-            .map_err(|_| SessionError::InvalidCredentials)?;
+            .unwrap_or(false);
         
         if !valid {
-            return Err(SessionError::InvalidCredentials);
+            attempts.record(email, Some(user.id), ip_address, false)
+                .map_err(|e| SessionError::Database(e.to_string()))?;
+            return Err(self.record_failure(user, SessionError::InvalidCredentials));
+        }
+
+        attempts.record(email, Some(user.id), ip_address, true)
+            .map_err(|e| SessionError::Database(e.to_string()))?;
+
+        self.check_device(&user, device_id)?;
+
+        // The password is verified, so it can open the device key, but the
+        // keys are only loaded once any second factor has passed too
+        if let Some(device_lock) = &self.device_lock {
+            let valid_for = self.jwt_manager.mfa_token_expiry().to_std().unwrap_or_default();
+            if let Err(e) = device_lock.stage_unlock(user.id, password, valid_for) {
+                tracing::warn!("Login by {} did not open the device key: {}", user.id, e);
+            }
+        }
+
+        let methods = self.enrolled_methods(user.id)?;
+        if methods.is_empty() && !self.mfa_policy.requires_mfa(user.role) {
+            return self.complete_login(user, device_id);
+        }
+
+        let challenge = generate_challenge().map_err(|e| SessionError::Mfa(e.to_string()))?;
+        let mfa_token = self.jwt_manager.create_mfa_token(user.id, device_id, &challenge)
+            .map_err(|e| SessionError::Token(e.to_string()))?;
+
+        let webauthn = if methods.contains(&MfaMethod::Webauthn) {
+            let credentials = self.mfa_repo().find_credentials_by_user(user.id)
+                .map_err(|e| SessionError::Database(e.to_string()))?;
+            Some(WebauthnRequestOptions {
+                challenge,
+                rp_id: self.mfa_policy.rp_id.clone(),
+                allow_credentials: credentials.into_iter().map(|c| c.id).collect(),
+            })
+        } else {
+            None
+        };
+
+        Ok(LoginResponse::MfaRequired(MfaChallenge {
+            mfa_token,
+            expires_in: self.jwt_manager.mfa_token_expiry().num_seconds(),
+            enrollment_required: methods.is_empty(),
+            methods,
+            webauthn,
+        }))
+    }
+
+    /// Refuse an IP that has failed too often, or too recently, within the
+    /// throttle window
+    fn check_ip_throttle(&self, attempts: &LoginAttemptRepository, ip: &str) -> Result<()> {
+        let (failures, latest) = attempts
+            .recent_ip_failures(ip, self.throttle_policy.ip_window_start())
+            .map_err(|e| SessionError::Database(e.to_string()))?;
+
+        if failures >= self.throttle_policy.max_ip_failures {
+            let window_end = latest.unwrap_or_else(Utc::now)
+                + chrono::Duration::minutes(self.throttle_policy.ip_window_minutes);
+            return Err(SessionError::Throttled(seconds_until(window_end)));
+        }
+        if let Some(retry_at) = self.throttle_policy.retry_at(failures, latest) {
+            return Err(SessionError::Throttled(seconds_until(retry_at)));
+        }
+
+        Ok(())
+    }
+
+    /// Count a failed password or second factor against the account,
+    /// locking it at the policy limit. Returns the error to report.
+    fn record_failure(&self, mut user: User, error: SessionError) -> SessionError {
+        user.failed_login_attempts += 1;
+        user.last_failed_login_at = Some(Utc::now());
+        user.locked_until = self.throttle_policy.lock_until(user.failed_login_attempts);
+
+        if let Err(e) = UserRepository::new(self.db.clone()).record_login_failure(&user) {
+            return SessionError::Database(e.to_string());
+        }
+
+        match user.locked_until {
+            Some(until) => SessionError::AccountLocked(until),
+            None => error,
+        }
+    }
+
+    /// Final step of login once every factor has been checked: unlock the
+    /// device with the key the password opened. Accounts flagged for a
+    /// password change only get a token that can set one.
+    fn complete_login(&self, user: User, device_id: Id) -> Result<LoginResponse> {
+        // Failures only reset once every factor has passed, so a correct
+        // password does not wipe the count of wrong codes
+        UserRepository::new(self.db.clone())
+            .clear_login_failures(user.id, Some(Utc::now()))
+            .map_err(|e| SessionError::Database(e.to_string()))?;
+
+        if let Some(device_lock) = &self.device_lock {
+            if let Err(e) = device_lock.complete_unlock(user.id) {
+                tracing::warn!("Login by {} did not unlock the device: {}", user.id, e);
+            }
+        }
+
+        if user.must_change_password {
+            let password_change_token = self.jwt_manager
+                .create_password_change_token(user.id, device_id)
+                .map_err(|e| SessionError::Token(e.to_string()))?;
+
+            return Ok(LoginResponse::PasswordChangeRequired(PasswordChangeChallenge {
+                password_change_token,
+                expires_in: self.jwt_manager.mfa_token_expiry().num_seconds(),
+            }));
         }
 
-        // Create tokens
+        Ok(LoginResponse::Authenticated(self.issue_tokens(user, device_id)?))
+    }
+
+    /// Create the token pair for a fully authenticated user
+    fn issue_tokens(&self, user: User, device_id: Id) -> Result<AuthResponse> {
         let access_token = self.jwt_manager.create_access_token(
             user.id,
             &user.email,
@@ -88,36 +321,363 @@ impl AuthService {
         let refresh_token = self.jwt_manager.create_refresh_token(user.id, device_id)
             .map_err(|e| SessionError::Token(e.to_string()))?;
 
-        let offline_token = self.jwt_manager.create_offline_token(
-            user.id,
-            &user.email,
-            user.role,
-            device_id,
-            user.department_id,
-        ).map_err(|e| SessionError::Token(e.to_string()))?;
-
         Ok(AuthResponse {
             tokens: TokenPair::new(access_token, refresh_token, 900),
-            offline_token,
             user: UserInfo::from(user),
         })
     }
 
+    fn mfa_repo(&self) -> MfaRepository {
+        MfaRepository::new(self.db.clone())
+    }
+
+    fn find_active_user(&self, user_id: Option<Id>) -> Result<User> {
+        let user = UserRepository::new(self.db.clone())
+            .find_by_id(user_id.ok_or(SessionError::UserNotFound)?)
+            .map_err(|e| SessionError::Database(e.to_string()))?
+            .ok_or(SessionError::UserNotFound)?;
+
+        if !user.active {
+            return Err(SessionError::UserDisabled);
+        }
+
+        Ok(user)
+    }
+
+    /// Second factors the user has enrolled
+    pub fn enrolled_methods(&self, user_id: Id) -> Result<Vec<MfaMethod>> {
+        let repo = self.mfa_repo();
+        let mut methods = Vec::new();
+
+        let settings = repo.find_settings(user_id)
+            .map_err(|e| SessionError::Database(e.to_string()))?;
+        if settings.is_some_and(|s| s.totp_enabled) {
+            methods.push(MfaMethod::Totp);
+        }
+
+        let credentials = repo.find_credentials_by_user(user_id)
+            .map_err(|e| SessionError::Database(e.to_string()))?;
+        if !credentials.is_empty() {
+            methods.push(MfaMethod::Webauthn);
+        }
+
+        // Recovery codes only stand in for a factor that exists
+        if !methods.is_empty() {
+            let codes = repo.find_unused_recovery_codes(user_id)
+                .map_err(|e| SessionError::Database(e.to_string()))?;
+            if !codes.is_empty() {
+                methods.push(MfaMethod::RecoveryCode);
+            }
+        }
+
+        Ok(methods)
+    }
+
+    /// Complete login by answering the MFA challenge
+    pub fn verify_mfa(&self, req: &MfaVerifyRequest) -> Result<LoginResponse> {
+        let claims = self.jwt_manager.validate_mfa_token(&req.mfa_token)
+            .map_err(|e| SessionError::Token(e.to_string()))?;
+        let user = self.find_active_user(claims.user_id())?;
+        let device_id = claims.device_id().ok_or(SessionError::DeviceNotRegistered)?;
+
+        // Guessing codes counts towards the same lockout and delays as
+        // passwords
+        if let Some(until) = user.locked_until.filter(|_| user.is_locked()) {
+            return Err(SessionError::AccountLocked(until));
+        }
+        if let Some(retry_at) = self.throttle_policy
+            .retry_at(user.failed_login_attempts, user.last_failed_login_at)
+        {
+            return Err(SessionError::Throttled(seconds_until(retry_at)));
+        }
+
+        let verified = match req.method {
+            MfaMethod::Totp => self.verify_totp_code(&user, req.code.as_deref()),
+            MfaMethod::RecoveryCode => self.consume_recovery_code(&user, req.code.as_deref()),
+            MfaMethod::Webauthn => match req.assertion.as_ref() {
+                Some(assertion) => self.verify_webauthn(&user, &claims, assertion),
+                None => Err(SessionError::InvalidMfaCode),
+            },
+        };
+
+        match verified {
+            Ok(()) => {}
+            Err(e @ (SessionError::InvalidMfaCode | SessionError::Mfa(_))) => {
+                return Err(self.record_failure(user, e));
+            }
+            Err(e) => return Err(e),
+        }
+
+        // A challenge completes one login
+        let expires_at = DateTime::from_timestamp(claims.exp, 0).unwrap_or_else(Utc::now);
+        let fresh = self.mfa_repo().consume_challenge(&claims.jti, user.id, expires_at)
+            .map_err(|e| SessionError::Database(e.to_string()))?;
+        if !fresh {
+            return Err(SessionError::Token("MFA challenge has already been used".to_string()));
+        }
+
+        self.complete_login(user, device_id)
+    }
+
+    fn verify_totp_code(&self, user: &User, code: Option<&str>) -> Result<()> {
+        let repo = self.mfa_repo();
+        let settings = repo.find_settings(user.id)
+            .map_err(|e| SessionError::Database(e.to_string()))?
+            .filter(|s| s.totp_enabled)
+            .ok_or(SessionError::InvalidMfaCode)?;
+        let secret = settings.totp_secret.as_deref()
+            .and_then(|s| base32_decode(s).ok())
+            .ok_or(SessionError::InvalidMfaCode)?;
+
+        let step = verify_totp(&secret, code.unwrap_or_default(), Utc::now().timestamp() as u64, TOTP_SKEW_STEPS)
+            .ok_or(SessionError::InvalidMfaCode)?;
+
+        // A code is only good once
+        let fresh = repo.advance_totp_step(user.id, step as i64)
+            .map_err(|e| SessionError::Database(e.to_string()))?;
+        if !fresh {
+            return Err(SessionError::InvalidMfaCode);
+        }
+
+        Ok(())
+    }
+
+    fn consume_recovery_code(&self, user: &User, code: Option<&str>) -> Result<()> {
+        let code = normalize_recovery_code(code.unwrap_or_default());
+        let repo = self.mfa_repo();
+        let codes = repo.find_unused_recovery_codes(user.id)
+            .map_err(|e| SessionError::Database(e.to_string()))?;
+
+        let matched = codes.iter()
+            .find(|c| verify_password(&code, &c.code_hash).unwrap_or(false))
+            .ok_or(SessionError::InvalidMfaCode)?;
+
+        let consumed = repo.consume_recovery_code(matched.id)
+            .map_err(|e| SessionError::Database(e.to_string()))?;
+        if !consumed {
+            return Err(SessionError::InvalidMfaCode);
+        }
+
+        Ok(())
+    }
+
+    fn verify_webauthn(
+        &self,
+        user: &User,
+        claims: &MfaChallengeClaims,
+        assertion: &WebauthnAssertion,
+    ) -> Result<()> {
+        let repo = self.mfa_repo();
+        let credential = repo.find_credentials_by_user(user.id)
+            .map_err(|e| SessionError::Database(e.to_string()))?
+            .into_iter()
+            .find(|c| c.id == assertion.credential_id)
+            .ok_or(SessionError::InvalidMfaCode)?;
+
+        let sign_count = verify_assertion(&credential, assertion, &claims.challenge, &self.mfa_policy)
+            .map_err(|e| SessionError::Mfa(e.to_string()))?;
+
+        repo.update_credential_usage(&credential.id, sign_count)
+            .map_err(|e| SessionError::Database(e.to_string()))
+    }
+
+    /// Resolve who is managing MFA enrollment from a bearer token. This is
+    /// either a signed-in user, or one midway through login who has no
+    /// factor yet. A challenge token can never change existing factors,
+    /// since that would let a password alone replace them.
+    pub fn mfa_enrollment_user(&self, token: &str) -> Result<User> {
+        if let Ok(claims) = self.jwt_manager.validate_token(token) {
+            if claims.offline {
+                return Err(SessionError::Mfa("Offline tokens cannot manage MFA".to_string()));
+            }
+            return self.find_active_user(claims.user_id());
+        }
+
+        let claims = self.jwt_manager.validate_mfa_token(token)
+            .map_err(|e| SessionError::Token(e.to_string()))?;
+        let user = self.find_active_user(claims.user_id())?;
+
+        if !self.enrolled_methods(user.id)?.is_empty() {
+            return Err(SessionError::Mfa(
+                "Complete MFA before changing enrolled factors".to_string(),
+            ));
+        }
+
+        Ok(user)
+    }
+
+    /// Start TOTP enrollment by generating a secret for the authenticator app
+    pub fn enroll_totp(&self, user: &User) -> Result<TotpEnrollment> {
+        let repo = self.mfa_repo();
+        let mut settings = repo.find_settings(user.id)
+            .map_err(|e| SessionError::Database(e.to_string()))?
+            .unwrap_or_else(|| MfaSettings::new(user.id));
+
+        if settings.totp_enabled {
+            return Err(SessionError::Mfa("TOTP is already enrolled".to_string()));
+        }
+
+        let secret = generate_totp_secret().map_err(|e| SessionError::Mfa(e.to_string()))?;
+        settings.totp_secret = Some(base32_encode(&secret));
+        settings.updated_at = Utc::now();
+        repo.save_settings(&settings)
+            .map_err(|e| SessionError::Database(e.to_string()))?;
+
+        Ok(TotpEnrollment {
+            secret: base32_encode(&secret),
+            otpauth_uri: totp_provisioning_uri(&self.mfa_policy.totp_issuer, &user.email, &secret),
+        })
+    }
+
+    /// Finish TOTP enrollment with a code from the authenticator app
+    pub fn confirm_totp(&self, user: &User, code: &str) -> Result<RecoveryCodesResponse> {
+        let repo = self.mfa_repo();
+        let mut settings = repo.find_settings(user.id)
+            .map_err(|e| SessionError::Database(e.to_string()))?
+            .filter(|s| !s.totp_enabled)
+            .ok_or_else(|| SessionError::Mfa("No pending TOTP enrollment".to_string()))?;
+        let secret = settings.totp_secret.as_deref()
+            .and_then(|s| base32_decode(s).ok())
+            .ok_or_else(|| SessionError::Mfa("No pending TOTP enrollment".to_string()))?;
+
+        verify_totp(&secret, code, Utc::now().timestamp() as u64, TOTP_SKEW_STEPS)
+            .ok_or(SessionError::InvalidMfaCode)?;
+
+        settings.totp_enabled = true;
+        settings.updated_at = Utc::now();
+        repo.save_settings(&settings)
+            .map_err(|e| SessionError::Database(e.to_string()))?;
+
+        self.issue_initial_recovery_codes(user.id)
+    }
+
+    /// Challenge for registering a WebAuthn authenticator
+    pub fn webauthn_registration_options(&self, user: &User) -> Result<WebauthnRegistrationOptions> {
+        let repo = self.mfa_repo();
+        let mut settings = repo.find_settings(user.id)
+            .map_err(|e| SessionError::Database(e.to_string()))?
+            .unwrap_or_else(|| MfaSettings::new(user.id));
+
+        let challenge = generate_challenge().map_err(|e| SessionError::Mfa(e.to_string()))?;
+        settings.webauthn_challenge = Some(challenge.clone());
+        settings.updated_at = Utc::now();
+        repo.save_settings(&settings)
+            .map_err(|e| SessionError::Database(e.to_string()))?;
+
+        Ok(WebauthnRegistrationOptions {
+            challenge,
+            rp_id: self.mfa_policy.rp_id.clone(),
+            rp_name: self.mfa_policy.totp_issuer.clone(),
+            user_id: user.id.to_string(),
+            user_name: user.email.clone(),
+        })
+    }
+
+    /// Register a WebAuthn authenticator. Attestation is not verified; the
+    /// credential is trusted because the user is already authenticated.
+    pub fn register_webauthn(
+        &self,
+        user: &User,
+        req: &WebauthnRegistrationRequest,
+    ) -> Result<RecoveryCodesResponse> {
+        use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+
+        let repo = self.mfa_repo();
+        let mut settings = repo.find_settings(user.id)
+            .map_err(|e| SessionError::Database(e.to_string()))?
+            .ok_or_else(|| SessionError::Mfa("No pending WebAuthn registration".to_string()))?;
+        let challenge = settings.webauthn_challenge.take()
+            .ok_or_else(|| SessionError::Mfa("No pending WebAuthn registration".to_string()))?;
+
+        // The challenge is single use whether or not registration succeeds
+        settings.updated_at = Utc::now();
+        repo.save_settings(&settings)
+            .map_err(|e| SessionError::Database(e.to_string()))?;
+
+        let decode = |value: &str| URL_SAFE_NO_PAD.decode(value.trim_end_matches('='))
+            .map_err(|_| SessionError::Mfa("Invalid base64url encoding".to_string()));
+
+        verify_client_data(&decode(&req.client_data_json)?, "webauthn.create", &challenge, &self.mfa_policy)
+            .map_err(|e| SessionError::Mfa(e.to_string()))?;
+        public_key_from_spki(req.algorithm, &decode(&req.public_key)?)
+            .map_err(|e| SessionError::Mfa(e.to_string()))?;
+
+        let credential = WebauthnCredential {
+            id: req.credential_id.trim_end_matches('=').to_string(),
+            user_id: user.id,
+            public_key: req.public_key.trim_end_matches('=').to_string(),
+            algorithm: req.algorithm,
+            sign_count: 0,
+            name: req.name.clone(),
+            created_at: Utc::now(),
+            last_used_at: None,
+        };
+        repo.create_credential(&credential)
+            .map_err(|e| SessionError::Database(e.to_string()))?;
+
+        self.issue_initial_recovery_codes(user.id)
+    }
+
+    /// Replace a user's recovery codes, invalidating the old set
+    pub fn regenerate_recovery_codes(&self, user: &User) -> Result<RecoveryCodesResponse> {
+        if self.enrolled_methods(user.id)?.is_empty() {
+            return Err(SessionError::Mfa("No second factor is enrolled".to_string()));
+        }
+
+        let codes = generate_recovery_codes().map_err(|e| SessionError::Mfa(e.to_string()))?;
+        let stored = codes.iter()
+            .map(|code| {
+                hash_password(&normalize_recovery_code(code))
+                    .map(|hash| RecoveryCode::new(user.id, hash))
+                    .map_err(|e| SessionError::Mfa(e.to_string()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        self.mfa_repo().replace_recovery_codes(user.id, &stored)
+            .map_err(|e| SessionError::Database(e.to_string()))?;
+
+        Ok(RecoveryCodesResponse { recovery_codes: codes })
+    }
+
+    /// Recovery codes come with the first factor; later factors keep the
+    /// existing set
+    fn issue_initial_recovery_codes(&self, user_id: Id) -> Result<RecoveryCodesResponse> {
+        let existing = self.mfa_repo().find_unused_recovery_codes(user_id)
+            .map_err(|e| SessionError::Database(e.to_string()))?;
+        if !existing.is_empty() {
+            return Ok(RecoveryCodesResponse { recovery_codes: Vec::new() });
+        }
+
+        let user = self.find_active_user(Some(user_id))?;
+        self.regenerate_recovery_codes(&user)
+    }
+
     /// Refresh access token using refresh token
     pub fn refresh(&self, refresh_token: &str) -> Result<TokenPair> {
-        let claims = self.jwt_manager.validate_token(refresh_token)
+        let claims = self.jwt_manager.validate_refresh_token(refresh_token)
             .map_err(|e| SessionError::Token(e.to_string()))?;
+        let user_id = claims.user_id()
+            .ok_or_else(|| SessionError::Token("Refresh token has no valid subject".to_string()))?;
+        let device_id = claims.device_id()
+            .ok_or_else(|| SessionError::Token("Refresh token has no valid device".to_string()))?;
 
         let user_repo = UserRepository::new(self.db.clone());
-        let user = user_repo.find_by_id(claims.user_id().unwrap())
+        let user = user_repo.find_by_id(user_id)
             .map_err(|e| SessionError::Database(e.to_string()))?
             .ok_or(SessionError::UserNotFound)?;
 
+        // A refresh token only outlives the checks login made while they
+        // still hold
         if !user.active {
             return Err(SessionError::UserDisabled);
         }
-
-        let device_id = claims.device_id().unwrap();
+        if let Some(until) = user.locked_until.filter(|_| user.is_locked()) {
+            return Err(SessionError::AccountLocked(until));
+        }
+        if user.must_change_password {
+            return Err(SessionError::PasswordChangeRequired);
+        }
+        self.check_device(&user, device_id)?;
 
         let access_token = self.jwt_manager.create_access_token(
             user.id,
@@ -149,6 +709,230 @@ impl AuthService {
             .ok_or(SessionError::UserNotFound)
     }
 
+    /// A known device must belong to the user and still be trusted
+    fn check_device(&self, user: &User, device_id: Id) -> Result<()> {
+        if let Some(device) = self.find_device(device_id)? {
+            if device.user_id != user.id {
+                return Err(SessionError::DeviceNotRegistered);
+            }
+            if !device.is_valid() {
+                return Err(SessionError::DeviceRevoked);
+            }
+        }
+        Ok(())
+    }
+
+    fn find_device(&self, device_id: Id) -> Result<Option<Device>> {
+        DeviceRepository::new(self.db.clone())
+            .find_by_id(device_id)
+            .map_err(|e| SessionError::Database(e.to_string()))
+    }
+
+    /// Register the device the caller signed in with. A device registered
+    /// before it had an agreement key may add one by registering again.
+    pub fn register_device(&self, claims: &Claims, req: RegisterDevice) -> Result<Device> {
+        let user_id = claims.user_id().ok_or(SessionError::UserNotFound)?;
+        let device_id = claims.device_id().ok_or(SessionError::DeviceNotRegistered)?;
+        if let Some(key) = &req.agreement_key {
+            check_agreement_key(key)?;
+        }
+
+        if let Some(mut device) = self.find_device(device_id)? {
+            if device.user_id != user_id {
+                return Err(SessionError::DeviceNotRegistered);
+            }
+            if !device.is_valid() {
+                return Err(SessionError::DeviceRevoked);
+            }
+            if device.agreement_key.is_none() && req.agreement_key.is_some() {
+                device.agreement_key = req.agreement_key;
+                DeviceRepository::new(self.db.clone())
+                    .update(&device)
+                    .map_err(|e| SessionError::Database(e.to_string()))?;
+            }
+            return Ok(device);
+        }
+
+        let mut device = Device::new(user_id, req.public_key, req.device_type, req.user_agent);
+        device.id = device_id;
+        device.device_name = req.device_name;
+        device.agreement_key = req.agreement_key;
+
+        DeviceRepository::new(self.db.clone())
+            .create(&device)
+            .map_err(|e| SessionError::Database(e.to_string()))?;
+
+        Ok(device)
+    }
+
+    /// Issue an offline token for the caller's registered device, capped by
+    /// the offline token policy. Every issuance is audited.
+    pub fn issue_offline_token(
+        &self,
+        claims: &Claims,
+        policy: &OfflineTokenPolicy,
+        duration_hours: u32,
+        permissions: Option<&[String]>,
+    ) -> Result<OfflineTokenResponse> {
+        if claims.offline {
+            return Err(SessionError::OfflineTokenDenied(
+                "An offline token cannot be used to obtain another".to_string(),
+            ));
+        }
+
+        let user_id = claims.user_id().ok_or(SessionError::UserNotFound)?;
+        let user = UserRepository::new(self.db.clone())
+            .find_by_id(user_id)
+            .map_err(|e| SessionError::Database(e.to_string()))?
+            .ok_or(SessionError::UserNotFound)?;
+
+        if !user.active {
+            return Err(SessionError::UserDisabled);
+        }
+
+        let device_id = claims.device_id().ok_or(SessionError::DeviceNotRegistered)?;
+        let device = self.find_device(device_id)?
+            .filter(|d| d.user_id == user.id)
+            .ok_or(SessionError::DeviceNotRegistered)?;
+
+        if !device.is_valid() {
+            return Err(SessionError::DeviceRevoked);
+        }
+
+        let grant = policy
+            .evaluate(
+                user.role,
+                device.device_type,
+                &self.permissions.get_permissions(user.role_key()),
+                duration_hours,
+                permissions,
+            )
+            .map_err(SessionError::OfflineTokenDenied)?;
+
+        let (offline_token, token_claims) = self.jwt_manager.create_offline_token(
+            user.id,
+            &user.email,
+            user.role,
+            device.id,
+            user.department_id,
+            &grant,
+        ).map_err(|e| SessionError::Token(e.to_string()))?;
+
+        let audit = AuditLog::new(
+            AuditEventType::Create,
+            Some(user.id),
+            Some(device.id),
+            "OfflineToken".to_string(),
+            token_claims.jti.clone(),
+            serde_json::json!({
+                "requested_hours": duration_hours,
+                "granted_hours": grant.duration.num_hours(),
+                "device_type": device.device_type.as_str(),
+                "permissions": grant.permissions,
+            }),
+        );
+        AuditRepository::new(self.db.clone())
+            .append(&audit)
+            .map_err(|e| SessionError::Database(e.to_string()))?;
+
+        Ok(OfflineTokenResponse {
+            offline_token,
+            valid_until: DateTime::from_timestamp(token_claims.exp, 0).unwrap_or_else(Utc::now),
+            granted_hours: grant.duration.num_hours(),
+            permissions: grant.permissions,
+        })
+    }
+
+    /// Change a password. The caller is either signed in, or holds the
+    /// password change token from login, in which case a session is issued
+    /// once the new password is set.
+    pub fn change_password(
+        &self,
+        token: &str,
+        current_password: &str,
+        new_password: &str,
+    ) -> Result<PasswordChangeResponse> {
+        let (user_id, pending_device) = match self.jwt_manager.validate_token(token) {
+            Ok(claims) if claims.offline => {
+                return Err(SessionError::Token(
+                    "Offline tokens cannot change passwords".to_string(),
+                ));
+            }
+            Ok(claims) => (claims.user_id(), None),
+            Err(_) => {
+                let claims = self.jwt_manager.validate_password_change_token(token)
+                    .map_err(|e| SessionError::Token(e.to_string()))?;
+                let device_id = claims.device_id().ok_or(SessionError::DeviceNotRegistered)?;
+                (claims.user_id(), Some(device_id))
+            }
+        };
+
+        let user = self.find_active_user(user_id)?;
+        if let Some(until) = user.locked_until.filter(|_| user.is_locked()) {
+            return Err(SessionError::AccountLocked(until));
+        }
+        if !verify_password(current_password, &user.password_hash).unwrap_or(false) {
+            return Err(self.record_failure(user, SessionError::InvalidCredentials));
+        }
+
+        self.password_policy.validate(new_password, &user.email)
+            .map_err(SessionError::PasswordPolicy)?;
+
+        let user_repo = UserRepository::new(self.db.clone());
+        let history = user_repo
+            .password_history(user.id, self.password_policy.history_size())
+            .map_err(|e| SessionError::Database(e.to_string()))?;
+        let reused = std::iter::once(&user.password_hash)
+            .chain(history.iter())
+            .any(|hash| verify_password(new_password, hash).unwrap_or(false));
+        if reused {
+            return Err(SessionError::PasswordPolicy(
+                "Password was used recently".to_string(),
+            ));
+        }
+
+        let password_hash = hash_password(new_password)
+            .map_err(|e| SessionError::Token(e.to_string()))?;
+        user_repo
+            .change_password(&user, &password_hash, false, self.password_policy.history_size())
+            .map_err(|e| SessionError::Database(e.to_string()))?;
+        if let Some(device_lock) = &self.device_lock {
+            if let Err(e) = device_lock.change_passphrase(user.id, current_password, new_password) {
+                tracing::warn!("Could not rewrap the device key for {}: {}", user.id, e);
+            }
+        }
+
+        let session = match pending_device {
+            Some(device_id) => Some(self.issue_tokens(user, device_id)?),
+            None => None,
+        };
+
+        Ok(PasswordChangeResponse { changed: true, session })
+    }
+
+    /// Lift a lockout and reset the failure count (admin only)
+    pub fn unlock_user(&self, user_id: Id) -> Result<()> {
+        let user_repo = UserRepository::new(self.db.clone());
+        user_repo.find_by_id(user_id)
+            .map_err(|e| SessionError::Database(e.to_string()))?
+            .ok_or(SessionError::UserNotFound)?;
+
+        user_repo.clear_login_failures(user_id, None)
+            .map_err(|e| SessionError::Database(e.to_string()))
+    }
+
+    /// Create an account on someone's behalf (admin only). The user must
+    /// replace the initial password at first login.
+    pub fn create_user_by_admin(
+        &self,
+        email: &str,
+        name: &str,
+        password: &str,
+        role: UserRole,
+    ) -> Result<User> {
+        self.create_user(email, name, password, role, true)
+    }
+
     /// Register a new user (admin only)
     pub fn register_user(
         &self,
@@ -157,15 +941,30 @@ impl AuthService {
         password: &str,
         role: UserRole,
     ) -> Result<User> {
+        self.create_user(email, name, password, role, false)
+    }
+
+    fn create_user(
+        &self,
+        email: &str,
+        name: &str,
+        password: &str,
+        role: UserRole,
+        must_change_password: bool,
+    ) -> Result<User> {
+        self.password_policy.validate(password, email)
+            .map_err(SessionError::PasswordPolicy)?;
+
         let password_hash = hash_password(password)
             .map_err(|e| SessionError::Token(e.to_string()))?;
 
-        let user = User::new(
+        let mut user = User::new(
             email.to_string(),
             name.to_string(),
             role,
             password_hash,
         );
+        user.must_change_password = must_change_password;
 
         let user_repo = UserRepository::new(self.db.clone());
         user_repo.create(&user)
@@ -179,10 +978,132 @@ impl AuthService {
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct AuthResponse {
     pub tokens: TokenPair,
-    pub offline_token: String,
     pub user: UserInfo,
 }
 
+/// Result of the password step of login
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "status", rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum LoginResponse {
+    Authenticated(AuthResponse),
+    MfaRequired(MfaChallenge),
+    PasswordChangeRequired(PasswordChangeChallenge),
+}
+
+/// Returned instead of tokens when the password must be changed first
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct PasswordChangeChallenge {
+    pub password_change_token: String,
+    pub expires_in: i64,
+}
+
+/// Password change request DTO
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct PasswordChangeRequest {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+/// Password change response. A session is included when the change
+/// completed a pending login.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct PasswordChangeResponse {
+    pub changed: bool,
+    pub session: Option<AuthResponse>,
+}
+
+/// Second-factor challenge returned instead of tokens
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct MfaChallenge {
+    pub mfa_token: String,
+    pub expires_in: i64,
+    /// Factors the user can answer with
+    pub methods: Vec<MfaMethod>,
+    /// The role requires MFA but nothing is enrolled yet; the MFA token may
+    /// be used to enroll a first factor
+    pub enrollment_required: bool,
+    pub webauthn: Option<WebauthnRequestOptions>,
+}
+
+/// Parameters for `navigator.credentials.get()`
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct WebauthnRequestOptions {
+    pub challenge: String,
+    pub rp_id: String,
+    pub allow_credentials: Vec<String>,
+}
+
+/// MFA verification request DTO
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct MfaVerifyRequest {
+    pub mfa_token: String,
+    pub method: MfaMethod,
+    /// TOTP or recovery code
+    pub code: Option<String>,
+    pub assertion: Option<WebauthnAssertion>,
+}
+
+/// TOTP enrollment response
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct TotpEnrollment {
+    /// Base32 secret for manual entry
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+/// TOTP confirmation request DTO
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct TotpConfirmRequest {
+    pub code: String,
+}
+
+/// Newly issued recovery codes, shown once. Empty when the user already
+/// holds an unused set.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct RecoveryCodesResponse {
+    pub recovery_codes: Vec<String>,
+}
+
+/// Parameters for `navigator.credentials.create()`
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct WebauthnRegistrationOptions {
+    pub challenge: String,
+    pub rp_id: String,
+    pub rp_name: String,
+    pub user_id: String,
+    pub user_name: String,
+}
+
+/// WebAuthn registration request DTO (binary fields base64url)
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct WebauthnRegistrationRequest {
+    pub credential_id: String,
+    /// SubjectPublicKeyInfo from `getPublicKey()`
+    pub public_key: String,
+    /// COSE algorithm from `getPublicKeyAlgorithm()`
+    pub algorithm: i64,
+    pub client_data_json: String,
+    pub name: Option<String>,
+}
+
+/// Offline token request DTO
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct OfflineTokenRequest {
+    pub duration_hours: u32,
+    /// Subset of `resource:action` permissions to carry; defaults to all the
+    /// policy allows for the role
+    pub permissions: Option<Vec<String>>,
+}
+
+/// Offline token response
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct OfflineTokenResponse {
+    pub offline_token: String,
+    pub valid_until: DateTime<Utc>,
+    pub granted_hours: i64,
+    pub permissions: Vec<String>,
+}
+
 /// Public user information
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct UserInfo {
@@ -205,6 +1126,30 @@ impl From<User> for UserInfo {
     }
 }
 
+/// Whole seconds from now until `at`, at least one
+fn seconds_until(at: DateTime<Utc>) -> i64 {
+    let millis = (at - Utc::now()).num_milliseconds();
+    ((millis + 999) / 1000).max(1)
+}
+
+/// Hash checked against when the email matches no account
+fn dummy_password_hash() -> &'static str {
+    static HASH: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+    HASH.get_or_init(|| hash_password("no account has this password").unwrap_or_default())
+}
+
+/// An agreement key must be a base64 X25519 public key
+fn check_agreement_key(key: &str) -> Result<()> {
+    use base64::{engine::general_purpose::STANDARD, Engine as _};
+
+    match STANDARD.decode(key) {
+        Ok(bytes) if bytes.len() == 32 => Ok(()),
+        _ => Err(SessionError::InvalidDeviceKey(
+            "agreement_key must be a base64 X25519 public key".to_string(),
+        )),
+    }
+}
+
 /// Login request DTO
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct LoginRequest {
@@ -218,3 +1163,376 @@ pub struct LoginRequest {
 pub struct RefreshRequest {
     pub refresh_token: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hedtronix_core::DeviceType;
+
+    const PASSWORD: &str = "correct-horse-battery-staple";
+
+    fn setup(role: UserRole, device_type: DeviceType) -> (AuthService, Claims) {
+        let mut db = Database::in_memory().unwrap();
+        db.initialize().unwrap();
+
+        let jwt_manager = Arc::new(JwtManager::new(b"test-secret-key-32-bytes-long!!"));
+        let service = AuthService::new(jwt_manager.clone(), db);
+        let user = service.register_user("doc@example.com", "Doc", PASSWORD, role).unwrap();
+
+        let token = jwt_manager
+            .create_access_token(user.id, &user.email, user.role, Id::new_v4(), None)
+            .unwrap();
+        let claims = jwt_manager.validate_token(&token).unwrap();
+
+        service.register_device(&claims, RegisterDevice {
+            public_key: "pk".to_string(),
+            agreement_key: None,
+            device_type,
+            device_name: None,
+            user_agent: "test".to_string(),
+        }).unwrap();
+
+        (service, claims)
+    }
+
+    #[test]
+    fn test_issue_offline_token_is_capped_and_audited() {
+        let (service, claims) = setup(UserRole::Physician, DeviceType::Tablet);
+        let policy = OfflineTokenPolicy::default();
+
+        let response = service.issue_offline_token(&claims, &policy, 100, None).unwrap();
+        assert_eq!(response.granted_hours, 72);
+
+        let offline = service.validate(&response.offline_token).unwrap();
+        assert!(offline.offline);
+        assert!(!offline.allows("clinical_notes", "sign"));
+
+        // Offline tokens cannot be chained
+        assert!(matches!(
+            service.issue_offline_token(&offline, &policy, 1, None),
+            Err(SessionError::OfflineTokenDenied(_))
+        ));
+
+        let conn = service.db.connection();
+        let conn = conn.lock().unwrap();
+        let audited: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM audit_logs WHERE entity_type = 'OfflineToken' AND entity_id = ?",
+            [&offline.jti],
+            |row| row.get(0),
+        ).unwrap();
+        assert_eq!(audited, 1);
+    }
+
+    #[test]
+    fn test_kiosk_denied_offline_token() {
+        let (service, claims) = setup(UserRole::Physician, DeviceType::Kiosk);
+        let result = service.issue_offline_token(&claims, &OfflineTokenPolicy::default(), 8, None);
+        assert!(matches!(result, Err(SessionError::OfflineTokenDenied(_))));
+    }
+
+    #[test]
+    fn test_refresh_only_accepts_refresh_tokens() {
+        let (service, claims) = setup(UserRole::Physician, DeviceType::Tablet);
+        let user_id = claims.user_id().unwrap();
+        let device_id = claims.device_id().unwrap();
+
+        let refresh_token = service.jwt_manager.create_refresh_token(user_id, device_id).unwrap();
+        let tokens = service.refresh(&refresh_token).unwrap();
+        assert!(service.validate(&tokens.access_token).is_ok());
+        // A refresh token is not an access token
+        assert!(service.validate(&tokens.refresh_token).is_err());
+
+        let offline = service
+            .issue_offline_token(&claims, &OfflineTokenPolicy::default(), 8, None)
+            .unwrap();
+        assert!(matches!(
+            service.refresh(&offline.offline_token),
+            Err(SessionError::Token(_))
+        ));
+
+        let access_token = service.jwt_manager
+            .create_access_token(user_id, "doc@example.com", UserRole::Physician, device_id, None)
+            .unwrap();
+        assert!(matches!(service.refresh(&access_token), Err(SessionError::Token(_))));
+    }
+
+    #[test]
+    fn test_refresh_rechecks_account_and_device() {
+        let (service, claims) = setup(UserRole::Physician, DeviceType::Tablet);
+        let user_id = claims.user_id().unwrap();
+        let device_id = claims.device_id().unwrap();
+        let refresh_token = service.jwt_manager.create_refresh_token(user_id, device_id).unwrap();
+        let users = UserRepository::new(service.db.clone());
+        let devices = DeviceRepository::new(service.db.clone());
+
+        let mut user = users.find_by_id(user_id).unwrap().unwrap();
+        user.locked_until = Some(Utc::now() + chrono::Duration::minutes(15));
+        users.record_login_failure(&user).unwrap();
+        assert!(matches!(service.refresh(&refresh_token), Err(SessionError::AccountLocked(_))));
+
+        users.clear_login_failures(user_id, None).unwrap();
+        users.change_password(&user, &user.password_hash, true, 0).unwrap();
+        assert!(matches!(service.refresh(&refresh_token), Err(SessionError::PasswordChangeRequired)));
+
+        users.change_password(&user, &user.password_hash, false, 0).unwrap();
+        assert!(service.refresh(&refresh_token).is_ok());
+
+        let mut device = devices.find_by_id(device_id).unwrap().unwrap();
+        device.revoke(user_id);
+        devices.update(&device).unwrap();
+        assert!(matches!(service.refresh(&refresh_token), Err(SessionError::DeviceRevoked)));
+    }
+
+    fn login_challenge(service: &AuthService) -> MfaChallenge {
+        match service.login("doc@example.com", PASSWORD, Id::new_v4(), None).unwrap() {
+            LoginResponse::MfaRequired(challenge) => challenge,
+            other => panic!("expected an MFA challenge, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_totp_enrollment_and_login() {
+        let (service, _) = setup(UserRole::Physician, DeviceType::Desktop);
+        let service = service.with_throttle_policy(Arc::new(LoginThrottlePolicy {
+            base_delay_ms: 0,
+            ..Default::default()
+        }));
+
+        // Physicians must enroll before they can finish logging in
+        let challenge = login_challenge(&service);
+        assert!(challenge.enrollment_required);
+
+        let user = service.mfa_enrollment_user(&challenge.mfa_token).unwrap();
+        let enrollment = service.enroll_totp(&user).unwrap();
+        let secret = base32_decode(&enrollment.secret).unwrap();
+        let code = hedtronix_crypto::otp::totp(&secret, Utc::now().timestamp() as u64);
+
+        let recovery = service.confirm_totp(&user, &code).unwrap();
+        assert_eq!(recovery.recovery_codes.len(), crate::mfa::RECOVERY_CODE_COUNT);
+
+        // With a factor enrolled, the challenge token can no longer change it
+        assert!(service.mfa_enrollment_user(&challenge.mfa_token).is_err());
+
+        let challenge = login_challenge(&service);
+        assert!(!challenge.enrollment_required);
+        assert_eq!(challenge.methods, vec![MfaMethod::Totp, MfaMethod::RecoveryCode]);
+
+        let verify = |challenge: &MfaChallenge, method, code: &str| service.verify_mfa(&MfaVerifyRequest {
+            mfa_token: challenge.mfa_token.clone(),
+            method,
+            code: Some(code.to_string()),
+            assertion: None,
+        });
+
+        assert!(matches!(verify(&challenge, MfaMethod::Totp, "abcdef"), Err(SessionError::InvalidMfaCode)));
+        let response = match verify(&challenge, MfaMethod::Totp, &code).unwrap() {
+            LoginResponse::Authenticated(response) => response,
+            other => panic!("expected tokens, got {:?}", other),
+        };
+        assert!(service.validate(&response.tokens.access_token).is_ok());
+
+        // Codes are single use
+        let challenge = login_challenge(&service);
+        assert!(matches!(verify(&challenge, MfaMethod::Totp, &code), Err(SessionError::InvalidMfaCode)));
+
+        let recovery_code = recovery.recovery_codes[0].to_lowercase();
+        assert!(verify(&challenge, MfaMethod::RecoveryCode, &recovery_code).is_ok());
+        let challenge = login_challenge(&service);
+        assert!(matches!(
+            verify(&challenge, MfaMethod::RecoveryCode, &recovery_code),
+            Err(SessionError::InvalidMfaCode)
+        ));
+    }
+
+    #[test]
+    fn test_device_unlocks_only_after_second_factor() {
+        let (service, _) = setup(UserRole::Physician, DeviceType::Desktop);
+        let service = service.with_throttle_policy(Arc::new(LoginThrottlePolicy {
+            base_delay_ms: 0,
+            ..Default::default()
+        }));
+        let policy = crate::DeviceLockPolicy {
+            idle_timeout_minutes: 15,
+            kdf: hedtronix_crypto::KdfParams { memory_kib: 64, iterations: 1, parallelism: 1 },
+        };
+        let lock = Arc::new(
+            DeviceLock::new(service.db.clone(), Arc::new(hedtronix_crypto::Keyring::new()))
+                .with_policy(Arc::new(policy)),
+        );
+        let service = service.with_device_lock(Some(lock.clone()));
+
+        let user = service.mfa_enrollment_user(&login_challenge(&service).mfa_token).unwrap();
+        let secret = base32_decode(&service.enroll_totp(&user).unwrap().secret).unwrap();
+        let code = hedtronix_crypto::otp::totp(&secret, Utc::now().timestamp() as u64);
+        service.confirm_totp(&user, &code).unwrap();
+
+        // The password alone neither unlocks nor creates the device key
+        let challenge = login_challenge(&service);
+        assert!(!lock.is_unlocked());
+        assert_eq!(lock.status().unwrap().enrolled_users, 0);
+
+        let verify = |code: &str| service.verify_mfa(&MfaVerifyRequest {
+            mfa_token: challenge.mfa_token.clone(),
+            method: MfaMethod::Totp,
+            code: Some(code.to_string()),
+            assertion: None,
+        });
+        assert!(verify("abcdef").is_err());
+        assert!(!lock.is_unlocked());
+
+        assert!(matches!(verify(&code), Ok(LoginResponse::Authenticated(_))));
+        assert!(lock.is_unlocked());
+        assert_eq!(lock.status().unwrap().enrolled_users, 1);
+    }
+
+    #[test]
+    fn test_mfa_guesses_are_throttled_and_challenges_single_use() {
+        let (service, _) = setup(UserRole::Physician, DeviceType::Desktop);
+        let user = service.mfa_enrollment_user(&login_challenge(&service).mfa_token).unwrap();
+        let secret = base32_decode(&service.enroll_totp(&user).unwrap().secret).unwrap();
+        let code = hedtronix_crypto::otp::totp(&secret, Utc::now().timestamp() as u64);
+        service.confirm_totp(&user, &code).unwrap();
+
+        let challenge = login_challenge(&service);
+        let verify = |code: &str| service.verify_mfa(&MfaVerifyRequest {
+            mfa_token: challenge.mfa_token.clone(),
+            method: MfaMethod::RecoveryCode,
+            code: Some(code.to_string()),
+            assertion: None,
+        });
+
+        // A wrong code delays the next attempt like a wrong password
+        assert!(matches!(verify("wrong-code"), Err(SessionError::InvalidMfaCode)));
+        assert!(matches!(verify("wrong-code"), Err(SessionError::Throttled(_))));
+
+        // Once a challenge has completed a login it cannot complete another
+        let service = service.with_throttle_policy(Arc::new(LoginThrottlePolicy {
+            base_delay_ms: 0,
+            ..Default::default()
+        }));
+        let recovery = service.regenerate_recovery_codes(&user).unwrap().recovery_codes;
+        let challenge = login_challenge(&service);
+        let verify = |code: &str| service.verify_mfa(&MfaVerifyRequest {
+            mfa_token: challenge.mfa_token.clone(),
+            method: MfaMethod::RecoveryCode,
+            code: Some(code.to_string()),
+            assertion: None,
+        });
+        assert!(matches!(verify(&recovery[0]), Ok(LoginResponse::Authenticated(_))));
+        assert!(matches!(verify(&recovery[1]), Err(SessionError::Token(_))));
+    }
+
+    #[test]
+    fn test_login_without_mfa_for_patients() {
+        let (service, _) = setup(UserRole::Patient, DeviceType::Mobile);
+        let response = service.login("doc@example.com", PASSWORD, Id::new_v4(), None).unwrap();
+        assert!(matches!(response, LoginResponse::Authenticated(_)));
+    }
+
+    #[test]
+    fn test_lockout_after_repeated_failures() {
+        let (service, _) = setup(UserRole::Patient, DeviceType::Mobile);
+        // No progressive delay, so the lockout itself is what is exercised
+        let service = service.with_throttle_policy(Arc::new(LoginThrottlePolicy {
+            base_delay_ms: 0,
+            ..Default::default()
+        }));
+        let login = |password: &str| service.login("doc@example.com", password, Id::new_v4(), Some("10.0.0.1"));
+
+        for _ in 0..4 {
+            assert!(matches!(login("wrong-password"), Err(SessionError::InvalidCredentials)));
+        }
+        assert!(matches!(login("wrong-password"), Err(SessionError::AccountLocked(_))));
+
+        // Even the right password is refused while locked
+        assert!(matches!(login(PASSWORD), Err(SessionError::AccountLocked(_))));
+
+        let user = UserRepository::new(service.db.clone())
+            .find_by_email("doc@example.com").unwrap().unwrap();
+        service.unlock_user(user.id).unwrap();
+        assert!(matches!(login(PASSWORD), Ok(LoginResponse::Authenticated(_))));
+    }
+
+    #[test]
+    fn test_progressive_delay_and_ip_throttle() {
+        let (service, _) = setup(UserRole::Patient, DeviceType::Mobile);
+
+        assert!(matches!(
+            service.login("doc@example.com", "wrong-password", Id::new_v4(), None),
+            Err(SessionError::InvalidCredentials)
+        ));
+        // Retrying straight away is throttled rather than checked
+        assert!(matches!(
+            service.login("doc@example.com", PASSWORD, Id::new_v4(), None),
+            Err(SessionError::Throttled(_))
+        ));
+
+        // Without the delay, an IP is still cut off after too many failures
+        let service = AuthService::new(service.jwt_manager.clone(), service.db.clone())
+            .with_throttle_policy(Arc::new(LoginThrottlePolicy {
+                max_ip_failures: 3,
+                base_delay_ms: 0,
+                ..Default::default()
+            }));
+        let ip = Some("10.0.0.2");
+        for email in ["a@example.com", "b@example.com", "c@example.com"] {
+            assert!(matches!(
+                service.login(email, "guess", Id::new_v4(), ip),
+                Err(SessionError::InvalidCredentials)
+            ));
+        }
+        assert!(matches!(
+            service.login("d@example.com", "guess", Id::new_v4(), ip),
+            Err(SessionError::Throttled(_))
+        ));
+    }
+
+    #[test]
+    fn test_admin_created_user_must_change_password() {
+        let (service, _) = setup(UserRole::Patient, DeviceType::Mobile);
+        assert!(matches!(
+            service.create_user_by_admin("pat@example.com", "Pat", "short", UserRole::Patient),
+            Err(SessionError::PasswordPolicy(_))
+        ));
+        service.create_user_by_admin("pat@example.com", "Pat", "initial-password-1", UserRole::Patient)
+            .unwrap();
+
+        let challenge = match service.login("pat@example.com", "initial-password-1", Id::new_v4(), None).unwrap() {
+            LoginResponse::PasswordChangeRequired(challenge) => challenge,
+            other => panic!("expected a password change, got {:?}", other),
+        };
+
+        // The password change token is not a session
+        assert!(service.validate(&challenge.password_change_token).is_err());
+
+        let token = &challenge.password_change_token;
+        assert!(matches!(
+            service.change_password(token, "initial-password-1", "initial-password-1"),
+            Err(SessionError::PasswordPolicy(_))
+        ));
+
+        let response = service.change_password(token, "initial-password-1", "a-brand-new-passphrase").unwrap();
+        let session = response.session.expect("a session after the forced change");
+        assert!(service.validate(&session.tokens.access_token).is_ok());
+
+        let response = service.login("pat@example.com", "a-brand-new-passphrase", Id::new_v4(), None).unwrap();
+        assert!(matches!(response, LoginResponse::Authenticated(_)));
+    }
+
+    #[test]
+    fn test_password_history_prevents_reuse() {
+        let (service, claims) = setup(UserRole::Patient, DeviceType::Mobile);
+        let token = service.jwt_manager
+            .create_access_token(claims.user_id().unwrap(), &claims.email, UserRole::Patient, Id::new_v4(), None)
+            .unwrap();
+
+        let response = service.change_password(&token, PASSWORD, "second-passphrase-here").unwrap();
+        assert!(response.session.is_none());
+        service.change_password(&token, "second-passphrase-here", "third-passphrase-here").unwrap();
+
+        assert!(matches!(
+            service.change_password(&token, "third-passphrase-here", PASSWORD),
+            Err(SessionError::PasswordPolicy(_))
+        ));
+    }
+}
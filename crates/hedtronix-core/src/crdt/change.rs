@@ -63,3 +63,26 @@ impl Change {
         Self::new(entity_type, entity_id, ChangeOperation::Delete, serde_json::Value::Null, device_id)
     }
 }
+
+/// A change whose data is encrypted on the originating device, as stored
+/// and forwarded by the sync relay. The metadata stays readable so the
+/// relay can order and route changes; the payload is only readable by
+/// devices holding the scope's sync key.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SealedChange {
+    pub id: Uuid,
+    pub entity_type: String,
+    pub entity_id: Uuid,
+    pub operation: ChangeOperation,
+    pub timestamp: DateTime<Utc>,
+    pub device_id: String,
+    pub version: VersionVector,
+    /// Who may read the change: `organization` or `department:<id>`
+    pub scope: String,
+    /// Sync key the payload is encrypted with
+    pub key_id: String,
+    /// Base64 AES-256-GCM ciphertext of the change data
+    pub payload: String,
+    /// Base64 Ed25519 signature by the originating device
+    pub signature: String,
+}
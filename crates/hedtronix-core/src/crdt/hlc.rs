@@ -0,0 +1,103 @@
+//! Hybrid Logical Clock timestamps
+//!
+//! An HLC stays close to wall-clock time but never goes backwards and always
+//! orders an event after anything it has observed, even across devices with
+//! skewed clocks.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Hybrid logical timestamp: wall-clock milliseconds plus a counter for
+/// events within the same millisecond
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct HybridTimestamp {
+    pub physical: i64,
+    pub logical: u32,
+}
+
+impl HybridTimestamp {
+    pub fn new(physical: i64, logical: u32) -> Self {
+        Self { physical, logical }
+    }
+
+    /// Timestamp for a local event, after the last one issued
+    pub fn tick(last: Option<&Self>, now_ms: i64) -> Self {
+        match last {
+            Some(last) if last.physical >= now_ms => Self::new(last.physical, last.logical + 1),
+            _ => Self::new(now_ms, 0),
+        }
+    }
+
+    /// Timestamp for a local event that follows receipt of `remote`
+    pub fn receive(last: Option<&Self>, remote: &Self, now_ms: i64) -> Self {
+        let latest = match last {
+            Some(last) if last > remote => last,
+            _ => remote,
+        };
+        Self::tick(Some(latest), now_ms)
+    }
+
+    /// Current wall-clock time in milliseconds
+    pub fn wall_clock_ms() -> i64 {
+        chrono::Utc::now().timestamp_millis()
+    }
+}
+
+/// Fixed-width text form, so timestamps sort correctly as strings
+impl fmt::Display for HybridTimestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:015}-{:010}", self.physical, self.logical)
+    }
+}
+
+impl FromStr for HybridTimestamp {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (physical, logical) = s
+            .split_once('-')
+            .ok_or_else(|| format!("Invalid HLC timestamp: {}", s))?;
+        Ok(Self {
+            physical: physical.parse().map_err(|_| format!("Invalid HLC timestamp: {}", s))?,
+            logical: logical.parse().map_err(|_| format!("Invalid HLC timestamp: {}", s))?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tick_never_goes_backwards() {
+        let first = HybridTimestamp::tick(None, 1_000);
+        assert_eq!(first, HybridTimestamp::new(1_000, 0));
+
+        // Wall clock stepped back
+        let second = HybridTimestamp::tick(Some(&first), 900);
+        assert_eq!(second, HybridTimestamp::new(1_000, 1));
+        assert!(second > first);
+
+        let third = HybridTimestamp::tick(Some(&second), 1_200);
+        assert_eq!(third, HybridTimestamp::new(1_200, 0));
+    }
+
+    #[test]
+    fn test_receive_orders_after_remote() {
+        let local = HybridTimestamp::new(1_000, 3);
+        let remote = HybridTimestamp::new(5_000, 7);
+
+        let next = HybridTimestamp::receive(Some(&local), &remote, 2_000);
+        assert!(next > remote);
+        assert!(next > local);
+    }
+
+    #[test]
+    fn test_text_form_sorts_like_timestamps() {
+        let a = HybridTimestamp::new(999, 12);
+        let b = HybridTimestamp::new(1_000, 2);
+        assert!(a.to_string() < b.to_string());
+        assert_eq!(b.to_string().parse::<HybridTimestamp>().unwrap(), b);
+    }
+}
@@ -5,9 +5,11 @@ pub mod mv_register;
 pub mod crdt_list;
 pub mod version_vector;
 pub mod change;
+pub mod hlc;
 
 pub use lww_register::*;
 pub use mv_register::*;
 pub use crdt_list::*;
 pub use version_vector::*;
 pub use change::*;
+pub use hlc::*;
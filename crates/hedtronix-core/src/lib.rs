@@ -7,6 +7,7 @@ pub mod models;
 pub mod error;
 pub mod types;
 pub mod crdt;
+pub mod pdf;
 
 pub use error::{Error, Result};
 pub use models::*;
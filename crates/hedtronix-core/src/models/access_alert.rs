@@ -0,0 +1,152 @@
+//! Alerts raised by anomalous access detection over the audit trail
+
+use serde::{Deserialize, Serialize};
+
+use crate::types::{Id, Timestamp};
+
+/// The pattern an alert was raised for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum AccessAlertRule {
+    /// Far more charts opened in a day than the user's baseline
+    ExcessiveChartAccess,
+    /// Access to a patient flagged as a VIP by the privacy office
+    VipPatientAccess,
+    /// Access to a patient who shares the user's surname
+    SameSurname,
+    /// A burst of reads outside business hours
+    OffHoursBurst,
+    /// Access from a device that is not registered to the user
+    UnregisteredDevice,
+}
+
+impl AccessAlertRule {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AccessAlertRule::ExcessiveChartAccess => "EXCESSIVE_CHART_ACCESS",
+            AccessAlertRule::VipPatientAccess => "VIP_PATIENT_ACCESS",
+            AccessAlertRule::SameSurname => "SAME_SURNAME",
+            AccessAlertRule::OffHoursBurst => "OFF_HOURS_BURST",
+            AccessAlertRule::UnregisteredDevice => "UNREGISTERED_DEVICE",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum AccessAlertSeverity {
+    Low,
+    Medium,
+    High,
+}
+
+impl AccessAlertSeverity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AccessAlertSeverity::Low => "LOW",
+            AccessAlertSeverity::Medium => "MEDIUM",
+            AccessAlertSeverity::High => "HIGH",
+        }
+    }
+}
+
+/// Where an alert stands in the privacy office's queue
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum AccessAlertStatus {
+    Open,
+    /// Confirmed as inappropriate access and taken up for investigation
+    Escalated,
+    /// Reviewed and found to be legitimate
+    Dismissed,
+}
+
+impl AccessAlertStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AccessAlertStatus::Open => "OPEN",
+            AccessAlertStatus::Escalated => "ESCALATED",
+            AccessAlertStatus::Dismissed => "DISMISSED",
+        }
+    }
+}
+
+/// Suspicious access found in the audit trail, awaiting review
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccessAlert {
+    pub id: Id,
+    pub rule: AccessAlertRule,
+    pub severity: AccessAlertSeverity,
+
+    /// User whose access raised the alert
+    pub user_id: Id,
+
+    /// Patient concerned, for rules about a single chart
+    pub patient_id: Option<Id>,
+
+    /// Audit entries that triggered the alert
+    pub audit_log_ids: Vec<Id>,
+
+    /// What was seen, for the reviewer
+    pub description: String,
+
+    pub created_at: Timestamp,
+
+    pub status: AccessAlertStatus,
+    pub reviewed_by: Option<Id>,
+    pub reviewed_at: Option<Timestamp>,
+    pub review_notes: Option<String>,
+}
+
+impl AccessAlert {
+    pub fn new(
+        rule: AccessAlertRule,
+        severity: AccessAlertSeverity,
+        user_id: Id,
+        patient_id: Option<Id>,
+        audit_log_ids: Vec<Id>,
+        description: String,
+    ) -> Self {
+        Self {
+            id: Id::new_v4(),
+            rule,
+            severity,
+            user_id,
+            patient_id,
+            audit_log_ids,
+            description,
+            created_at: chrono::Utc::now(),
+            status: AccessAlertStatus::Open,
+            reviewed_by: None,
+            reviewed_at: None,
+            review_notes: None,
+        }
+    }
+}
+
+/// Alert queue filters
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AccessAlertFilters {
+    pub status: Option<AccessAlertStatus>,
+    pub rule: Option<AccessAlertRule>,
+    pub user_id: Option<Id>,
+    pub patient_id: Option<Id>,
+    pub page: u32,
+    pub limit: u32,
+}
+
+/// Privacy officer review DTO
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReviewAccessAlert {
+    pub status: AccessAlertStatus,
+    pub notes: Option<String>,
+}
+
+/// Patient flagged for extra access monitoring
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VipPatient {
+    pub patient_id: Id,
+    pub reason: Option<String>,
+    pub flagged_by: Option<Id>,
+    pub flagged_at: Timestamp,
+}
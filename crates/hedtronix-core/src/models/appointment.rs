@@ -100,12 +100,8 @@ impl Appointment {
         self.check_in_time = Some(now);
         self.status = AppointmentStatus::CheckedIn;
         
-        // Calculate wait time from appointment start
-        if now > self.start_time {
-            self.wait_time = Some(0);
-        } else {
-            self.wait_time = Some(0);
-        }
+        // Wait time is measured from check-in, so it starts at zero
+        self.wait_time = Some(0);
         self.updated_at = now;
     }
 
@@ -2,14 +2,25 @@
 
 use serde::{Deserialize, Serialize};
 
+use crate::crdt::HybridTimestamp;
 use crate::types::{AuditEventType, Id, Timestamp};
 
 /// Audit Log entry - immutable record of all system events
 /// CRDT Type: APPEND_ONLY_LOG
-/// Conflict Resolution: Immutable, ordered by timestamp
+/// Conflict Resolution: Immutable; each node's chain is ordered by sequence,
+/// the merged view across devices by HLC
+///
+/// Entries form a hash chain: each entry's hash covers every field except
+/// the hash and signature, including the previous entry's hash, so editing
+/// or removing any entry breaks every link after it.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AuditLog {
     pub id: Id,
+
+    /// Position in the chain, starting at 1; assigned on append
+    #[serde(default)]
+    pub sequence: i64,
+
     pub event_type: AuditEventType,
     
     /// User who performed the action (None for system events)
@@ -44,6 +55,14 @@ pub struct AuditLog {
     
     /// Hash of this log entry
     pub hash: String,
+
+    /// Emergency access grant the action was made under, if any
+    #[serde(default)]
+    pub break_glass_grant_id: Option<Id>,
+
+    /// Hybrid logical clock time; assigned on append
+    #[serde(default)]
+    pub hlc: Option<HybridTimestamp>,
 }
 
 impl AuditLog {
@@ -55,15 +74,9 @@ impl AuditLog {
         entity_id: String,
         changes: serde_json::Value,
     ) -> Self {
-        let now = chrono::Utc::now();
-        let id = Id::new_v4();
-        
-        // Create a simple hash (in production, use proper cryptographic hashing)
-        let hash_input = format!("{}{:?}{}{}", id, event_type, entity_type, now);
-        let hash = format!("{:x}", md5_hash(&hash_input));
-        
-        Self {
-            id,
+        let mut log = Self {
+            id: Id::new_v4(),
+            sequence: 0,
             event_type,
             user_id,
             device_id,
@@ -72,11 +85,66 @@ impl AuditLog {
             changes,
             ip_address: None,
             user_agent: None,
-            timestamp: now,
+            timestamp: chrono::Utc::now(),
             signature: String::new(), // Will be set by signing service
             previous_hash: None,
-            hash,
-        }
+            hash: String::new(),
+            break_glass_grant_id: None,
+            hlc: None,
+        };
+        log.hash = log.compute_hash();
+        log
+    }
+
+    /// Deterministic serialization of every field except `hash` and
+    /// `signature`, used as the hash input
+    pub fn canonical_bytes(&self) -> Vec<u8> {
+        let fields = serde_json::json!([
+            self.sequence,
+            self.id,
+            self.event_type.as_str(),
+            self.user_id,
+            self.device_id,
+            self.entity_type,
+            self.entity_id,
+            canonical_json(&self.changes),
+            self.ip_address,
+            self.user_agent,
+            self.timestamp.to_rfc3339_opts(chrono::SecondsFormat::Nanos, true),
+            self.previous_hash,
+            self.break_glass_grant_id,
+            self.hlc.map(|hlc| hlc.to_string()),
+        ]);
+        fields.to_string().into_bytes()
+    }
+
+    /// SHA-256 of the canonical serialization, hex encoded
+    pub fn compute_hash(&self) -> String {
+        hedtronix_crypto::hashing::sha256_hex(&self.canonical_bytes())
+    }
+
+    /// Whether the stored hash matches the entry's contents
+    pub fn verify_hash(&self) -> bool {
+        self.hash == self.compute_hash()
+    }
+
+    /// Place the entry after `previous` (its sequence, hash and HLC time)
+    /// in the chain and recompute its hash. The entry's HLC time follows
+    /// both the previous entry and any time already set on it, which callers
+    /// use to order an entry after events received from elsewhere.
+    pub fn chain_after(&mut self, previous: Option<(i64, String, Option<HybridTimestamp>)>) {
+        let now = HybridTimestamp::wall_clock_ms();
+        let (sequence, previous_hash, last_hlc) = match previous {
+            Some((sequence, hash, hlc)) => (sequence + 1, Some(hash), hlc),
+            None => (1, None, None),
+        };
+        self.sequence = sequence;
+        self.previous_hash = previous_hash;
+        self.hlc = Some(match (last_hlc, self.hlc) {
+            (last, Some(observed)) => HybridTimestamp::receive(last.as_ref(), &observed, now),
+            (last, None) => HybridTimestamp::tick(last.as_ref(), now),
+        });
+        self.hash = self.compute_hash();
     }
 
     /// Create a read event audit log
@@ -96,6 +164,31 @@ impl AuditLog {
         )
     }
 
+    /// Create a read event made under an emergency access grant
+    pub fn break_glass_read_event(
+        user_id: Id,
+        device_id: Option<Id>,
+        entity_type: &str,
+        entity_id: &str,
+        grant_id: Id,
+    ) -> Self {
+        let mut log = Self::new(
+            AuditEventType::Read,
+            Some(user_id),
+            device_id,
+            entity_type.to_string(),
+            entity_id.to_string(),
+            serde_json::json!({ "break_glass": true }),
+        );
+        log.break_glass_grant_id = Some(grant_id);
+        log
+    }
+
+    /// Whether the action bypassed normal access rules
+    pub fn is_break_glass(&self) -> bool {
+        self.break_glass_grant_id.is_some()
+    }
+
     /// Create a create event audit log
     pub fn create_event(
         user_id: Id,
@@ -200,28 +293,42 @@ impl AuditLog {
 
     pub fn with_ip_address(mut self, ip: String) -> Self {
         self.ip_address = Some(ip);
+        self.hash = self.compute_hash();
         self
     }
 
     pub fn with_user_agent(mut self, ua: String) -> Self {
         self.user_agent = Some(ua);
+        self.hash = self.compute_hash();
         self
     }
 
     pub fn with_previous_hash(mut self, hash: String) -> Self {
         self.previous_hash = Some(hash);
+        self.hash = self.compute_hash();
         self
     }
 }
 
-/// Simple MD5 hash function (for demo purposes - use proper crypto in production)
-fn md5_hash(input: &str) -> u128 {
-    use std::collections::hash_map::DefaultHasher;
-    use std::hash::{Hash, Hasher};
-    
-    let mut hasher = DefaultHasher::new();
-    input.hash(&mut hasher);
-    hasher.finish() as u128
+/// JSON text with object keys sorted at every level, so the hash does not
+/// depend on map ordering
+fn canonical_json(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::Object(map) => {
+            let mut entries: Vec<(&String, &serde_json::Value)> = map.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            let fields: Vec<String> = entries
+                .into_iter()
+                .map(|(k, v)| format!("{}:{}", serde_json::Value::from(k.as_str()), canonical_json(v)))
+                .collect();
+            format!("{{{}}}", fields.join(","))
+        }
+        serde_json::Value::Array(items) => {
+            let items: Vec<String> = items.iter().map(canonical_json).collect();
+            format!("[{}]", items.join(","))
+        }
+        other => other.to_string(),
+    }
 }
 
 /// Audit log query filters
@@ -237,3 +344,65 @@ pub struct AuditLogFilters {
     pub page: u32,
     pub limit: u32,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry() -> AuditLog {
+        AuditLog::create_event(
+            Id::new_v4(),
+            Id::new_v4(),
+            "Patient",
+            "p1",
+            serde_json::json!({ "b": 1, "a": { "y": true, "x": [1, 2] } }),
+        )
+    }
+
+    #[test]
+    fn test_hash_covers_every_field() {
+        let log = entry();
+        assert!(log.verify_hash());
+        assert_eq!(log.hash.len(), 64);
+
+        let mut tampered = log.clone();
+        tampered.entity_id = "p2".to_string();
+        assert!(!tampered.verify_hash());
+
+        let mut tampered = log.clone();
+        tampered.changes["b"] = serde_json::json!(2);
+        assert!(!tampered.verify_hash());
+
+        let mut tampered = log.clone();
+        tampered.previous_hash = Some("0".repeat(64));
+        assert!(!tampered.verify_hash());
+
+        // The signature is not part of the hash input
+        let mut signed = log.clone();
+        signed.signature = "sig".to_string();
+        assert!(signed.verify_hash());
+    }
+
+    #[test]
+    fn test_hash_ignores_key_order() {
+        let log = entry();
+        let mut reordered = log.clone();
+        reordered.changes = serde_json::from_str(r#"{"a":{"x":[1,2],"y":true},"b":1}"#).unwrap();
+        assert_eq!(log.compute_hash(), reordered.compute_hash());
+    }
+
+    #[test]
+    fn test_chain_after() {
+        let mut first = entry();
+        first.chain_after(None);
+        assert_eq!(first.sequence, 1);
+        assert!(first.previous_hash.is_none());
+
+        let mut second = entry();
+        second.chain_after(Some((first.sequence, first.hash.clone(), first.hlc)));
+        assert_eq!(second.sequence, 2);
+        assert_eq!(second.previous_hash.as_deref(), Some(first.hash.as_str()));
+        assert!(second.hlc > first.hlc);
+        assert!(second.verify_hash());
+    }
+}
@@ -1,6 +1,5 @@
 //! Billing model
 
-use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 use validator::Validate;
 
@@ -188,3 +188,35 @@ pub struct UpdateClinicalNote {
     pub assessment: Option<SoapSection>,
     pub plan: Option<SoapSection>,
 }
+
+/// Clinical note DTO for API responses
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClinicalNoteDto {
+    pub id: String,
+    pub patient_id: String,
+    pub author_id: String,
+    pub encounter_id: Option<String>,
+    pub note_type: NoteType,
+    pub content: String,
+    pub status: NoteStatus,
+    pub created_at: String,
+    pub updated_at: String,
+    pub signed_at: Option<String>,
+}
+
+impl From<ClinicalNote> for ClinicalNoteDto {
+    fn from(n: ClinicalNote) -> Self {
+        Self {
+            id: n.id.to_string(),
+            patient_id: n.patient_id.to_string(),
+            author_id: n.author_id.to_string(),
+            encounter_id: n.encounter_id.map(|id| id.to_string()),
+            note_type: n.note_type,
+            content: n.content,
+            status: n.status,
+            created_at: n.created_at.to_rfc3339(),
+            updated_at: n.updated_at.to_rfc3339(),
+            signed_at: n.signed_at.map(|t| t.to_rfc3339()),
+        }
+    }
+}
@@ -15,6 +15,9 @@ pub struct Device {
     /// Device public key for authentication
     pub public_key: String,
     
+    /// Base64 X25519 public key that sync keys are sealed to
+    pub agreement_key: Option<String>,
+    
     pub device_type: DeviceType,
     
     /// User-friendly device name
@@ -43,6 +46,7 @@ impl Device {
             id: Id::new_v4(),
             user_id,
             public_key,
+            agreement_key: None,
             device_type,
             device_name: None,
             last_sync_at: None,
@@ -69,7 +73,11 @@ impl Device {
 /// DTO for device registration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RegisterDevice {
+    /// Base64 Ed25519 public key that signs the device's sync changes
     pub public_key: String,
+    /// Base64 X25519 public key for receiving sync keys
+    #[serde(default)]
+    pub agreement_key: Option<String>,
     pub device_type: DeviceType,
     pub device_name: Option<String>,
     pub user_agent: String,
@@ -0,0 +1,344 @@
+//! Accounting of disclosures - who accessed a patient's chart, and why
+
+use serde::{Deserialize, Serialize};
+
+use crate::pdf::TextDocument;
+use crate::types::{AuditEventType, Id, Timestamp, UserRole};
+
+/// Why a user accessed a chart, as reported to the patient
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum DisclosurePurpose {
+    Treatment,
+    Payment,
+    HealthcareOperations,
+    /// Break-the-glass access outside the user's normal scope
+    EmergencyAccess,
+    /// Records exported out of the system
+    Disclosure,
+    /// The patient viewing their own chart
+    PatientAccess,
+}
+
+impl DisclosurePurpose {
+    /// Infer the purpose of one audited access
+    pub fn classify(
+        role: Option<UserRole>,
+        event_type: AuditEventType,
+        entity_type: &str,
+        emergency: bool,
+    ) -> Self {
+        if emergency {
+            return DisclosurePurpose::EmergencyAccess;
+        }
+        if event_type == AuditEventType::Export {
+            return DisclosurePurpose::Disclosure;
+        }
+        match role {
+            Some(UserRole::Patient) => DisclosurePurpose::PatientAccess,
+            Some(UserRole::Billing) => DisclosurePurpose::Payment,
+            _ if entity_type == "BillingEntry" => DisclosurePurpose::Payment,
+            Some(UserRole::Physician) | Some(UserRole::Nurse) => DisclosurePurpose::Treatment,
+            _ => DisclosurePurpose::HealthcareOperations,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DisclosurePurpose::Treatment => "TREATMENT",
+            DisclosurePurpose::Payment => "PAYMENT",
+            DisclosurePurpose::HealthcareOperations => "HEALTHCARE_OPERATIONS",
+            DisclosurePurpose::EmergencyAccess => "EMERGENCY_ACCESS",
+            DisclosurePurpose::Disclosure => "DISCLOSURE",
+            DisclosurePurpose::PatientAccess => "PATIENT_ACCESS",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            DisclosurePurpose::Treatment => "Treatment",
+            DisclosurePurpose::Payment => "Payment",
+            DisclosurePurpose::HealthcareOperations => "Healthcare operations",
+            DisclosurePurpose::EmergencyAccess => "Emergency access",
+            DisclosurePurpose::Disclosure => "Disclosure (export)",
+            DisclosurePurpose::PatientAccess => "Patient's own access",
+        }
+    }
+}
+
+/// One audited access to the patient's records
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DisclosureEvent {
+    pub audit_log_id: Id,
+    pub timestamp: Timestamp,
+    pub event_type: AuditEventType,
+    pub entity_type: String,
+    pub entity_id: String,
+    pub device_id: Option<Id>,
+    pub ip_address: Option<String>,
+    /// Justification given for emergency access
+    pub emergency_reason: Option<String>,
+}
+
+/// The user who accessed the records (`None` for system events)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DisclosureAccessor {
+    pub user_id: Option<Id>,
+    pub name: Option<String>,
+    pub email: Option<String>,
+    pub role: Option<UserRole>,
+}
+
+impl DisclosureAccessor {
+    pub fn display_name(&self) -> String {
+        match (&self.name, &self.role) {
+            (Some(name), Some(role)) => format!("{} ({})", name, role.as_str()),
+            (Some(name), None) => name.clone(),
+            (None, _) => match self.user_id {
+                Some(id) => format!("Unknown user {}", id),
+                None => "System".to_string(),
+            },
+        }
+    }
+}
+
+/// Accesses by one user for one purpose
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DisclosureGroup {
+    pub accessor: DisclosureAccessor,
+    pub purpose: DisclosurePurpose,
+    pub access_count: usize,
+    pub first_access: Timestamp,
+    pub last_access: Timestamp,
+    pub events: Vec<DisclosureEvent>,
+}
+
+/// Every audited access to a patient's records within a period
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DisclosureReport {
+    pub patient_id: Id,
+    pub patient_name: String,
+    pub period_start: Timestamp,
+    pub period_end: Timestamp,
+    pub generated_at: Timestamp,
+    pub total_events: usize,
+    pub groups: Vec<DisclosureGroup>,
+}
+
+impl DisclosureReport {
+    /// Group accesses by user and purpose; groups are ordered by their first
+    /// access and events within a group by time
+    pub fn new(
+        patient_id: Id,
+        patient_name: String,
+        period_start: Timestamp,
+        period_end: Timestamp,
+        accesses: Vec<(DisclosureAccessor, DisclosureEvent)>,
+    ) -> Self {
+        let total_events = accesses.len();
+        let mut groups: Vec<DisclosureGroup> = Vec::new();
+
+        for (accessor, event) in accesses {
+            let purpose = DisclosurePurpose::classify(
+                accessor.role,
+                event.event_type,
+                &event.entity_type,
+                event.emergency_reason.is_some(),
+            );
+            match groups
+                .iter_mut()
+                .find(|g| g.accessor.user_id == accessor.user_id && g.purpose == purpose)
+            {
+                Some(group) => {
+                    group.access_count += 1;
+                    group.first_access = group.first_access.min(event.timestamp);
+                    group.last_access = group.last_access.max(event.timestamp);
+                    group.events.push(event);
+                }
+                None => groups.push(DisclosureGroup {
+                    accessor,
+                    purpose,
+                    access_count: 1,
+                    first_access: event.timestamp,
+                    last_access: event.timestamp,
+                    events: vec![event],
+                }),
+            }
+        }
+
+        for group in &mut groups {
+            group.events.sort_by_key(|e| e.timestamp);
+        }
+        groups.sort_by_key(|g| g.first_access);
+
+        Self {
+            patient_id,
+            patient_name,
+            period_start,
+            period_end,
+            generated_at: chrono::Utc::now(),
+            total_events,
+            groups,
+        }
+    }
+
+    /// One row per access
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from(
+            "timestamp,user_id,user_name,user_role,purpose,event_type,entity_type,entity_id,device_id,ip_address,emergency_reason\r\n",
+        );
+        for group in &self.groups {
+            for event in &group.events {
+                let fields = [
+                    event.timestamp.to_rfc3339(),
+                    group.accessor.user_id.map(|id| id.to_string()).unwrap_or_default(),
+                    group.accessor.name.clone().unwrap_or_default(),
+                    group.accessor.role.map(|r| r.as_str().to_string()).unwrap_or_default(),
+                    group.purpose.as_str().to_string(),
+                    event.event_type.as_str().to_string(),
+                    event.entity_type.clone(),
+                    event.entity_id.clone(),
+                    event.device_id.map(|id| id.to_string()).unwrap_or_default(),
+                    event.ip_address.clone().unwrap_or_default(),
+                    event.emergency_reason.clone().unwrap_or_default(),
+                ];
+                let row: Vec<String> = fields.iter().map(|f| csv_field(f)).collect();
+                csv.push_str(&row.join(","));
+                csv.push_str("\r\n");
+            }
+        }
+        csv
+    }
+
+    /// Printable report for the patient
+    pub fn to_pdf(&self) -> Vec<u8> {
+        let mut doc = TextDocument::new(format!("Accounting of disclosures - {}", self.patient_name));
+        doc.line("ACCOUNTING OF DISCLOSURES");
+        doc.blank();
+        doc.line(format!("Patient:   {} ({})", self.patient_name, self.patient_id));
+        doc.line(format!(
+            "Period:    {} to {}",
+            self.period_start.format("%Y-%m-%d %H:%M UTC"),
+            self.period_end.format("%Y-%m-%d %H:%M UTC")
+        ));
+        doc.line(format!("Generated: {}", self.generated_at.format("%Y-%m-%d %H:%M UTC")));
+        doc.line(format!("Accesses:  {}", self.total_events));
+
+        if self.groups.is_empty() {
+            doc.blank();
+            doc.line("No access to this patient's records was recorded in this period.");
+        }
+
+        for group in &self.groups {
+            doc.blank();
+            doc.line(format!(
+                "{} - {} - {} access{}",
+                group.accessor.display_name(),
+                group.purpose.label(),
+                group.access_count,
+                if group.access_count == 1 { "" } else { "es" }
+            ));
+            for event in &group.events {
+                doc.line(format!(
+                    "  {}  {:<7} {} {}",
+                    event.timestamp.format("%Y-%m-%d %H:%M:%S"),
+                    event.event_type.as_str(),
+                    event.entity_type,
+                    event.entity_id
+                ));
+                if let Some(reason) = &event.emergency_reason {
+                    doc.line(format!("      Reason given: {}", reason));
+                }
+            }
+        }
+
+        doc.to_pdf()
+    }
+}
+
+/// Quote a CSV field when it contains a separator, quote or line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn access(user_id: Id, role: UserRole, event_type: AuditEventType, minutes: i64) -> (DisclosureAccessor, DisclosureEvent) {
+        (
+            DisclosureAccessor {
+                user_id: Some(user_id),
+                name: Some("Dr. Grey, Meredith".to_string()),
+                email: None,
+                role: Some(role),
+            },
+            DisclosureEvent {
+                audit_log_id: Id::new_v4(),
+                timestamp: chrono::Utc::now() - chrono::Duration::minutes(minutes),
+                event_type,
+                entity_type: "Patient".to_string(),
+                entity_id: "p1".to_string(),
+                device_id: None,
+                ip_address: None,
+                emergency_reason: None,
+            },
+        )
+    }
+
+    #[test]
+    fn test_groups_by_user_and_purpose() {
+        let doctor = Id::new_v4();
+        let clerk = Id::new_v4();
+        let mut emergency = access(doctor, UserRole::Physician, AuditEventType::Read, 5);
+        emergency.1.emergency_reason = Some("Unconscious in ED".to_string());
+
+        let report = DisclosureReport::new(
+            Id::new_v4(),
+            "Jane Doe".to_string(),
+            chrono::Utc::now() - chrono::Duration::days(1),
+            chrono::Utc::now(),
+            vec![
+                access(doctor, UserRole::Physician, AuditEventType::Read, 10),
+                access(clerk, UserRole::Billing, AuditEventType::Read, 30),
+                access(doctor, UserRole::Physician, AuditEventType::Update, 20),
+                emergency,
+            ],
+        );
+
+        assert_eq!(report.total_events, 4);
+        let summary: Vec<_> = report.groups.iter()
+            .map(|g| (g.accessor.user_id, g.purpose, g.access_count))
+            .collect();
+        assert_eq!(summary, vec![
+            (Some(clerk), DisclosurePurpose::Payment, 1),
+            (Some(doctor), DisclosurePurpose::Treatment, 2),
+            (Some(doctor), DisclosurePurpose::EmergencyAccess, 1),
+        ]);
+        assert!(report.groups[1].events[0].timestamp < report.groups[1].events[1].timestamp);
+    }
+
+    #[test]
+    fn test_csv_and_pdf_output() {
+        let report = DisclosureReport::new(
+            Id::new_v4(),
+            "Jane Doe".to_string(),
+            chrono::Utc::now() - chrono::Duration::days(1),
+            chrono::Utc::now(),
+            vec![access(Id::new_v4(), UserRole::Nurse, AuditEventType::Read, 1)],
+        );
+
+        let csv = report.to_csv();
+        assert_eq!(csv.lines().count(), 2);
+        assert!(csv.contains("\"Dr. Grey, Meredith\""));
+        assert!(csv.contains(",TREATMENT,READ,Patient,p1,"));
+
+        let pdf = String::from_utf8(report.to_pdf()).unwrap();
+        assert!(pdf.starts_with("%PDF-1.4"));
+        assert!(pdf.contains("Treatment - 1 access)"));
+    }
+}
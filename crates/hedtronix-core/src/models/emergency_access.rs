@@ -0,0 +1,98 @@
+//! Emergency ("break-the-glass") access to charts outside a clinician's
+//! normal scope
+
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::types::{Id, Timestamp};
+
+/// Outcome of the privacy officer's review of a grant
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum EmergencyReviewStatus {
+    Pending,
+    Justified,
+    Unjustified,
+}
+
+impl EmergencyReviewStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EmergencyReviewStatus::Pending => "PENDING",
+            EmergencyReviewStatus::Justified => "JUSTIFIED",
+            EmergencyReviewStatus::Unjustified => "UNJUSTIFIED",
+        }
+    }
+}
+
+/// Time-limited grant of full access to one patient's chart
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmergencyAccessGrant {
+    pub id: Id,
+    pub user_id: Id,
+    pub patient_id: Id,
+    pub device_id: Option<Id>,
+
+    /// Justification given by the clinician
+    pub reason: String,
+
+    pub granted_at: Timestamp,
+    pub expires_at: Timestamp,
+
+    /// Set when the grant was ended before it expired
+    pub revoked_at: Option<Timestamp>,
+
+    pub review_status: EmergencyReviewStatus,
+    pub reviewed_by: Option<Id>,
+    pub reviewed_at: Option<Timestamp>,
+    pub review_notes: Option<String>,
+}
+
+impl EmergencyAccessGrant {
+    pub fn new(
+        user_id: Id,
+        patient_id: Id,
+        device_id: Option<Id>,
+        reason: String,
+        duration: chrono::Duration,
+    ) -> Self {
+        let now = chrono::Utc::now();
+        Self {
+            id: Id::new_v4(),
+            user_id,
+            patient_id,
+            device_id,
+            reason,
+            granted_at: now,
+            expires_at: now + duration,
+            revoked_at: None,
+            review_status: EmergencyReviewStatus::Pending,
+            reviewed_by: None,
+            reviewed_at: None,
+            review_notes: None,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.revoked_at.is_none() && chrono::Utc::now() < self.expires_at
+    }
+}
+
+/// Emergency access request DTO
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
+pub struct CreateEmergencyAccess {
+    pub patient_id: Id,
+
+    #[validate(length(min = 1, max = 2000))]
+    pub reason: String,
+
+    /// Requested length of the grant; capped by policy
+    pub duration_minutes: Option<u32>,
+}
+
+/// Privacy officer review DTO
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReviewEmergencyAccess {
+    pub status: EmergencyReviewStatus,
+    pub notes: Option<String>,
+}
@@ -0,0 +1,94 @@
+//! Multi-factor authentication models
+
+use serde::{Deserialize, Serialize};
+
+use crate::types::{Id, Timestamp};
+
+/// Second factors a user can authenticate with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum MfaMethod {
+    Totp,
+    Webauthn,
+    RecoveryCode,
+}
+
+/// Per-user MFA enrollment state
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MfaSettings {
+    pub user_id: Id,
+
+    /// Base32 TOTP secret; only trusted once `totp_enabled` is set
+    #[serde(skip_serializing)]
+    pub totp_secret: Option<String>,
+
+    pub totp_enabled: bool,
+
+    /// Last accepted TOTP time step, so a code cannot be replayed
+    pub totp_last_step: Option<i64>,
+
+    /// Outstanding WebAuthn registration challenge (base64url)
+    #[serde(skip_serializing)]
+    pub webauthn_challenge: Option<String>,
+
+    pub updated_at: Timestamp,
+}
+
+impl MfaSettings {
+    pub fn new(user_id: Id) -> Self {
+        Self {
+            user_id,
+            totp_secret: None,
+            totp_enabled: false,
+            totp_last_step: None,
+            webauthn_challenge: None,
+            updated_at: chrono::Utc::now(),
+        }
+    }
+}
+
+/// Single-use recovery code, stored as an Argon2 hash
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecoveryCode {
+    pub id: Id,
+    pub user_id: Id,
+
+    #[serde(skip_serializing)]
+    pub code_hash: String,
+
+    pub used_at: Option<Timestamp>,
+    pub created_at: Timestamp,
+}
+
+impl RecoveryCode {
+    pub fn new(user_id: Id, code_hash: String) -> Self {
+        Self {
+            id: Id::new_v4(),
+            user_id,
+            code_hash,
+            used_at: None,
+            created_at: chrono::Utc::now(),
+        }
+    }
+}
+
+/// Registered WebAuthn authenticator
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebauthnCredential {
+    /// Credential ID (base64url)
+    pub id: String,
+    pub user_id: Id,
+
+    /// SubjectPublicKeyInfo DER (base64url)
+    pub public_key: String,
+
+    /// COSE algorithm identifier (-7 for ES256, -8 for EdDSA)
+    pub algorithm: i64,
+
+    /// Authenticator signature counter, used to detect cloned keys
+    pub sign_count: i64,
+
+    pub name: Option<String>,
+    pub created_at: Timestamp,
+    pub last_used_at: Option<Timestamp>,
+}
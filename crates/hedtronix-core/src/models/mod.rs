@@ -4,7 +4,9 @@
 //! with CRDT support for offline-first operation.
 
 pub mod user;
+pub mod role;
 pub mod device;
+pub mod mfa;
 pub mod patient;
 pub mod appointment;
 pub mod clinical_note;
@@ -13,9 +15,14 @@ pub mod audit_log;
 pub mod department;
 pub mod room;
 pub mod encounter;
+pub mod emergency_access;
+pub mod disclosure;
+pub mod access_alert;
 
 pub use user::*;
+pub use role::*;
 pub use device::*;
+pub use mfa::*;
 pub use patient::*;
 pub use appointment::*;
 pub use clinical_note::*;
@@ -24,3 +31,6 @@ pub use audit_log::*;
 pub use department::*;
 pub use room::*;
 pub use encounter::*;
+pub use emergency_access::*;
+pub use disclosure::*;
+pub use access_alert::*;
@@ -1,5 +1,7 @@
 //! Patient model with CRDT support
 
+use std::collections::HashSet;
+
 use chrono::NaiveDate;
 use serde::{Deserialize, Serialize};
 use validator::Validate;
@@ -108,8 +110,8 @@ impl Patient {
 
     pub fn age(&self) -> i32 {
         let today = chrono::Utc::now().date_naive();
-        let years = today.years_since(self.date_of_birth).unwrap_or(0) as i32;
-        years
+        
+        today.years_since(self.date_of_birth).unwrap_or(0) as i32
     }
 
     pub fn add_allergy(&mut self, allergy: Allergy) {
@@ -183,10 +185,52 @@ pub struct UpdatePatient {
 /// Patient search filters
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct PatientSearchFilters {
+    /// Free text matched against names, MRN, phone and date of birth
     pub query: Option<String>,
+    /// Exact matches, ignoring case and punctuation
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    pub medical_record_number: Option<String>,
+    pub date_of_birth: Option<NaiveDate>,
+    pub phone: Option<String>,
     pub active_only: bool,
     pub physician_id: Option<Id>,
     pub department_id: Option<Id>,
+    /// Charts the caller may open; `None` searches every chart
+    #[serde(skip)]
+    pub visible_to: Option<ChartFilter>,
     pub page: u32,
     pub limit: u32,
 }
+
+/// Relationships that open a chart to a caller, for filtering searches. A
+/// patient matching any of them is included; an empty filter matches no one.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChartFilter {
+    /// Patients whose care team includes this user
+    pub care_team_member: Option<Id>,
+    /// Patients seen in this department
+    pub department_id: Option<Id>,
+    /// The chart linked to a patient portal account
+    pub patient_id: Option<Id>,
+}
+
+/// People and departments involved in a patient's care, used to decide who
+/// may open the chart
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PatientCareTeam {
+    pub patient_id: Id,
+    pub primary_care_physician_id: Option<Id>,
+    
+    /// Providers of the patient's encounters and appointments, and note authors
+    pub provider_ids: HashSet<Id>,
+    
+    /// Departments of the patient's encounters and of the PCP
+    pub department_ids: HashSet<Id>,
+}
+
+impl PatientCareTeam {
+    pub fn includes(&self, user_id: Id) -> bool {
+        self.primary_care_physician_id == Some(user_id) || self.provider_ids.contains(&user_id)
+    }
+}
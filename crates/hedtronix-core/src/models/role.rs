@@ -0,0 +1,210 @@
+//! Role model: built-in and custom roles with their granted permissions
+
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::types::{Id, Timestamp, UserRole};
+
+/// A `resource:action` grant. `*` in either part matches anything.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Permission {
+    pub resource: String,
+    pub action: String,
+}
+
+impl Permission {
+    pub fn new(resource: &str, action: &str) -> Self {
+        Self {
+            resource: resource.to_string(),
+            action: action.to_string(),
+        }
+    }
+
+    /// Parse the `resource:action` form used in tokens and URLs
+    pub fn parse(s: &str) -> Option<Self> {
+        let (resource, action) = s.split_once(':')?;
+        if resource.is_empty() || action.is_empty() {
+            return None;
+        }
+        Some(Self::new(resource, action))
+    }
+
+    pub fn matches(&self, resource: &str, action: &str) -> bool {
+        (self.resource == "*" || self.resource == resource)
+            && (self.action == "*" || self.action == action)
+    }
+}
+
+impl std::fmt::Display for Permission {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.resource, self.action)
+    }
+}
+
+/// Role entity. Built-in roles use their `UserRole` name as id; custom
+/// roles get a generated id and take MFA and offline token limits from
+/// `base_role`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Role {
+    pub id: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub base_role: UserRole,
+    pub built_in: bool,
+    pub permissions: Vec<Permission>,
+    pub created_at: Timestamp,
+    pub updated_at: Timestamp,
+}
+
+impl Role {
+    /// A built-in role with its default grants
+    pub fn built_in(role: UserRole) -> Self {
+        let now = chrono::Utc::now();
+        Self {
+            id: role.as_str().to_string(),
+            name: built_in_role_name(role).to_string(),
+            description: None,
+            base_role: role,
+            built_in: true,
+            permissions: default_permissions(role),
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    pub fn new_custom(name: String, base_role: UserRole, permissions: Vec<Permission>) -> Self {
+        let now = chrono::Utc::now();
+        Self {
+            id: Id::new_v4().to_string(),
+            name,
+            description: None,
+            base_role,
+            built_in: false,
+            permissions,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    pub fn allows(&self, resource: &str, action: &str) -> bool {
+        self.permissions.iter().any(|p| p.matches(resource, action))
+    }
+}
+
+/// Role creation DTO
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
+pub struct CreateRole {
+    #[validate(length(min = 1, max = 100))]
+    pub name: String,
+
+    pub description: Option<String>,
+
+    /// Built-in role whose MFA and offline rules apply to holders
+    pub base_role: UserRole,
+
+    #[serde(default)]
+    pub permissions: Vec<Permission>,
+}
+
+pub const ALL_USER_ROLES: [UserRole; 6] = [
+    UserRole::Physician,
+    UserRole::Nurse,
+    UserRole::Receptionist,
+    UserRole::Billing,
+    UserRole::Admin,
+    UserRole::Patient,
+];
+
+fn built_in_role_name(role: UserRole) -> &'static str {
+    match role {
+        UserRole::Physician => "Physician",
+        UserRole::Nurse => "Nurse",
+        UserRole::Receptionist => "Receptionist",
+        UserRole::Billing => "Billing",
+        UserRole::Admin => "Administrator",
+        UserRole::Patient => "Patient",
+    }
+}
+
+/// Grants the built-in roles start with. The database is seeded from this
+/// table; after that the database is the source of truth.
+pub fn default_permissions(role: UserRole) -> Vec<Permission> {
+    let grants: &[(&str, &[&str])] = match role {
+        UserRole::Admin => &[("*", &["*"])],
+        UserRole::Physician => &[
+            ("patients", &["read", "write", "create", "list"]),
+            ("appointments", &["read", "write", "create", "list", "cancel"]),
+            ("clinical_notes", &["read", "write", "create", "sign", "list"]),
+            ("encounters", &["read", "write", "create", "list"]),
+            ("prescriptions", &["read", "write", "create", "sign"]),
+            ("billing", &["read", "list"]),
+            ("reports", &["read"]),
+            ("users", &["read"]),
+            ("sync", &["push", "pull"]),
+        ],
+        UserRole::Nurse => &[
+            ("patients", &["read", "write", "list"]),
+            ("appointments", &["read", "write", "list"]),
+            ("clinical_notes", &["read", "write", "list"]),
+            ("encounters", &["read", "write", "list"]),
+            ("vitals", &["read", "write", "create"]),
+            ("medication_administration", &["read", "write", "create"]),
+            ("billing", &["read", "list"]),
+            ("users", &["read"]),
+            ("sync", &["push", "pull"]),
+        ],
+        UserRole::Receptionist => &[
+            ("patients", &["read_financial", "write", "create", "list"]),
+            ("appointments", &["read", "write", "create", "cancel", "check_in", "list"]),
+            ("billing", &["read", "create_charges", "list"]),
+            ("clinical_notes", &["read"]),
+            ("users", &["read"]),
+            ("rooms", &["read", "list"]),
+            ("sync", &["push", "pull"]),
+        ],
+        UserRole::Billing => &[
+            ("patients", &["read_financial", "list"]),
+            ("appointments", &["read", "list"]),
+            ("clinical_notes", &["read", "list"]),
+            ("encounters", &["read", "list"]),
+            ("billing", &["read", "write", "create", "submit", "adjust", "list"]),
+            ("reports", &["read_financial"]),
+            ("users", &["read"]),
+            ("sync", &["push", "pull"]),
+        ],
+        UserRole::Patient => &[
+            ("own_data", &["read"]),
+            ("appointments", &["read_own", "create_own", "cancel_own"]),
+            ("clinical_notes", &["read_own"]),
+            ("billing", &["read_own", "pay"]),
+            ("messages", &["read", "create"]),
+        ],
+    };
+
+    grants
+        .iter()
+        .flat_map(|(resource, actions)| actions.iter().map(|action| Permission::new(resource, action)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wildcard_matching() {
+        let admin = Role::built_in(UserRole::Admin);
+        assert!(admin.allows("anything", "anything"));
+
+        let physician = Role::built_in(UserRole::Physician);
+        assert!(physician.allows("clinical_notes", "sign"));
+        assert!(!physician.allows("billing", "adjust"));
+    }
+
+    #[test]
+    fn test_permission_parse() {
+        assert_eq!(Permission::parse("billing:adjust"), Some(Permission::new("billing", "adjust")));
+        assert_eq!(Permission::parse("billing"), None);
+        assert_eq!(Permission::new("sync", "push").to_string(), "sync:push");
+    }
+}
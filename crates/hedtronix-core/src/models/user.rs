@@ -1,6 +1,5 @@
 //! User model
 
-use chrono::NaiveDate;
 use serde::{Deserialize, Serialize};
 use validator::Validate;
 
@@ -20,8 +19,17 @@ pub struct User {
     
     pub role: UserRole,
     
+    /// Custom role assignment. Permissions come from this role; `role`
+    /// holds its base role.
+    #[serde(default)]
+    pub role_id: Option<String>,
+    
     pub department_id: Option<Id>,
     
+    /// Chart of the patient a portal account belongs to
+    #[serde(default)]
+    pub patient_id: Option<Id>,
+    
     /// License number (for clinical staff)
     pub license_number: Option<String>,
     
@@ -38,6 +46,25 @@ pub struct User {
     #[serde(skip_serializing)]
     pub password_hash: String,
     
+    /// Consecutive failed login attempts since the last success
+    #[serde(default)]
+    pub failed_login_attempts: u32,
+    
+    #[serde(default)]
+    pub last_failed_login_at: Option<Timestamp>,
+    
+    /// Logins are refused until this time
+    #[serde(default)]
+    pub locked_until: Option<Timestamp>,
+    
+    /// Set on accounts created by an admin; the user must choose a new
+    /// password before getting a session
+    #[serde(default)]
+    pub must_change_password: bool,
+    
+    #[serde(default)]
+    pub password_changed_at: Option<Timestamp>,
+    
     /// CRDT version tracking
     pub version: VersionVector,
     
@@ -53,7 +80,9 @@ impl User {
             email,
             name,
             role,
+            role_id: None,
             department_id: None,
+            patient_id: None,
             license_number: None,
             npi_number: None,
             active: true,
@@ -61,66 +90,25 @@ impl User {
             updated_at: now,
             last_login_at: None,
             password_hash,
+            failed_login_attempts: 0,
+            last_failed_login_at: None,
+            locked_until: None,
+            must_change_password: false,
+            password_changed_at: Some(now),
             version: VersionVector::new(),
             last_modified_by: None,
         }
     }
 
-    /// Check if user has permission for an action
-    pub fn has_permission(&self, resource: &str, action: &str) -> bool {
-        match self.role {
-            UserRole::Admin => true, // Admin has all permissions
-            UserRole::Physician => {
-                matches!(
-                    (resource, action),
-                    ("patients", "read" | "write" | "create")
-                        | ("appointments", "read" | "write" | "create")
-                        | ("clinical_notes", "read" | "write" | "create" | "sign")
-                        | ("prescriptions", "read" | "write" | "create" | "sign")
-                        | ("billing", "read")
-                        | ("reports", "read")
-                )
-            }
-            UserRole::Nurse => {
-                matches!(
-                    (resource, action),
-                    ("patients", "read" | "write")
-                        | ("appointments", "read" | "write")
-                        | ("clinical_notes", "read" | "write")
-                        | ("vitals", "read" | "write" | "create")
-                        | ("medication_administration", "read" | "write" | "create")
-                        | ("billing", "read")
-                )
-            }
-            UserRole::Receptionist => {
-                matches!(
-                    (resource, action),
-                    ("patients", "read" | "write" | "create")
-                        | ("appointments", "read" | "write" | "create" | "cancel")
-                        | ("billing", "read" | "create_charges")
-                        | ("clinical_notes", "read")
-                )
-            }
-            UserRole::Billing => {
-                matches!(
-                    (resource, action),
-                    ("patients", "read")
-                        | ("appointments", "read")
-                        | ("clinical_notes", "read")
-                        | ("billing", "read" | "write" | "create" | "submit" | "adjust")
-                        | ("reports", "read_financial")
-                )
-            }
-            UserRole::Patient => {
-                matches!(
-                    (resource, action),
-                    ("own_data", "read")
-                        | ("appointments", "read_own" | "create_own" | "cancel_own")
-                        | ("messages", "read" | "create")
-                        | ("billing", "read_own" | "pay")
-                )
-            }
-        }
+    /// Whether the account is temporarily locked out
+    pub fn is_locked(&self) -> bool {
+        self.locked_until.is_some_and(|until| until > chrono::Utc::now())
+    }
+
+    /// Id of the role whose grants apply: the custom role if one is
+    /// assigned, otherwise the built-in role
+    pub fn role_key(&self) -> &str {
+        self.role_id.as_deref().unwrap_or(self.role.as_str())
     }
 }
 
@@ -135,7 +123,7 @@ pub struct CreateUser {
     
     pub role: UserRole,
     
-    #[validate(length(min = 8))]
+    #[validate(length(min = 12))]
     pub password: String,
     
     pub department_id: Option<Id>,
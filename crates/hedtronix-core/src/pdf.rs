@@ -0,0 +1,192 @@
+//! Minimal PDF writer for plain-text reports
+//!
+//! Lays monospaced lines of text out on US Letter pages with a page number
+//! footer. Enough for printable reports without a layout engine.
+
+const PAGE_WIDTH: usize = 612;
+const PAGE_HEIGHT: usize = 792;
+const MARGIN: usize = 50;
+const FONT_SIZE: usize = 9;
+const LEADING: usize = 12;
+
+/// Characters per line; Courier glyphs are 0.6 em wide
+pub const LINE_WIDTH: usize = (PAGE_WIDTH - 2 * MARGIN) * 10 / (FONT_SIZE * 6);
+
+/// Lines per page, leaving room for the footer
+const PAGE_LINES: usize = (PAGE_HEIGHT - 2 * MARGIN) / LEADING;
+
+/// A document of plain text lines
+#[derive(Debug, Clone, Default)]
+pub struct TextDocument {
+    title: String,
+    lines: Vec<String>,
+}
+
+impl TextDocument {
+    pub fn new(title: impl Into<String>) -> Self {
+        Self {
+            title: title.into(),
+            lines: Vec::new(),
+        }
+    }
+
+    /// Add a line, wrapping it at word boundaries where it is too long
+    pub fn line(&mut self, text: impl AsRef<str>) {
+        let mut rest: &str = text.as_ref();
+        while rest.chars().count() > LINE_WIDTH {
+            let limit = rest.char_indices().nth(LINE_WIDTH).map(|(i, _)| i).unwrap_or(rest.len());
+            let split = rest[..limit].rfind(' ').filter(|&i| i > 0).unwrap_or(limit);
+            self.lines.push(rest[..split].to_string());
+            rest = rest[split..].trim_start();
+        }
+        self.lines.push(rest.to_string());
+    }
+
+    pub fn blank(&mut self) {
+        self.lines.push(String::new());
+    }
+
+    pub fn page_count(&self) -> usize {
+        self.lines.len().div_ceil(PAGE_LINES).max(1)
+    }
+
+    /// Render the document as PDF 1.4
+    pub fn to_pdf(&self) -> Vec<u8> {
+        let pages: Vec<&[String]> = if self.lines.is_empty() {
+            vec![&[]]
+        } else {
+            self.lines.chunks(PAGE_LINES).collect()
+        };
+
+        // Objects 1-4 are fixed; each page adds a page and a content stream
+        let page_ids: Vec<usize> = (0..pages.len()).map(|i| 5 + 2 * i).collect();
+        let mut objects = vec![
+            "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+            format!(
+                "<< /Type /Pages /Kids [{}] /Count {} >>",
+                page_ids.iter().map(|id| format!("{} 0 R", id)).collect::<Vec<_>>().join(" "),
+                pages.len()
+            ),
+            "<< /Type /Font /Subtype /Type1 /BaseFont /Courier >>".to_string(),
+            format!("<< /Title ({}) /Producer (HEDTRONIX) >>", escape(&self.title)),
+        ];
+
+        for (index, lines) in pages.iter().enumerate() {
+            let content = page_content(lines, index + 1, pages.len());
+            objects.push(format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] \
+                 /Resources << /Font << /F1 3 0 R >> >> /Contents {} 0 R >>",
+                PAGE_WIDTH,
+                PAGE_HEIGHT,
+                page_ids[index] + 1
+            ));
+            objects.push(format!(
+                "<< /Length {} >>\nstream\n{}\nendstream",
+                content.len(),
+                content
+            ));
+        }
+
+        let mut out = b"%PDF-1.4\n".to_vec();
+        let mut offsets = Vec::with_capacity(objects.len());
+        for (index, object) in objects.iter().enumerate() {
+            offsets.push(out.len());
+            out.extend_from_slice(format!("{} 0 obj\n{}\nendobj\n", index + 1, object).as_bytes());
+        }
+
+        let xref = out.len();
+        out.extend_from_slice(format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).as_bytes());
+        for offset in offsets {
+            out.extend_from_slice(format!("{:010} 00000 n \n", offset).as_bytes());
+        }
+        out.extend_from_slice(
+            format!(
+                "trailer\n<< /Size {} /Root 1 0 R /Info 4 0 R >>\nstartxref\n{}\n%%EOF\n",
+                objects.len() + 1,
+                xref
+            )
+            .as_bytes(),
+        );
+
+        out
+    }
+}
+
+fn page_content(lines: &[String], page: usize, pages: usize) -> String {
+    let mut content = format!(
+        "BT\n/F1 {} Tf\n{} TL\n{} {} Td\n",
+        FONT_SIZE,
+        LEADING,
+        MARGIN,
+        PAGE_HEIGHT - MARGIN - FONT_SIZE
+    );
+    for line in lines {
+        content.push_str(&format!("({}) Tj T*\n", escape(line)));
+    }
+    content.push_str("ET\n");
+    content.push_str(&format!(
+        "BT\n/F1 {} Tf\n{} {} Td\n(Page {} of {}) Tj\nET",
+        FONT_SIZE,
+        MARGIN,
+        MARGIN / 2,
+        page,
+        pages
+    ));
+    content
+}
+
+/// Escape text for a PDF string literal. The standard fonts only cover
+/// ASCII here, so anything else is shown as `?`.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '(' | ')' | '\\' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            ' '..='~' => escaped.push(c),
+            c if c.is_whitespace() => escaped.push(' '),
+            _ => escaped.push('?'),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pdf_structure() {
+        let mut doc = TextDocument::new("Report (draft)");
+        for n in 0..(PAGE_LINES + 5) {
+            doc.line(format!("Line {}", n));
+        }
+        assert_eq!(doc.page_count(), 2);
+
+        let pdf = doc.to_pdf();
+        let text = String::from_utf8(pdf.clone()).unwrap();
+        assert!(text.starts_with("%PDF-1.4"));
+        assert!(text.ends_with("%%EOF\n"));
+        assert!(text.contains("/Count 2"));
+        assert!(text.contains("(Report \\(draft\\))"));
+        assert!(text.contains("(Page 2 of 2)"));
+
+        // Every xref entry points at its object
+        let xref = text.rfind("xref\n").unwrap();
+        for (index, entry) in text[xref..].lines().skip(3).take(8).enumerate() {
+            let offset: usize = entry[..10].parse().unwrap();
+            assert!(text[offset..].starts_with(&format!("{} 0 obj", index + 1)));
+        }
+    }
+
+    #[test]
+    fn test_long_lines_wrap() {
+        let mut doc = TextDocument::new("Wrap");
+        doc.line("word ".repeat(LINE_WIDTH / 2));
+        assert_eq!(doc.lines.len(), 3);
+        assert!(doc.lines.iter().all(|l| l.chars().count() <= LINE_WIDTH));
+        assert_eq!(escape("Zoë"), "Zo?");
+    }
+}
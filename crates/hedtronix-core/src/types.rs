@@ -11,7 +11,7 @@ pub type Id = Uuid;
 pub type Timestamp = DateTime<Utc>;
 
 /// User roles as defined in specs
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum UserRole {
     Physician,
@@ -36,7 +36,7 @@ impl UserRole {
 }
 
 /// Device types
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum DeviceType {
     Desktop,
@@ -45,6 +45,17 @@ pub enum DeviceType {
     Kiosk,
 }
 
+impl DeviceType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeviceType::Desktop => "DESKTOP",
+            DeviceType::Tablet => "TABLET",
+            DeviceType::Mobile => "MOBILE",
+            DeviceType::Kiosk => "KIOSK",
+        }
+    }
+}
+
 /// Patient gender options
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
@@ -136,6 +147,21 @@ pub enum AuditEventType {
     Sync,
 }
 
+impl AuditEventType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditEventType::Create => "CREATE",
+            AuditEventType::Read => "READ",
+            AuditEventType::Update => "UPDATE",
+            AuditEventType::Delete => "DELETE",
+            AuditEventType::Login => "LOGIN",
+            AuditEventType::Logout => "LOGOUT",
+            AuditEventType::Export => "EXPORT",
+            AuditEventType::Sync => "SYNC",
+        }
+    }
+}
+
 /// Sync health status
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
@@ -169,6 +195,9 @@ pub struct InsuranceInfo {
     pub provider: Option<String>,
     pub policy_number: Option<String>,
     pub group_number: Option<String>,
+    /// Member identifier; some payers still use the SSN
+    #[serde(default)]
+    pub subscriber_id: Option<String>,
     pub subscriber_name: Option<String>,
     pub subscriber_dob: Option<String>,
 }
@@ -0,0 +1,133 @@
+//! Keys sealed to a device's X25519 public key
+//!
+//! Each device holds an X25519 key pair and publishes the public half when
+//! it registers. A key is sealed to a device by agreeing an ephemeral key
+//! with the device's public key, deriving an AES-256-GCM key from the
+//! shared secret with HKDF, and encrypting under it. Only the device can
+//! open the result; the server that stores and forwards it cannot.
+//!
+//! Sealed format: base64 of `ephemeral public key (32) || nonce || ciphertext || tag`.
+
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use ring::hkdf::{Salt, HKDF_SHA256};
+use x25519_dalek::{PublicKey, StaticSecret};
+use zeroize::Zeroizing;
+
+use crate::encryption::{EncryptionError, Encryptor, Result};
+use crate::keys::generate_random_bytes;
+use crate::secret::SecretKey;
+
+const PUBLIC_KEY_LENGTH: usize = 32;
+
+/// A device's X25519 key pair. The secret is zeroized on drop.
+pub struct AgreementKeyPair {
+    secret: StaticSecret,
+    public: PublicKey,
+}
+
+impl AgreementKeyPair {
+    /// Generate a new random key pair
+    pub fn generate() -> Result<Self> {
+        let bytes = SecretKey::new(
+            generate_random_bytes(32).map_err(|e| EncryptionError::KeyGeneration(e.to_string()))?,
+        );
+        Self::from_secret(&bytes)
+    }
+
+    /// Load a key pair from the 32 secret bytes returned by `secret`
+    pub fn from_secret(secret: &[u8]) -> Result<Self> {
+        let bytes: [u8; 32] = secret.try_into().map_err(|_| EncryptionError::InvalidKeyLength)?;
+        let secret = StaticSecret::from(bytes);
+        let public = PublicKey::from(&secret);
+        Ok(Self { secret, public })
+    }
+
+    /// The secret, for storing the key pair on the device
+    pub fn secret(&self) -> SecretKey {
+        SecretKey::from_slice(self.secret.as_bytes())
+    }
+
+    pub fn public_key(&self) -> &[u8] {
+        self.public.as_bytes()
+    }
+
+    /// Open a key sealed to this device by `seal_key` with the same context
+    pub fn open(&self, sealed: &str, context: &str) -> Result<SecretKey> {
+        let sealed = BASE64.decode(sealed).map_err(|_| EncryptionError::InvalidFormat)?;
+        if sealed.len() <= PUBLIC_KEY_LENGTH {
+            return Err(EncryptionError::InvalidFormat);
+        }
+        let (ephemeral, ciphertext) = sealed.split_at(PUBLIC_KEY_LENGTH);
+        let ephemeral = public_key(ephemeral)?;
+
+        let shared = self.secret.diffie_hellman(&ephemeral);
+        let encryptor = seal_encryptor(shared.as_bytes(), ephemeral.as_bytes(), self.public.as_bytes())?;
+        Ok(SecretKey::new(encryptor.decrypt_bytes(ciphertext, seal_aad(context).as_bytes())?))
+    }
+}
+
+/// Encrypt `key` so that only the holder of the secret for `recipient` (a
+/// 32-byte X25519 public key) can open it. `context` is bound as associated
+/// data, e.g. the key id and the device it was sealed for.
+pub fn seal_key(recipient: &[u8], key: &[u8], context: &str) -> Result<String> {
+    let recipient = public_key(recipient)?;
+    let ephemeral = AgreementKeyPair::generate()?;
+    let shared = ephemeral.secret.diffie_hellman(&recipient);
+    if !shared.was_contributory() {
+        return Err(EncryptionError::InvalidFormat);
+    }
+
+    let encryptor = seal_encryptor(shared.as_bytes(), ephemeral.public_key(), recipient.as_bytes())?;
+    let mut sealed = ephemeral.public_key().to_vec();
+    sealed.extend(encryptor.encrypt_bytes(key, seal_aad(context).as_bytes())?);
+    Ok(BASE64.encode(sealed))
+}
+
+fn public_key(bytes: &[u8]) -> Result<PublicKey> {
+    let bytes: [u8; PUBLIC_KEY_LENGTH] = bytes.try_into().map_err(|_| EncryptionError::InvalidFormat)?;
+    Ok(PublicKey::from(bytes))
+}
+
+/// AES key from the shared secret, salted with both public keys
+fn seal_encryptor(shared: &[u8], ephemeral: &[u8], recipient: &[u8]) -> Result<Encryptor> {
+    let salt = [ephemeral, recipient].concat();
+    let mut key = Zeroizing::new([0u8; 32]);
+    Salt::new(HKDF_SHA256, &salt)
+        .extract(shared)
+        .expand(&[b"hedtronix-seal"], HKDF_SHA256)
+        .and_then(|okm| okm.fill(key.as_mut()))
+        .map_err(|_| EncryptionError::KeyDerivation("HKDF expansion failed".into()))?;
+    Encryptor::new(key.as_ref())
+}
+
+fn seal_aad(context: &str) -> String {
+    format!("hedtronix-seal:{}", context)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seal_and_open() {
+        let device = AgreementKeyPair::generate().unwrap();
+        let key = Encryptor::generate_key().unwrap();
+
+        let sealed = seal_key(device.public_key(), &key, "key-1:device-1").unwrap();
+        assert_eq!(device.open(&sealed, "key-1:device-1").unwrap(), key);
+
+        // Bound to its context and its recipient
+        assert!(device.open(&sealed, "key-1:device-2").is_err());
+        let other = AgreementKeyPair::generate().unwrap();
+        assert!(other.open(&sealed, "key-1:device-1").is_err());
+
+        // The stored secret reloads the same key pair
+        let reloaded = AgreementKeyPair::from_secret(&device.secret()).unwrap();
+        assert_eq!(reloaded.public_key(), device.public_key());
+        assert_eq!(reloaded.open(&sealed, "key-1:device-1").unwrap(), key);
+
+        assert!(seal_key(&[0u8; 31], &key, "key-1").is_err());
+        // The all-zero point yields no shared secret
+        assert!(seal_key(&[0u8; 32], &key, "key-1").is_err());
+    }
+}
@@ -0,0 +1,172 @@
+//! Blind indexes for searching encrypted fields
+//!
+//! A blind index is a keyed HMAC of a normalized value, so equal values can
+//! be matched in SQL without decrypting them and without the index revealing
+//! the value to anyone lacking the key. Besides the exact index, each value
+//! yields tokens for its one- and two-character prefixes and for every
+//! trigram: short queries match by prefix, longer ones by substring (every
+//! trigram of the query present). Tokens still reveal which rows share a
+//! prefix or trigram, which is the usual trade-off for searchable fields.
+
+use std::collections::BTreeSet;
+
+use ring::hmac;
+
+use crate::encryption::{EncryptionError, Result};
+
+/// Bytes of HMAC output kept per index value
+const TAG_LENGTH: usize = 16;
+
+/// Longest prefix indexed; longer queries use trigrams
+const MAX_PREFIX: usize = 2;
+
+/// A searchable encrypted field
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlindIndexField {
+    FirstName,
+    LastName,
+    MedicalRecordNumber,
+    DateOfBirth,
+    Phone,
+}
+
+impl BlindIndexField {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BlindIndexField::FirstName => "first_name",
+            BlindIndexField::LastName => "last_name",
+            BlindIndexField::MedicalRecordNumber => "medical_record_number",
+            BlindIndexField::DateOfBirth => "date_of_birth",
+            BlindIndexField::Phone => "phone",
+        }
+    }
+
+    /// Only exact matches make sense for dates
+    fn has_tokens(&self) -> bool {
+        !matches!(self, BlindIndexField::DateOfBirth)
+    }
+
+    /// Canonical form compared by the index: case, spacing and punctuation
+    /// are ignored, and phone numbers keep only their digits
+    pub fn normalize(&self, value: &str) -> String {
+        match self {
+            BlindIndexField::Phone => value.chars().filter(|c| c.is_ascii_digit()).collect(),
+            BlindIndexField::DateOfBirth => value.trim().to_string(),
+            _ => value
+                .chars()
+                .filter(|c| c.is_alphanumeric())
+                .flat_map(|c| c.to_lowercase())
+                .collect(),
+        }
+    }
+}
+
+/// Key for computing blind indexes
+pub struct BlindIndexKey {
+    key: hmac::Key,
+}
+
+impl BlindIndexKey {
+    pub fn new(key: &[u8]) -> Result<Self> {
+        if key.len() != 32 {
+            return Err(EncryptionError::InvalidKeyLength);
+        }
+        Ok(Self { key: hmac::Key::new(hmac::HMAC_SHA256, key) })
+    }
+
+    fn tag(&self, field: BlindIndexField, kind: &str, value: &str) -> String {
+        let message = format!("{}:{}:{}", field.as_str(), kind, value);
+        let tag = hmac::sign(&self.key, message.as_bytes());
+        tag.as_ref()[..TAG_LENGTH].iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    /// Index of the whole normalized value, or `None` if nothing is left
+    /// after normalizing
+    pub fn exact(&self, field: BlindIndexField, value: &str) -> Option<String> {
+        let normalized = field.normalize(value);
+        (!normalized.is_empty()).then(|| self.tag(field, "eq", &normalized))
+    }
+
+    /// Prefix and trigram tokens to store for a value
+    pub fn tokens(&self, field: BlindIndexField, value: &str) -> Vec<String> {
+        if !field.has_tokens() {
+            return Vec::new();
+        }
+        let chars: Vec<char> = field.normalize(value).chars().collect();
+
+        let mut tokens = BTreeSet::new();
+        for len in 1..=chars.len().min(MAX_PREFIX) {
+            tokens.insert(self.tag(field, "pre", &chars[..len].iter().collect::<String>()));
+        }
+        for window in chars.windows(3) {
+            tokens.insert(self.tag(field, "tri", &window.iter().collect::<String>()));
+        }
+        tokens.into_iter().collect()
+    }
+
+    /// Tokens a stored value must all have to match `query`: its prefix for
+    /// one or two characters, otherwise its trigrams (a substring match).
+    /// Empty when the query has nothing searchable.
+    pub fn query_tokens(&self, field: BlindIndexField, query: &str) -> Vec<String> {
+        if !field.has_tokens() {
+            return Vec::new();
+        }
+        let chars: Vec<char> = field.normalize(query).chars().collect();
+
+        if chars.len() <= MAX_PREFIX {
+            if chars.is_empty() {
+                return Vec::new();
+            }
+            return vec![self.tag(field, "pre", &chars.iter().collect::<String>())];
+        }
+        let tokens: BTreeSet<String> = chars
+            .windows(3)
+            .map(|w| self.tag(field, "tri", &w.iter().collect::<String>()))
+            .collect();
+        tokens.into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contains_all(stored: &[String], query: &[String]) -> bool {
+        !query.is_empty() && query.iter().all(|t| stored.contains(t))
+    }
+
+    #[test]
+    fn test_exact_index_is_normalized_and_keyed() {
+        let key = BlindIndexKey::new(&[1u8; 32]).unwrap();
+        let name = BlindIndexField::LastName;
+
+        assert_eq!(key.exact(name, "O'Brien"), key.exact(name, "obrien "));
+        assert_ne!(key.exact(name, "Obrien"), key.exact(name, "Obrian"));
+        assert_eq!(key.exact(name, " - "), None);
+
+        // Fields and keys are separated
+        assert_ne!(key.exact(name, "Lee"), key.exact(BlindIndexField::FirstName, "Lee"));
+        let other = BlindIndexKey::new(&[2u8; 32]).unwrap();
+        assert_ne!(key.exact(name, "Lee"), other.exact(name, "Lee"));
+
+        let phone = BlindIndexField::Phone;
+        assert_eq!(key.exact(phone, "(555) 010-2030"), key.exact(phone, "555.010.2030"));
+    }
+
+    #[test]
+    fn test_prefix_and_substring_tokens() {
+        let key = BlindIndexKey::new(&[1u8; 32]).unwrap();
+        let field = BlindIndexField::LastName;
+        let stored = key.tokens(field, "Lovelace");
+
+        assert!(contains_all(&stored, &key.query_tokens(field, "L")));
+        assert!(contains_all(&stored, &key.query_tokens(field, "lo")));
+        assert!(contains_all(&stored, &key.query_tokens(field, "LOVE")));
+        assert!(contains_all(&stored, &key.query_tokens(field, "elac")));
+        assert!(!contains_all(&stored, &key.query_tokens(field, "ov")));
+        assert!(!contains_all(&stored, &key.query_tokens(field, "lace x")));
+        assert!(key.query_tokens(field, "'").is_empty());
+
+        assert!(key.tokens(BlindIndexField::DateOfBirth, "1980-01-02").is_empty());
+    }
+}
@@ -1,9 +1,12 @@
 //! AES-256-GCM encryption for sensitive data
 
-use ring::aead::{self, Aad, BoundKey, Nonce, NonceSequence, SealingKey, OpeningKey, UnboundKey};
+use ring::aead::{self, Aad};
 use ring::rand::{SecureRandom, SystemRandom};
 use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
 use thiserror::Error;
+use zeroize::Zeroizing;
+
+use crate::secret::SecretKey;
 
 /// Encryption error types
 #[derive(Error, Debug)]
@@ -17,41 +20,31 @@ pub enum EncryptionError {
     #[error("Decryption failed: {0}")]
     Decryption(String),
     
+    #[error("Key derivation failed: {0}")]
+    KeyDerivation(String),
+
     #[error("Invalid key length")]
     InvalidKeyLength,
     
     #[error("Invalid data format")]
     InvalidFormat,
-}
 
-/// Result type for encryption operations
-pub type Result<T> = std::result::Result<T, EncryptionError>;
+    #[error("Unknown encryption key: {0}")]
+    UnknownKey(String),
 
-/// Counter-based nonce sequence for AES-GCM
-struct CounterNonceSequence {
-    counter: u64,
-    prefix: [u8; 4],
-}
+    #[error("No active encryption key")]
+    NoActiveKey,
 
-impl CounterNonceSequence {
-    fn new(prefix: [u8; 4]) -> Self {
-        Self { counter: 0, prefix }
-    }
+    #[error("Unsupported ciphertext format: {0}")]
+    UnsupportedVersion(String),
 }
 
-impl NonceSequence for CounterNonceSequence {
-    fn advance(&mut self) -> std::result::Result<Nonce, ring::error::Unspecified> {
-        let mut nonce_bytes = [0u8; 12];
-        nonce_bytes[..4].copy_from_slice(&self.prefix);
-        nonce_bytes[4..].copy_from_slice(&self.counter.to_be_bytes());
-        self.counter += 1;
-        Nonce::try_assume_unique_for_key(&nonce_bytes)
-    }
-}
+/// Result type for encryption operations
+pub type Result<T> = std::result::Result<T, EncryptionError>;
 
 /// AES-256-GCM encryptor for field-level encryption
 pub struct Encryptor {
-    key: Vec<u8>,
+    key: SecretKey,
     rng: SystemRandom,
 }
 
@@ -62,32 +55,46 @@ impl Encryptor {
             return Err(EncryptionError::InvalidKeyLength);
         }
         Ok(Self {
-            key: key.to_vec(),
+            key: SecretKey::from_slice(key),
             rng: SystemRandom::new(),
         })
     }
 
     /// Generate a new random 256-bit key
-    pub fn generate_key() -> Result<Vec<u8>> {
+    pub fn generate_key() -> Result<SecretKey> {
         let rng = SystemRandom::new();
-        let mut key = vec![0u8; 32];
+        let mut key = Zeroizing::new(vec![0u8; 32]);
         rng.fill(&mut key)
             .map_err(|_| EncryptionError::KeyGeneration("Failed to generate random key".into()))?;
-        Ok(key)
+        Ok(key.into())
     }
 
     /// Encrypt plaintext and return base64-encoded ciphertext
     pub fn encrypt(&self, plaintext: &str) -> Result<String> {
+        let sealed = self.encrypt_bytes(plaintext.as_bytes(), &[])?;
+        Ok(BASE64.encode(&sealed))
+    }
+
+    /// Decrypt base64-encoded ciphertext
+    pub fn decrypt(&self, ciphertext: &str) -> Result<String> {
+        let data = BASE64.decode(ciphertext)
+            .map_err(|_| EncryptionError::InvalidFormat)?;
+
+        let plaintext = self.decrypt_bytes(&data, &[])?;
+        String::from_utf8(plaintext)
+            .map_err(|_| EncryptionError::Decryption("Invalid UTF-8".into()))
+    }
+
+    /// Encrypt binary data, authenticating `aad` alongside it. Returns the
+    /// nonce followed by the ciphertext and tag.
+    pub fn encrypt_bytes(&self, plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
         let mut nonce_bytes = [0u8; 12];
         self.rng.fill(&mut nonce_bytes)
             .map_err(|_| EncryptionError::Encryption("Failed to generate nonce".into()))?;
 
-        let unbound_key = aead::UnboundKey::new(&aead::AES_256_GCM, &self.key)
-            .map_err(|_| EncryptionError::Encryption("Failed to create key".into()))?;
-
         let nonce = aead::Nonce::assume_unique_for_key(nonce_bytes);
         
-        let mut in_out = plaintext.as_bytes().to_vec();
+        let mut in_out = plaintext.to_vec();
         
         // Use seal_in_place_append_tag for simpler one-shot encryption
         let algorithm = &aead::AES_256_GCM;
@@ -96,21 +103,18 @@ impl Encryptor {
                 .map_err(|_| EncryptionError::Encryption("Failed to create key".into()))?
         );
         
-        key.seal_in_place_append_tag(nonce, Aad::empty(), &mut in_out)
+        key.seal_in_place_append_tag(nonce, Aad::from(aad), &mut in_out)
             .map_err(|_| EncryptionError::Encryption("Encryption failed".into()))?;
 
         // Prepend nonce to ciphertext
         let mut result = nonce_bytes.to_vec();
         result.extend(in_out);
 
-        Ok(BASE64.encode(&result))
+        Ok(result)
     }
 
-    /// Decrypt base64-encoded ciphertext
-    pub fn decrypt(&self, ciphertext: &str) -> Result<String> {
-        let data = BASE64.decode(ciphertext)
-            .map_err(|_| EncryptionError::InvalidFormat)?;
-
+    /// Decrypt data produced by `encrypt_bytes` with the same `aad`
+    pub fn decrypt_bytes(&self, data: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
         if data.len() < 12 {
             return Err(EncryptionError::InvalidFormat);
         }
@@ -127,11 +131,10 @@ impl Encryptor {
 
         let nonce = aead::Nonce::assume_unique_for_key(nonce_bytes);
         
-        let plaintext = key.open_in_place(nonce, Aad::empty(), &mut ciphertext_with_tag)
+        let plaintext = key.open_in_place(nonce, Aad::from(aad), &mut ciphertext_with_tag)
             .map_err(|_| EncryptionError::Decryption("Decryption failed".into()))?;
 
-        String::from_utf8(plaintext.to_vec())
-            .map_err(|_| EncryptionError::Decryption("Invalid UTF-8".into()))
+        Ok(plaintext.to_vec())
     }
 }
 
@@ -163,6 +166,15 @@ mod tests {
         assert_eq!(plaintext, decrypted);
     }
 
+    #[test]
+    fn test_bytes_are_bound_to_aad() {
+        let encryptor = Encryptor::new(&Encryptor::generate_key().unwrap()).unwrap();
+
+        let sealed = encryptor.encrypt_bytes(b"\x00\x01 binary", b"segment-1").unwrap();
+        assert_eq!(encryptor.decrypt_bytes(&sealed, b"segment-1").unwrap(), b"\x00\x01 binary");
+        assert!(encryptor.decrypt_bytes(&sealed, b"segment-2").is_err());
+    }
+
     #[test]
     fn test_invalid_key_length() {
         let result = Encryptor::new(&[0u8; 16]);
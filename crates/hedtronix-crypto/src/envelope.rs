@@ -0,0 +1,463 @@
+//! Envelope encryption for PHI fields
+//!
+//! Fields are encrypted with data encryption keys (DEKs). Each DEK is stored
+//! wrapped by the key encryption key (KEK) from the server configuration, so
+//! rotating the KEK only rewraps the DEKs and rotating a DEK only requires
+//! re-encrypting rows in the background.
+//!
+//! Field ciphertexts are written as `v2:<key id>:<base64>`, with the table,
+//! column and row id of the value as associated data, so a ciphertext copied
+//! to another row or column no longer decrypts. Two older formats carry no
+//! associated data: `<key id>:<base64>`, and plain base64 from before
+//! envelope encryption, decrypted with the legacy key. They are only read
+//! while re-encrypting, so a downgraded value cannot be moved between rows.
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use ring::hmac;
+
+use crate::blind_index::BlindIndexKey;
+use crate::encryption::{EncryptionError, Encryptor, Result};
+use crate::keys::generate_random_bytes;
+use crate::secret::SecretKey;
+
+/// A data encryption key and its id
+pub struct DataKey {
+    id: String,
+    key: SecretKey,
+}
+
+impl std::fmt::Debug for DataKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DataKey").field("id", &self.id).finish_non_exhaustive()
+    }
+}
+
+impl DataKey {
+    /// A new random 256-bit key with a random id
+    pub fn generate() -> Result<Self> {
+        let id = generate_random_bytes(8)
+            .map_err(|e| EncryptionError::KeyGeneration(e.to_string()))?;
+        Ok(Self { id: to_hex(&id), key: Encryptor::generate_key()? })
+    }
+
+    pub fn from_bytes(id: &str, key: &[u8]) -> Result<Self> {
+        if key.len() != 32 {
+            return Err(EncryptionError::InvalidKeyLength);
+        }
+        if id.is_empty() || id.contains(':') || is_version_tag(id) {
+            return Err(EncryptionError::InvalidFormat);
+        }
+        Ok(Self { id: id.to_string(), key: SecretKey::from_slice(key) })
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+/// The key that wraps data keys for storage
+pub struct KeyEncryptionKey {
+    id: String,
+    encryptor: Encryptor,
+}
+
+impl KeyEncryptionKey {
+    pub fn new(key: &[u8]) -> Result<Self> {
+        let encryptor = Encryptor::new(key)?;
+        // Identifies the key without revealing anything about it
+        let tag = hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, key), b"hedtronix-kek-id");
+        Ok(Self { id: to_hex(&tag.as_ref()[..8]), encryptor })
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Encrypt a data key for storage, bound to its id
+    pub fn wrap(&self, key: &DataKey) -> Result<String> {
+        let sealed = self.encryptor.encrypt_bytes(&key.key, wrap_aad(&key.id).as_bytes())?;
+        Ok(BASE64.encode(sealed))
+    }
+
+    /// Recover a data key wrapped by `wrap`
+    pub fn unwrap(&self, id: &str, wrapped: &str) -> Result<DataKey> {
+        let sealed = BASE64.decode(wrapped).map_err(|_| EncryptionError::InvalidFormat)?;
+        let key = SecretKey::new(self.encryptor.decrypt_bytes(&sealed, wrap_aad(id).as_bytes())?);
+        DataKey::from_bytes(id, &key)
+    }
+}
+
+fn wrap_aad(id: &str) -> String {
+    format!("hedtronix-dek:{}", id)
+}
+
+/// Version tag of the current field ciphertext format
+pub const FIELD_FORMAT_VERSION: &str = "v2";
+
+/// Whether the first segment of a ciphertext is a format version (`v<n>`)
+/// rather than a key id
+fn is_version_tag(segment: &str) -> bool {
+    segment.len() > 1 && segment.starts_with('v') && segment[1..].bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Where an encrypted value is stored. It is bound into the ciphertext as
+/// associated data, so the value only decrypts in the same place.
+#[derive(Debug, Clone, Copy)]
+pub struct FieldContext<'a> {
+    pub table: &'a str,
+    pub column: &'a str,
+    pub row_id: &'a str,
+}
+
+impl<'a> FieldContext<'a> {
+    pub fn new(table: &'a str, column: &'a str, row_id: &'a str) -> Self {
+        Self { table, column, row_id }
+    }
+
+    /// The row id is the only free-form part, so it goes last
+    fn aad(&self, key_id: &str) -> String {
+        format!(
+            "hedtronix-field:{}:{}:{}:{}:{}",
+            FIELD_FORMAT_VERSION, key_id, self.table, self.column, self.row_id
+        )
+    }
+}
+
+/// A field ciphertext split into its parts
+enum FieldCiphertext<'a> {
+    /// Current format, bound to its field
+    Bound { key_id: &'a str, data: &'a str },
+    /// Names its key but has no associated data
+    Keyed { key_id: &'a str, data: &'a str },
+    /// Written before envelope encryption
+    Legacy(&'a str),
+}
+
+impl<'a> FieldCiphertext<'a> {
+    fn parse(ciphertext: &'a str) -> Result<Self> {
+        match ciphertext.split_once(':') {
+            Some((version, rest)) if version == FIELD_FORMAT_VERSION => {
+                let (key_id, data) = rest.split_once(':').ok_or(EncryptionError::InvalidFormat)?;
+                Ok(Self::Bound { key_id, data })
+            }
+            Some((version, _)) if is_version_tag(version) => {
+                Err(EncryptionError::UnsupportedVersion(version.to_string()))
+            }
+            Some((key_id, data)) => Ok(Self::Keyed { key_id, data }),
+            None => Ok(Self::Legacy(ciphertext)),
+        }
+    }
+
+    fn key_id(&self) -> Option<&'a str> {
+        match self {
+            Self::Bound { key_id, .. } | Self::Keyed { key_id, .. } => Some(key_id),
+            Self::Legacy(_) => None,
+        }
+    }
+}
+
+#[derive(Default)]
+struct KeyringState {
+    keys: HashMap<String, Encryptor>,
+    active: Option<String>,
+    legacy: Option<Encryptor>,
+    index: Option<Arc<BlindIndexKey>>,
+}
+
+/// Data keys available for field encryption. New ciphertexts use the
+/// active key; any key in the ring can decrypt. Shared between repositories
+/// so a rotation takes effect everywhere at once.
+#[derive(Default)]
+pub struct Keyring {
+    state: RwLock<KeyringState>,
+}
+
+impl Keyring {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A keyring holding only a pre-envelope key, from which the blind
+    /// index key is derived. It cannot encrypt until a data key is active.
+    pub fn legacy(key: &[u8]) -> Result<Self> {
+        let keyring = Self::new();
+        keyring.set_legacy_key(key)?;
+        let index_key = hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, key), b"hedtronix-blind-index");
+        keyring.set_index_key(index_key.as_ref())?;
+        Ok(keyring)
+    }
+
+    /// A keyring whose only data key is active and also derives the blind
+    /// index key, for callers that keep no key store
+    pub fn with_key(key: DataKey) -> Result<Self> {
+        let keyring = Self::new();
+        let index_key = hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, &key.key), b"hedtronix-blind-index");
+        keyring.set_index_key(index_key.as_ref())?;
+        let id = key.id.clone();
+        keyring.insert(key)?;
+        keyring.activate(&id)?;
+        Ok(keyring)
+    }
+
+    /// Key for ciphertexts written before envelope encryption. It only
+    /// decrypts, and only for re-encryption.
+    pub fn set_legacy_key(&self, key: &[u8]) -> Result<()> {
+        let encryptor = Encryptor::new(key)?;
+        self.write()?.legacy = Some(encryptor);
+        Ok(())
+    }
+
+    /// Key for the blind indexes of searchable fields. Unlike data keys it
+    /// is never rotated, since every index would have to be rebuilt.
+    pub fn set_index_key(&self, key: &[u8]) -> Result<()> {
+        let index = BlindIndexKey::new(key)?;
+        self.write()?.index = Some(Arc::new(index));
+        Ok(())
+    }
+
+    /// Use a stored key for blind indexes
+    pub fn insert_index_key(&self, key: DataKey) -> Result<()> {
+        self.set_index_key(&key.key)
+    }
+
+    pub fn index_key(&self) -> Result<Arc<BlindIndexKey>> {
+        self.read()?
+            .index
+            .clone()
+            .ok_or_else(|| EncryptionError::UnknownKey("blind index".to_string()))
+    }
+
+    /// Add a key for decryption
+    pub fn insert(&self, key: DataKey) -> Result<()> {
+        let encryptor = Encryptor::new(&key.key)?;
+        self.write()?.keys.insert(key.id, encryptor);
+        Ok(())
+    }
+
+    /// Encrypt new values with a key already in the ring
+    pub fn activate(&self, id: &str) -> Result<()> {
+        let mut state = self.write()?;
+        if !state.keys.contains_key(id) {
+            return Err(EncryptionError::UnknownKey(id.to_string()));
+        }
+        state.active = Some(id.to_string());
+        Ok(())
+    }
+
+    pub fn active_key_id(&self) -> Option<String> {
+        self.read().ok().and_then(|state| state.active.clone())
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.read().is_ok_and(|state| state.keys.contains_key(id))
+    }
+
+    /// Encrypt a field value with the active key, bound to where it is
+    /// stored
+    pub fn encrypt(&self, plaintext: &str, field: &FieldContext) -> Result<String> {
+        let state = self.read()?;
+        let id = state.active.as_ref().ok_or(EncryptionError::NoActiveKey)?;
+        let encryptor = state.keys.get(id).ok_or_else(|| EncryptionError::UnknownKey(id.clone()))?;
+        let sealed = encryptor.encrypt_bytes(plaintext.as_bytes(), field.aad(id).as_bytes())?;
+        Ok(format!("{}:{}:{}", FIELD_FORMAT_VERSION, id, BASE64.encode(sealed)))
+    }
+
+    /// Decrypt a field value bound to `field` with whichever key the
+    /// ciphertext names. Older formats are refused; re-encryption moves
+    /// them onto the current one.
+    pub fn decrypt(&self, ciphertext: &str, field: &FieldContext) -> Result<String> {
+        match FieldCiphertext::parse(ciphertext)? {
+            FieldCiphertext::Bound { .. } => self.decrypt_any(ciphertext, field),
+            _ => Err(EncryptionError::UnsupportedVersion("unbound".to_string())),
+        }
+    }
+
+    /// Decrypt a field value in any format, including those without
+    /// associated data. Only for re-encrypting the value in place.
+    pub fn decrypt_for_reencryption(&self, ciphertext: &str, field: &FieldContext) -> Result<String> {
+        self.decrypt_any(ciphertext, field)
+    }
+
+    fn decrypt_any(&self, ciphertext: &str, field: &FieldContext) -> Result<String> {
+        let state = self.read()?;
+        let (key_id, data, aad) = match FieldCiphertext::parse(ciphertext)? {
+            FieldCiphertext::Bound { key_id, data } => (key_id, data, field.aad(key_id)),
+            FieldCiphertext::Keyed { key_id, data } => (key_id, data, String::new()),
+            FieldCiphertext::Legacy(data) => {
+                return state
+                    .legacy
+                    .as_ref()
+                    .ok_or_else(|| EncryptionError::UnknownKey("legacy".to_string()))?
+                    .decrypt(data);
+            }
+        };
+
+        let encryptor = state.keys.get(key_id).ok_or_else(|| EncryptionError::UnknownKey(key_id.to_string()))?;
+        let sealed = BASE64.decode(data).map_err(|_| EncryptionError::InvalidFormat)?;
+        let plaintext = encryptor.decrypt_bytes(&sealed, aad.as_bytes())?;
+        String::from_utf8(plaintext).map_err(|_| EncryptionError::Decryption("Invalid UTF-8".into()))
+    }
+
+    /// Id of the key a ciphertext was written with (`None` for legacy
+    /// ciphertexts)
+    pub fn key_id(ciphertext: &str) -> Option<&str> {
+        FieldCiphertext::parse(ciphertext).ok().and_then(|c| c.key_id())
+    }
+
+    /// Whether a ciphertext should be rewritten: it uses a key other than
+    /// the active one, or an older format
+    pub fn needs_reencryption(&self, ciphertext: &str) -> bool {
+        let Some(active) = self.active_key_id() else {
+            return false;
+        };
+        !matches!(
+            FieldCiphertext::parse(ciphertext),
+            Ok(FieldCiphertext::Bound { key_id, .. }) if key_id == active
+        )
+    }
+
+    /// Drop every key, e.g. when the device locks. Key bytes are zeroized
+    /// as they are dropped; encrypting or decrypting fails until keys are
+    /// loaded again.
+    pub fn clear(&self) -> Result<()> {
+        *self.write()? = KeyringState::default();
+        Ok(())
+    }
+
+    /// Whether any key is loaded
+    pub fn is_loaded(&self) -> bool {
+        self.read().is_ok_and(|state| !state.keys.is_empty() || state.legacy.is_some())
+    }
+
+    fn read(&self) -> Result<std::sync::RwLockReadGuard<'_, KeyringState>> {
+        self.state.read().map_err(|_| EncryptionError::Encryption("Keyring lock poisoned".into()))
+    }
+
+    fn write(&self) -> Result<std::sync::RwLockWriteGuard<'_, KeyringState>> {
+        self.state.write().map_err(|_| EncryptionError::Encryption("Keyring lock poisoned".into()))
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrap_unwrap() {
+        let kek = KeyEncryptionKey::new(&Encryptor::generate_key().unwrap()).unwrap();
+        let dek = DataKey::generate().unwrap();
+
+        let wrapped = kek.wrap(&dek).unwrap();
+        let unwrapped = kek.unwrap(dek.id(), &wrapped).unwrap();
+        assert_eq!(unwrapped.key, dek.key);
+
+        // Bound to the key id, and only the same KEK opens it
+        assert!(kek.unwrap("0000000000000000", &wrapped).is_err());
+        let other = KeyEncryptionKey::new(&Encryptor::generate_key().unwrap()).unwrap();
+        assert!(other.unwrap(dek.id(), &wrapped).is_err());
+        assert_ne!(kek.id(), other.id());
+    }
+
+    const FIELD: FieldContext<'static> = FieldContext {
+        table: "patients",
+        column: "first_name",
+        row_id: "row-1",
+    };
+
+    #[test]
+    fn test_rotation_keeps_old_ciphertexts_readable() {
+        let legacy_key = Encryptor::generate_key().unwrap();
+        let keyring = Keyring::legacy(&legacy_key).unwrap();
+
+        let legacy = crate::encrypt_field("legacy", &legacy_key).unwrap();
+        assert_eq!(Keyring::key_id(&legacy), None);
+
+        let first = DataKey::generate().unwrap();
+        let first_id = first.id().to_string();
+        let unbound = Encryptor::new(&first.key).unwrap().encrypt_bytes(b"unbound", &[]).unwrap();
+        let unbound = format!("{}:{}", first_id, BASE64.encode(unbound));
+        keyring.insert(first).unwrap();
+        keyring.activate(&first_id).unwrap();
+        let old = keyring.encrypt("first", &FIELD).unwrap();
+        assert!(old.starts_with("v2:"));
+        assert_eq!(Keyring::key_id(&old), Some(first_id.as_str()));
+
+        let second = DataKey::generate().unwrap();
+        let second_id = second.id().to_string();
+        keyring.insert(second).unwrap();
+        keyring.activate(&second_id).unwrap();
+        let new = keyring.encrypt("second", &FIELD).unwrap();
+
+        assert_eq!(keyring.decrypt_for_reencryption(&legacy, &FIELD).unwrap(), "legacy");
+        assert_eq!(keyring.decrypt_for_reencryption(&unbound, &FIELD).unwrap(), "unbound");
+        assert_eq!(keyring.decrypt(&old, &FIELD).unwrap(), "first");
+        assert_eq!(keyring.decrypt(&new, &FIELD).unwrap(), "second");
+        assert!(keyring.needs_reencryption(&legacy));
+        assert!(keyring.needs_reencryption(&unbound));
+        assert!(keyring.needs_reencryption(&old));
+        assert!(!keyring.needs_reencryption(&new));
+
+        assert!(matches!(keyring.activate("missing"), Err(EncryptionError::UnknownKey(_))));
+        assert!(matches!(keyring.decrypt("v2:missing:AAAA", &FIELD), Err(EncryptionError::UnknownKey(_))));
+        assert!(matches!(keyring.decrypt("v9:AAAA", &FIELD), Err(EncryptionError::UnsupportedVersion(_))));
+    }
+
+    #[test]
+    fn test_unbound_formats_are_only_read_for_reencryption() {
+        let legacy_key = Encryptor::generate_key().unwrap();
+        let keyring = Keyring::legacy(&legacy_key).unwrap();
+        let key = DataKey::generate().unwrap();
+        let id = key.id().to_string();
+        let unbound = Encryptor::new(&key.key).unwrap().encrypt_bytes(b"unbound", &[]).unwrap();
+        let unbound = format!("{}:{}", id, BASE64.encode(unbound));
+        keyring.insert(key).unwrap();
+
+        let legacy = crate::encrypt_field("legacy", &legacy_key).unwrap();
+        for ciphertext in [&legacy, &unbound] {
+            assert!(matches!(keyring.decrypt(ciphertext, &FIELD), Err(EncryptionError::UnsupportedVersion(_))));
+        }
+        assert_eq!(keyring.decrypt_for_reencryption(&legacy, &FIELD).unwrap(), "legacy");
+        assert_eq!(keyring.decrypt_for_reencryption(&unbound, &FIELD).unwrap(), "unbound");
+    }
+
+    #[test]
+    fn test_ciphertext_is_bound_to_its_field() {
+        let keyring = Keyring::new();
+        let key = DataKey::generate().unwrap();
+        let id = key.id().to_string();
+        keyring.insert(key).unwrap();
+        keyring.activate(&id).unwrap();
+
+        let ciphertext = keyring.encrypt("Ada", &FIELD).unwrap();
+        assert_eq!(keyring.decrypt(&ciphertext, &FIELD).unwrap(), "Ada");
+
+        // Moved to another row, column or table
+        for field in [
+            FieldContext::new("patients", "first_name", "row-2"),
+            FieldContext::new("patients", "last_name", "row-1"),
+            FieldContext::new("clinical_notes", "first_name", "row-1"),
+        ] {
+            assert!(keyring.decrypt(&ciphertext, &field).is_err());
+        }
+
+        // Stripping the header does not fall back to the unbound format
+        let stripped = ciphertext.trim_start_matches("v2:");
+        assert!(keyring.decrypt(stripped, &FIELD).is_err());
+    }
+
+    #[test]
+    fn test_empty_keyring_cannot_encrypt() {
+        assert!(matches!(Keyring::new().encrypt("x", &FIELD), Err(EncryptionError::NoActiveKey)));
+
+        // The legacy key never encrypts new values
+        let legacy = Keyring::legacy(&Encryptor::generate_key().unwrap()).unwrap();
+        assert!(matches!(legacy.encrypt("x", &FIELD), Err(EncryptionError::NoActiveKey)));
+    }
+}
@@ -0,0 +1,411 @@
+//! Key escrow with Shamir secret sharing
+//!
+//! The key encryption key is split into N shares for designated custodians,
+//! any M of which reconstruct it; fewer reveal nothing about it. Each byte
+//! of the key is the constant term of a random polynomial of degree M - 1
+//! over GF(2^8), and share `i` holds the polynomials evaluated at `x = i`.
+//!
+//! A share is written as one line of text, to print or store offline:
+//!
+//! ```text
+//! hxs1:<key id>:<threshold>:<index>:<share hex>:<checksum>
+//! ```
+//!
+//! - `key id` is the id of the escrowed key (as `KeyEncryptionKey::id`),
+//!   which identifies it without revealing it and lets recovery confirm the
+//!   reconstructed key is the right one
+//! - `threshold` is M, and `index` the share's x coordinate (1 to 255)
+//! - `share hex` is the 32 share bytes in lowercase hex
+//! - `checksum` is the first 4 bytes of SHA-256 over everything before it
+//!   (including the final colon), in hex, catching transcription errors
+//!
+//! Recovery is a ceremony: custodians present their shares one at a time to
+//! a `RecoveryCeremony`, which rejects damaged, duplicate or foreign shares
+//! and reconstructs the key once M have been accepted.
+
+use std::fmt;
+
+use ring::digest::{digest, SHA256};
+use thiserror::Error;
+use zeroize::Zeroizing;
+
+use crate::envelope::KeyEncryptionKey;
+use crate::keys::generate_random_bytes;
+use crate::secret::SecretKey;
+
+/// Prefix and format version of serialized shares
+const SHARE_PREFIX: &str = "hxs1";
+
+/// Length of escrowed keys
+const KEY_LENGTH: usize = 32;
+
+/// Key escrow error types
+#[derive(Error, Debug)]
+pub enum EscrowError {
+    #[error("Invalid escrow parameters: {0}")]
+    InvalidParameters(String),
+
+    #[error("Invalid share: {0}")]
+    InvalidShare(String),
+
+    #[error("Share checksum does not match; check it was copied correctly")]
+    ChecksumMismatch,
+
+    #[error("Share belongs to a different key or split")]
+    MismatchedShare,
+
+    #[error("Share {0} was already presented")]
+    DuplicateShare(u8),
+
+    #[error("{have} of {need} shares presented")]
+    NotEnoughShares { have: usize, need: usize },
+
+    #[error("Shares do not reconstruct the escrowed key")]
+    WrongKey,
+
+    #[error("Random generation failed: {0}")]
+    Random(String),
+}
+
+/// Result type for escrow operations
+pub type Result<T> = std::result::Result<T, EscrowError>;
+
+/// One custodian's share of an escrowed key
+#[derive(Clone)]
+pub struct KeyShare {
+    key_id: String,
+    threshold: u8,
+    index: u8,
+    data: Zeroizing<Vec<u8>>,
+}
+
+impl fmt::Debug for KeyShare {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyShare")
+            .field("key_id", &self.key_id)
+            .field("threshold", &self.threshold)
+            .field("index", &self.index)
+            .finish_non_exhaustive()
+    }
+}
+
+impl KeyShare {
+    pub fn key_id(&self) -> &str {
+        &self.key_id
+    }
+
+    /// Shares needed to recover the key
+    pub fn threshold(&self) -> u8 {
+        self.threshold
+    }
+
+    pub fn index(&self) -> u8 {
+        self.index
+    }
+
+    /// The share as one line of text, with a checksum
+    pub fn encode(&self) -> String {
+        let body = format!(
+            "{}:{}:{}:{}:{}:",
+            SHARE_PREFIX,
+            self.key_id,
+            self.threshold,
+            self.index,
+            to_hex(&self.data),
+        );
+        let checksum = to_hex(&digest(&SHA256, body.as_bytes()).as_ref()[..4]);
+        format!("{}{}", body, checksum)
+    }
+
+    /// Parse a share written by `encode`. Surrounding whitespace and case
+    /// are ignored.
+    pub fn decode(encoded: &str) -> Result<Self> {
+        let encoded = encoded.trim().to_ascii_lowercase();
+        let (body, checksum) = encoded
+            .rsplit_once(':')
+            .ok_or_else(|| EscrowError::InvalidShare("missing checksum".to_string()))?;
+        let body = format!("{}:", body);
+        if to_hex(&digest(&SHA256, body.as_bytes()).as_ref()[..4]) != checksum {
+            return Err(EscrowError::ChecksumMismatch);
+        }
+
+        let parts: Vec<&str> = body.trim_end_matches(':').split(':').collect();
+        let [prefix, key_id, threshold, index, data] = parts[..] else {
+            return Err(EscrowError::InvalidShare("wrong number of fields".to_string()));
+        };
+        if prefix != SHARE_PREFIX {
+            return Err(EscrowError::InvalidShare(format!("unsupported format {}", prefix)));
+        }
+        let threshold: u8 = threshold
+            .parse()
+            .map_err(|_| EscrowError::InvalidShare("invalid threshold".to_string()))?;
+        let index: u8 = index
+            .parse()
+            .map_err(|_| EscrowError::InvalidShare("invalid index".to_string()))?;
+        let data = Zeroizing::new(
+            from_hex(data).ok_or_else(|| EscrowError::InvalidShare("invalid share data".to_string()))?,
+        );
+        if threshold < 2 || index == 0 || data.len() != KEY_LENGTH {
+            return Err(EscrowError::InvalidShare("out of range".to_string()));
+        }
+
+        Ok(Self { key_id: key_id.to_string(), threshold, index, data })
+    }
+}
+
+/// Split a 256-bit key into `shares` shares, any `threshold` of which
+/// recover it
+pub fn split_key(key: &[u8], threshold: u8, shares: u8) -> Result<Vec<KeyShare>> {
+    if key.len() != KEY_LENGTH {
+        return Err(EscrowError::InvalidParameters("keys must be 32 bytes".to_string()));
+    }
+    if threshold < 2 || threshold > shares {
+        return Err(EscrowError::InvalidParameters(
+            "threshold must be at least 2 and at most the number of shares".to_string(),
+        ));
+    }
+    let key_id = KeyEncryptionKey::new(key)
+        .map_err(|e| EscrowError::InvalidParameters(e.to_string()))?
+        .id()
+        .to_string();
+
+    // Coefficients 1..threshold of each byte's polynomial
+    let degree = threshold as usize - 1;
+    let coefficients = Zeroizing::new(
+        generate_random_bytes(KEY_LENGTH * degree).map_err(|e| EscrowError::Random(e.to_string()))?,
+    );
+
+    let split = (1..=shares)
+        .map(|x| {
+            let data = key
+                .iter()
+                .enumerate()
+                .map(|(i, &secret)| {
+                    // Horner's rule, highest coefficient first, ending with
+                    // the secret as the constant term
+                    let coefficients = &coefficients[i * degree..(i + 1) * degree];
+                    let high = coefficients.iter().rev().fold(0u8, |acc, &c| gf_mul(acc, x) ^ c);
+                    gf_mul(high, x) ^ secret
+                })
+                .collect();
+            KeyShare {
+                key_id: key_id.clone(),
+                threshold,
+                index: x,
+                data: Zeroizing::new(data),
+            }
+        })
+        .collect();
+
+    Ok(split)
+}
+
+/// Reconstruct a key from at least `threshold` shares of the same split,
+/// checking it against the escrowed key's id
+pub fn combine_shares(shares: &[KeyShare]) -> Result<SecretKey> {
+    let first = shares.first().ok_or(EscrowError::NotEnoughShares { have: 0, need: 2 })?;
+    let need = first.threshold as usize;
+    for (i, share) in shares.iter().enumerate() {
+        if share.key_id != first.key_id || share.threshold != first.threshold {
+            return Err(EscrowError::MismatchedShare);
+        }
+        if shares[..i].iter().any(|s| s.index == share.index) {
+            return Err(EscrowError::DuplicateShare(share.index));
+        }
+    }
+    if shares.len() < need {
+        return Err(EscrowError::NotEnoughShares { have: shares.len(), need });
+    }
+    let shares = &shares[..need];
+
+    // Lagrange interpolation at x = 0
+    let mut key = Zeroizing::new(vec![0u8; KEY_LENGTH]);
+    for (i, share) in shares.iter().enumerate() {
+        let mut basis = 1u8;
+        for (j, other) in shares.iter().enumerate() {
+            if i != j {
+                // x_j / (x_j - x_i); subtraction is XOR in GF(2^8)
+                basis = gf_mul(basis, gf_div(other.index, other.index ^ share.index));
+            }
+        }
+        for (byte, &y) in key.iter_mut().zip(share.data.iter()) {
+            *byte ^= gf_mul(basis, y);
+        }
+    }
+
+    let recovered_id = KeyEncryptionKey::new(&key).map_err(|_| EscrowError::WrongKey)?;
+    if recovered_id.id() != first.key_id {
+        return Err(EscrowError::WrongKey);
+    }
+    Ok(key.into())
+}
+
+/// How far a recovery ceremony has come
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CeremonyProgress {
+    pub key_id: String,
+    pub threshold: usize,
+    pub received: usize,
+}
+
+impl CeremonyProgress {
+    pub fn is_complete(&self) -> bool {
+        self.received >= self.threshold
+    }
+}
+
+/// Collects custodians' shares until the key can be recovered
+#[derive(Debug, Default)]
+pub struct RecoveryCeremony {
+    shares: Vec<KeyShare>,
+}
+
+impl RecoveryCeremony {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accept one serialized share. The first share fixes the key and
+    /// threshold; later ones must match it and not repeat an index.
+    pub fn add_share(&mut self, encoded: &str) -> Result<CeremonyProgress> {
+        let share = KeyShare::decode(encoded)?;
+        if let Some(first) = self.shares.first() {
+            if share.key_id != first.key_id || share.threshold != first.threshold {
+                return Err(EscrowError::MismatchedShare);
+            }
+            if self.shares.iter().any(|s| s.index == share.index) {
+                return Err(EscrowError::DuplicateShare(share.index));
+            }
+        }
+        self.shares.push(share);
+        Ok(self.progress().expect("a share was just added"))
+    }
+
+    /// `None` until the first share is presented
+    pub fn progress(&self) -> Option<CeremonyProgress> {
+        self.shares.first().map(|first| CeremonyProgress {
+            key_id: first.key_id.clone(),
+            threshold: first.threshold as usize,
+            received: self.shares.len(),
+        })
+    }
+
+    /// Reconstruct the key. Share data is zeroized as the ceremony is
+    /// dropped.
+    pub fn recover(self) -> Result<SecretKey> {
+        combine_shares(&self.shares)
+    }
+}
+
+/// Multiplication in GF(2^8) modulo x^8 + x^4 + x^3 + x + 1, without
+/// data-dependent branches
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0u8;
+    for _ in 0..8 {
+        product ^= a & (b & 1).wrapping_neg();
+        let carry = (a >> 7).wrapping_neg();
+        a = (a << 1) ^ (carry & 0x1b);
+        b >>= 1;
+    }
+    product
+}
+
+/// Division in GF(2^8); `b` is never zero for distinct share indexes
+fn gf_div(a: u8, b: u8) -> u8 {
+    // b^254 is the inverse of b
+    let mut inverse = 1u8;
+    let mut power = b;
+    let mut exponent = 254u8;
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            inverse = gf_mul(inverse, power);
+        }
+        power = gf_mul(power, power);
+        exponent >>= 1;
+    }
+    gf_mul(a, inverse)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(value: &str) -> Option<Vec<u8>> {
+    if !value.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(value.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encryption::Encryptor;
+
+    #[test]
+    fn test_any_threshold_shares_recover_the_key() {
+        let key = Encryptor::generate_key().unwrap();
+        let shares = split_key(&key, 3, 5).unwrap();
+        assert_eq!(shares.len(), 5);
+
+        for subset in [[0, 1, 2], [4, 2, 0], [1, 3, 4]] {
+            let chosen: Vec<KeyShare> = subset.iter().map(|&i| shares[i].clone()).collect();
+            assert_eq!(combine_shares(&chosen).unwrap(), key);
+        }
+
+        // Too few shares are refused, and a share from another split of
+        // the same key does not combine into it
+        assert!(matches!(
+            combine_shares(&shares[..2]),
+            Err(EscrowError::NotEnoughShares { have: 2, need: 3 })
+        ));
+        let other = split_key(&key, 3, 5).unwrap();
+        let mixed = vec![shares[0].clone(), shares[1].clone(), other[2].clone()];
+        assert!(matches!(combine_shares(&mixed), Err(EscrowError::WrongKey)));
+
+        assert!(split_key(&key, 1, 5).is_err());
+        assert!(split_key(&key, 4, 3).is_err());
+        assert!(split_key(&key[..16], 2, 3).is_err());
+    }
+
+    #[test]
+    fn test_share_serialization_and_checksum() {
+        let key = Encryptor::generate_key().unwrap();
+        let share = split_key(&key, 2, 3).unwrap().remove(1);
+
+        let encoded = share.encode();
+        assert!(encoded.starts_with("hxs1:"));
+        let decoded = KeyShare::decode(&format!("  {}\n", encoded.to_uppercase())).unwrap();
+        assert_eq!((decoded.index(), decoded.threshold()), (2, 2));
+        assert_eq!(*decoded.data, *share.data);
+
+        // A single mistyped digit is caught
+        let mut typo = encoded.into_bytes();
+        let at = typo.len() - 20;
+        typo[at] = if typo[at] == b'0' { b'1' } else { b'0' };
+        let typo = String::from_utf8(typo).unwrap();
+        assert!(matches!(KeyShare::decode(&typo), Err(EscrowError::ChecksumMismatch)));
+        assert!(KeyShare::decode("hxs1:abc").is_err());
+    }
+
+    #[test]
+    fn test_recovery_ceremony() {
+        let key = Encryptor::generate_key().unwrap();
+        let shares: Vec<String> = split_key(&key, 2, 4).unwrap().iter().map(KeyShare::encode).collect();
+        let foreign = split_key(&Encryptor::generate_key().unwrap(), 2, 4).unwrap();
+
+        let mut ceremony = RecoveryCeremony::new();
+        assert!(ceremony.progress().is_none());
+        let progress = ceremony.add_share(&shares[3]).unwrap();
+        assert!(!progress.is_complete());
+
+        assert!(matches!(ceremony.add_share(&shares[3]), Err(EscrowError::DuplicateShare(4))));
+        assert!(matches!(ceremony.add_share(&foreign[0].encode()), Err(EscrowError::MismatchedShare)));
+
+        assert!(ceremony.add_share(&shares[0]).unwrap().is_complete());
+        assert_eq!(ceremony.recover().unwrap(), key);
+    }
+}
@@ -4,6 +4,8 @@ use ring::rand::{SecureRandom, SystemRandom};
 use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
 use thiserror::Error;
 
+use crate::secret::SecretKey;
+
 /// Key management error types
 #[derive(Error, Debug)]
 pub enum KeyError {
@@ -30,8 +32,8 @@ pub fn generate_random_bytes(length: usize) -> Result<Vec<u8>> {
 }
 
 /// Generate a 256-bit (32 byte) encryption key
-pub fn generate_encryption_key() -> Result<Vec<u8>> {
-    generate_random_bytes(32)
+pub fn generate_encryption_key() -> Result<SecretKey> {
+    SecretKey::generate(32)
 }
 
 /// Generate a base64-encoded key for storage
@@ -45,12 +47,32 @@ pub fn decode_key(encoded: &str) -> Result<Vec<u8>> {
     BASE64.decode(encoded).map_err(|_| KeyError::Invalid)
 }
 
-/// Derive a key from a password using HKDF
-pub fn derive_key_from_password(password: &str, salt: &[u8], key_length: usize) -> Result<Vec<u8>> {
-    use ring::hkdf::{self, KeyType, Prk, Salt, HKDF_SHA256};
+/// Parse a 256-bit key given as 64 hex digits or as base64
+pub fn parse_encryption_key(value: &str) -> Result<SecretKey> {
+    let value = value.trim();
+    let key = if value.len() == 64 && value.bytes().all(|b| b.is_ascii_hexdigit()) {
+        (0..64)
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&value[i..i + 2], 16).map_err(|_| KeyError::Invalid))
+            .collect::<Result<Vec<u8>>>()?
+    } else {
+        decode_key(value)?
+    };
+    let key = SecretKey::new(key);
+
+    if key.len() != 32 {
+        return Err(KeyError::Invalid);
+    }
+    Ok(key)
+}
+
+/// Derive a key from high-entropy key material using HKDF. This is not a
+/// password KDF; passphrases go through `derive_passphrase_key` instead.
+pub fn hkdf_derive_key(key_material: &[u8], salt: &[u8], key_length: usize) -> Result<SecretKey> {
+    use ring::hkdf::{KeyType, Salt, HKDF_SHA256};
     
     let salt = Salt::new(HKDF_SHA256, salt);
-    let prk = salt.extract(password.as_bytes());
+    let prk = salt.extract(key_material);
     
     let mut output = vec![0u8; key_length];
     
@@ -64,13 +86,23 @@ pub fn derive_key_from_password(password: &str, salt: &[u8], key_length: usize)
         .fill(&mut output)
         .map_err(|_| KeyError::Derivation("Key fill failed".into()))?;
     
-    Ok(output)
+    Ok(SecretKey::new(output))
+}
+
+/// Derive an independent key for one purpose (e.g. archive encryption) from
+/// a master key
+pub fn derive_subkey(master_key: &[u8], purpose: &str) -> Result<SecretKey> {
+    hkdf_derive_key(
+        BASE64.encode(master_key).as_bytes(),
+        format!("hedtronix-subkey:{}", purpose).as_bytes(),
+        32,
+    )
 }
 
 /// Per-device key derivation
-pub fn derive_device_key(master_key: &[u8], device_id: &str) -> Result<Vec<u8>> {
-    derive_key_from_password(
-        &BASE64.encode(master_key),
+pub fn derive_device_key(master_key: &[u8], device_id: &str) -> Result<SecretKey> {
+    hkdf_derive_key(
+        BASE64.encode(master_key).as_bytes(),
         device_id.as_bytes(),
         32,
     )
@@ -93,15 +125,27 @@ mod tests {
         assert_eq!(decoded.len(), 32);
     }
 
+    #[test]
+    fn test_parse_encryption_key() {
+        let key = generate_encryption_key().unwrap();
+        let hex: String = key.iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(parse_encryption_key(&hex).unwrap(), key);
+        assert_eq!(parse_encryption_key(&BASE64.encode(&key)).unwrap(), key);
+
+        // Short or unencoded keys are rejected rather than padded
+        assert!(parse_encryption_key("secret").is_err());
+        assert!(parse_encryption_key(&BASE64.encode([1u8; 16])).is_err());
+    }
+
     #[test]
     fn test_key_derivation() {
-        let password = "test_password";
+        let material = b"test_key_material";
         let salt = b"test_salt_value";
-        let key = derive_key_from_password(password, salt, 32).unwrap();
+        let key = hkdf_derive_key(material, salt, 32).unwrap();
         assert_eq!(key.len(), 32);
         
         // Same inputs should produce same output
-        let key2 = derive_key_from_password(password, salt, 32).unwrap();
+        let key2 = hkdf_derive_key(material, salt, 32).unwrap();
         assert_eq!(key, key2);
     }
 }
@@ -2,10 +2,34 @@
 //!
 //! Provides encryption, hashing, and key management for healthcare data security.
 
+pub mod agreement;
+pub mod blind_index;
 pub mod encryption;
+pub mod envelope;
+pub mod escrow;
 pub mod hashing;
 pub mod keys;
+pub mod otp;
+pub mod secret;
+pub mod passphrase;
+pub mod signing;
+pub mod stream;
 
+pub use agreement::*;
+pub use blind_index::*;
+#[allow(ambiguous_glob_reexports)]
 pub use encryption::*;
+pub use envelope::*;
+#[allow(ambiguous_glob_reexports)]
+pub use escrow::*;
 pub use hashing::*;
+#[allow(ambiguous_glob_reexports)]
 pub use keys::*;
+#[allow(ambiguous_glob_reexports)]
+pub use otp::*;
+pub use passphrase::*;
+pub use secret::*;
+#[allow(ambiguous_glob_reexports)]
+pub use signing::*;
+#[allow(ambiguous_glob_reexports)]
+pub use stream::*;
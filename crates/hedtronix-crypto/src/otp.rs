@@ -0,0 +1,173 @@
+//! Time-based one-time passwords (RFC 6238)
+
+use ring::hmac;
+use thiserror::Error;
+
+use crate::keys::generate_random_bytes;
+
+/// OTP error types
+#[derive(Error, Debug)]
+pub enum OtpError {
+    #[error("Secret generation failed: {0}")]
+    Generation(String),
+
+    #[error("Invalid base32 secret")]
+    InvalidSecret,
+}
+
+/// Result type for OTP operations
+pub type Result<T> = std::result::Result<T, OtpError>;
+
+/// Length of generated TOTP secrets (160 bits, as recommended by RFC 4226)
+pub const TOTP_SECRET_LENGTH: usize = 20;
+
+/// Time step in seconds
+pub const TOTP_STEP: u64 = 30;
+
+/// Number of digits in a code
+pub const TOTP_DIGITS: u32 = 6;
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// Generate a random TOTP secret
+pub fn generate_totp_secret() -> Result<Vec<u8>> {
+    generate_random_bytes(TOTP_SECRET_LENGTH).map_err(|e| OtpError::Generation(e.to_string()))
+}
+
+/// Encode bytes as unpadded RFC 4648 base32, the format authenticator apps expect
+pub fn base32_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(5) * 8);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+
+    for &byte in data {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+
+    out
+}
+
+/// Decode base32, ignoring case, spaces and padding
+pub fn base32_decode(encoded: &str) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(encoded.len() * 5 / 8);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+
+    for c in encoded.chars().filter(|c| !c.is_whitespace() && *c != '=') {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|&a| a as char == c.to_ascii_uppercase())
+            .ok_or(OtpError::InvalidSecret)? as u32;
+
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+
+    Ok(out)
+}
+
+/// HOTP code for a counter value (RFC 4226, HMAC-SHA1)
+pub fn hotp(secret: &[u8], counter: u64, digits: u32) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, secret);
+    let tag = hmac::sign(&key, &counter.to_be_bytes());
+    let mac = tag.as_ref();
+
+    // Dynamic truncation
+    let offset = (mac[mac.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([mac[offset], mac[offset + 1], mac[offset + 2], mac[offset + 3]])
+        & 0x7fff_ffff;
+
+    format!("{:0width$}", binary % 10u32.pow(digits), width = digits as usize)
+}
+
+/// TOTP code for a Unix timestamp
+pub fn totp(secret: &[u8], unix_time: u64) -> String {
+    hotp(secret, unix_time / TOTP_STEP, TOTP_DIGITS)
+}
+
+/// Verify a TOTP code, allowing `skew` steps of clock drift either way.
+/// Returns the matched time step so callers can reject reuse of a code.
+pub fn verify_totp(secret: &[u8], code: &str, unix_time: u64, skew: u64) -> Option<u64> {
+    let code = code.trim();
+    if code.len() != TOTP_DIGITS as usize {
+        return None;
+    }
+
+    let current = unix_time / TOTP_STEP;
+    (current.saturating_sub(skew)..=current + skew).find(|&step| {
+        let expected = hotp(secret, step, TOTP_DIGITS);
+        // Compare without short-circuiting on the first differing digit
+        expected.bytes().zip(code.bytes()).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+    })
+}
+
+/// `otpauth://` provisioning URI for authenticator app enrollment
+pub fn totp_provisioning_uri(issuer: &str, account: &str, secret: &[u8]) -> String {
+    format!(
+        "otpauth://totp/{issuer}:{account}?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={digits}&period={period}",
+        issuer = percent_encode(issuer),
+        account = percent_encode(account),
+        secret = base32_encode(secret),
+        digits = TOTP_DIGITS,
+        period = TOTP_STEP,
+    )
+}
+
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'@' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rfc6238_vectors() {
+        // RFC 6238 Appendix B, SHA-1 with 8 digits
+        let secret = b"12345678901234567890";
+        assert_eq!(hotp(secret, 59 / TOTP_STEP, 8), "94287082");
+        assert_eq!(hotp(secret, 1111111109 / TOTP_STEP, 8), "07081804");
+        assert_eq!(hotp(secret, 20000000000 / TOTP_STEP, 8), "65353130");
+    }
+
+    #[test]
+    fn test_verify_with_skew() {
+        let secret = generate_totp_secret().unwrap();
+        let now = 1_700_000_000;
+        let previous = totp(&secret, now - TOTP_STEP);
+
+        assert_eq!(verify_totp(&secret, &totp(&secret, now), now, 1), Some(now / TOTP_STEP));
+        assert!(verify_totp(&secret, &previous, now, 1).is_some());
+        assert!(verify_totp(&secret, &previous, now, 0).is_none());
+        assert!(verify_totp(&secret, "12345", now, 1).is_none());
+    }
+
+    #[test]
+    fn test_base32_roundtrip() {
+        assert_eq!(base32_encode(b"foobar"), "MZXW6YTBOI");
+        assert_eq!(base32_decode("mzxw6ytboi======").unwrap(), b"foobar");
+
+        let secret = generate_totp_secret().unwrap();
+        assert_eq!(base32_decode(&base32_encode(&secret)).unwrap(), secret);
+    }
+}
@@ -0,0 +1,128 @@
+//! Keys wrapped by a user's passphrase
+//!
+//! A random key (e.g. the device database key) is encrypted with a key
+//! derived from a passphrase by Argon2id. The salt and cost parameters are
+//! stored with the wrapping, so they can be raised for new wrappings without
+//! breaking old ones. Several wrappings of the same key let different users
+//! unlock it with their own passphrases.
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+use crate::encryption::{EncryptionError, Encryptor, Result};
+use crate::keys::generate_random_bytes;
+use crate::secret::SecretKey;
+
+const SALT_LENGTH: usize = 16;
+
+/// Argon2id cost parameters
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KdfParams {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for KdfParams {
+    /// The OWASP minimum for Argon2id: 19 MiB, two passes, one lane
+    fn default() -> Self {
+        Self {
+            memory_kib: 19 * 1024,
+            iterations: 2,
+            parallelism: 1,
+        }
+    }
+}
+
+/// Derive a 256-bit key from a passphrase with Argon2id
+pub fn derive_passphrase_key(
+    passphrase: &str,
+    salt: &[u8],
+    params: &KdfParams,
+) -> Result<SecretKey> {
+    let params = Params::new(params.memory_kib, params.iterations, params.parallelism, Some(32))
+        .map_err(|e| EncryptionError::KeyDerivation(e.to_string()))?;
+    let mut key = Zeroizing::new(vec![0u8; 32]);
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| EncryptionError::KeyDerivation(e.to_string()))?;
+    Ok(key.into())
+}
+
+/// A key encrypted under a passphrase-derived key, as stored
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PassphraseWrapping {
+    /// Base64 Argon2 salt
+    pub salt: String,
+    pub params: KdfParams,
+    /// Base64 AES-256-GCM ciphertext of the key
+    pub wrapped_key: String,
+}
+
+impl PassphraseWrapping {
+    /// Wrap `key` under `passphrase`. `context` (e.g. the owning user's id)
+    /// is bound as associated data, so a wrapping only opens where it was
+    /// stored.
+    pub fn wrap(key: &[u8], passphrase: &str, context: &str, params: KdfParams) -> Result<Self> {
+        let salt = generate_random_bytes(SALT_LENGTH)
+            .map_err(|e| EncryptionError::KeyGeneration(e.to_string()))?;
+        let encryptor = Encryptor::new(&derive_passphrase_key(passphrase, &salt, &params)?)?;
+        let sealed = encryptor.encrypt_bytes(key, wrap_aad(context).as_bytes())?;
+
+        Ok(Self {
+            salt: BASE64.encode(salt),
+            params,
+            wrapped_key: BASE64.encode(sealed),
+        })
+    }
+
+    /// Recover the key. A wrong passphrase fails like tampered data.
+    pub fn unwrap(&self, passphrase: &str, context: &str) -> Result<SecretKey> {
+        let salt = BASE64.decode(&self.salt).map_err(|_| EncryptionError::InvalidFormat)?;
+        let sealed = BASE64.decode(&self.wrapped_key).map_err(|_| EncryptionError::InvalidFormat)?;
+        let encryptor = Encryptor::new(&derive_passphrase_key(passphrase, &salt, &self.params)?)?;
+
+        Ok(SecretKey::new(encryptor.decrypt_bytes(&sealed, wrap_aad(context).as_bytes())?))
+    }
+}
+
+fn wrap_aad(context: &str) -> String {
+    format!("hedtronix-passphrase:{}", context)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Cheap parameters so tests stay fast
+    const TEST_PARAMS: KdfParams = KdfParams { memory_kib: 64, iterations: 1, parallelism: 1 };
+
+    #[test]
+    fn test_wrap_unwrap() {
+        let key = Encryptor::generate_key().unwrap();
+        let wrapping = PassphraseWrapping::wrap(&key, "correct horse", "user-1", TEST_PARAMS).unwrap();
+
+        assert_eq!(wrapping.unwrap("correct horse", "user-1").unwrap(), key);
+        assert!(wrapping.unwrap("wrong horse", "user-1").is_err());
+        // Bound to its owner
+        assert!(wrapping.unwrap("correct horse", "user-2").is_err());
+
+        // Fresh salt each time
+        let again = PassphraseWrapping::wrap(&key, "correct horse", "user-1", TEST_PARAMS).unwrap();
+        assert_ne!(wrapping.salt, again.salt);
+        assert_ne!(wrapping.wrapped_key, again.wrapped_key);
+    }
+
+    #[test]
+    fn test_derivation_depends_on_params() {
+        let salt = [7u8; SALT_LENGTH];
+        let key = derive_passphrase_key("passphrase", &salt, &TEST_PARAMS).unwrap();
+        assert_eq!(key.len(), 32);
+        assert_eq!(key, derive_passphrase_key("passphrase", &salt, &TEST_PARAMS).unwrap());
+
+        let costlier = KdfParams { iterations: 2, ..TEST_PARAMS };
+        assert_ne!(key, derive_passphrase_key("passphrase", &salt, &costlier).unwrap());
+    }
+}
@@ -0,0 +1,100 @@
+//! Secret key material
+//!
+//! `SecretKey` holds key bytes that are zeroized when dropped, print as
+//! `[REDACTED]` and implement no serde traits, so a key cannot end up in a
+//! log line or a JSON response by accident. It is deliberately not `Clone`:
+//! share one with `Arc<SecretKey>` instead of copying the bytes around.
+
+use std::fmt;
+use std::ops::Deref;
+
+use zeroize::Zeroizing;
+
+use crate::keys::{generate_random_bytes, Result};
+
+/// Key bytes, zeroized on drop
+pub struct SecretKey {
+    bytes: Zeroizing<Vec<u8>>,
+}
+
+impl SecretKey {
+    /// Take ownership of key bytes
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Self { bytes: Zeroizing::new(bytes) }
+    }
+
+    /// Copy key bytes; the caller remains responsible for its copy
+    pub fn from_slice(bytes: &[u8]) -> Self {
+        Self::new(bytes.to_vec())
+    }
+
+    /// A random key of `length` bytes
+    pub fn generate(length: usize) -> Result<Self> {
+        generate_random_bytes(length).map(Self::new)
+    }
+
+    /// The key bytes, for handing to a cipher
+    pub fn expose(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl Deref for SecretKey {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl AsRef<[u8]> for SecretKey {
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl From<Vec<u8>> for SecretKey {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self::new(bytes)
+    }
+}
+
+impl From<Zeroizing<Vec<u8>>> for SecretKey {
+    fn from(bytes: Zeroizing<Vec<u8>>) -> Self {
+        Self { bytes }
+    }
+}
+
+/// Compares in constant time for keys of equal length
+impl PartialEq for SecretKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.bytes.len() == other.bytes.len()
+            && self.bytes.iter().zip(other.bytes.iter()).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+    }
+}
+
+impl Eq for SecretKey {}
+
+impl fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SecretKey([REDACTED; {}])", self.bytes.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_debug_is_redacted() {
+        let key = SecretKey::from_slice(b"super secret key material 123456");
+        let printed = format!("{:?}", key);
+        assert_eq!(printed, "SecretKey([REDACTED; 32])");
+        assert!(!printed.contains("secret key material"));
+
+        assert_eq!(key.expose(), b"super secret key material 123456");
+        assert_eq!(key, SecretKey::from_slice(b"super secret key material 123456"));
+        assert_ne!(key, SecretKey::from_slice(b"super secret key material 654321"));
+        assert_ne!(key, SecretKey::from_slice(b"short"));
+    }
+}
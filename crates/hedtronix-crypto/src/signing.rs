@@ -0,0 +1,180 @@
+//! Asymmetric signing keys (Ed25519 and ECDSA P-256)
+
+use ring::rand::SystemRandom;
+use ring::signature::{self, EcdsaKeyPair, Ed25519KeyPair, KeyPair, UnparsedPublicKey};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::secret::SecretKey;
+
+/// Signing error types
+#[derive(Error, Debug)]
+pub enum SigningError {
+    #[error("Key generation failed: {0}")]
+    KeyGeneration(String),
+
+    #[error("Invalid key: {0}")]
+    InvalidKey(String),
+
+    #[error("Signing failed: {0}")]
+    Signing(String),
+}
+
+/// Result type for signing operations
+pub type Result<T> = std::result::Result<T, SigningError>;
+
+/// Supported signature algorithms
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum SignatureAlgorithm {
+    Ed25519,
+    EcdsaP256,
+}
+
+/// Asymmetric key pair held as PKCS#8 DER
+pub struct SigningKeyPair {
+    algorithm: SignatureAlgorithm,
+    pkcs8: SecretKey,
+    public_key: Vec<u8>,
+}
+
+impl SigningKeyPair {
+    /// Generate a new random key pair
+    pub fn generate(algorithm: SignatureAlgorithm) -> Result<Self> {
+        let rng = SystemRandom::new();
+        let pkcs8 = match algorithm {
+            SignatureAlgorithm::Ed25519 => Ed25519KeyPair::generate_pkcs8(&rng)
+                .map_err(|_| SigningError::KeyGeneration("Ed25519 generation failed".into()))?,
+            SignatureAlgorithm::EcdsaP256 => EcdsaKeyPair::generate_pkcs8(
+                &signature::ECDSA_P256_SHA256_FIXED_SIGNING,
+                &rng,
+            )
+            .map_err(|_| SigningError::KeyGeneration("P-256 generation failed".into()))?,
+        };
+
+        Self::from_pkcs8(algorithm, pkcs8.as_ref())
+    }
+
+    /// Load a key pair from PKCS#8 DER bytes
+    pub fn from_pkcs8(algorithm: SignatureAlgorithm, pkcs8: &[u8]) -> Result<Self> {
+        let public_key = match algorithm {
+            SignatureAlgorithm::Ed25519 => Ed25519KeyPair::from_pkcs8(pkcs8)
+                .map_err(|e| SigningError::InvalidKey(e.to_string()))?
+                .public_key()
+                .as_ref()
+                .to_vec(),
+            SignatureAlgorithm::EcdsaP256 => EcdsaKeyPair::from_pkcs8(
+                &signature::ECDSA_P256_SHA256_FIXED_SIGNING,
+                pkcs8,
+                &SystemRandom::new(),
+            )
+            .map_err(|e| SigningError::InvalidKey(e.to_string()))?
+            .public_key()
+            .as_ref()
+            .to_vec(),
+        };
+
+        Ok(Self {
+            algorithm,
+            pkcs8: SecretKey::from_slice(pkcs8),
+            public_key,
+        })
+    }
+
+    pub fn algorithm(&self) -> SignatureAlgorithm {
+        self.algorithm
+    }
+
+    /// PKCS#8 DER encoding of the private key
+    pub fn pkcs8(&self) -> &[u8] {
+        &self.pkcs8
+    }
+
+    /// Raw public key (32 bytes for Ed25519, uncompressed SEC1 point for P-256)
+    pub fn public_key(&self) -> &[u8] {
+        &self.public_key
+    }
+
+    /// Sign a message
+    pub fn sign(&self, message: &[u8]) -> Result<Vec<u8>> {
+        match self.algorithm {
+            SignatureAlgorithm::Ed25519 => {
+                let pair = Ed25519KeyPair::from_pkcs8(&self.pkcs8)
+                    .map_err(|e| SigningError::InvalidKey(e.to_string()))?;
+                Ok(pair.sign(message).as_ref().to_vec())
+            }
+            SignatureAlgorithm::EcdsaP256 => {
+                let rng = SystemRandom::new();
+                let pair = EcdsaKeyPair::from_pkcs8(
+                    &signature::ECDSA_P256_SHA256_FIXED_SIGNING,
+                    &self.pkcs8,
+                    &rng,
+                )
+                .map_err(|e| SigningError::InvalidKey(e.to_string()))?;
+                pair.sign(&rng, message)
+                    .map(|sig| sig.as_ref().to_vec())
+                    .map_err(|_| SigningError::Signing("ECDSA signing failed".into()))
+            }
+        }
+    }
+}
+
+/// Verify a signature against a raw public key
+pub fn verify_signature(
+    algorithm: SignatureAlgorithm,
+    public_key: &[u8],
+    message: &[u8],
+    sig: &[u8],
+) -> bool {
+    match algorithm {
+        SignatureAlgorithm::Ed25519 => UnparsedPublicKey::new(&signature::ED25519, public_key)
+            .verify(message, sig)
+            .is_ok(),
+        SignatureAlgorithm::EcdsaP256 => {
+            UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_FIXED, public_key)
+                .verify(message, sig)
+                .is_ok()
+        }
+    }
+}
+
+/// Verify a signature in ASN.1 DER form (as produced by WebAuthn
+/// authenticators for ECDSA); Ed25519 signatures are the same either way
+pub fn verify_der_signature(
+    algorithm: SignatureAlgorithm,
+    public_key: &[u8],
+    message: &[u8],
+    sig: &[u8],
+) -> bool {
+    match algorithm {
+        SignatureAlgorithm::Ed25519 => verify_signature(algorithm, public_key, message, sig),
+        SignatureAlgorithm::EcdsaP256 => {
+            UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_ASN1, public_key)
+                .verify(message, sig)
+                .is_ok()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_and_verify() {
+        for algorithm in [SignatureAlgorithm::Ed25519, SignatureAlgorithm::EcdsaP256] {
+            let pair = SigningKeyPair::generate(algorithm).unwrap();
+            let sig = pair.sign(b"message").unwrap();
+
+            assert!(verify_signature(algorithm, pair.public_key(), b"message", &sig));
+            assert!(!verify_signature(algorithm, pair.public_key(), b"tampered", &sig));
+        }
+    }
+
+    #[test]
+    fn test_pkcs8_roundtrip() {
+        let pair = SigningKeyPair::generate(SignatureAlgorithm::Ed25519).unwrap();
+        let loaded = SigningKeyPair::from_pkcs8(SignatureAlgorithm::Ed25519, pair.pkcs8()).unwrap();
+        assert_eq!(pair.public_key(), loaded.public_key());
+    }
+}
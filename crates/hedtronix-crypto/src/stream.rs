@@ -0,0 +1,441 @@
+//! Streaming authenticated encryption
+//!
+//! Payloads too large to hold in memory (backups, exports, archive
+//! segments) are encrypted in chunks with AES-256-GCM, following the STREAM
+//! construction. Each chunk's nonce is a random per-stream prefix, the
+//! chunk counter and a flag marking the final chunk, so chunks cannot be
+//! reordered, dropped or moved between streams, and cutting the stream
+//! short is detected. The header is bound to every chunk as associated
+//! data along with the caller's own.
+//!
+//! Format: `HXS1 || chunk size (u32 BE) || nonce prefix (7)`, then chunks
+//! of `chunk size` plaintext bytes plus a 16-byte tag. The final chunk is
+//! always shorter than a full chunk and may be empty.
+//!
+//! Decryption releases each chunk once it is authenticated. A consumer
+//! that acts on output before the stream ends must be prepared to discard
+//! it if a later chunk fails.
+
+use std::io::{self, Read, Write};
+
+use ring::aead::{self, Aad, LessSafeKey, Nonce, UnboundKey};
+use ring::rand::{SecureRandom, SystemRandom};
+use thiserror::Error;
+use zeroize::Zeroizing;
+
+const MAGIC: &[u8; 4] = b"HXS1";
+const NONCE_PREFIX_LENGTH: usize = 7;
+const HEADER_LENGTH: usize = MAGIC.len() + 4 + NONCE_PREFIX_LENGTH;
+const TAG_LENGTH: usize = 16;
+
+/// Plaintext bytes per chunk unless configured otherwise
+pub const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Largest chunk size a stream may declare, bounding reader allocations
+pub const MAX_CHUNK_SIZE: usize = 16 * 1024 * 1024;
+
+/// Stream encryption errors
+#[derive(Error, Debug)]
+pub enum StreamError {
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Invalid key length")]
+    InvalidKeyLength,
+
+    #[error("Invalid chunk size: {0}")]
+    InvalidChunkSize(usize),
+
+    #[error("Not an encrypted stream")]
+    InvalidHeader,
+
+    #[error("Chunk {0} failed authentication")]
+    Tampered(u64),
+
+    #[error("Stream ends before its final chunk")]
+    Truncated,
+
+    #[error("Stream has too many chunks")]
+    TooLong,
+
+    #[error("Random generation failed")]
+    Random,
+}
+
+/// Result type for stream encryption
+pub type Result<T> = std::result::Result<T, StreamError>;
+
+/// Seals and opens the chunks of one stream in order
+struct ChunkCipher {
+    key: LessSafeKey,
+    prefix: [u8; NONCE_PREFIX_LENGTH],
+    counter: u32,
+    aad: Vec<u8>,
+}
+
+impl ChunkCipher {
+    fn new(key: &[u8], header: &[u8], aad: &[u8]) -> Result<Self> {
+        let key = UnboundKey::new(&aead::AES_256_GCM, key).map_err(|_| StreamError::InvalidKeyLength)?;
+        let mut prefix = [0u8; NONCE_PREFIX_LENGTH];
+        prefix.copy_from_slice(&header[MAGIC.len() + 4..HEADER_LENGTH]);
+        Ok(Self {
+            key: LessSafeKey::new(key),
+            prefix,
+            counter: 0,
+            aad: [header, aad].concat(),
+        })
+    }
+
+    fn nonce(&self, last: bool) -> Nonce {
+        let mut nonce = [0u8; aead::NONCE_LEN];
+        nonce[..NONCE_PREFIX_LENGTH].copy_from_slice(&self.prefix);
+        nonce[NONCE_PREFIX_LENGTH..NONCE_PREFIX_LENGTH + 4].copy_from_slice(&self.counter.to_be_bytes());
+        nonce[aead::NONCE_LEN - 1] = u8::from(last);
+        Nonce::assume_unique_for_key(nonce)
+    }
+
+    fn advance(&mut self) -> Result<()> {
+        self.counter = self.counter.checked_add(1).ok_or(StreamError::TooLong)?;
+        Ok(())
+    }
+
+    /// Encrypt `chunk` in place, appending its tag
+    fn seal(&mut self, chunk: &mut Vec<u8>, last: bool) -> Result<()> {
+        self.key
+            .seal_in_place_append_tag(self.nonce(last), Aad::from(&self.aad), chunk)
+            .map_err(|_| StreamError::Tampered(self.counter.into()))?;
+        self.advance()
+    }
+
+    /// Decrypt `chunk` in place, leaving only the plaintext
+    fn open(&mut self, chunk: &mut Vec<u8>, last: bool) -> Result<()> {
+        let length = self.key
+            .open_in_place(self.nonce(last), Aad::from(&self.aad), chunk)
+            .map_err(|_| StreamError::Tampered(self.counter.into()))?
+            .len();
+        chunk.truncate(length);
+        self.advance()
+    }
+}
+
+fn check_chunk_size(chunk_size: usize) -> Result<()> {
+    if chunk_size == 0 || chunk_size > MAX_CHUNK_SIZE {
+        return Err(StreamError::InvalidChunkSize(chunk_size));
+    }
+    Ok(())
+}
+
+/// Encrypts everything written to it into `inner`. Call `finish` to write
+/// the final chunk; a writer dropped without it leaves a stream that fails
+/// to decrypt as truncated.
+pub struct EncryptingWriter<W: Write> {
+    inner: W,
+    cipher: ChunkCipher,
+    chunk_size: usize,
+    buffer: Zeroizing<Vec<u8>>,
+}
+
+impl<W: Write> EncryptingWriter<W> {
+    pub fn new(key: &[u8], aad: &[u8], inner: W) -> Result<Self> {
+        Self::with_chunk_size(key, aad, inner, DEFAULT_CHUNK_SIZE)
+    }
+
+    pub fn with_chunk_size(key: &[u8], aad: &[u8], mut inner: W, chunk_size: usize) -> Result<Self> {
+        check_chunk_size(chunk_size)?;
+        let mut prefix = [0u8; NONCE_PREFIX_LENGTH];
+        SystemRandom::new().fill(&mut prefix).map_err(|_| StreamError::Random)?;
+
+        let mut header = Vec::with_capacity(HEADER_LENGTH);
+        header.extend_from_slice(MAGIC);
+        header.extend_from_slice(&(chunk_size as u32).to_be_bytes());
+        header.extend_from_slice(&prefix);
+        let cipher = ChunkCipher::new(key, &header, aad)?;
+        inner.write_all(&header)?;
+
+        Ok(Self {
+            inner,
+            cipher,
+            chunk_size,
+            buffer: Zeroizing::new(Vec::with_capacity(chunk_size)),
+        })
+    }
+
+    /// Seal and write the buffered plaintext as one chunk
+    fn write_chunk(&mut self, length: usize, last: bool) -> Result<()> {
+        let mut chunk = Zeroizing::new(Vec::with_capacity(length + TAG_LENGTH));
+        chunk.extend(self.buffer.drain(..length));
+        self.cipher.seal(&mut chunk, last)?;
+        self.inner.write_all(&chunk)?;
+        Ok(())
+    }
+
+    /// Write the final chunk and return the inner writer
+    pub fn finish(mut self) -> Result<W> {
+        let remaining = self.buffer.len();
+        self.write_chunk(remaining, true)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for EncryptingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let take = buf.len().min(self.chunk_size - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..take]);
+        // A full chunk is never the final one: the final chunk is always
+        // shorter, possibly empty
+        if self.buffer.len() == self.chunk_size {
+            self.write_chunk(self.chunk_size, false).map_err(into_io)?;
+        }
+        Ok(take)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Decrypts a stream written by `EncryptingWriter`, yielding plaintext one
+/// authenticated chunk at a time
+pub struct DecryptingReader<R: Read> {
+    inner: R,
+    cipher: ChunkCipher,
+    chunk_size: usize,
+    chunk: Zeroizing<Vec<u8>>,
+    position: usize,
+    finished: bool,
+}
+
+impl<R: Read> DecryptingReader<R> {
+    pub fn new(key: &[u8], aad: &[u8], mut inner: R) -> Result<Self> {
+        let mut header = [0u8; HEADER_LENGTH];
+        inner.read_exact(&mut header).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => StreamError::InvalidHeader,
+            _ => StreamError::Io(e),
+        })?;
+        if &header[..MAGIC.len()] != MAGIC {
+            return Err(StreamError::InvalidHeader);
+        }
+        let mut size = [0u8; 4];
+        size.copy_from_slice(&header[MAGIC.len()..MAGIC.len() + 4]);
+        let chunk_size = u32::from_be_bytes(size) as usize;
+        check_chunk_size(chunk_size)?;
+
+        Ok(Self {
+            inner,
+            cipher: ChunkCipher::new(key, &header, aad)?,
+            chunk_size,
+            chunk: Zeroizing::new(Vec::new()),
+            position: 0,
+            finished: false,
+        })
+    }
+
+    /// Read and authenticate the next chunk. Returns false after the final
+    /// chunk.
+    fn next_chunk(&mut self) -> Result<bool> {
+        if self.finished {
+            return Ok(false);
+        }
+        let full = self.chunk_size + TAG_LENGTH;
+        self.chunk.resize(full, 0);
+        let length = read_up_to(&mut self.inner, &mut self.chunk)?;
+        self.chunk.truncate(length);
+        self.position = 0;
+
+        if length == full {
+            self.cipher.open(&mut self.chunk, false)?;
+            return Ok(true);
+        }
+        if length < TAG_LENGTH {
+            return Err(StreamError::Truncated);
+        }
+        // A short read means the reader is exhausted, so anything appended
+        // after the final chunk fails its authentication
+        self.cipher.open(&mut self.chunk, true)?;
+        self.finished = true;
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for DecryptingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.chunk.len() {
+            if !self.next_chunk().map_err(into_io)? {
+                return Ok(0);
+            }
+        }
+        let length = buf.len().min(self.chunk.len() - self.position);
+        buf[..length].copy_from_slice(&self.chunk[self.position..self.position + length]);
+        self.position += length;
+        Ok(length)
+    }
+}
+
+/// Fill `buf` unless the reader ends first; returns the bytes read
+fn read_up_to(reader: &mut impl Read, buf: &mut [u8]) -> Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(filled)
+}
+
+fn into_io(e: StreamError) -> io::Error {
+    match e {
+        StreamError::Io(e) => e,
+        e => io::Error::new(io::ErrorKind::InvalidData, e),
+    }
+}
+
+/// Encrypt everything from `reader` into `writer`. Returns the plaintext
+/// length.
+pub fn encrypt_stream<R: Read, W: Write>(key: &[u8], aad: &[u8], mut reader: R, writer: W) -> Result<u64> {
+    let mut encryptor = EncryptingWriter::new(key, aad, writer)?;
+    let length = io::copy(&mut reader, &mut encryptor)?;
+    encryptor.finish()?;
+    Ok(length)
+}
+
+/// Decrypt a stream from `reader` into `writer`, failing on any tampered,
+/// reordered or missing chunk. Returns the plaintext length.
+pub fn decrypt_stream<R: Read, W: Write>(key: &[u8], aad: &[u8], reader: R, mut writer: W) -> Result<u64> {
+    let mut decryptor = DecryptingReader::new(key, aad, reader)?;
+    let mut length = 0u64;
+    while decryptor.next_chunk()? {
+        writer.write_all(&decryptor.chunk)?;
+        length += decryptor.chunk.len() as u64;
+    }
+    writer.flush()?;
+    Ok(length)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encryption::Encryptor;
+
+    const CHUNK: usize = 32;
+    const FRAME: usize = CHUNK + TAG_LENGTH;
+
+    fn encrypt(key: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        let mut writer = EncryptingWriter::with_chunk_size(key, b"backup", Vec::new(), CHUNK).unwrap();
+        // Uneven writes exercise the buffering
+        for piece in plaintext.chunks(7) {
+            writer.write_all(piece).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    fn decrypt(key: &[u8], stream: &[u8]) -> Result<Vec<u8>> {
+        let mut plaintext = Vec::new();
+        decrypt_stream(key, b"backup", stream, &mut plaintext)?;
+        Ok(plaintext)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let key = Encryptor::generate_key().unwrap();
+        for length in [0, 1, CHUNK - 1, CHUNK, CHUNK + 1, 3 * CHUNK, 1000] {
+            let plaintext: Vec<u8> = (0..length).map(|i| i as u8).collect();
+            let stream = encrypt(&key, &plaintext);
+            // Full chunks, then a shorter final chunk
+            assert_eq!(stream.len(), HEADER_LENGTH + length / CHUNK * FRAME + length % CHUNK + TAG_LENGTH);
+            assert_eq!(decrypt(&key, &stream).unwrap(), plaintext);
+
+            let mut read_back = Vec::new();
+            DecryptingReader::new(&key, b"backup", stream.as_slice()).unwrap()
+                .read_to_end(&mut read_back)
+                .unwrap();
+            assert_eq!(read_back, plaintext);
+        }
+
+        let mut large = Vec::new();
+        let plaintext = vec![42u8; DEFAULT_CHUNK_SIZE * 2 + 5];
+        assert_eq!(encrypt_stream(&key, b"", plaintext.as_slice(), &mut large).unwrap(), plaintext.len() as u64);
+        let mut output = Vec::new();
+        decrypt_stream(&key, b"", large.as_slice(), &mut output).unwrap();
+        assert_eq!(output, plaintext);
+    }
+
+    #[test]
+    fn test_tampering_is_detected() {
+        let key = Encryptor::generate_key().unwrap();
+        let plaintext = vec![7u8; 3 * CHUNK + 5];
+        let stream = encrypt(&key, &plaintext);
+
+        let mut flipped = stream.clone();
+        flipped[HEADER_LENGTH + FRAME + 3] ^= 1;
+        assert!(matches!(decrypt(&key, &flipped), Err(StreamError::Tampered(1))));
+
+        // The header is authenticated too
+        let mut header = stream.clone();
+        header[HEADER_LENGTH - 1] ^= 1;
+        assert!(matches!(decrypt(&key, &header), Err(StreamError::Tampered(0))));
+
+        let other = Encryptor::generate_key().unwrap();
+        assert!(decrypt(&other, &stream).is_err());
+        let mut output = Vec::new();
+        assert!(decrypt_stream(&key, b"export", stream.as_slice(), &mut output).is_err());
+
+        let mut trailing = stream.clone();
+        trailing.push(0);
+        assert!(matches!(decrypt(&key, &trailing), Err(StreamError::Tampered(3))));
+        assert!(matches!(decrypt(&key, b"HXS0"), Err(StreamError::InvalidHeader)));
+    }
+
+    #[test]
+    fn test_reordering_is_detected() {
+        let key = Encryptor::generate_key().unwrap();
+        let plaintext: Vec<u8> = (0..3 * CHUNK + 5).map(|i| i as u8).collect();
+        let stream = encrypt(&key, &plaintext);
+
+        let first = HEADER_LENGTH..HEADER_LENGTH + FRAME;
+        let second = HEADER_LENGTH + FRAME..HEADER_LENGTH + 2 * FRAME;
+        let mut swapped = stream[..HEADER_LENGTH].to_vec();
+        swapped.extend_from_slice(&stream[second.clone()]);
+        swapped.extend_from_slice(&stream[first.clone()]);
+        swapped.extend_from_slice(&stream[second.end..]);
+        assert!(matches!(decrypt(&key, &swapped), Err(StreamError::Tampered(0))));
+
+        // A chunk from another stream of the same key does not fit either
+        let other = encrypt(&key, &plaintext);
+        let mut spliced = stream.clone();
+        spliced[second.clone()].copy_from_slice(&other[second]);
+        assert!(matches!(decrypt(&key, &spliced), Err(StreamError::Tampered(1))));
+    }
+
+    #[test]
+    fn test_truncation_is_detected() {
+        let key = Encryptor::generate_key().unwrap();
+        let plaintext = vec![1u8; 2 * CHUNK];
+        let stream = encrypt(&key, &plaintext);
+        // Two full chunks and an empty final chunk
+        assert_eq!(stream.len(), HEADER_LENGTH + 2 * FRAME + TAG_LENGTH);
+
+        // Dropping the final chunk leaves a stream ending on a chunk boundary
+        let at_boundary = &stream[..HEADER_LENGTH + 2 * FRAME];
+        assert!(matches!(decrypt(&key, at_boundary), Err(StreamError::Truncated)));
+
+        // A full chunk cut short cannot pass as the final chunk
+        let mid_chunk = &stream[..HEADER_LENGTH + FRAME + 20];
+        assert!(matches!(decrypt(&key, mid_chunk), Err(StreamError::Tampered(1))));
+
+        // A writer dropped without finishing produces a truncated stream
+        let mut unfinished = Vec::new();
+        {
+            let mut writer = EncryptingWriter::with_chunk_size(&key, b"backup", &mut unfinished, CHUNK).unwrap();
+            writer.write_all(&plaintext).unwrap();
+        }
+        assert!(matches!(decrypt(&key, &unfinished), Err(StreamError::Truncated)));
+
+        let mut reader = DecryptingReader::new(&key, b"backup", at_boundary).unwrap();
+        let error = reader.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,115 @@
+//! Concurrent read throughput
+//!
+//! Threads run patient searches against one database file, first with
+//! every query sharing the writer connection (how the database worked
+//! before the reader pool) and then with a reader per thread. Each is run
+//! alone and again while another thread keeps creating patients: with a
+//! shared connection searches queue behind every write, with the pool they
+//! read the last commit. Searches decrypt every returned row, so the
+//! pool's gains need as many cores as threads.
+//!
+//! Run with `cargo bench -p hedtronix-db --bench concurrent_reads`.
+
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use hedtronix_core::{Gender, Patient, PatientSearchFilters};
+use hedtronix_crypto::{DataKey, Keyring};
+use hedtronix_db::{Database, PatientRepository};
+
+const PATIENTS: usize = 2_000;
+const SURNAMES: usize = 50;
+const RUN_FOR: Duration = Duration::from_secs(2);
+const THREADS: &[usize] = &[1, 2, 4, 8];
+
+fn main() {
+    let dir = std::env::temp_dir().join(format!("hedtronix-bench-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("bench.db");
+    let key = DataKey::from_bytes("bench", &[7u8; 32]).unwrap();
+    let keyring = Arc::new(Keyring::with_key(key).unwrap());
+    seed(&path, &keyring);
+
+    println!("patient searches per second, {} patients, {:?} per run", PATIENTS, RUN_FOR);
+    for writing in [false, true] {
+        println!();
+        println!("{}", if writing { "while a writer creates patients" } else { "reads only" });
+        println!("{:>8} {:>14} {:>14} {:>8}", "threads", "shared writer", "reader pool", "speedup");
+        for &threads in THREADS {
+            let shared = throughput(Database::open_with_readers(&path, 0).unwrap(), &keyring, threads, writing);
+            let pooled = throughput(Database::open_with_readers(&path, threads).unwrap(), &keyring, threads, writing);
+            println!("{:>8} {:>14.0} {:>14.0} {:>7.2}x", threads, shared, pooled, pooled / shared);
+        }
+    }
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+fn seed(path: &Path, keyring: &Arc<Keyring>) {
+    let mut db = Database::open(path).unwrap();
+    db.initialize().unwrap();
+    let repo = PatientRepository::new(db, keyring.clone());
+    for i in 0..PATIENTS {
+        repo.create(&patient(&format!("MRN{:06}", i), i)).unwrap();
+    }
+}
+
+fn patient(mrn: &str, i: usize) -> Patient {
+    Patient::new(
+        mrn.to_string(),
+        format!("First{}", i),
+        format!("Surname{}", i % SURNAMES),
+        chrono::NaiveDate::from_ymd_opt(1980, 1, 2).unwrap(),
+        Gender::Unknown,
+    )
+}
+
+/// Searches per second across `threads` threads, optionally beside a
+/// thread creating patients
+fn throughput(db: Database, keyring: &Arc<Keyring>, threads: usize, writing: bool) -> f64 {
+    let stop = Arc::new(AtomicBool::new(false));
+    let completed = Arc::new(AtomicU64::new(0));
+
+    let writer = writing.then(|| {
+        let repo = PatientRepository::new(db.clone(), keyring.clone());
+        let stop = stop.clone();
+        thread::spawn(move || {
+            let mut i = 0;
+            while !stop.load(Ordering::Relaxed) {
+                repo.create(&patient(&uuid::Uuid::new_v4().to_string(), i)).unwrap();
+                i += 1;
+            }
+        })
+    });
+
+    let workers: Vec<_> = (0..threads)
+        .map(|worker| {
+            let repo = PatientRepository::new(db.clone(), keyring.clone());
+            let (stop, completed) = (stop.clone(), completed.clone());
+            thread::spawn(move || {
+                let mut i = worker;
+                while !stop.load(Ordering::Relaxed) {
+                    let filters = PatientSearchFilters {
+                        query: Some(format!("Surname{}", i % SURNAMES)),
+                        limit: 20,
+                        ..Default::default()
+                    };
+                    assert!(!repo.search(&filters).unwrap().is_empty());
+                    completed.fetch_add(1, Ordering::Relaxed);
+                    i += threads;
+                }
+            })
+        })
+        .collect();
+
+    let started = Instant::now();
+    thread::sleep(RUN_FOR);
+    stop.store(true, Ordering::Relaxed);
+    for worker in workers.into_iter().chain(writer) {
+        worker.join().unwrap();
+    }
+    completed.load(Ordering::Relaxed) as f64 / started.elapsed().as_secs_f64()
+}
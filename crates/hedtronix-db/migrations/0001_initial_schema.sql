@@ -1,8 +1,8 @@
 -- HEDTRONIX Database Schema
 -- SQLite with CRDT metadata support
-
--- Enable foreign keys
-PRAGMA foreign_keys = ON;
+--
+-- Version 1: the schema as first released. Databases created before
+-- schema versioning are adopted at this version.
 
 -- ============================================================================
 -- Core Tables
@@ -20,7 +20,7 @@ CREATE TABLE IF NOT EXISTS departments (
     updated_at TEXT NOT NULL
 );
 
-CREATE INDEX idx_departments_parent ON departments(parent_id);
+CREATE INDEX IF NOT EXISTS idx_departments_parent ON departments(parent_id);
 
 -- Users
 CREATE TABLE IF NOT EXISTS users (
@@ -40,9 +40,9 @@ CREATE TABLE IF NOT EXISTS users (
     last_modified_by TEXT
 );
 
-CREATE INDEX idx_users_email ON users(email);
-CREATE INDEX idx_users_department ON users(department_id);
-CREATE INDEX idx_users_role ON users(role);
+CREATE INDEX IF NOT EXISTS idx_users_email ON users(email);
+CREATE INDEX IF NOT EXISTS idx_users_department ON users(department_id);
+CREATE INDEX IF NOT EXISTS idx_users_role ON users(role);
 
 -- Devices
 CREATE TABLE IF NOT EXISTS devices (
@@ -60,8 +60,8 @@ CREATE TABLE IF NOT EXISTS devices (
     created_at TEXT NOT NULL
 );
 
-CREATE INDEX idx_devices_user ON devices(user_id);
-CREATE INDEX idx_devices_revoked ON devices(revoked);
+CREATE INDEX IF NOT EXISTS idx_devices_user ON devices(user_id);
+CREATE INDEX IF NOT EXISTS idx_devices_revoked ON devices(revoked);
 
 -- Rooms
 CREATE TABLE IF NOT EXISTS rooms (
@@ -77,7 +77,7 @@ CREATE TABLE IF NOT EXISTS rooms (
     updated_at TEXT NOT NULL
 );
 
-CREATE INDEX idx_rooms_department ON rooms(department_id);
+CREATE INDEX IF NOT EXISTS idx_rooms_department ON rooms(department_id);
 
 -- ============================================================================
 -- Patient Management
@@ -109,9 +109,9 @@ CREATE TABLE IF NOT EXISTS patients (
     last_modified_by TEXT
 );
 
-CREATE INDEX idx_patients_mrn ON patients(medical_record_number);
-CREATE INDEX idx_patients_name ON patients(last_name, first_name);
-CREATE INDEX idx_patients_physician ON patients(primary_care_physician_id);
+CREATE INDEX IF NOT EXISTS idx_patients_mrn ON patients(medical_record_number);
+CREATE INDEX IF NOT EXISTS idx_patients_name ON patients(last_name, first_name);
+CREATE INDEX IF NOT EXISTS idx_patients_physician ON patients(primary_care_physician_id);
 
 -- ============================================================================
 -- Scheduling
@@ -142,10 +142,10 @@ CREATE TABLE IF NOT EXISTS appointments (
     last_modified_by TEXT
 );
 
-CREATE INDEX idx_appointments_patient ON appointments(patient_id);
-CREATE INDEX idx_appointments_provider ON appointments(provider_id);
-CREATE INDEX idx_appointments_time ON appointments(start_time, end_time);
-CREATE INDEX idx_appointments_status ON appointments(status);
+CREATE INDEX IF NOT EXISTS idx_appointments_patient ON appointments(patient_id);
+CREATE INDEX IF NOT EXISTS idx_appointments_provider ON appointments(provider_id);
+CREATE INDEX IF NOT EXISTS idx_appointments_time ON appointments(start_time, end_time);
+CREATE INDEX IF NOT EXISTS idx_appointments_status ON appointments(status);
 
 -- ============================================================================
 -- Clinical Documentation
@@ -170,8 +170,8 @@ CREATE TABLE IF NOT EXISTS encounters (
     version_json TEXT NOT NULL DEFAULT '{}'
 );
 
-CREATE INDEX idx_encounters_patient ON encounters(patient_id);
-CREATE INDEX idx_encounters_provider ON encounters(provider_id);
+CREATE INDEX IF NOT EXISTS idx_encounters_patient ON encounters(patient_id);
+CREATE INDEX IF NOT EXISTS idx_encounters_provider ON encounters(provider_id);
 
 -- Clinical Notes
 CREATE TABLE IF NOT EXISTS clinical_notes (
@@ -197,9 +197,9 @@ CREATE TABLE IF NOT EXISTS clinical_notes (
     last_modified_by TEXT
 );
 
-CREATE INDEX idx_clinical_notes_patient ON clinical_notes(patient_id);
-CREATE INDEX idx_clinical_notes_author ON clinical_notes(author_id);
-CREATE INDEX idx_clinical_notes_encounter ON clinical_notes(encounter_id);
+CREATE INDEX IF NOT EXISTS idx_clinical_notes_patient ON clinical_notes(patient_id);
+CREATE INDEX IF NOT EXISTS idx_clinical_notes_author ON clinical_notes(author_id);
+CREATE INDEX IF NOT EXISTS idx_clinical_notes_encounter ON clinical_notes(encounter_id);
 
 -- ============================================================================
 -- Billing
@@ -232,9 +232,9 @@ CREATE TABLE IF NOT EXISTS billing_entries (
     version_json TEXT NOT NULL DEFAULT '{}'
 );
 
-CREATE INDEX idx_billing_patient ON billing_entries(patient_id);
-CREATE INDEX idx_billing_encounter ON billing_entries(encounter_id);
-CREATE INDEX idx_billing_status ON billing_entries(status);
+CREATE INDEX IF NOT EXISTS idx_billing_patient ON billing_entries(patient_id);
+CREATE INDEX IF NOT EXISTS idx_billing_encounter ON billing_entries(encounter_id);
+CREATE INDEX IF NOT EXISTS idx_billing_status ON billing_entries(status);
 
 -- ============================================================================
 -- Audit & Sync
@@ -257,9 +257,9 @@ CREATE TABLE IF NOT EXISTS audit_logs (
     hash TEXT NOT NULL
 );
 
-CREATE INDEX idx_audit_user ON audit_logs(user_id);
-CREATE INDEX idx_audit_entity ON audit_logs(entity_type, entity_id);
-CREATE INDEX idx_audit_timestamp ON audit_logs(timestamp);
+CREATE INDEX IF NOT EXISTS idx_audit_user ON audit_logs(user_id);
+CREATE INDEX IF NOT EXISTS idx_audit_entity ON audit_logs(entity_type, entity_id);
+CREATE INDEX IF NOT EXISTS idx_audit_timestamp ON audit_logs(timestamp);
 
 -- Sync Queue
 CREATE TABLE IF NOT EXISTS sync_queue (
@@ -277,8 +277,8 @@ CREATE TABLE IF NOT EXISTS sync_queue (
     retry_count INTEGER NOT NULL DEFAULT 0
 );
 
-CREATE INDEX idx_sync_queue_pending ON sync_queue(synced, timestamp);
-CREATE INDEX idx_sync_queue_entity ON sync_queue(entity_type, entity_id);
+CREATE INDEX IF NOT EXISTS idx_sync_queue_pending ON sync_queue(synced, timestamp);
+CREATE INDEX IF NOT EXISTS idx_sync_queue_entity ON sync_queue(entity_type, entity_id);
 
 -- Sync Metadata
 CREATE TABLE IF NOT EXISTS sync_metadata (
@@ -299,4 +299,4 @@ CREATE TABLE IF NOT EXISTS conflicts (
     created_at TEXT NOT NULL
 );
 
-CREATE INDEX idx_conflicts_unresolved ON conflicts(resolved, created_at);
+CREATE INDEX IF NOT EXISTS idx_conflicts_unresolved ON conflicts(resolved, created_at);
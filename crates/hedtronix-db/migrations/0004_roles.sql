@@ -0,0 +1,19 @@
+-- Roles: the built-in roles (id = role name) plus admin-defined custom roles
+CREATE TABLE IF NOT EXISTS roles (
+    id TEXT PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE,
+    description TEXT,
+    base_role TEXT NOT NULL CHECK (base_role IN ('PHYSICIAN', 'NURSE', 'RECEPTIONIST', 'BILLING', 'ADMIN', 'PATIENT')),
+    built_in INTEGER NOT NULL DEFAULT 0,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS role_permissions (
+    role_id TEXT NOT NULL REFERENCES roles(id) ON DELETE CASCADE,
+    resource TEXT NOT NULL,
+    action TEXT NOT NULL,
+    PRIMARY KEY (role_id, resource, action)
+);
+
+ALTER TABLE users ADD COLUMN role_id TEXT REFERENCES roles(id);
@@ -0,0 +1,2 @@
+-- Patient portal accounts are linked to their own record
+ALTER TABLE users ADD COLUMN patient_id TEXT REFERENCES patients(id);
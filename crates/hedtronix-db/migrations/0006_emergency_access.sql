@@ -0,0 +1,21 @@
+-- Emergency ("break-the-glass") access grants
+CREATE TABLE IF NOT EXISTS emergency_access_grants (
+    id TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL REFERENCES users(id),
+    patient_id TEXT NOT NULL REFERENCES patients(id),
+    device_id TEXT REFERENCES devices(id),
+    reason TEXT NOT NULL,
+    granted_at TEXT NOT NULL,
+    expires_at TEXT NOT NULL,
+    revoked_at TEXT,
+    review_status TEXT NOT NULL DEFAULT 'PENDING' CHECK (review_status IN ('PENDING', 'JUSTIFIED', 'UNJUSTIFIED')),
+    reviewed_by TEXT REFERENCES users(id),
+    reviewed_at TEXT,
+    review_notes TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_emergency_access_review ON emergency_access_grants(review_status);
+
+ALTER TABLE audit_logs ADD COLUMN break_glass_grant_id TEXT REFERENCES emergency_access_grants(id);
+
+CREATE INDEX IF NOT EXISTS idx_audit_break_glass ON audit_logs(break_glass_grant_id);
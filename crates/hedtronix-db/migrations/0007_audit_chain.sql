@@ -0,0 +1,40 @@
+-- Audit entries get a chain position. SQLite cannot add a NOT NULL UNIQUE
+-- column, so the table is rebuilt; existing entries are numbered in the
+-- order they were written.
+CREATE TABLE audit_logs_chained (
+    id TEXT PRIMARY KEY,
+    -- Position in the hash chain; unique so concurrent writers cannot fork it
+    sequence INTEGER NOT NULL UNIQUE,
+    event_type TEXT NOT NULL CHECK (event_type IN ('CREATE', 'READ', 'UPDATE', 'DELETE', 'LOGIN', 'LOGOUT', 'EXPORT', 'SYNC')),
+    user_id TEXT REFERENCES users(id),
+    device_id TEXT REFERENCES devices(id),
+    entity_type TEXT NOT NULL,
+    entity_id TEXT NOT NULL,
+    changes_json TEXT NOT NULL,
+    ip_address TEXT,
+    user_agent TEXT,
+    timestamp TEXT NOT NULL,
+    signature TEXT NOT NULL,
+    previous_hash TEXT,
+    hash TEXT NOT NULL,
+    break_glass_grant_id TEXT REFERENCES emergency_access_grants(id)
+);
+
+INSERT INTO audit_logs_chained (
+    id, sequence, event_type, user_id, device_id, entity_type, entity_id, changes_json,
+    ip_address, user_agent, timestamp, signature, previous_hash, hash, break_glass_grant_id
+)
+SELECT
+    id, ROW_NUMBER() OVER (ORDER BY timestamp, rowid), event_type, user_id, device_id,
+    entity_type, entity_id, changes_json, ip_address, user_agent, timestamp, signature,
+    previous_hash, hash, break_glass_grant_id
+FROM audit_logs;
+
+DROP TABLE audit_logs;
+ALTER TABLE audit_logs_chained RENAME TO audit_logs;
+
+CREATE INDEX IF NOT EXISTS idx_audit_user ON audit_logs(user_id);
+CREATE INDEX IF NOT EXISTS idx_audit_entity ON audit_logs(entity_type, entity_id);
+CREATE INDEX IF NOT EXISTS idx_audit_timestamp ON audit_logs(timestamp);
+CREATE INDEX IF NOT EXISTS idx_audit_break_glass ON audit_logs(break_glass_grant_id);
+CREATE UNIQUE INDEX IF NOT EXISTS idx_audit_previous_hash ON audit_logs(previous_hash);
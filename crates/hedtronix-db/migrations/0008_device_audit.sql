@@ -0,0 +1,30 @@
+-- Hybrid logical clock time, orders entries across devices
+ALTER TABLE audit_logs ADD COLUMN hlc TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_audit_hlc ON audit_logs(hlc);
+
+-- Audit entries replicated from devices' local chains. Append-only: each
+-- device's sub-chain is verified on receipt and rows are never updated.
+CREATE TABLE IF NOT EXISTS device_audit_logs (
+    origin_device_id TEXT NOT NULL REFERENCES devices(id),
+    sequence INTEGER NOT NULL,
+    id TEXT NOT NULL UNIQUE,
+    event_type TEXT NOT NULL CHECK (event_type IN ('CREATE', 'READ', 'UPDATE', 'DELETE', 'LOGIN', 'LOGOUT', 'EXPORT', 'SYNC')),
+    user_id TEXT,
+    device_id TEXT,
+    entity_type TEXT NOT NULL,
+    entity_id TEXT NOT NULL,
+    changes_json TEXT NOT NULL,
+    ip_address TEXT,
+    user_agent TEXT,
+    timestamp TEXT NOT NULL,
+    signature TEXT NOT NULL,
+    previous_hash TEXT,
+    hash TEXT NOT NULL,
+    break_glass_grant_id TEXT,
+    hlc TEXT NOT NULL,
+    received_at TEXT NOT NULL,
+    PRIMARY KEY (origin_device_id, sequence)
+);
+
+CREATE INDEX IF NOT EXISTS idx_device_audit_hlc ON device_audit_logs(hlc);
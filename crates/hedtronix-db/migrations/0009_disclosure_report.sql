@@ -0,0 +1,2 @@
+-- Disclosure reports look up replicated entries by patient
+CREATE INDEX IF NOT EXISTS idx_device_audit_entity ON device_audit_logs(entity_type, entity_id);
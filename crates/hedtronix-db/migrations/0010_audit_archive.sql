@@ -0,0 +1,16 @@
+-- Audit entries moved out of audit_logs into archive files. Each segment
+-- records the hash of the entry before it and of its last entry, so the
+-- chain still verifies across segments and into audit_logs.
+CREATE TABLE IF NOT EXISTS audit_archive_segments (
+    id TEXT PRIMARY KEY,
+    first_sequence INTEGER NOT NULL UNIQUE,
+    last_sequence INTEGER NOT NULL UNIQUE,
+    entry_count INTEGER NOT NULL,
+    earliest_timestamp TEXT NOT NULL,
+    latest_timestamp TEXT NOT NULL,
+    previous_hash TEXT,
+    last_hash TEXT NOT NULL,
+    content_hash TEXT NOT NULL,
+    file_name TEXT NOT NULL,
+    created_at TEXT NOT NULL
+);
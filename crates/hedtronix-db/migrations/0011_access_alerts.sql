@@ -0,0 +1,26 @@
+-- Patients whose charts get extra access monitoring
+CREATE TABLE IF NOT EXISTS vip_patients (
+    patient_id TEXT PRIMARY KEY REFERENCES patients(id),
+    reason TEXT,
+    flagged_by TEXT REFERENCES users(id),
+    flagged_at TEXT NOT NULL
+);
+
+-- Suspicious access found in the audit trail, queued for privacy review
+CREATE TABLE IF NOT EXISTS access_alerts (
+    id TEXT PRIMARY KEY,
+    rule TEXT NOT NULL,
+    severity TEXT NOT NULL CHECK (severity IN ('LOW', 'MEDIUM', 'HIGH')),
+    user_id TEXT NOT NULL REFERENCES users(id),
+    patient_id TEXT,
+    audit_log_ids_json TEXT NOT NULL DEFAULT '[]',
+    description TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'OPEN' CHECK (status IN ('OPEN', 'ESCALATED', 'DISMISSED')),
+    reviewed_by TEXT REFERENCES users(id),
+    reviewed_at TEXT,
+    review_notes TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_access_alerts_status ON access_alerts(status, created_at);
+CREATE INDEX IF NOT EXISTS idx_access_alerts_user ON access_alerts(user_id, rule);
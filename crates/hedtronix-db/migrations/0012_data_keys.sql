@@ -0,0 +1,27 @@
+-- Data encryption keys for PHI fields, wrapped by the key encryption key.
+-- Exactly one is active; retired keys still decrypt older rows.
+CREATE TABLE IF NOT EXISTS data_keys (
+    id TEXT PRIMARY KEY,
+    wrapped_key TEXT NOT NULL,
+    kek_id TEXT NOT NULL,
+    status TEXT NOT NULL CHECK (status IN ('ACTIVE', 'RETIRED')),
+    created_at TEXT NOT NULL,
+    retired_at TEXT
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_data_keys_active ON data_keys(status) WHERE status = 'ACTIVE';
+
+-- Background re-encryption of PHI fields under the active data key
+CREATE TABLE IF NOT EXISTS reencryption_jobs (
+    id TEXT PRIMARY KEY,
+    target_key_id TEXT NOT NULL REFERENCES data_keys(id),
+    status TEXT NOT NULL CHECK (status IN ('RUNNING', 'COMPLETED', 'FAILED')),
+    total_rows INTEGER NOT NULL,
+    processed_rows INTEGER NOT NULL DEFAULT 0,
+    error TEXT,
+    started_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    completed_at TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_reencryption_jobs_status ON reencryption_jobs(status);
@@ -0,0 +1,31 @@
+-- Blind indexes (keyed HMACs of the normalized values) for exact lookup.
+-- Rows written before this version are indexed by
+-- `PatientRepository::index_batch` once the blind index key is available.
+ALTER TABLE patients ADD COLUMN first_name_index TEXT;
+ALTER TABLE patients ADD COLUMN last_name_index TEXT;
+ALTER TABLE patients ADD COLUMN mrn_index TEXT;
+ALTER TABLE patients ADD COLUMN dob_index TEXT;
+ALTER TABLE patients ADD COLUMN phone_index TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_patients_mrn_index ON patients(mrn_index);
+CREATE INDEX IF NOT EXISTS idx_patients_name_index ON patients(last_name_index, first_name_index);
+CREATE INDEX IF NOT EXISTS idx_patients_dob_index ON patients(dob_index);
+CREATE INDEX IF NOT EXISTS idx_patients_phone_index ON patients(phone_index);
+
+-- Blind prefix and trigram tokens of searchable patient fields
+CREATE TABLE IF NOT EXISTS patient_search_tokens (
+    token TEXT NOT NULL,
+    patient_id TEXT NOT NULL REFERENCES patients(id) ON DELETE CASCADE,
+    PRIMARY KEY (token, patient_id)
+) WITHOUT ROWID;
+
+CREATE INDEX IF NOT EXISTS idx_patient_search_tokens_patient ON patient_search_tokens(patient_id);
+
+-- Key for patient search blind indexes, wrapped by the key encryption key.
+-- Never rotated: every index would have to be rebuilt.
+CREATE TABLE IF NOT EXISTS blind_index_keys (
+    id TEXT PRIMARY KEY,
+    wrapped_key TEXT NOT NULL,
+    kek_id TEXT NOT NULL,
+    created_at TEXT NOT NULL
+);
@@ -0,0 +1,11 @@
+-- The device database key (the key encryption key in device lock mode),
+-- wrapped once per user by a key derived from their passphrase
+CREATE TABLE IF NOT EXISTS device_key_wrappings (
+    user_id TEXT PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    key_id TEXT NOT NULL,
+    salt TEXT NOT NULL,
+    kdf_params TEXT NOT NULL,
+    wrapped_key TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL
+);
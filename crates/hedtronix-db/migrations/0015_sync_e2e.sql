@@ -0,0 +1,35 @@
+-- Base64 X25519 public key that sync keys are sealed to
+ALTER TABLE devices ADD COLUMN agreement_key TEXT;
+
+-- Sync keys sealed to each device's agreement key. The server only ever
+-- sees sealed copies, so it cannot read the changes it relays.
+CREATE TABLE IF NOT EXISTS sync_key_grants (
+    key_id TEXT NOT NULL,
+    scope TEXT NOT NULL,
+    device_id TEXT NOT NULL REFERENCES devices(id),
+    sealed_key TEXT NOT NULL,
+    granted_by TEXT NOT NULL REFERENCES devices(id),
+    created_at TEXT NOT NULL,
+    PRIMARY KEY (key_id, device_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_sync_key_grants_device ON sync_key_grants(device_id);
+
+-- End-to-end encrypted changes held by the relay, in arrival order
+CREATE TABLE IF NOT EXISTS sync_relay (
+    sequence INTEGER PRIMARY KEY AUTOINCREMENT,
+    id TEXT NOT NULL UNIQUE,
+    scope TEXT NOT NULL,
+    key_id TEXT NOT NULL,
+    entity_type TEXT NOT NULL,
+    entity_id TEXT NOT NULL,
+    operation TEXT NOT NULL CHECK (operation IN ('CREATE', 'UPDATE', 'DELETE')),
+    timestamp TEXT NOT NULL,
+    device_id TEXT NOT NULL REFERENCES devices(id),
+    version_json TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    signature TEXT NOT NULL,
+    received_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_sync_relay_scope ON sync_relay(scope, sequence);
@@ -0,0 +1,8 @@
+-- MFA challenge tokens that have completed a login, so each is used once
+CREATE TABLE IF NOT EXISTS used_mfa_challenges (
+    jti TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL REFERENCES users(id),
+    expires_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_used_mfa_challenges_expiry ON used_mfa_challenges(expires_at);
@@ -0,0 +1,6 @@
+-- Financial chart access is its own grant. `patients:read` now opens only
+-- charts within the caller's care team or department, so the built-in
+-- roles that saw every chart's financial view move to `patients:read_financial`.
+UPDATE role_permissions
+SET action = 'read_financial'
+WHERE role_id IN ('BILLING', 'RECEPTIONIST') AND resource = 'patients' AND action = 'read';
@@ -0,0 +1,408 @@
+//! Audit log compaction and archival
+//!
+//! Entries older than the retention period are moved out of `audit_logs`
+//! into segment files: gzip-compressed JSON lines, encrypted with AES-256-GCM
+//! and bound to the segment's position in the chain. Each segment's record
+//! holds the hash of the entry before it, the hash of its last entry and a
+//! hash of the file, so the chain still verifies from entry 1 through the
+//! segments and into `audit_logs`.
+
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use hedtronix_core::{AuditLog, AuditLogFilters, Id, Timestamp};
+use hedtronix_crypto::{sha256_hex, Encryptor};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    AuditArchiveRepository, AuditArchiveSegment, AuditChainVerifier, AuditRepository, Database,
+    DbError, Result,
+};
+
+/// Entries per segment unless configured otherwise
+pub const DEFAULT_SEGMENT_SIZE: u32 = 10_000;
+
+/// Outcome of a compaction run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditCompactionReport {
+    pub segments: Vec<AuditArchiveSegment>,
+    pub entries_archived: i64,
+    /// Last sequence number held in the archive
+    pub archived_through: Option<i64>,
+}
+
+/// Archived entries matching a query, newest first
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchivedAuditLogs {
+    pub logs: Vec<AuditLog>,
+    pub total: usize,
+    /// Segments that were opened to answer the query
+    pub segments_read: usize,
+}
+
+/// Reads and writes audit archive segments in one directory
+pub struct AuditArchive {
+    db: Database,
+    dir: PathBuf,
+    encryptor: Encryptor,
+    /// The last segment read, since the verifier reads one in batches
+    cached: Mutex<Option<(Id, Arc<Vec<AuditLog>>)>>,
+}
+
+/// Additional data binding a segment's ciphertext to its chain position
+fn segment_aad(first_sequence: i64, last_sequence: i64, last_hash: &str) -> String {
+    format!("hedtronix-audit-archive:{}:{}:{}", first_sequence, last_sequence, last_hash)
+}
+
+fn io_error(e: impl std::fmt::Display) -> DbError {
+    DbError::AuditArchive(e.to_string())
+}
+
+fn archive_error(segment: &AuditArchiveSegment, detail: impl std::fmt::Display) -> DbError {
+    DbError::AuditArchive(format!("Segment {}: {}", segment.file_name, detail))
+}
+
+impl AuditArchive {
+    pub fn new(db: Database, dir: impl Into<PathBuf>, key: &[u8]) -> Result<Self> {
+        let encryptor = Encryptor::new(key).map_err(|e| DbError::AuditArchive(e.to_string()))?;
+        Ok(Self {
+            db,
+            dir: dir.into(),
+            encryptor,
+            cached: Mutex::new(None),
+        })
+    }
+
+    pub fn segments(&self) -> Result<Vec<AuditArchiveSegment>> {
+        AuditArchiveRepository::new(self.db.clone()).list()
+    }
+
+    /// Move entries older than `cutoff` into segments of at most
+    /// `segment_size` entries. The chain is verified first so a tampered
+    /// chain is never sealed into the archive, and the newest entry always
+    /// stays in `audit_logs` for the next append to link to.
+    pub fn compact(&self, cutoff: Timestamp, segment_size: u32) -> Result<AuditCompactionReport> {
+        let verification = AuditChainVerifier::new(self.db.clone()).verify()?;
+        if !verification.valid {
+            return Err(DbError::AuditArchive(
+                "The audit chain does not verify; refusing to archive it".to_string(),
+            ));
+        }
+
+        let mut report = AuditCompactionReport {
+            segments: Vec::new(),
+            entries_archived: 0,
+            archived_through: verification.archived_through,
+        };
+        let Some(head) = verification.head_sequence else {
+            return Ok(report);
+        };
+
+        let repo = AuditRepository::new(self.db.clone());
+        let archive_repo = AuditArchiveRepository::new(self.db.clone());
+        fs::create_dir_all(&self.dir).map_err(io_error)?;
+
+        loop {
+            let from = report.archived_through.unwrap_or(0) + 1;
+            let entries: Vec<AuditLog> = repo
+                .find_range(from, segment_size.max(1))?
+                .into_iter()
+                .take_while(|e| e.sequence < head && e.timestamp < cutoff)
+                .collect();
+            if entries.is_empty() {
+                break;
+            }
+
+            let segment = self.write_segment(&entries)?;
+            if let Err(e) = archive_repo.record(&segment) {
+                let _ = fs::remove_file(self.dir.join(&segment.file_name));
+                return Err(e);
+            }
+            tracing::info!(
+                "Archived audit entries {}-{} to {}",
+                segment.first_sequence,
+                segment.last_sequence,
+                segment.file_name
+            );
+
+            report.entries_archived += segment.entry_count;
+            report.archived_through = Some(segment.last_sequence);
+            report.segments.push(segment);
+        }
+
+        Ok(report)
+    }
+
+    fn write_segment(&self, entries: &[AuditLog]) -> Result<AuditArchiveSegment> {
+        let (first, last) = match (entries.first(), entries.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Err(DbError::AuditArchive("Empty segment".to_string())),
+        };
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        for entry in entries {
+            serde_json::to_writer(&mut encoder, entry).map_err(io_error)?;
+            encoder.write_all(b"\n").map_err(io_error)?;
+        }
+        let compressed = encoder.finish().map_err(io_error)?;
+
+        let aad = segment_aad(first.sequence, last.sequence, &last.hash);
+        let sealed = self.encryptor
+            .encrypt_bytes(&compressed, aad.as_bytes())
+            .map_err(|e| DbError::AuditArchive(e.to_string()))?;
+
+        // Write under a temporary name so a crash never leaves a partial
+        // segment behind
+        let file_name = format!("audit-{:012}-{:012}.seg", first.sequence, last.sequence);
+        let path = self.dir.join(&file_name);
+        let partial = path.with_extension("partial");
+        {
+            let mut file = File::create(&partial).map_err(io_error)?;
+            file.write_all(&sealed).map_err(io_error)?;
+            file.sync_all().map_err(io_error)?;
+        }
+        fs::rename(&partial, &path).map_err(io_error)?;
+
+        Ok(AuditArchiveSegment {
+            id: Id::new_v4(),
+            first_sequence: first.sequence,
+            last_sequence: last.sequence,
+            entry_count: entries.len() as i64,
+            earliest_timestamp: entries.iter().map(|e| e.timestamp).min().unwrap_or(first.timestamp),
+            latest_timestamp: entries.iter().map(|e| e.timestamp).max().unwrap_or(last.timestamp),
+            previous_hash: first.previous_hash.clone(),
+            last_hash: last.hash.clone(),
+            content_hash: sha256_hex(&sealed),
+            file_name,
+            created_at: chrono::Utc::now(),
+        })
+    }
+
+    /// Check that a segment's file exists and is the file that was written
+    pub fn check_segment(&self, segment: &AuditArchiveSegment) -> Result<Vec<u8>> {
+        let sealed = fs::read(self.dir.join(&segment.file_name))
+            .map_err(|e| archive_error(segment, e))?;
+        if sha256_hex(&sealed) != segment.content_hash {
+            return Err(archive_error(segment, "file does not match its recorded hash"));
+        }
+        Ok(sealed)
+    }
+
+    /// Decrypt and decompress a segment's entries
+    pub fn read_segment(&self, segment: &AuditArchiveSegment) -> Result<Arc<Vec<AuditLog>>> {
+        if let Ok(cached) = self.cached.lock() {
+            if let Some((id, entries)) = cached.as_ref() {
+                if *id == segment.id {
+                    return Ok(entries.clone());
+                }
+            }
+        }
+
+        let sealed = self.check_segment(segment)?;
+        let aad = segment_aad(segment.first_sequence, segment.last_sequence, &segment.last_hash);
+        let compressed = self.encryptor
+            .decrypt_bytes(&sealed, aad.as_bytes())
+            .map_err(|e| archive_error(segment, e))?;
+
+        let mut entries = Vec::with_capacity(segment.entry_count.max(0) as usize);
+        for line in BufReader::new(GzDecoder::new(compressed.as_slice())).lines() {
+            let line = line.map_err(|e| archive_error(segment, e))?;
+            entries.push(serde_json::from_str::<AuditLog>(&line).map_err(|e| archive_error(segment, e))?);
+        }
+
+        let bounds = (entries.first().map(|e| e.sequence), entries.last().map(|e| e.hash.as_str()));
+        if entries.len() as i64 != segment.entry_count
+            || bounds != (Some(segment.first_sequence), Some(segment.last_hash.as_str()))
+        {
+            return Err(archive_error(segment, "contents do not match the segment record"));
+        }
+
+        let entries = Arc::new(entries);
+        if let Ok(mut cached) = self.cached.lock() {
+            *cached = Some((segment.id, entries.clone()));
+        }
+        Ok(entries)
+    }
+
+    /// Archived entries from sequence `from`, in chain order
+    pub fn find_range(&self, from: i64, limit: u32) -> Result<Vec<AuditLog>> {
+        let mut logs = Vec::new();
+        for segment in self.segments()?.iter().filter(|s| s.last_sequence >= from) {
+            let remaining = limit as usize - logs.len();
+            if remaining == 0 {
+                break;
+            }
+            logs.extend(
+                self.read_segment(segment)?
+                    .iter()
+                    .filter(|e| e.sequence >= from)
+                    .take(remaining)
+                    .cloned(),
+            );
+        }
+        Ok(logs)
+    }
+
+    /// Search archived entries, opening only the segments whose sequence
+    /// and time ranges overlap the query. Results are newest first and
+    /// paged like `AuditRepository::search`.
+    pub fn search(
+        &self,
+        filters: &AuditLogFilters,
+        from_sequence: Option<i64>,
+        to_sequence: Option<i64>,
+    ) -> Result<ArchivedAuditLogs> {
+        let segments: Vec<AuditArchiveSegment> = self
+            .segments()?
+            .into_iter()
+            .filter(|s| from_sequence.is_none_or(|from| s.last_sequence >= from))
+            .filter(|s| to_sequence.is_none_or(|to| s.first_sequence <= to))
+            .filter(|s| filters.start_time.is_none_or(|start| s.latest_timestamp >= start))
+            .filter(|s| filters.end_time.is_none_or(|end| s.earliest_timestamp < end))
+            .collect();
+
+        let mut matches = Vec::new();
+        for segment in &segments {
+            matches.extend(
+                self.read_segment(segment)?
+                    .iter()
+                    .filter(|e| from_sequence.is_none_or(|from| e.sequence >= from))
+                    .filter(|e| to_sequence.is_none_or(|to| e.sequence <= to))
+                    .filter(|e| matches_filters(filters, e))
+                    .cloned(),
+            );
+        }
+        matches.reverse();
+
+        let total = matches.len();
+        let offset = filters.page as usize * filters.limit as usize;
+        let logs = matches.into_iter().skip(offset).take(filters.limit as usize).collect();
+
+        Ok(ArchivedAuditLogs { logs, total, segments_read: segments.len() })
+    }
+}
+
+/// The in-memory counterpart of `filter_clause`
+fn matches_filters(filters: &AuditLogFilters, log: &AuditLog) -> bool {
+    filters.user_id.is_none_or(|id| log.user_id == Some(id))
+        && filters.device_id.is_none_or(|id| log.device_id == Some(id))
+        && filters.entity_type.as_ref().is_none_or(|t| &log.entity_type == t)
+        && filters.entity_id.as_ref().is_none_or(|id| &log.entity_id == id)
+        && filters.event_types.as_ref().is_none_or(|types| types.contains(&log.event_type))
+        && filters.start_time.is_none_or(|start| log.timestamp >= start)
+        && filters.end_time.is_none_or(|end| log.timestamp < end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AuditChainBreakKind, AuditSigner};
+    use hedtronix_core::AuditEventType;
+
+    fn read(repo: &AuditRepository, entity_id: &str) {
+        repo.append(&AuditLog::new(
+            AuditEventType::Read,
+            None,
+            None,
+            "Patient".to_string(),
+            entity_id.to_string(),
+            serde_json::json!({}),
+        )).unwrap();
+    }
+
+    fn archived_chain(entries: usize, segment_size: u32) -> (Database, AuditArchive, PathBuf) {
+        let mut db = Database::in_memory().unwrap();
+        db.initialize().unwrap();
+        db.set_audit_signer(AuditSigner::generate().unwrap());
+
+        let repo = AuditRepository::new(db.clone());
+        for n in 0..entries {
+            read(&repo, &n.to_string());
+        }
+
+        let dir = std::env::temp_dir().join(format!("hedtronix-archive-{}", Id::new_v4()));
+        let archive = AuditArchive::new(db.clone(), &dir, &[7u8; 32]).unwrap();
+        let cutoff = chrono::Utc::now() + chrono::Duration::minutes(1);
+        archive.compact(cutoff, segment_size).unwrap();
+
+        (db, archive, dir)
+    }
+
+    #[test]
+    fn test_compaction_keeps_chain_verifiable() {
+        let (db, archive, dir) = archived_chain(8, 3);
+
+        // The newest entry stays behind for the next append to link to
+        let segments = archive.segments().unwrap();
+        let ranges: Vec<_> = segments.iter().map(|s| (s.first_sequence, s.last_sequence)).collect();
+        assert_eq!(ranges, vec![(1, 3), (4, 6), (7, 7)]);
+        let repo = AuditRepository::new(db.clone());
+        assert_eq!(repo.find_range(1, 100).unwrap().len(), 1);
+
+        read(&repo, "after");
+        let live = repo.find_range(1, 100).unwrap();
+        assert_eq!(live[1].previous_hash.as_deref(), Some(live[0].hash.as_str()));
+
+        let shallow = AuditChainVerifier::new(db.clone()).verify().unwrap();
+        assert!(shallow.valid);
+        assert_eq!(shallow.archived_through, Some(7));
+        assert_eq!(shallow.entries_checked, 2);
+
+        let deep = AuditChainVerifier::new(db.clone())
+            .with_archive(AuditArchive::new(db, &dir, &[7u8; 32]).unwrap())
+            .verify()
+            .unwrap();
+        assert!(deep.valid);
+        assert!(deep.archive_checked);
+        assert_eq!(deep.entries_checked, 9);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_search_reads_only_overlapping_segments() {
+        let (_db, archive, dir) = archived_chain(7, 2);
+
+        let filters = AuditLogFilters { entity_id: Some("4".to_string()), limit: 10, ..Default::default() };
+        let found = archive.search(&filters, None, None).unwrap();
+        assert_eq!(found.total, 1);
+        assert_eq!(found.logs[0].sequence, 5);
+        assert_eq!(found.segments_read, 3);
+
+        let ranged = archive.search(&AuditLogFilters { limit: 10, ..Default::default() }, Some(3), Some(4)).unwrap();
+        assert_eq!(ranged.segments_read, 1);
+        let sequences: Vec<i64> = ranged.logs.iter().map(|e| e.sequence).collect();
+        assert_eq!(sequences, vec![4, 3]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_tampered_segments_are_reported() {
+        let (db, archive, dir) = archived_chain(5, 2);
+        let segments = archive.segments().unwrap();
+
+        let path = dir.join(&segments[1].file_name);
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[20] ^= 1;
+        fs::write(&path, bytes).unwrap();
+
+        let report = AuditChainVerifier::new(db.clone())
+            .with_archive(AuditArchive::new(db.clone(), &dir, &[7u8; 32]).unwrap())
+            .verify()
+            .unwrap();
+        let first_break = report.first_break.unwrap();
+        assert_eq!(first_break.kind, AuditChainBreakKind::ArchiveTampered);
+        assert_eq!(first_break.sequence, 3);
+
+        // Dropping a segment record leaves a gap even without the archive key
+        db.execute("DELETE FROM audit_archive_segments WHERE first_sequence = 1", &[]).unwrap();
+        let report = AuditChainVerifier::new(db).verify().unwrap();
+        assert_eq!(report.first_break.unwrap().kind, AuditChainBreakKind::SequenceGap);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
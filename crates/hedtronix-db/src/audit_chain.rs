@@ -0,0 +1,634 @@
+//! Audit chain verification and anchoring
+//!
+//! The verifier walks `audit_logs` in sequence order, recomputing each
+//! entry's hash and checking its link to the previous entry and its
+//! signature. Hashes alone cannot reveal entries removed from the end of the
+//! chain, so the chain head is periodically anchored in `sync_metadata`; a
+//! chain that no longer reaches the anchored entry has been truncated.
+//!
+//! Once entries have been archived, the walk starts after the last archive
+//! segment and checks that segment records link up; given the archive, it
+//! also reads the segments and walks every entry from 1.
+
+use chrono::{DateTime, Utc};
+use hedtronix_core::{AuditLog, Id};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    AuditArchive, AuditArchiveRepository, AuditArchiveSegment, AuditRepository, AuditSigner,
+    Database, DbError, DeviceAuditRepository, Result, SyncRepository,
+};
+
+/// `sync_metadata` key holding the last anchored chain head
+pub const AUDIT_ANCHOR_KEY: &str = "audit_chain_anchor";
+
+/// `sync_metadata` key holding the sequence number of the first signed entry
+pub const AUDIT_SIGNING_START_KEY: &str = "audit_signing_started";
+
+/// Entries read per batch while walking the chain
+const BATCH_SIZE: u32 = 1000;
+
+/// A chain position recorded so later truncation can be detected
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditChainAnchor {
+    pub sequence: i64,
+    pub hash: String,
+    pub anchored_at: DateTime<Utc>,
+}
+
+/// How the chain is broken
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum AuditChainBreakKind {
+    /// The entry's contents no longer match its hash
+    HashMismatch,
+    /// `previous_hash` does not match the preceding entry
+    BrokenLink,
+    /// Sequence numbers skip, i.e. entries were removed
+    SequenceGap,
+    /// The signature is missing, malformed or not from the audit key
+    BadSignature,
+    /// The chain ends before, or differs at, the anchored head
+    Truncated,
+    /// An archive segment file is missing or is not the file written
+    ArchiveTampered,
+    /// An entry's row cannot be read back, e.g. a column was overwritten
+    /// with a value of the wrong type
+    Unreadable,
+}
+
+/// The first problem found, with the entries on either side of it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditChainBreak {
+    pub kind: AuditChainBreakKind,
+    /// Sequence number where the chain breaks
+    pub sequence: i64,
+    pub detail: String,
+    /// The last entry that verified
+    pub previous: Option<AuditLog>,
+    /// The offending entry, if it exists
+    pub entry: Option<AuditLog>,
+}
+
+/// Outcome of a full verification pass
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditChainReport {
+    pub valid: bool,
+    pub entries_checked: u64,
+    /// Entries without a signature, written while no audit key was set
+    pub unsigned_entries: u64,
+    /// Whether signatures were checked against an audit key
+    pub signatures_checked: bool,
+    pub head_sequence: Option<i64>,
+    pub head_hash: Option<String>,
+    /// Last sequence number moved to the archive
+    pub archived_through: Option<i64>,
+    /// Whether archived entries were read and verified, rather than only
+    /// the segment records
+    pub archive_checked: bool,
+    pub anchor: Option<AuditChainAnchor>,
+    pub first_break: Option<AuditChainBreak>,
+    pub verified_at: DateTime<Utc>,
+}
+
+/// Verifies and anchors the audit hash chain
+pub struct AuditChainVerifier {
+    db: Database,
+    archive: Option<AuditArchive>,
+}
+
+impl AuditChainVerifier {
+    pub fn new(db: Database) -> Self {
+        Self { db, archive: None }
+    }
+
+    /// Also read and verify archived entries
+    pub fn with_archive(mut self, archive: AuditArchive) -> Self {
+        self.archive = Some(archive);
+        self
+    }
+
+    /// Walk the whole chain and report the first broken link. Signatures
+    /// are checked when the database has an audit key.
+    pub fn verify(&self) -> Result<AuditChainReport> {
+        let repo = AuditRepository::new(self.db.clone());
+        let anchor = self.anchor_value()?;
+        let segments = AuditArchiveRepository::new(self.db.clone()).list()?;
+        let archived_through = segments.last().map(|s| s.last_sequence);
+        let signer = self.db.audit_signer();
+        let signed_from = self.signing_start()?;
+
+        let segment_break = match &self.archive {
+            Some(archive) => check_segments(&segments)
+                .or_else(|| check_segment_files(archive, &segments)),
+            None => check_segments(&segments),
+        };
+
+        let (mut report, head) = if let Some(segment_break) = segment_break {
+            let mut report = empty_report(signer.is_some());
+            report.first_break = Some(segment_break);
+            (report, None)
+        } else {
+            match &self.archive {
+                Some(archive) => walk_chain(
+                    |from, limit| match archived_through {
+                        Some(through) if from <= through => archive.find_range(from, limit),
+                        _ => repo.find_range(from, limit),
+                    },
+                    signer.as_deref(),
+                    signed_from,
+                    (1, None),
+                )?,
+                // Start after the archive, linked to its last entry
+                None => walk_chain(
+                    |from, limit| repo.find_range(from, limit),
+                    signer.as_deref(),
+                    signed_from,
+                    match segments.last() {
+                        Some(last) => (last.last_sequence + 1, Some(last.last_hash.clone())),
+                        None => (1, None),
+                    },
+                )?,
+            }
+        };
+
+        if report.first_break.is_none() {
+            if let Some(anchor) = &anchor {
+                let anchored = self.anchored_entry_hash(anchor, &segments, &repo)?;
+                report.first_break = check_anchor(anchor, head.as_ref(), archived_through, anchored);
+            }
+        }
+        report.archived_through = archived_through;
+        report.archive_checked = self.archive.is_some();
+        report.anchor = anchor;
+        report.valid = report.first_break.is_none();
+        Ok(report)
+    }
+
+    /// Hash of the entry at the anchor, or `None` if it has been archived
+    /// and cannot be read here
+    fn anchored_entry_hash(
+        &self,
+        anchor: &AuditChainAnchor,
+        segments: &[AuditArchiveSegment],
+        repo: &AuditRepository,
+    ) -> Result<Option<Option<String>>> {
+        let archived = segments.iter().find(|s| s.last_sequence >= anchor.sequence);
+        let entry = match (archived, &self.archive) {
+            (None, _) => repo.find_range(anchor.sequence, 1)?.into_iter().next(),
+            (Some(segment), _) if segment.last_sequence == anchor.sequence => {
+                return Ok(Some(Some(segment.last_hash.clone())));
+            }
+            (Some(_), Some(archive)) => archive.find_range(anchor.sequence, 1)?.into_iter().next(),
+            (Some(_), None) => return Ok(None),
+        };
+        Ok(Some(entry.filter(|e| e.sequence == anchor.sequence).map(|e| e.hash)))
+    }
+
+    /// Walk the sub-chain a device has replicated to this database. Device
+    /// entries are not signed with the server key, so only hashes and links
+    /// are checked.
+    pub fn verify_device(&self, device_id: Id) -> Result<AuditChainReport> {
+        let repo = DeviceAuditRepository::new(self.db.clone());
+        let (report, _) = walk_chain(
+            |from, limit| repo.find_range(device_id, from, limit),
+            None,
+            None,
+            (1, None),
+        )?;
+        Ok(report)
+    }
+
+    /// Sequence number of the first entry signed with an audit key
+    pub fn signing_start(&self) -> Result<Option<i64>> {
+        let value = SyncRepository::new(self.db.clone()).get_metadata(AUDIT_SIGNING_START_KEY)?;
+        Ok(value.and_then(|v| v.parse().ok()))
+    }
+
+    /// The last anchored chain head
+    pub fn anchor_value(&self) -> Result<Option<AuditChainAnchor>> {
+        let value = SyncRepository::new(self.db.clone()).get_metadata(AUDIT_ANCHOR_KEY)?;
+        Ok(value.and_then(|v| serde_json::from_str(&v).ok()))
+    }
+
+    /// Record the current chain head as the anchor. The chain is verified
+    /// first, so a tampered or truncated chain is reported rather than
+    /// anchored over.
+    pub fn anchor(&self) -> Result<AuditChainReport> {
+        let mut report = self.verify()?;
+        if !report.valid {
+            return Ok(report);
+        }
+
+        if let (Some(sequence), Some(hash)) = (report.head_sequence, report.head_hash.clone()) {
+            let anchor = AuditChainAnchor { sequence, hash, anchored_at: Utc::now() };
+            let value = serde_json::to_string(&anchor)
+                .map_err(|e| crate::DbError::Serialization(e.to_string()))?;
+            SyncRepository::new(self.db.clone()).set_metadata(AUDIT_ANCHOR_KEY, &value)?;
+            report.anchor = Some(anchor);
+        }
+
+        Ok(report)
+    }
+}
+
+fn empty_report(signatures_checked: bool) -> AuditChainReport {
+    AuditChainReport {
+        valid: true,
+        entries_checked: 0,
+        unsigned_entries: 0,
+        signatures_checked,
+        head_sequence: None,
+        head_hash: None,
+        archived_through: None,
+        archive_checked: false,
+        anchor: None,
+        first_break: None,
+        verified_at: Utc::now(),
+    }
+}
+
+/// Walk a chain in batches from `start` (the first sequence number and the
+/// hash it must link to), stopping at the first problem. Entries from
+/// `signed_from` on must carry a signature. Returns the report and the last
+/// entry that verified.
+fn walk_chain(
+    fetch: impl Fn(i64, u32) -> Result<Vec<AuditLog>>,
+    signer: Option<&AuditSigner>,
+    signed_from: Option<i64>,
+    start: (i64, Option<String>),
+) -> Result<(AuditChainReport, Option<AuditLog>)> {
+    let mut report = empty_report(signer.is_some());
+    let mut previous: Option<AuditLog> = None;
+    let (mut next_sequence, mut link) = start;
+    let mut signed_seen = false;
+    let mut batch_size = BATCH_SIZE;
+    // End of a batch that failed to decode, read one entry at a time
+    let mut narrowed_until = None;
+
+    'walk: loop {
+        if narrowed_until.is_some_and(|until| next_sequence >= until) {
+            batch_size = BATCH_SIZE;
+            narrowed_until = None;
+        }
+
+        let batch = match fetch(next_sequence, batch_size) {
+            Ok(batch) => batch,
+            // Re-read the failed batch one entry at a time to find the row
+            Err(e) if is_undecodable(&e) && batch_size > 1 => {
+                narrowed_until = Some(next_sequence + batch_size as i64);
+                batch_size = 1;
+                continue;
+            }
+            Err(e) if is_undecodable(&e) => {
+                report.first_break = Some(AuditChainBreak {
+                    kind: AuditChainBreakKind::Unreadable,
+                    sequence: next_sequence,
+                    detail: e.to_string(),
+                    previous: previous.clone(),
+                    entry: None,
+                });
+                break;
+            }
+            Err(e) => return Err(e),
+        };
+        if batch.is_empty() {
+            break;
+        }
+
+        for entry in batch {
+            if let Some((kind, detail)) = check_entry(&entry, link.as_deref(), next_sequence) {
+                report.first_break = Some(AuditChainBreak {
+                    kind,
+                    sequence: next_sequence,
+                    detail,
+                    previous: previous.clone(),
+                    entry: Some(entry),
+                });
+                break 'walk;
+            }
+
+            // Entries written before a key was configured are unsigned,
+            // but once signing starts every later entry must be signed
+            let signing_started = signed_seen || signed_from.is_some_and(|from| entry.sequence >= from);
+            let signature_error = match signer {
+                _ if entry.signature.is_empty() && signing_started => {
+                    Some("Unsigned entry after signing started")
+                }
+                _ if entry.signature.is_empty() => None,
+                Some(signer) if !signer.verify(&entry) => {
+                    Some("Signature does not verify with the audit key")
+                }
+                _ => None,
+            };
+            if let Some(detail) = signature_error {
+                report.first_break = Some(AuditChainBreak {
+                    kind: AuditChainBreakKind::BadSignature,
+                    sequence: entry.sequence,
+                    detail: detail.to_string(),
+                    previous: previous.clone(),
+                    entry: Some(entry),
+                });
+                break 'walk;
+            }
+            if entry.signature.is_empty() {
+                report.unsigned_entries += 1;
+            } else {
+                signed_seen = true;
+            }
+
+            report.entries_checked += 1;
+            next_sequence = entry.sequence + 1;
+            link = Some(entry.hash.clone());
+            previous = Some(entry);
+        }
+    }
+
+    report.head_sequence = previous.as_ref().map(|e| e.sequence);
+    report.head_hash = previous.as_ref().map(|e| e.hash.clone());
+    report.valid = report.first_break.is_none();
+    Ok((report, previous))
+}
+
+/// Whether a fetch failed because a row holds values that cannot be
+/// decoded, as opposed to the database being unavailable
+fn is_undecodable(error: &DbError) -> bool {
+    matches!(
+        error,
+        DbError::Sqlite(
+            rusqlite::Error::InvalidColumnType(..)
+                | rusqlite::Error::FromSqlConversionFailure(..)
+                | rusqlite::Error::IntegralValueOutOfRange(..)
+        )
+    )
+}
+
+/// Check an entry against its predecessor's hash
+fn check_entry(
+    entry: &AuditLog,
+    expected_link: Option<&str>,
+    expected_sequence: i64,
+) -> Option<(AuditChainBreakKind, String)> {
+    if entry.sequence != expected_sequence {
+        return Some((
+            AuditChainBreakKind::SequenceGap,
+            format!("Expected entry {} but found {}", expected_sequence, entry.sequence),
+        ));
+    }
+
+    if !entry.verify_hash() {
+        return Some((
+            AuditChainBreakKind::HashMismatch,
+            format!("Stored hash {} does not match recomputed {}", entry.hash, entry.compute_hash()),
+        ));
+    }
+
+    if entry.previous_hash.as_deref() != expected_link {
+        return Some((
+            AuditChainBreakKind::BrokenLink,
+            format!(
+                "previous_hash is {} but the preceding entry's hash is {}",
+                entry.previous_hash.as_deref().unwrap_or("empty"),
+                expected_link.unwrap_or("empty"),
+            ),
+        ));
+    }
+
+    None
+}
+
+/// Check that segment records cover the chain from entry 1 without gaps
+/// and link to each other
+fn check_segments(segments: &[AuditArchiveSegment]) -> Option<AuditChainBreak> {
+    let mut expected_first = 1;
+    let mut expected_link: Option<&str> = None;
+
+    for segment in segments {
+        let problem = if segment.first_sequence != expected_first
+            || segment.last_sequence - segment.first_sequence + 1 != segment.entry_count
+        {
+            Some((
+                AuditChainBreakKind::SequenceGap,
+                format!(
+                    "Expected an archive segment from entry {} but found entries {}-{}",
+                    expected_first, segment.first_sequence, segment.last_sequence,
+                ),
+            ))
+        } else if segment.previous_hash.as_deref() != expected_link {
+            Some((
+                AuditChainBreakKind::BrokenLink,
+                format!("Archive segment {} does not link to the segment before it", segment.file_name),
+            ))
+        } else {
+            None
+        };
+
+        if let Some((kind, detail)) = problem {
+            return Some(AuditChainBreak {
+                kind,
+                sequence: expected_first,
+                detail,
+                previous: None,
+                entry: None,
+            });
+        }
+
+        expected_first = segment.last_sequence + 1;
+        expected_link = Some(&segment.last_hash);
+    }
+
+    None
+}
+
+/// Check that every segment file is present and unmodified
+fn check_segment_files(archive: &AuditArchive, segments: &[AuditArchiveSegment]) -> Option<AuditChainBreak> {
+    segments.iter().find_map(|segment| {
+        let detail = match archive.check_segment(segment).err()? {
+            DbError::AuditArchive(detail) => detail,
+            e => e.to_string(),
+        };
+        Some(AuditChainBreak {
+            kind: AuditChainBreakKind::ArchiveTampered,
+            sequence: segment.first_sequence,
+            detail,
+            previous: None,
+            entry: None,
+        })
+    })
+}
+
+/// Check that the verified chain still reaches the anchored entry.
+/// `anchored` is the hash now at the anchored position, or `None` when it
+/// has been archived and the archive was not given.
+fn check_anchor(
+    anchor: &AuditChainAnchor,
+    head: Option<&AuditLog>,
+    archived_through: Option<i64>,
+    anchored: Option<Option<String>>,
+) -> Option<AuditChainBreak> {
+    let head_sequence = head.map(|h| h.sequence).or(archived_through).unwrap_or(0);
+    if head_sequence < anchor.sequence {
+        return Some(AuditChainBreak {
+            kind: AuditChainBreakKind::Truncated,
+            sequence: head_sequence + 1,
+            detail: format!(
+                "Chain ends at entry {} but entry {} was anchored at {}",
+                head_sequence,
+                anchor.sequence,
+                anchor.anchored_at.to_rfc3339(),
+            ),
+            previous: head.cloned(),
+            entry: None,
+        });
+    }
+
+    let anchored = anchored?;
+    if anchored.as_deref() != Some(anchor.hash.as_str()) {
+        return Some(AuditChainBreak {
+            kind: AuditChainBreakKind::Truncated,
+            sequence: anchor.sequence,
+            detail: format!(
+                "Entry {} no longer has the hash anchored at {}; the chain was rewritten",
+                anchor.sequence,
+                anchor.anchored_at.to_rfc3339(),
+            ),
+            previous: None,
+            entry: None,
+        });
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AuditSigner;
+    use hedtronix_core::AuditEventType;
+
+    fn chain(entries: usize) -> Database {
+        let mut db = Database::in_memory().unwrap();
+        db.initialize().unwrap();
+        db.set_audit_signer(AuditSigner::generate().unwrap());
+
+        let repo = AuditRepository::new(db.clone());
+        for n in 0..entries {
+            repo.append(&AuditLog::new(
+                AuditEventType::Read,
+                None,
+                None,
+                "Patient".to_string(),
+                n.to_string(),
+                serde_json::json!({}),
+            )).unwrap();
+        }
+        db
+    }
+
+    fn tamper(db: &Database, sql: &str) {
+        db.execute(sql, &[]).unwrap();
+    }
+
+    fn first_break(db: &Database) -> AuditChainBreak {
+        let report = AuditChainVerifier::new(db.clone()).verify().unwrap();
+        assert!(!report.valid);
+        report.first_break.unwrap()
+    }
+
+    #[test]
+    fn test_intact_chain_verifies() {
+        let db = chain(5);
+        let report = AuditChainVerifier::new(db).verify().unwrap();
+        assert!(report.valid);
+        assert!(report.signatures_checked);
+        assert_eq!(report.entries_checked, 5);
+        assert_eq!(report.head_sequence, Some(5));
+    }
+
+    #[test]
+    fn test_edited_entry_is_reported() {
+        let db = chain(5);
+        tamper(&db, "UPDATE audit_logs SET entity_id = 'x' WHERE sequence = 3");
+
+        let broken = first_break(&db);
+        assert_eq!(broken.kind, AuditChainBreakKind::HashMismatch);
+        assert_eq!(broken.sequence, 3);
+        assert_eq!(broken.previous.unwrap().sequence, 2);
+    }
+
+    #[test]
+    fn test_removed_entry_is_reported() {
+        let db = chain(5);
+        tamper(&db, "DELETE FROM audit_logs WHERE sequence = 2");
+
+        let broken = first_break(&db);
+        assert_eq!(broken.kind, AuditChainBreakKind::SequenceGap);
+        assert_eq!(broken.sequence, 2);
+    }
+
+    #[test]
+    fn test_unreadable_entry_is_reported() {
+        let db = chain(5);
+        tamper(&db, "UPDATE audit_logs SET entity_type = X'00' WHERE sequence = 4");
+
+        let broken = first_break(&db);
+        assert_eq!(broken.kind, AuditChainBreakKind::Unreadable);
+        assert_eq!(broken.sequence, 4);
+        assert_eq!(broken.previous.unwrap().sequence, 3);
+        assert!(AuditRepository::new(db).find_range(1, 10).is_err());
+    }
+
+    #[test]
+    fn test_rehashed_entry_fails_signature() {
+        let db = chain(3);
+        let repo = AuditRepository::new(db.clone());
+        let mut entry = repo.find_range(3, 1).unwrap().remove(0);
+        entry.entity_id = "x".to_string();
+        entry.hash = entry.compute_hash();
+        tamper(&db, &format!(
+            "UPDATE audit_logs SET entity_id = 'x', hash = '{}' WHERE sequence = 3",
+            entry.hash,
+        ));
+
+        assert_eq!(first_break(&db).kind, AuditChainBreakKind::BadSignature);
+    }
+
+    #[test]
+    fn test_stripped_signature_is_reported() {
+        let db = chain(3);
+        tamper(&db, "UPDATE audit_logs SET signature = '' WHERE sequence = 2");
+
+        let broken = first_break(&db);
+        assert_eq!(broken.kind, AuditChainBreakKind::BadSignature);
+        assert_eq!(broken.sequence, 2);
+    }
+
+    #[test]
+    fn test_signatures_stripped_from_the_start_are_reported() {
+        let db = chain(3);
+        assert_eq!(AuditChainVerifier::new(db.clone()).signing_start().unwrap(), Some(1));
+        tamper(&db, "UPDATE audit_logs SET signature = ''");
+
+        let broken = first_break(&db);
+        assert_eq!(broken.kind, AuditChainBreakKind::BadSignature);
+        assert_eq!(broken.sequence, 1);
+    }
+
+    #[test]
+    fn test_truncation_after_anchor_is_reported() {
+        let db = chain(5);
+        let verifier = AuditChainVerifier::new(db.clone());
+        let report = verifier.anchor().unwrap();
+        assert_eq!(report.anchor.unwrap().sequence, 5);
+
+        tamper(&db, "DELETE FROM audit_logs WHERE sequence >= 4");
+
+        let broken = first_break(&db);
+        assert_eq!(broken.kind, AuditChainBreakKind::Truncated);
+        assert_eq!(broken.sequence, 4);
+
+        // A truncated chain is not re-anchored
+        assert!(!verifier.anchor().unwrap().valid);
+        assert_eq!(verifier.anchor_value().unwrap().unwrap().sequence, 5);
+    }
+}
@@ -0,0 +1,116 @@
+//! Server key that signs audit log entries
+//!
+//! Each appended entry's hash is signed so that rewriting the chain — even
+//! consistently, with recomputed hashes — requires the server's private key.
+
+use std::fs;
+use std::path::Path;
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use hedtronix_core::AuditLog;
+use hedtronix_crypto::hashing::sha256_hex;
+use hedtronix_crypto::signing::{verify_signature, SignatureAlgorithm, SigningKeyPair};
+
+use crate::{DbError, Result};
+
+/// Ed25519 key used to sign audit entries
+pub struct AuditSigner {
+    key: SigningKeyPair,
+    key_id: String,
+}
+
+impl AuditSigner {
+    /// Generate a new random key
+    pub fn generate() -> Result<Self> {
+        let key = SigningKeyPair::generate(SignatureAlgorithm::Ed25519)
+            .map_err(|e| DbError::AuditKey(e.to_string()))?;
+        Ok(Self::from_key(key))
+    }
+
+    /// Load a key from PKCS#8 DER bytes
+    pub fn from_pkcs8(pkcs8: &[u8]) -> Result<Self> {
+        let key = SigningKeyPair::from_pkcs8(SignatureAlgorithm::Ed25519, pkcs8)
+            .map_err(|e| DbError::AuditKey(e.to_string()))?;
+        Ok(Self::from_key(key))
+    }
+
+    /// Load the key stored at `path`, generating and saving one if the
+    /// file does not exist
+    pub fn load_or_create(path: &Path) -> Result<Self> {
+        if path.exists() {
+            let der = fs::read(path).map_err(|e| DbError::AuditKey(e.to_string()))?;
+            return Self::from_pkcs8(&der);
+        }
+
+        let signer = Self::generate()?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| DbError::AuditKey(e.to_string()))?;
+        }
+        write_private_key(path, signer.key.pkcs8())?;
+        Ok(signer)
+    }
+
+    fn from_key(key: SigningKeyPair) -> Self {
+        let key_id = sha256_hex(key.public_key())[..16].to_string();
+        Self { key, key_id }
+    }
+
+    /// Short identifier of the key, recorded with every signature
+    pub fn key_id(&self) -> &str {
+        &self.key_id
+    }
+
+    /// Raw Ed25519 public key
+    pub fn public_key(&self) -> &[u8] {
+        self.key.public_key()
+    }
+
+    /// Sign an entry's hash; the result is `<key id>.<base64 signature>`
+    pub fn sign(&self, log: &AuditLog) -> Result<String> {
+        let sig = self.key
+            .sign(log.hash.as_bytes())
+            .map_err(|e| DbError::AuditKey(e.to_string()))?;
+        Ok(format!("{}.{}", self.key_id, STANDARD.encode(sig)))
+    }
+
+    /// Whether the entry carries a valid signature from this key over its
+    /// stored hash
+    pub fn verify(&self, log: &AuditLog) -> bool {
+        Self::verify_with(self.public_key(), log)
+    }
+
+    /// Whether the entry carries a valid signature, in the format `sign`
+    /// writes, from the Ed25519 key `public_key`, e.g. a device's
+    /// registered key
+    pub fn verify_with(public_key: &[u8], log: &AuditLog) -> bool {
+        let Some((key_id, sig)) = log.signature.split_once('.') else {
+            return false;
+        };
+        if key_id != &sha256_hex(public_key)[..16] {
+            return false;
+        }
+        let Ok(sig) = STANDARD.decode(sig) else {
+            return false;
+        };
+        verify_signature(SignatureAlgorithm::Ed25519, public_key, log.hash.as_bytes(), &sig)
+    }
+}
+
+#[cfg(unix)]
+fn write_private_key(path: &Path, der: &[u8]) -> Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+        .map_err(|e| DbError::AuditKey(e.to_string()))?;
+    file.write_all(der).map_err(|e| DbError::AuditKey(e.to_string()))
+}
+
+#[cfg(not(unix))]
+fn write_private_key(path: &Path, der: &[u8]) -> Result<()> {
+    fs::write(path, der).map_err(|e| DbError::AuditKey(e.to_string()))
+}
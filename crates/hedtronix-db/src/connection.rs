@@ -1,10 +1,19 @@
 //! Database connection management
+//!
+//! A file database runs in WAL mode with one writer connection and a pool
+//! of read-only connections, so reads proceed in parallel with each other
+//! and with the writer. In-memory databases are private to one connection
+//! and read through the writer.
 
-use rusqlite::{Connection, Result as SqliteResult};
+use rusqlite::{Connection, OpenFlags};
+use std::ops::Deref;
 use std::path::Path;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::Duration;
 use thiserror::Error;
 
+use crate::AuditSigner;
+
 /// Database error types
 #[derive(Error, Debug)]
 pub enum DbError {
@@ -23,6 +32,15 @@ pub enum DbError {
     #[error("Not found: {0}")]
     NotFound(String),
     
+    #[error("Audit key error: {0}")]
+    AuditKey(String),
+
+    #[error("Audit archive error: {0}")]
+    AuditArchive(String),
+
+    #[error("Encryption key error: {0}")]
+    EncryptionKey(String),
+    
     #[error("SQLite error: {0}")]
     Sqlite(#[from] rusqlite::Error),
 }
@@ -30,23 +48,48 @@ pub enum DbError {
 /// Result type for database operations
 pub type Result<T> = std::result::Result<T, DbError>;
 
+/// Reader connections opened by `Database::open`
+pub const DEFAULT_READERS: usize = 4;
+
+/// How long a connection waits for another connection's lock
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
 /// Database connection wrapper
 pub struct Database {
     conn: Arc<Mutex<Connection>>,
+    readers: Option<Arc<ReaderPool>>,
     initialized: bool,
+    audit_signer: Option<Arc<AuditSigner>>,
 }
 
 impl Database {
     /// Open or create a database at the specified path
     pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let conn = Connection::open(path)?;
+        Self::open_with_readers(path, DEFAULT_READERS)
+    }
+
+    /// Open or create a database with `readers` read-only connections
+    /// beside the writer. With no readers, every query shares the writer.
+    pub fn open_with_readers<P: AsRef<Path>>(path: P, readers: usize) -> Result<Self> {
+        let conn = Connection::open(&path)?;
         
         // Enable foreign keys
         conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+        // Readers see the last commit while the writer works
+        conn.query_row("PRAGMA journal_mode = WAL", [], |row| row.get::<_, String>(0))?;
+        conn.execute_batch("PRAGMA synchronous = NORMAL;")?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+
+        let readers = match readers {
+            0 => None,
+            count => Some(Arc::new(ReaderPool::open(path.as_ref(), count)?)),
+        };
         
         Ok(Self {
             conn: Arc::new(Mutex::new(conn)),
+            readers,
             initialized: false,
+            audit_signer: None,
         })
     }
 
@@ -59,30 +102,53 @@ impl Database {
         
         Ok(Self {
             conn: Arc::new(Mutex::new(conn)),
+            readers: None,
             initialized: false,
+            audit_signer: None,
         })
     }
 
-    /// Initialize the database with the schema
+    /// Bring the schema up to date and seed the built-in roles
     pub fn initialize(&mut self) -> Result<()> {
         if self.initialized {
             return Ok(());
         }
 
-        let schema = include_str!("schema.sql");
-        let conn = self.conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
-        conn.execute_batch(schema)?;
-        
-        drop(conn);
+        crate::migrations::migrate_to(self, crate::migrations::latest_version())?;
+        crate::RoleRepository::new(self.clone()).ensure_built_in_roles()?;
         self.initialized = true;
         Ok(())
     }
 
-    /// Get a connection for executing queries
+    /// Sign audit entries appended through this database (and its clones
+    /// made afterwards) with the given key
+    pub fn set_audit_signer(&mut self, signer: AuditSigner) {
+        self.audit_signer = Some(Arc::new(signer));
+    }
+
+    /// Key signing appended audit entries, if one is configured
+    pub fn audit_signer(&self) -> Option<Arc<AuditSigner>> {
+        self.audit_signer.clone()
+    }
+
+    /// Get the writer connection. Statements that only read should use
+    /// `reader` instead.
     pub fn connection(&self) -> Arc<Mutex<Connection>> {
         Arc::clone(&self.conn)
     }
 
+    /// Get a connection for queries that only read. Waits while every
+    /// reader is in use.
+    pub fn reader(&self) -> Result<ReadConnection<'_>> {
+        match &self.readers {
+            Some(pool) => pool.get(),
+            None => {
+                let conn = self.conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+                Ok(ReadConnection { inner: ReadInner::Writer(conn) })
+            }
+        }
+    }
+
     /// Execute a query that doesn't return rows
     pub fn execute(&self, sql: &str, params: &[&dyn rusqlite::ToSql]) -> Result<usize> {
         let conn = self.conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
@@ -99,7 +165,7 @@ impl Database {
 
     /// Check if a table exists
     pub fn table_exists(&self, table_name: &str) -> Result<bool> {
-        let conn = self.conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+        let conn = self.reader()?;
         let mut stmt = conn.prepare(
             "SELECT name FROM sqlite_master WHERE type='table' AND name=?"
         )?;
@@ -109,7 +175,7 @@ impl Database {
 
     /// Get database statistics
     pub fn stats(&self) -> Result<DatabaseStats> {
-        let conn = self.conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+        let conn = self.reader()?;
         
         let mut stmt = conn.prepare("SELECT COUNT(*) FROM users")?;
         let user_count: i64 = stmt.query_row([], |row| row.get(0)).unwrap_or(0);
@@ -136,7 +202,78 @@ impl Clone for Database {
     fn clone(&self) -> Self {
         Self {
             conn: Arc::clone(&self.conn),
+            readers: self.readers.clone(),
             initialized: self.initialized,
+            audit_signer: self.audit_signer.clone(),
+        }
+    }
+}
+
+/// Read-only connections handed out one caller at a time
+struct ReaderPool {
+    idle: Mutex<Vec<Connection>>,
+    returned: Condvar,
+}
+
+impl ReaderPool {
+    fn open(path: &Path, count: usize) -> Result<Self> {
+        let flags = OpenFlags::SQLITE_OPEN_READ_ONLY
+            | OpenFlags::SQLITE_OPEN_NO_MUTEX
+            | OpenFlags::SQLITE_OPEN_URI;
+        let connections = (0..count)
+            .map(|_| {
+                let conn = Connection::open_with_flags(path, flags)?;
+                conn.busy_timeout(BUSY_TIMEOUT)?;
+                Ok(conn)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            idle: Mutex::new(connections),
+            returned: Condvar::new(),
+        })
+    }
+
+    fn get(&self) -> Result<ReadConnection<'_>> {
+        let mut idle = self.idle.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+        loop {
+            if let Some(conn) = idle.pop() {
+                return Ok(ReadConnection { inner: ReadInner::Pooled(self, Some(conn)) });
+            }
+            idle = self.returned.wait(idle).map_err(|e| DbError::Connection(e.to_string()))?;
+        }
+    }
+}
+
+/// A connection for reading, returned to the pool when dropped
+pub struct ReadConnection<'a> {
+    inner: ReadInner<'a>,
+}
+
+enum ReadInner<'a> {
+    Pooled(&'a ReaderPool, Option<Connection>),
+    /// In-memory databases read through the writer
+    Writer(MutexGuard<'a, Connection>),
+}
+
+impl Deref for ReadConnection<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        match &self.inner {
+            ReadInner::Pooled(_, conn) => conn.as_ref().expect("reader is held until dropped"),
+            ReadInner::Writer(conn) => conn,
+        }
+    }
+}
+
+impl Drop for ReadConnection<'_> {
+    fn drop(&mut self) {
+        if let ReadInner::Pooled(pool, conn) = &mut self.inner {
+            if let (Ok(mut idle), Some(conn)) = (pool.idle.lock(), conn.take()) {
+                idle.push(conn);
+                pool.returned.notify_one();
+            }
         }
     }
 }
@@ -161,6 +298,47 @@ mod tests {
         assert!(db.table_exists("users").unwrap());
     }
 
+    #[test]
+    fn test_readers_share_a_wal_database() {
+        let dir = std::env::temp_dir().join(format!("hedtronix-db-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut db = Database::open_with_readers(dir.join("hedtronix.db"), 2).unwrap();
+        db.initialize().unwrap();
+
+        let mode: String = db.reader().unwrap().query_row("PRAGMA journal_mode", [], |row| row.get(0)).unwrap();
+        assert_eq!(mode, "wal");
+
+        // Readers are read-only and see the last commit, even while the
+        // writer holds a transaction open
+        let first = db.reader().unwrap();
+        let second = db.reader().unwrap();
+        assert!(first.execute("DELETE FROM users", []).is_err());
+        db.execute(
+            "INSERT INTO departments (id, name, created_at, updated_at) VALUES ('d1', 'ICU', '', '')",
+            &[],
+        )
+        .unwrap();
+        let writer = db.connection();
+        let writer = writer.lock().unwrap();
+        writer.execute_batch("BEGIN; DELETE FROM departments;").unwrap();
+        let count: i64 = second.query_row("SELECT COUNT(*) FROM departments", [], |row| row.get(0)).unwrap();
+        assert_eq!(count, 1);
+        writer.execute_batch("ROLLBACK;").unwrap();
+        drop(writer);
+
+        // A waiting caller gets the next reader returned to the pool
+        let waiter = {
+            let db = db.clone();
+            std::thread::spawn(move || db.table_exists("users").unwrap())
+        };
+        drop(first);
+        assert!(waiter.join().unwrap());
+        drop(second);
+
+        drop(db);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
     #[test]
     fn test_stats() {
         let mut db = Database::in_memory().unwrap();
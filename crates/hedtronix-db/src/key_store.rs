@@ -0,0 +1,546 @@
+//! Data key storage and background re-encryption
+//!
+//! Data keys live in `data_keys`, wrapped by the key encryption key from the
+//! server configuration. `KeyStore` loads them into the shared `Keyring` at
+//! startup and rotates them; `FieldReencryptor` then moves encrypted columns
+//! onto the active key and the current ciphertext format in small batches,
+//! recording progress in `reencryption_jobs` so a job survives a restart.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use hedtronix_core::Id;
+use hedtronix_crypto::{DataKey, FieldContext, KeyEncryptionKey, Keyring, FIELD_FORMAT_VERSION};
+use rusqlite::{params, TransactionBehavior};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    BlindIndexKeyRecord, DataKeyRecord, DataKeyRepository, DataKeyStatus, Database, DbError, ReencryptionJob,
+    ReencryptionStatus, Result,
+};
+
+/// Columns holding field-encrypted values, by table
+pub const ENCRYPTED_COLUMNS: &[(&str, &[&str])] = &[
+    (
+        "patients",
+        &[
+            "medical_record_number",
+            "first_name",
+            "last_name",
+            "date_of_birth",
+            "address_json",
+            "phone",
+            "email",
+            "emergency_contact_json",
+            "insurance_info_json",
+            "allergies_json",
+            "medications_json",
+            "problems_json",
+        ],
+    ),
+    ("clinical_notes", &["content"]),
+];
+
+/// Rows re-encrypted per transaction unless configured otherwise
+pub const DEFAULT_REENCRYPTION_BATCH: u32 = 200;
+
+/// Key id reported for values written before envelope encryption
+pub const LEGACY_KEY_ID: &str = "legacy";
+
+fn key_error(e: impl std::fmt::Display) -> DbError {
+    DbError::EncryptionKey(e.to_string())
+}
+
+/// A data key and how many encrypted values still use it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DataKeyUsage {
+    #[serde(flatten)]
+    pub key: DataKeyRecord,
+    pub encrypted_values: i64,
+}
+
+/// Overview of the stored data keys
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DataKeyReport {
+    pub kek_id: String,
+    pub active_key_id: Option<String>,
+    pub keys: Vec<DataKeyUsage>,
+    /// Values written before envelope encryption
+    pub legacy_values: i64,
+}
+
+/// Loads, rotates and rewraps the stored data keys
+pub struct KeyStore {
+    db: Database,
+    kek: KeyEncryptionKey,
+}
+
+impl KeyStore {
+    pub fn new(db: Database, kek: &[u8]) -> Result<Self> {
+        let kek = KeyEncryptionKey::new(kek).map_err(key_error)?;
+        Ok(Self { db, kek })
+    }
+
+    pub fn kek_id(&self) -> &str {
+        self.kek.id()
+    }
+
+    /// Unwrap every stored data key into the keyring and activate the
+    /// current one. The first load creates a data key, so new values get a
+    /// key id from then on.
+    pub fn load(&self, keyring: &Keyring) -> Result<()> {
+        let records = DataKeyRepository::new(self.db.clone()).list()?;
+        if records.is_empty() {
+            let record = self.rotate(keyring)?;
+            tracing::info!("Created data encryption key {}", record.id);
+            return self.load_index_key(keyring);
+        }
+
+        for record in &records {
+            keyring.insert(self.unwrap(&record.id, &record.kek_id, &record.wrapped_key)?).map_err(key_error)?;
+        }
+        match records.iter().find(|r| r.status == DataKeyStatus::Active) {
+            Some(active) => keyring.activate(&active.id).map_err(key_error)?,
+            None => return Err(DbError::EncryptionKey("No active data encryption key".to_string())),
+        }
+
+        self.load_index_key(keyring)
+    }
+
+    /// Unwrap the blind index key into the keyring, creating it on first use
+    fn load_index_key(&self, keyring: &Keyring) -> Result<()> {
+        let repo = DataKeyRepository::new(self.db.clone());
+        let key = match repo.find_index_key()? {
+            Some(record) => self.unwrap(&record.id, &record.kek_id, &record.wrapped_key)?,
+            None => {
+                let key = DataKey::generate().map_err(key_error)?;
+                repo.insert_index_key(&BlindIndexKeyRecord {
+                    id: key.id().to_string(),
+                    wrapped_key: self.kek.wrap(&key).map_err(key_error)?,
+                    kek_id: self.kek.id().to_string(),
+                    created_at: chrono::Utc::now(),
+                })?;
+                key
+            }
+        };
+        keyring.insert_index_key(key).map_err(key_error)
+    }
+
+    /// Generate a data key and make it the active one. Existing values keep
+    /// their key until a re-encryption job moves them.
+    pub fn rotate(&self, keyring: &Keyring) -> Result<DataKeyRecord> {
+        let key = DataKey::generate().map_err(key_error)?;
+        let record = DataKeyRecord {
+            id: key.id().to_string(),
+            wrapped_key: self.kek.wrap(&key).map_err(key_error)?,
+            kek_id: self.kek.id().to_string(),
+            status: DataKeyStatus::Active,
+            created_at: chrono::Utc::now(),
+            retired_at: None,
+        };
+
+        DataKeyRepository::new(self.db.clone()).insert_active(&record)?;
+        let id = record.id.clone();
+        keyring.insert(key).map_err(key_error)?;
+        keyring.activate(&id).map_err(key_error)?;
+
+        Ok(record)
+    }
+
+    /// Rewrap data keys and the blind index key still wrapped by
+    /// `previous`, after the key encryption key was changed. Returns how
+    /// many keys were rewrapped.
+    pub fn rewrap(&self, previous: &[u8]) -> Result<usize> {
+        let previous = KeyEncryptionKey::new(previous).map_err(key_error)?;
+        if previous.id() == self.kek.id() {
+            return Ok(0);
+        }
+        let repo = DataKeyRepository::new(self.db.clone());
+        let rewrap = |id: &str, wrapped: &str| -> Result<String> {
+            let key = previous.unwrap(id, wrapped).map_err(key_error)?;
+            self.kek.wrap(&key).map_err(key_error)
+        };
+
+        let mut rewrapped = 0;
+        for record in repo.list()? {
+            if record.kek_id == previous.id() {
+                repo.update_wrapping(&record.id, &rewrap(&record.id, &record.wrapped_key)?, self.kek.id())?;
+                rewrapped += 1;
+            }
+        }
+        if let Some(record) = repo.find_index_key()?.filter(|r| r.kek_id == previous.id()) {
+            repo.update_index_key_wrapping(&record.id, &rewrap(&record.id, &record.wrapped_key)?, self.kek.id())?;
+            rewrapped += 1;
+        }
+
+        Ok(rewrapped)
+    }
+
+    /// The stored keys with the number of values each one encrypts
+    pub fn report(&self, keyring: &Keyring) -> Result<DataKeyReport> {
+        let mut usage = encrypted_values_by_key(&self.db)?;
+        let keys = DataKeyRepository::new(self.db.clone())
+            .list()?
+            .into_iter()
+            .map(|key| DataKeyUsage {
+                encrypted_values: usage.remove(&key.id).unwrap_or(0),
+                key,
+            })
+            .collect();
+
+        Ok(DataKeyReport {
+            kek_id: self.kek.id().to_string(),
+            active_key_id: keyring.active_key_id(),
+            keys,
+            legacy_values: usage.remove(LEGACY_KEY_ID).unwrap_or(0),
+        })
+    }
+
+    fn unwrap(&self, id: &str, kek_id: &str, wrapped_key: &str) -> Result<DataKey> {
+        if kek_id != self.kek.id() {
+            return Err(DbError::EncryptionKey(format!(
+                "Key {} is wrapped by key encryption key {}, not the configured {}",
+                id,
+                kek_id,
+                self.kek.id()
+            )));
+        }
+        self.kek.unwrap(id, wrapped_key).map_err(key_error)
+    }
+}
+
+/// Count encrypted values per key id across all encrypted columns
+fn encrypted_values_by_key(db: &Database) -> Result<HashMap<String, i64>> {
+    let conn = db.connection();
+    let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+
+    let mut counts = HashMap::new();
+    for (table, columns) in ENCRYPTED_COLUMNS {
+        for column in *columns {
+            // Skip the format header; legacy values hold no ':', leaving an
+            // empty key id
+            let sql = format!(
+                "SELECT substr(v, 1, instr(v, ':') - 1), COUNT(*) FROM ( \
+                   SELECT CASE WHEN {c} LIKE '{h}:%' THEN substr({c}, {n}) ELSE {c} END AS v \
+                   FROM {t} WHERE {c} IS NOT NULL AND {c} <> '' \
+                 ) GROUP BY 1",
+                t = table,
+                c = column,
+                h = FIELD_FORMAT_VERSION,
+                n = FIELD_FORMAT_VERSION.len() + 2
+            );
+            let mut stmt = conn.prepare(&sql)?;
+            let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)))?;
+            for (id, count) in rows.filter_map(|r| r.ok()) {
+                let id = if id.is_empty() { LEGACY_KEY_ID.to_string() } else { id };
+                *counts.entry(id).or_insert(0) += count;
+            }
+        }
+    }
+
+    Ok(counts)
+}
+
+/// SQL condition matching rows with a value not in the current format
+/// under `key_id` (bound as `?1`)
+fn stale_condition(columns: &[&str]) -> String {
+    columns
+        .iter()
+        .map(|c| {
+            format!(
+                "({c} IS NOT NULL AND {c} <> '' AND {c} NOT LIKE '{h}:' || ?1 || ':%')",
+                c = c,
+                h = FIELD_FORMAT_VERSION
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(" OR ")
+}
+
+/// Moves encrypted values onto the active data key
+pub struct FieldReencryptor {
+    db: Database,
+    keyring: Arc<Keyring>,
+}
+
+impl FieldReencryptor {
+    pub fn new(db: Database, keyring: Arc<Keyring>) -> Self {
+        Self { db, keyring }
+    }
+
+    /// Rows holding at least one value not encrypted with `key_id`
+    pub fn pending_rows(&self, key_id: &str) -> Result<i64> {
+        let conn = self.db.reader()?;
+
+        let mut total = 0;
+        for (table, columns) in ENCRYPTED_COLUMNS {
+            let sql = format!("SELECT COUNT(*) FROM {} WHERE {}", table, stale_condition(columns));
+            total += conn.query_row(&sql, [key_id], |row| row.get::<_, i64>(0))?;
+        }
+
+        Ok(total)
+    }
+
+    /// Record a job moving every row to the active key
+    pub fn start(&self) -> Result<ReencryptionJob> {
+        let target = self
+            .keyring
+            .active_key_id()
+            .ok_or_else(|| DbError::EncryptionKey("No active data encryption key".to_string()))?;
+        let job = ReencryptionJob::new(target.clone(), self.pending_rows(&target)?);
+        DataKeyRepository::new(self.db.clone()).create_job(&job)?;
+        Ok(job)
+    }
+
+    /// Re-encrypt up to `batch` rows of a running job, in one transaction.
+    /// Returns how many rows were moved; zero means the job is complete.
+    pub fn run_batch(&self, job: &mut ReencryptionJob, batch: u32) -> Result<usize> {
+        if self.keyring.active_key_id().as_deref() != Some(job.target_key_id.as_str()) {
+            return Err(DbError::EncryptionKey(format!(
+                "Data key {} is no longer active",
+                job.target_key_id
+            )));
+        }
+
+        let conn = self.db.connection();
+        let mut conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        let mut remaining = batch.max(1) as usize;
+        let mut moved = 0;
+        for (table, columns) in ENCRYPTED_COLUMNS {
+            if remaining == 0 {
+                break;
+            }
+
+            let select = format!(
+                "SELECT id, {} FROM {} WHERE {} LIMIT ?2",
+                columns.join(", "),
+                table,
+                stale_condition(columns)
+            );
+            let rows: Vec<(String, Vec<Option<String>>)> = {
+                let mut stmt = tx.prepare(&select)?;
+                let rows = stmt.query_map(params![job.target_key_id, remaining as i64], |row| {
+                    let values = (0..columns.len())
+                        .map(|i| row.get::<_, Option<String>>(i + 1))
+                        .collect::<rusqlite::Result<Vec<_>>>()?;
+                    Ok((row.get::<_, String>(0)?, values))
+                })?;
+                rows.collect::<rusqlite::Result<Vec<_>>>()?
+            };
+
+            let update = format!(
+                "UPDATE {} SET {} WHERE id = ?",
+                table,
+                columns.iter().map(|c| format!("{} = ?", c)).collect::<Vec<_>>().join(", ")
+            );
+            for (id, values) in &rows {
+                let mut reencrypted = Vec::with_capacity(values.len());
+                for (column, value) in columns.iter().zip(values) {
+                    let field = FieldContext::new(table, column, id);
+                    reencrypted.push(match value.as_deref() {
+                        Some(v) if !v.is_empty() && self.keyring.needs_reencryption(v) => {
+                            let plaintext = self.keyring.decrypt_for_reencryption(v, &field).map_err(|e| {
+                                DbError::EncryptionKey(format!("{}.{} {}: {}", table, column, id, e))
+                            })?;
+                            Some(self.keyring.encrypt(&plaintext, &field).map_err(key_error)?)
+                        }
+                        _ => value.clone(),
+                    });
+                }
+
+                let mut bound: Vec<&dyn rusqlite::ToSql> =
+                    reencrypted.iter().map(|v| v as &dyn rusqlite::ToSql).collect();
+                bound.push(id);
+                tx.execute(&update, bound.as_slice())?;
+            }
+
+            moved += rows.len();
+            remaining -= rows.len();
+        }
+
+        tx.commit()?;
+        Ok(moved)
+    }
+
+    /// Run a job to completion, saving progress after every batch. A failure
+    /// is recorded on the job as well as returned.
+    pub fn run(&self, job_id: Id, batch: u32) -> Result<ReencryptionJob> {
+        let repo = DataKeyRepository::new(self.db.clone());
+        let mut job = repo
+            .find_job(job_id)?
+            .ok_or_else(|| DbError::NotFound(format!("Re-encryption job {}", job_id)))?;
+        if job.status != ReencryptionStatus::Running {
+            return Ok(job);
+        }
+
+        loop {
+            let moved = match self.run_batch(&mut job, batch) {
+                Ok(moved) => moved,
+                Err(e) => {
+                    job.status = ReencryptionStatus::Failed;
+                    job.error = Some(e.to_string());
+                    job.updated_at = chrono::Utc::now();
+                    repo.update_job(&job)?;
+                    return Err(e);
+                }
+            };
+
+            let now = chrono::Utc::now();
+            job.processed_rows += moved as i64;
+            job.updated_at = now;
+            if moved == 0 {
+                job.status = ReencryptionStatus::Completed;
+                job.completed_at = Some(now);
+            }
+            repo.update_job(&job)?;
+
+            if moved == 0 {
+                return Ok(job);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PatientRepository;
+    use hedtronix_core::{Gender, Patient};
+
+    fn setup() -> (Database, Arc<Keyring>) {
+        let mut db = Database::in_memory().unwrap();
+        db.initialize().unwrap();
+        (db, Arc::new(Keyring::legacy(&[1u8; 32]).unwrap()))
+    }
+
+    fn add_patient(db: &Database, keyring: &Arc<Keyring>, mrn: &str) -> Patient {
+        let dob = chrono::NaiveDate::from_ymd_opt(1980, 1, 2).unwrap();
+        let patient = Patient::new(mrn.to_string(), "Ada".to_string(), "Lovelace".to_string(), dob, Gender::Female);
+        PatientRepository::new(db.clone(), keyring.clone()).create(&patient).unwrap();
+        patient
+    }
+
+    /// Rewrite a patient's fields as they were stored before envelope
+    /// encryption
+    fn downgrade(db: &Database, keyring: &Keyring, patient: &Patient) {
+        let id = patient.id.to_string();
+        let conn = db.connection();
+        let conn = conn.lock().unwrap();
+        for column in ENCRYPTED_COLUMNS[0].1 {
+            let value: Option<String> = conn
+                .query_row(&format!("SELECT {} FROM patients WHERE id = ?1", column), [&id], |row| row.get(0))
+                .unwrap();
+            let Some(value) = value.filter(|v| !v.is_empty()) else { continue };
+            let plaintext = keyring.decrypt(&value, &FieldContext::new("patients", column, &id)).unwrap();
+            let legacy = hedtronix_crypto::encrypt_field(&plaintext, &[1u8; 32]).unwrap();
+            conn.execute(&format!("UPDATE patients SET {} = ?1 WHERE id = ?2", column), params![legacy, id])
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_rotation_and_reencryption() {
+        let (db, keyring) = setup();
+        let store = KeyStore::new(db.clone(), &[9u8; 32]).unwrap();
+        store.load(&keyring).unwrap();
+        let first = keyring.active_key_id().unwrap();
+        let legacy = add_patient(&db, &keyring, "MRN-1");
+        downgrade(&db, &keyring, &legacy);
+        let keyed = add_patient(&db, &keyring, "MRN-2");
+
+        // Unbound values are not read until they are re-encrypted
+        let repo = PatientRepository::new(db.clone(), keyring.clone());
+        assert!(!matches!(repo.find_by_id(legacy.id), Ok(Some(_))));
+
+        let report = store.report(&keyring).unwrap();
+        assert_eq!(report.keys.len(), 1);
+        assert_eq!(report.keys[0].encrypted_values, 11);
+        assert_eq!(report.legacy_values, 11);
+
+        let second = store.rotate(&keyring).unwrap();
+        assert_ne!(second.id, first);
+        let reencryptor = FieldReencryptor::new(db.clone(), keyring.clone());
+        let job = reencryptor.start().unwrap();
+        assert_eq!(job.total_rows, 2);
+        assert_eq!(job.target_key_id, second.id);
+
+        let job = reencryptor.run(job.id, 1).unwrap();
+        assert_eq!(job.status, ReencryptionStatus::Completed);
+        assert_eq!(job.processed_rows, 2);
+        assert_eq!(job.progress(), 1.0);
+        assert_eq!(reencryptor.pending_rows(&second.id).unwrap(), 0);
+
+        let report = store.report(&keyring).unwrap();
+        assert_eq!(report.legacy_values, 0);
+        assert_eq!(report.keys.iter().find(|k| k.key.id == first).unwrap().encrypted_values, 0);
+        assert_eq!(report.keys.iter().find(|k| k.key.id == second.id).unwrap().key.status, DataKeyStatus::Active);
+
+        // Values are unchanged and readable without the legacy key
+        let fresh = Arc::new(Keyring::new());
+        store.load(&fresh).unwrap();
+        let repo = PatientRepository::new(db.clone(), fresh);
+        assert_eq!(repo.find_by_id(legacy.id).unwrap().unwrap().medical_record_number, "MRN-1");
+        assert_eq!(repo.find_by_id(keyed.id).unwrap().unwrap().last_name, "Lovelace");
+    }
+
+    #[test]
+    fn test_swapped_ciphertext_is_rejected() {
+        let (db, keyring) = setup();
+        KeyStore::new(db.clone(), &[9u8; 32]).unwrap().load(&keyring).unwrap();
+        let ada = add_patient(&db, &keyring, "MRN-1");
+        let other = add_patient(&db, &keyring, "MRN-2");
+
+        // Copy one patient's encrypted name onto another row
+        {
+            let conn = db.connection();
+            let conn = conn.lock().unwrap();
+            conn.execute(
+                "UPDATE patients SET first_name = (SELECT first_name FROM patients WHERE id = ?1) WHERE id = ?2",
+                params![ada.id.to_string(), other.id.to_string()],
+            ).unwrap();
+        }
+
+        let repo = PatientRepository::new(db, keyring);
+        assert!(repo.find_by_id(ada.id).unwrap().is_some());
+        assert!(!matches!(repo.find_by_id(other.id), Ok(Some(_))));
+    }
+
+    #[test]
+    fn test_failed_job_records_error() {
+        let (db, keyring) = setup();
+        KeyStore::new(db.clone(), &[9u8; 32]).unwrap().load(&keyring).unwrap();
+        let patient = add_patient(&db, &keyring, "MRN-1");
+        downgrade(&db, &keyring, &patient);
+
+        // Without the legacy key the old values cannot be read
+        let keyring = Arc::new(Keyring::new());
+        let store = KeyStore::new(db.clone(), &[9u8; 32]).unwrap();
+        store.load(&keyring).unwrap();
+
+        let reencryptor = FieldReencryptor::new(db.clone(), keyring);
+        let job = reencryptor.start().unwrap();
+        assert!(reencryptor.run(job.id, 10).is_err());
+
+        let job = DataKeyRepository::new(db).find_job(job.id).unwrap().unwrap();
+        assert_eq!(job.status, ReencryptionStatus::Failed);
+        assert!(job.error.is_some());
+        assert_eq!(job.processed_rows, 0);
+    }
+
+    #[test]
+    fn test_kek_rotation_rewraps_keys() {
+        let (db, keyring) = setup();
+        KeyStore::new(db.clone(), &[9u8; 32]).unwrap().load(&keyring).unwrap();
+
+        let store = KeyStore::new(db.clone(), &[10u8; 32]).unwrap();
+        assert!(matches!(store.load(&Keyring::new()), Err(DbError::EncryptionKey(_))));
+        assert_eq!(store.rewrap(&[9u8; 32]).unwrap(), 2);
+        assert_eq!(store.rewrap(&[9u8; 32]).unwrap(), 0);
+
+        let reloaded = Keyring::new();
+        store.load(&reloaded).unwrap();
+        assert_eq!(reloaded.active_key_id(), keyring.active_key_id());
+    }
+}
@@ -2,10 +2,18 @@
 //!
 //! SQLite-based persistence with CRDT support.
 
+pub mod audit_archive;
+pub mod audit_chain;
+pub mod audit_signer;
 pub mod connection;
+pub mod key_store;
 pub mod repositories;
 pub mod migrations;
 
+pub use audit_archive::*;
+pub use audit_chain::*;
+pub use audit_signer::*;
 pub use connection::*;
+pub use key_store::*;
 pub use repositories::*;
 pub use migrations::*;
@@ -1,18 +1,417 @@
 //! Database migrations
+//!
+//! The schema is built by numbered forward migrations embedded from
+//! `migrations/`. `schema_version` records each one applied. A migration
+//! runs in its own transaction together with its `schema_version` row, so
+//! a failure leaves the database at the previous version. Before a file
+//! database that already has a schema is upgraded, a copy of it is written
+//! next to it.
+//!
+//! Migrations are never edited once released: a schema change is a new
+//! file here and a new entry in `MIGRATIONS`.
+
+use std::path::PathBuf;
+
+use chrono::Utc;
+use rusqlite::{Connection, TransactionBehavior};
 
 use crate::{Database, DbError, Result};
 
-/// Run all migrations
-pub fn run_migrations(db: &mut Database) -> Result<()> {
+/// A numbered schema change
+#[derive(Debug, Clone, Copy)]
+pub struct Migration {
+    pub version: u32,
+    pub name: &'static str,
+    sql: &'static str,
+}
+
+macro_rules! migration {
+    ($version:literal, $name:literal) => {
+        Migration {
+            version: $version,
+            name: $name,
+            sql: include_str!(concat!("../migrations/", $name, ".sql")),
+        }
+    };
+}
+
+/// Every migration, in order
+pub const MIGRATIONS: &[Migration] = &[
+    migration!(1, "0001_initial_schema"),
+    migration!(2, "0002_mfa"),
+    migration!(3, "0003_account_lockout"),
+    migration!(4, "0004_roles"),
+    migration!(5, "0005_patient_accounts"),
+    migration!(6, "0006_emergency_access"),
+    migration!(7, "0007_audit_chain"),
+    migration!(8, "0008_device_audit"),
+    migration!(9, "0009_disclosure_report"),
+    migration!(10, "0010_audit_archive"),
+    migration!(11, "0011_access_alerts"),
+    migration!(12, "0012_data_keys"),
+    migration!(13, "0013_blind_indexes"),
+    migration!(14, "0014_device_key_wrappings"),
+    migration!(15, "0015_sync_e2e"),
+    migration!(16, "0016_mfa_challenges"),
+    migration!(17, "0017_financial_chart_grant"),
+];
+
+const VERSION_TABLE: &str = r#"
+    CREATE TABLE IF NOT EXISTS schema_version (
+        version INTEGER PRIMARY KEY,
+        name TEXT NOT NULL,
+        applied_at TEXT NOT NULL
+    );
+"#;
+
+/// What a migration run did
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrationReport {
+    pub from_version: u32,
+    pub to_version: u32,
+    /// Versions applied, in order
+    pub applied: Vec<u32>,
+    /// Copy of the database taken before the first migration
+    pub backup: Option<PathBuf>,
+}
+
+/// Version the schema is at after every migration
+pub fn latest_version() -> u32 {
+    MIGRATIONS.last().map_or(0, |m| m.version)
+}
+
+/// Run all pending migrations
+pub fn run_migrations(db: &mut Database) -> Result<MigrationReport> {
+    let report = migrate_to(db, latest_version())?;
     db.initialize()?;
-    
-    // Add any additional migrations here
-    // For now, the schema.sql contains the initial migration
-    
-    Ok(())
+    Ok(report)
 }
 
 /// Check if migrations are up to date
 pub fn check_migrations(db: &Database) -> Result<bool> {
-    db.table_exists("users")
+    Ok(schema_version(db)? == latest_version())
+}
+
+/// Version the database schema is at; 0 for an empty database
+pub fn schema_version(db: &Database) -> Result<u32> {
+    let conn = db.connection();
+    let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+    current_version(&conn)
+}
+
+/// Apply pending migrations up to and including `target`
+pub(crate) fn migrate_to(db: &Database, target: u32) -> Result<MigrationReport> {
+    migrate(db, MIGRATIONS, target)
+}
+
+fn migrate(db: &Database, migrations: &[Migration], target: u32) -> Result<MigrationReport> {
+    let conn = db.connection();
+    let mut conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+
+    let from_version = current_version(&conn)?;
+    let known = migrations.last().map_or(0, |m| m.version);
+    if from_version > known {
+        return Err(DbError::Migration(format!(
+            "Database schema version {} is newer than this build supports ({})",
+            from_version, known
+        )));
+    }
+
+    let mut report = MigrationReport {
+        from_version,
+        to_version: from_version,
+        applied: Vec::new(),
+        backup: None,
+    };
+    let pending: Vec<&Migration> = migrations
+        .iter()
+        .filter(|m| m.version > from_version && m.version <= target)
+        .collect();
+    if pending.is_empty() {
+        return Ok(report);
+    }
+
+    if from_version > 0 {
+        report.backup = backup(&conn, from_version)?;
+    }
+
+    // Table rebuilds need foreign key enforcement off, and it can only be
+    // switched outside a transaction; each migration checks the keys
+    // itself before committing
+    conn.execute_batch("PRAGMA foreign_keys = OFF;")?;
+    let result = apply(&mut conn, migrations, &pending, &mut report);
+    conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+    result?;
+
+    Ok(report)
+}
+
+fn apply(
+    conn: &mut Connection,
+    migrations: &[Migration],
+    pending: &[&Migration],
+    report: &mut MigrationReport,
+) -> Result<()> {
+    for migration in pending {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        // Another connection may have migrated while this one waited
+        let version = current_version(&tx)?;
+        if version >= migration.version {
+            report.to_version = version;
+            continue;
+        }
+
+        tx.execute_batch(VERSION_TABLE)?;
+        if version > 0 && recorded_version(&tx)?.is_none() {
+            // Databases created before versioning get their original
+            // schema recorded as version 1
+            record(&tx, &migrations[0])?;
+        }
+
+        tx.execute_batch(migration.sql)
+            .map_err(|e| DbError::Migration(format!("{} failed: {}", migration.name, e)))?;
+        check_foreign_keys(&tx, migration)?;
+        record(&tx, migration)?;
+        tx.commit()?;
+
+        tracing::info!("Applied database migration {}", migration.name);
+        report.to_version = migration.version;
+        report.applied.push(migration.version);
+    }
+    Ok(())
+}
+
+fn current_version(conn: &Connection) -> Result<u32> {
+    if let Some(version) = recorded_version(conn)? {
+        return Ok(version);
+    }
+    // A schema without version records predates versioning and is at the
+    // original schema
+    Ok(if table_exists(conn, "users")? { 1 } else { 0 })
+}
+
+fn recorded_version(conn: &Connection) -> Result<Option<u32>> {
+    if !table_exists(conn, "schema_version")? {
+        return Ok(None);
+    }
+    let version = conn.query_row("SELECT MAX(version) FROM schema_version", [], |row| row.get(0))?;
+    Ok(version)
+}
+
+fn table_exists(conn: &Connection, name: &str) -> Result<bool> {
+    let mut stmt = conn.prepare("SELECT name FROM sqlite_master WHERE type='table' AND name=?")?;
+    Ok(stmt.exists([name])?)
+}
+
+fn record(conn: &Connection, migration: &Migration) -> Result<()> {
+    conn.execute(
+        "INSERT INTO schema_version (version, name, applied_at) VALUES (?, ?, ?)",
+        rusqlite::params![migration.version, migration.name, Utc::now().to_rfc3339()],
+    )?;
+    Ok(())
+}
+
+fn check_foreign_keys(conn: &Connection, migration: &Migration) -> Result<()> {
+    let mut stmt = conn.prepare("PRAGMA foreign_key_check")?;
+    let violation: Option<String> = stmt.query_map([], |row| row.get(0))?.next().transpose()?;
+    match violation {
+        Some(table) => Err(DbError::Migration(format!(
+            "{} leaves rows in {} violating foreign keys",
+            migration.name, table
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Copy a file database aside before it is upgraded. In-memory databases
+/// have nothing to keep.
+fn backup(conn: &Connection, version: u32) -> Result<Option<PathBuf>> {
+    let Some(path) = conn.path().filter(|p| !p.is_empty()) else {
+        return Ok(None);
+    };
+    let backup = PathBuf::from(format!(
+        "{}.v{}-{}.bak",
+        path,
+        version,
+        Utc::now().format("%Y%m%dT%H%M%S%3f")
+    ));
+    conn.execute("VACUUM INTO ?", [backup.to_string_lossy()])
+        .map_err(|e| DbError::Migration(format!("Backup before migrating failed: {}", e)))?;
+
+    tracing::info!("Backed up database schema version {} to {}", version, backup.display());
+    Ok(Some(backup))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AuditRepository, UserRepository};
+
+    /// Tables and indexes with their definitions, in a stable order
+    fn schema(db: &Database) -> Vec<(String, String)> {
+        let conn = db.connection();
+        let conn = conn.lock().unwrap();
+        let mut stmt = conn
+            .prepare(
+                "SELECT name, sql FROM sqlite_master
+                 WHERE sql IS NOT NULL AND name != 'sqlite_sequence'
+                 ORDER BY type, name",
+            )
+            .unwrap();
+        stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect()
+    }
+
+    /// A user and an audit entry as a database at `version` stores them
+    fn seed_rows(db: &Database, version: u32) {
+        let now = Utc::now().to_rfc3339();
+        // Audit entries have had a chain position since version 7
+        let (sequence_column, sequence) = if version >= 7 { (", sequence", ", 1") } else { ("", "") };
+        db.connection()
+            .lock()
+            .unwrap()
+            .execute_batch(&format!(
+                r#"
+                INSERT INTO users (id, email, name, role, created_at, updated_at, password_hash)
+                VALUES ('{user}', 'nurse@example.com', 'Nurse', 'NURSE', '{now}', '{now}', 'hash');
+                INSERT INTO audit_logs (
+                    id, event_type, user_id, entity_type, entity_id, changes_json, timestamp, signature, hash{sequence_column}
+                ) VALUES ('{log}', 'LOGIN', '{user}', 'User', '{user}', '{{}}', '{now}', 'sig', 'hash'{sequence});
+                "#,
+                user = hedtronix_core::Id::new_v4(),
+                log = hedtronix_core::Id::new_v4(),
+            ))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_migrations_are_numbered_in_order() {
+        for (i, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version, i as u32 + 1);
+            assert!(migration.name.starts_with(&format!("{:04}_", migration.version)));
+        }
+    }
+
+    #[test]
+    fn test_fresh_database_is_at_latest_version() {
+        let mut db = Database::in_memory().unwrap();
+        assert_eq!(schema_version(&db).unwrap(), 0);
+        assert!(!check_migrations(&db).unwrap());
+
+        let report = run_migrations(&mut db).unwrap();
+        assert_eq!(report.from_version, 0);
+        assert_eq!(report.applied, (1..=latest_version()).collect::<Vec<_>>());
+        assert_eq!(report.backup, None);
+        assert!(check_migrations(&db).unwrap());
+
+        // Running again finds nothing to do
+        let again = run_migrations(&mut db).unwrap();
+        assert!(again.applied.is_empty());
+        assert_eq!(again.to_version, latest_version());
+    }
+
+    #[test]
+    fn test_upgrade_from_every_prior_version() {
+        let mut fresh = Database::in_memory().unwrap();
+        fresh.initialize().unwrap();
+        let expected = schema(&fresh);
+
+        for version in 0..latest_version() {
+            let mut db = Database::in_memory().unwrap();
+            migrate_to(&db, version).unwrap();
+            assert_eq!(schema_version(&db).unwrap(), version);
+            if version > 0 {
+                seed_rows(&db, version);
+            }
+
+            let report = run_migrations(&mut db).unwrap();
+            assert_eq!(report.from_version, version);
+            assert_eq!(report.applied, (version + 1..=latest_version()).collect::<Vec<_>>());
+            assert_eq!(schema(&db), expected, "upgrading from version {}", version);
+
+            if version > 0 {
+                let user = UserRepository::new(db.clone()).find_by_email("nurse@example.com").unwrap().unwrap();
+                assert_eq!(user.failed_login_attempts, 0);
+                let chain = AuditRepository::new(db.clone()).find_range(1, 10).unwrap();
+                assert_eq!(chain.len(), 1);
+                assert_eq!(chain[0].sequence, 1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_unversioned_database_is_adopted() {
+        let mut db = Database::in_memory().unwrap();
+        db.connection().lock().unwrap().execute_batch(MIGRATIONS[0].sql).unwrap();
+        seed_rows(&db, 1);
+        assert_eq!(schema_version(&db).unwrap(), 1);
+
+        let report = run_migrations(&mut db).unwrap();
+        assert_eq!(report.from_version, 1);
+        assert_eq!(report.applied.first(), Some(&2));
+
+        let conn = db.connection();
+        let conn = conn.lock().unwrap();
+        let recorded: Vec<u32> = conn
+            .prepare("SELECT version FROM schema_version ORDER BY version")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect();
+        assert_eq!(recorded, (1..=latest_version()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_failed_migration_rolls_back() {
+        let db = Database::in_memory().unwrap();
+        let broken = Migration {
+            version: 3,
+            name: "0003_broken",
+            sql: "CREATE TABLE half_done (id TEXT); INSERT INTO missing_table VALUES (1);",
+        };
+        let migrations = [MIGRATIONS[0], MIGRATIONS[1], broken];
+
+        let error = migrate(&db, &migrations, 3).unwrap_err();
+        assert!(matches!(error, DbError::Migration(message) if message.contains("0003_broken")));
+        assert_eq!(schema_version(&db).unwrap(), 2);
+        assert!(!db.table_exists("half_done").unwrap());
+
+        // Foreign keys are enforced again afterwards
+        let enforced: bool = db.connection().lock().unwrap()
+            .query_row("PRAGMA foreign_keys", [], |row| row.get(0))
+            .unwrap();
+        assert!(enforced);
+    }
+
+    #[test]
+    fn test_backup_before_upgrade() {
+        let dir = std::env::temp_dir().join(format!("hedtronix-migrate-{}", hedtronix_core::Id::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("hedtronix.db");
+
+        let db = Database::open(&path).unwrap();
+        migrate_to(&db, 5).unwrap();
+        seed_rows(&db, 5);
+        let mut db = Database::open(&path).unwrap();
+        let report = run_migrations(&mut db).unwrap();
+
+        let backup = report.backup.unwrap();
+        assert!(backup.starts_with(&dir));
+        let copy = Database::open(&backup).unwrap();
+        assert_eq!(schema_version(&copy).unwrap(), 5);
+        assert!(UserRepository::new(copy).find_by_email("nurse@example.com").unwrap().is_some());
+
+        // A newer schema than this build knows is refused
+        db.execute(
+            "INSERT INTO schema_version (version, name, applied_at) VALUES (?, 'future', '')",
+            &[&(latest_version() + 1)],
+        )
+        .unwrap();
+        assert!(matches!(run_migrations(&mut Database::open(&path).unwrap()), Err(DbError::Migration(_))));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
 }
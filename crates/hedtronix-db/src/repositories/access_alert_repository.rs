@@ -0,0 +1,391 @@
+//! Access alert repository: the privacy office's alert queue and VIP list
+
+use rusqlite::{params, OptionalExtension, Row};
+use hedtronix_core::{
+    AccessAlert, AccessAlertFilters, AccessAlertRule, AccessAlertSeverity, AccessAlertStatus, Id,
+    Timestamp, VipPatient,
+};
+use crate::{Database, DbError, Result};
+
+pub struct AccessAlertRepository {
+    db: Database,
+}
+
+fn parse_time(s: &str) -> Option<chrono::DateTime<chrono::Utc>> {
+    chrono::DateTime::parse_from_rfc3339(s)
+        .map(|dt| dt.with_timezone(&chrono::Utc))
+        .ok()
+}
+
+fn parse_rule(s: &str) -> AccessAlertRule {
+    match s {
+        "VIP_PATIENT_ACCESS" => AccessAlertRule::VipPatientAccess,
+        "SAME_SURNAME" => AccessAlertRule::SameSurname,
+        "OFF_HOURS_BURST" => AccessAlertRule::OffHoursBurst,
+        "UNREGISTERED_DEVICE" => AccessAlertRule::UnregisteredDevice,
+        _ => AccessAlertRule::ExcessiveChartAccess,
+    }
+}
+
+fn parse_severity(s: &str) -> AccessAlertSeverity {
+    match s {
+        "LOW" => AccessAlertSeverity::Low,
+        "MEDIUM" => AccessAlertSeverity::Medium,
+        _ => AccessAlertSeverity::High,
+    }
+}
+
+fn parse_status(s: &str) -> AccessAlertStatus {
+    match s {
+        "ESCALATED" => AccessAlertStatus::Escalated,
+        "DISMISSED" => AccessAlertStatus::Dismissed,
+        _ => AccessAlertStatus::Open,
+    }
+}
+
+const SELECT_ALERT: &str = r#"
+    SELECT id, rule, severity, user_id, patient_id, audit_log_ids_json, description,
+           created_at, status, reviewed_by, reviewed_at, review_notes
+    FROM access_alerts
+"#;
+
+/// WHERE clause and bound values for a set of filters
+fn filter_clause(filters: &AccessAlertFilters) -> (String, Vec<String>) {
+    let mut sql = String::from(" WHERE 1=1");
+    let mut values = Vec::new();
+
+    if let Some(status) = filters.status {
+        sql.push_str(" AND status = ?");
+        values.push(status.as_str().to_string());
+    }
+    if let Some(rule) = filters.rule {
+        sql.push_str(" AND rule = ?");
+        values.push(rule.as_str().to_string());
+    }
+    if let Some(user_id) = filters.user_id {
+        sql.push_str(" AND user_id = ?");
+        values.push(user_id.to_string());
+    }
+    if let Some(patient_id) = filters.patient_id {
+        sql.push_str(" AND patient_id = ?");
+        values.push(patient_id.to_string());
+    }
+
+    (sql, values)
+}
+
+impl AccessAlertRepository {
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+
+    fn row_to_alert(row: &Row) -> rusqlite::Result<AccessAlert> {
+        let id: String = row.get(0)?;
+        let rule: String = row.get(1)?;
+        let severity: String = row.get(2)?;
+        let user_id: String = row.get(3)?;
+        let patient_id: Option<String> = row.get(4)?;
+        let audit_log_ids_json: String = row.get(5)?;
+        let created_at: String = row.get(7)?;
+        let status: String = row.get(8)?;
+        let reviewed_by: Option<String> = row.get(9)?;
+        let reviewed_at: Option<String> = row.get(10)?;
+
+        Ok(AccessAlert {
+            id: Id::parse_str(&id).unwrap_or_else(|_| Id::new_v4()),
+            rule: parse_rule(&rule),
+            severity: parse_severity(&severity),
+            user_id: Id::parse_str(&user_id).unwrap_or_else(|_| Id::new_v4()),
+            patient_id: patient_id.and_then(|s| Id::parse_str(&s).ok()),
+            audit_log_ids: serde_json::from_str(&audit_log_ids_json).unwrap_or_default(),
+            description: row.get(6)?,
+            created_at: parse_time(&created_at).unwrap_or_else(chrono::Utc::now),
+            status: parse_status(&status),
+            reviewed_by: reviewed_by.and_then(|s| Id::parse_str(&s).ok()),
+            reviewed_at: reviewed_at.as_deref().and_then(parse_time),
+            review_notes: row.get(11)?,
+        })
+    }
+
+    pub fn create(&self, alert: &AccessAlert) -> Result<()> {
+        let conn = self.db.connection();
+        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+
+        conn.execute(
+            r#"
+            INSERT INTO access_alerts (
+                id, rule, severity, user_id, patient_id, audit_log_ids_json, description,
+                created_at, status, reviewed_by, reviewed_at, review_notes
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+            params![
+                alert.id.to_string(),
+                alert.rule.as_str(),
+                alert.severity.as_str(),
+                alert.user_id.to_string(),
+                alert.patient_id.map(|id| id.to_string()),
+                serde_json::to_string(&alert.audit_log_ids).unwrap_or_default(),
+                alert.description,
+                alert.created_at.to_rfc3339(),
+                alert.status.as_str(),
+                alert.reviewed_by.map(|id| id.to_string()),
+                alert.reviewed_at.map(|t| t.to_rfc3339()),
+                alert.review_notes,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn find_by_id(&self, id: Id) -> Result<Option<AccessAlert>> {
+        let conn = self.db.reader()?;
+
+        let alert = conn.query_row(
+            &format!("{} WHERE id = ?", SELECT_ALERT),
+            [id.to_string()],
+            Self::row_to_alert,
+        ).optional()?;
+
+        Ok(alert)
+    }
+
+    /// Alerts matching the filters, newest first, one page at a time
+    pub fn search(&self, filters: &AccessAlertFilters) -> Result<Vec<AccessAlert>> {
+        let conn = self.db.reader()?;
+
+        let (clause, values) = filter_clause(filters);
+        let sql = format!(
+            "{}{} ORDER BY created_at DESC LIMIT {} OFFSET {}",
+            SELECT_ALERT,
+            clause,
+            filters.limit,
+            filters.page as u64 * filters.limit as u64,
+        );
+        let mut stmt = conn.prepare(&sql)?;
+
+        let alerts = stmt
+            .query_map(rusqlite::params_from_iter(values), Self::row_to_alert)?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        Ok(alerts)
+    }
+
+    /// Number of alerts matching the filters, ignoring pagination
+    pub fn count(&self, filters: &AccessAlertFilters) -> Result<i64> {
+        let conn = self.db.reader()?;
+
+        let (clause, values) = filter_clause(filters);
+        let count = conn.query_row(
+            &format!("SELECT COUNT(*) FROM access_alerts{}", clause),
+            rusqlite::params_from_iter(values),
+            |row| row.get(0),
+        )?;
+
+        Ok(count)
+    }
+
+    /// Whether the rule has already raised an alert for the user (and
+    /// patient, when given) since `since`, whatever its status
+    pub fn raised_since(
+        &self,
+        rule: AccessAlertRule,
+        user_id: Id,
+        patient_id: Option<Id>,
+        since: Timestamp,
+    ) -> Result<bool> {
+        let conn = self.db.reader()?;
+
+        let exists = conn.query_row(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM access_alerts
+                WHERE rule = ?1 AND user_id = ?2 AND created_at >= ?3
+                  AND (?4 IS NULL OR patient_id = ?4)
+            )
+            "#,
+            params![
+                rule.as_str(),
+                user_id.to_string(),
+                since.to_rfc3339(),
+                patient_id.map(|id| id.to_string()),
+            ],
+            |row| row.get(0),
+        )?;
+
+        Ok(exists)
+    }
+
+    /// Record the review outcome; false if the alert was already reviewed
+    pub fn review(
+        &self,
+        id: Id,
+        status: AccessAlertStatus,
+        reviewer_id: Id,
+        notes: Option<&str>,
+    ) -> Result<bool> {
+        let conn = self.db.connection();
+        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+
+        let updated = conn.execute(
+            r#"
+            UPDATE access_alerts
+            SET status = ?, reviewed_by = ?, reviewed_at = ?, review_notes = ?
+            WHERE id = ? AND status = 'OPEN'
+            "#,
+            params![
+                status.as_str(),
+                reviewer_id.to_string(),
+                chrono::Utc::now().to_rfc3339(),
+                notes,
+                id.to_string(),
+            ],
+        )?;
+
+        Ok(updated == 1)
+    }
+
+    /// The patient whose records an audited entity belongs to
+    pub fn patient_for_entity(&self, entity_type: &str, entity_id: &str) -> Result<Option<Id>> {
+        let sql = match entity_type {
+            "Patient" => "SELECT id FROM patients WHERE id = ?",
+            "ClinicalNote" => "SELECT patient_id FROM clinical_notes WHERE id = ?",
+            "Appointment" => "SELECT patient_id FROM appointments WHERE id = ?",
+            "BillingEntry" => "SELECT patient_id FROM billing_entries WHERE id = ?",
+            "EmergencyAccessGrant" => "SELECT patient_id FROM emergency_access_grants WHERE id = ?",
+            _ => return Ok(None),
+        };
+
+        let conn = self.db.reader()?;
+
+        let patient_id: Option<String> = conn
+            .query_row(sql, [entity_id], |row| row.get(0))
+            .optional()?;
+
+        Ok(patient_id.and_then(|s| Id::parse_str(&s).ok()))
+    }
+
+    /// Flag a patient as a VIP, replacing any earlier flag
+    pub fn flag_vip(&self, vip: &VipPatient) -> Result<()> {
+        let conn = self.db.connection();
+        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+
+        conn.execute(
+            r#"
+            INSERT OR REPLACE INTO vip_patients (patient_id, reason, flagged_by, flagged_at)
+            VALUES (?, ?, ?, ?)
+            "#,
+            params![
+                vip.patient_id.to_string(),
+                vip.reason,
+                vip.flagged_by.map(|id| id.to_string()),
+                vip.flagged_at.to_rfc3339(),
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Remove a patient's VIP flag; false if they were not flagged
+    pub fn unflag_vip(&self, patient_id: Id) -> Result<bool> {
+        let conn = self.db.connection();
+        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+
+        let removed = conn.execute(
+            "DELETE FROM vip_patients WHERE patient_id = ?",
+            [patient_id.to_string()],
+        )?;
+
+        Ok(removed == 1)
+    }
+
+    pub fn find_vip(&self, patient_id: Id) -> Result<Option<VipPatient>> {
+        let conn = self.db.reader()?;
+
+        let vip = conn.query_row(
+            "SELECT patient_id, reason, flagged_by, flagged_at FROM vip_patients WHERE patient_id = ?",
+            [patient_id.to_string()],
+            Self::row_to_vip,
+        ).optional()?;
+
+        Ok(vip)
+    }
+
+    /// Every flagged patient, most recently flagged first
+    pub fn list_vips(&self) -> Result<Vec<VipPatient>> {
+        let conn = self.db.reader()?;
+
+        let mut stmt = conn.prepare(
+            "SELECT patient_id, reason, flagged_by, flagged_at FROM vip_patients ORDER BY flagged_at DESC"
+        )?;
+
+        let vips = stmt
+            .query_map([], Self::row_to_vip)?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        Ok(vips)
+    }
+
+    fn row_to_vip(row: &Row) -> rusqlite::Result<VipPatient> {
+        let patient_id: String = row.get(0)?;
+        let flagged_by: Option<String> = row.get(2)?;
+        let flagged_at: String = row.get(3)?;
+
+        Ok(VipPatient {
+            patient_id: Id::parse_str(&patient_id).unwrap_or_else(|_| Id::new_v4()),
+            reason: row.get(1)?,
+            flagged_by: flagged_by.and_then(|s| Id::parse_str(&s).ok()),
+            flagged_at: parse_time(&flagged_at).unwrap_or_else(chrono::Utc::now),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::UserRepository;
+    use hedtronix_core::{User, UserRole};
+
+    #[test]
+    fn test_queue_and_review() {
+        let mut db = Database::in_memory().unwrap();
+        db.initialize().unwrap();
+
+        let user = User::new("clerk@example.com".into(), "Cal Clerk".into(), UserRole::Receptionist, "hash".into());
+        let officer = User::new("privacy@example.com".into(), "Pat Privacy".into(), UserRole::Admin, "hash".into());
+        let users = UserRepository::new(db.clone());
+        users.create(&user).unwrap();
+        users.create(&officer).unwrap();
+
+        let repo = AccessAlertRepository::new(db.clone());
+        let patient_id = Id::new_v4();
+        let alert = AccessAlert::new(
+            AccessAlertRule::SameSurname,
+            AccessAlertSeverity::Medium,
+            user.id,
+            Some(patient_id),
+            vec![Id::new_v4()],
+            "Read a chart with the same surname".into(),
+        );
+        repo.create(&alert).unwrap();
+
+        let since = chrono::Utc::now() - chrono::Duration::hours(1);
+        assert!(repo.raised_since(AccessAlertRule::SameSurname, user.id, Some(patient_id), since).unwrap());
+        assert!(repo.raised_since(AccessAlertRule::SameSurname, user.id, None, since).unwrap());
+        assert!(!repo.raised_since(AccessAlertRule::SameSurname, user.id, Some(Id::new_v4()), since).unwrap());
+        assert!(!repo.raised_since(AccessAlertRule::VipPatientAccess, user.id, None, since).unwrap());
+
+        let open = AccessAlertFilters { status: Some(AccessAlertStatus::Open), limit: 10, ..Default::default() };
+        assert_eq!(repo.count(&open).unwrap(), 1);
+        assert_eq!(repo.search(&open).unwrap()[0].audit_log_ids, alert.audit_log_ids);
+
+        assert!(repo.review(alert.id, AccessAlertStatus::Dismissed, officer.id, Some("Spouse, consented")).unwrap());
+        assert!(!repo.review(alert.id, AccessAlertStatus::Escalated, officer.id, None).unwrap());
+
+        let reviewed = repo.find_by_id(alert.id).unwrap().unwrap();
+        assert_eq!(reviewed.status, AccessAlertStatus::Dismissed);
+        assert_eq!(reviewed.reviewed_by, Some(officer.id));
+        assert_eq!(repo.count(&open).unwrap(), 0);
+    }
+}
@@ -1,7 +1,7 @@
 //! Appointment repository
 
 use rusqlite::{params, Row};
-use hedtronix_core::{Appointment, AppointmentStatus, AppointmentType, CalendarFilters, Id, RecurrenceRule, VersionVector};
+use hedtronix_core::{Appointment, AppointmentStatus, AppointmentType, CalendarFilters, Id};
 use crate::{Database, DbError, Result};
 
 pub struct AppointmentRepository {
@@ -156,8 +156,7 @@ impl AppointmentRepository {
     }
 
     pub fn find_by_id(&self, id: Id) -> Result<Option<Appointment>> {
-        let conn = self.db.connection();
-        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+        let conn = self.db.reader()?;
 
         let mut stmt = conn.prepare(
             r#"
@@ -175,8 +174,7 @@ impl AppointmentRepository {
     }
 
     pub fn find_by_provider(&self, provider_id: Id, filters: &CalendarFilters) -> Result<Vec<Appointment>> {
-        let conn = self.db.connection();
-        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+        let conn = self.db.reader()?;
 
         let mut stmt = conn.prepare(
             r#"
@@ -206,8 +204,7 @@ impl AppointmentRepository {
     }
 
     pub fn find_by_patient(&self, patient_id: Id) -> Result<Vec<Appointment>> {
-        let conn = self.db.connection();
-        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+        let conn = self.db.reader()?;
 
         let mut stmt = conn.prepare(
             r#"
@@ -238,8 +235,7 @@ impl AppointmentRepository {
         end_time: chrono::DateTime<chrono::Utc>,
         exclude_id: Option<Id>,
     ) -> Result<Vec<Appointment>> {
-        let conn = self.db.connection();
-        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+        let conn = self.db.reader()?;
 
         let sql = if let Some(exclude) = exclude_id {
             format!(
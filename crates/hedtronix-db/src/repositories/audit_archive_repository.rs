@@ -0,0 +1,163 @@
+//! Audit archive segment repository
+
+use rusqlite::{params, OptionalExtension, Row, TransactionBehavior};
+use hedtronix_core::{Id, Timestamp};
+use serde::{Deserialize, Serialize};
+
+use crate::{Database, DbError, Result};
+
+pub struct AuditArchiveRepository {
+    db: Database,
+}
+
+/// A contiguous run of audit entries moved to an archive file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditArchiveSegment {
+    pub id: Id,
+    pub first_sequence: i64,
+    pub last_sequence: i64,
+    pub entry_count: i64,
+    /// Range of entry timestamps, for finding segments by time
+    pub earliest_timestamp: Timestamp,
+    pub latest_timestamp: Timestamp,
+    /// Hash of the entry before the segment (`None` for the first)
+    pub previous_hash: Option<String>,
+    /// Hash of the segment's last entry
+    pub last_hash: String,
+    /// SHA-256 of the archive file
+    pub content_hash: String,
+    pub file_name: String,
+    pub created_at: Timestamp,
+}
+
+fn parse_time(s: &str) -> Option<chrono::DateTime<chrono::Utc>> {
+    chrono::DateTime::parse_from_rfc3339(s)
+        .map(|dt| dt.with_timezone(&chrono::Utc))
+        .ok()
+}
+
+const SELECT_SEGMENT: &str = r#"
+    SELECT id, first_sequence, last_sequence, entry_count, earliest_timestamp,
+           latest_timestamp, previous_hash, last_hash, content_hash, file_name,
+           created_at
+    FROM audit_archive_segments
+"#;
+
+impl AuditArchiveRepository {
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+
+    fn row_to_segment(row: &Row) -> rusqlite::Result<AuditArchiveSegment> {
+        let id: String = row.get(0)?;
+        let earliest: String = row.get(4)?;
+        let latest: String = row.get(5)?;
+        let created_at: String = row.get(10)?;
+
+        Ok(AuditArchiveSegment {
+            id: Id::parse_str(&id).unwrap_or_else(|_| Id::new_v4()),
+            first_sequence: row.get(1)?,
+            last_sequence: row.get(2)?,
+            entry_count: row.get(3)?,
+            earliest_timestamp: parse_time(&earliest).unwrap_or_default(),
+            latest_timestamp: parse_time(&latest).unwrap_or_default(),
+            previous_hash: row.get(6)?,
+            last_hash: row.get(7)?,
+            content_hash: row.get(8)?,
+            file_name: row.get(9)?,
+            created_at: parse_time(&created_at).unwrap_or_else(chrono::Utc::now),
+        })
+    }
+
+    /// All segments in chain order
+    pub fn list(&self) -> Result<Vec<AuditArchiveSegment>> {
+        let conn = self.db.reader()?;
+
+        let mut stmt = conn.prepare(&format!("{} ORDER BY first_sequence", SELECT_SEGMENT))?;
+        let segments = stmt
+            .query_map([], Self::row_to_segment)?
+            .collect::<rusqlite::Result<_>>()?;
+
+        Ok(segments)
+    }
+
+    pub fn find_by_id(&self, id: Id) -> Result<Option<AuditArchiveSegment>> {
+        let conn = self.db.reader()?;
+
+        let segment = conn
+            .query_row(
+                &format!("{} WHERE id = ?", SELECT_SEGMENT),
+                params![id.to_string()],
+                Self::row_to_segment,
+            )
+            .optional()?;
+
+        Ok(segment)
+    }
+
+    /// Record a written segment and remove its entries from `audit_logs`,
+    /// atomically. The segment must directly follow the last one recorded
+    /// and cover exactly the entries it claims.
+    pub fn record(&self, segment: &AuditArchiveSegment) -> Result<()> {
+        let conn = self.db.connection();
+        let mut conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        let tail: Option<(i64, String)> = tx
+            .query_row(
+                "SELECT last_sequence, last_hash FROM audit_archive_segments
+                 ORDER BY last_sequence DESC LIMIT 1",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        let (expected_first, expected_previous) = match tail {
+            Some((sequence, hash)) => (sequence + 1, Some(hash)),
+            None => (1, None),
+        };
+        if segment.first_sequence != expected_first || segment.previous_hash != expected_previous {
+            return Err(DbError::AuditArchive(format!(
+                "Segment starting at entry {} does not follow the archive, which ends at entry {}",
+                segment.first_sequence,
+                expected_first - 1,
+            )));
+        }
+
+        tx.execute(
+            r#"
+            INSERT INTO audit_archive_segments (
+                id, first_sequence, last_sequence, entry_count, earliest_timestamp,
+                latest_timestamp, previous_hash, last_hash, content_hash, file_name,
+                created_at
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
+            "#,
+            params![
+                segment.id.to_string(),
+                segment.first_sequence,
+                segment.last_sequence,
+                segment.entry_count,
+                segment.earliest_timestamp.to_rfc3339(),
+                segment.latest_timestamp.to_rfc3339(),
+                segment.previous_hash,
+                segment.last_hash,
+                segment.content_hash,
+                segment.file_name,
+                segment.created_at.to_rfc3339(),
+            ],
+        )?;
+
+        let removed = tx.execute(
+            "DELETE FROM audit_logs WHERE sequence BETWEEN ?1 AND ?2",
+            params![segment.first_sequence, segment.last_sequence],
+        )?;
+        if removed as i64 != segment.entry_count {
+            return Err(DbError::AuditArchive(format!(
+                "Segment covers {} entries but {} were in the audit log",
+                segment.entry_count, removed,
+            )));
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,403 @@
+//! Audit log repository
+
+use rusqlite::{params, OptionalExtension, Row, TransactionBehavior};
+use hedtronix_core::crdt::HybridTimestamp;
+use hedtronix_core::{AuditEventType, AuditLog, AuditLogFilters, Id};
+use crate::{Database, DbError, Result, AUDIT_SIGNING_START_KEY};
+
+pub struct AuditRepository {
+    db: Database,
+}
+
+fn parse_time(s: &str) -> Option<chrono::DateTime<chrono::Utc>> {
+    chrono::DateTime::parse_from_rfc3339(s)
+        .map(|dt| dt.with_timezone(&chrono::Utc))
+        .ok()
+}
+
+fn parse_event_type(s: &str) -> AuditEventType {
+    match s {
+        "CREATE" => AuditEventType::Create,
+        "UPDATE" => AuditEventType::Update,
+        "DELETE" => AuditEventType::Delete,
+        "LOGIN" => AuditEventType::Login,
+        "LOGOUT" => AuditEventType::Logout,
+        "EXPORT" => AuditEventType::Export,
+        "SYNC" => AuditEventType::Sync,
+        _ => AuditEventType::Read,
+    }
+}
+
+/// WHERE clause and bound values for a set of filters
+pub(crate) fn filter_clause(filters: &AuditLogFilters) -> (String, Vec<String>) {
+    let mut sql = String::from(" WHERE 1=1");
+    let mut values = Vec::new();
+
+    if let Some(user_id) = filters.user_id {
+        sql.push_str(" AND user_id = ?");
+        values.push(user_id.to_string());
+    }
+    if let Some(device_id) = filters.device_id {
+        sql.push_str(" AND device_id = ?");
+        values.push(device_id.to_string());
+    }
+    if let Some(entity_type) = &filters.entity_type {
+        sql.push_str(" AND entity_type = ?");
+        values.push(entity_type.clone());
+    }
+    if let Some(entity_id) = &filters.entity_id {
+        sql.push_str(" AND entity_id = ?");
+        values.push(entity_id.clone());
+    }
+    if let Some(event_types) = filters.event_types.as_ref().filter(|t| !t.is_empty()) {
+        let placeholders = vec!["?"; event_types.len()].join(", ");
+        sql.push_str(&format!(" AND event_type IN ({})", placeholders));
+        values.extend(event_types.iter().map(|t| t.as_str().to_string()));
+    }
+    // Timestamps are stored as RFC 3339 in UTC, so they compare as text
+    if let Some(start) = filters.start_time {
+        sql.push_str(" AND timestamp >= ?");
+        values.push(start.to_rfc3339());
+    }
+    if let Some(end) = filters.end_time {
+        sql.push_str(" AND timestamp < ?");
+        values.push(end.to_rfc3339());
+    }
+
+    (sql, values)
+}
+
+const SELECT_LOG: &str = r#"
+    SELECT id, event_type, user_id, device_id, entity_type, entity_id,
+           changes_json, ip_address, user_agent, timestamp, signature,
+           previous_hash, hash, break_glass_grant_id, sequence, hlc
+    FROM audit_logs
+"#;
+
+impl AuditRepository {
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+
+    /// Append an entry to the end of the hash chain and return it as
+    /// stored: linked to the previous entry, re-hashed and signed with the
+    /// database's audit key when one is configured.
+    ///
+    /// The tail is read and the entry written in one immediate transaction,
+    /// so concurrent writers, including other connections to the same file,
+    /// cannot both link to the same predecessor.
+    pub fn append(&self, log: &AuditLog) -> Result<AuditLog> {
+        let mut log = log.clone();
+        let signer = self.db.audit_signer();
+
+        let conn = self.db.connection();
+        let mut conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        let tail = tx.query_row(
+            "SELECT sequence, hash, hlc FROM audit_logs ORDER BY sequence DESC LIMIT 1",
+            [],
+            |row| {
+                let hlc: Option<String> = row.get(2)?;
+                Ok((row.get(0)?, row.get(1)?, hlc.and_then(|h| h.parse::<HybridTimestamp>().ok())))
+            },
+        ).optional()?;
+
+        log.chain_after(tail);
+        log.signature = match &signer {
+            Some(signer) => signer.sign(&log)?,
+            None => String::new(),
+        };
+        if signer.is_some() {
+            // Remember where signing began; every later entry must be signed
+            tx.execute(
+                "INSERT OR IGNORE INTO sync_metadata (key, value, updated_at) VALUES (?, ?, ?)",
+                params![AUDIT_SIGNING_START_KEY, log.sequence.to_string(), log.timestamp.to_rfc3339()],
+            )?;
+        }
+
+        tx.execute(
+            r#"
+            INSERT INTO audit_logs (
+                id, event_type, user_id, device_id, entity_type, entity_id,
+                changes_json, ip_address, user_agent, timestamp, signature,
+                previous_hash, hash, break_glass_grant_id, sequence, hlc
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+            params![
+                log.id.to_string(),
+                log.event_type.as_str(),
+                log.user_id.map(|id| id.to_string()),
+                log.device_id.map(|id| id.to_string()),
+                log.entity_type,
+                log.entity_id,
+                log.changes.to_string(),
+                log.ip_address,
+                log.user_agent,
+                log.timestamp.to_rfc3339(),
+                log.signature,
+                log.previous_hash,
+                log.hash,
+                log.break_glass_grant_id.map(|id| id.to_string()),
+                log.sequence,
+                log.hlc.map(|hlc| hlc.to_string()),
+            ],
+        )?;
+
+        tx.commit()?;
+        Ok(log)
+    }
+
+    /// Entries with a sequence number of at least `from`, in chain order
+    pub fn find_range(&self, from: i64, limit: u32) -> Result<Vec<AuditLog>> {
+        let conn = self.db.reader()?;
+
+        let mut stmt = conn.prepare(
+            &format!("{} WHERE sequence >= ? ORDER BY sequence LIMIT ?", SELECT_LOG)
+        )?;
+
+        let logs = stmt
+            .query_map(params![from, limit], Self::row_to_log)?
+            .collect::<rusqlite::Result<_>>()?;
+
+        Ok(logs)
+    }
+
+    /// Map a row selected in `SELECT_LOG` column order (shared with the
+    /// device audit tables)
+    pub(crate) fn row_to_log(row: &Row) -> rusqlite::Result<AuditLog> {
+        let id: String = row.get(0)?;
+        let event_type: String = row.get(1)?;
+        let user_id: Option<String> = row.get(2)?;
+        let device_id: Option<String> = row.get(3)?;
+        let changes_json: String = row.get(6)?;
+        let timestamp: String = row.get(9)?;
+        let grant_id: Option<String> = row.get(13)?;
+        let hlc: Option<String> = row.get(15)?;
+
+        Ok(AuditLog {
+            id: Id::parse_str(&id).unwrap_or_else(|_| Id::new_v4()),
+            event_type: parse_event_type(&event_type),
+            user_id: user_id.and_then(|s| Id::parse_str(&s).ok()),
+            device_id: device_id.and_then(|s| Id::parse_str(&s).ok()),
+            entity_type: row.get(4)?,
+            entity_id: row.get(5)?,
+            changes: serde_json::from_str(&changes_json).unwrap_or_default(),
+            ip_address: row.get(7)?,
+            user_agent: row.get(8)?,
+            timestamp: parse_time(&timestamp).unwrap_or_else(chrono::Utc::now),
+            signature: row.get(10)?,
+            previous_hash: row.get(11)?,
+            hash: row.get(12)?,
+            break_glass_grant_id: grant_id.and_then(|s| Id::parse_str(&s).ok()),
+            sequence: row.get(14)?,
+            hlc: hlc.and_then(|h| h.parse().ok()),
+        })
+    }
+
+    /// Entries matching the filters, newest first, one page at a time
+    pub fn search(&self, filters: &AuditLogFilters) -> Result<Vec<AuditLog>> {
+        let conn = self.db.reader()?;
+
+        let (clause, values) = filter_clause(filters);
+        let sql = format!(
+            "{}{} ORDER BY sequence DESC LIMIT {} OFFSET {}",
+            SELECT_LOG,
+            clause,
+            filters.limit,
+            filters.page as u64 * filters.limit as u64,
+        );
+        let mut stmt = conn.prepare(&sql)?;
+
+        let logs = stmt
+            .query_map(rusqlite::params_from_iter(values), Self::row_to_log)?
+            .collect::<rusqlite::Result<_>>()?;
+
+        Ok(logs)
+    }
+
+    /// Number of entries matching the filters, ignoring pagination
+    pub fn count(&self, filters: &AuditLogFilters) -> Result<i64> {
+        let conn = self.db.reader()?;
+
+        let (clause, values) = filter_clause(filters);
+        let count = conn.query_row(
+            &format!("SELECT COUNT(*) FROM audit_logs{}", clause),
+            rusqlite::params_from_iter(values),
+            |row| row.get(0),
+        )?;
+
+        Ok(count)
+    }
+
+    /// Distinct entities touched per UTC day by the entries matching the
+    /// filters, for days with any, oldest first
+    pub fn daily_distinct_entities(&self, filters: &AuditLogFilters) -> Result<Vec<(chrono::NaiveDate, i64)>> {
+        let conn = self.db.reader()?;
+
+        let (clause, values) = filter_clause(filters);
+        let mut stmt = conn.prepare(&format!(
+            "SELECT substr(timestamp, 1, 10) AS day, COUNT(DISTINCT entity_id) FROM audit_logs{} GROUP BY day ORDER BY day",
+            clause
+        ))?;
+
+        let rows = stmt
+            .query_map(rusqlite::params_from_iter(values), |row| {
+                let day: String = row.get(0)?;
+                Ok((day, row.get(1)?))
+            })?
+            .collect::<rusqlite::Result<Vec<(String, i64)>>>()?;
+        let days = rows
+            .into_iter()
+            .filter_map(|(day, count)| {
+                chrono::NaiveDate::parse_from_str(&day, "%Y-%m-%d").ok().map(|d| (d, count))
+            })
+            .collect();
+
+        Ok(days)
+    }
+
+    pub fn find_by_id(&self, id: Id) -> Result<Option<AuditLog>> {
+        let conn = self.db.reader()?;
+
+        let log = conn.query_row(
+            &format!("{} WHERE id = ?", SELECT_LOG),
+            [id.to_string()],
+            Self::row_to_log,
+        ).optional()?;
+
+        Ok(log)
+    }
+
+    /// Every action taken under an emergency access grant, oldest first
+    pub fn find_by_break_glass_grant(&self, grant_id: Id) -> Result<Vec<AuditLog>> {
+        let conn = self.db.reader()?;
+
+        let mut stmt = conn.prepare(
+            &format!("{} WHERE break_glass_grant_id = ? ORDER BY sequence", SELECT_LOG)
+        )?;
+
+        let logs = stmt
+            .query_map([grant_id.to_string()], Self::row_to_log)?
+            .collect::<rusqlite::Result<_>>()?;
+
+        Ok(logs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AuditSigner;
+
+    fn database() -> Database {
+        let mut db = Database::in_memory().unwrap();
+        db.initialize().unwrap();
+        db
+    }
+
+    fn entry(n: usize) -> AuditLog {
+        AuditLog::new(
+            AuditEventType::Read,
+            None,
+            None,
+            "Patient".to_string(),
+            n.to_string(),
+            serde_json::json!({ "n": n }),
+        )
+    }
+
+    #[test]
+    fn test_append_links_and_signs() {
+        let mut db = database();
+        db.set_audit_signer(AuditSigner::generate().unwrap());
+        let repo = AuditRepository::new(db.clone());
+
+        let first = repo.append(&entry(1)).unwrap();
+        let second = repo.append(&entry(2)).unwrap();
+        assert_eq!(second.previous_hash.as_deref(), Some(first.hash.as_str()));
+
+        let stored = repo.find_range(1, 10).unwrap();
+        assert_eq!(stored.len(), 2);
+
+        let signer = db.audit_signer().unwrap();
+        for log in &stored {
+            assert!(log.verify_hash());
+            assert!(signer.verify(log));
+        }
+
+        let mut forged = stored[1].clone();
+        forged.entity_id = "other".to_string();
+        forged.hash = forged.compute_hash();
+        assert!(!signer.verify(&forged));
+    }
+
+    #[test]
+    fn test_concurrent_appends_form_one_chain() {
+        let db = database();
+
+        let handles: Vec<_> = (0..4)
+            .map(|t| {
+                let repo = AuditRepository::new(db.clone());
+                std::thread::spawn(move || {
+                    for i in 0..25 {
+                        repo.append(&entry(t * 100 + i)).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let chain = AuditRepository::new(db).find_range(1, 1000).unwrap();
+        assert_eq!(chain.len(), 100);
+        assert!(chain[0].previous_hash.is_none());
+        for (i, pair) in chain.windows(2).enumerate() {
+            assert_eq!(pair[1].sequence, i as i64 + 2);
+            assert_eq!(pair[1].previous_hash.as_deref(), Some(pair[0].hash.as_str()));
+            assert!(pair[1].verify_hash());
+        }
+    }
+
+    #[test]
+    fn test_search_filters() {
+        let repo = AuditRepository::new(database());
+        for n in 0..5 {
+            repo.append(&entry(n)).unwrap();
+        }
+        let mut update = entry(3);
+        update.event_type = AuditEventType::Update;
+        repo.append(&update).unwrap();
+
+        let filters = AuditLogFilters {
+            entity_id: Some("3".to_string()),
+            limit: 10,
+            ..Default::default()
+        };
+        assert_eq!(repo.search(&filters).unwrap().len(), 2);
+        assert_eq!(repo.count(&filters).unwrap(), 2);
+
+        let filters = AuditLogFilters {
+            event_types: Some(vec![AuditEventType::Update, AuditEventType::Delete]),
+            limit: 10,
+            ..Default::default()
+        };
+        let found = repo.search(&filters).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].sequence, 6);
+
+        // Newest first, paged
+        let filters = AuditLogFilters { page: 1, limit: 2, ..Default::default() };
+        let page: Vec<i64> = repo.search(&filters).unwrap().iter().map(|l| l.sequence).collect();
+        assert_eq!(page, vec![4, 3]);
+        assert_eq!(repo.count(&filters).unwrap(), 6);
+
+        let filters = AuditLogFilters {
+            start_time: Some(chrono::Utc::now() + chrono::Duration::minutes(1)),
+            limit: 10,
+            ..Default::default()
+        };
+        assert!(repo.search(&filters).unwrap().is_empty());
+    }
+}
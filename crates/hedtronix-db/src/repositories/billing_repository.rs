@@ -1,9 +1,8 @@
 //! Billing repository
 
-use hedtronix_core::{BillingEntry, BillingStatus, Id, Money};
+use hedtronix_core::{BillingEntry, BillingStatus, Id};
 use crate::{Database, DbError, Result};
 use rusqlite::{params, Row};
-use std::sync::Arc;
 
 pub struct BillingRepository {
     db: Database,
@@ -44,8 +43,7 @@ impl BillingRepository {
     }
 
     pub fn find_by_id(&self, id: Id) -> Result<Option<BillingEntry>> {
-        let conn = self.db.connection();
-        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+        let conn = self.db.reader()?;
 
         let mut stmt = conn.prepare(
             r#"
@@ -83,9 +81,30 @@ impl BillingRepository {
         Ok(())
     }
     
+    pub fn find_by_patient(&self, patient_id: Id) -> Result<Vec<BillingEntry>> {
+        let conn = self.db.reader()?;
+
+        let mut stmt = conn.prepare(
+            r#"
+            SELECT id, patient_id, encounter_id, provider_id, cpt_code,
+                   description, unit_price, total_amount, status, created_at, updated_at
+            FROM billing_entries
+            WHERE patient_id = ?
+            ORDER BY created_at DESC
+            "#,
+        )?;
+
+        let entries = stmt.query_map([patient_id.to_string()], |row| {
+            Ok(map_row_to_billing(row))
+        })?
+        .filter_map(|r| r.ok())
+        .collect();
+
+        Ok(entries)
+    }
+
     pub fn find_all(&self) -> Result<Vec<BillingEntry>> {
-        let conn = self.db.connection();
-        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+        let conn = self.db.reader()?;
 
         let mut stmt = conn.prepare(
             r#"
@@ -0,0 +1,64 @@
+//! Care team repository: who is involved in a patient's care
+
+use std::collections::HashSet;
+
+use rusqlite::{params, OptionalExtension};
+use hedtronix_core::{Id, PatientCareTeam};
+use crate::{Database, Result};
+
+pub struct CareTeamRepository {
+    db: Database,
+}
+
+impl CareTeamRepository {
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+
+    /// Care relationships for a patient, or `None` if the patient does not exist
+    pub fn find_for_patient(&self, patient_id: Id) -> Result<Option<PatientCareTeam>> {
+        let conn = self.db.reader()?;
+        let pid = patient_id.to_string();
+
+        let pcp: Option<Option<String>> = conn.query_row(
+            "SELECT primary_care_physician_id FROM patients WHERE id = ?",
+            [&pid],
+            |row| row.get(0),
+        ).optional()?;
+        let Some(pcp) = pcp else {
+            return Ok(None);
+        };
+
+        let parse_ids = |sql: &str| -> Result<HashSet<Id>> {
+            let mut stmt = conn.prepare(sql)?;
+            let ids = stmt
+                .query_map(params![pid], |row| row.get::<_, Option<String>>(0))?
+                .filter_map(|r| r.ok().flatten())
+                .filter_map(|s| Id::parse_str(&s).ok())
+                .collect();
+            Ok(ids)
+        };
+
+        let mut provider_ids = parse_ids("SELECT provider_id FROM encounters WHERE patient_id = ?1")?;
+        provider_ids.extend(parse_ids("SELECT provider_id FROM appointments WHERE patient_id = ?1")?);
+        provider_ids.extend(parse_ids("SELECT author_id FROM clinical_notes WHERE patient_id = ?1")?);
+
+        let mut department_ids = parse_ids(
+            "SELECT department_id FROM encounters WHERE patient_id = ?1 AND department_id IS NOT NULL"
+        )?;
+        department_ids.extend(parse_ids(
+            r#"
+            SELECT u.department_id FROM patients p
+            JOIN users u ON u.id = p.primary_care_physician_id
+            WHERE p.id = ?1
+            "#
+        )?);
+
+        Ok(Some(PatientCareTeam {
+            patient_id,
+            primary_care_physician_id: pcp.and_then(|s| Id::parse_str(&s).ok()),
+            provider_ids,
+            department_ids,
+        }))
+    }
+}
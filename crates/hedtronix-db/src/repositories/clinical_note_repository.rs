@@ -1,27 +1,27 @@
 //! Clinical Note repository
 
-use hedtronix_core::{ClinicalNote, Id, NoteType, NoteStatus, SoapSection, SoapItem, SignatureData};
+use hedtronix_core::{ClinicalNote, Id, NoteType, NoteStatus};
 use crate::{Database, DbError, Result};
 use rusqlite::{params, Row};
+use hedtronix_crypto::{FieldContext, Keyring};
 use std::sync::Arc;
-use hedtronix_crypto::{encrypt_field, decrypt_field};
 
 pub struct ClinicalNoteRepository {
     db: Database,
-    encryption_key: Vec<u8>,
+    keyring: Arc<Keyring>,
 }
 
 impl ClinicalNoteRepository {
-    pub fn new(db: Database, encryption_key: Vec<u8>) -> Self {
-        Self { db, encryption_key }
+    pub fn new(db: Database, keyring: Arc<Keyring>) -> Self {
+        Self { db, keyring }
     }
 
     pub fn create(&self, note: &ClinicalNote) -> Result<()> {
         let conn = self.db.connection();
         let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
 
-        let key = &self.encryption_key;
-        let content_enc = encrypt_field(&note.content, key)
+        let keyring = &self.keyring;
+        let content_enc = keyring.encrypt(&note.content, &FieldContext::new("clinical_notes", "content", &note.id.to_string()))
             .map_err(|e| DbError::Serialization(format!("Encryption failed: {}", e)))?;
 
         conn.execute(
@@ -48,8 +48,7 @@ impl ClinicalNoteRepository {
     }
 
     pub fn find_by_id(&self, id: Id) -> Result<Option<ClinicalNote>> {
-        let conn = self.db.connection();
-        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+        let conn = self.db.reader()?;
 
         let mut stmt = conn.prepare(
             r#"
@@ -60,9 +59,9 @@ impl ClinicalNoteRepository {
             "#,
         )?;
 
-        let key = &self.encryption_key;
+        let keyring = &self.keyring;
         let note = stmt.query_row([id.to_string()], |row| {
-             Self::map_row_to_note(row, key)
+             Self::map_row_to_note(row, keyring)
         }).ok();
 
         Ok(note)
@@ -72,8 +71,8 @@ impl ClinicalNoteRepository {
         let conn = self.db.connection();
         let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
 
-        let key = &self.encryption_key;
-        let content_enc = encrypt_field(&note.content, key)
+        let keyring = &self.keyring;
+        let content_enc = keyring.encrypt(&note.content, &FieldContext::new("clinical_notes", "content", &note.id.to_string()))
             .map_err(|e| DbError::Serialization(format!("Encryption failed: {}", e)))?;
 
         conn.execute(
@@ -94,8 +93,7 @@ impl ClinicalNoteRepository {
     }
     
     pub fn find_by_patient(&self, patient_id: Id) -> Result<Vec<ClinicalNote>> {
-        let conn = self.db.connection();
-        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+        let conn = self.db.reader()?;
 
         let mut stmt = conn.prepare(
             r#"
@@ -107,9 +105,9 @@ impl ClinicalNoteRepository {
             "#,
         )?;
 
-        let key = &self.encryption_key;
+        let keyring = &self.keyring;
         let notes = stmt.query_map([patient_id.to_string()], |row| {
-            Self::map_row_to_note(row, key)
+            Self::map_row_to_note(row, keyring)
         })?
         .filter_map(|r| r.ok())
         .collect();
@@ -117,7 +115,7 @@ impl ClinicalNoteRepository {
         Ok(notes)
     }
 
-    fn map_row_to_note(row: &Row, key: &[u8]) -> rusqlite::Result<ClinicalNote> {
+    fn map_row_to_note(row: &Row, keyring: &Keyring) -> rusqlite::Result<ClinicalNote> {
         let id: String = row.get(0)?;
         let patient_id: String = row.get(1)?;
         let author_id: String = row.get(2)?;
@@ -131,7 +129,7 @@ impl ClinicalNoteRepository {
         let content = if content_enc.is_empty() {
              String::new()
         } else {
-             decrypt_field(&content_enc, key).unwrap_or_else(|_| "[Decryption Failed]".to_string())
+             keyring.decrypt(&content_enc, &FieldContext::new("clinical_notes", "content", &id)).unwrap_or_else(|_| "[Decryption Failed]".to_string())
         };
 
         let nt = match note_type.to_uppercase().as_str() {
@@ -177,3 +175,4 @@ impl ClinicalNoteRepository {
     }
 
 
+}
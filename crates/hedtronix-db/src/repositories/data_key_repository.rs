@@ -0,0 +1,363 @@
+//! Data encryption key and re-encryption job repository
+
+use rusqlite::{params, OptionalExtension, Row, TransactionBehavior};
+use hedtronix_core::{Id, Timestamp};
+use serde::{Deserialize, Serialize};
+
+use crate::{Database, DbError, Result};
+
+pub struct DataKeyRepository {
+    db: Database,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum DataKeyStatus {
+    /// Encrypts new values
+    Active,
+    /// Only decrypts values not yet re-encrypted
+    Retired,
+}
+
+impl DataKeyStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DataKeyStatus::Active => "ACTIVE",
+            DataKeyStatus::Retired => "RETIRED",
+        }
+    }
+}
+
+/// A stored data encryption key, still wrapped
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DataKeyRecord {
+    pub id: String,
+    #[serde(skip_serializing)]
+    pub wrapped_key: String,
+    /// Id of the key encryption key that wrapped it
+    pub kek_id: String,
+    pub status: DataKeyStatus,
+    pub created_at: Timestamp,
+    pub retired_at: Option<Timestamp>,
+}
+
+/// The stored blind index key, still wrapped
+#[derive(Debug, Clone)]
+pub struct BlindIndexKeyRecord {
+    pub id: String,
+    pub wrapped_key: String,
+    pub kek_id: String,
+    pub created_at: Timestamp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ReencryptionStatus {
+    Running,
+    Completed,
+    Failed,
+}
+
+impl ReencryptionStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReencryptionStatus::Running => "RUNNING",
+            ReencryptionStatus::Completed => "COMPLETED",
+            ReencryptionStatus::Failed => "FAILED",
+        }
+    }
+}
+
+/// Progress of moving encrypted rows to a data key
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReencryptionJob {
+    pub id: Id,
+    pub target_key_id: String,
+    pub status: ReencryptionStatus,
+    /// Rows needing re-encryption when the job started
+    pub total_rows: i64,
+    pub processed_rows: i64,
+    pub error: Option<String>,
+    pub started_at: Timestamp,
+    pub updated_at: Timestamp,
+    pub completed_at: Option<Timestamp>,
+}
+
+impl ReencryptionJob {
+    pub fn new(target_key_id: String, total_rows: i64) -> Self {
+        let now = chrono::Utc::now();
+        Self {
+            id: Id::new_v4(),
+            target_key_id,
+            status: ReencryptionStatus::Running,
+            total_rows,
+            processed_rows: 0,
+            error: None,
+            started_at: now,
+            updated_at: now,
+            completed_at: None,
+        }
+    }
+
+    /// Fraction of rows done, from 0 to 1
+    pub fn progress(&self) -> f64 {
+        if self.total_rows <= 0 {
+            return 1.0;
+        }
+        (self.processed_rows as f64 / self.total_rows as f64).min(1.0)
+    }
+}
+
+fn parse_time(s: &str) -> Option<chrono::DateTime<chrono::Utc>> {
+    chrono::DateTime::parse_from_rfc3339(s)
+        .map(|dt| dt.with_timezone(&chrono::Utc))
+        .ok()
+}
+
+fn parse_key_status(s: &str) -> DataKeyStatus {
+    match s {
+        "ACTIVE" => DataKeyStatus::Active,
+        _ => DataKeyStatus::Retired,
+    }
+}
+
+fn parse_job_status(s: &str) -> ReencryptionStatus {
+    match s {
+        "RUNNING" => ReencryptionStatus::Running,
+        "COMPLETED" => ReencryptionStatus::Completed,
+        _ => ReencryptionStatus::Failed,
+    }
+}
+
+const SELECT_KEY: &str = r#"
+    SELECT id, wrapped_key, kek_id, status, created_at, retired_at
+    FROM data_keys
+"#;
+
+const SELECT_JOB: &str = r#"
+    SELECT id, target_key_id, status, total_rows, processed_rows, error,
+           started_at, updated_at, completed_at
+    FROM reencryption_jobs
+"#;
+
+impl DataKeyRepository {
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+
+    fn row_to_key(row: &Row) -> rusqlite::Result<DataKeyRecord> {
+        let status: String = row.get(3)?;
+        let created_at: String = row.get(4)?;
+        let retired_at: Option<String> = row.get(5)?;
+
+        Ok(DataKeyRecord {
+            id: row.get(0)?,
+            wrapped_key: row.get(1)?,
+            kek_id: row.get(2)?,
+            status: parse_key_status(&status),
+            created_at: parse_time(&created_at).unwrap_or_default(),
+            retired_at: retired_at.as_deref().and_then(parse_time),
+        })
+    }
+
+    fn row_to_job(row: &Row) -> rusqlite::Result<ReencryptionJob> {
+        let id: String = row.get(0)?;
+        let status: String = row.get(2)?;
+        let started_at: String = row.get(6)?;
+        let updated_at: String = row.get(7)?;
+        let completed_at: Option<String> = row.get(8)?;
+
+        Ok(ReencryptionJob {
+            id: Id::parse_str(&id).unwrap_or_else(|_| Id::new_v4()),
+            target_key_id: row.get(1)?,
+            status: parse_job_status(&status),
+            total_rows: row.get(3)?,
+            processed_rows: row.get(4)?,
+            error: row.get(5)?,
+            started_at: parse_time(&started_at).unwrap_or_default(),
+            updated_at: parse_time(&updated_at).unwrap_or_default(),
+            completed_at: completed_at.as_deref().and_then(parse_time),
+        })
+    }
+
+    /// Every data key, oldest first
+    pub fn list(&self) -> Result<Vec<DataKeyRecord>> {
+        let conn = self.db.reader()?;
+
+        let mut stmt = conn.prepare(&format!("{} ORDER BY created_at", SELECT_KEY))?;
+        let keys = stmt
+            .query_map([], Self::row_to_key)?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        Ok(keys)
+    }
+
+    /// Store a new key as the active one, retiring the previous active key
+    pub fn insert_active(&self, key: &DataKeyRecord) -> Result<()> {
+        let conn = self.db.connection();
+        let mut conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        tx.execute(
+            "UPDATE data_keys SET status = 'RETIRED', retired_at = ? WHERE status = 'ACTIVE'",
+            params![key.created_at.to_rfc3339()],
+        )?;
+        tx.execute(
+            r#"
+            INSERT INTO data_keys (id, wrapped_key, kek_id, status, created_at, retired_at)
+            VALUES (?, ?, ?, 'ACTIVE', ?, NULL)
+            "#,
+            params![key.id, key.wrapped_key, key.kek_id, key.created_at.to_rfc3339()],
+        )?;
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Replace a key's wrapping, e.g. after the key encryption key changed
+    pub fn update_wrapping(&self, id: &str, wrapped_key: &str, kek_id: &str) -> Result<()> {
+        let conn = self.db.connection();
+        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+
+        conn.execute(
+            "UPDATE data_keys SET wrapped_key = ?, kek_id = ? WHERE id = ?",
+            params![wrapped_key, kek_id, id],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn find_index_key(&self) -> Result<Option<BlindIndexKeyRecord>> {
+        let conn = self.db.reader()?;
+
+        let key = conn
+            .query_row(
+                "SELECT id, wrapped_key, kek_id, created_at FROM blind_index_keys ORDER BY created_at LIMIT 1",
+                [],
+                |row| {
+                    let created_at: String = row.get(3)?;
+                    Ok(BlindIndexKeyRecord {
+                        id: row.get(0)?,
+                        wrapped_key: row.get(1)?,
+                        kek_id: row.get(2)?,
+                        created_at: parse_time(&created_at).unwrap_or_default(),
+                    })
+                },
+            )
+            .optional()?;
+
+        Ok(key)
+    }
+
+    pub fn insert_index_key(&self, key: &BlindIndexKeyRecord) -> Result<()> {
+        let conn = self.db.connection();
+        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+
+        conn.execute(
+            "INSERT INTO blind_index_keys (id, wrapped_key, kek_id, created_at) VALUES (?, ?, ?, ?)",
+            params![key.id, key.wrapped_key, key.kek_id, key.created_at.to_rfc3339()],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn update_index_key_wrapping(&self, id: &str, wrapped_key: &str, kek_id: &str) -> Result<()> {
+        let conn = self.db.connection();
+        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+
+        conn.execute(
+            "UPDATE blind_index_keys SET wrapped_key = ?, kek_id = ? WHERE id = ?",
+            params![wrapped_key, kek_id, id],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn create_job(&self, job: &ReencryptionJob) -> Result<()> {
+        let conn = self.db.connection();
+        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+
+        conn.execute(
+            r#"
+            INSERT INTO reencryption_jobs (
+                id, target_key_id, status, total_rows, processed_rows, error,
+                started_at, updated_at, completed_at
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+            params![
+                job.id.to_string(),
+                job.target_key_id,
+                job.status.as_str(),
+                job.total_rows,
+                job.processed_rows,
+                job.error,
+                job.started_at.to_rfc3339(),
+                job.updated_at.to_rfc3339(),
+                job.completed_at.map(|t| t.to_rfc3339()),
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Record a job's progress and outcome
+    pub fn update_job(&self, job: &ReencryptionJob) -> Result<()> {
+        let conn = self.db.connection();
+        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+
+        conn.execute(
+            r#"
+            UPDATE reencryption_jobs
+            SET status = ?, processed_rows = ?, error = ?, updated_at = ?, completed_at = ?
+            WHERE id = ?
+            "#,
+            params![
+                job.status.as_str(),
+                job.processed_rows,
+                job.error,
+                job.updated_at.to_rfc3339(),
+                job.completed_at.map(|t| t.to_rfc3339()),
+                job.id.to_string(),
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn find_job(&self, id: Id) -> Result<Option<ReencryptionJob>> {
+        let conn = self.db.reader()?;
+
+        let job = conn
+            .query_row(&format!("{} WHERE id = ?", SELECT_JOB), [id.to_string()], Self::row_to_job)
+            .optional()?;
+
+        Ok(job)
+    }
+
+    /// Jobs newest first
+    pub fn list_jobs(&self, limit: u32) -> Result<Vec<ReencryptionJob>> {
+        let conn = self.db.reader()?;
+
+        let mut stmt = conn.prepare(&format!("{} ORDER BY started_at DESC LIMIT ?", SELECT_JOB))?;
+        let jobs = stmt
+            .query_map([limit], Self::row_to_job)?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        Ok(jobs)
+    }
+
+    /// Jobs that were still running, e.g. when the server stopped
+    pub fn running_jobs(&self) -> Result<Vec<ReencryptionJob>> {
+        let conn = self.db.reader()?;
+
+        let mut stmt = conn.prepare(&format!("{} WHERE status = 'RUNNING' ORDER BY started_at", SELECT_JOB))?;
+        let jobs = stmt
+            .query_map([], Self::row_to_job)?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        Ok(jobs)
+    }
+}
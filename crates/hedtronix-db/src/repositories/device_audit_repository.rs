@@ -0,0 +1,410 @@
+//! Audit entries replicated from devices
+//!
+//! Each device keeps its own hash-chained audit log, signed with the key it
+//! registered, and pushes it here. The copies are append-only: an entry is
+//! stored only if the pushing device recorded and signed it and it extends
+//! the device's sub-chain, and a stored entry is never replaced.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use rusqlite::{params, OptionalExtension, TransactionBehavior};
+use hedtronix_core::crdt::HybridTimestamp;
+use hedtronix_core::{AuditLog, AuditLogFilters, Device, Id};
+use serde::{Deserialize, Serialize};
+
+use super::audit_repository::filter_clause;
+use crate::{AuditRepository, AuditSigner, Database, DbError, Result};
+
+pub struct DeviceAuditRepository {
+    db: Database,
+}
+
+/// A pushed entry that was not stored
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RejectedAuditEntry {
+    pub sequence: i64,
+    pub reason: String,
+}
+
+/// Outcome of receiving a batch of a device's audit entries
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceAuditReceipt {
+    /// Last sequence number held for the device; the device can stop
+    /// resending entries up to here
+    pub stored_through: i64,
+    pub accepted: usize,
+    pub rejected: Vec<RejectedAuditEntry>,
+    /// Latest HLC time among the accepted entries
+    pub latest_hlc: Option<HybridTimestamp>,
+}
+
+/// An entry in the merged view, with the device whose chain it came from
+/// (`None` for the server's own chain)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplicatedAuditLog {
+    pub origin_device_id: Option<Id>,
+    #[serde(flatten)]
+    pub entry: AuditLog,
+}
+
+const SELECT_DEVICE_LOG: &str = r#"
+    SELECT id, event_type, user_id, device_id, entity_type, entity_id,
+           changes_json, ip_address, user_agent, timestamp, signature,
+           previous_hash, hash, break_glass_grant_id, sequence, hlc,
+           origin_device_id
+    FROM device_audit_logs
+"#;
+
+/// The server's own entries, in the same columns as `SELECT_DEVICE_LOG`
+const SELECT_SERVER_LOG: &str = r#"
+    SELECT id, event_type, user_id, device_id, entity_type, entity_id,
+           changes_json, ip_address, user_agent, timestamp, signature,
+           previous_hash, hash, break_glass_grant_id, sequence, hlc,
+           NULL AS origin_device_id
+    FROM audit_logs
+"#;
+
+impl DeviceAuditRepository {
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+
+    /// Store the entries that extend the device's sub-chain. Entries already
+    /// held are acknowledged again; anything that would fork, skip or
+    /// rewrite the sub-chain, or that the device did not record and sign,
+    /// is rejected along with every entry after it.
+    pub fn receive(&self, device: &Device, entries: &[AuditLog]) -> Result<DeviceAuditReceipt> {
+        let device_id = device.id;
+        let public_key = BASE64
+            .decode(&device.public_key)
+            .map_err(|_| DbError::AuditKey("Device public key is not base64".to_string()))?;
+        let mut entries: Vec<&AuditLog> = entries.iter().collect();
+        entries.sort_by_key(|e| e.sequence);
+
+        let conn = self.db.connection();
+        let mut conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        let mut tail: Option<(i64, String)> = tx.query_row(
+            r#"
+            SELECT sequence, hash FROM device_audit_logs
+            WHERE origin_device_id = ?
+            ORDER BY sequence DESC LIMIT 1
+            "#,
+            [device_id.to_string()],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        ).optional()?;
+
+        let mut receipt = DeviceAuditReceipt {
+            stored_through: 0,
+            accepted: 0,
+            rejected: Vec::new(),
+            latest_hlc: None,
+        };
+        let mut halted = false;
+
+        for entry in entries {
+            if halted {
+                receipt.rejected.push(RejectedAuditEntry {
+                    sequence: entry.sequence,
+                    reason: "Follows a rejected entry".to_string(),
+                });
+                continue;
+            }
+
+            let tail_sequence = tail.as_ref().map(|t| t.0).unwrap_or(0);
+            if entry.sequence <= tail_sequence {
+                let stored: Option<String> = tx.query_row(
+                    "SELECT hash FROM device_audit_logs WHERE origin_device_id = ? AND sequence = ?",
+                    params![device_id.to_string(), entry.sequence],
+                    |row| row.get(0),
+                ).optional()?;
+                if stored.as_deref() != Some(entry.hash.as_str()) {
+                    receipt.rejected.push(RejectedAuditEntry {
+                        sequence: entry.sequence,
+                        reason: "Differs from the stored entry; audit entries cannot be replaced".to_string(),
+                    });
+                }
+                continue;
+            }
+
+            if let Some(reason) = check_origin(entry, device_id, &public_key)
+                .or_else(|| check_extends(entry, tail.as_ref()))
+            {
+                receipt.rejected.push(RejectedAuditEntry { sequence: entry.sequence, reason });
+                halted = true;
+                continue;
+            }
+
+            tx.execute(
+                r#"
+                INSERT INTO device_audit_logs (
+                    origin_device_id, sequence, id, event_type, user_id, device_id,
+                    entity_type, entity_id, changes_json, ip_address, user_agent,
+                    timestamp, signature, previous_hash, hash, break_glass_grant_id,
+                    hlc, received_at
+                ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                "#,
+                params![
+                    device_id.to_string(),
+                    entry.sequence,
+                    entry.id.to_string(),
+                    entry.event_type.as_str(),
+                    entry.user_id.map(|id| id.to_string()),
+                    entry.device_id.map(|id| id.to_string()),
+                    entry.entity_type,
+                    entry.entity_id,
+                    entry.changes.to_string(),
+                    entry.ip_address,
+                    entry.user_agent,
+                    entry.timestamp.to_rfc3339(),
+                    entry.signature,
+                    entry.previous_hash,
+                    entry.hash,
+                    entry.break_glass_grant_id.map(|id| id.to_string()),
+                    entry.hlc.map(|hlc| hlc.to_string()),
+                    chrono::Utc::now().to_rfc3339(),
+                ],
+            )?;
+
+            receipt.accepted += 1;
+            receipt.latest_hlc = receipt.latest_hlc.max(entry.hlc);
+            tail = Some((entry.sequence, entry.hash.clone()));
+        }
+
+        tx.commit()?;
+        receipt.stored_through = tail.map(|t| t.0).unwrap_or(0);
+        Ok(receipt)
+    }
+
+    /// A device's entries from sequence `from` on, in chain order
+    pub fn find_range(&self, device_id: Id, from: i64, limit: u32) -> Result<Vec<AuditLog>> {
+        let conn = self.db.reader()?;
+
+        let mut stmt = conn.prepare(&format!(
+            "{} WHERE origin_device_id = ? AND sequence >= ? ORDER BY sequence LIMIT ?",
+            SELECT_DEVICE_LOG,
+        ))?;
+
+        let logs = stmt
+            .query_map(params![device_id.to_string(), from, limit], AuditRepository::row_to_log)?
+            .collect::<rusqlite::Result<_>>()?;
+
+        Ok(logs)
+    }
+
+    /// Devices that have pushed audit entries
+    pub fn devices(&self) -> Result<Vec<Id>> {
+        let conn = self.db.reader()?;
+
+        let mut stmt = conn.prepare("SELECT DISTINCT origin_device_id FROM device_audit_logs")?;
+        let ids = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        let devices = ids
+            .iter()
+            .map(|s| Id::parse_str(s).map_err(|_| DbError::Query(format!("Invalid device id {}", s))))
+            .collect::<Result<_>>()?;
+
+        Ok(devices)
+    }
+
+    /// The server's own entries and every device's, merged in HLC order
+    pub fn global_view(&self, filters: &AuditLogFilters) -> Result<Vec<ReplicatedAuditLog>> {
+        let conn = self.db.reader()?;
+
+        let (clause, values) = filter_clause(filters);
+        let sql = format!(
+            "SELECT * FROM ({}{} UNION ALL {}{}) ORDER BY hlc, origin_device_id, sequence LIMIT {} OFFSET {}",
+            SELECT_SERVER_LOG,
+            clause,
+            SELECT_DEVICE_LOG,
+            clause,
+            filters.limit,
+            filters.page as u64 * filters.limit as u64,
+        );
+        let mut stmt = conn.prepare(&sql)?;
+
+        let params = values.iter().chain(values.iter());
+        let logs = stmt
+            .query_map(rusqlite::params_from_iter(params), |row| {
+                let origin: Option<String> = row.get(16)?;
+                Ok(ReplicatedAuditLog {
+                    origin_device_id: origin.and_then(|s| Id::parse_str(&s).ok()),
+                    entry: AuditRepository::row_to_log(row)?,
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+
+        Ok(logs)
+    }
+}
+
+/// Why an entry cannot be taken as the pushing device's own, if it can't
+fn check_origin(entry: &AuditLog, device_id: Id, public_key: &[u8]) -> Option<String> {
+    if entry.device_id != Some(device_id) {
+        return Some("Entry was not recorded by the pushing device".to_string());
+    }
+    if !AuditSigner::verify_with(public_key, entry) {
+        return Some("Signature does not verify with the device's key".to_string());
+    }
+    None
+}
+
+/// Why an entry cannot follow the stored tail of its sub-chain, if it can't
+fn check_extends(entry: &AuditLog, tail: Option<&(i64, String)>) -> Option<String> {
+    let expected = tail.map(|t| t.0).unwrap_or(0) + 1;
+    if entry.sequence != expected {
+        return Some(format!("Expected entry {} but received {}", expected, entry.sequence));
+    }
+    if entry.hlc.is_none() {
+        return Some("Missing HLC timestamp".to_string());
+    }
+    if !entry.verify_hash() {
+        return Some("Hash does not match the entry".to_string());
+    }
+    if entry.previous_hash.as_deref() != tail.map(|t| t.1.as_str()) {
+        return Some("previous_hash does not match the stored chain".to_string());
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DeviceRepository, UserRepository};
+    use hedtronix_core::{AuditEventType, DeviceType, User, UserRole};
+    use hedtronix_crypto::signing::{SignatureAlgorithm, SigningKeyPair};
+
+    fn database() -> Database {
+        let mut db = Database::in_memory().unwrap();
+        db.initialize().unwrap();
+        db
+    }
+
+    /// A database knowing the device and its user
+    fn with_device(user: &User, device: &Device) -> Database {
+        let db = database();
+        UserRepository::new(db.clone()).create(user).unwrap();
+        DeviceRepository::new(db.clone()).create(device).unwrap();
+        db
+    }
+
+    /// A server with one registered device, and that device's own database
+    /// signing with the device's key
+    fn server_and_device() -> (Database, Device, Database) {
+        let user = User::new("nurse@example.com".into(), "Nurse".into(), UserRole::Nurse, "hash".into());
+        let key = SigningKeyPair::generate(SignatureAlgorithm::Ed25519).unwrap();
+        let device = Device::new(user.id, BASE64.encode(key.public_key()), DeviceType::Tablet, "tablet".into());
+        let server = with_device(&user, &device);
+
+        let mut local = with_device(&user, &device);
+        local.set_audit_signer(AuditSigner::from_pkcs8(key.pkcs8()).unwrap());
+        (server, device, local)
+    }
+
+    fn read(db: &Database, device_id: Option<Id>, entity_id: &str) -> AuditLog {
+        AuditRepository::new(db.clone())
+            .append(&AuditLog::new(
+                AuditEventType::Read,
+                None,
+                device_id,
+                "Patient".to_string(),
+                entity_id.to_string(),
+                serde_json::json!({}),
+            ))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_receive_is_idempotent_and_append_only() {
+        let (server, device, local) = server_and_device();
+        let device_id = device.id;
+        let entries: Vec<AuditLog> = (0..3).map(|n| read(&local, Some(device_id), &n.to_string())).collect();
+        let repo = DeviceAuditRepository::new(server.clone());
+
+        let receipt = repo.receive(&device, &entries[..2]).unwrap();
+        assert_eq!((receipt.accepted, receipt.stored_through), (2, 2));
+
+        // Resending overlaps is harmless
+        let receipt = repo.receive(&device, &entries).unwrap();
+        assert_eq!((receipt.accepted, receipt.stored_through), (1, 3));
+        assert!(receipt.rejected.is_empty());
+
+        // A different entry at a stored position is never accepted
+        let mut forged = entries[1].clone();
+        forged.entity_id = "other".to_string();
+        forged.hash = forged.compute_hash();
+        let receipt = repo.receive(&device, &[forged]).unwrap();
+        assert_eq!(receipt.rejected.len(), 1);
+        assert_eq!(repo.find_range(device_id, 2, 1).unwrap()[0].hash, entries[1].hash);
+
+        let report = crate::AuditChainVerifier::new(server).verify_device(device_id).unwrap();
+        assert!(report.valid);
+        assert_eq!(report.entries_checked, 3);
+    }
+
+    #[test]
+    fn test_receive_rejects_gaps_and_bad_links() {
+        let (server, device, local) = server_and_device();
+        let device_id = device.id;
+        let entries: Vec<AuditLog> = (0..4).map(|n| read(&local, Some(device_id), &n.to_string())).collect();
+        let repo = DeviceAuditRepository::new(server);
+
+        // Entry 2 missing: 3 and everything after it wait
+        let batch = [entries[0].clone(), entries[2].clone(), entries[3].clone()];
+        let receipt = repo.receive(&device, &batch).unwrap();
+        assert_eq!(receipt.stored_through, 1);
+        assert_eq!(receipt.rejected.len(), 2);
+
+        let mut tampered = entries[1].clone();
+        tampered.entity_id = "other".to_string();
+        let receipt = repo.receive(&device, &[tampered]).unwrap();
+        assert_eq!(receipt.stored_through, 1);
+        assert_eq!(receipt.rejected[0].reason, "Hash does not match the entry");
+    }
+
+    #[test]
+    fn test_receive_requires_the_devices_own_signed_entries() {
+        let (server, device, local) = server_and_device();
+        let repo = DeviceAuditRepository::new(server);
+
+        // Signed with another key, or not at all
+        let user = UserRepository::new(local.clone()).find_by_id(device.user_id).unwrap().unwrap();
+        let mut impostor = with_device(&user, &device);
+        impostor.set_audit_signer(AuditSigner::generate().unwrap());
+        let unsigned = with_device(&user, &device);
+        for forged in [read(&impostor, Some(device.id), "forged"), read(&unsigned, Some(device.id), "unsigned")] {
+            let receipt = repo.receive(&device, &[forged]).unwrap();
+            assert_eq!(receipt.stored_through, 0);
+            assert_eq!(receipt.rejected[0].reason, "Signature does not verify with the device's key");
+        }
+
+        // Recorded by another device
+        let other = Device::new(user.id, "key".into(), DeviceType::Desktop, "desktop".into());
+        DeviceRepository::new(local.clone()).create(&other).unwrap();
+        let relayed = read(&local, Some(other.id), "relayed");
+        let receipt = repo.receive(&device, &[relayed]).unwrap();
+        assert_eq!(receipt.stored_through, 0);
+        assert_eq!(receipt.rejected[0].reason, "Entry was not recorded by the pushing device");
+    }
+
+    #[test]
+    fn test_global_view_merges_by_hlc() {
+        let (server, device, local) = server_and_device();
+        let device_id = device.id;
+        let offline = read(&local, Some(device_id), "offline");
+        // Distinct wall-clock millisecond, so the order is not a tie-break
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        read(&server, None, "online");
+
+        DeviceAuditRepository::new(server.clone()).receive(&device, std::slice::from_ref(&offline)).unwrap();
+
+        let filters = AuditLogFilters { limit: 10, ..Default::default() };
+        let view = DeviceAuditRepository::new(server).global_view(&filters).unwrap();
+        assert_eq!(view.len(), 2);
+        assert_eq!(view[0].entry.entity_id, "offline");
+        assert_eq!(view[0].origin_device_id, Some(device_id));
+        assert_eq!(view[1].entry.entity_id, "online");
+        assert_eq!(view[1].origin_device_id, None);
+    }
+}
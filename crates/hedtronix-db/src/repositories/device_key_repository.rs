@@ -0,0 +1,137 @@
+//! Passphrase wrappings of the device database key
+
+use hedtronix_core::{Id, Timestamp};
+use hedtronix_crypto::{KdfParams, PassphraseWrapping};
+use rusqlite::{params, OptionalExtension, Row};
+
+use crate::{Database, DbError, Result};
+
+pub struct DeviceKeyRepository {
+    db: Database,
+}
+
+/// The device key wrapped under one user's passphrase
+#[derive(Debug, Clone)]
+pub struct DeviceKeyWrapping {
+    pub user_id: Id,
+    /// Id of the wrapped device key, the same for every current wrapping
+    pub key_id: String,
+    pub wrapping: PassphraseWrapping,
+    pub created_at: Timestamp,
+    pub updated_at: Timestamp,
+}
+
+fn parse_time(s: &str) -> Option<chrono::DateTime<chrono::Utc>> {
+    chrono::DateTime::parse_from_rfc3339(s)
+        .map(|dt| dt.with_timezone(&chrono::Utc))
+        .ok()
+}
+
+const SELECT_WRAPPING: &str = r#"
+    SELECT user_id, key_id, salt, kdf_params, wrapped_key, created_at, updated_at
+    FROM device_key_wrappings
+"#;
+
+impl DeviceKeyRepository {
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+
+    fn row_to_wrapping(row: &Row) -> rusqlite::Result<DeviceKeyWrapping> {
+        let user_id: String = row.get(0)?;
+        let kdf_params: String = row.get(3)?;
+        let created_at: String = row.get(5)?;
+        let updated_at: String = row.get(6)?;
+
+        Ok(DeviceKeyWrapping {
+            user_id: Id::parse_str(&user_id).unwrap_or_else(|_| Id::new_v4()),
+            key_id: row.get(1)?,
+            wrapping: PassphraseWrapping {
+                salt: row.get(2)?,
+                params: serde_json::from_str::<KdfParams>(&kdf_params).unwrap_or_default(),
+                wrapped_key: row.get(4)?,
+            },
+            created_at: parse_time(&created_at).unwrap_or_default(),
+            updated_at: parse_time(&updated_at).unwrap_or_default(),
+        })
+    }
+
+    pub fn find(&self, user_id: Id) -> Result<Option<DeviceKeyWrapping>> {
+        let conn = self.db.reader()?;
+
+        let wrapping = conn
+            .query_row(
+                &format!("{} WHERE user_id = ?", SELECT_WRAPPING),
+                [user_id.to_string()],
+                Self::row_to_wrapping,
+            )
+            .optional()?;
+
+        Ok(wrapping)
+    }
+
+    /// Every user's wrapping, oldest first
+    pub fn list(&self) -> Result<Vec<DeviceKeyWrapping>> {
+        let conn = self.db.reader()?;
+
+        let mut stmt = conn.prepare(&format!("{} ORDER BY created_at", SELECT_WRAPPING))?;
+        let wrappings = stmt
+            .query_map([], Self::row_to_wrapping)?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        Ok(wrappings)
+    }
+
+    pub fn count(&self) -> Result<i64> {
+        let conn = self.db.reader()?;
+
+        Ok(conn.query_row("SELECT COUNT(*) FROM device_key_wrappings", [], |row| row.get(0))?)
+    }
+
+    /// Store a user's wrapping, replacing any previous one
+    pub fn upsert(&self, wrapping: &DeviceKeyWrapping) -> Result<()> {
+        let conn = self.db.connection();
+        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+
+        let kdf_params = serde_json::to_string(&wrapping.wrapping.params)
+            .map_err(|e| DbError::Serialization(e.to_string()))?;
+        conn.execute(
+            r#"
+            INSERT INTO device_key_wrappings (
+                user_id, key_id, salt, kdf_params, wrapped_key, created_at, updated_at
+            ) VALUES (?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT(user_id) DO UPDATE SET
+                key_id = excluded.key_id,
+                salt = excluded.salt,
+                kdf_params = excluded.kdf_params,
+                wrapped_key = excluded.wrapped_key,
+                updated_at = excluded.updated_at
+            "#,
+            params![
+                wrapping.user_id.to_string(),
+                wrapping.key_id,
+                wrapping.wrapping.salt,
+                kdf_params,
+                wrapping.wrapping.wrapped_key,
+                wrapping.created_at.to_rfc3339(),
+                wrapping.updated_at.to_rfc3339(),
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Remove a user's wrapping. Returns whether there was one.
+    pub fn delete(&self, user_id: Id) -> Result<bool> {
+        let conn = self.db.connection();
+        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+
+        let deleted = conn.execute(
+            "DELETE FROM device_key_wrappings WHERE user_id = ?",
+            [user_id.to_string()],
+        )?;
+
+        Ok(deleted > 0)
+    }
+}
@@ -0,0 +1,176 @@
+//! Device repository
+
+use rusqlite::{params, Row};
+use hedtronix_core::{Device, DeviceType, Id};
+use crate::{Database, DbError, Result};
+
+pub struct DeviceRepository {
+    db: Database,
+}
+
+impl DeviceRepository {
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+
+    fn row_to_device(row: &Row) -> rusqlite::Result<Device> {
+        let id: String = row.get(0)?;
+        let user_id: String = row.get(1)?;
+        let public_key: String = row.get(2)?;
+        let device_type: String = row.get(3)?;
+        let device_name: Option<String> = row.get(4)?;
+        let last_sync_at: Option<String> = row.get(5)?;
+        let ip_address: Option<String> = row.get(6)?;
+        let user_agent: String = row.get(7)?;
+        let revoked: i32 = row.get(8)?;
+        let revoked_at: Option<String> = row.get(9)?;
+        let revoked_by: Option<String> = row.get(10)?;
+        let created_at: String = row.get(11)?;
+        let agreement_key: Option<String> = row.get(12)?;
+
+        let device_type = match device_type.as_str() {
+            "DESKTOP" => DeviceType::Desktop,
+            "TABLET" => DeviceType::Tablet,
+            "MOBILE" => DeviceType::Mobile,
+            _ => DeviceType::Kiosk,
+        };
+
+        let parse_time = |s: &str| {
+            chrono::DateTime::parse_from_rfc3339(s)
+                .map(|dt| dt.with_timezone(&chrono::Utc))
+                .ok()
+        };
+
+        Ok(Device {
+            id: Id::parse_str(&id).unwrap_or_else(|_| Id::new_v4()),
+            user_id: Id::parse_str(&user_id).unwrap_or_else(|_| Id::new_v4()),
+            public_key,
+            agreement_key,
+            device_type,
+            device_name,
+            last_sync_at: last_sync_at.as_deref().and_then(parse_time),
+            ip_address,
+            user_agent,
+            revoked: revoked == 1,
+            revoked_at: revoked_at.as_deref().and_then(parse_time),
+            revoked_by: revoked_by.and_then(|s| Id::parse_str(&s).ok()),
+            created_at: parse_time(&created_at).unwrap_or_else(chrono::Utc::now),
+        })
+    }
+
+    pub fn create(&self, device: &Device) -> Result<()> {
+        let conn = self.db.connection();
+        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+
+        conn.execute(
+            r#"
+            INSERT INTO devices (
+                id, user_id, public_key, device_type, device_name, last_sync_at,
+                ip_address, user_agent, revoked, revoked_at, revoked_by, created_at,
+                agreement_key
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+            params![
+                device.id.to_string(),
+                device.user_id.to_string(),
+                device.public_key,
+                device.device_type.as_str(),
+                device.device_name,
+                device.last_sync_at.map(|dt| dt.to_rfc3339()),
+                device.ip_address,
+                device.user_agent,
+                if device.revoked { 1 } else { 0 },
+                device.revoked_at.map(|dt| dt.to_rfc3339()),
+                device.revoked_by.map(|id| id.to_string()),
+                device.created_at.to_rfc3339(),
+                device.agreement_key,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn find_by_id(&self, id: Id) -> Result<Option<Device>> {
+        let conn = self.db.reader()?;
+
+        let mut stmt = conn.prepare(
+            r#"
+            SELECT id, user_id, public_key, device_type, device_name, last_sync_at,
+                   ip_address, user_agent, revoked, revoked_at, revoked_by, created_at, agreement_key
+            FROM devices WHERE id = ?
+            "#
+        )?;
+
+        let device = stmt.query_row([id.to_string()], Self::row_to_device).ok();
+        Ok(device)
+    }
+
+    pub fn find_by_user(&self, user_id: Id) -> Result<Vec<Device>> {
+        let conn = self.db.reader()?;
+
+        let mut stmt = conn.prepare(
+            r#"
+            SELECT id, user_id, public_key, device_type, device_name, last_sync_at,
+                   ip_address, user_agent, revoked, revoked_at, revoked_by, created_at, agreement_key
+            FROM devices WHERE user_id = ?
+            ORDER BY created_at DESC
+            "#
+        )?;
+
+        let devices = stmt
+            .query_map([user_id.to_string()], Self::row_to_device)?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        Ok(devices)
+    }
+
+    /// Devices that are not revoked and can receive sync keys
+    pub fn find_with_agreement_keys(&self) -> Result<Vec<Device>> {
+        let conn = self.db.reader()?;
+
+        let mut stmt = conn.prepare(
+            r#"
+            SELECT id, user_id, public_key, device_type, device_name, last_sync_at,
+                   ip_address, user_agent, revoked, revoked_at, revoked_by, created_at, agreement_key
+            FROM devices WHERE revoked = 0 AND agreement_key IS NOT NULL
+            ORDER BY created_at
+            "#
+        )?;
+
+        let devices = stmt
+            .query_map([], Self::row_to_device)?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        Ok(devices)
+    }
+
+    pub fn update(&self, device: &Device) -> Result<()> {
+        let conn = self.db.connection();
+        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+
+        conn.execute(
+            r#"
+            UPDATE devices SET
+                public_key = ?, device_name = ?, last_sync_at = ?, ip_address = ?,
+                user_agent = ?, revoked = ?, revoked_at = ?, revoked_by = ?, agreement_key = ?
+            WHERE id = ?
+            "#,
+            params![
+                device.public_key,
+                device.device_name,
+                device.last_sync_at.map(|dt| dt.to_rfc3339()),
+                device.ip_address,
+                device.user_agent,
+                if device.revoked { 1 } else { 0 },
+                device.revoked_at.map(|dt| dt.to_rfc3339()),
+                device.revoked_by.map(|id| id.to_string()),
+                device.agreement_key,
+                device.id.to_string(),
+            ],
+        )?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,186 @@
+//! Accounting of disclosures from the audit trail
+//!
+//! Collects every audited access to a patient's chart, notes, appointments,
+//! billing entries and emergency grants, from the server's chain and the
+//! chains replicated from devices.
+
+use rusqlite::{params, Row};
+use hedtronix_core::{
+    DisclosureAccessor, DisclosureEvent, DisclosureReport, Patient, Timestamp, UserRole,
+};
+
+use crate::{AuditRepository, Database, Result};
+
+pub struct DisclosureRepository {
+    db: Database,
+}
+
+/// Audit entries about the patient (?3) within [?1, ?2), served by the
+/// `(entity_type, entity_id)` indexes
+const PATIENT_SCOPE: &str = r#"
+    timestamp >= ?1 AND timestamp < ?2 AND (
+        (entity_type = 'Patient' AND entity_id = ?3)
+        OR (entity_type = 'ClinicalNote'
+            AND entity_id IN (SELECT id FROM clinical_notes WHERE patient_id = ?3))
+        OR (entity_type = 'Appointment'
+            AND entity_id IN (SELECT id FROM appointments WHERE patient_id = ?3))
+        OR (entity_type = 'BillingEntry'
+            AND entity_id IN (SELECT id FROM billing_entries WHERE patient_id = ?3))
+        OR (entity_type = 'EmergencyAccessGrant'
+            AND entity_id IN (SELECT id FROM emergency_access_grants WHERE patient_id = ?3))
+    )
+"#;
+
+/// Columns in `AuditRepository::row_to_log` order
+const LOG_COLUMNS: &str = r#"
+    id, event_type, user_id, device_id, entity_type, entity_id,
+    changes_json, ip_address, user_agent, timestamp, signature,
+    previous_hash, hash, break_glass_grant_id, sequence, hlc
+"#;
+
+fn parse_role(s: &str) -> UserRole {
+    match s {
+        "PHYSICIAN" => UserRole::Physician,
+        "NURSE" => UserRole::Nurse,
+        "RECEPTIONIST" => UserRole::Receptionist,
+        "BILLING" => UserRole::Billing,
+        "ADMIN" => UserRole::Admin,
+        _ => UserRole::Patient,
+    }
+}
+
+impl DisclosureRepository {
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+
+    /// Every access to the patient's records in `[start, end)`, grouped by
+    /// user and purpose
+    pub fn report(&self, patient: &Patient, start: Timestamp, end: Timestamp) -> Result<DisclosureReport> {
+        let conn = self.db.reader()?;
+
+        let sql = format!(
+            r#"
+            SELECT e.*, u.name, u.email, u.role, g.reason
+            FROM (
+                SELECT {columns} FROM audit_logs WHERE {scope}
+                UNION ALL
+                SELECT {columns} FROM device_audit_logs WHERE {scope}
+            ) e
+            LEFT JOIN users u ON u.id = e.user_id
+            LEFT JOIN emergency_access_grants g ON g.id = e.break_glass_grant_id
+            ORDER BY e.timestamp
+            "#,
+            columns = LOG_COLUMNS,
+            scope = PATIENT_SCOPE,
+        );
+        let mut stmt = conn.prepare(&sql)?;
+
+        let accesses = stmt
+            .query_map(
+                params![start.to_rfc3339(), end.to_rfc3339(), patient.id.to_string()],
+                Self::row_to_access,
+            )?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        Ok(DisclosureReport::new(patient.id, patient.full_name(), start, end, accesses))
+    }
+
+    fn row_to_access(row: &Row) -> rusqlite::Result<(DisclosureAccessor, DisclosureEvent)> {
+        let log = AuditRepository::row_to_log(row)?;
+        let role: Option<String> = row.get(18)?;
+
+        let accessor = DisclosureAccessor {
+            user_id: log.user_id,
+            name: row.get(16)?,
+            email: row.get(17)?,
+            role: role.as_deref().map(parse_role),
+        };
+        let event = DisclosureEvent {
+            audit_log_id: log.id,
+            timestamp: log.timestamp,
+            event_type: log.event_type,
+            entity_type: log.entity_type,
+            entity_id: log.entity_id,
+            device_id: log.device_id,
+            ip_address: log.ip_address,
+            emergency_reason: row.get(19)?,
+        };
+
+        Ok((accessor, event))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EmergencyAccessRepository, PatientRepository, UserRepository};
+    use hedtronix_core::{
+        AuditEventType, AuditLog, DisclosurePurpose, EmergencyAccessGrant, Gender, Id, User,
+    };
+
+    fn read(db: &Database, user: &User, patient: &Patient, grant: Option<Id>) {
+        let mut log = AuditLog::new(
+            AuditEventType::Read,
+            Some(user.id),
+            None,
+            "Patient".to_string(),
+            patient.id.to_string(),
+            serde_json::json!({}),
+        );
+        log.break_glass_grant_id = grant;
+        AuditRepository::new(db.clone()).append(&log).unwrap();
+    }
+
+    #[test]
+    fn test_report_covers_only_the_patient() {
+        let mut db = Database::in_memory().unwrap();
+        db.initialize().unwrap();
+
+        let key = hedtronix_crypto::DataKey::from_bytes("test", &[0; 32]).unwrap();
+        let keyring = std::sync::Arc::new(hedtronix_crypto::Keyring::with_key(key).unwrap());
+        let patients = PatientRepository::new(db.clone(), keyring);
+        let dob = chrono::NaiveDate::from_ymd_opt(1980, 1, 1).unwrap();
+        let jane = Patient::new("MRN1".into(), "Jane".into(), "Doe".into(), dob, Gender::Female);
+        let john = Patient::new("MRN2".into(), "John".into(), "Roe".into(), dob, Gender::Male);
+        patients.create(&jane).unwrap();
+        patients.create(&john).unwrap();
+
+        let nurse = User::new("nurse@example.com".into(), "Nora Nurse".into(), UserRole::Nurse, "hash".into());
+        UserRepository::new(db.clone()).create(&nurse).unwrap();
+
+        let grant = EmergencyAccessGrant::new(
+            nurse.id, jane.id, None, "Unresponsive on arrival".into(), chrono::Duration::hours(1),
+        );
+        EmergencyAccessRepository::new(db.clone()).create(&grant).unwrap();
+
+        let start = chrono::Utc::now() - chrono::Duration::minutes(1);
+        read(&db, &nurse, &jane, None);
+        read(&db, &nurse, &jane, None);
+        read(&db, &nurse, &jane, Some(grant.id));
+        read(&db, &nurse, &john, None);
+
+        let report = DisclosureRepository::new(db.clone())
+            .report(&jane, start, chrono::Utc::now() + chrono::Duration::minutes(1))
+            .unwrap();
+
+        assert_eq!(report.patient_name, jane.full_name());
+        assert_eq!(report.total_events, 3);
+        assert_eq!(report.groups.len(), 2);
+        assert_eq!(report.groups[0].purpose, DisclosurePurpose::Treatment);
+        assert_eq!(report.groups[0].access_count, 2);
+        assert_eq!(report.groups[0].accessor.name.as_deref(), Some("Nora Nurse"));
+        assert_eq!(report.groups[1].purpose, DisclosurePurpose::EmergencyAccess);
+        assert_eq!(
+            report.groups[1].events[0].emergency_reason.as_deref(),
+            Some("Unresponsive on arrival")
+        );
+
+        // Outside the period
+        let earlier = DisclosureRepository::new(db)
+            .report(&jane, start - chrono::Duration::days(1), start)
+            .unwrap();
+        assert_eq!(earlier.total_events, 0);
+    }
+}
@@ -0,0 +1,164 @@
+//! Emergency access grant repository
+
+use rusqlite::{params, OptionalExtension, Row};
+use hedtronix_core::{EmergencyAccessGrant, EmergencyReviewStatus, Id};
+use crate::{Database, DbError, Result};
+
+pub struct EmergencyAccessRepository {
+    db: Database,
+}
+
+fn parse_time(s: &str) -> Option<chrono::DateTime<chrono::Utc>> {
+    chrono::DateTime::parse_from_rfc3339(s)
+        .map(|dt| dt.with_timezone(&chrono::Utc))
+        .ok()
+}
+
+fn parse_status(s: &str) -> EmergencyReviewStatus {
+    match s {
+        "JUSTIFIED" => EmergencyReviewStatus::Justified,
+        "UNJUSTIFIED" => EmergencyReviewStatus::Unjustified,
+        _ => EmergencyReviewStatus::Pending,
+    }
+}
+
+const SELECT_GRANT: &str = r#"
+    SELECT id, user_id, patient_id, device_id, reason, granted_at, expires_at,
+           revoked_at, review_status, reviewed_by, reviewed_at, review_notes
+    FROM emergency_access_grants
+"#;
+
+impl EmergencyAccessRepository {
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+
+    fn row_to_grant(row: &Row) -> rusqlite::Result<EmergencyAccessGrant> {
+        let id: String = row.get(0)?;
+        let user_id: String = row.get(1)?;
+        let patient_id: String = row.get(2)?;
+        let device_id: Option<String> = row.get(3)?;
+        let granted_at: String = row.get(5)?;
+        let expires_at: String = row.get(6)?;
+        let revoked_at: Option<String> = row.get(7)?;
+        let review_status: String = row.get(8)?;
+        let reviewed_by: Option<String> = row.get(9)?;
+        let reviewed_at: Option<String> = row.get(10)?;
+
+        Ok(EmergencyAccessGrant {
+            id: Id::parse_str(&id).unwrap_or_else(|_| Id::new_v4()),
+            user_id: Id::parse_str(&user_id).unwrap_or_else(|_| Id::new_v4()),
+            patient_id: Id::parse_str(&patient_id).unwrap_or_else(|_| Id::new_v4()),
+            device_id: device_id.and_then(|s| Id::parse_str(&s).ok()),
+            reason: row.get(4)?,
+            granted_at: parse_time(&granted_at).unwrap_or_else(chrono::Utc::now),
+            // An unreadable expiry never grants access
+            expires_at: parse_time(&expires_at).unwrap_or_default(),
+            revoked_at: revoked_at.as_deref().and_then(parse_time),
+            review_status: parse_status(&review_status),
+            reviewed_by: reviewed_by.and_then(|s| Id::parse_str(&s).ok()),
+            reviewed_at: reviewed_at.as_deref().and_then(parse_time),
+            review_notes: row.get(11)?,
+        })
+    }
+
+    pub fn create(&self, grant: &EmergencyAccessGrant) -> Result<()> {
+        let conn = self.db.connection();
+        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+
+        conn.execute(
+            r#"
+            INSERT INTO emergency_access_grants (
+                id, user_id, patient_id, device_id, reason, granted_at, expires_at,
+                revoked_at, review_status, reviewed_by, reviewed_at, review_notes
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+            params![
+                grant.id.to_string(),
+                grant.user_id.to_string(),
+                grant.patient_id.to_string(),
+                grant.device_id.map(|id| id.to_string()),
+                grant.reason,
+                grant.granted_at.to_rfc3339(),
+                grant.expires_at.to_rfc3339(),
+                grant.revoked_at.map(|t| t.to_rfc3339()),
+                grant.review_status.as_str(),
+                grant.reviewed_by.map(|id| id.to_string()),
+                grant.reviewed_at.map(|t| t.to_rfc3339()),
+                grant.review_notes,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn find_by_id(&self, id: Id) -> Result<Option<EmergencyAccessGrant>> {
+        let conn = self.db.reader()?;
+
+        let grant = conn.query_row(
+            &format!("{} WHERE id = ?", SELECT_GRANT),
+            [id.to_string()],
+            Self::row_to_grant,
+        ).optional()?;
+
+        Ok(grant)
+    }
+
+    /// Grants still awaiting privacy officer review, oldest first
+    pub fn find_pending_review(&self) -> Result<Vec<EmergencyAccessGrant>> {
+        let conn = self.db.reader()?;
+
+        let mut stmt = conn.prepare(
+            &format!("{} WHERE review_status = 'PENDING' ORDER BY granted_at", SELECT_GRANT)
+        )?;
+
+        let grants = stmt
+            .query_map([], Self::row_to_grant)?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        Ok(grants)
+    }
+
+    /// End a grant early; false if it was already revoked
+    pub fn revoke(&self, id: Id) -> Result<bool> {
+        let conn = self.db.connection();
+        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+
+        let updated = conn.execute(
+            "UPDATE emergency_access_grants SET revoked_at = ? WHERE id = ? AND revoked_at IS NULL",
+            params![chrono::Utc::now().to_rfc3339(), id.to_string()],
+        )?;
+
+        Ok(updated == 1)
+    }
+
+    /// Record the review outcome; false if the grant was already reviewed
+    pub fn review(
+        &self,
+        id: Id,
+        status: EmergencyReviewStatus,
+        reviewer_id: Id,
+        notes: Option<&str>,
+    ) -> Result<bool> {
+        let conn = self.db.connection();
+        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+
+        let updated = conn.execute(
+            r#"
+            UPDATE emergency_access_grants
+            SET review_status = ?, reviewed_by = ?, reviewed_at = ?, review_notes = ?
+            WHERE id = ? AND review_status = 'PENDING'
+            "#,
+            params![
+                status.as_str(),
+                reviewer_id.to_string(),
+                chrono::Utc::now().to_rfc3339(),
+                notes,
+                id.to_string(),
+            ],
+        )?;
+
+        Ok(updated == 1)
+    }
+}
@@ -0,0 +1,65 @@
+//! Login attempt repository, used for per-IP throttling
+
+use rusqlite::params;
+use hedtronix_core::{Id, Timestamp};
+use crate::{Database, DbError, Result};
+
+pub struct LoginAttemptRepository {
+    db: Database,
+}
+
+impl LoginAttemptRepository {
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+
+    pub fn record(
+        &self,
+        email: &str,
+        user_id: Option<Id>,
+        ip_address: Option<&str>,
+        success: bool,
+    ) -> Result<()> {
+        let conn = self.db.connection();
+        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+
+        conn.execute(
+            r#"
+            INSERT INTO login_attempts (id, email, user_id, ip_address, success, attempted_at)
+            VALUES (?, ?, ?, ?, ?, ?)
+            "#,
+            params![
+                Id::new_v4().to_string(),
+                email,
+                user_id.map(|id| id.to_string()),
+                ip_address,
+                if success { 1 } else { 0 },
+                chrono::Utc::now().to_rfc3339(),
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Failed attempts from an IP since `since`, with the time of the latest
+    pub fn recent_ip_failures(&self, ip_address: &str, since: Timestamp) -> Result<(u32, Option<Timestamp>)> {
+        let conn = self.db.reader()?;
+
+        let (count, latest): (u32, Option<String>) = conn.query_row(
+            r#"
+            SELECT COUNT(*), MAX(attempted_at) FROM login_attempts
+            WHERE ip_address = ? AND success = 0 AND attempted_at >= ?
+            "#,
+            params![ip_address, since.to_rfc3339()],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+
+        let latest = latest.and_then(|s| {
+            chrono::DateTime::parse_from_rfc3339(&s)
+                .map(|dt| dt.with_timezone(&chrono::Utc))
+                .ok()
+        });
+
+        Ok((count, latest))
+    }
+}
@@ -0,0 +1,260 @@
+//! MFA repository: TOTP enrollment, recovery codes and WebAuthn credentials
+
+use rusqlite::{params, OptionalExtension, Row};
+use hedtronix_core::{Id, MfaSettings, RecoveryCode, WebauthnCredential};
+use crate::{Database, DbError, Result};
+
+pub struct MfaRepository {
+    db: Database,
+}
+
+fn parse_time(s: &str) -> Option<chrono::DateTime<chrono::Utc>> {
+    chrono::DateTime::parse_from_rfc3339(s)
+        .map(|dt| dt.with_timezone(&chrono::Utc))
+        .ok()
+}
+
+impl MfaRepository {
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+
+    fn row_to_settings(row: &Row) -> rusqlite::Result<MfaSettings> {
+        let user_id: String = row.get(0)?;
+        let totp_enabled: i32 = row.get(2)?;
+        let updated_at: String = row.get(5)?;
+
+        Ok(MfaSettings {
+            user_id: Id::parse_str(&user_id).unwrap_or_else(|_| Id::new_v4()),
+            totp_secret: row.get(1)?,
+            totp_enabled: totp_enabled == 1,
+            totp_last_step: row.get(3)?,
+            webauthn_challenge: row.get(4)?,
+            updated_at: parse_time(&updated_at).unwrap_or_else(chrono::Utc::now),
+        })
+    }
+
+    fn row_to_recovery_code(row: &Row) -> rusqlite::Result<RecoveryCode> {
+        let id: String = row.get(0)?;
+        let user_id: String = row.get(1)?;
+        let used_at: Option<String> = row.get(3)?;
+        let created_at: String = row.get(4)?;
+
+        Ok(RecoveryCode {
+            id: Id::parse_str(&id).unwrap_or_else(|_| Id::new_v4()),
+            user_id: Id::parse_str(&user_id).unwrap_or_else(|_| Id::new_v4()),
+            code_hash: row.get(2)?,
+            used_at: used_at.as_deref().and_then(parse_time),
+            created_at: parse_time(&created_at).unwrap_or_else(chrono::Utc::now),
+        })
+    }
+
+    fn row_to_credential(row: &Row) -> rusqlite::Result<WebauthnCredential> {
+        let user_id: String = row.get(1)?;
+        let created_at: String = row.get(6)?;
+        let last_used_at: Option<String> = row.get(7)?;
+
+        Ok(WebauthnCredential {
+            id: row.get(0)?,
+            user_id: Id::parse_str(&user_id).unwrap_or_else(|_| Id::new_v4()),
+            public_key: row.get(2)?,
+            algorithm: row.get(3)?,
+            sign_count: row.get(4)?,
+            name: row.get(5)?,
+            created_at: parse_time(&created_at).unwrap_or_else(chrono::Utc::now),
+            last_used_at: last_used_at.as_deref().and_then(parse_time),
+        })
+    }
+
+    pub fn find_settings(&self, user_id: Id) -> Result<Option<MfaSettings>> {
+        let conn = self.db.reader()?;
+
+        let settings = conn.query_row(
+            r#"
+            SELECT user_id, totp_secret, totp_enabled, totp_last_step, webauthn_challenge, updated_at
+            FROM user_mfa WHERE user_id = ?
+            "#,
+            [user_id.to_string()],
+            Self::row_to_settings,
+        ).optional()?;
+
+        Ok(settings)
+    }
+
+    pub fn save_settings(&self, settings: &MfaSettings) -> Result<()> {
+        let conn = self.db.connection();
+        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+
+        conn.execute(
+            r#"
+            INSERT INTO user_mfa (
+                user_id, totp_secret, totp_enabled, totp_last_step, webauthn_challenge, updated_at
+            ) VALUES (?, ?, ?, ?, ?, ?)
+            ON CONFLICT(user_id) DO UPDATE SET
+                totp_secret = excluded.totp_secret,
+                totp_enabled = excluded.totp_enabled,
+                totp_last_step = excluded.totp_last_step,
+                webauthn_challenge = excluded.webauthn_challenge,
+                updated_at = excluded.updated_at
+            "#,
+            params![
+                settings.user_id.to_string(),
+                settings.totp_secret,
+                if settings.totp_enabled { 1 } else { 0 },
+                settings.totp_last_step,
+                settings.webauthn_challenge,
+                settings.updated_at.to_rfc3339(),
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Record a used TOTP step, failing if it is not newer than the last one.
+    /// The compare-and-set keeps two concurrent logins from sharing a code.
+    pub fn advance_totp_step(&self, user_id: Id, step: i64) -> Result<bool> {
+        let conn = self.db.connection();
+        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+
+        let updated = conn.execute(
+            r#"
+            UPDATE user_mfa SET totp_last_step = ?, updated_at = ?
+            WHERE user_id = ? AND (totp_last_step IS NULL OR totp_last_step < ?)
+            "#,
+            params![step, chrono::Utc::now().to_rfc3339(), user_id.to_string(), step],
+        )?;
+
+        Ok(updated == 1)
+    }
+
+    /// Replace all of a user's recovery codes
+    pub fn replace_recovery_codes(&self, user_id: Id, codes: &[RecoveryCode]) -> Result<()> {
+        let conn = self.db.connection();
+        let mut conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+
+        let tx = conn.transaction()?;
+        tx.execute("DELETE FROM mfa_recovery_codes WHERE user_id = ?", [user_id.to_string()])?;
+        for code in codes {
+            tx.execute(
+                r#"
+                INSERT INTO mfa_recovery_codes (id, user_id, code_hash, used_at, created_at)
+                VALUES (?, ?, ?, ?, ?)
+                "#,
+                params![
+                    code.id.to_string(),
+                    code.user_id.to_string(),
+                    code.code_hash,
+                    code.used_at.map(|dt| dt.to_rfc3339()),
+                    code.created_at.to_rfc3339(),
+                ],
+            )?;
+        }
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    pub fn find_unused_recovery_codes(&self, user_id: Id) -> Result<Vec<RecoveryCode>> {
+        let conn = self.db.reader()?;
+
+        let mut stmt = conn.prepare(
+            r#"
+            SELECT id, user_id, code_hash, used_at, created_at
+            FROM mfa_recovery_codes WHERE user_id = ? AND used_at IS NULL
+            "#
+        )?;
+
+        let codes = stmt
+            .query_map([user_id.to_string()], Self::row_to_recovery_code)?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        Ok(codes)
+    }
+
+    /// Mark a recovery code used; false if it was already consumed
+    pub fn consume_recovery_code(&self, id: Id) -> Result<bool> {
+        let conn = self.db.connection();
+        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+
+        let updated = conn.execute(
+            "UPDATE mfa_recovery_codes SET used_at = ? WHERE id = ? AND used_at IS NULL",
+            params![chrono::Utc::now().to_rfc3339(), id.to_string()],
+        )?;
+
+        Ok(updated == 1)
+    }
+
+    /// Record an MFA challenge token as used; false if it already was.
+    /// Records past their expiry are dropped, since the token is refused
+    /// by then anyway.
+    pub fn consume_challenge(&self, jti: &str, user_id: Id, expires_at: chrono::DateTime<chrono::Utc>) -> Result<bool> {
+        let conn = self.db.connection();
+        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+
+        let now = chrono::Utc::now().to_rfc3339();
+        conn.execute("DELETE FROM used_mfa_challenges WHERE expires_at < ?", [&now])?;
+        let inserted = conn.execute(
+            "INSERT OR IGNORE INTO used_mfa_challenges (jti, user_id, expires_at) VALUES (?, ?, ?)",
+            params![jti, user_id.to_string(), expires_at.to_rfc3339()],
+        )?;
+
+        Ok(inserted == 1)
+    }
+
+    pub fn create_credential(&self, credential: &WebauthnCredential) -> Result<()> {
+        let conn = self.db.connection();
+        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+
+        conn.execute(
+            r#"
+            INSERT INTO webauthn_credentials (
+                id, user_id, public_key, algorithm, sign_count, name, created_at, last_used_at
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+            params![
+                credential.id,
+                credential.user_id.to_string(),
+                credential.public_key,
+                credential.algorithm,
+                credential.sign_count,
+                credential.name,
+                credential.created_at.to_rfc3339(),
+                credential.last_used_at.map(|dt| dt.to_rfc3339()),
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn find_credentials_by_user(&self, user_id: Id) -> Result<Vec<WebauthnCredential>> {
+        let conn = self.db.reader()?;
+
+        let mut stmt = conn.prepare(
+            r#"
+            SELECT id, user_id, public_key, algorithm, sign_count, name, created_at, last_used_at
+            FROM webauthn_credentials WHERE user_id = ?
+            ORDER BY created_at
+            "#
+        )?;
+
+        let credentials = stmt
+            .query_map([user_id.to_string()], Self::row_to_credential)?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        Ok(credentials)
+    }
+
+    pub fn update_credential_usage(&self, id: &str, sign_count: i64) -> Result<()> {
+        let conn = self.db.connection();
+        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+
+        conn.execute(
+            "UPDATE webauthn_credentials SET sign_count = ?, last_used_at = ? WHERE id = ?",
+            params![sign_count, chrono::Utc::now().to_rfc3339(), id],
+        )?;
+
+        Ok(())
+    }
+}
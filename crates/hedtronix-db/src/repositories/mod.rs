@@ -6,6 +6,21 @@ mod appointment_repository;
 mod sync_repository;
 mod clinical_note_repository;
 mod billing_repository;
+mod device_repository;
+mod audit_repository;
+mod mfa_repository;
+mod login_attempt_repository;
+mod role_repository;
+mod care_team_repository;
+mod emergency_access_repository;
+mod device_audit_repository;
+mod disclosure_repository;
+mod audit_archive_repository;
+mod access_alert_repository;
+mod data_key_repository;
+mod device_key_repository;
+mod sync_key_repository;
+mod sync_relay_repository;
 
 pub use user_repository::*;
 pub use patient_repository::*;
@@ -13,3 +28,18 @@ pub use appointment_repository::*;
 pub use sync_repository::*;
 pub use clinical_note_repository::*;
 pub use billing_repository::*;
+pub use device_repository::*;
+pub use audit_repository::*;
+pub use mfa_repository::*;
+pub use login_attempt_repository::*;
+pub use role_repository::*;
+pub use care_team_repository::*;
+pub use emergency_access_repository::*;
+pub use device_audit_repository::*;
+pub use disclosure_repository::*;
+pub use audit_archive_repository::*;
+pub use access_alert_repository::*;
+pub use data_key_repository::*;
+pub use device_key_repository::*;
+pub use sync_key_repository::*;
+pub use sync_relay_repository::*;
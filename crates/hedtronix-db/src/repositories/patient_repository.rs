@@ -1,21 +1,41 @@
 //! Patient repository
 
 use rusqlite::{params, Row};
-use hedtronix_core::{Patient, CreatePatient, UpdatePatient, PatientSearchFilters, Gender, Id, Address, EmergencyContact, InsuranceInfo, Allergy, Medication, VersionVector};
+use hedtronix_core::{Patient, PatientSearchFilters, Gender, Id};
 use crate::{Database, DbError, Result};
-use hedtronix_crypto::{encrypt_field, decrypt_field};
+use hedtronix_crypto::{BlindIndexField, BlindIndexKey, FieldContext, Keyring};
+use std::sync::Arc;
+
+const SELECT_PATIENT: &str = r#"
+    SELECT id, medical_record_number, first_name, last_name, date_of_birth,
+           gender, address_json, phone, email, emergency_contact_json,
+           primary_care_physician_id, insurance_info_json, allergies_json,
+           medications_json, problems_json, active, deceased, deceased_at,
+           created_at, updated_at, version_json, last_modified_by
+    FROM patients
+"#;
+
+/// Blind indexes of one patient
+struct SearchIndex {
+    first_name: Option<String>,
+    last_name: Option<String>,
+    mrn: String,
+    dob: Option<String>,
+    phone: Option<String>,
+    tokens: Vec<String>,
+}
 
 pub struct PatientRepository {
     db: Database,
-    encryption_key: Vec<u8>,
+    keyring: Arc<Keyring>,
 }
 
 impl PatientRepository {
-    pub fn new(db: Database, encryption_key: Vec<u8>) -> Self {
-        Self { db, encryption_key }
+    pub fn new(db: Database, keyring: Arc<Keyring>) -> Self {
+        Self { db, keyring }
     }
 
-    fn row_to_patient(row: &Row, key: &[u8]) -> rusqlite::Result<Patient> {
+    fn row_to_patient(row: &Row, keyring: &Keyring) -> rusqlite::Result<Patient> {
         let id: String = row.get(0)?;
         let mrn_enc: String = row.get(1)?;
         let first_name_enc: String = row.get(2)?;
@@ -40,32 +60,32 @@ impl PatientRepository {
         let last_modified_by: Option<String> = row.get(21)?;
 
         // Decrypt helper closure
-        let decrypt = |s: &str| -> rusqlite::Result<String> {
+        let decrypt = |column: &str, s: &str| -> rusqlite::Result<String> {
              if s.is_empty() { return Ok(String::new()); }
-             decrypt_field(s, key).map_err(|e| rusqlite::Error::FromSqlConversionFailure(
+             keyring.decrypt(s, &FieldContext::new("patients", column, &id)).map_err(|e| rusqlite::Error::FromSqlConversionFailure(
                  0, 
                  rusqlite::types::Type::Text, 
                  Box::new(std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string()))
              ))
         };
 
-        let mrn = decrypt(&mrn_enc)?;
-        let first_name = decrypt(&first_name_enc)?;
-        let last_name = decrypt(&last_name_enc)?;
-        let dob = decrypt(&dob_enc)?;
-        let address_json = decrypt(&address_json_enc)?;
-        let phone = decrypt(&phone_enc)?;
+        let mrn = decrypt("medical_record_number", &mrn_enc)?;
+        let first_name = decrypt("first_name", &first_name_enc)?;
+        let last_name = decrypt("last_name", &last_name_enc)?;
+        let dob = decrypt("date_of_birth", &dob_enc)?;
+        let address_json = decrypt("address_json", &address_json_enc)?;
+        let phone = decrypt("phone", &phone_enc)?;
         
         let email = match email_enc {
-            Some(e) => Some(decrypt(&e)?),
+            Some(e) => Some(decrypt("email", &e)?),
             None => None,
         };
         
-        let emergency_contact_json = decrypt(&emergency_contact_json_enc)?;
-        let insurance_json = decrypt(&insurance_json_enc)?;
-        let allergies_json = decrypt(&allergies_json_enc)?;
-        let medications_json = decrypt(&medications_json_enc)?;
-        let problems_json = decrypt(&problems_json_enc)?;
+        let emergency_contact_json = decrypt("emergency_contact_json", &emergency_contact_json_enc)?;
+        let insurance_json = decrypt("insurance_info_json", &insurance_json_enc)?;
+        let allergies_json = decrypt("allergies_json", &allergies_json_enc)?;
+        let medications_json = decrypt("medications_json", &medications_json_enc)?;
+        let problems_json = decrypt("problems_json", &problems_json_enc)?;
 
         let gender = match gender_str.as_str() {
             "MALE" => Gender::Male,
@@ -111,7 +131,7 @@ impl PatientRepository {
 
     pub fn create(&self, patient: &Patient) -> Result<()> {
         let conn = self.db.connection();
-        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+        let mut conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
 
         let gender_str = match patient.gender {
             Gender::Male => "MALE",
@@ -121,30 +141,34 @@ impl PatientRepository {
         };
 
         // Encrypt sensitive fields
-        let key = &self.encryption_key;
-        let encrypt = |s: &str| -> Result<String> {
-            encrypt_field(s, key).map_err(|e| DbError::Serialization(format!("Encryption failed: {}", e)))
+        let keyring = &self.keyring;
+        let row_id = patient.id.to_string();
+        let encrypt = |column: &str, s: &str| -> Result<String> {
+            keyring.encrypt(s, &FieldContext::new("patients", column, &row_id)).map_err(|e| DbError::Serialization(format!("Encryption failed: {}", e)))
         };
 
-        let mrn_enc = encrypt(&patient.medical_record_number)?;
-        let first_name_enc = encrypt(&patient.first_name)?;
-        let last_name_enc = encrypt(&patient.last_name)?;
-        let dob_enc = encrypt(&patient.date_of_birth.format("%Y-%m-%d").to_string())?;
-        let address_enc = encrypt(&serde_json::to_string(&patient.address).unwrap_or_default())?;
-        let phone_enc = encrypt(&patient.phone)?;
+        let mrn_enc = encrypt("medical_record_number", &patient.medical_record_number)?;
+        let first_name_enc = encrypt("first_name", &patient.first_name)?;
+        let last_name_enc = encrypt("last_name", &patient.last_name)?;
+        let dob_enc = encrypt("date_of_birth", &patient.date_of_birth.format("%Y-%m-%d").to_string())?;
+        let address_enc = encrypt("address_json", &serde_json::to_string(&patient.address).unwrap_or_default())?;
+        let phone_enc = encrypt("phone", &patient.phone)?;
         
         let email_enc = match &patient.email {
-            Some(e) => Some(encrypt(e)?),
+            Some(e) => Some(encrypt("email", e)?),
             None => None,
         };
         
-        let emergency_enc = encrypt(&serde_json::to_string(&patient.emergency_contact).unwrap_or_default())?;
-        let insurance_enc = encrypt(&serde_json::to_string(&patient.insurance_info).unwrap_or_default())?;
-        let allergies_enc = encrypt(&serde_json::to_string(&patient.allergies).unwrap_or_default())?;
-        let medications_enc = encrypt(&serde_json::to_string(&patient.medications).unwrap_or_default())?;
-        let problems_enc = encrypt(&serde_json::to_string(&patient.problems).unwrap_or_default())?;
+        let emergency_enc = encrypt("emergency_contact_json", &serde_json::to_string(&patient.emergency_contact).unwrap_or_default())?;
+        let insurance_enc = encrypt("insurance_info_json", &serde_json::to_string(&patient.insurance_info).unwrap_or_default())?;
+        let allergies_enc = encrypt("allergies_json", &serde_json::to_string(&patient.allergies).unwrap_or_default())?;
+        let medications_enc = encrypt("medications_json", &serde_json::to_string(&patient.medications).unwrap_or_default())?;
+        let problems_enc = encrypt("problems_json", &serde_json::to_string(&patient.problems).unwrap_or_default())?;
 
-        conn.execute(
+        let index = self.search_index(patient)?;
+
+        let tx = conn.transaction()?;
+        tx.execute(
             r#"
             INSERT INTO patients (
                 id, medical_record_number, first_name, last_name, date_of_birth,
@@ -179,13 +203,14 @@ impl PatientRepository {
                 patient.last_modified_by.clone(),
             ],
         )?;
+        Self::write_search_index(&tx, &patient.id.to_string(), &index)?;
+        tx.commit()?;
 
         Ok(())
     }
 
     pub fn find_by_id(&self, id: Id) -> Result<Option<Patient>> {
-        let conn = self.db.connection();
-        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+        let conn = self.db.reader()?;
 
         let mut stmt = conn.prepare(
             r#"
@@ -198,108 +223,189 @@ impl PatientRepository {
             "#
         )?;
 
-        let key = &self.encryption_key;
-        let patient = stmt.query_row([id.to_string()], |row| Self::row_to_patient(row, key)).ok();
+        let keyring = &self.keyring;
+        let patient = stmt.query_row([id.to_string()], |row| Self::row_to_patient(row, keyring)).ok();
         Ok(patient)
     }
 
     pub fn find_by_mrn(&self, mrn: &str) -> Result<Option<Patient>> {
-        let conn = self.db.connection();
-        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+        let conn = self.db.reader()?;
 
-        // Encryption prevents SQL lookup. Scan all active patients.
-        // Optimization: In a real system, use a deterministic hash for lookup.
-        let mut stmt = conn.prepare(
-            r#"
-            SELECT id, medical_record_number, first_name, last_name, date_of_birth,
-                   gender, address_json, phone, email, emergency_contact_json,
-                   primary_care_physician_id, insurance_info_json, allergies_json,
-                   medications_json, problems_json, active, deceased, deceased_at,
-                   created_at, updated_at, version_json, last_modified_by
-            FROM patients
-            "#
-        )?;
+        let index = self.index_key()?;
+        let Some(mrn_index) = index.exact(BlindIndexField::MedicalRecordNumber, mrn) else {
+            return Ok(None);
+        };
 
-        let key = &self.encryption_key;
-        let mut rows = stmt.query([])?;
-        
+        let mut stmt = conn.prepare(&format!("{} WHERE mrn_index = ?", SELECT_PATIENT))?;
+        let keyring = &self.keyring;
+        let mut rows = stmt.query([mrn_index])?;
+
+        // The index ignores case and punctuation, so confirm the exact value
         while let Some(row) = rows.next()? {
-            match Self::row_to_patient(row, key) {
-                Ok(patient) => {
-                    if patient.medical_record_number == mrn {
-                        return Ok(Some(patient));
-                    }
-                },
+            match Self::row_to_patient(row, keyring) {
+                Ok(patient) if patient.medical_record_number == mrn => return Ok(Some(patient)),
+                Ok(_) => continue,
                 Err(_) => continue, // Skip malformed/decryption failure
             }
         }
-        
+
         Ok(None)
     }
 
+    /// Patients matching the filters, oldest first. Name, MRN and phone
+    /// filters are answered from blind indexes, so only the returned page is
+    /// decrypted. A free-text query matches first name, last name or MRN by
+    /// prefix (one or two characters) or substring, a phone number by
+    /// substring and a `YYYY-MM-DD` date by date of birth.
     pub fn search(&self, filters: &PatientSearchFilters) -> Result<Vec<Patient>> {
-        let conn = self.db.connection();
-        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+        let conn = self.db.reader()?;
 
-        // Build base SQL for non-encrypted fields
-        let mut sql = r#"
-            SELECT id, medical_record_number, first_name, last_name, date_of_birth,
-                   gender, address_json, phone, email, emergency_contact_json,
-                   primary_care_physician_id, insurance_info_json, allergies_json,
-                   medications_json, problems_json, active, deceased, deceased_at,
-                   created_at, updated_at, version_json, last_modified_by
-            FROM patients WHERE 1=1
-        "#.to_string();
+        let (clause, params) = self.search_clause(filters)?;
+        let sql = format!(
+            "{}{} ORDER BY created_at, id LIMIT {} OFFSET {}",
+            SELECT_PATIENT,
+            clause,
+            filters.limit,
+            filters.page as u64 * filters.limit as u64
+        );
+
+        let mut stmt = conn.prepare(&sql)?;
+        let keyring = &self.keyring;
+        let patients = stmt
+            .query_map(rusqlite::params_from_iter(params.iter()), |row| Self::row_to_patient(row, keyring))?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        Ok(patients)
+    }
+
+    /// Number of patients matching the filters, ignoring pagination
+    pub fn search_count(&self, filters: &PatientSearchFilters) -> Result<i64> {
+        let conn = self.db.reader()?;
+
+        let (clause, params) = self.search_clause(filters)?;
+        let count = conn.query_row(
+            &format!("SELECT COUNT(*) FROM patients{}", clause),
+            rusqlite::params_from_iter(params.iter()),
+            |row| row.get(0),
+        )?;
+
+        Ok(count)
+    }
+
+    fn search_clause(&self, filters: &PatientSearchFilters) -> Result<(String, Vec<String>)> {
+        let index = self.index_key()?;
+        let mut conditions = Vec::new();
+        let mut params = Vec::new();
 
         if filters.active_only {
-            sql.push_str(" AND active = 1");
+            conditions.push("active = 1".to_string());
         }
 
-        if let Some(physician_id) = filters.physician_id {
-            sql.push_str(&format!(" AND primary_care_physician_id = '{}'", physician_id));
+        // The same relationships `CareTeamRepository` reads for one patient
+        if let Some(visible) = &filters.visible_to {
+            let mut relationships = Vec::new();
+            if let Some(user_id) = visible.care_team_member {
+                relationships.extend([
+                    "primary_care_physician_id = ?",
+                    "id IN (SELECT patient_id FROM encounters WHERE provider_id = ?)",
+                    "id IN (SELECT patient_id FROM appointments WHERE provider_id = ?)",
+                    "id IN (SELECT patient_id FROM clinical_notes WHERE author_id = ?)",
+                ]);
+                params.extend(std::iter::repeat_n(user_id.to_string(), 4));
+            }
+            if let Some(department_id) = visible.department_id {
+                relationships.extend([
+                    "id IN (SELECT patient_id FROM encounters WHERE department_id = ?)",
+                    "primary_care_physician_id IN (SELECT id FROM users WHERE department_id = ?)",
+                ]);
+                params.extend(std::iter::repeat_n(department_id.to_string(), 2));
+            }
+            if let Some(patient_id) = visible.patient_id {
+                relationships.push("id = ?");
+                params.push(patient_id.to_string());
+            }
+
+            if relationships.is_empty() {
+                conditions.push("0".to_string());
+            } else {
+                conditions.push(format!("({})", relationships.join(" OR ")));
+            }
         }
 
-        // We must fetch ALL matching the base criteria, then decrypt, filter, sort, paginate in memory
-        let mut stmt = conn.prepare(&sql)?;
-        let key = &self.encryption_key;
-        
-        let mut patients: Vec<Patient> = stmt
-            .query_map([], |row| Self::row_to_patient(row, key))?
-            .filter_map(|r| r.ok())
-            .collect();
+        if let Some(physician_id) = filters.physician_id {
+            conditions.push("primary_care_physician_id = ?".to_string());
+            params.push(physician_id.to_string());
+        }
 
-        // In-memory filtering
-        if let Some(ref query) = filters.query {
-            let q = query.to_lowercase();
-            patients.retain(|p| {
-                p.first_name.to_lowercase().contains(&q) ||
-                p.last_name.to_lowercase().contains(&q) ||
-                p.medical_record_number.to_lowercase().contains(&q)
-            });
+        let exact_filters = [
+            ("first_name_index", BlindIndexField::FirstName, filters.first_name.clone()),
+            ("last_name_index", BlindIndexField::LastName, filters.last_name.clone()),
+            ("mrn_index", BlindIndexField::MedicalRecordNumber, filters.medical_record_number.clone()),
+            ("dob_index", BlindIndexField::DateOfBirth, filters.date_of_birth.map(|d| d.format("%Y-%m-%d").to_string())),
+            ("phone_index", BlindIndexField::Phone, filters.phone.clone()),
+        ];
+        for (column, field, value) in exact_filters {
+            let Some(value) = value else { continue };
+            match index.exact(field, &value) {
+                Some(tag) => {
+                    conditions.push(format!("{} = ?", column));
+                    params.push(tag);
+                }
+                None => conditions.push("0".to_string()),
+            }
         }
 
-        // In-memory sorting (Last Name, First Name)
-        patients.sort_by(|a, b| {
-            a.last_name.cmp(&b.last_name)
-                .then(a.first_name.cmp(&b.first_name))
-        });
+        if let Some(query) = filters.query.as_deref().map(str::trim).filter(|q| !q.is_empty()) {
+            let mut fields = vec![
+                BlindIndexField::FirstName,
+                BlindIndexField::LastName,
+                BlindIndexField::MedicalRecordNumber,
+            ];
+            if query.chars().all(|c| c.is_ascii_digit() || " ()+-.".contains(c)) {
+                fields.push(BlindIndexField::Phone);
+            }
 
-        // Pagination
-        let total = patients.len();
-        let offset = (filters.page * filters.limit) as usize;
-        let limit = filters.limit as usize;
-        
-        if offset >= total {
-            return Ok(Vec::new());
+            let mut alternatives = Vec::new();
+            for field in fields {
+                let tokens = index.query_tokens(field, query);
+                if tokens.is_empty() {
+                    continue;
+                }
+                // Every token of the query must be among the patient's tokens
+                alternatives.push(format!(
+                    "id IN (SELECT patient_id FROM patient_search_tokens WHERE token IN ({}) \
+                     GROUP BY patient_id HAVING COUNT(*) = {})",
+                    vec!["?"; tokens.len()].join(", "),
+                    tokens.len()
+                ));
+                params.extend(tokens);
+            }
+            if chrono::NaiveDate::parse_from_str(query, "%Y-%m-%d").is_ok() {
+                if let Some(tag) = index.exact(BlindIndexField::DateOfBirth, query) {
+                    alternatives.push("dob_index = ?".to_string());
+                    params.push(tag);
+                }
+            }
+
+            if alternatives.is_empty() {
+                conditions.push("0".to_string());
+            } else {
+                conditions.push(format!("({})", alternatives.join(" OR ")));
+            }
         }
 
-        let end = std::cmp::min(offset + limit, total);
-        Ok(patients[offset..end].to_vec())
+        let clause = if conditions.is_empty() {
+            String::new()
+        } else {
+            format!(" WHERE {}", conditions.join(" AND "))
+        };
+        Ok((clause, params))
     }
 
     pub fn update(&self, patient: &Patient) -> Result<()> {
         let conn = self.db.connection();
-        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+        let mut conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
 
         let gender_str = match patient.gender {
             Gender::Male => "MALE",
@@ -309,9 +415,10 @@ impl PatientRepository {
         };
 
         // Encrypt sensitive fields
-        let key = &self.encryption_key;
-        let encrypt = |s: &str| -> Result<String> {
-            encrypt_field(s, key).map_err(|e| DbError::Serialization(format!("Encryption failed: {}", e)))
+        let keyring = &self.keyring;
+        let row_id = patient.id.to_string();
+        let encrypt = |column: &str, s: &str| -> Result<String> {
+            keyring.encrypt(s, &FieldContext::new("patients", column, &row_id)).map_err(|e| DbError::Serialization(format!("Encryption failed: {}", e)))
         };
 
         // Note: MRN is usually immutable but if allowed to change it should be encrypted too
@@ -319,24 +426,27 @@ impl PatientRepository {
         // Checking original code: "UPDATE patients SET first_name = ? ..."
         // MRN is NOT in the update list in original code. Good.
 
-        let first_name_enc = encrypt(&patient.first_name)?;
-        let last_name_enc = encrypt(&patient.last_name)?;
-        let dob_enc = encrypt(&patient.date_of_birth.format("%Y-%m-%d").to_string())?;
-        let address_enc = encrypt(&serde_json::to_string(&patient.address).unwrap_or_default())?;
-        let phone_enc = encrypt(&patient.phone)?;
+        let first_name_enc = encrypt("first_name", &patient.first_name)?;
+        let last_name_enc = encrypt("last_name", &patient.last_name)?;
+        let dob_enc = encrypt("date_of_birth", &patient.date_of_birth.format("%Y-%m-%d").to_string())?;
+        let address_enc = encrypt("address_json", &serde_json::to_string(&patient.address).unwrap_or_default())?;
+        let phone_enc = encrypt("phone", &patient.phone)?;
         
         let email_enc = match &patient.email {
-            Some(e) => Some(encrypt(e)?),
+            Some(e) => Some(encrypt("email", e)?),
             None => None,
         };
         
-        let emergency_enc = encrypt(&serde_json::to_string(&patient.emergency_contact).unwrap_or_default())?;
-        let insurance_enc = encrypt(&serde_json::to_string(&patient.insurance_info).unwrap_or_default())?;
-        let allergies_enc = encrypt(&serde_json::to_string(&patient.allergies).unwrap_or_default())?;
-        let medications_enc = encrypt(&serde_json::to_string(&patient.medications).unwrap_or_default())?;
-        let problems_enc = encrypt(&serde_json::to_string(&patient.problems).unwrap_or_default())?;
+        let emergency_enc = encrypt("emergency_contact_json", &serde_json::to_string(&patient.emergency_contact).unwrap_or_default())?;
+        let insurance_enc = encrypt("insurance_info_json", &serde_json::to_string(&patient.insurance_info).unwrap_or_default())?;
+        let allergies_enc = encrypt("allergies_json", &serde_json::to_string(&patient.allergies).unwrap_or_default())?;
+        let medications_enc = encrypt("medications_json", &serde_json::to_string(&patient.medications).unwrap_or_default())?;
+        let problems_enc = encrypt("problems_json", &serde_json::to_string(&patient.problems).unwrap_or_default())?;
 
-        conn.execute(
+        let index = self.search_index(patient)?;
+
+        let tx = conn.transaction()?;
+        let updated = tx.execute(
             r#"
             UPDATE patients SET
                 first_name = ?, last_name = ?, date_of_birth = ?,
@@ -370,10 +480,99 @@ impl PatientRepository {
                 patient.id.to_string(),
             ],
         )?;
+        if updated > 0 {
+            Self::write_search_index(&tx, &patient.id.to_string(), &index)?;
+        }
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    fn index_key(&self) -> Result<Arc<BlindIndexKey>> {
+        self.keyring
+            .index_key()
+            .map_err(|e| DbError::Serialization(format!("Blind index unavailable: {}", e)))
+    }
+
+    /// Blind indexes of a patient's searchable fields
+    fn search_index(&self, patient: &Patient) -> Result<SearchIndex> {
+        let index = self.index_key()?;
+        let dob = patient.date_of_birth.format("%Y-%m-%d").to_string();
+        let searchable = [
+            (BlindIndexField::FirstName, patient.first_name.as_str()),
+            (BlindIndexField::LastName, patient.last_name.as_str()),
+            (BlindIndexField::MedicalRecordNumber, patient.medical_record_number.as_str()),
+            (BlindIndexField::Phone, patient.phone.as_str()),
+        ];
+
+        Ok(SearchIndex {
+            first_name: index.exact(BlindIndexField::FirstName, &patient.first_name),
+            last_name: index.exact(BlindIndexField::LastName, &patient.last_name),
+            // Never NULL once indexed, so unindexed rows can be found
+            mrn: index
+                .exact(BlindIndexField::MedicalRecordNumber, &patient.medical_record_number)
+                .unwrap_or_default(),
+            dob: index.exact(BlindIndexField::DateOfBirth, &dob),
+            phone: index.exact(BlindIndexField::Phone, &patient.phone),
+            tokens: searchable
+                .iter()
+                .flat_map(|(field, value)| index.tokens(*field, value))
+                .collect(),
+        })
+    }
+
+    fn write_search_index(conn: &rusqlite::Connection, id: &str, index: &SearchIndex) -> Result<()> {
+        conn.execute(
+            r#"
+            UPDATE patients SET
+                first_name_index = ?, last_name_index = ?, mrn_index = ?,
+                dob_index = ?, phone_index = ?
+            WHERE id = ?
+            "#,
+            params![index.first_name, index.last_name, index.mrn, index.dob, index.phone, id],
+        )?;
+
+        conn.execute("DELETE FROM patient_search_tokens WHERE patient_id = ?", [id])?;
+        let mut stmt = conn.prepare("INSERT OR IGNORE INTO patient_search_tokens (token, patient_id) VALUES (?, ?)")?;
+        for token in &index.tokens {
+            stmt.execute([token.as_str(), id])?;
+        }
 
         Ok(())
     }
 
+    /// Patients written before blind indexing, still unsearchable
+    pub fn unindexed_count(&self) -> Result<i64> {
+        let conn = self.db.connection();
+        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+
+        let count = conn.query_row("SELECT COUNT(*) FROM patients WHERE mrn_index IS NULL", [], |row| row.get(0))?;
+        Ok(count)
+    }
+
+    /// Build the blind indexes of up to `batch` unindexed patients in one
+    /// transaction. Returns how many were indexed; zero means none are left.
+    pub fn index_batch(&self, batch: u32) -> Result<usize> {
+        let conn = self.db.connection();
+        let mut conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+        let tx = conn.transaction()?;
+
+        let patients: Vec<Patient> = {
+            let mut stmt = tx.prepare(&format!("{} WHERE mrn_index IS NULL LIMIT ?", SELECT_PATIENT))?;
+            let keyring = &self.keyring;
+            let rows = stmt.query_map([batch.max(1)], |row| Self::row_to_patient(row, keyring))?;
+            rows.collect::<rusqlite::Result<Vec<_>>>()?
+        };
+
+        for patient in &patients {
+            let index = self.search_index(patient)?;
+            Self::write_search_index(&tx, &patient.id.to_string(), &index)?;
+        }
+
+        tx.commit()?;
+        Ok(patients.len())
+    }
+
     pub fn delete(&self, id: Id) -> Result<()> {
         let conn = self.db.connection();
         let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
@@ -383,8 +582,7 @@ impl PatientRepository {
     }
 
     pub fn count(&self) -> Result<i64> {
-        let conn = self.db.connection();
-        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+        let conn = self.db.reader()?;
 
         let mut stmt = conn.prepare("SELECT COUNT(*) FROM patients")?;
         let count: i64 = stmt.query_row([], |row| row.get(0))?;
@@ -397,3 +595,127 @@ impl PatientRepository {
         Ok(format!("MRN{:08}", count))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hedtronix_core::ChartFilter;
+
+    fn setup() -> PatientRepository {
+        let mut db = Database::in_memory().unwrap();
+        db.initialize().unwrap();
+        let key = hedtronix_crypto::DataKey::from_bytes("test", &[3u8; 32]).unwrap();
+        PatientRepository::new(db, Arc::new(Keyring::with_key(key).unwrap()))
+    }
+
+    fn add(repo: &PatientRepository, mrn: &str, first: &str, last: &str, phone: &str) -> Patient {
+        let dob = chrono::NaiveDate::from_ymd_opt(1980, 1, 2).unwrap();
+        let mut patient = Patient::new(mrn.to_string(), first.to_string(), last.to_string(), dob, Gender::Unknown);
+        patient.phone = phone.to_string();
+        repo.create(&patient).unwrap();
+        patient
+    }
+
+    fn search(repo: &PatientRepository, filters: PatientSearchFilters) -> Vec<String> {
+        let filters = PatientSearchFilters { limit: 50, ..filters };
+        repo.search(&filters).unwrap().into_iter().map(|p| p.medical_record_number).collect()
+    }
+
+    fn query(repo: &PatientRepository, q: &str) -> Vec<String> {
+        search(repo, PatientSearchFilters { query: Some(q.to_string()), ..Default::default() })
+    }
+
+    #[test]
+    fn test_search_uses_blind_indexes() {
+        let repo = setup();
+        add(&repo, "MRN-001", "Ada", "Lovelace", "(555) 010-2030");
+        add(&repo, "MRN-002", "Grace", "Hopper", "555 777 8888");
+        add(&repo, "MRN-003", "Alan", "Turing", "");
+
+        // Stored values are ciphertext; only the indexes are searchable
+        assert_eq!(query(&repo, "a"), vec!["MRN-001", "MRN-003"]);
+        assert_eq!(query(&repo, "LOVE"), vec!["MRN-001"]);
+        assert_eq!(query(&repo, "opp"), vec!["MRN-002"]);
+        assert_eq!(query(&repo, "mrn-00"), vec!["MRN-001", "MRN-002", "MRN-003"]);
+        assert_eq!(query(&repo, "7788"), vec!["MRN-002"]);
+        assert_eq!(query(&repo, "1980-01-02").len(), 3);
+        assert!(query(&repo, "xyz").is_empty());
+
+        let exact = search(&repo, PatientSearchFilters {
+            last_name: Some("lovelace".to_string()),
+            phone: Some("555-010-2030".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(exact, vec!["MRN-001"]);
+        assert!(search(&repo, PatientSearchFilters { last_name: Some("Love".to_string()), ..Default::default() }).is_empty());
+
+        let filters = PatientSearchFilters { query: Some("a".to_string()), limit: 1, page: 1, ..Default::default() };
+        assert_eq!(repo.search(&filters).unwrap()[0].medical_record_number, "MRN-003");
+        assert_eq!(repo.search_count(&filters).unwrap(), 2);
+
+        assert_eq!(repo.find_by_mrn("MRN-002").unwrap().unwrap().last_name, "Hopper");
+        assert!(repo.find_by_mrn("mrn002").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_update_and_backfill_indexes() {
+        let repo = setup();
+        let mut patient = add(&repo, "MRN-001", "Ada", "Byron", "");
+        patient.last_name = "Lovelace".to_string();
+        repo.update(&patient).unwrap();
+        assert!(query(&repo, "byron").is_empty());
+        assert_eq!(query(&repo, "lovelace"), vec!["MRN-001"]);
+
+        // Rows written before blind indexing are indexed in batches
+        add(&repo, "MRN-002", "Grace", "Hopper", "");
+        {
+            let conn = repo.db.connection();
+            let conn = conn.lock().unwrap();
+            conn.execute_batch("UPDATE patients SET mrn_index = NULL; DELETE FROM patient_search_tokens;").unwrap();
+        }
+        assert!(query(&repo, "hopper").is_empty());
+        assert_eq!(repo.unindexed_count().unwrap(), 2);
+
+        assert_eq!(repo.index_batch(1).unwrap(), 1);
+        assert_eq!(repo.index_batch(10).unwrap(), 1);
+        assert_eq!(repo.index_batch(10).unwrap(), 0);
+        assert_eq!(query(&repo, "hopper"), vec!["MRN-002"]);
+    }
+
+    #[test]
+    fn test_search_pages_only_visible_charts() {
+        let repo = setup();
+        let physician = hedtronix_core::User::new(
+            "pcp@example.com".to_string(),
+            "PCP".to_string(),
+            hedtronix_core::UserRole::Physician,
+            "hash".to_string(),
+        );
+        crate::UserRepository::new(repo.db.clone()).create(&physician).unwrap();
+
+        let mut own = None;
+        for i in 0..6 {
+            let mut patient = add(&repo, &format!("MRN-00{}", i), "Ada", "Lovelace", "");
+            if i % 2 == 1 {
+                patient.primary_care_physician_id = Some(physician.id);
+                repo.update(&patient).unwrap();
+            }
+            own.get_or_insert(patient.id);
+        }
+
+        let cared_for = ChartFilter { care_team_member: Some(physician.id), ..Default::default() };
+        let page = |page, visible_to: &ChartFilter| {
+            let filters = PatientSearchFilters { limit: 2, page, visible_to: Some(visible_to.clone()), ..Default::default() };
+            let mrns: Vec<String> = repo.search(&filters).unwrap().into_iter().map(|p| p.medical_record_number).collect();
+            (mrns, repo.search_count(&filters).unwrap())
+        };
+
+        // Paging and totals count only the charts the caller may open
+        assert_eq!(page(0, &cared_for), (vec!["MRN-001".to_string(), "MRN-003".to_string()], 3));
+        assert_eq!(page(1, &cared_for), (vec!["MRN-005".to_string()], 3));
+
+        let with_own = ChartFilter { patient_id: own, ..cared_for };
+        assert_eq!(page(0, &with_own).1, 4);
+        assert_eq!(page(0, &ChartFilter::default()), (Vec::new(), 0));
+    }
+}
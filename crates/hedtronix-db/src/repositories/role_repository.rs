@@ -0,0 +1,248 @@
+//! Role repository: built-in and custom roles with their permissions
+
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use hedtronix_core::{Id, Permission, Role, UserRole, ALL_USER_ROLES};
+use crate::{Database, DbError, Result};
+
+pub struct RoleRepository {
+    db: Database,
+}
+
+fn parse_time(s: &str) -> Option<chrono::DateTime<chrono::Utc>> {
+    chrono::DateTime::parse_from_rfc3339(s)
+        .map(|dt| dt.with_timezone(&chrono::Utc))
+        .ok()
+}
+
+fn parse_role(s: &str) -> UserRole {
+    match s {
+        "PHYSICIAN" => UserRole::Physician,
+        "NURSE" => UserRole::Nurse,
+        "RECEPTIONIST" => UserRole::Receptionist,
+        "BILLING" => UserRole::Billing,
+        "ADMIN" => UserRole::Admin,
+        _ => UserRole::Patient,
+    }
+}
+
+impl RoleRepository {
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+
+    fn row_to_role(row: &Row) -> rusqlite::Result<Role> {
+        let base_role: String = row.get(3)?;
+        let built_in: i32 = row.get(4)?;
+        let created_at: String = row.get(5)?;
+        let updated_at: String = row.get(6)?;
+
+        Ok(Role {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            description: row.get(2)?,
+            base_role: parse_role(&base_role),
+            built_in: built_in == 1,
+            permissions: Vec::new(),
+            created_at: parse_time(&created_at).unwrap_or_else(chrono::Utc::now),
+            updated_at: parse_time(&updated_at).unwrap_or_else(chrono::Utc::now),
+        })
+    }
+
+    fn load_permissions(conn: &Connection, role_id: &str) -> Result<Vec<Permission>> {
+        let mut stmt = conn.prepare(
+            "SELECT resource, action FROM role_permissions WHERE role_id = ? ORDER BY resource, action"
+        )?;
+
+        let permissions = stmt
+            .query_map([role_id], |row| {
+                let resource: String = row.get(0)?;
+                let action: String = row.get(1)?;
+                Ok(Permission::new(&resource, &action))
+            })?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        Ok(permissions)
+    }
+
+    fn insert_role(conn: &Connection, role: &Role) -> Result<()> {
+        conn.execute(
+            r#"
+            INSERT INTO roles (id, name, description, base_role, built_in, created_at, updated_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+            "#,
+            params![
+                role.id,
+                role.name,
+                role.description,
+                role.base_role.as_str(),
+                if role.built_in { 1 } else { 0 },
+                role.created_at.to_rfc3339(),
+                role.updated_at.to_rfc3339(),
+            ],
+        )?;
+
+        for permission in &role.permissions {
+            conn.execute(
+                "INSERT OR IGNORE INTO role_permissions (role_id, resource, action) VALUES (?, ?, ?)",
+                params![role.id, permission.resource, permission.action],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Insert any missing built-in role with its default grants. Existing
+    /// rows are left alone so admin changes to them survive restarts.
+    pub fn ensure_built_in_roles(&self) -> Result<()> {
+        let conn = self.db.connection();
+        let mut conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+
+        let tx = conn.transaction()?;
+        for role in ALL_USER_ROLES {
+            let exists: bool = tx
+                .prepare("SELECT 1 FROM roles WHERE id = ?")?
+                .exists([role.as_str()])?;
+            if !exists {
+                Self::insert_role(&tx, &Role::built_in(role))?;
+            }
+        }
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    pub fn find_all(&self) -> Result<Vec<Role>> {
+        let conn = self.db.reader()?;
+
+        let mut stmt = conn.prepare(
+            r#"
+            SELECT id, name, description, base_role, built_in, created_at, updated_at
+            FROM roles
+            ORDER BY built_in DESC, name
+            "#
+        )?;
+
+        let mut roles: Vec<Role> = stmt
+            .query_map([], Self::row_to_role)?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        for role in &mut roles {
+            role.permissions = Self::load_permissions(&conn, &role.id)?;
+        }
+
+        Ok(roles)
+    }
+
+    pub fn find_by_id(&self, id: &str) -> Result<Option<Role>> {
+        let conn = self.db.reader()?;
+
+        let role = conn.query_row(
+            r#"
+            SELECT id, name, description, base_role, built_in, created_at, updated_at
+            FROM roles WHERE id = ?
+            "#,
+            [id],
+            Self::row_to_role,
+        ).optional()?;
+
+        match role {
+            Some(mut role) => {
+                role.permissions = Self::load_permissions(&conn, &role.id)?;
+                Ok(Some(role))
+            }
+            None => Ok(None),
+        }
+    }
+
+    pub fn create(&self, role: &Role) -> Result<()> {
+        let conn = self.db.connection();
+        let mut conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+
+        let tx = conn.transaction()?;
+        Self::insert_role(&tx, role)?;
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    pub fn grant(&self, role_id: &str, permission: &Permission) -> Result<()> {
+        let conn = self.db.connection();
+        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+
+        conn.execute(
+            "INSERT OR IGNORE INTO role_permissions (role_id, resource, action) VALUES (?, ?, ?)",
+            params![role_id, permission.resource, permission.action],
+        )?;
+        conn.execute(
+            "UPDATE roles SET updated_at = ? WHERE id = ?",
+            params![chrono::Utc::now().to_rfc3339(), role_id],
+        )?;
+
+        Ok(())
+    }
+
+    /// Remove a grant; false if the role did not hold it
+    pub fn revoke(&self, role_id: &str, permission: &Permission) -> Result<bool> {
+        let conn = self.db.connection();
+        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+
+        let removed = conn.execute(
+            "DELETE FROM role_permissions WHERE role_id = ? AND resource = ? AND action = ?",
+            params![role_id, permission.resource, permission.action],
+        )?;
+        conn.execute(
+            "UPDATE roles SET updated_at = ? WHERE id = ?",
+            params![chrono::Utc::now().to_rfc3339(), role_id],
+        )?;
+
+        Ok(removed == 1)
+    }
+
+    /// Number of users assigned a custom role
+    pub fn count_users(&self, role_id: &str) -> Result<i64> {
+        let conn = self.db.reader()?;
+
+        let count = conn.query_row(
+            "SELECT COUNT(*) FROM users WHERE role_id = ?",
+            [role_id],
+            |row| row.get(0),
+        )?;
+
+        Ok(count)
+    }
+
+    /// Delete a custom role; built-in roles are never deleted
+    pub fn delete(&self, id: &str) -> Result<bool> {
+        let conn = self.db.connection();
+        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+
+        let deleted = conn.execute("DELETE FROM roles WHERE id = ? AND built_in = 0", [id])?;
+
+        Ok(deleted == 1)
+    }
+
+    /// Give a user a role. Custom roles also set the user's base role.
+    pub fn assign(&self, user_id: Id, role: &Role) -> Result<()> {
+        let conn = self.db.connection();
+        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+
+        let role_id = (!role.built_in).then_some(role.id.as_str());
+        let updated = conn.execute(
+            "UPDATE users SET role = ?, role_id = ?, updated_at = ? WHERE id = ?",
+            params![
+                role.base_role.as_str(),
+                role_id,
+                chrono::Utc::now().to_rfc3339(),
+                user_id.to_string(),
+            ],
+        )?;
+
+        if updated == 0 {
+            return Err(DbError::NotFound(format!("User {}", user_id)));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,144 @@
+//! Sync keys sealed to devices
+
+use hedtronix_core::{Id, Timestamp};
+use rusqlite::{params, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+
+use crate::{Database, DbError, Result};
+
+pub struct SyncKeyRepository {
+    db: Database,
+}
+
+/// One device's sealed copy of a sync key
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncKeyGrant {
+    pub key_id: String,
+    /// `organization` or `department:<id>`
+    pub scope: String,
+    pub device_id: Id,
+    /// The key sealed to the device's agreement key
+    pub sealed_key: String,
+    /// Device that sealed the key
+    pub granted_by: Id,
+    pub created_at: Timestamp,
+}
+
+fn parse_time(s: &str) -> Option<chrono::DateTime<chrono::Utc>> {
+    chrono::DateTime::parse_from_rfc3339(s)
+        .map(|dt| dt.with_timezone(&chrono::Utc))
+        .ok()
+}
+
+const SELECT_GRANT: &str = r#"
+    SELECT key_id, scope, device_id, sealed_key, granted_by, created_at
+    FROM sync_key_grants
+"#;
+
+impl SyncKeyRepository {
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+
+    fn row_to_grant(row: &Row) -> rusqlite::Result<SyncKeyGrant> {
+        let device_id: String = row.get(2)?;
+        let granted_by: String = row.get(4)?;
+        let created_at: String = row.get(5)?;
+
+        Ok(SyncKeyGrant {
+            key_id: row.get(0)?,
+            scope: row.get(1)?,
+            device_id: Id::parse_str(&device_id).unwrap_or_else(|_| Id::new_v4()),
+            sealed_key: row.get(3)?,
+            granted_by: Id::parse_str(&granted_by).unwrap_or_else(|_| Id::new_v4()),
+            created_at: parse_time(&created_at).unwrap_or_default(),
+        })
+    }
+
+    /// Store a grant, replacing the device's previous copy of the key
+    pub fn grant(&self, grant: &SyncKeyGrant) -> Result<()> {
+        let conn = self.db.connection();
+        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+
+        conn.execute(
+            r#"
+            INSERT INTO sync_key_grants (
+                key_id, scope, device_id, sealed_key, granted_by, created_at
+            ) VALUES (?, ?, ?, ?, ?, ?)
+            ON CONFLICT(key_id, device_id) DO UPDATE SET
+                sealed_key = excluded.sealed_key,
+                granted_by = excluded.granted_by,
+                created_at = excluded.created_at
+            "#,
+            params![
+                grant.key_id,
+                grant.scope,
+                grant.device_id.to_string(),
+                grant.sealed_key,
+                grant.granted_by.to_string(),
+                grant.created_at.to_rfc3339(),
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn find(&self, key_id: &str, device_id: Id) -> Result<Option<SyncKeyGrant>> {
+        let conn = self.db.reader()?;
+
+        let grant = conn
+            .query_row(
+                &format!("{} WHERE key_id = ? AND device_id = ?", SELECT_GRANT),
+                params![key_id, device_id.to_string()],
+                Self::row_to_grant,
+            )
+            .optional()?;
+
+        Ok(grant)
+    }
+
+    /// Every key granted to a device, oldest first
+    pub fn find_for_device(&self, device_id: Id) -> Result<Vec<SyncKeyGrant>> {
+        let conn = self.db.reader()?;
+
+        let mut stmt = conn.prepare(&format!(
+            "{} WHERE device_id = ? ORDER BY created_at",
+            SELECT_GRANT
+        ))?;
+        let grants = stmt
+            .query_map([device_id.to_string()], Self::row_to_grant)?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        Ok(grants)
+    }
+
+    /// Scope of a key, if it has been granted to any device
+    pub fn key_scope(&self, key_id: &str) -> Result<Option<String>> {
+        let conn = self.db.reader()?;
+
+        let scope = conn
+            .query_row(
+                "SELECT scope FROM sync_key_grants WHERE key_id = ? LIMIT 1",
+                [key_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        Ok(scope)
+    }
+
+    /// Drop every key held by a device, e.g. when it is revoked. Returns
+    /// how many grants were removed.
+    pub fn revoke_device(&self, device_id: Id) -> Result<usize> {
+        let conn = self.db.connection();
+        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+
+        let deleted = conn.execute(
+            "DELETE FROM sync_key_grants WHERE device_id = ?",
+            [device_id.to_string()],
+        )?;
+
+        Ok(deleted)
+    }
+}
@@ -0,0 +1,202 @@
+//! End-to-end encrypted changes held by the sync relay
+
+use hedtronix_core::Id;
+use hedtronix_core::crdt::{ChangeOperation, SealedChange};
+use rusqlite::{params, Row};
+use serde::{Deserialize, Serialize};
+
+use crate::{Database, DbError, Result};
+
+pub struct SyncRelayRepository {
+    db: Database,
+}
+
+/// A relayed change and its position in the relay's order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RelayedChange {
+    pub sequence: i64,
+    #[serde(flatten)]
+    pub change: SealedChange,
+}
+
+fn parse_time(s: &str) -> Option<chrono::DateTime<chrono::Utc>> {
+    chrono::DateTime::parse_from_rfc3339(s)
+        .map(|dt| dt.with_timezone(&chrono::Utc))
+        .ok()
+}
+
+fn operation_str(operation: ChangeOperation) -> &'static str {
+    match operation {
+        ChangeOperation::Create => "CREATE",
+        ChangeOperation::Update => "UPDATE",
+        ChangeOperation::Delete => "DELETE",
+    }
+}
+
+impl SyncRelayRepository {
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+
+    fn row_to_change(row: &Row) -> rusqlite::Result<RelayedChange> {
+        let id: String = row.get(1)?;
+        let entity_id: String = row.get(6)?;
+        let operation: String = row.get(7)?;
+        let timestamp: String = row.get(8)?;
+        let version_json: String = row.get(10)?;
+
+        let operation = match operation.as_str() {
+            "CREATE" => ChangeOperation::Create,
+            "DELETE" => ChangeOperation::Delete,
+            _ => ChangeOperation::Update,
+        };
+
+        Ok(RelayedChange {
+            sequence: row.get(0)?,
+            change: SealedChange {
+                id: Id::parse_str(&id).unwrap_or_else(|_| Id::new_v4()),
+                scope: row.get(2)?,
+                key_id: row.get(3)?,
+                entity_type: row.get(4)?,
+                device_id: row.get(5)?,
+                entity_id: Id::parse_str(&entity_id).unwrap_or_else(|_| Id::new_v4()),
+                operation,
+                timestamp: parse_time(&timestamp).unwrap_or_default(),
+                payload: row.get(9)?,
+                version: serde_json::from_str(&version_json).unwrap_or_default(),
+                signature: row.get(11)?,
+            },
+        })
+    }
+
+    /// Append a change. Returns false if the relay already holds it, so
+    /// devices can safely resend.
+    pub fn store(&self, change: &SealedChange) -> Result<bool> {
+        let conn = self.db.connection();
+        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+
+        let version_json = serde_json::to_string(&change.version)
+            .map_err(|e| DbError::Serialization(e.to_string()))?;
+        let inserted = conn.execute(
+            r#"
+            INSERT OR IGNORE INTO sync_relay (
+                id, scope, key_id, entity_type, entity_id, operation, timestamp,
+                device_id, version_json, payload, signature, received_at
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+            params![
+                change.id.to_string(),
+                change.scope,
+                change.key_id,
+                change.entity_type,
+                change.entity_id.to_string(),
+                operation_str(change.operation),
+                change.timestamp.to_rfc3339(),
+                change.device_id,
+                version_json,
+                change.payload,
+                change.signature,
+                chrono::Utc::now().to_rfc3339(),
+            ],
+        )?;
+
+        Ok(inserted > 0)
+    }
+
+    /// Changes after `after` in the given scopes, in relay order, leaving
+    /// out the ones `device_id` sent itself
+    pub fn find_after(
+        &self,
+        after: i64,
+        scopes: &[String],
+        device_id: &str,
+        limit: u32,
+    ) -> Result<Vec<RelayedChange>> {
+        if scopes.is_empty() {
+            return Ok(Vec::new());
+        }
+        let conn = self.db.reader()?;
+
+        let placeholders = vec!["?"; scopes.len()].join(", ");
+        let mut stmt = conn.prepare(&format!(
+            r#"
+            SELECT sequence, id, scope, key_id, entity_type, device_id, entity_id,
+                   operation, timestamp, payload, version_json, signature
+            FROM sync_relay
+            WHERE sequence > ? AND device_id != ? AND scope IN ({})
+            ORDER BY sequence
+            LIMIT ?
+            "#,
+            placeholders
+        ))?;
+
+        let mut values: Vec<rusqlite::types::Value> = vec![after.into(), device_id.to_string().into()];
+        values.extend(scopes.iter().map(|s| s.clone().into()));
+        values.push(i64::from(limit).into());
+
+        let changes = stmt
+            .query_map(rusqlite::params_from_iter(values), Self::row_to_change)?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        Ok(changes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DeviceRepository, UserRepository};
+    use hedtronix_core::{Device, DeviceType, User, UserRole};
+
+    fn sealed(device_id: Id, scope: &str) -> SealedChange {
+        SealedChange {
+            id: Id::new_v4(),
+            entity_type: "Patient".to_string(),
+            entity_id: Id::new_v4(),
+            operation: ChangeOperation::Update,
+            timestamp: chrono::Utc::now(),
+            device_id: device_id.to_string(),
+            version: Default::default(),
+            scope: scope.to_string(),
+            key_id: "key-1".to_string(),
+            payload: "c2VhbGVk".to_string(),
+            signature: "c2lnbmVk".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_relay_orders_and_filters_by_scope() {
+        let mut db = Database::in_memory().unwrap();
+        db.initialize().unwrap();
+        let user = User::new("nurse@example.com".into(), "Nurse".into(), UserRole::Nurse, "hash".into());
+        UserRepository::new(db.clone()).create(&user).unwrap();
+        let devices: Vec<Id> = (0..2)
+            .map(|_| {
+                let device = Device::new(user.id, "key".into(), DeviceType::Tablet, "tablet".into());
+                DeviceRepository::new(db.clone()).create(&device).unwrap();
+                device.id
+            })
+            .collect();
+
+        let repo = SyncRelayRepository::new(db);
+        let first = sealed(devices[0], "organization");
+        assert!(repo.store(&first).unwrap());
+        // Resending is harmless
+        assert!(!repo.store(&first).unwrap());
+        repo.store(&sealed(devices[0], "department:icu")).unwrap();
+        repo.store(&sealed(devices[1], "organization")).unwrap();
+        let last = sealed(devices[0], "organization");
+        repo.store(&last).unwrap();
+
+        let scopes = vec!["organization".to_string()];
+        let pulled = repo.find_after(0, &scopes, &devices[1].to_string(), 10).unwrap();
+        let ids: Vec<Id> = pulled.iter().map(|c| c.change.id).collect();
+        assert_eq!(ids, vec![first.id, last.id]);
+        assert_eq!(pulled[0].change.payload, first.payload);
+
+        let after = repo.find_after(pulled[0].sequence, &scopes, &devices[1].to_string(), 10).unwrap();
+        assert_eq!(after.len(), 1);
+        assert!(repo.find_after(0, &[], &devices[1].to_string(), 10).unwrap().is_empty());
+    }
+}
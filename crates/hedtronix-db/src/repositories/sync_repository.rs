@@ -1,7 +1,7 @@
 //! Sync queue repository for offline-first operations
 
-use rusqlite::{params, Row};
-use hedtronix_core::{Id, VersionVector};
+use rusqlite::params;
+use hedtronix_core::Id;
 use hedtronix_core::crdt::{Change, ChangeOperation};
 use crate::{Database, DbError, Result};
 
@@ -131,8 +131,7 @@ impl SyncRepository {
 
     /// Get sync metadata
     pub fn get_metadata(&self, key: &str) -> Result<Option<String>> {
-        let conn = self.db.connection();
-        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+        let conn = self.db.reader()?;
 
         let mut stmt = conn.prepare("SELECT value FROM sync_metadata WHERE key = ?")?;
         let value: Option<String> = stmt.query_row([key], |row| row.get(0)).ok();
@@ -173,8 +172,7 @@ impl SyncRepository {
 
     /// Get pending sync count
     pub fn pending_count(&self) -> Result<i64> {
-        let conn = self.db.connection();
-        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+        let conn = self.db.reader()?;
 
         let mut stmt = conn.prepare("SELECT COUNT(*) FROM sync_queue WHERE synced = 0")?;
         let count: i64 = stmt.query_row([], |row| row.get(0))?;
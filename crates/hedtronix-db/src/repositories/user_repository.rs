@@ -1,7 +1,7 @@
 //! User repository
 
 use rusqlite::{params, Row};
-use hedtronix_core::{User, CreateUser, UpdateUser, UserRole, Id, VersionVector};
+use hedtronix_core::{User, UserRole, Id};
 use crate::{Database, DbError, Result};
 
 pub struct UserRepository {
@@ -28,6 +28,19 @@ impl UserRepository {
         let password_hash: String = row.get(11)?;
         let version_json: String = row.get(12)?;
         let last_modified_by: Option<String> = row.get(13)?;
+        let failed_login_attempts: u32 = row.get(14)?;
+        let last_failed_login_at: Option<String> = row.get(15)?;
+        let locked_until: Option<String> = row.get(16)?;
+        let must_change_password: i32 = row.get(17)?;
+        let password_changed_at: Option<String> = row.get(18)?;
+        let role_id: Option<String> = row.get(19)?;
+        let patient_id: Option<String> = row.get(20)?;
+
+        let parse_time = |s: String| {
+            chrono::DateTime::parse_from_rfc3339(&s)
+                .map(|dt| dt.with_timezone(&chrono::Utc))
+                .ok()
+        };
 
         let role = match role_str.as_str() {
             "PHYSICIAN" => UserRole::Physician,
@@ -44,7 +57,9 @@ impl UserRepository {
             email,
             name,
             role,
+            role_id,
             department_id: department_id.and_then(|s| Id::parse_str(&s).ok()),
+            patient_id: patient_id.and_then(|s| Id::parse_str(&s).ok()),
             license_number,
             npi_number,
             active: active == 1,
@@ -60,6 +75,11 @@ impl UserRepository {
                     .ok()
             ),
             password_hash,
+            failed_login_attempts,
+            last_failed_login_at: last_failed_login_at.and_then(parse_time),
+            locked_until: locked_until.and_then(parse_time),
+            must_change_password: must_change_password == 1,
+            password_changed_at: password_changed_at.and_then(parse_time),
             version: serde_json::from_str(&version_json).unwrap_or_default(),
             last_modified_by,
         })
@@ -74,8 +94,9 @@ impl UserRepository {
             INSERT INTO users (
                 id, email, name, role, department_id, license_number, npi_number,
                 active, created_at, updated_at, last_login_at, password_hash,
-                version_json, last_modified_by
-            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                version_json, last_modified_by, must_change_password, password_changed_at,
+                role_id, patient_id
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
             "#,
             params![
                 user.id.to_string(),
@@ -92,6 +113,10 @@ impl UserRepository {
                 user.password_hash,
                 serde_json::to_string(&user.version).unwrap_or_default(),
                 user.last_modified_by,
+                if user.must_change_password { 1 } else { 0 },
+                user.password_changed_at.map(|dt| dt.to_rfc3339()),
+                user.role_id,
+                user.patient_id.map(|id| id.to_string()),
             ],
         )?;
 
@@ -99,14 +124,15 @@ impl UserRepository {
     }
 
     pub fn find_by_id(&self, id: Id) -> Result<Option<User>> {
-        let conn = self.db.connection();
-        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+        let conn = self.db.reader()?;
 
         let mut stmt = conn.prepare(
             r#"
             SELECT id, email, name, role, department_id, license_number, npi_number,
                    active, created_at, updated_at, last_login_at, password_hash,
-                   version_json, last_modified_by
+                   version_json, last_modified_by, failed_login_attempts,
+                   last_failed_login_at, locked_until, must_change_password, password_changed_at,
+                   role_id, patient_id
             FROM users WHERE id = ?
             "#
         )?;
@@ -116,14 +142,15 @@ impl UserRepository {
     }
 
     pub fn find_by_email(&self, email: &str) -> Result<Option<User>> {
-        let conn = self.db.connection();
-        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+        let conn = self.db.reader()?;
 
         let mut stmt = conn.prepare(
             r#"
             SELECT id, email, name, role, department_id, license_number, npi_number,
                    active, created_at, updated_at, last_login_at, password_hash,
-                   version_json, last_modified_by
+                   version_json, last_modified_by, failed_login_attempts,
+                   last_failed_login_at, locked_until, must_change_password, password_changed_at,
+                   role_id, patient_id
             FROM users WHERE email = ?
             "#
         )?;
@@ -133,14 +160,15 @@ impl UserRepository {
     }
 
     pub fn find_all(&self, limit: u32, offset: u32) -> Result<Vec<User>> {
-        let conn = self.db.connection();
-        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+        let conn = self.db.reader()?;
 
         let mut stmt = conn.prepare(
             r#"
             SELECT id, email, name, role, department_id, license_number, npi_number,
                    active, created_at, updated_at, last_login_at, password_hash,
-                   version_json, last_modified_by
+                   version_json, last_modified_by, failed_login_attempts,
+                   last_failed_login_at, locked_until, must_change_password, password_changed_at,
+                   role_id, patient_id
             FROM users
             ORDER BY created_at DESC
             LIMIT ? OFFSET ?
@@ -162,7 +190,7 @@ impl UserRepository {
         conn.execute(
             r#"
             UPDATE users SET
-                email = ?, name = ?, role = ?, department_id = ?,
+                email = ?, name = ?, role = ?, role_id = ?, department_id = ?, patient_id = ?,
                 license_number = ?, npi_number = ?, active = ?,
                 updated_at = ?, last_login_at = ?,
                 version_json = ?, last_modified_by = ?
@@ -172,7 +200,9 @@ impl UserRepository {
                 user.email,
                 user.name,
                 user.role.as_str(),
+                user.role_id,
                 user.department_id.map(|id| id.to_string()),
+                user.patient_id.map(|id| id.to_string()),
                 user.license_number,
                 user.npi_number,
                 if user.active { 1 } else { 0 },
@@ -187,6 +217,120 @@ impl UserRepository {
         Ok(())
     }
 
+    /// Persist the failed-login counter and any lockout
+    pub fn record_login_failure(&self, user: &User) -> Result<()> {
+        let conn = self.db.connection();
+        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+
+        conn.execute(
+            r#"
+            UPDATE users SET
+                failed_login_attempts = ?, last_failed_login_at = ?, locked_until = ?
+            WHERE id = ?
+            "#,
+            params![
+                user.failed_login_attempts,
+                user.last_failed_login_at.map(|dt| dt.to_rfc3339()),
+                user.locked_until.map(|dt| dt.to_rfc3339()),
+                user.id.to_string(),
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Clear failed-login state, optionally recording a successful login
+    pub fn clear_login_failures(
+        &self,
+        id: Id,
+        last_login_at: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> Result<()> {
+        let conn = self.db.connection();
+        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+
+        conn.execute(
+            r#"
+            UPDATE users SET
+                failed_login_attempts = 0, last_failed_login_at = NULL, locked_until = NULL,
+                last_login_at = COALESCE(?, last_login_at)
+            WHERE id = ?
+            "#,
+            params![last_login_at.map(|dt| dt.to_rfc3339()), id.to_string()],
+        )?;
+
+        Ok(())
+    }
+
+    /// Replace the password hash, keeping the old one in the history table
+    /// and trimming the history to `history_size` entries
+    pub fn change_password(
+        &self,
+        user: &User,
+        new_hash: &str,
+        must_change_password: bool,
+        history_size: usize,
+    ) -> Result<()> {
+        let conn = self.db.connection();
+        let mut conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+        let now = chrono::Utc::now().to_rfc3339();
+
+        let tx = conn.transaction()?;
+        tx.execute(
+            r#"
+            INSERT INTO password_history (id, user_id, password_hash, created_at)
+            VALUES (?, ?, ?, ?)
+            "#,
+            params![Id::new_v4().to_string(), user.id.to_string(), user.password_hash, now],
+        )?;
+        tx.execute(
+            r#"
+            DELETE FROM password_history WHERE user_id = ?1 AND id NOT IN (
+                SELECT id FROM password_history WHERE user_id = ?1
+                ORDER BY created_at DESC LIMIT ?2
+            )
+            "#,
+            params![user.id.to_string(), history_size as i64],
+        )?;
+        tx.execute(
+            r#"
+            UPDATE users SET
+                password_hash = ?, must_change_password = ?, password_changed_at = ?, updated_at = ?
+            WHERE id = ?
+            "#,
+            params![
+                new_hash,
+                if must_change_password { 1 } else { 0 },
+                now,
+                now,
+                user.id.to_string(),
+            ],
+        )?;
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    /// Most recent previous password hashes, newest first
+    pub fn password_history(&self, id: Id, limit: usize) -> Result<Vec<String>> {
+        let conn = self.db.reader()?;
+
+        let mut stmt = conn.prepare(
+            r#"
+            SELECT password_hash FROM password_history
+            WHERE user_id = ?
+            ORDER BY created_at DESC
+            LIMIT ?
+            "#
+        )?;
+
+        let hashes = stmt
+            .query_map(params![id.to_string(), limit as i64], |row| row.get(0))?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        Ok(hashes)
+    }
+
     pub fn delete(&self, id: Id) -> Result<()> {
         let conn = self.db.connection();
         let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
@@ -196,8 +340,7 @@ impl UserRepository {
     }
 
     pub fn count(&self) -> Result<i64> {
-        let conn = self.db.connection();
-        let conn = conn.lock().map_err(|e| DbError::Connection(e.to_string()))?;
+        let conn = self.db.reader()?;
 
         let mut stmt = conn.prepare("SELECT COUNT(*) FROM users")?;
         let count: i64 = stmt.query_row([], |row| row.get(0))?;
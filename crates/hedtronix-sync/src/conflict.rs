@@ -128,8 +128,8 @@ mod tests {
     fn test_delete_wins() {
         let resolver = ConflictResolver::new();
         
-        let local = Change::delete("Patient".into(), Id::new_v4(), "device1".into());
-        let remote = Change::update("Patient".into(), Id::new_v4(), serde_json::json!({"name": "test"}), "device2".into());
+        let local = Change::delete("Patient", Id::new_v4(), "device1");
+        let remote = Change::update("Patient", Id::new_v4(), serde_json::json!({"name": "test"}), "device2");
         
         match resolver.resolve(&local, &remote) {
             ResolutionResult::KeepLocal => (),
@@ -142,9 +142,9 @@ mod tests {
         let resolver = ConflictResolver::new();
         let entity_id = Id::new_v4();
         
-        let local = Change::update("Patient".into(), entity_id, serde_json::json!({"name": "John"}), "device1".into());
+        let local = Change::update("Patient", entity_id, serde_json::json!({"name": "John"}), "device1");
         std::thread::sleep(std::time::Duration::from_millis(10));
-        let remote = Change::update("Patient".into(), entity_id, serde_json::json!({"phone": "555-1234"}), "device2".into());
+        let remote = Change::update("Patient", entity_id, serde_json::json!({"phone": "555-1234"}), "device2");
         
         match resolver.resolve(&local, &remote) {
             ResolutionResult::Merge(merged) => {
@@ -0,0 +1,290 @@
+//! End-to-end encryption of sync changes
+//!
+//! A change's data is encrypted on the originating device with a sync key
+//! shared by every device in a scope (the organization, or one
+//! department), then signed with the device's Ed25519 key. Sync keys are
+//! created by a device and distributed sealed to each recipient device's
+//! X25519 agreement key, so the relay server stores grants and ciphertexts
+//! but never a key.
+//!
+//! The readable envelope (ids, entity type, operation, timestamps, scope
+//! and key id) is bound to the ciphertext as associated data and covered
+//! by the signature: the relay can order, route and authenticate changes,
+//! and any change it makes to the envelope is detected by the recipient.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use hedtronix_core::Id;
+use hedtronix_core::crdt::{Change, SealedChange};
+use hedtronix_crypto::{
+    generate_random_bytes, seal_key, verify_signature, AgreementKeyPair, Encryptor, SecretKey,
+    SignatureAlgorithm, SigningKeyPair,
+};
+
+use crate::engine::{Result, SyncError};
+
+/// Scope of changes readable by every device in the organization
+pub const ORGANIZATION_SCOPE: &str = "organization";
+
+/// Scope of changes readable by the devices of one department's staff
+pub fn department_scope(department_id: Id) -> String {
+    format!("department:{}", department_id)
+}
+
+/// Whether `scope` is a well-formed sync scope
+pub fn is_valid_scope(scope: &str) -> bool {
+    scope == ORGANIZATION_SCOPE
+        || scope
+            .strip_prefix("department:")
+            .is_some_and(|id| Id::parse_str(id).is_ok())
+}
+
+fn envelope_error(e: impl std::fmt::Display) -> SyncError {
+    SyncError::Envelope(e.to_string())
+}
+
+/// A scope's data key for sync payloads
+pub struct SyncKey {
+    id: String,
+    scope: String,
+    key: SecretKey,
+}
+
+impl SyncKey {
+    /// A new random key for `scope`
+    pub fn generate(scope: &str) -> Result<Self> {
+        if !is_valid_scope(scope) {
+            return Err(SyncError::Envelope(format!("Invalid sync scope: {}", scope)));
+        }
+        let id = generate_random_bytes(8).map_err(envelope_error)?;
+        Ok(Self {
+            id: id.iter().map(|b| format!("{:02x}", b)).collect(),
+            scope: scope.to_string(),
+            key: Encryptor::generate_key().map_err(envelope_error)?,
+        })
+    }
+
+    /// Open a grant sealed to this device
+    pub fn open_grant(
+        key_id: &str,
+        scope: &str,
+        device_id: &str,
+        sealed_key: &str,
+        agreement: &AgreementKeyPair,
+    ) -> Result<Self> {
+        let key = agreement
+            .open(sealed_key, &grant_context(key_id, scope, device_id))
+            .map_err(envelope_error)?;
+        Ok(Self { id: key_id.to_string(), scope: scope.to_string(), key })
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn scope(&self) -> &str {
+        &self.scope
+    }
+
+    /// Seal the key to another device's base64 agreement key
+    pub fn seal_for(&self, device_id: &str, agreement_key: &str) -> Result<String> {
+        let recipient = BASE64.decode(agreement_key).map_err(envelope_error)?;
+        seal_key(&recipient, &self.key, &grant_context(&self.id, &self.scope, device_id))
+            .map_err(envelope_error)
+    }
+}
+
+impl std::fmt::Debug for SyncKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SyncKey").field("id", &self.id).field("scope", &self.scope).finish()
+    }
+}
+
+/// A grant only opens for the key, scope and device it was sealed for
+fn grant_context(key_id: &str, scope: &str, device_id: &str) -> String {
+    format!("sync-key:{}:{}:{}", key_id, scope, device_id)
+}
+
+/// The sync keys a device holds, with the newest key of each scope used
+/// for new changes
+#[derive(Default)]
+pub struct SyncKeyring {
+    keys: RwLock<HashMap<String, Arc<SyncKey>>>,
+    active: RwLock<HashMap<String, String>>,
+}
+
+impl SyncKeyring {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a key and make it the active one for its scope
+    pub fn insert(&self, key: SyncKey) -> Result<()> {
+        let mut keys = self.keys.write().map_err(envelope_error)?;
+        let mut active = self.active.write().map_err(envelope_error)?;
+        active.insert(key.scope.clone(), key.id.clone());
+        keys.insert(key.id.clone(), Arc::new(key));
+        Ok(())
+    }
+
+    pub fn get(&self, key_id: &str) -> Option<Arc<SyncKey>> {
+        self.keys.read().ok()?.get(key_id).cloned()
+    }
+
+    /// Key new changes in `scope` are encrypted with
+    pub fn active(&self, scope: &str) -> Option<Arc<SyncKey>> {
+        let key_id = self.active.read().ok()?.get(scope).cloned()?;
+        self.get(&key_id)
+    }
+}
+
+/// Encrypt a change under `key` and sign it as the originating device
+pub fn seal_change(change: &Change, key: &SyncKey, signer: &SigningKeyPair) -> Result<SealedChange> {
+    let mut sealed = SealedChange {
+        id: change.id,
+        entity_type: change.entity_type.clone(),
+        entity_id: change.entity_id,
+        operation: change.operation,
+        timestamp: change.timestamp,
+        device_id: change.device_id.clone(),
+        version: change.version.clone(),
+        scope: key.scope.clone(),
+        key_id: key.id.clone(),
+        payload: String::new(),
+        signature: String::new(),
+    };
+
+    let header = envelope_header(&sealed)?;
+    let data = serde_json::to_vec(&change.data).map_err(|e| SyncError::Serialization(e.to_string()))?;
+    let ciphertext = Encryptor::new(&key.key)
+        .and_then(|encryptor| encryptor.encrypt_bytes(&data, header.as_bytes()))
+        .map_err(envelope_error)?;
+    sealed.payload = BASE64.encode(ciphertext);
+
+    let signature = signer.sign(&signed_message(&header, &sealed.payload)).map_err(envelope_error)?;
+    sealed.signature = BASE64.encode(signature);
+    Ok(sealed)
+}
+
+/// Check the originating device's signature over the envelope and
+/// ciphertext. Needs only the device's public key, not the sync key.
+pub fn verify_change(sealed: &SealedChange, public_key: &[u8]) -> Result<()> {
+    let signature = BASE64.decode(&sealed.signature)
+        .map_err(|_| SyncError::Envelope("Signature is not base64".to_string()))?;
+    let header = envelope_header(sealed)?;
+    if !verify_signature(
+        SignatureAlgorithm::Ed25519,
+        public_key,
+        &signed_message(&header, &sealed.payload),
+        &signature,
+    ) {
+        return Err(SyncError::Envelope("Invalid signature".to_string()));
+    }
+    Ok(())
+}
+
+/// Decrypt a change with the scope key from `keyring`
+pub fn open_change(sealed: &SealedChange, keyring: &SyncKeyring) -> Result<Change> {
+    let key = keyring
+        .get(&sealed.key_id)
+        .ok_or_else(|| SyncError::Envelope(format!("Unknown sync key: {}", sealed.key_id)))?;
+    if key.scope != sealed.scope {
+        return Err(SyncError::Envelope("Sync key belongs to another scope".to_string()));
+    }
+
+    let header = envelope_header(sealed)?;
+    let ciphertext = BASE64.decode(&sealed.payload)
+        .map_err(|_| SyncError::Envelope("Payload is not base64".to_string()))?;
+    let data = Encryptor::new(&key.key)
+        .and_then(|encryptor| encryptor.decrypt_bytes(&ciphertext, header.as_bytes()))
+        .map_err(envelope_error)?;
+
+    Ok(Change {
+        id: sealed.id,
+        entity_type: sealed.entity_type.clone(),
+        entity_id: sealed.entity_id,
+        operation: sealed.operation,
+        data: serde_json::from_slice(&data).map_err(|e| SyncError::Serialization(e.to_string()))?,
+        timestamp: sealed.timestamp,
+        device_id: sealed.device_id.clone(),
+        version: sealed.version.clone(),
+    })
+}
+
+/// Canonical encoding of the readable envelope. Version vectors are sorted
+/// so the encoding does not depend on hash map order.
+fn envelope_header(sealed: &SealedChange) -> Result<String> {
+    let version: BTreeMap<_, _> = sealed.version.versions.iter().collect();
+    serde_json::to_string(&(
+        "hedtronix-sync-v1",
+        sealed.id,
+        &sealed.entity_type,
+        sealed.entity_id,
+        sealed.operation,
+        sealed.timestamp.to_rfc3339(),
+        &sealed.device_id,
+        version,
+        &sealed.scope,
+        &sealed.key_id,
+    ))
+    .map_err(|e| SyncError::Serialization(e.to_string()))
+}
+
+fn signed_message(header: &str, payload: &str) -> Vec<u8> {
+    format!("{}\n{}", header, payload).into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sealed_change_round_trip() {
+        let signer = SigningKeyPair::generate(SignatureAlgorithm::Ed25519).unwrap();
+        let recipient = AgreementKeyPair::generate().unwrap();
+
+        // The originating device creates the key and grants it to another device
+        let key = SyncKey::generate(ORGANIZATION_SCOPE).unwrap();
+        let sealed_key = key.seal_for("device-2", &BASE64.encode(recipient.public_key())).unwrap();
+        let received = SyncKey::open_grant(key.id(), key.scope(), "device-2", &sealed_key, &recipient).unwrap();
+        assert!(SyncKey::open_grant(key.id(), key.scope(), "device-3", &sealed_key, &recipient).is_err());
+
+        let mut change = Change::update(
+            "Patient",
+            Id::new_v4(),
+            serde_json::json!({"first_name": "Ada"}),
+            "device-1",
+        );
+        change.version.increment("device-1");
+        change.version.increment("device-0");
+        let sealed = seal_change(&change, &key, &signer).unwrap();
+        assert!(!sealed.payload.contains("Ada"));
+        verify_change(&sealed, signer.public_key()).unwrap();
+
+        let keyring = SyncKeyring::new();
+        keyring.insert(received).unwrap();
+        let opened = open_change(&sealed, &keyring).unwrap();
+        assert_eq!(opened.data, change.data);
+        assert_eq!(opened.id, change.id);
+
+        // Tampering with the envelope breaks the signature and the AEAD
+        let mut moved = sealed.clone();
+        moved.entity_id = Id::new_v4();
+        assert!(verify_change(&moved, signer.public_key()).is_err());
+        assert!(open_change(&moved, &keyring).is_err());
+
+        let other = SigningKeyPair::generate(SignatureAlgorithm::Ed25519).unwrap();
+        assert!(verify_change(&sealed, other.public_key()).is_err());
+        assert!(open_change(&sealed, &SyncKeyring::new()).is_err());
+    }
+
+    #[test]
+    fn test_scopes() {
+        assert!(is_valid_scope(ORGANIZATION_SCOPE));
+        assert!(is_valid_scope(&department_scope(Id::new_v4())));
+        assert!(!is_valid_scope("department:icu"));
+        assert!(SyncKey::generate("everyone").is_err());
+    }
+}
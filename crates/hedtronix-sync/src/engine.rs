@@ -1,12 +1,14 @@
 //! Sync engine for offline-first operation
 
-use hedtronix_core::{Id, Timestamp};
-use hedtronix_core::crdt::{Change, ChangeOperation};
-use hedtronix_db::{Database, SyncRepository};
+use hedtronix_core::{AuditLog, Id, Timestamp};
+use hedtronix_core::crdt::{Change, SealedChange};
+use hedtronix_crypto::SigningKeyPair;
+use hedtronix_db::{AuditRepository, Database, SyncRepository};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 use crate::conflict::{ConflictResolver, ResolutionResult};
+use crate::e2e::{open_change, seal_change, SyncKeyring};
 
 /// Sync error types
 #[derive(Error, Debug)]
@@ -25,11 +27,20 @@ pub enum SyncError {
     
     #[error("Sync in progress")]
     SyncInProgress,
+    
+    #[error("Envelope error: {0}")]
+    Envelope(String),
+    
+    #[error("Not permitted: {0}")]
+    NotPermitted(String),
 }
 
 /// Result type for sync operations
 pub type Result<T> = std::result::Result<T, SyncError>;
 
+/// Metadata key for the last local audit sequence the server holds
+const AUDIT_PUSHED_KEY: &str = "audit_pushed_sequence";
+
 /// Sync engine state
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum SyncState {
@@ -125,6 +136,37 @@ impl SyncEngine {
             .map_err(|e| SyncError::Database(e.to_string()))
     }
 
+    /// Pending changes encrypted for the relay with the active key of
+    /// `scope` and signed by this device
+    pub fn seal_pending_changes(
+        &self,
+        limit: u32,
+        keyring: &SyncKeyring,
+        scope: &str,
+        signer: &SigningKeyPair,
+    ) -> Result<Vec<SealedChange>> {
+        let key = keyring
+            .active(scope)
+            .ok_or_else(|| SyncError::Envelope(format!("No sync key for scope {}", scope)))?;
+        self.get_pending_changes(limit)?
+            .iter()
+            .map(|change| seal_change(change, &key, signer))
+            .collect()
+    }
+
+    /// Decrypt changes pulled from the relay and apply them locally
+    pub fn apply_sealed_changes(
+        &self,
+        changes: &[SealedChange],
+        keyring: &SyncKeyring,
+    ) -> Result<ApplyResult> {
+        let changes = changes
+            .iter()
+            .map(|sealed| open_change(sealed, keyring))
+            .collect::<Result<Vec<_>>>()?;
+        self.apply_remote_changes(changes)
+    }
+
     /// Apply remote changes locally
     pub fn apply_remote_changes(&self, changes: Vec<Change>) -> Result<ApplyResult> {
         let mut applied = 0;
@@ -134,7 +176,7 @@ impl SyncEngine {
         for change in changes {
             match self.apply_single_change(&change, &resolver) {
                 Ok(()) => applied += 1,
-                Err(SyncError::Conflict(msg)) => {
+                Err(SyncError::Conflict(_msg)) => {
                     conflicts.push(change.entity_id);
                 }
                 Err(e) => return Err(e),
@@ -225,6 +267,32 @@ impl SyncEngine {
             .map_err(|e| SyncError::Database(e.to_string()))
     }
 
+    /// Local audit entries the server does not hold yet, oldest first
+    pub fn pending_audit_entries(&self, limit: u32) -> Result<Vec<AuditLog>> {
+        let from = self.audit_pushed_through()? + 1;
+        AuditRepository::new(self.db.clone())
+            .find_range(from, limit)
+            .map_err(|e| SyncError::Database(e.to_string()))
+    }
+
+    /// Last local audit sequence number acknowledged by the server
+    pub fn audit_pushed_through(&self) -> Result<i64> {
+        let sync_repo = SyncRepository::new(self.db.clone());
+        let value = sync_repo.get_metadata(AUDIT_PUSHED_KEY)
+            .map_err(|e| SyncError::Database(e.to_string()))?;
+        Ok(value.and_then(|v| v.parse().ok()).unwrap_or(0))
+    }
+
+    /// Record the server's `stored_through` from an audit push
+    pub fn mark_audit_pushed(&self, through: i64) -> Result<()> {
+        if through <= self.audit_pushed_through()? {
+            return Ok(());
+        }
+        let sync_repo = SyncRepository::new(self.db.clone());
+        sync_repo.set_metadata(AUDIT_PUSHED_KEY, &through.to_string())
+            .map_err(|e| SyncError::Database(e.to_string()))
+    }
+
     /// Get sync status for UI display
     pub fn get_status(&self) -> SyncStatus {
         let pending = self.pending_count().unwrap_or(0);
@@ -4,8 +4,12 @@
 
 pub mod engine;
 pub mod conflict;
+pub mod e2e;
 pub mod protocol;
+pub mod relay;
 
 pub use engine::*;
 pub use conflict::*;
+pub use e2e::*;
 pub use protocol::*;
+pub use relay::*;
@@ -1,14 +1,16 @@
 //! Sync protocol definitions
 
-use hedtronix_core::Id;
-use hedtronix_core::crdt::Change;
+use hedtronix_core::{AuditLog, Id};
+use hedtronix_core::crdt::{HybridTimestamp, SealedChange};
+use hedtronix_db::{RejectedAuditEntry, RelayedChange};
 use serde::{Deserialize, Serialize};
 
-/// Sync push request - send local changes to server
+/// Sync push request - send local changes to server, encrypted and signed
+/// on the device
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PushRequest {
     pub device_id: String,
-    pub changes: Vec<Change>,
+    pub changes: Vec<SealedChange>,
     pub client_time: chrono::DateTime<chrono::Utc>,
 }
 
@@ -27,6 +29,24 @@ pub struct RejectedChange {
     pub reason: String,
 }
 
+/// Audit push request - replicate the device's audit sub-chain, oldest
+/// entry first
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditPushRequest {
+    pub entries: Vec<AuditLog>,
+}
+
+/// Audit push response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditPushResponse {
+    /// Entries up to this sequence number are held by the server
+    pub stored_through: i64,
+    pub rejected: Vec<RejectedAuditEntry>,
+    /// Server HLC time after the push, for the device to observe
+    pub server_hlc: Option<HybridTimestamp>,
+    pub server_time: chrono::DateTime<chrono::Utc>,
+}
+
 /// Sync pull request - get changes from server
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PullRequest {
@@ -34,17 +54,45 @@ pub struct PullRequest {
     pub since: Option<chrono::DateTime<chrono::Utc>>,
     pub entity_types: Option<Vec<String>>,
     pub limit: Option<u32>,
+    /// `next_cursor` of the previous pull
+    #[serde(default)]
+    pub cursor: Option<String>,
 }
 
 /// Sync pull response
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PullResponse {
-    pub changes: Vec<Change>,
+    pub changes: Vec<RelayedChange>,
     pub has_more: bool,
     pub next_cursor: Option<String>,
     pub server_time: chrono::DateTime<chrono::Utc>,
 }
 
+/// A sync key sealed to one device by a device that holds it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncKeyGrantRequest {
+    pub key_id: String,
+    pub scope: String,
+    pub device_id: Id,
+    pub sealed_key: String,
+}
+
+/// Upload sealed copies of sync keys for other devices (or, for a new key,
+/// for the creating device itself)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GrantKeysRequest {
+    pub grants: Vec<SyncKeyGrantRequest>,
+}
+
+/// A device a scope's sync key may be sealed to
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncRecipient {
+    pub device_id: Id,
+    pub user_id: Id,
+    /// Base64 X25519 public key
+    pub agreement_key: String,
+}
+
 /// Full sync request (initial sync or recovery)
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FullSyncRequest {
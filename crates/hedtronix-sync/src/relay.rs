@@ -0,0 +1,380 @@
+//! Server side of end-to-end encrypted sync
+//!
+//! The relay checks that each pushed change was signed by the registered
+//! device that claims to have sent it, under a sync key that device holds,
+//! then stores it unread and forwards it to the other devices holding keys
+//! for its scope. It also stores and hands out sealed sync key grants.
+//!
+//! Only devices of active users whose role reads charts in full are in any
+//! scope. This is checked when keys are granted and again on every push and
+//! pull, so a user who loses the grant stops syncing at once.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use hedtronix_core::crdt::SealedChange;
+use hedtronix_core::Device;
+use hedtronix_db::{
+    Database, DeviceRepository, RoleRepository, SyncKeyGrant, SyncKeyRepository,
+    SyncRelayRepository, UserRepository,
+};
+
+use crate::e2e::{is_valid_scope, verify_change, ORGANIZATION_SCOPE};
+use crate::engine::{Result, SyncError};
+use crate::protocol::{
+    PullResponse, PushResponse, RejectedChange, SyncKeyGrantRequest, SyncRecipient,
+};
+
+/// Most changes returned by one pull
+pub const MAX_PULL_LIMIT: u32 = 500;
+
+/// Grants that open charts in full. Synced changes carry whole records, so
+/// a device's user needs one of these to be in any scope.
+const PHI_READ_GRANTS: [(&str, &str); 2] = [("patients", "read"), ("patients", "read_all")];
+
+fn db_error(e: impl std::fmt::Display) -> SyncError {
+    SyncError::Database(e.to_string())
+}
+
+/// Stores and forwards sealed changes between devices
+pub struct SyncRelay {
+    db: Database,
+}
+
+impl SyncRelay {
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+
+    /// Validate and store changes pushed by `device`. Changes already held
+    /// are acknowledged again.
+    pub fn push(&self, device: &Device, changes: &[SealedChange]) -> Result<PushResponse> {
+        let relay = SyncRelayRepository::new(self.db.clone());
+        let mut acknowledged = Vec::new();
+        let mut rejected = Vec::new();
+
+        for change in changes {
+            match self.check_change(device, change) {
+                Ok(()) => {
+                    relay.store(change).map_err(db_error)?;
+                    acknowledged.push(change.id);
+                }
+                Err(reason) => rejected.push(RejectedChange { change_id: change.id, reason }),
+            }
+        }
+
+        Ok(PushResponse { acknowledged, rejected, server_time: chrono::Utc::now() })
+    }
+
+    /// Changes for the scopes `device` holds keys for and is still within,
+    /// after `cursor`
+    pub fn pull(&self, device: &Device, cursor: Option<&str>, limit: u32) -> Result<PullResponse> {
+        let after = match cursor {
+            Some(cursor) => cursor
+                .parse::<i64>()
+                .map_err(|_| SyncError::Envelope("Invalid cursor".to_string()))?,
+            None => 0,
+        };
+        let limit = limit.clamp(1, MAX_PULL_LIMIT);
+
+        let mut held: Vec<String> = self.grants(device)?.into_iter().map(|g| g.scope).collect();
+        held.sort();
+        held.dedup();
+        let mut scopes = Vec::with_capacity(held.len());
+        for scope in held {
+            if self.in_scope(device, &scope)? {
+                scopes.push(scope);
+            }
+        }
+
+        let mut changes = SyncRelayRepository::new(self.db.clone())
+            .find_after(after, &scopes, &device.id.to_string(), limit + 1)
+            .map_err(db_error)?;
+        let has_more = changes.len() > limit as usize;
+        changes.truncate(limit as usize);
+
+        Ok(PullResponse {
+            next_cursor: changes.last().map(|c| c.sequence.to_string()).or(cursor.map(String::from)),
+            changes,
+            has_more,
+            server_time: chrono::Utc::now(),
+        })
+    }
+
+    /// Keys granted to `device`, oldest first
+    pub fn grants(&self, device: &Device) -> Result<Vec<SyncKeyGrant>> {
+        SyncKeyRepository::new(self.db.clone()).find_for_device(device.id).map_err(db_error)
+    }
+
+    /// Devices a key for `scope` may be sealed to
+    pub fn recipients(&self, scope: &str) -> Result<Vec<SyncRecipient>> {
+        if !is_valid_scope(scope) {
+            return Err(SyncError::Envelope(format!("Invalid sync scope: {}", scope)));
+        }
+        let devices = DeviceRepository::new(self.db.clone())
+            .find_with_agreement_keys()
+            .map_err(db_error)?;
+
+        let mut recipients = Vec::new();
+        for device in devices {
+            if !self.in_scope(&device, scope)? {
+                continue;
+            }
+            if let Some(agreement_key) = device.agreement_key {
+                recipients.push(SyncRecipient { device_id: device.id, user_id: device.user_id, agreement_key });
+            }
+        }
+        Ok(recipients)
+    }
+
+    /// Store keys sealed by `granter` for other devices. Sharing a key
+    /// requires holding it; introducing a new key requires `may_create`
+    /// (the `sync_keys:manage` permission). Every recipient must be an
+    /// active device within the key's scope.
+    pub fn grant(
+        &self,
+        granter: &Device,
+        grants: &[SyncKeyGrantRequest],
+        may_create: bool,
+    ) -> Result<Vec<SyncKeyGrant>> {
+        let keys = SyncKeyRepository::new(self.db.clone());
+        let devices = DeviceRepository::new(self.db.clone());
+        let mut stored = Vec::new();
+
+        for request in grants {
+            if !is_valid_scope(&request.scope) {
+                return Err(SyncError::Envelope(format!("Invalid sync scope: {}", request.scope)));
+            }
+            if BASE64.decode(&request.sealed_key).is_err() {
+                return Err(SyncError::Envelope("sealed_key is not base64".to_string()));
+            }
+
+            match keys.key_scope(&request.key_id).map_err(db_error)? {
+                Some(scope) if scope != request.scope => {
+                    return Err(SyncError::Envelope(format!(
+                        "Sync key {} belongs to scope {}",
+                        request.key_id, scope
+                    )));
+                }
+                Some(_) => {
+                    let holds = keys.find(&request.key_id, granter.id).map_err(db_error)?.is_some();
+                    if !holds {
+                        return Err(SyncError::NotPermitted(format!(
+                            "Device does not hold sync key {}",
+                            request.key_id
+                        )));
+                    }
+                }
+                None if !may_create => {
+                    return Err(SyncError::NotPermitted(
+                        "Requires the sync_keys:manage permission".to_string(),
+                    ));
+                }
+                None => {}
+            }
+
+            let recipient = devices
+                .find_by_id(request.device_id)
+                .map_err(db_error)?
+                .filter(|d| d.is_valid() && d.agreement_key.is_some())
+                .ok_or_else(|| {
+                    SyncError::Envelope(format!("Device {} cannot receive sync keys", request.device_id))
+                })?;
+            if !self.in_scope(&recipient, &request.scope)? {
+                return Err(SyncError::NotPermitted(format!(
+                    "Device {} is outside scope {}",
+                    request.device_id, request.scope
+                )));
+            }
+
+            let grant = SyncKeyGrant {
+                key_id: request.key_id.clone(),
+                scope: request.scope.clone(),
+                device_id: recipient.id,
+                sealed_key: request.sealed_key.clone(),
+                granted_by: granter.id,
+                created_at: chrono::Utc::now(),
+            };
+            keys.grant(&grant).map_err(db_error)?;
+            stored.push(grant);
+        }
+
+        Ok(stored)
+    }
+
+    /// Why a pushed change cannot be relayed, if it cannot
+    fn check_change(&self, device: &Device, change: &SealedChange) -> std::result::Result<(), String> {
+        if change.device_id != device.id.to_string() {
+            return Err("Change was not sent by the calling device".to_string());
+        }
+        let grant = SyncKeyRepository::new(self.db.clone())
+            .find(&change.key_id, device.id)
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| format!("Device does not hold sync key {}", change.key_id))?;
+        if grant.scope != change.scope {
+            return Err(format!("Sync key {} belongs to scope {}", change.key_id, grant.scope));
+        }
+        if !self.in_scope(device, &change.scope).map_err(|e| e.to_string())? {
+            return Err(format!("Device is outside scope {}", change.scope));
+        }
+        if BASE64.decode(&change.payload).is_err() {
+            return Err("Payload is not base64".to_string());
+        }
+
+        let public_key = BASE64.decode(&device.public_key)
+            .map_err(|_| "Device public key is not base64".to_string())?;
+        verify_change(change, &public_key).map_err(|e| e.to_string())
+    }
+
+    /// Whether the device's user may read changes in `scope`: an active
+    /// user whose role reads charts in full and, for a department scope,
+    /// who works in that department
+    fn in_scope(&self, device: &Device, scope: &str) -> Result<bool> {
+        let user = UserRepository::new(self.db.clone()).find_by_id(device.user_id).map_err(db_error)?;
+        let Some(user) = user.filter(|u| u.active) else {
+            return Ok(false);
+        };
+        let role = RoleRepository::new(self.db.clone()).find_by_id(user.role_key()).map_err(db_error)?;
+        let reads_charts = role.is_some_and(|role| {
+            PHI_READ_GRANTS.iter().any(|(resource, action)| role.allows(resource, action))
+        });
+        if !reads_charts {
+            return Ok(false);
+        }
+
+        if scope == ORGANIZATION_SCOPE {
+            return Ok(true);
+        }
+        let Some(department) = scope.strip_prefix("department:") else {
+            return Ok(false);
+        };
+        Ok(user.department_id.is_some_and(|id| id.to_string() == department))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::e2e::{open_change, seal_change, SyncKey, SyncKeyring};
+    use hedtronix_core::crdt::Change;
+    use hedtronix_core::{DeviceType, Id, User, UserRole};
+    use hedtronix_crypto::{AgreementKeyPair, SignatureAlgorithm, SigningKeyPair};
+
+    struct TestDevice {
+        device: Device,
+        signer: SigningKeyPair,
+        agreement: AgreementKeyPair,
+    }
+
+    fn register(db: &Database, user: &User) -> TestDevice {
+        let signer = SigningKeyPair::generate(SignatureAlgorithm::Ed25519).unwrap();
+        let agreement = AgreementKeyPair::generate().unwrap();
+        let mut device = Device::new(user.id, BASE64.encode(signer.public_key()), DeviceType::Tablet, "tablet".into());
+        device.agreement_key = Some(BASE64.encode(agreement.public_key()));
+        DeviceRepository::new(db.clone()).create(&device).unwrap();
+        TestDevice { device, signer, agreement }
+    }
+
+    fn grant_request(key: &SyncKey, to: &TestDevice) -> SyncKeyGrantRequest {
+        SyncKeyGrantRequest {
+            key_id: key.id().to_string(),
+            scope: key.scope().to_string(),
+            device_id: to.device.id,
+            sealed_key: key
+                .seal_for(&to.device.id.to_string(), to.device.agreement_key.as_ref().unwrap())
+                .unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_relay_forwards_changes_it_cannot_read() {
+        let mut db = Database::in_memory().unwrap();
+        db.initialize().unwrap();
+        let user = User::new("nurse@example.com".into(), "Nurse".into(), UserRole::Nurse, "hash".into());
+        UserRepository::new(db.clone()).create(&user).unwrap();
+        let (alice, bob, mallory) = (register(&db, &user), register(&db, &user), register(&db, &user));
+        let relay = SyncRelay::new(db);
+
+        // Alice creates the organization key and shares it with Bob only
+        let key = SyncKey::generate(ORGANIZATION_SCOPE).unwrap();
+        let grants = [grant_request(&key, &alice), grant_request(&key, &bob)];
+        assert!(matches!(relay.grant(&alice.device, &grants, false), Err(SyncError::NotPermitted(_))));
+        relay.grant(&alice.device, &grants, true).unwrap();
+        // Holding no copy, Mallory cannot hand the key out
+        let to_mallory = [grant_request(&key, &mallory)];
+        assert!(relay.grant(&mallory.device, &to_mallory, false).is_err());
+
+        let change = Change::update(
+            "Patient",
+            Id::new_v4(),
+            serde_json::json!({"last_name": "Lovelace"}),
+            alice.device.id.to_string(),
+        );
+        let sealed = seal_change(&change, &key, &alice.signer).unwrap();
+        let forged = seal_change(&change, &key, &mallory.signer).unwrap();
+        let response = relay.push(&alice.device, &[sealed.clone(), forged]).unwrap();
+        assert_eq!(response.acknowledged, vec![change.id]);
+        assert_eq!(response.rejected.len(), 1);
+        // Mallory holds no key for the scope, so cannot relay in it
+        assert!(!relay.push(&mallory.device, &[sealed]).unwrap().rejected.is_empty());
+
+        let pulled = relay.pull(&bob.device, None, 10).unwrap();
+        assert_eq!(pulled.changes.len(), 1);
+        assert!(!pulled.has_more);
+        assert!(relay.pull(&mallory.device, None, 10).unwrap().changes.is_empty());
+        assert!(relay.pull(&bob.device, pulled.next_cursor.as_deref(), 10).unwrap().changes.is_empty());
+
+        // Bob opens his grant and reads the change
+        let keyring = SyncKeyring::new();
+        for grant in relay.grants(&bob.device).unwrap() {
+            keyring.insert(SyncKey::open_grant(
+                &grant.key_id,
+                &grant.scope,
+                &bob.device.id.to_string(),
+                &grant.sealed_key,
+                &bob.agreement,
+            ).unwrap()).unwrap();
+        }
+        let opened = open_change(&pulled.changes[0].change, &keyring).unwrap();
+        assert_eq!(opened.data, change.data);
+
+        // Department keys only go to that department's staff
+        let department = crate::e2e::department_scope(Id::new_v4());
+        assert!(relay.recipients(&department).unwrap().is_empty());
+        assert_eq!(relay.recipients(ORGANIZATION_SCOPE).unwrap().len(), 3);
+        let icu_key = SyncKey::generate(&department).unwrap();
+        assert!(relay.grant(&alice.device, &[grant_request(&icu_key, &bob)], true).is_err());
+    }
+
+    #[test]
+    fn test_only_chart_readers_are_in_scope() {
+        let mut db = Database::in_memory().unwrap();
+        db.initialize().unwrap();
+        let users = UserRepository::new(db.clone());
+        let nurse = User::new("nurse@example.com".into(), "Nurse".into(), UserRole::Nurse, "hash".into());
+        let portal = User::new("patient@example.com".into(), "Patient".into(), UserRole::Patient, "hash".into());
+        users.create(&nurse).unwrap();
+        users.create(&portal).unwrap();
+        let (ward, station, home) = (register(&db, &nurse), register(&db, &nurse), register(&db, &portal));
+        let relay = SyncRelay::new(db.clone());
+
+        // Patient portal devices never receive the organization key
+        assert_eq!(relay.recipients(ORGANIZATION_SCOPE).unwrap().len(), 2);
+        let key = SyncKey::generate(ORGANIZATION_SCOPE).unwrap();
+        relay.grant(&ward.device, &[grant_request(&key, &ward), grant_request(&key, &station)], true).unwrap();
+        assert!(matches!(
+            relay.grant(&ward.device, &[grant_request(&key, &home)], false),
+            Err(SyncError::NotPermitted(_))
+        ));
+
+        let change = Change::update("Patient", Id::new_v4(), serde_json::json!({}), ward.device.id.to_string());
+        let sealed = seal_change(&change, &key, &ward.signer).unwrap();
+        assert_eq!(relay.push(&ward.device, &[sealed]).unwrap().acknowledged.len(), 1);
+        assert_eq!(relay.pull(&station.device, None, 10).unwrap().changes.len(), 1);
+
+        // Losing chart access stops push and pull, whatever keys are held
+        RoleRepository::new(db).revoke("NURSE", &hedtronix_core::Permission::new("patients", "read")).unwrap();
+        assert!(relay.pull(&station.device, None, 10).unwrap().changes.is_empty());
+        let change = Change::update("Patient", Id::new_v4(), serde_json::json!({}), ward.device.id.to_string());
+        let sealed = seal_change(&change, &key, &ward.signer).unwrap();
+        assert_eq!(relay.push(&ward.device, &[sealed]).unwrap().rejected.len(), 1);
+    }
+}
@@ -9,9 +9,18 @@ fn main() {
         let rt = tokio::runtime::Runtime::new().unwrap();
         rt.block_on(async {
              // Use a randomly assigned port or specific desktop configuration
-             let config = ServerConfig {
-                 bind_address: "127.0.0.1:8080".to_string(), // Keep consistent with frontend proxy
-                 ..ServerConfig::from_env()
+             let config = match ServerConfig::from_env() {
+                 Ok(config) => ServerConfig {
+                     bind_address: "127.0.0.1:8080".to_string(), // Keep consistent with frontend proxy
+                     // The database key is unlocked by each user's login
+                     // rather than read from the environment
+                     device_lock: true,
+                     ..config
+                 },
+                 Err(e) => {
+                     eprintln!("Invalid server configuration: {}", e);
+                     return;
+                 }
              };
              if let Err(e) = hedtronix_api::start_server(config).await {
                  eprintln!("Failed to start server: {}", e);